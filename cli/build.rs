@@ -2,7 +2,11 @@
 mod cli;
 
 use clap::CommandFactory;
-use std::{fs, io, path::PathBuf};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+// The shell command that prints the names of the existing mailboxes, one per line
+const LIST_MAILBOXES: &str = "mailbox __complete-mailboxes 2>/dev/null";
 
 fn main() {
     // Don't rebuild when the generated completions change
@@ -27,6 +31,56 @@ fn generate_completions() -> io::Result<()> {
     generate_to(Fish, cmd, &bin_name, out_dir)?;
     generate_to(PowerShell, cmd, &bin_name, out_dir)?;
     generate_to(Zsh, cmd, &bin_name, out_dir)?;
+    complete_mailbox_names(out_dir, &bin_name)?;
+
+    Ok(())
+}
+
+// Modify a generated completion script line by line
+fn edit_completions(path: &Path, mut edit_line: impl FnMut(&str) -> String) -> io::Result<()> {
+    let script = fs::read_to_string(path)?;
+    let edited = script
+        .lines()
+        .map(|line| edit_line(line) + "\n")
+        .collect::<String>();
+    fs::write(path, edited)
+}
+
+// Make the bash, zsh, and fish completions complete --mailbox values with the names of the
+// existing mailboxes instead of file names
+fn complete_mailbox_names(out_dir: &Path, bin_name: &str) -> io::Result<()> {
+    // Bash completes each option's value in a case arm that follows the option name
+    let mut in_mailbox_arm = false;
+    edit_completions(&out_dir.join(format!("{bin_name}.bash")), |line| {
+        let trimmed = line.trim();
+        if trimmed == "--mailbox)" || trimmed == "-m)" {
+            in_mailbox_arm = true;
+        } else if in_mailbox_arm && trimmed.starts_with("COMPREPLY=") {
+            in_mailbox_arm = false;
+            return line.replace(
+                r#"compgen -f "${cur}""#,
+                &format!(r#"compgen -W "$({LIST_MAILBOXES})" -- "${{cur}}""#),
+            );
+        }
+        line.to_owned()
+    })?;
+
+    // Zsh completes each option's value with the action after the value name
+    edit_completions(&out_dir.join(format!("_{bin_name}")), |line| {
+        line.replace(
+            ":MAILBOX:_default'",
+            &format!(r#":MAILBOX:{{compadd -- ${{(f)"$({LIST_MAILBOXES})"}}}}'"#),
+        )
+    })?;
+
+    // Fish completes each option's value with the arguments passed to -a
+    edit_completions(&out_dir.join(format!("{bin_name}.fish")), |line| {
+        if line.contains(" -l mailbox ") && line.ends_with(" -r") {
+            format!(r#"{line} -f -a "({LIST_MAILBOXES})""#)
+        } else {
+            line.to_owned()
+        }
+    })?;
 
     Ok(())
 }
@@ -0,0 +1,320 @@
+use crate::cli::{AdminSubcommand, TokensSubcommand};
+use crate::confirm::format_count;
+use anyhow::Result;
+use database::{
+    AdminStats, ApiToken, AuditEntry, DeliveryStatus, ForwardDelivery, HttpBackend, MailboxAcl,
+};
+use std::fmt::Write;
+
+// Format a number of bytes with the largest unit that keeps it at least 1, like "1.5 MB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut unit_size = 1024u64;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if bytes / unit_size < 1024 {
+            break;
+        }
+        unit_size *= 1024;
+        unit = next_unit;
+    }
+    let tenths = u128::from(bytes) * 10 / u128::from(unit_size);
+    format!("{}.{} {unit}", tenths / 10, tenths % 10)
+}
+
+// Format the server's database statistics as aligned lines
+fn format_admin_stats(stats: &AdminStats) -> String {
+    let rows = [
+        ("messages", format_count(stats.message_count)),
+        ("mailboxes", format_count(stats.mailbox_count)),
+        ("tokens", format_count(stats.token_count)),
+        ("size", format_bytes(stats.database_bytes)),
+        ("unused", format_bytes(stats.free_bytes)),
+    ];
+    let mut output = String::new();
+    for (label, value) in rows {
+        let _ = writeln!(output, "{label:<9}  {value}");
+    }
+    output
+}
+
+// Format the mailbox patterns in an access list, like "ci/*, logs/*"
+fn format_patterns(patterns: Option<&Vec<String>>) -> String {
+    match patterns {
+        None => String::from("all"),
+        Some(patterns) if patterns.is_empty() => String::from("none"),
+        Some(patterns) => patterns.join(", "),
+    }
+}
+
+// Format API tokens as a list of names, creation times, and the mailboxes that restricted tokens
+// can access
+fn format_tokens(tokens: &[ApiToken]) -> String {
+    let width = tokens
+        .iter()
+        .map(|token| token.name.chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for token in tokens {
+        let _ = write!(
+            output,
+            "{:<width$}  created {}",
+            token.name,
+            token.created.format("%Y-%m-%d %H:%M UTC")
+        );
+        if !token.acl.is_unrestricted() {
+            let _ = write!(
+                output,
+                "  read {}  write {}",
+                format_patterns(token.acl.read.as_ref()),
+                format_patterns(token.acl.write.as_ref())
+            );
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// Format audit log entries as aligned lines of their time, actor, operation, target, and the
+// number of messages or tokens affected
+fn format_audit_log(entries: &[AuditEntry]) -> String {
+    let actor_width = entries
+        .iter()
+        .map(|entry| entry.actor.chars().count())
+        .max()
+        .unwrap_or_default();
+    let operation_width = entries
+        .iter()
+        .map(|entry| entry.operation.chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for entry in entries {
+        let _ = writeln!(
+            output,
+            "{}  {:<actor_width$}  {:<operation_width$}  {} ({} affected)",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+            entry.actor,
+            entry.operation,
+            entry.target,
+            entry.affected
+        );
+    }
+    output
+}
+
+// Format forward deliveries as aligned lines of when they were last updated, their message id,
+// forward, and status, followed by the most recent error of undelivered messages
+fn format_forward_deliveries(deliveries: &[ForwardDelivery]) -> String {
+    let id_width = deliveries
+        .iter()
+        .map(|delivery| delivery.message_id.to_string().len())
+        .max()
+        .unwrap_or_default();
+    let forward_width = deliveries
+        .iter()
+        .map(|delivery| delivery.forward.chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for delivery in deliveries {
+        let status = match delivery.status {
+            DeliveryStatus::Pending => format!(
+                "pending, retrying at {}",
+                delivery.next_attempt.format("%H:%M:%S UTC")
+            ),
+            DeliveryStatus::Delivered => String::from("delivered"),
+            DeliveryStatus::Failed => String::from("failed"),
+        };
+        let plural = if delivery.attempts == 1 { "" } else { "s" };
+        let _ = write!(
+            output,
+            "{}  #{:<id_width$}  {:<forward_width$}  {status} after {} attempt{plural}",
+            delivery.updated.format("%Y-%m-%d %H:%M:%S UTC"),
+            delivery.message_id,
+            delivery.forward,
+            delivery.attempts
+        );
+        if let Some(error) = &delivery.last_error {
+            let _ = write!(output, ": {error}");
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// Run an admin command against a remote server
+pub async fn run_admin(backend: &HttpBackend, subcommand: AdminSubcommand) -> Result<()> {
+    match subcommand {
+        AdminSubcommand::Stats => {
+            print!("{}", format_admin_stats(&backend.load_admin_stats().await?));
+        }
+        AdminSubcommand::Compact => {
+            let compaction = backend.compact().await?;
+            println!(
+                "Compacted database from {} to {}",
+                format_bytes(compaction.before_bytes),
+                format_bytes(compaction.after_bytes)
+            );
+        }
+        AdminSubcommand::Tokens { subcommand } => match subcommand {
+            TokensSubcommand::List => {
+                print!("{}", format_tokens(&backend.load_api_tokens().await?));
+            }
+            TokensSubcommand::Create { name, read, write } => {
+                let token = backend
+                    .create_api_token(&name, &MailboxAcl { read, write })
+                    .await?;
+                // Print the secret by itself so that scripts can capture it
+                println!("{}", token.token.unwrap_or_default());
+                eprintln!(
+                    "Created token \"{name}\". Store it now because it won't be shown again."
+                );
+            }
+            TokensSubcommand::Revoke { name } => {
+                backend.revoke_api_token(&name).await?;
+                println!("Revoked token \"{name}\"");
+            }
+        },
+        AdminSubcommand::Audit { limit } => {
+            print!(
+                "{}",
+                format_audit_log(&backend.load_audit_log(limit).await?)
+            );
+        }
+        AdminSubcommand::Forwards { limit } => {
+            print!(
+                "{}",
+                format_forward_deliveries(&backend.load_forward_deliveries(limit).await?)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(format_bytes(u64::MAX), "16777215.9 TB");
+    }
+
+    #[test]
+    fn test_format_admin_stats() {
+        assert_eq!(
+            format_admin_stats(&AdminStats {
+                message_count: 1204,
+                mailbox_count: 12,
+                token_count: 2,
+                database_bytes: 2 * 1024 * 1024,
+                free_bytes: 512,
+            }),
+            "messages   1,204
+mailboxes  12
+tokens     2
+size       2.0 MB
+unused     512 B
+"
+        );
+    }
+
+    #[test]
+    fn test_format_tokens() {
+        let created =
+            NaiveDateTime::parse_from_str("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            format_tokens(&[
+                ApiToken {
+                    name: String::from("ci"),
+                    created,
+                    token: None,
+                    acl: MailboxAcl {
+                        read: Some(vec![]),
+                        write: Some(vec![String::from("ci/*"), String::from("deploys")]),
+                    },
+                },
+                ApiToken {
+                    name: String::from("laptop"),
+                    created,
+                    token: None,
+                    acl: MailboxAcl::default(),
+                },
+            ]),
+            "ci      created 2024-01-02 03:04 UTC  read none  write ci/*, deploys
+laptop  created 2024-01-02 03:04 UTC
+"
+        );
+    }
+
+    #[test]
+    fn test_format_audit_log() {
+        let timestamp =
+            NaiveDateTime::parse_from_str("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            format_audit_log(&[
+                AuditEntry {
+                    id: 2,
+                    timestamp,
+                    actor: String::from("laptop"),
+                    operation: String::from("delete_mailbox"),
+                    target: String::from("mailbox=alerts"),
+                    affected: 3,
+                },
+                AuditEntry {
+                    id: 1,
+                    timestamp,
+                    actor: String::from("ci"),
+                    operation: String::from("create_messages"),
+                    target: String::from("ids=1"),
+                    affected: 1,
+                },
+            ]),
+            "2024-01-02 03:04:05 UTC  laptop  delete_mailbox   mailbox=alerts (3 affected)
+2024-01-02 03:04:05 UTC  ci      create_messages  ids=1 (1 affected)
+"
+        );
+    }
+
+    #[test]
+    fn test_format_forward_deliveries() {
+        let timestamp =
+            NaiveDateTime::parse_from_str("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            format_forward_deliveries(&[
+                ForwardDelivery {
+                    message_id: 12,
+                    forward: String::from("phone"),
+                    status: DeliveryStatus::Pending,
+                    attempts: 2,
+                    last_error: Some(String::from("connection refused")),
+                    next_attempt: timestamp,
+                    updated: timestamp,
+                },
+                ForwardDelivery {
+                    message_id: 9,
+                    forward: String::from("gotify"),
+                    status: DeliveryStatus::Delivered,
+                    attempts: 1,
+                    last_error: None,
+                    next_attempt: timestamp,
+                    updated: timestamp,
+                },
+            ]),
+            "2024-01-02 03:04:05 UTC  #12  phone   pending, retrying at 03:04:05 UTC after 2 attempts: connection refused
+2024-01-02 03:04:05 UTC  #9   gotify  delivered after 1 attempt
+"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::cli::parse_duration;
+use chrono::NaiveDateTime;
+use database::{matches_pattern, Heartbeat, Mailbox, Message, State};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Thresholds that every mailbox matching a rule's pattern must stay within, which `mailbox check`
+// enforces
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    // The maximum number of unread messages in each mailbox
+    pub max_unread: Option<usize>,
+    // The maximum age of the newest message in each mailbox, which catches jobs that stopped
+    // reporting
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub stale_after: Option<Duration>,
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_duration(&value)
+        .map(Some)
+        .map_err(|err| D::Error::custom(format!("invalid duration \"{value}\": {err}")))
+}
+
+// Format a duration in the largest unit that parse_duration accepts and that represents it exactly
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    [("w", 7 * 24 * 60 * 60), ("d", 24 * 60 * 60), ("h", 60 * 60)]
+        .into_iter()
+        .find(|(_, unit_seconds)| seconds.is_multiple_of(*unit_seconds))
+        .map_or_else(
+            || format!("{}m", seconds / 60),
+            |(unit, unit_seconds)| format!("{}{unit}", seconds / unit_seconds),
+        )
+}
+
+// An alert rule that a mailbox violates
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Violation {
+    // The pattern of the violated rule
+    pub rule: String,
+    // The mailbox that violates the rule, which is None if no mailboxes match the rule's pattern
+    pub mailbox: Option<Mailbox>,
+    pub problem: String,
+}
+
+// The number of unread messages and the newest message in a mailbox
+struct MailboxActivity {
+    unread: usize,
+    newest: NaiveDateTime,
+}
+
+// Find the alert rules violated by the messages, which are expected to include messages in every
+// state
+pub fn check_alerts(
+    rules: &BTreeMap<String, AlertRule>,
+    messages: &[Message],
+    now: NaiveDateTime,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (pattern, rule) in rules {
+        let mut mailboxes = BTreeMap::<&Mailbox, MailboxActivity>::new();
+        for message in messages
+            .iter()
+            .filter(|message| matches_pattern(pattern, &message.mailbox))
+        {
+            let activity = mailboxes
+                .entry(&message.mailbox)
+                .or_insert(MailboxActivity {
+                    unread: 0,
+                    newest: message.timestamp,
+                });
+            if message.state == State::Unread {
+                activity.unread += 1;
+            }
+            activity.newest = activity.newest.max(message.timestamp);
+        }
+
+        // A mailbox that never received a message is as stale as one that stopped receiving them
+        if let (true, Some(stale_after)) = (mailboxes.is_empty(), rule.stale_after) {
+            violations.push(Violation {
+                rule: pattern.clone(),
+                mailbox: None,
+                problem: format!(
+                    "no messages, expected one within {}",
+                    format_duration(stale_after)
+                ),
+            });
+        }
+
+        for (mailbox, activity) in mailboxes {
+            if let Some(max_unread) = rule.max_unread {
+                if activity.unread > max_unread {
+                    violations.push(Violation {
+                        rule: pattern.clone(),
+                        mailbox: Some(mailbox.clone()),
+                        problem: format!(
+                            "{} unread messages, more than the maximum of {max_unread}",
+                            activity.unread
+                        ),
+                    });
+                }
+            }
+            if let Some(stale_after) = rule.stale_after {
+                let age = (now - activity.newest).to_std().unwrap_or_default();
+                if age > stale_after {
+                    violations.push(Violation {
+                        rule: pattern.clone(),
+                        mailbox: Some(mailbox.clone()),
+                        problem: format!(
+                            "no messages within {}, the newest is from {}",
+                            format_duration(stale_after),
+                            activity.newest.format("%Y-%m-%d %H:%M UTC")
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    violations
+}
+
+// Find the heartbeat mailboxes that have gone longer than their interval without a message
+pub fn check_heartbeats(heartbeats: &[Heartbeat], now: NaiveDateTime) -> Vec<Violation> {
+    heartbeats
+        .iter()
+        .filter(|heartbeat| heartbeat.is_overdue(now))
+        .map(|heartbeat| Violation {
+            rule: String::from("heartbeat"),
+            mailbox: Some(heartbeat.mailbox.clone()),
+            problem: heartbeat.describe(now),
+        })
+        .collect()
+}
+
+// Format violations with one line per violation
+pub fn format_violations(violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(|violation| {
+            violation.mailbox.as_ref().map_or_else(
+                || format!("{}: {}\n", violation.rule, violation.problem),
+                |mailbox| format!("{mailbox}: {} ({})\n", violation.problem, violation.rule),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeDelta};
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 2)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn message(mailbox: &str, state: State, hours_ago: i64) -> Message {
+        Message {
+            id: 1,
+            timestamp: now() - TimeDelta::hours(hours_ago),
+            mailbox: mailbox.try_into().unwrap(),
+            content: String::from("Backup succeeded"),
+            state,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            priority: 0,
+            kind: database::Kind::Text,
+            tags: vec![],
+            related_ids: vec![],
+        }
+    }
+
+    fn rules(toml: &str) -> BTreeMap<String, AlertRule> {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_deserialize() {
+        assert_eq!(
+            rules("'backups/*' = { max_unread = 5, stale_after = '26h' }\n"),
+            BTreeMap::from([(
+                String::from("backups/*"),
+                AlertRule {
+                    max_unread: Some(5),
+                    stale_after: Some(Duration::from_hours(26)),
+                }
+            )])
+        );
+        assert!(
+            toml::from_str::<BTreeMap<String, AlertRule>>("ci = { stale_after = '26' }\n").is_err()
+        );
+        assert!(toml::from_str::<BTreeMap<String, AlertRule>>("ci = { max_read = 5 }\n").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_hours(26)), "26h");
+        assert_eq!(format_duration(Duration::from_hours(48)), "2d");
+        assert_eq!(format_duration(Duration::from_hours(14 * 24)), "2w");
+        assert_eq!(format_duration(Duration::from_mins(90)), "90m");
+    }
+
+    #[test]
+    fn test_check_alerts() {
+        let rules = rules(
+            "'backups/*' = { max_unread = 2, stale_after = '26h' }\n'deploys' = { stale_after = '1d' }\n",
+        );
+        let messages = vec![
+            message("backups/laptop", State::Unread, 1),
+            message("backups/laptop", State::Unread, 2),
+            message("backups/laptop", State::Read, 3),
+            message("backups/nas", State::Archived, 30),
+            message("backups/nas", State::Unread, 40),
+            message("ci", State::Unread, 100),
+        ];
+        let violations = check_alerts(&rules, &messages, now());
+        assert_eq!(
+            format_violations(&violations),
+            "backups/nas: no messages within 26h, the newest is from 2024-03-01 06:00 UTC (backups/*)\ndeploys: no messages, expected one within 1d\n"
+        );
+
+        let messages = vec![
+            message("backups/laptop", State::Unread, 1),
+            message("backups/laptop", State::Unread, 2),
+            message("backups/laptop", State::Unread, 3),
+            message("deploys", State::Read, 23),
+        ];
+        let violations = check_alerts(&rules, &messages, now());
+        assert_eq!(
+            violations,
+            vec![Violation {
+                rule: String::from("backups/*"),
+                mailbox: Some("backups/laptop".try_into().unwrap()),
+                problem: String::from("3 unread messages, more than the maximum of 2"),
+            }]
+        );
+
+        assert!(check_alerts(&BTreeMap::new(), &messages, now()).is_empty());
+    }
+
+    #[test]
+    fn test_check_heartbeats() {
+        let heartbeat = |mailbox: &str, last_message_hours_ago: i64| Heartbeat {
+            mailbox: mailbox.try_into().unwrap(),
+            interval_seconds: 3600,
+            created: now() - TimeDelta::days(1),
+            last_message: Some(now() - TimeDelta::hours(last_message_hours_ago)),
+        };
+        let violations = check_heartbeats(
+            &[heartbeat("cron/backup", 3), heartbeat("cron/sync", 0)],
+            now(),
+        );
+        assert_eq!(
+            format_violations(&violations),
+            "cron/backup: last message 3h ago (expected hourly) (heartbeat)\n"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+// The text that resets all ANSI styles
+pub const RESET: &str = "\u{1b}[0m";
+
+// A piece of a string that is either a single grapheme cluster or an entire ANSI escape sequence
+#[derive(Debug, Eq, PartialEq)]
+pub enum Token<'a> {
+    Grapheme(&'a str),
+    Escape(&'a str),
+}
+
+// Return the length in bytes of the ANSI escape sequence at the start of the input
+// The input must start with ESC
+fn escape_len(input: &str) -> usize {
+    let mut chars = input.char_indices().skip(1);
+    match chars.next() {
+        // Control Sequence Introducer: parameter and intermediate bytes followed by a final byte
+        Some((_, '[')) => chars
+            .find(|(_, char)| ('\u{40}'..='\u{7e}').contains(char))
+            .map_or(input.len(), |(index, char)| index + char.len_utf8()),
+        // Operating System Command: terminated by BEL or ESC \
+        Some((_, ']')) => {
+            let mut previous = None;
+            chars
+                .find(|&(_, char)| {
+                    let terminated = char == BEL || (previous == Some(ESC) && char == '\\');
+                    previous = Some(char);
+                    terminated
+                })
+                .map_or(input.len(), |(index, char)| index + char.len_utf8())
+        }
+        // Other escape sequences are two characters long
+        Some((index, char)) => index + char.len_utf8(),
+        None => input.len(),
+    }
+}
+
+// Split a string into grapheme clusters and ANSI escape sequences
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let len = if rest.starts_with(ESC) {
+            escape_len(rest)
+        } else {
+            rest.find(ESC).unwrap_or(rest.len())
+        };
+        let (token, remaining) = rest.split_at(len);
+        if token.starts_with(ESC) {
+            tokens.push(Token::Escape(token));
+        } else {
+            tokens.extend(token.graphemes(true).map(Token::Grapheme));
+        }
+        rest = remaining;
+    }
+    tokens
+}
+
+// Calculate the number of columns that a grapheme cluster occupies
+// Terminals render a cluster in at most two columns, even when the widths of its code points, like
+// an emoji and a skin tone modifier, add up to more
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().min(2)
+}
+
+// Remove all ANSI escape sequences from a string
+pub fn strip_ansi(input: &str) -> String {
+    tokenize(input)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Grapheme(grapheme) => Some(grapheme),
+            Token::Escape(_) => None,
+        })
+        .collect()
+}
+
+// Calculate the number of columns that a string occupies when printed, ignoring ANSI escape
+// sequences
+pub fn display_width(input: &str) -> usize {
+    tokenize(input)
+        .into_iter()
+        .map(|token| match token {
+            Token::Grapheme(grapheme) => grapheme_width(grapheme),
+            Token::Escape(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("plain ⭐"), "plain ⭐");
+        assert_eq!(strip_ansi("\u{1b}[1;31mred\u{1b}[0m text"), "red text");
+        assert_eq!(
+            strip_ansi("\u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{1b}\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi("a\u{1b}cb"), "ab");
+        // Unterminated sequences are removed entirely
+        assert_eq!(strip_ansi("a\u{1b}[31"), "a");
+        assert_eq!(strip_ansi("a\u{1b}"), "a");
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("hello"), 5);
+        assert_eq!(display_width("\u{1b}[31mhello\u{1b}[0m"), 5);
+        assert_eq!(display_width("\u{1b}[31m⭐\u{1b}[0m"), 2);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("👍\u{1f3fd}"), 2);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("a\u{1b}[31mbe\u{301}"),
+            vec![
+                Token::Grapheme("a"),
+                Token::Escape("\u{1b}[31m"),
+                Token::Grapheme("b"),
+                Token::Grapheme("e\u{301}")
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::cli::ImportMessageFormat;
+use crate::import::{import_stdin, ImportOptions};
+use anyhow::{Context, Result};
+use database::{ArchivedMessage, Backend, Database, Filter, Message, NewMessage};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Determine whether an archive file is compressed with zstd based on its extension
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+// Convert an archived message back into a new message that recreates it when imported
+fn to_new_message(archived: ArchivedMessage) -> NewMessage {
+    let ArchivedMessage {
+        message,
+        attachments,
+    } = archived;
+    NewMessage {
+        mailbox: message.mailbox,
+        content: message.content,
+        state: Some(message.state),
+        tags: message.tags,
+        timestamp: Some(message.timestamp),
+        external_id: message.external_id,
+        attachments,
+        priority: Some(message.priority),
+        kind: Some(message.kind),
+    }
+}
+
+// Write messages to a writer as JSON lines in the format that `import --format=json` reads
+fn write_messages(writer: &mut impl Write, messages: &[NewMessage]) -> Result<()> {
+    for message in messages {
+        serde_json::to_writer(&mut *writer, message)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Write messages to an archive file, compressing them if the path ends in .zst
+fn write_archive(file: File, path: &Path, messages: &[NewMessage]) -> Result<()> {
+    let mut writer = BufWriter::new(file);
+    if is_compressed(path) {
+        let mut encoder = zstd::Encoder::new(&mut writer, 0)?;
+        write_messages(&mut encoder, messages)?;
+        encoder.finish()?;
+    } else {
+        write_messages(&mut writer, messages)?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(std::io::IntoInnerError::into_error)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// Write archived messages to a new archive file
+// An existing file is never overwritten so that an earlier archive can't be lost, and a partially
+// written archive is removed.
+fn create_archive(path: &Path, messages: &[ArchivedMessage]) -> Result<()> {
+    // Imports give the first message of each batch the newest id, so keeping the messages in the
+    // order they were loaded lets importing the archive recreate that order
+    let new_messages = messages
+        .iter()
+        .cloned()
+        .map(to_new_message)
+        .collect::<Vec<_>>();
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create archive file {}", path.display()))?;
+    if let Err(err) = write_archive(file, path, &new_messages) {
+        let _ = fs::remove_file(path);
+        return Err(err)
+            .with_context(|| format!("Failed to write archive file {}", path.display()));
+    }
+    Ok(())
+}
+
+// Export the messages that match the filter to a new archive file and delete them from the
+// database, returning the number of archived messages
+// The backend selects and deletes the messages in one transaction that it only commits after the
+// archive is completely written and synced to disk, so a failure never loses messages, and
+// messages that change in the meantime can't be deleted with a stale copy in the archive.
+pub async fn archive_to_file<B: Backend>(
+    db: &Database<B>,
+    filter: Filter,
+    path: &Path,
+) -> Result<usize> {
+    let path = path.to_owned();
+    let archived = db
+        .archive_messages(
+            filter,
+            Box::new(move |messages| create_archive(&path, messages)),
+        )
+        .await?;
+    Ok(archived.len())
+}
+
+// Open an archive file for reading, decompressing it if the path ends in .zst
+fn open_archive(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open archive file {}", path.display()))?;
+    Ok(if is_compressed(path) {
+        Box::new(BufReader::new(zstd::Decoder::new(file)?))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+// Restore the messages in an archive file created by archive_to_file
+// The messages are restored verbatim, so the configured overrides aren't applied to them again.
+pub async fn import_from_file<B: Backend>(
+    db: &Database<B>,
+    path: &Path,
+    upsert: bool,
+    report_progress: bool,
+) -> Result<Vec<Message>> {
+    let options = ImportOptions {
+        format: ImportMessageFormat::Json,
+        tags: vec![],
+        // The archived content was already processed when it was originally added
+        keep_ansi: true,
+        upsert,
+        resume_from: 0,
+        checkpoint: None,
+        report_progress,
+        concurrency: 1,
+    };
+    let (messages, _) = import_stdin(db, None, open_archive(path)?, options).await?;
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::{Attachment, MemoryBackend, State};
+
+    // Create a temporary archive path that is unique to this test
+    fn get_archive_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mailbox-archive-{}-{name}", std::process::id()))
+    }
+
+    // Create a database with two messages in old-project before 2024 and one after
+    async fn make_db() -> Result<Database<MemoryBackend>> {
+        let db = Database::new(MemoryBackend::new());
+        let timestamp = |date: &str| -> Result<_> {
+            Ok(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")?.and_hms_opt(0, 0, 0))
+        };
+        db.add_messages(vec![
+            NewMessage {
+                mailbox: "old-project".try_into()?,
+                content: String::from("old message"),
+                state: Some(State::Archived),
+                tags: vec!["urgent".try_into()?],
+                timestamp: timestamp("2023-06-01")?,
+                external_id: Some(String::from("build-1")),
+                attachments: vec![Attachment::Reference {
+                    location: String::from("https://example.com/build/1"),
+                }],
+                priority: None,
+                kind: None,
+            },
+            NewMessage {
+                mailbox: "old-project/ci".try_into()?,
+                content: String::from("old child message"),
+                state: Some(State::Read),
+                tags: vec![],
+                timestamp: timestamp("2023-12-31")?,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            },
+            NewMessage {
+                mailbox: "old-project".try_into()?,
+                content: String::from("new message"),
+                state: Some(State::Unread),
+                tags: vec![],
+                timestamp: timestamp("2024-01-01")?,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            },
+        ])
+        .await?;
+        Ok(db)
+    }
+
+    async fn round_trip(name: &str) -> Result<()> {
+        let db = make_db().await?;
+        let path = get_archive_path(name);
+        let _ = fs::remove_file(&path);
+        let before = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap();
+        let filter = Filter::new()
+            .with_mailbox("old-project".try_into()?)
+            .with_before(before);
+        let archived = db.load_messages(filter.clone()).await?;
+        let mut archived_attachments = vec![];
+        for message in &archived {
+            archived_attachments.push(db.load_attachments(message.id).await?);
+        }
+        assert_eq!(archive_to_file(&db, filter.clone(), &path).await?, 2);
+
+        // The archived messages are removed from the database
+        let remaining = db.load_messages(Filter::new()).await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].content, "new message");
+
+        // An existing archive is never overwritten, and the messages stay in the database
+        assert!(archive_to_file(&db, Filter::new(), &path).await.is_err());
+        assert!(path.exists());
+        assert_eq!(db.load_messages(Filter::new()).await?.len(), 1);
+
+        // Importing the archive recreates the messages
+        let restored = import_from_file(&db, &path, false, false).await;
+        fs::remove_file(&path)?;
+        assert_eq!(restored?.len(), 2);
+        let restored = db.load_messages(filter).await?;
+        assert_eq!(restored.len(), archived.len());
+        for ((restored, archived), attachments) in
+            restored.iter().zip(archived).zip(archived_attachments)
+        {
+            assert_eq!(restored.mailbox, archived.mailbox);
+            assert_eq!(restored.content, archived.content);
+            assert_eq!(restored.state, archived.state);
+            assert_eq!(restored.tags, archived.tags);
+            assert_eq!(restored.timestamp, archived.timestamp);
+            assert_eq!(restored.external_id, archived.external_id);
+            assert_eq!(db.load_attachments(restored.id).await?, attachments);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_compressed() -> Result<()> {
+        round_trip("compressed.jsonl.zst").await
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_uncompressed() -> Result<()> {
+        round_trip("uncompressed.jsonl").await
+    }
+
+    #[tokio::test]
+    async fn test_archive_nothing() -> Result<()> {
+        let db = make_db().await?;
+        let path = get_archive_path("empty.jsonl.zst");
+        let filter = Filter::new().with_mailbox("missing".try_into()?);
+        assert_eq!(archive_to_file(&db, filter, &path).await?, 0);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
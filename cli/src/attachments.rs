@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use database::{Attachment, Id};
+use std::fs::{create_dir_all, metadata, read, write};
+use std::path::{Path, PathBuf};
+
+// Create an attachment from a command-line value
+// URLs and files larger than the maximum attachment size are stored as references, and smaller
+// files are stored in the database.
+pub fn make_attachment(value: &str, max_size: usize) -> Result<Attachment> {
+    if value.starts_with("http://") || value.starts_with("https://") {
+        return Ok(Attachment::Reference {
+            location: value.to_owned(),
+        });
+    }
+
+    let path = Path::new(value);
+    let size = metadata(path)
+        .with_context(|| format!("Failed to read attachment {value}"))?
+        .len();
+    if usize::try_from(size).is_ok_and(|size| size <= max_size) {
+        let name = path
+            .file_name()
+            .with_context(|| format!("Attachment {value} is not a file"))?
+            .to_string_lossy()
+            .into_owned();
+        let data = read(path).with_context(|| format!("Failed to read attachment {value}"))?;
+        Ok(Attachment::Blob { name, data })
+    } else {
+        // Store the absolute path so that the reference works from any directory
+        let location = path
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve attachment {value}"))?
+            .to_string_lossy()
+            .into_owned();
+        Ok(Attachment::Reference { location })
+    }
+}
+
+// Return the path that a message's attached blob is saved to before it is opened
+fn get_blob_path(message_id: Id, name: &str) -> PathBuf {
+    // Only keep the final component of the name so that it can't escape the directory
+    let name = Path::new(name).file_name().map_or_else(
+        || String::from("attachment"),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    std::env::temp_dir()
+        .join("mailbox-attachments")
+        .join(format!("{message_id}-{name}"))
+}
+
+// Open a message's attachment with the default program, first saving it to a temporary file if
+// it is stored in the database
+pub fn open_attachment(message_id: Id, attachment: &Attachment) -> Result<()> {
+    let location = match attachment {
+        Attachment::Blob { name, data } => {
+            let path = get_blob_path(message_id, name);
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir).context("Failed to create attachments directory")?;
+            }
+            write(&path, data).context("Failed to save attachment")?;
+            path.to_string_lossy().into_owned()
+        }
+        Attachment::Reference { location } => location.clone(),
+    };
+    webbrowser::open(&location).with_context(|| format!("Failed to open attachment {location}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_attachment() -> Result<()> {
+        assert_eq!(
+            make_attachment("https://example.com/build/1", 0)?,
+            Attachment::Reference {
+                location: String::from("https://example.com/build/1")
+            }
+        );
+
+        let dir = std::env::temp_dir().join(format!("mailbox-test-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let path = dir.join("report.txt");
+        write(&path, "report")?;
+        let value = path.to_string_lossy();
+        assert_eq!(
+            make_attachment(&value, 6)?,
+            Attachment::Blob {
+                name: String::from("report.txt"),
+                data: b"report".to_vec(),
+            }
+        );
+        assert_eq!(
+            make_attachment(&value, 5)?,
+            Attachment::Reference {
+                location: path.canonicalize()?.to_string_lossy().into_owned()
+            }
+        );
+        assert!(make_attachment(&dir.join("missing.txt").to_string_lossy(), 6).is_err());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_blob_path() {
+        let dir = std::env::temp_dir().join("mailbox-attachments");
+        assert_eq!(get_blob_path(1, "report.txt"), dir.join("1-report.txt"));
+        assert_eq!(get_blob_path(2, "../../etc/passwd"), dir.join("2-passwd"));
+        assert_eq!(get_blob_path(3, ".."), dir.join("3-attachment"));
+    }
+}
@@ -1,5 +1,13 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
 use clap::{Parser, ValueEnum};
-use database::Mailbox;
+use database::{Id, Mailbox, Tag};
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Clone, ValueEnum)]
 pub enum AddMessageState {
@@ -8,13 +16,22 @@ pub enum AddMessageState {
     Archived,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+pub enum AddMessageKind {
+    Text,
+    Url,
+    Markdown,
+    Json,
+}
+
 #[derive(Clone, Copy, ValueEnum)]
 pub enum ImportMessageFormat {
     Json,
     Tsv,
 }
 
-#[derive(Clone, Copy, ValueEnum)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum ViewMessageState {
     Unread,
     Read,
@@ -23,11 +40,142 @@ pub enum ViewMessageState {
     All,
 }
 
-#[derive(Clone, Copy, ValueEnum)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
 pub enum TimestampFormat {
     Relative,
     Local,
     Utc,
+    // A strftime format string like "%H:%M" applied to the local time
+    Custom(String),
+}
+
+// Parse a timestamp format from the --timestamp-format flag or the config file
+// Accepts "relative", "local", "utc", or "custom:<strftime>"
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "relative" => Ok(Self::Relative),
+            "local" => Ok(Self::Local),
+            "utc" => Ok(Self::Utc),
+            _ => {
+                let format = value.strip_prefix("custom:").ok_or_else(|| {
+                    format!(
+                        "invalid timestamp format \"{value}\" (expected relative, local, utc, or custom:<strftime>)"
+                    )
+                })?;
+                if format.is_empty() || StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(format!("invalid strftime format \"{format}\""));
+                }
+                Ok(Self::Custom(format.to_owned()))
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for TimestampFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value.parse()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum MessageSort {
+    Timestamp,
+    Mailbox,
+    State,
+    LastViewed,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StatsBucket {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Mbox,
+    Maildir,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum MailboxesFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DigestFormat {
+    Text,
+    Markdown,
+}
+
+// The description of the exit codes that is shown in --help, which must match exit_code::Exit
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success
+  1  Failure
+  2  Invalid command line arguments
+  3  No messages matched (view, read, archive, clear, and delete-mailbox)
+  4  Couldn't connect to the server
+  5  The server rejected the API token
+  6  Invalid message or request
+  7  An alert rule was violated or a heartbeat is overdue (check, heartbeat list)";
+
+// Parse a duration like "90m", "12h", "30d", or "4w"
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit_start = value
+        .find(|char: char| !char.is_ascii_digit())
+        .ok_or_else(|| String::from("missing unit (m, h, d, or w)"))?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| format!("invalid amount \"{amount}\""))?;
+    let unit_seconds = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid unit \"{unit}\" (expected m, h, d, or w)")),
+    };
+    amount
+        .checked_mul(unit_seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| String::from("duration is too large"))
+}
+
+// Parse a workspace name, which can only contain lowercase letters, digits, '-', and '_' because it
+// is part of the workspace's database filename
+pub fn parse_workspace(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.len() > 64 {
+        return Err(String::from("must be between 1 and 64 characters"));
+    }
+    if !value.chars().all(|char| {
+        char.is_ascii_lowercase() || char.is_ascii_digit() || char == '-' || char == '_'
+    }) {
+        return Err(String::from(
+            "must only contain lowercase letters, digits, '-', and '_'",
+        ));
+    }
+    Ok(value.to_owned())
 }
 
 #[derive(Parser)]
@@ -39,19 +187,238 @@ pub enum ConfigSubcommand {
     Edit,
 }
 
+#[derive(Parser)]
+pub enum TokensSubcommand {
+    /// List the server's API tokens
+    List,
+
+    /// Create an API token and print its secret
+    Create {
+        /// Token name, which can only contain letters, numbers, '-', '_', and '.'
+        name: String,
+
+        /// Only allow the token to read mailboxes matching this glob pattern, like 'ci/*', and can
+        /// be repeated. Without any patterns, the token can't read any mailboxes.
+        #[clap(long, value_name = "PATTERN", num_args = 0..=1)]
+        read: Option<Vec<String>>,
+
+        /// Only allow the token to add, change, and delete messages in mailboxes matching this glob
+        /// pattern, and can be repeated. Without any patterns, the token can't write any mailboxes.
+        #[clap(long, value_name = "PATTERN", num_args = 0..=1)]
+        write: Option<Vec<String>>,
+    },
+
+    /// Revoke an API token
+    Revoke {
+        /// Token name
+        name: String,
+    },
+}
+
+#[derive(Parser)]
+pub enum AdminSubcommand {
+    /// Show statistics about the server's database
+    Stats,
+
+    /// Reclaim unused space in the server's database
+    Compact,
+
+    /// Manage the API tokens that authorize requests to the server
+    Tokens {
+        #[clap(subcommand)]
+        subcommand: TokensSubcommand,
+    },
+
+    /// Show who added, changed, and deleted messages and tokens, newest first
+    Audit {
+        /// Maximum number of entries to show
+        #[clap(long, short = 'n', default_value_t = 100)]
+        limit: usize,
+    },
+
+    /// Show whether messages were delivered to push notification services, newest first
+    Forwards {
+        /// Maximum number of deliveries to show
+        #[clap(long, short = 'n', default_value_t = 100)]
+        limit: usize,
+    },
+}
+
+#[derive(Parser)]
+pub enum IngestSubcommand {
+    /// Create unread messages in calendar/<name> for the events in an iCalendar file that start
+    /// soon, updating them when the events change
+    Ics {
+        /// Path or http(s) or webcal URL of the iCalendar file
+        source: String,
+
+        /// Include events that start within this long from now, like "12h" or "2d"
+        #[clap(long, value_parser = parse_duration, default_value = "1d")]
+        lookahead: Duration,
+
+        /// The calendar's name in the mailbox, which defaults to the name in the file or the
+        /// file's name
+        #[clap(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+pub enum HeartbeatSubcommand {
+    /// Expect a mailbox to receive a message at least once per interval
+    Set {
+        /// Mailbox name
+        mailbox: Mailbox,
+
+        /// The longest that the mailbox can go without a message, like "1h" or "1d"
+        #[clap(value_parser = parse_duration)]
+        interval: Duration,
+    },
+
+    /// Stop expecting a mailbox to receive messages regularly
+    Remove {
+        /// Mailbox name
+        mailbox: Mailbox,
+    },
+
+    /// List the heartbeat mailboxes and when they last received a message, exiting with a non-zero
+    /// code if any of them are overdue
+    List,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum MailboxConfigField {
+    Description,
+    Color,
+    DefaultState,
+    Quota,
+    Heartbeat,
+    PinnedOrder,
+}
+
+#[derive(Parser)]
+pub enum MailboxConfigSubcommand {
+    /// Print a mailbox's metadata
+    Show {
+        /// Mailbox name
+        mailbox: Mailbox,
+    },
+
+    /// List the metadata of every mailbox that has any
+    List,
+
+    /// Change a mailbox's metadata, keeping the fields that aren't provided
+    Set {
+        /// Mailbox name
+        mailbox: Mailbox,
+
+        /// What the mailbox's messages are about
+        #[clap(long)]
+        description: Option<String>,
+
+        /// The color that clients show the mailbox in, like "red" or "#ff8800"
+        #[clap(long)]
+        color: Option<String>,
+
+        /// The state that new messages in the mailbox start in when they don't specify one
+        #[clap(value_enum, long)]
+        default_state: Option<AddMessageState>,
+
+        /// The maximum number of unread and read messages in the mailbox, not counting its
+        /// descendants
+        #[clap(long)]
+        quota: Option<u32>,
+
+        /// The longest that the mailbox can go without a message, like "1h" or "1d"
+        #[clap(long, value_parser = parse_duration)]
+        heartbeat: Option<Duration>,
+
+        /// Where the mailbox is listed among its pinned siblings, lowest first
+        #[clap(long)]
+        pinned_order: Option<u32>,
+
+        /// Remove a field from the metadata (can be repeated)
+        #[clap(value_enum, long)]
+        unset: Vec<MailboxConfigField>,
+    },
+
+    /// Remove all of a mailbox's metadata
+    Remove {
+        /// Mailbox name
+        mailbox: Mailbox,
+    },
+}
+
+#[derive(Parser)]
+pub enum WorkspaceSubcommand {
+    /// List the workspaces that have a local database, marking the active one
+    List,
+}
+
+#[derive(Parser)]
+pub enum DbSubcommand {
+    /// Copy a consistent snapshot of the local database to a new file, even while it is in use
+    Backup {
+        /// The backup file to create
+        dest: PathBuf,
+    },
+
+    /// Print where the database is, its size, and how many messages it contains
+    Info,
+}
+
 #[derive(Parser)]
 pub enum Command {
     /// Add a message to a mailbox
     Add {
         /// Mailbox name
-        mailbox: Mailbox,
+        #[clap(required_unless_present = "edit")]
+        mailbox: Option<Mailbox>,
 
         /// Message content
-        content: String,
+        #[clap(required_unless_present = "edit")]
+        content: Option<String>,
+
+        /// Write the message in $EDITOR, starting with the provided mailbox, content, state, and
+        /// tags
+        #[clap(long)]
+        edit: bool,
 
-        /// Mailbox state
-        #[clap(value_enum, short = 's', long, default_value = "unread")]
-        state: AddMessageState,
+        /// Message state [default: the mailbox's default state, or unread]
+        #[clap(value_enum, short = 's', long)]
+        state: Option<AddMessageState>,
+
+        /// Tag to attach to the message (can be repeated)
+        #[clap(short = 't', long = "tag")]
+        tags: Vec<Tag>,
+
+        /// Identifier for the message that is unique within the mailbox
+        #[clap(short = 'e', long)]
+        external_id: Option<String>,
+
+        /// Update the existing message with the same external ID instead of adding a new message
+        #[clap(long, requires = "external_id")]
+        upsert: bool,
+
+        /// Priority of the message, which is claimed before messages with lower priorities (defaults
+        /// to 0)
+        #[clap(short = 'p', long, allow_negative_numbers = true)]
+        priority: Option<i32>,
+
+        /// Format of the message content, which determines how it is displayed and opened
+        /// (detected from the content if omitted)
+        #[clap(value_enum, short = 'k', long)]
+        kind: Option<AddMessageKind>,
+
+        /// File or URL to attach to the message (can be repeated). Files larger than the maximum
+        /// attachment size are attached by path instead of being stored in the database.
+        #[clap(short = 'a', long = "attach")]
+        attachments: Vec<String>,
+
+        /// Sign the message with this producer secret so that a server that has the secret
+        /// accepts it without a token (only used with an HTTP database)
+        #[clap(long, env = "MAILBOX_SIGN_KEY", hide_env_values = true)]
+        sign_key: Option<String>,
     },
 
     /// Add multiple messages
@@ -59,6 +426,78 @@ pub enum Command {
         /// Import format
         #[clap(value_enum, long, default_value = "tsv")]
         format: ImportMessageFormat,
+
+        /// Tag to attach to every imported message (can be repeated)
+        #[clap(short = 't', long = "tag")]
+        tags: Vec<Tag>,
+
+        /// Update existing messages with the same external IDs instead of adding new messages
+        #[clap(long)]
+        upsert: bool,
+
+        /// Skip the first lines of input because an earlier import already imported them
+        #[clap(long, value_name = "LINES", default_value_t = 0)]
+        resume_from: usize,
+
+        /// Record the number of imported lines in a file after every batch and resume from it if
+        /// it exists, so that an interrupted import can continue where it left off
+        #[clap(long, value_name = "FILE")]
+        checkpoint: Option<PathBuf>,
+
+        /// The number of batches of messages to insert at the same time, which speeds up
+        /// importing to a remote server
+        #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+    },
+
+    /// Create messages from external sources
+    Ingest {
+        #[clap(subcommand)]
+        subcommand: IngestSubcommand,
+    },
+
+    /// Move messages into an archive file and delete them from the database
+    ArchiveToFile {
+        /// Only archive messages in a particular mailbox
+        #[clap(short = 'm', long)]
+        mailbox: Option<Mailbox>,
+
+        /// Only archive messages added before this date (in UTC), like "2024-01-01"
+        #[clap(long)]
+        before: Option<NaiveDate>,
+
+        /// The archive file to create, which contains JSON lines and is compressed with zstd if its
+        /// name ends in ".zst"
+        path: PathBuf,
+    },
+
+    /// Export messages as email so that they can be read in mail clients like mutt
+    Export {
+        /// Export format
+        #[clap(value_enum, long)]
+        format: ExportFormat,
+
+        /// Only export messages in a particular mailbox
+        #[clap(short = 'm', long)]
+        mailbox: Option<Mailbox>,
+
+        /// Only export messages in a particular state
+        #[clap(value_enum, short = 's', long, default_value = "all")]
+        state: ViewMessageState,
+
+        /// The mbox file to create, which defaults to stdout, or the Maildir directory to write to,
+        /// which is required
+        path: Option<PathBuf>,
+    },
+
+    /// Add the messages in a file created by archive-to-file
+    ImportFromFile {
+        /// The archive file to import
+        path: PathBuf,
+
+        /// Update existing messages with the same external IDs instead of adding new messages
+        #[clap(long)]
+        upsert: bool,
     },
 
     /// View messages
@@ -67,13 +506,48 @@ pub enum Command {
         #[clap(short = 'm', long)]
         mailbox: Option<Mailbox>,
 
-        /// Only view messages in a particular state
-        #[clap(value_enum, short = 's', long, default_value = "unread")]
-        state: ViewMessageState,
+        /// Only view messages in a particular state (defaults to the configured state or unread)
+        #[clap(value_enum, short = 's', long)]
+        state: Option<ViewMessageState>,
+
+        /// Only view messages in a custom state defined in the config file
+        #[clap(long, conflicts_with = "state")]
+        custom_state: Option<String>,
+
+        /// Only view messages with a particular tag (can be repeated to match any of the tags)
+        #[clap(short = 't', long = "tag")]
+        tags: Vec<Tag>,
 
         /// Show all messages in output instead of summarizing
         #[clap(short = 'f', long)]
         full_output: bool,
+
+        /// Order messages by a field instead of grouping them by mailbox, newest first
+        #[clap(value_enum, long)]
+        sort: Option<MessageSort>,
+
+        /// Reverse the order of the messages, like oldest first when combined with --sort=timestamp
+        #[clap(short = 'r', long)]
+        reverse: bool,
+
+        /// Show one line per mailbox with its newest message and the number of older messages
+        #[clap(long, conflicts_with_all = ["full_output", "sort", "reverse"])]
+        summary: bool,
+
+        /// Print only the groups captured by this regex in each message's content, separated by
+        /// tabs, skipping messages that don't match
+        #[clap(long, conflicts_with = "summary")]
+        extract: Option<String>,
+    },
+
+    /// Open the URL in the newest message in a mailbox in a web browser
+    Open {
+        /// Mailbox name
+        mailbox: Mailbox,
+
+        /// Open the message with this external ID instead of the newest message
+        #[clap(short = 'e', long)]
+        external_id: Option<String>,
     },
 
     /// Mark unread messages as read
@@ -81,6 +555,14 @@ pub enum Command {
         /// Only read messages in a particular mailbox
         #[clap(short = 'm', long)]
         mailbox: Option<Mailbox>,
+
+        /// Print the messages that would be read without changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Skip the confirmation prompt when many messages would be read
+        #[clap(short = 'y', long)]
+        yes: bool,
     },
 
     /// Archive all read and unread messages
@@ -88,6 +570,14 @@ pub enum Command {
         /// Only archive messages in a particular mailbox
         #[clap(short = 'm', long)]
         mailbox: Option<Mailbox>,
+
+        /// Print the messages that would be archived without changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Skip the confirmation prompt when many messages would be archived
+        #[clap(short = 'y', long)]
+        yes: bool,
     },
 
     /// Permanently clear archived messages
@@ -95,6 +585,162 @@ pub enum Command {
         /// Only clear archived messages in a particular mailbox
         #[clap(short = 'm', long)]
         mailbox: Option<Mailbox>,
+
+        /// Print the messages that would be cleared without changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Skip the confirmation prompt when many messages would be cleared
+        #[clap(short = 'y', long)]
+        yes: bool,
+    },
+
+    /// Move messages into a built-in state or a custom state defined in the config file
+    Mark {
+        /// The state to move the messages into, like "read" or "in-progress"
+        state: String,
+
+        /// The ids of the messages
+        #[clap(required = true)]
+        ids: Vec<Id>,
+    },
+
+    /// Claim unread messages for a worker, most urgent and then oldest first, so that other workers
+    /// don't process them too
+    Claim {
+        /// Only claim messages in a particular mailbox
+        #[clap(short = 'm', long)]
+        mailbox: Option<Mailbox>,
+
+        /// The id of the worker claiming the messages
+        #[clap(short = 'w', long)]
+        worker: String,
+
+        /// How long the worker has to process the messages before they return to unread, like
+        /// "5m" or "1h"
+        #[clap(long, value_parser = parse_duration, default_value = "5m")]
+        lease: Duration,
+
+        /// The maximum number of messages to claim
+        #[clap(short = 'n', long, default_value_t = 1)]
+        limit: usize,
+
+        /// Only claim messages with at least this priority
+        #[clap(long, allow_negative_numbers = true)]
+        min_priority: Option<i32>,
+    },
+
+    /// Release a claimed message that failed to process so that it can be claimed again, or move it
+    /// into the dead-letter mailbox once it has been claimed too many times
+    Nack {
+        /// The id of the claimed message
+        id: Id,
+
+        /// Why the message failed, which is recorded if it is moved into the dead-letter mailbox
+        #[clap(short = 'r', long)]
+        reason: String,
+    },
+
+    /// Schedule a reminder that returns a message to unread later, marking it as read until then
+    Remind {
+        /// The id of the message
+        id: Id,
+
+        /// How long until the message returns to unread, like "2h" or "3d"
+        #[clap(long = "in", value_parser = parse_duration)]
+        delay: Duration,
+    },
+
+    /// Link two related messages, like an alert and the message that resolves it
+    Link {
+        /// The id of the first message
+        id: Id,
+
+        /// The id of the message to link it to
+        related_id: Id,
+    },
+
+    /// Permanently delete all messages in a mailbox and its child mailboxes, regardless of state
+    DeleteMailbox {
+        /// Mailbox name
+        mailbox: Mailbox,
+
+        /// Print the messages that would be deleted without changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Skip the confirmation prompt
+        #[clap(short = 'y', long)]
+        yes: bool,
+    },
+
+    /// List mailboxes and the number of messages in each state
+    Mailboxes {
+        /// Only count messages in a particular state
+        #[clap(value_enum, short = 's', long, default_value = "all")]
+        state: ViewMessageState,
+
+        /// Show mailboxes indented under their parent mailbox
+        #[clap(long)]
+        tree: bool,
+
+        /// Output format (defaults to the --output format)
+        #[clap(value_enum, long)]
+        format: Option<MailboxesFormat>,
+    },
+
+    /// Summarize how many messages were added, read, and archived over time
+    Stats {
+        /// Only count messages in a particular mailbox
+        #[clap(short = 'm', long)]
+        mailbox: Option<Mailbox>,
+
+        /// Only count messages added within this long ago, like "12h", "30d", or "4w"
+        #[clap(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+
+        /// Group messages into buckets of this length
+        #[clap(value_enum, short = 'b', long, default_value = "day")]
+        bucket: StatsBucket,
+
+        /// Output format
+        #[clap(value_enum, long, default_value = "text")]
+        format: StatsFormat,
+    },
+
+    /// Summarize recent messages in a single digest
+    Digest {
+        /// Only summarize messages in a particular mailbox
+        #[clap(short = 'm', long)]
+        mailbox: Option<Mailbox>,
+
+        /// Summarize messages added within this long ago, like "12h", "1d", or "1w"
+        #[clap(long, value_parser = parse_duration, default_value = "1d")]
+        since: Duration,
+
+        /// Output format
+        #[clap(value_enum, long, default_value = "text")]
+        format: DigestFormat,
+
+        /// Add the digest as a new message in this mailbox instead of printing it
+        #[clap(long)]
+        post: Option<Mailbox>,
+    },
+
+    /// Check the alert rules in the config file, printing the violated rules and exiting with a
+    /// non-zero code if there are any
+    Check,
+
+    /// Track mailboxes that are expected to receive messages regularly
+    Heartbeat {
+        #[clap(subcommand)]
+        subcommand: HeartbeatSubcommand,
+    },
+
+    /// Manage the metadata attached to mailboxes, like their descriptions and quotas
+    MailboxConfig {
+        #[clap(subcommand)]
+        subcommand: MailboxConfigSubcommand,
     },
 
     /// Open an interactive terminal UI to interact with messages
@@ -103,9 +749,86 @@ pub enum Command {
         #[clap(short = 'm', long)]
         mailbox: Option<Mailbox>,
 
-        /// Set the initial message state filter to particular states
-        #[clap(value_enum, short = 's', long, default_value = "unread")]
-        state: ViewMessageState,
+        /// Set the initial message state filter to particular states (defaults to the configured
+        /// state or unread)
+        #[clap(value_enum, short = 's', long)]
+        state: Option<ViewMessageState>,
+    },
+
+    /// Run the HTTP server in-process against the configured sqlite database
+    Serve {
+        /// The port that the HTTP server will listen on
+        #[clap(short = 'p', long, default_value = "8080", env = "PORT")]
+        port: u16,
+
+        /// Accept connections from the local network, i.e. bind to 0.0.0.0 instead of 127.0.0.1
+        #[clap(short = 'e', long)]
+        expose: bool,
+
+        /// Require all requests to have an "Authorization: Bearer" header containing this token
+        #[clap(long, env = "MAILBOX_AUTH_TOKEN", hide_env_values = true)]
+        auth_token: Option<String>,
+
+        /// Enable the admin routes and require them to have an "Authorization: Bearer" header
+        /// containing this token
+        #[clap(long, env = "MAILBOX_ADMIN_TOKEN", hide_env_values = true)]
+        admin_token: Option<String>,
+
+        /// The number of seconds to wait for in-flight requests to finish after receiving SIGTERM
+        /// or SIGINT before forcibly stopping
+        #[clap(long, default_value = "30")]
+        shutdown_timeout: u64,
+
+        /// Accept new messages without a token if they are signed with this producer secret (can
+        /// be repeated)
+        #[clap(
+            long = "signing-key",
+            env = "MAILBOX_SIGNING_KEYS",
+            hide_env_values = true,
+            value_delimiter = ','
+        )]
+        signing_keys: Vec<String>,
+
+        /// Accept Slack slash commands at /slack/commands that are signed with this Slack app
+        /// signing secret
+        #[clap(long, env = "MAILBOX_SLACK_SIGNING_SECRET", hide_env_values = true)]
+        slack_signing_secret: Option<String>,
+    },
+
+    /// Administer the remote mailbox server
+    Admin {
+        /// Token that authorizes admin requests, which must match the server's admin token
+        #[clap(long, env = "MAILBOX_ADMIN_TOKEN", hide_env_values = true)]
+        admin_token: String,
+
+        #[clap(subcommand)]
+        subcommand: AdminSubcommand,
+    },
+
+    /// Manage the local sqlite database
+    Db {
+        #[clap(subcommand)]
+        subcommand: DbSubcommand,
+    },
+
+    /// Run a read-only SQL query against the local SQLite database for ad hoc analysis
+    Query {
+        /// The SELECT query to run, like "SELECT mailbox, COUNT(*) FROM message GROUP BY mailbox"
+        #[clap(long)]
+        sql: String,
+    },
+
+    /// Diagnose problems with the config file, database, server, and terminal
+    Doctor,
+
+    /// Let AI assistants list, read, add, and triage messages by serving them as tools over the
+    /// Model Context Protocol on stdin and stdout
+    Mcp,
+
+    /// Manage the workspaces, which each store their messages in a separate local database
+    Workspace {
+        #[clap(subcommand)]
+        subcommand: WorkspaceSubcommand,
     },
 
     /// Manage the configuration
@@ -113,14 +836,33 @@ pub enum Command {
         #[clap(subcommand)]
         subcommand: ConfigSubcommand,
     },
+
+    /// Print the names of all mailboxes for shell completions
+    #[clap(name = "__complete-mailboxes", hide = true)]
+    CompleteMailboxes,
 }
 
+// Global flags are independent switches, so they are naturally bools
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser)]
-#[clap(about, version, author)]
+#[clap(about, version, author, after_help = EXIT_CODES_HELP)]
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Command,
 
+    /// Choose the output format, which also prints errors to stderr as JSON objects when "json"
+    #[clap(value_enum, long, global = true, default_value = "text")]
+    pub output: OutputFormat,
+
+    /// Don't print the messages that commands like add, read, and archive changed
+    #[clap(short = 'q', long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print how long database operations take and which overrides applied to added messages to
+    /// stderr (repeat to also print the arguments of database operations)
+    #[clap(short = 'v', long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
     /// Enable color even when terminal is not a TTY
     #[clap(long, global = true)]
     pub color: bool,
@@ -129,7 +871,51 @@ pub struct Cli {
     #[clap(long, global = true, conflicts_with = "color")]
     pub no_color: bool,
 
-    /// Choose the timestamp format to use (defaults to relative with a TTY and UTC otherwise)
-    #[clap(value_enum, long, global = true)]
+    /// Choose the timestamp format to use: relative, local, utc, or custom:<strftime> like
+    /// "custom:%Y-%m-%d %I:%M %p" (defaults to the configured format or relative with a TTY and local
+    /// otherwise)
+    #[clap(long, global = true)]
     pub timestamp_format: Option<TimestampFormat>,
+
+    /// Display timestamps in a time zone like "Europe/Berlin" instead of the local time zone
+    /// (defaults to the configured time zone)
+    #[clap(long, global = true)]
+    pub timezone: Option<Tz>,
+
+    /// Truncate viewed messages to this many columns, even when the output isn't a terminal
+    /// (defaults to the width of the terminal or no truncation otherwise)
+    #[clap(long, global = true, env = "MAILBOX_WIDTH")]
+    pub width: Option<NonZeroUsize>,
+
+    /// Only print ASCII characters in place of the ellipsis, the cursor, and other Unicode symbols
+    #[clap(long, global = true)]
+    pub ascii: bool,
+
+    /// Keep ANSI escape sequences in added messages and printed output instead of stripping them
+    #[clap(long, global = true)]
+    pub keep_ansi: bool,
+
+    /// Override the configured database with "sqlite", "sqlite:<path>", or an http(s) server URL
+    #[clap(long, global = true, env = "MAILBOX_DATABASE_URL")]
+    pub database: Option<String>,
+
+    /// Override the configured API token of the http database
+    #[clap(long, global = true, env = "MAILBOX_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Read and change this consumer's own message states instead of the shared message states
+    #[clap(long, global = true, env = "MAILBOX_CONSUMER")]
+    pub consumer: Option<String>,
+
+    /// Use a configuration file at a custom path instead of the default location
+    #[clap(long, global = true, env = "MAILBOX_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Store the local database in a custom directory instead of the default location
+    #[clap(long, global = true, env = "MAILBOX_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Use a named workspace's separate local database instead of the default one
+    #[clap(long, global = true, env = "MAILBOX_WORKSPACE", value_parser = parse_workspace)]
+    pub workspace: Option<String>,
 }
@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use database::{Mailbox, State, Tag};
+use std::fs;
+use std::path::Path;
+
+// The instructions appended to the message template, which are removed from the edited message
+const TEMPLATE_HELP: &str = "# Write the message content below the headers and the blank line.
+# Lines starting with '#' are ignored, and an empty message aborts adding the message.
+# Separate multiple tags with commas.";
+
+// A message written in an editor
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComposedMessage {
+    pub mailbox: Mailbox,
+    pub state: State,
+    pub tags: Vec<Tag>,
+    pub content: String,
+}
+
+// Open a file in $EDITOR and wait for the editor to exit
+pub fn open_editor(path: &Path) -> Result<()> {
+    match std::env::var_os("EDITOR") {
+        Some(editor) => {
+            let status = std::process::Command::new(&editor)
+                .arg(path)
+                .status()
+                .with_context(|| format!("Failed to open editor: {}", editor.to_string_lossy()))?;
+            if !status.success() {
+                bail!("Editor {} exited with {status}", editor.to_string_lossy());
+            }
+            Ok(())
+        }
+        None => bail!("$EDITOR environment variable isn't set"),
+    }
+}
+
+// Generate the template that the user edits to compose a message
+fn make_template(mailbox: Option<&Mailbox>, state: State, tags: &[Tag], content: &str) -> String {
+    let mailbox = mailbox.map(ToString::to_string).unwrap_or_default();
+    let tags = tags
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let content = if content.is_empty() {
+        String::new()
+    } else {
+        format!("{content}\n")
+    };
+    format!("mailbox: {mailbox}\nstate: {state}\ntags: {tags}\n\n{content}\n{TEMPLATE_HELP}\n")
+}
+
+// Parse an edited template into a message, returning None if the message content is empty
+fn parse_template(template: &str) -> Result<Option<ComposedMessage>> {
+    let mut lines = template.lines().filter(|line| !line.starts_with('#'));
+
+    let mut mailbox = None;
+    let mut state = State::Unread;
+    let mut tags = vec![];
+    for line in lines.by_ref() {
+        if line.trim().is_empty() {
+            break;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            bail!("Invalid header \"{line}\", expected \"<name>: <value>\"");
+        };
+        let value = value.trim();
+        match key.trim().to_lowercase().as_str() {
+            "mailbox" => {
+                mailbox = if value.is_empty() {
+                    None
+                } else {
+                    Some(value.parse()?)
+                };
+            }
+            "state" => state = value.parse()?,
+            "tags" => {
+                tags = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_>>()?;
+            }
+            key => bail!("Unknown header \"{key}\""),
+        }
+    }
+
+    let content = lines.collect::<Vec<_>>().join("\n");
+    let content = content.trim_matches('\n').trim_end();
+    if content.is_empty() {
+        return Ok(None);
+    }
+    let Some(mailbox) = mailbox else {
+        bail!("The mailbox header must not be empty");
+    };
+    Ok(Some(ComposedMessage {
+        mailbox,
+        state,
+        tags,
+        content: content.to_owned(),
+    }))
+}
+
+// Let the user compose a message in $EDITOR, starting with the provided values, similar to
+// `git commit`
+// Returns None if the user left the message content empty. If the edited message is invalid, the
+// file is kept so that the message isn't lost.
+pub fn compose_message(
+    mailbox: Option<&Mailbox>,
+    state: State,
+    tags: &[Tag],
+    content: &str,
+) -> Result<Option<ComposedMessage>> {
+    let path = std::env::temp_dir().join(format!("mailbox-message-{}.txt", std::process::id()));
+    fs::write(&path, make_template(mailbox, state, tags, content))
+        .with_context(|| format!("Failed to write message file {}", path.display()))?;
+    open_editor(&path)?;
+    let template = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read message file {}", path.display()))?;
+    let message = parse_template(&template)
+        .with_context(|| format!("Invalid message, which was saved to {}", path.display()))?;
+    let _ = fs::remove_file(&path);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let message = ComposedMessage {
+            mailbox: "my-script".try_into()?,
+            state: State::Read,
+            tags: vec!["urgent".try_into()?, "ci".try_into()?],
+            content: String::from("Line 1\n\nLine 3"),
+        };
+        let template = make_template(
+            Some(&message.mailbox),
+            message.state,
+            &message.tags,
+            &message.content,
+        );
+        assert_eq!(
+            template,
+            format!(
+                "mailbox: my-script\nstate: read\ntags: urgent, ci\n\nLine 1\n\nLine 3\n\n{TEMPLATE_HELP}\n"
+            )
+        );
+        assert_eq!(parse_template(&template)?, Some(message));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_template() -> Result<()> {
+        assert_eq!(
+            parse_template("Mailbox: my-script\n\n\nHello, world!  \n\n")?,
+            Some(ComposedMessage {
+                mailbox: "my-script".try_into()?,
+                state: State::Unread,
+                tags: vec![],
+                content: String::from("Hello, world!"),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_template_empty() -> Result<()> {
+        let template = make_template(None, State::Unread, &[], "");
+        assert_eq!(parse_template(&template)?, None);
+        assert_eq!(parse_template("mailbox: my-script\n\n# Hello")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_template_invalid() {
+        assert!(parse_template("mailbox:\n\nHello").is_err());
+        assert!(parse_template("mailbox: my-script\nstate: new\n\nHello").is_err());
+        assert!(parse_template("mailbox: my-script\npriority: high\n\nHello").is_err());
+        assert!(parse_template("mailbox: my-script\ntags: a b\n\nHello").is_err());
+        assert!(parse_template("Hello\n\nworld").is_err());
+    }
+}
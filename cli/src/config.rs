@@ -1,9 +1,19 @@
-use anyhow::{Context, Result};
-use database::{Mailbox, NewMessage, State};
-use serde::Deserialize;
-use std::{collections::HashMap, io::ErrorKind, path::PathBuf};
+use crate::alerts::AlertRule;
+use crate::cli::{TimestampFormat, ViewMessageState};
+use crate::theme::Theme;
+use crate::tui::Pane;
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use database::{
+    validate_custom_state, Bridges, CustomState, DeadLetterPolicy, Forward, Limits, Mailbox,
+    NewMessage, State, TransitionPolicy,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::{io::ErrorKind, path::PathBuf};
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Override {
     Unread,
@@ -12,15 +22,93 @@ pub enum Override {
     Ignored,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "lowercase", tag = "provider")]
 pub enum DatabaseProvider {
-    #[default]
-    Sqlite,
+    Sqlite {
+        // Defaults to mailbox.db in the data directory
+        path: Option<PathBuf>,
+    },
     Http {
         url: String,
         token: Option<String>,
+        // The request timeout in seconds
+        timeout: Option<u64>,
+        // The number of times to retry requests that failed because of network errors
+        retries: Option<u32>,
     },
+    // Messages are only stored for the duration of the command
+    Memory,
+}
+
+impl Default for DatabaseProvider {
+    fn default() -> Self {
+        Self::Sqlite { path: None }
+    }
+}
+
+// Parse a database provider from the --database flag
+// Accepts "sqlite", "sqlite:<path>", "memory", or an http(s) URL
+impl FromStr for DatabaseProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value == "sqlite" {
+            return Ok(Self::Sqlite { path: None });
+        }
+        if value == "memory" {
+            return Ok(Self::Memory);
+        }
+        if let Some(path) = value.strip_prefix("sqlite:") {
+            if path.is_empty() {
+                bail!("SQLite database path must not be empty");
+            }
+            return Ok(Self::Sqlite {
+                path: Some(PathBuf::from(path)),
+            });
+        }
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Ok(Self::Http {
+                url: value.to_owned(),
+                token: None,
+                timeout: None,
+                retries: None,
+            });
+        }
+        bail!("Invalid database {value}, expected sqlite, sqlite:<path>, memory, or an http(s) URL")
+    }
+}
+
+// Defaults for command line flags and the TUI's initial view
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    // The state filter used by view and tui when --state isn't provided
+    pub state: Option<ViewMessageState>,
+
+    // The timestamp format used when --timestamp-format isn't provided
+    pub timestamp_format: Option<TimestampFormat>,
+
+    // The time zone used when --timezone isn't provided
+    pub timezone: Option<Tz>,
+
+    // The pane that is active when the TUI starts
+    pub tui_pane: Option<Pane>,
+
+    // Whether the TUI starts with archived messages shown when --state isn't provided
+    #[serde(default)]
+    pub tui_show_archived: bool,
+
+    // Whether the TUI starts with unread messages sorted first
+    #[serde(default)]
+    pub tui_unread_first: bool,
+
+    // Whether the TUI keeps messages selected while the filters hide them
+    #[serde(default)]
+    pub tui_persistent_selection: bool,
+
+    // The number of messages that read, archive, and clear can change before asking for confirmation
+    pub confirm_threshold: Option<usize>,
 }
 
 #[derive(Default, Deserialize)]
@@ -29,8 +117,57 @@ pub struct Config {
     #[serde(default)]
     overrides: HashMap<String, Override>,
 
+    // Messages in these mailboxes and their descendants are archived when they are read
+    #[serde(default)]
+    pub archive_on_read: Vec<Mailbox>,
+
     #[serde(default)]
     pub database: DatabaseProvider,
+
+    // Limits on the size of new messages
+    #[serde(default)]
+    pub limits: Limits,
+
+    // The state transitions that messages are allowed to make
+    #[serde(default)]
+    pub transitions: TransitionPolicy,
+
+    // Custom states that refine the built-in states, keyed by name
+    #[serde(default)]
+    states: BTreeMap<String, CustomState>,
+
+    // The consumer whose own message states are read and changed instead of the shared states
+    pub consumer: Option<String>,
+
+    // Short names that expand to the full paths of frequently used mailboxes
+    #[serde(default)]
+    pub aliases: HashMap<String, Mailbox>,
+
+    // Where nack moves messages that have failed too many times
+    #[serde(default)]
+    pub dead_letter: DeadLetterPolicy,
+
+    // The push notification services that serve forwards new high-priority messages to
+    #[serde(default)]
+    pub forwards: Vec<Forward>,
+
+    // The chat channels that serve mirrors new messages to
+    #[serde(default)]
+    pub bridges: Bridges,
+
+    // Thresholds that mailboxes must stay within, keyed by mailbox pattern, which check enforces
+    #[serde(default)]
+    pub alerts: BTreeMap<String, AlertRule>,
+
+    // The mailbox that alerts are added to when heartbeat mailboxes go too long without a message
+    pub heartbeat_alerts: Option<Mailbox>,
+
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    // The colors and glyphs of printed messages and the TUI
+    #[serde(default)]
+    pub theme: Theme,
 }
 
 impl Config {
@@ -45,13 +182,57 @@ impl Config {
         }
     }
 
+    // Return the definition of a custom state
+    pub fn get_custom_state(&self, name: &str) -> Result<&CustomState> {
+        validate_custom_state(name)?;
+        self.states
+            .get(name)
+            .with_context(|| format!("Custom state {name} isn't defined in the config file"))
+    }
+
+    // Return the names of the custom states that a command skips because their definition opts out
+    // of it
+    pub fn get_excluded_custom_states(&self, included: fn(&CustomState) -> bool) -> Vec<String> {
+        self.states
+            .iter()
+            .filter(|(_, custom_state)| !included(custom_state))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
     // Return the configured override for the given mailbox if there is one
     pub fn get_override(&self, mailbox: &Mailbox) -> Option<Override> {
+        self.get_override_rule(mailbox)
+            .map(|(_, override_state)| override_state)
+    }
+
+    // Return the configured override for the given mailbox along with the mailbox that it is
+    // configured for, which is the mailbox itself or its closest ancestor with an override
+    pub fn get_override_rule(&self, mailbox: &Mailbox) -> Option<(&str, Override)> {
         let sections = mailbox.as_ref().split('/').collect::<Vec<_>>();
-        (0..sections.len())
-            .rev()
-            .find_map(|index| self.overrides.get(&sections[0..=index].join("/")))
-            .copied()
+        (0..sections.len()).rev().find_map(|index| {
+            self.overrides
+                .get_key_value(&sections[0..=index].join("/"))
+                .map(|(rule, override_state)| (rule.as_str(), *override_state))
+        })
+    }
+
+    // Expand a mailbox whose first section is an alias into the aliased mailbox, keeping the rest of
+    // its sections, so that "inbox/urgent" becomes "personal/todo/urgent" if "inbox" is an alias of
+    // "personal/todo"
+    pub fn resolve_alias(&self, mailbox: Mailbox) -> Mailbox {
+        let name = mailbox.as_ref();
+        let (alias, children) = match name.split_once('/') {
+            Some((alias, children)) => (alias, Some(children)),
+            None => (name, None),
+        };
+        match (self.aliases.get(alias), children) {
+            (Some(target), None) => target.clone(),
+            // Joining two valid mailboxes always produces a valid mailbox
+            (Some(target), Some(children)) => Mailbox::try_from(format!("{target}/{children}"))
+                .unwrap_or_else(|_| mailbox.clone()),
+            (None, _) => mailbox,
+        }
     }
 
     // Take an iterator of new messages and apply the overrides defined in
@@ -73,6 +254,7 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use database::MailboxCharset;
 
     fn load_config(toml: &str) -> Result<Config> {
         Ok(toml::from_str(toml)?)
@@ -83,6 +265,12 @@ mod tests {
             mailbox: mailbox.try_into().unwrap(),
             content: String::from("Content"),
             state: Some(State::Unread),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
         })
     }
 
@@ -97,7 +285,16 @@ mod tests {
             load_config("[database]\nprovider = 'sqlite'\n")
                 .unwrap()
                 .database,
-            DatabaseProvider::Sqlite
+            DatabaseProvider::Sqlite { path: None }
+        );
+
+        assert_eq!(
+            load_config("[database]\nprovider = 'sqlite'\npath = '/tmp/mailbox.db'\n")
+                .unwrap()
+                .database,
+            DatabaseProvider::Sqlite {
+                path: Some(PathBuf::from("/tmp/mailbox.db"))
+            }
         );
     }
 
@@ -111,7 +308,9 @@ mod tests {
                 .database,
             DatabaseProvider::Http {
                 url: String::from("http://localhost:8080"),
-                token: None
+                token: None,
+                timeout: None,
+                retries: None,
             }
         );
 
@@ -123,7 +322,23 @@ mod tests {
             .database,
             DatabaseProvider::Http {
                 url: String::from("http://localhost:8080"),
-                token: Some(String::from("foo"))
+                token: Some(String::from("foo")),
+                timeout: None,
+                retries: None,
+            }
+        );
+
+        assert_eq!(
+            load_config(
+                "[database]\nprovider = 'http'\nurl = 'http://localhost:8080'\ntimeout = 10\nretries = 0"
+            )
+            .unwrap()
+            .database,
+            DatabaseProvider::Http {
+                url: String::from("http://localhost:8080"),
+                token: None,
+                timeout: Some(10),
+                retries: Some(0),
             }
         );
     }
@@ -133,6 +348,147 @@ mod tests {
         assert!(load_config("[database]\nprovider = 'foo'\n").is_err());
     }
 
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!(
+            "sqlite".parse::<DatabaseProvider>().unwrap(),
+            DatabaseProvider::Sqlite { path: None }
+        );
+        assert_eq!(
+            "sqlite:/path/to.db".parse::<DatabaseProvider>().unwrap(),
+            DatabaseProvider::Sqlite {
+                path: Some(PathBuf::from("/path/to.db"))
+            }
+        );
+        assert_eq!(
+            "http://host:8080".parse::<DatabaseProvider>().unwrap(),
+            DatabaseProvider::Http {
+                url: String::from("http://host:8080"),
+                token: None,
+                timeout: None,
+                retries: None,
+            }
+        );
+        assert_eq!(
+            "memory".parse::<DatabaseProvider>().unwrap(),
+            DatabaseProvider::Memory
+        );
+        assert!("sqlite:".parse::<DatabaseProvider>().is_err());
+        assert!("postgres://host".parse::<DatabaseProvider>().is_err());
+    }
+
+    #[test]
+    fn test_load_defaults() {
+        assert_eq!(load_config("").unwrap().defaults, Defaults::default());
+
+        assert_eq!(
+            load_config(
+                "[defaults]\nstate = 'unarchived'\ntimestamp_format = 'utc'\ntimezone = 'Europe/Berlin'\ntui_pane = 'mailboxes'\ntui_show_archived = true\ntui_unread_first = true\ntui_persistent_selection = true\nconfirm_threshold = 500"
+            )
+            .unwrap()
+            .defaults,
+            Defaults {
+                state: Some(ViewMessageState::Unarchived),
+                timestamp_format: Some(TimestampFormat::Utc),
+                timezone: Some(Tz::Europe__Berlin),
+                tui_pane: Some(Pane::Mailboxes),
+                tui_show_archived: true,
+                tui_unread_first: true,
+                tui_persistent_selection: true,
+                confirm_threshold: Some(500),
+            }
+        );
+
+        assert_eq!(
+            load_config("[defaults]\ntimestamp_format = 'custom:%H:%M'\n")
+                .unwrap()
+                .defaults
+                .timestamp_format,
+            Some(TimestampFormat::Custom(String::from("%H:%M")))
+        );
+        assert!(load_config("[defaults]\ntimestamp_format = 'custom:%Q'\n").is_err());
+        assert!(load_config("[defaults]\ntimestamp_format = 'iso'\n").is_err());
+        assert!(load_config("[defaults]\nstate = 'foo'\n").is_err());
+        assert!(load_config("[defaults]\nfoo = 'bar'\n").is_err());
+    }
+
+    #[test]
+    fn test_load_archive_on_read() -> Result<()> {
+        assert!(load_config("")?.archive_on_read.is_empty());
+        assert_eq!(
+            load_config("archive_on_read = ['notifications', 'ci/project-a']\n")?.archive_on_read,
+            vec![
+                Mailbox::try_from("notifications")?,
+                Mailbox::try_from("ci/project-a")?
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_consumer() -> Result<()> {
+        assert_eq!(load_config("")?.consumer, None);
+        assert_eq!(
+            load_config("consumer = 'laptop'\n")?.consumer.as_deref(),
+            Some("laptop")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_dead_letter() -> Result<()> {
+        assert_eq!(load_config("")?.dead_letter, DeadLetterPolicy::default());
+        assert_eq!(
+            load_config("[dead_letter]\nmailbox = 'jobs/failed'\nmax_attempts = 5\n")?.dead_letter,
+            DeadLetterPolicy {
+                mailbox: "jobs/failed".try_into()?,
+                max_attempts: 5,
+            }
+        );
+        assert!(load_config("[dead_letter]\nattempts = 5\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_alias() -> Result<()> {
+        let config = load_config("[aliases]\ninbox = 'personal/todo'\n")?;
+        assert_eq!(
+            config.resolve_alias("inbox".try_into()?),
+            Mailbox::try_from("personal/todo")?
+        );
+        assert_eq!(
+            config.resolve_alias("inbox/urgent".try_into()?),
+            Mailbox::try_from("personal/todo/urgent")?
+        );
+        assert_eq!(
+            config.resolve_alias("personal/inbox".try_into()?),
+            Mailbox::try_from("personal/inbox")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_limits() -> Result<()> {
+        assert_eq!(load_config("")?.limits, Limits::default());
+        assert_eq!(
+            load_config("[limits]\nmax_content_length = 4096\n")?.limits,
+            Limits {
+                max_content_length: 4096,
+                ..Limits::default()
+            }
+        );
+        assert_eq!(
+            load_config("[limits]\nmailbox_charset = 'ascii'\n")?.limits,
+            Limits {
+                mailbox_charset: MailboxCharset::Ascii,
+                ..Limits::default()
+            }
+        );
+        assert!(load_config("[limits]\nmailbox_charset = 'latin1'\n").is_err());
+        assert!(load_config("[limits]\nmax_size = 4096\n").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_load_overrides() {
         assert!(load_config("[overrides]\nfoo = 'unread'\n").is_ok());
@@ -156,6 +512,14 @@ mod tests {
         );
         assert_eq!(config.get_override(&"a".try_into()?), Some(Override::Read));
         assert_eq!(config.get_override(&"b".try_into()?), None);
+        assert_eq!(
+            config.get_override_rule(&"a/b/c/d".try_into()?),
+            Some(("a/b/c", Override::Ignored))
+        );
+        assert_eq!(
+            config.get_override_rule(&"a/b".try_into()?),
+            Some(("a", Override::Read))
+        );
         Ok(())
     }
 
@@ -178,4 +542,27 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_custom_states() -> Result<()> {
+        let config = load_config(
+            "[states.in-progress]\nbase = 'read'\narchive = false\n\n[states.snoozed]\nbase = 'archived'\nview = false\n",
+        )?;
+        assert_eq!(config.get_custom_state("in-progress")?.base, State::Read);
+        assert!(config.get_custom_state("waiting").is_err());
+        assert!(config.get_custom_state("In Progress").is_err());
+        assert_eq!(
+            config.get_excluded_custom_states(|custom_state| custom_state.archive),
+            vec![String::from("in-progress")]
+        );
+        assert_eq!(
+            config.get_excluded_custom_states(|custom_state| custom_state.view),
+            vec![String::from("snoozed")]
+        );
+        assert!(config
+            .get_excluded_custom_states(|custom_state| custom_state.clear)
+            .is_empty());
+        assert!(load_config("[states.in-progress]\nbase = 'started'\n").is_err());
+        Ok(())
+    }
 }
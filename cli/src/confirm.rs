@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use database::Mailbox;
+use std::io::{stderr, stdin, Write};
+
+// Mutations that affect more messages than this need to be confirmed unless configured otherwise
+pub const DEFAULT_CONFIRM_THRESHOLD: usize = 100;
+
+// Format a count with commas separating each group of thousands
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+// Generate the question asking the user to confirm a bulk mutation
+fn format_prompt(action: &str, count: usize, mailbox: Option<&Mailbox>) -> String {
+    let plural = if count == 1 { "" } else { "s" };
+    let location = mailbox.map_or_else(
+        || String::from("in all mailboxes"),
+        |mailbox| format!("in '{mailbox}'"),
+    );
+    format!(
+        "This will {action} {} message{plural} {location}. Continue? [y/N] ",
+        format_count(count)
+    )
+}
+
+// Determine whether the user's answer to the prompt is yes
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+// Ask the user to confirm a bulk mutation, returning true if they agreed
+// Anything other than yes, including no input at all, declines
+pub fn confirm(action: &str, count: usize, mailbox: Option<&Mailbox>) -> Result<bool> {
+    let mut stderr = stderr();
+    write!(stderr, "{}", format_prompt(action, count, mailbox))?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    stdin()
+        .read_line(&mut answer)
+        .context("Failed to read confirmation")?;
+    Ok(is_yes(&answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_count() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1204), "1,204");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    fn test_format_prompt() {
+        assert_eq!(
+            format_prompt("archive", 1204, Some(&"ci".try_into().unwrap())),
+            "This will archive 1,204 messages in 'ci'. Continue? [y/N] "
+        );
+        assert_eq!(
+            format_prompt("clear", 1, None),
+            "This will clear 1 message in all mailboxes. Continue? [y/N] "
+        );
+    }
+
+    #[test]
+    fn test_is_yes() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes("YES"));
+        assert!(!is_yes("n"));
+        assert!(!is_yes(""));
+        assert!(!is_yes("yep"));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::admin::format_bytes;
+use crate::confirm::format_count;
+use chrono::NaiveDateTime;
+use database::{DatabaseInfo, SchemaVersion};
+use std::fmt::Write;
+use std::path::Path;
+
+// Format labeled values as aligned lines
+fn format_rows(rows: &[(&str, String)]) -> String {
+    let mut output = String::new();
+    for (label, value) in rows {
+        let _ = writeln!(output, "{label:<9}  {value}");
+    }
+    output
+}
+
+// Format a schema version, noting when it doesn't match the version that this build supports
+fn format_schema_version(version: SchemaVersion) -> String {
+    if version.current == version.latest {
+        version.current.to_string()
+    } else {
+        format!(
+            "{} (this version of mailbox supports {})",
+            version.current, version.latest
+        )
+    }
+}
+
+fn format_timestamp(timestamp: Option<NaiveDateTime>) -> String {
+    timestamp.map_or_else(
+        || String::from("none"),
+        |timestamp| timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+    )
+}
+
+// Format the location, size, and contents of a local SQLite database
+pub fn format_sqlite_info(
+    path: &Path,
+    info: &DatabaseInfo,
+    database_bytes: u64,
+    wal_bytes: u64,
+) -> String {
+    format_rows(&[
+        ("path", path.display().to_string()),
+        ("schema", format_schema_version(info.schema_version)),
+        ("size", format_bytes(database_bytes)),
+        ("wal", format_bytes(wal_bytes)),
+        ("unread", format_count(info.unread_count)),
+        ("read", format_count(info.read_count)),
+        ("archived", format_count(info.archived_count)),
+        ("oldest", format_timestamp(info.oldest)),
+        ("newest", format_timestamp(info.newest)),
+    ])
+}
+
+// Format the location of a remote database and the number of messages in each state
+// The server's schema and files aren't visible through its API, so they are omitted.
+pub fn format_http_info(url: &str, version: Option<&str>, counts: [usize; 3]) -> String {
+    let [unread, read, archived] = counts;
+    format_rows(&[
+        ("url", url.to_owned()),
+        ("version", version.unwrap_or("unknown").to_owned()),
+        ("unread", format_count(unread)),
+        ("read", format_count(read)),
+        ("archived", format_count(archived)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_sqlite_info() {
+        let info = DatabaseInfo {
+            schema_version: SchemaVersion {
+                current: 20,
+                latest: 20,
+            },
+            unread_count: 1200,
+            read_count: 3,
+            archived_count: 0,
+            oldest: NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").ok(),
+            newest: None,
+        };
+        assert_eq!(
+            format_sqlite_info(Path::new("/data/mailbox.db"), &info, 98_304, 0),
+            "path       /data/mailbox.db
+schema     20
+size       96.0 KB
+wal        0 B
+unread     1,200
+read       3
+archived   0
+oldest     2024-01-01 00:00:00 UTC
+newest     none
+"
+        );
+    }
+
+    #[test]
+    fn test_format_schema_version() {
+        assert_eq!(
+            format_schema_version(SchemaVersion {
+                current: 21,
+                latest: 20,
+            }),
+            "21 (this version of mailbox supports 20)"
+        );
+    }
+
+    #[test]
+    fn test_format_http_info() {
+        assert_eq!(
+            format_http_info("http://localhost:8080", None, [1, 2, 3]),
+            "url        http://localhost:8080
+version    unknown
+unread     1
+read       2
+archived   3
+"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::cli::DigestFormat;
+use chrono::NaiveDateTime;
+use database::{Mailbox, Message, State};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// The maximum number of unread messages to list individually
+const MAX_NOTABLE: usize = 10;
+
+// The number of messages and unread messages in a mailbox
+#[derive(Default)]
+struct MailboxCounts {
+    total: usize,
+    unread: usize,
+}
+
+// Summarize messages into a digest with counts per mailbox and the most recent unread messages
+// The messages are expected to be sorted newest first
+pub fn format_digest(messages: &[Message], since: NaiveDateTime, format: DigestFormat) -> String {
+    let mut counts = BTreeMap::<&Mailbox, MailboxCounts>::new();
+    for message in messages {
+        let entry = counts.entry(&message.mailbox).or_default();
+        entry.total += 1;
+        if message.state == State::Unread {
+            entry.unread += 1;
+        }
+    }
+    let unread = messages
+        .iter()
+        .filter(|message| message.state == State::Unread)
+        .collect::<Vec<_>>();
+    let since = since.format("%Y-%m-%d %H:%M UTC");
+    let total = messages.len();
+    let plural = if total == 1 { "" } else { "s" };
+
+    let mut output = String::new();
+    match format {
+        DigestFormat::Text => {
+            let _ = writeln!(output, "{total} message{plural} since {since}");
+            for (mailbox, counts) in &counts {
+                let _ = writeln!(
+                    output,
+                    "  {mailbox}: {} ({} unread)",
+                    counts.total, counts.unread
+                );
+            }
+            if !unread.is_empty() {
+                let _ = writeln!(output, "Unread:");
+                for message in unread.iter().take(MAX_NOTABLE) {
+                    let _ = writeln!(output, "  - {} [{}]", message.content, message.mailbox);
+                }
+                if unread.len() > MAX_NOTABLE {
+                    let _ = writeln!(output, "  ...and {} more", unread.len() - MAX_NOTABLE);
+                }
+            }
+        }
+        DigestFormat::Markdown => {
+            let _ = writeln!(output, "## Mailbox digest\n");
+            let _ = writeln!(output, "{total} message{plural} since {since}");
+            if !counts.is_empty() {
+                let _ = writeln!(output, "\n| Mailbox | Messages | Unread |");
+                let _ = writeln!(output, "| --- | ---: | ---: |");
+                for (mailbox, counts) in &counts {
+                    let _ = writeln!(
+                        output,
+                        "| {mailbox} | {} | {} |",
+                        counts.total, counts.unread
+                    );
+                }
+            }
+            if !unread.is_empty() {
+                let _ = writeln!(output, "\n### Unread\n");
+                for message in unread.iter().take(MAX_NOTABLE) {
+                    let _ = writeln!(
+                        output,
+                        "- **{}**: {}",
+                        message.mailbox,
+                        // Keep multiline messages within their list item
+                        message.content.replace('\n', " ")
+                    );
+                }
+                if unread.len() > MAX_NOTABLE {
+                    let _ = writeln!(output, "- ...and {} more", unread.len() - MAX_NOTABLE);
+                }
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::Kind;
+
+    fn make_message(mailbox: &str, content: &str, state: State) -> Message {
+        Message {
+            id: 1,
+            timestamp: NaiveDateTime::MIN,
+            mailbox: mailbox.try_into().unwrap(),
+            content: content.to_owned(),
+            state,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            tags: vec![],
+            priority: 0,
+            kind: Kind::Text,
+            related_ids: vec![],
+        }
+    }
+
+    fn get_messages() -> Vec<Message> {
+        vec![
+            make_message("ci/project-a", "Build failed", State::Unread),
+            make_message("ci", "Build passed", State::Read),
+            make_message("ci/project-a", "Build passed", State::Archived),
+        ]
+    }
+
+    fn get_since() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            format_digest(&get_messages(), get_since(), DigestFormat::Text),
+            "3 messages since 2024-01-02 03:04 UTC
+  ci: 1 (0 unread)
+  ci/project-a: 2 (1 unread)
+Unread:
+  - Build failed [ci/project-a]
+"
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            format_digest(&get_messages(), get_since(), DigestFormat::Markdown),
+            "## Mailbox digest
+
+3 messages since 2024-01-02 03:04 UTC
+
+| Mailbox | Messages | Unread |
+| --- | ---: | ---: |
+| ci | 1 | 0 |
+| ci/project-a | 2 | 1 |
+
+### Unread
+
+- **ci/project-a**: Build failed
+"
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(
+            format_digest(&[], get_since(), DigestFormat::Text),
+            "0 messages since 2024-01-02 03:04 UTC\n"
+        );
+    }
+
+    #[test]
+    fn test_truncates_unread() {
+        let messages = (0..12)
+            .map(|index| make_message("ci", &format!("Message {index}"), State::Unread))
+            .collect::<Vec<_>>();
+        let digest = format_digest(&messages, get_since(), DigestFormat::Text);
+        assert!(digest.contains("  - Message 9 [ci]\n  ...and 2 more\n"));
+        assert!(!digest.contains("Message 10"));
+    }
+}
@@ -0,0 +1,407 @@
+use crate::cli::Cli;
+use crate::config::{Config, DatabaseProvider};
+use crate::exit_code::Exit;
+use crate::{get_config_path, get_database_provider, get_default_db_path, should_colorize};
+use chrono::{DateTime, TimeDelta, Utc};
+use colored::Colorize;
+use database::{HttpBackend, SchemaVersion, SqliteBackend, WalStatus};
+use std::path::Path;
+use std::time::Duration;
+
+// The largest difference between the local clock and the server's clock that is tolerated, since
+// the server timestamps messages and expires claims with its own clock
+const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::seconds(30);
+
+// The number of frames that can remain in the write-ahead log after a checkpoint before it is
+// considered to be growing out of control
+const MAX_WAL_FRAMES: i64 = 10_000;
+
+// How long to wait for the server to respond to the doctor's requests
+const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Outcome {
+    Pass,
+    // Something may cause problems, but mailbox still works
+    Warn,
+    Fail,
+}
+
+// The result of one of the doctor's checks
+#[derive(Debug, Eq, PartialEq)]
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Outcome, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome,
+            detail: detail.into(),
+        }
+    }
+}
+
+// Format a check as a single line, like "[PASS] config: Loaded config.toml"
+fn format_check(check: &Check, color: bool) -> String {
+    let label = match check.outcome {
+        Outcome::Pass => "PASS".green(),
+        Outcome::Warn => "WARN".yellow(),
+        Outcome::Fail => "FAIL".red(),
+    };
+    let label = if color {
+        label.to_string()
+    } else {
+        label.clear().to_string()
+    };
+    format!("[{label}] {}: {}", check.name, check.detail)
+}
+
+// Check that the config file parses
+fn check_config(path: &Path) -> (Check, Option<Config>) {
+    match Config::load(&path.to_path_buf()) {
+        Ok(Some(config)) => (
+            Check::new(
+                "config",
+                Outcome::Pass,
+                format!("Loaded {}", path.display()),
+            ),
+            Some(config),
+        ),
+        Ok(None) => (
+            Check::new(
+                "config",
+                Outcome::Pass,
+                format!("No config file at {}, using the defaults", path.display()),
+            ),
+            None,
+        ),
+        Err(err) => (
+            Check::new("config", Outcome::Fail, format!("{err:#}")),
+            None,
+        ),
+    }
+}
+
+// Check that the database schema is the one that this version of mailbox expects
+fn check_schema_version(version: SchemaVersion) -> Check {
+    let SchemaVersion { current, latest } = version;
+    if current == latest {
+        Check::new("schema", Outcome::Pass, format!("Version {current}"))
+    } else {
+        Check::new(
+            "schema",
+            Outcome::Fail,
+            format!("Version {current}, but this version of mailbox expects version {latest}"),
+        )
+    }
+}
+
+// Check that the write-ahead log is enabled and being checkpointed
+fn check_wal_status(status: &WalStatus) -> Check {
+    if status.journal_mode != "wal" {
+        return Check::new(
+            "wal",
+            Outcome::Warn,
+            format!(
+                "The journal mode is {}, so other processes can't read while mailbox writes",
+                status.journal_mode
+            ),
+        );
+    }
+    let remaining = status.frames - status.checkpointed;
+    if status.busy && remaining > MAX_WAL_FRAMES {
+        return Check::new(
+            "wal",
+            Outcome::Warn,
+            format!("{remaining} frames couldn't be checkpointed because another process is reading the database"),
+        );
+    }
+    Check::new(
+        "wal",
+        Outcome::Pass,
+        format!(
+            "Checkpointed {} of {} frames",
+            status.checkpointed, status.frames
+        ),
+    )
+}
+
+// Check the SQLite database without creating it if it doesn't exist
+async fn check_sqlite(path: &Path) -> Vec<Check> {
+    if !path.exists() {
+        return vec![Check::new(
+            "database",
+            Outcome::Pass,
+            format!(
+                "{} doesn't exist yet and will be created when it is first used",
+                path.display()
+            ),
+        )];
+    }
+    let backend = match SqliteBackend::new(path.to_path_buf()).await {
+        Ok(backend) => backend,
+        Err(err) => return vec![Check::new("database", Outcome::Fail, format!("{err:#}"))],
+    };
+    let mut checks = vec![Check::new(
+        "database",
+        Outcome::Pass,
+        format!("Opened {}", path.display()),
+    )];
+    checks.push(match backend.load_schema_version().await {
+        Ok(version) => check_schema_version(version),
+        Err(err) => Check::new("schema", Outcome::Fail, format!("{err:#}")),
+    });
+    checks.push(match backend.check_wal().await {
+        Ok(status) => check_wal_status(&status),
+        Err(err) => Check::new("wal", Outcome::Fail, format!("{err:#}")),
+    });
+    if let Err(err) = backend.close().await {
+        checks.push(Check::new("database", Outcome::Fail, format!("{err:#}")));
+    }
+    checks
+}
+
+// Check that the local clock agrees with the server's clock
+fn check_clock_skew(server: DateTime<Utc>, local: DateTime<Utc>) -> Check {
+    let skew = server - local;
+    let seconds = skew.num_seconds().abs();
+    if skew.abs() <= MAX_CLOCK_SKEW {
+        return Check::new(
+            "clock",
+            Outcome::Pass,
+            format!("The server's clock is within {seconds} seconds of this computer's clock"),
+        );
+    }
+    let direction = if skew > TimeDelta::zero() {
+        "ahead of"
+    } else {
+        "behind"
+    };
+    Check::new(
+        "clock",
+        Outcome::Fail,
+        format!("The server's clock is {seconds} seconds {direction} this computer's clock"),
+    )
+}
+
+// Check that the server is reachable, accepts the token, and has an accurate clock
+async fn check_http(url: String, token: Option<String>) -> Vec<Check> {
+    let backend = match HttpBackend::new(url.clone(), token) {
+        Ok(backend) => backend
+            .with_timeout(Some(SERVER_TIMEOUT))
+            .with_max_retries(0),
+        Err(err) => return vec![Check::new("database", Outcome::Fail, format!("{err:#}"))],
+    };
+    let status = match backend.check_server().await {
+        Ok(status) => status,
+        Err(err) => {
+            return vec![Check::new(
+                "database",
+                Outcome::Fail,
+                format!("Couldn't reach {url}: {err:#}"),
+            )]
+        }
+    };
+    let version = status
+        .version
+        .map_or_else(String::new, |version| format!(" (version {version})"));
+    let mut checks = vec![Check::new(
+        "database",
+        Outcome::Pass,
+        format!("Reached {url}{version}"),
+    )];
+    checks.push(if status.authorized {
+        Check::new("auth", Outcome::Pass, "The server accepted the token")
+    } else {
+        Check::new(
+            "auth",
+            Outcome::Fail,
+            "The server rejected the token, which is set with --token or the database section of the config file",
+        )
+    });
+    checks.push(status.date.map_or_else(
+        || Check::new("clock", Outcome::Warn, "The server didn't report its time"),
+        |date| check_clock_skew(date, Utc::now()),
+    ));
+    checks
+}
+
+// Check whether messages will be printed with colors
+fn check_color(cli: &Cli) -> Check {
+    if should_colorize(cli) {
+        Check::new("color", Outcome::Pass, "Colors are enabled")
+    } else if cli.no_color {
+        Check::new("color", Outcome::Pass, "Colors are disabled by --no-color")
+    } else {
+        Check::new(
+            "color",
+            Outcome::Warn,
+            "Colors are disabled because the output isn't a terminal or NO_COLOR is set, pass --color to force them",
+        )
+    }
+}
+
+// Check whether the locale uses UTF-8, without which the terminal may not render wide characters,
+// like emoji, as two columns wide, misaligning messages
+fn check_locale(locale: Option<&str>) -> Check {
+    match locale {
+        Some(locale)
+            if locale.to_lowercase().contains("utf-8")
+                || locale.to_lowercase().contains("utf8") =>
+        {
+            Check::new("unicode", Outcome::Pass, format!("The locale is {locale}"))
+        }
+        Some(locale) => Check::new(
+            "unicode",
+            Outcome::Warn,
+            format!("The locale {locale} doesn't use UTF-8, so wide characters may be misaligned"),
+        ),
+        None => Check::new(
+            "unicode",
+            Outcome::Warn,
+            "No locale is set, so wide characters may be misaligned",
+        ),
+    }
+}
+
+// Determine the locale that controls character encoding, in order of precedence
+fn get_locale() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+// Diagnose common setup problems, printing the result of each check, and fail if any check failed
+// The checks continue after failures so that every problem is reported at once
+pub async fn run_doctor(cli: &Cli) -> anyhow::Result<Exit> {
+    let mut checks = vec![];
+    let config = match get_config_path(cli) {
+        Ok(path) => {
+            let (check, config) = check_config(&path);
+            checks.push(check);
+            config
+        }
+        Err(err) => {
+            checks.push(Check::new("config", Outcome::Fail, format!("{err:#}")));
+            None
+        }
+    };
+    match get_database_provider(cli, config.as_ref()) {
+        Ok(DatabaseProvider::Sqlite { path }) => {
+            match path.map_or_else(|| get_default_db_path(cli), Ok) {
+                Ok(path) => checks.extend(check_sqlite(&path).await),
+                Err(err) => checks.push(Check::new("database", Outcome::Fail, format!("{err:#}"))),
+            }
+        }
+        Ok(DatabaseProvider::Http { url, token, .. }) => {
+            checks.extend(check_http(url, token).await);
+        }
+        Ok(DatabaseProvider::Memory) => checks.push(Check::new(
+            "database",
+            Outcome::Pass,
+            "Messages are stored in memory and discarded when each command exits",
+        )),
+        Err(err) => checks.push(Check::new("database", Outcome::Fail, format!("{err:#}"))),
+    }
+    checks.push(check_color(cli));
+    checks.push(check_locale(get_locale().as_deref()));
+
+    let color = should_colorize(cli);
+    for check in &checks {
+        println!("{}", format_check(check, color));
+    }
+    Ok(
+        if checks.iter().any(|check| check.outcome == Outcome::Fail) {
+            Exit::Failure
+        } else {
+            Exit::Success
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_check() {
+        let check = Check::new("config", Outcome::Fail, "Invalid key");
+        assert_eq!(format_check(&check, false), "[FAIL] config: Invalid key");
+    }
+
+    #[test]
+    fn test_check_schema_version() {
+        assert_eq!(
+            check_schema_version(SchemaVersion {
+                current: 3,
+                latest: 3
+            })
+            .outcome,
+            Outcome::Pass
+        );
+        assert_eq!(
+            check_schema_version(SchemaVersion {
+                current: 2,
+                latest: 3
+            })
+            .outcome,
+            Outcome::Fail
+        );
+    }
+
+    #[test]
+    fn test_check_wal_status() {
+        let status = WalStatus {
+            journal_mode: String::from("wal"),
+            busy: false,
+            frames: 20,
+            checkpointed: 20,
+        };
+        assert_eq!(check_wal_status(&status).outcome, Outcome::Pass);
+        assert_eq!(
+            check_wal_status(&WalStatus {
+                busy: true,
+                frames: MAX_WAL_FRAMES * 2,
+                ..status.clone()
+            })
+            .outcome,
+            Outcome::Warn
+        );
+        assert_eq!(
+            check_wal_status(&WalStatus {
+                journal_mode: String::from("delete"),
+                ..status
+            })
+            .outcome,
+            Outcome::Warn
+        );
+    }
+
+    #[test]
+    fn test_check_clock_skew() {
+        let local = Utc::now();
+        assert_eq!(
+            check_clock_skew(local + TimeDelta::seconds(5), local).outcome,
+            Outcome::Pass
+        );
+        let check = check_clock_skew(local - TimeDelta::minutes(2), local);
+        assert_eq!(check.outcome, Outcome::Fail);
+        assert_eq!(
+            check.detail,
+            "The server's clock is 120 seconds behind this computer's clock"
+        );
+    }
+
+    #[test]
+    fn test_check_locale() {
+        assert_eq!(check_locale(Some("en_US.UTF-8")).outcome, Outcome::Pass);
+        assert_eq!(check_locale(Some("C.utf8")).outcome, Outcome::Pass);
+        assert_eq!(check_locale(Some("C")).outcome, Outcome::Warn);
+        assert_eq!(check_locale(None).outcome, Outcome::Warn);
+    }
+}
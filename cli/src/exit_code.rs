@@ -0,0 +1,131 @@
+use database::{ConnectionError, Message, ResponseError, ValidationError};
+use serde_json::json;
+use std::process::ExitCode;
+
+// The exit codes of the CLI, which scripts can use to distinguish failures, documented by
+// EXIT_CODES_HELP
+// clap exits with 2 when the command line arguments are invalid
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exit {
+    Success = 0,
+    Failure = 1,
+    // The command succeeded, but no messages matched
+    NoMessages = 3,
+    // The server couldn't be reached
+    Connection = 4,
+    // The server rejected the API token
+    Auth = 5,
+    // A message or request was invalid
+    Validation = 6,
+    // Messages violated an alert rule or a heartbeat mailbox is overdue
+    Alert = 7,
+}
+
+impl Exit {
+    // Determine the exit code of a command that operated on messages
+    pub fn from_messages(messages: &[Message]) -> Self {
+        if messages.is_empty() {
+            Self::NoMessages
+        } else {
+            Self::Success
+        }
+    }
+
+    // Determine the exit code of a command that failed
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        if err.downcast_ref::<ValidationError>().is_some() {
+            return Self::Validation;
+        }
+        if err.downcast_ref::<ConnectionError>().is_some() {
+            return Self::Connection;
+        }
+        match err
+            .downcast_ref::<ResponseError>()
+            .map(|err| err.status.as_u16())
+        {
+            Some(401 | 403) => Self::Auth,
+            Some(400 | 422) => Self::Validation,
+            _ => Self::Failure,
+        }
+    }
+
+    // Get the name of the kind of failure that the exit code represents
+    pub const fn kind(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::NoMessages => "no_messages",
+            Self::Connection => "connection",
+            Self::Auth => "auth",
+            Self::Validation => "validation",
+            Self::Alert => "alert",
+        }
+    }
+}
+
+// Format an error as a JSON object for tools that wrap the CLI
+// The message contains the whole chain of error contexts on a single line
+pub fn format_error_json(err: &anyhow::Error) -> String {
+    json!({
+        "error": {
+            "kind": Exit::from_error(err).kind(),
+            "message": format!("{err:#}"),
+        }
+    })
+    .to_string()
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        Self::from(exit as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_from_error() {
+        let err = anyhow::Error::new(ValidationError(String::from("content must not be empty")))
+            .context("Failed to add message");
+        assert_eq!(Exit::from_error(&err), Exit::Validation);
+        assert_eq!(
+            Exit::from_error(&anyhow::anyhow!("Something failed")),
+            Exit::Failure
+        );
+        let err = Err::<(), _>(std::io::Error::other("failed"))
+            .context("Failed to read file")
+            .unwrap_err();
+        assert_eq!(Exit::from_error(&err), Exit::Failure);
+    }
+
+    #[test]
+    fn test_format_error_json() {
+        let err = anyhow::Error::new(ValidationError(String::from("content must not be empty")))
+            .context("Failed to add message");
+        assert_eq!(
+            format_error_json(&err),
+            r#"{"error":{"kind":"validation","message":"Failed to add message: content must not be empty"}}"#
+        );
+        assert_eq!(
+            format_error_json(&anyhow::anyhow!("Something \"failed\"")),
+            r#"{"error":{"kind":"failure","message":"Something \"failed\""}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_connection_error() -> anyhow::Result<()> {
+        // Nothing listens on port 1, so the request fails to connect
+        let backend = database::HttpBackend::new(String::from("http://127.0.0.1:1"), None)?
+            .with_max_retries(0);
+        let err = database::Database::new(backend)
+            .load_messages(database::Filter::new())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(Exit::from_error(&err), Exit::Connection);
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::{Context, Result};
+use database::{Message, State};
+use std::fmt::Write as _;
+use std::fs::{self, create_dir_all};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// The maximum length of a subject line before it is truncated
+const MAX_SUBJECT_LENGTH: usize = 78;
+
+// Encode a header value as an RFC 2047 encoded word if it isn't plain ASCII
+fn encode_header(value: &str) -> String {
+    if value
+        .chars()
+        .all(|char| char.is_ascii() && !char.is_ascii_control())
+    {
+        return value.to_owned();
+    }
+    let mut encoded = String::from("=?UTF-8?Q?");
+    for byte in value.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => {
+                encoded.push(char::from(byte));
+            }
+            _ => {
+                let _ = write!(encoded, "={byte:02X}");
+            }
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+// Use the first line of the message's content as its subject
+fn get_subject(content: &str) -> String {
+    let line = content.lines().find(|line| !line.trim().is_empty());
+    let line = line.unwrap_or_default().trim();
+    match line.char_indices().nth(MAX_SUBJECT_LENGTH) {
+        Some((index, _)) => format!("{}...", &line[..index]),
+        None => line.to_owned(),
+    }
+}
+
+// Format a message as an RFC 5322 email with headers that preserve its mailbox, state, tags, and
+// priority
+// mbox files record whether a message was read in its Status header, and Maildirs record it in its
+// filename instead, so the Status header is optional.
+fn format_email(message: &Message, include_status: bool) -> String {
+    let mailbox = message.mailbox.as_ref();
+    let mut email = String::new();
+    let _ = writeln!(
+        email,
+        "From: \"{}\" <mailbox@localhost>",
+        mailbox.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    let _ = writeln!(email, "Date: {}", message.timestamp.and_utc().to_rfc2822());
+    let _ = writeln!(
+        email,
+        "Subject: {}",
+        encode_header(&get_subject(&message.content))
+    );
+    let _ = writeln!(email, "Message-ID: <{}@mailbox>", message.id);
+    if include_status && message.state != State::Unread {
+        email.push_str("Status: RO\n");
+    }
+    let _ = writeln!(email, "X-Mailbox: {}", encode_header(mailbox));
+    let _ = writeln!(
+        email,
+        "X-Mailbox-State: {}",
+        message
+            .custom_state
+            .clone()
+            .unwrap_or_else(|| message.state.to_string())
+    );
+    if !message.tags.is_empty() {
+        let tags = message
+            .tags
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(email, "Keywords: {}", encode_header(&tags));
+    }
+    if message.priority != 0 {
+        let _ = writeln!(email, "X-Mailbox-Priority: {}", message.priority);
+    }
+    email.push_str("MIME-Version: 1.0\n");
+    email.push_str("Content-Type: text/plain; charset=utf-8\n");
+    email.push_str("Content-Transfer-Encoding: 8bit\n\n");
+    email.push_str(&message.content);
+    if !message.content.ends_with('\n') {
+        email.push('\n');
+    }
+    email
+}
+
+// Write messages to an mbox file in the mboxrd format, which escapes lines in the content that
+// start with "From " so that they aren't mistaken for the start of the next message
+pub fn write_mbox(writer: &mut impl Write, messages: &[Message]) -> Result<()> {
+    for message in messages {
+        let timestamp = message.timestamp.format("%a %b %e %H:%M:%S %Y");
+        writeln!(writer, "From mailbox@localhost {timestamp}")?;
+        for line in format_email(message, true).lines() {
+            if line.trim_start_matches('>').starts_with("From ") {
+                writeln!(writer, ">{line}")?;
+            } else {
+                writeln!(writer, "{line}")?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Determine the Maildir++ folder that stores a mailbox's messages, like ".a.b" for the mailbox
+// "a/b"
+// Dots in mailbox names are percent-encoded because Maildir++ uses them to separate folders, and
+// "%" is encoded first so that the encoding is unambiguous.
+fn get_maildir_folder(mailbox: &str) -> String {
+    mailbox
+        .split('/')
+        .map(|section| format!(".{}", section.replace('%', "%25").replace('.', "%2E")))
+        .collect()
+}
+
+// Create a Maildir's cur, new, and tmp directories
+fn create_maildir(path: &Path) -> Result<()> {
+    for directory in ["cur", "new", "tmp"] {
+        let path = path.join(directory);
+        create_dir_all(&path)
+            .with_context(|| format!("Failed to create Maildir directory {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// Write messages to a Maildir++ directory, putting each mailbox in its own folder
+// Unread messages go in new and the rest go in cur with the seen flag. Filenames are derived from
+// the messages' ids, so exporting the same messages again replaces them instead of duplicating them.
+pub fn write_maildir(path: &Path, messages: &[Message]) -> Result<usize> {
+    // The root of the Maildir is the inbox, which is always empty
+    create_maildir(path)?;
+    for message in messages {
+        let folder = path.join(get_maildir_folder(message.mailbox.as_ref()));
+        create_maildir(&folder)?;
+        let name = format!(
+            "{}.M{}.mailbox",
+            message.timestamp.and_utc().timestamp(),
+            message.id
+        );
+        let destination: PathBuf = if message.state == State::Unread {
+            folder.join("new").join(&name)
+        } else {
+            folder.join("cur").join(format!("{name}:2,S"))
+        };
+        // Write the message to tmp first so that mail clients never see a partial message
+        let temporary = folder.join("tmp").join(&name);
+        fs::write(&temporary, format_email(message, false))
+            .with_context(|| format!("Failed to write message {}", temporary.display()))?;
+        fs::rename(&temporary, &destination)
+            .with_context(|| format!("Failed to write message {}", destination.display()))?;
+    }
+    Ok(messages.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use database::Kind;
+
+    fn make_message(id: u32, mailbox: &str, content: &str, state: State) -> Message {
+        Message {
+            id,
+            timestamp: DateTime::from_timestamp(1_640_995_200, 0)
+                .unwrap()
+                .naive_utc(),
+            mailbox: mailbox.try_into().unwrap(),
+            content: content.into(),
+            state,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            tags: vec![],
+            priority: 0,
+            kind: Kind::Text,
+            related_ids: vec![],
+        }
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(encode_header("Build failed"), "Build failed");
+        assert_eq!(encode_header("Café ok"), "=?UTF-8?Q?Caf=C3=A9_ok?=");
+    }
+
+    #[test]
+    fn test_get_subject() {
+        assert_eq!(get_subject("\nFirst line\nSecond line"), "First line");
+        assert_eq!(
+            get_subject(&"a".repeat(100)),
+            format!("{}...", "a".repeat(78))
+        );
+    }
+
+    #[test]
+    fn test_write_mbox() -> Result<()> {
+        let message = Message {
+            tags: vec!["urgent".try_into()?],
+            ..make_message(1, "ci/build", "Build failed\nFrom the logs", State::Read)
+        };
+        let mut output = vec![];
+        write_mbox(&mut output, &[message])?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "From mailbox@localhost Sat Jan  1 00:00:00 2022
+From: \"ci/build\" <mailbox@localhost>
+Date: Sat, 1 Jan 2022 00:00:00 +0000
+Subject: Build failed
+Message-ID: <1@mailbox>
+Status: RO
+X-Mailbox: ci/build
+X-Mailbox-State: read
+Keywords: urgent
+MIME-Version: 1.0
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: 8bit
+
+Build failed
+>From the logs
+
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_maildir_folder() {
+        assert_eq!(get_maildir_folder("ci"), ".ci");
+        assert_eq!(get_maildir_folder("ci/build"), ".ci.build");
+        assert_eq!(get_maildir_folder("v1.2/../x"), ".v1%2E2.%2E%2E.x");
+        assert_eq!(get_maildir_folder("a%2Eb"), ".a%252Eb");
+    }
+
+    #[test]
+    fn test_write_maildir() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("mailbox-maildir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let messages = vec![
+            make_message(1, "ci/build", "Build failed", State::Unread),
+            make_message(2, "ci", "Deployed", State::Archived),
+        ];
+        let result = write_maildir(&path, &messages);
+        let new = path.join(".ci.build/new/1640995200.M1.mailbox");
+        let cur = path.join(".ci/cur/1640995200.M2.mailbox:2,S");
+        let exists = [path.join("cur"), new.clone(), cur.clone()].map(|path| path.exists());
+        let content = fs::read_to_string(&new);
+        fs::remove_dir_all(&path)?;
+        assert_eq!(result?, 2);
+        assert_eq!(exists, [true, true, true]);
+        assert!(content?.ends_with("\n\nBuild failed\n"));
+        Ok(())
+    }
+}
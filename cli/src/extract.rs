@@ -0,0 +1,57 @@
+use regex::Regex;
+
+// Extract fields from a message's content with a regex, returning the captured groups separated by
+// tabs, the whole match if the regex has no groups, or None if the content doesn't match
+// Tabs and newlines in the captured text are replaced with spaces so that each message produces
+// exactly one line with a consistent number of fields.
+pub fn extract_fields(regex: &Regex, content: &str) -> Option<String> {
+    let captures = regex.captures(content)?;
+    let fields = if captures.len() == 1 {
+        vec![captures.get(0).map_or("", |capture| capture.as_str())]
+    } else {
+        captures
+            .iter()
+            .skip(1)
+            .map(|capture| capture.map_or("", |capture| capture.as_str()))
+            .collect()
+    };
+    Some(
+        fields
+            .into_iter()
+            .map(|field| field.replace(['\t', '\n', '\r'], " "))
+            .collect::<Vec<_>>()
+            .join("\t"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_fields() {
+        let regex = Regex::new(r"build (\d+) (passed|failed)(?: in (\d+)s)?").unwrap();
+        assert_eq!(
+            extract_fields(&regex, "CI: build 42 failed in 93s"),
+            Some(String::from("42\tfailed\t93"))
+        );
+        // Groups that don't participate in the match are empty
+        assert_eq!(
+            extract_fields(&regex, "build 43 passed"),
+            Some(String::from("43\tpassed\t"))
+        );
+        assert_eq!(extract_fields(&regex, "deploy finished"), None);
+
+        let regex = Regex::new(r"v\d+\.\d+").unwrap();
+        assert_eq!(
+            extract_fields(&regex, "Released v1.2 today"),
+            Some(String::from("v1.2"))
+        );
+
+        let regex = Regex::new(r"(?s)error: (.*)").unwrap();
+        assert_eq!(
+            extract_fields(&regex, "error: disk\tfull\nretrying"),
+            Some(String::from("disk full retrying"))
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use database::{Backend, Database, Filter, Mailbox, NewMessage, State};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+// An event in an iCalendar file
+#[derive(Debug, Eq, PartialEq)]
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    // When the event starts and ends in UTC
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub all_day: bool,
+    pub location: Option<String>,
+    pub cancelled: bool,
+}
+
+// The events in an iCalendar file and the calendar's name if the file has one
+#[derive(Debug, Eq, PartialEq)]
+pub struct Calendar {
+    pub name: Option<String>,
+    pub events: Vec<Event>,
+}
+
+// The properties of an event while it is being parsed
+#[derive(Default)]
+struct EventBuilder {
+    uid: Option<String>,
+    summary: Option<String>,
+    start: Option<(NaiveDateTime, bool)>,
+    end: Option<(NaiveDateTime, bool)>,
+    location: Option<String>,
+    cancelled: bool,
+}
+
+impl EventBuilder {
+    fn build(self) -> Result<Event> {
+        let uid = self.uid.context("Event is missing a UID")?;
+        let (start, all_day) = self
+            .start
+            .with_context(|| format!("Event {uid} is missing a DTSTART"))?;
+        // Events without an end last for a day if they are all-day events and are instantaneous
+        // otherwise
+        let end = self.end.map_or_else(
+            || {
+                if all_day {
+                    start + chrono::Duration::days(1)
+                } else {
+                    start
+                }
+            },
+            |(end, _)| end,
+        );
+        Ok(Event {
+            uid,
+            summary: self.summary.unwrap_or_else(|| String::from("(no title)")),
+            start,
+            end,
+            all_day,
+            location: self.location.filter(|location| !location.is_empty()),
+            cancelled: self.cancelled,
+        })
+    }
+}
+
+// Join lines that were folded by starting the following lines with a space or tab
+fn unfold_lines(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in contents.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+// Unescape a text value, which escapes newlines, commas, semicolons, and backslashes
+fn unescape_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => text.push('\n'),
+                Some(escaped) => text.push(escaped),
+                None => text.push('\\'),
+            }
+        } else {
+            text.push(char);
+        }
+    }
+    text
+}
+
+// Parse a date or date-time value into a UTC time and whether it is a date without a time
+// Times with a TZID parameter or without a "Z" suffix are treated as local times because
+// timezone definitions aren't supported.
+fn parse_date_time(params: &str, value: &str) -> Result<(NaiveDateTime, bool)> {
+    let date_only = params
+        .split(';')
+        .any(|param| param.eq_ignore_ascii_case("VALUE=DATE"))
+        || value.len() == 8;
+    if date_only {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d")
+            .with_context(|| format!("Invalid date \"{value}\""))?;
+        return Ok((to_utc(date.and_time(chrono::NaiveTime::MIN)), true));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .with_context(|| format!("Invalid date-time \"{value}\""))?;
+        return Ok((time, false));
+    }
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .with_context(|| format!("Invalid date-time \"{value}\""))?;
+    Ok((to_utc(time), false))
+}
+
+// Convert a local time to UTC, using the earlier time if it is ambiguous
+fn to_utc(local: NaiveDateTime) -> NaiveDateTime {
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map_or(local, |time| time.naive_utc())
+}
+
+// Parse the events in an iCalendar file
+// Recurring events only include their first occurrence because recurrence rules aren't expanded.
+pub fn parse_calendar(contents: &str) -> Result<Calendar> {
+    let mut name = None;
+    let mut events = vec![];
+    let mut event: Option<EventBuilder> = None;
+    // Components nested in events, like alarms, have their own properties that are ignored
+    let mut nested_depth = 0;
+    let mut found_calendar = false;
+    for line in unfold_lines(contents) {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (property, params) = key.split_once(';').unwrap_or((key, ""));
+        let property = property.to_ascii_uppercase();
+        match (property.as_str(), event.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VCALENDAR") => found_calendar = true,
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => {
+                event = Some(EventBuilder::default());
+            }
+            ("BEGIN", Some(_)) => nested_depth += 1,
+            ("END", Some(_)) if nested_depth > 0 => nested_depth -= 1,
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                if let Some(builder) = event.take() {
+                    events.push(builder.build()?);
+                }
+            }
+            ("X-WR-CALNAME", None) => name = Some(unescape_text(value)),
+            (_, Some(_)) if nested_depth > 0 => {}
+            ("UID", Some(builder)) => builder.uid = Some(value.to_owned()),
+            ("SUMMARY", Some(builder)) => builder.summary = Some(unescape_text(value)),
+            ("LOCATION", Some(builder)) => builder.location = Some(unescape_text(value)),
+            ("DTSTART", Some(builder)) => builder.start = Some(parse_date_time(params, value)?),
+            ("DTEND", Some(builder)) => builder.end = Some(parse_date_time(params, value)?),
+            ("STATUS", Some(builder)) => {
+                builder.cancelled = value.eq_ignore_ascii_case("CANCELLED");
+            }
+            _ => {}
+        }
+    }
+    if !found_calendar {
+        bail!("The file isn't an iCalendar file");
+    }
+    Ok(Calendar { name, events })
+}
+
+// Convert a calendar name into a mailbox section by lowercasing it and replacing every run of
+// characters other than letters and numbers with a hyphen
+fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|char: char| !char.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Determine the name of a calendar from the name in the file or the source's file name
+fn calendar_name(calendar: &Calendar, source: &str) -> String {
+    let name = calendar.name.as_deref().map(slugify).unwrap_or_default();
+    if !name.is_empty() {
+        return name;
+    }
+    let file_name = source
+        .trim_end_matches('/')
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let name = slugify(stem);
+    if name.is_empty() {
+        String::from("events")
+    } else {
+        name
+    }
+}
+
+// Format the content of the message for an event, with its start time in the timezone
+fn format_event<Tz: TimeZone>(event: &Event, timezone: &Tz) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let start = timezone.from_utc_datetime(&event.start);
+    let when = if event.all_day {
+        start.format("%a %b %-d, all day").to_string()
+    } else {
+        start.format("%a %b %-d at %H:%M").to_string()
+    };
+    let mut content = if event.cancelled {
+        format!("Cancelled: {}\n{when}", event.summary)
+    } else {
+        format!("{}\n{when}", event.summary)
+    };
+    if let Some(location) = &event.location {
+        content.push('\n');
+        content.push_str(location);
+    }
+    content
+}
+
+// Read an iCalendar file from a local path or download it from an http(s) or webcal URL
+async fn read_source(source: &str) -> Result<String> {
+    let url = source
+        .strip_prefix("webcal://")
+        .map(|rest| format!("https://{rest}"));
+    match url.as_deref().unwrap_or(source) {
+        url if url.starts_with("http://") || url.starts_with("https://") => reqwest::get(url)
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Failed to download calendar {url}"))?
+            .text()
+            .await
+            .with_context(|| format!("Failed to download calendar {url}")),
+        path => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read calendar file {path}")),
+    }
+}
+
+// The result of ingesting a calendar
+#[derive(Debug, Eq, PartialEq)]
+pub struct IngestSummary {
+    pub mailbox: Mailbox,
+    pub added: usize,
+    pub updated: usize,
+}
+
+// Create unread messages for the calendar's upcoming and ongoing events that start within the
+// lookahead, and update the messages of events that changed since they were last ingested
+// Each event is identified by its UID, so ingesting the same calendar repeatedly doesn't create
+// duplicate messages or mark unchanged messages as unread again.
+pub async fn ingest_calendar<B: Backend>(
+    db: &Database<B>,
+    calendar: &Calendar,
+    mailbox: Mailbox,
+    lookahead: Duration,
+    now: NaiveDateTime,
+) -> Result<IngestSummary> {
+    let existing = db
+        .load_messages(Filter::new().with_mailbox(mailbox.clone()))
+        .await?
+        .into_iter()
+        .filter(|message| message.mailbox == mailbox)
+        .filter_map(|message| Some((message.external_id?, message.content)))
+        .collect::<HashMap<_, _>>();
+    let horizon = now + lookahead;
+    let mut added = 0;
+    let mut updated = 0;
+    let mut messages = vec![];
+    for event in &calendar.events {
+        let content = format_event(event, &Local);
+        match existing.get(&event.uid) {
+            // Only events that start soon and haven't ended are added, and cancelled events are
+            // only recorded if they were added before
+            None if event.start <= horizon && event.end >= now && !event.cancelled => added += 1,
+            Some(existing_content) if existing_content != &content => updated += 1,
+            _ => continue,
+        }
+        messages.push(NewMessage {
+            mailbox: mailbox.clone(),
+            content,
+            state: Some(State::Unread),
+            tags: vec![],
+            timestamp: None,
+            external_id: Some(event.uid.clone()),
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        });
+    }
+    if !messages.is_empty() {
+        db.upsert_messages(messages).await?;
+    }
+    Ok(IngestSummary {
+        mailbox,
+        added,
+        updated,
+    })
+}
+
+// Ingest the calendar at a path or URL into calendar/<name>, where the name defaults to the
+// calendar's own name or its file name
+pub async fn ingest_ics<B: Backend>(
+    db: &Database<B>,
+    source: &str,
+    name: Option<String>,
+    lookahead: Duration,
+) -> Result<IngestSummary> {
+    let calendar = parse_calendar(&read_source(source).await?)
+        .with_context(|| format!("Failed to parse calendar {source}"))?;
+    let name = name.map_or_else(|| calendar_name(&calendar, source), |name| slugify(&name));
+    let mailbox = format!("calendar/{name}").parse()?;
+    ingest_calendar(db, &calendar, mailbox, lookahead, Utc::now().naive_utc()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::MemoryBackend;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+X-WR-CALNAME:Team Events\r
+BEGIN:VEVENT\r
+UID:standup@example.com\r
+SUMMARY:Team standup\\, daily\r
+DTSTART:20240102T093000Z\r
+DTEND:20240102T094500Z\r
+LOCATION:Room 4\r
+BEGIN:VALARM\r
+SUMMARY:Alarm\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:offsite@example.com\r
+SUMMARY:Offsite with a very long title that is folded onto\r
+  a second line\r
+DTSTART;VALUE=DATE:20240110\r
+STATUS:CANCELLED\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_calendar() -> Result<()> {
+        let calendar = parse_calendar(CALENDAR)?;
+        assert_eq!(calendar.name.as_deref(), Some("Team Events"));
+        assert_eq!(calendar.events.len(), 2);
+        assert_eq!(
+            calendar.events[0],
+            Event {
+                uid: String::from("standup@example.com"),
+                summary: String::from("Team standup, daily"),
+                start: time("2024-01-02 09:30"),
+                end: time("2024-01-02 09:45"),
+                all_day: false,
+                location: Some(String::from("Room 4")),
+                cancelled: false,
+            }
+        );
+        let offsite = &calendar.events[1];
+        assert_eq!(
+            offsite.summary,
+            "Offsite with a very long title that is folded onto a second line"
+        );
+        assert!(offsite.all_day);
+        assert!(offsite.cancelled);
+        assert_eq!(offsite.end - offsite.start, chrono::Duration::days(1));
+
+        assert!(parse_calendar("not a calendar").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_calendar_name() {
+        let calendar = Calendar {
+            name: None,
+            events: vec![],
+        };
+        assert_eq!(
+            calendar_name(&calendar, "https://example.com/cal/Work Holidays.ics"),
+            "work-holidays"
+        );
+        assert_eq!(calendar_name(&calendar, "https://example.com/"), "example");
+        let calendar = Calendar {
+            name: Some(String::from("Team Events!")),
+            events: vec![],
+        };
+        assert_eq!(calendar_name(&calendar, "basic.ics"), "team-events");
+    }
+
+    #[test]
+    fn test_format_event() -> Result<()> {
+        let calendar = parse_calendar(CALENDAR)?;
+        assert_eq!(
+            format_event(&calendar.events[0], &Utc),
+            "Team standup, daily\nTue Jan 2 at 09:30\nRoom 4"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_calendar() -> Result<()> {
+        let db = Database::new(MemoryBackend::new());
+        let mailbox: Mailbox = "calendar/team-events".parse()?;
+        let mut calendar = parse_calendar(CALENDAR)?;
+        let lookahead = Duration::from_hours(48);
+        let now = time("2024-01-01 12:00");
+
+        // The cancelled offsite isn't added
+        let summary = ingest_calendar(&db, &calendar, mailbox.clone(), lookahead, now).await?;
+        assert_eq!((summary.added, summary.updated), (1, 0));
+
+        // Ingesting again doesn't change read messages
+        db.change_state(Filter::new(), State::Read).await?;
+        let summary = ingest_calendar(&db, &calendar, mailbox.clone(), lookahead, now).await?;
+        assert_eq!((summary.added, summary.updated), (0, 0));
+
+        // Changed events are marked as unread again
+        calendar.events[0].location = Some(String::from("Room 5"));
+        let summary = ingest_calendar(&db, &calendar, mailbox.clone(), lookahead, now).await?;
+        assert_eq!((summary.added, summary.updated), (0, 1));
+        let messages = db.load_messages(Filter::new()).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].state, State::Unread);
+        assert!(messages[0].content.ends_with("Room 5"));
+
+        // Events that already ended aren't added
+        let summary = ingest_calendar(
+            &Database::new(MemoryBackend::new()),
+            &calendar,
+            mailbox,
+            lookahead,
+            time("2024-01-03 12:00"),
+        )
+        .await?;
+        assert_eq!((summary.added, summary.updated), (0, 0));
+        Ok(())
+    }
+}
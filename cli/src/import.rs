@@ -1,8 +1,162 @@
+use crate::ansi::strip_ansi;
 use crate::cli::ImportMessageFormat;
-use crate::config::Config;
+use crate::config::{Config, Override};
+use crate::confirm::format_count;
 use anyhow::{Context, Result};
 use csv::ReaderBuilder;
-use database::{Backend, Database, Message, NewMessage};
+use database::{Backend, Database, Message, NewMessage, Tag};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+// The number of input lines that are parsed and imported at a time
+const BATCH_SIZE: usize = 1000;
+
+// Options that control how messages are imported from stdin
+pub struct ImportOptions {
+    pub format: ImportMessageFormat,
+    // Tags to attach to every imported message
+    pub tags: Vec<Tag>,
+    pub keep_ansi: bool,
+    pub upsert: bool,
+    // The number of input lines to skip because they were imported by an earlier import
+    pub resume_from: usize,
+    // A file that records how many input lines have been imported so that an interrupted import
+    // can resume where it left off
+    pub checkpoint: Option<PathBuf>,
+    // Whether to print progress to stderr after every batch
+    pub report_progress: bool,
+    // The maximum number of batches to insert at the same time
+    pub concurrency: usize,
+}
+
+// The number of input lines that an import has processed so far
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ImportProgress {
+    // Including skipped lines
+    pub lines: usize,
+    pub inserted: usize,
+    pub failed: usize,
+}
+
+// The number of messages that a single configured override ignored or changed the state of
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct OverrideCount {
+    #[serde(rename = "override")]
+    pub override_state: Override,
+    pub count: usize,
+}
+
+// The messages that configured overrides ignored or changed the state of while adding messages
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct OverrideReport {
+    pub ignored: usize,
+    pub adjusted: usize,
+    // Keyed by the mailbox that each override is configured for
+    pub rules: BTreeMap<String, OverrideCount>,
+}
+
+impl OverrideReport {
+    // Record that an override ignored or changed the state of a message
+    fn record(&mut self, rule: &str, override_state: Override) {
+        if override_state == Override::Ignored {
+            self.ignored += 1;
+        } else {
+            self.adjusted += 1;
+        }
+        self.rules
+            .entry(rule.to_owned())
+            .or_insert(OverrideCount {
+                override_state,
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    // Combine the counts of another report into this one
+    fn merge(&mut self, other: Self) {
+        self.ignored += other.ignored;
+        self.adjusted += other.adjusted;
+        for (rule, count) in other.rules {
+            self.rules
+                .entry(rule)
+                .or_insert(OverrideCount { count: 0, ..count })
+                .count += count.count;
+        }
+    }
+}
+
+// Format a count of messages, like "1 message" or "2 messages"
+fn format_messages_count(count: usize) -> String {
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} message{plural}", format_count(count))
+}
+
+// Format how many messages configured overrides ignored or changed the state of and which
+// overrides were responsible
+pub fn format_override_report(report: &OverrideReport) -> String {
+    if report.rules.is_empty() {
+        return String::from("No overrides were applied\n");
+    }
+    let mut output = format!(
+        "Overrides ignored {} and changed the state of {}\n",
+        format_messages_count(report.ignored),
+        format_messages_count(report.adjusted)
+    );
+    for (rule, count) in &report.rules {
+        let messages = format_messages_count(count.count);
+        let action = match count.override_state {
+            Override::Ignored => format!("ignored {messages}"),
+            Override::Unread => format!("marked {messages} as unread"),
+            Override::Read => format!("marked {messages} as read"),
+            Override::Archived => format!("archived {messages}"),
+        };
+        let _ = writeln!(output, "  {rule}: {action}");
+    }
+    output
+}
+
+// Format an import's progress, including the rate of lines processed since the import started
+fn format_progress(progress: &ImportProgress, processed_lines: usize, elapsed: Duration) -> String {
+    let rate = if elapsed.is_zero() {
+        0
+    } else {
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let rate = (processed_lines as f64 / elapsed.as_secs_f64()) as usize;
+        rate
+    };
+    format!(
+        "Processed {} lines: {} inserted, {} failed ({} lines/s)",
+        format_count(progress.lines),
+        format_count(progress.inserted),
+        format_count(progress.failed),
+        format_count(rate)
+    )
+}
+
+// Read the number of imported lines from a checkpoint file, which is zero if it doesn't exist
+fn read_checkpoint(checkpoint: &PathBuf) -> Result<usize> {
+    match fs::read_to_string(checkpoint) {
+        Ok(contents) => contents
+            .trim()
+            .parse()
+            .with_context(|| format!("Failed to parse checkpoint file {}", checkpoint.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to read checkpoint file {}", checkpoint.display())),
+    }
+}
 
 // Import messages from stdin lines
 pub fn read_messages_stdin<Stdin>(stdin: Stdin, format: ImportMessageFormat) -> Vec<NewMessage>
@@ -24,7 +178,7 @@ where
         ImportMessageFormat::Tsv => {
             // ReaderBuilder needs a header row for the state column to be optional
             let lines = lines.collect::<Vec<_>>();
-            let tsv = format!("mailbox\tcontent\tstate\n{}", lines.join("\n"));
+            let tsv = format!("mailbox\tcontent\tstate\ttimestamp\n{}", lines.join("\n"));
             ReaderBuilder::new()
                 .has_headers(true)
                 .flexible(true)
@@ -55,29 +209,191 @@ where
     .collect()
 }
 
-// Add multiple messages to the database
+// Add multiple messages to the database, returning the added messages and the messages that
+// configured overrides ignored or changed the state of
+// ANSI escape sequences are stripped from the message content unless keep_ansi is true
+// If upsert is true, messages update existing messages with the same external id
 #[allow(clippy::module_name_repetitions)]
 pub async fn import_messages<B: Backend>(
     db: &Database<B>,
     config: Option<&Config>,
     new_messages: Vec<NewMessage>,
-) -> Result<Vec<Message>> {
-    let messages = db
-        .add_messages(
-            new_messages
-                .into_iter()
-                .filter_map(|message| match config.as_ref() {
-                    Some(config) => config.apply_override(message),
-                    None => Some(message),
-                })
-                .collect(),
-        )
-        .await?;
-    Ok(messages)
+    keep_ansi: bool,
+    upsert: bool,
+) -> Result<(Vec<Message>, OverrideReport)> {
+    let mut report = OverrideReport::default();
+    let new_messages = new_messages
+        .into_iter()
+        .filter_map(|message| {
+            let Some(config) = config else {
+                return Some(message);
+            };
+            let rule = config.get_override_rule(&message.mailbox);
+            let original_state = message.state;
+            let overridden = config.apply_override(message);
+            // Overrides that set a message's state to the state that it already had don't count
+            if let Some((rule, override_state)) = rule {
+                if overridden
+                    .as_ref()
+                    .is_none_or(|message| message.state != original_state)
+                {
+                    report.record(rule, override_state);
+                }
+            }
+            overridden
+        })
+        .map(|message| {
+            if keep_ansi {
+                message
+            } else {
+                NewMessage {
+                    content: strip_ansi(&message.content),
+                    ..message
+                }
+            }
+        })
+        .collect();
+    let messages = if upsert {
+        db.upsert_messages(new_messages).await?
+    } else {
+        db.add_messages(new_messages).await?
+    };
+    Ok((messages, report))
+}
+
+// A batch of messages parsed from stdin lines
+struct Batch {
+    // The number of input lines in the batch, including blank and invalid lines
+    lines: usize,
+    // The number of lines that couldn't be parsed
+    failed: usize,
+    messages: Vec<NewMessage>,
+}
+
+// Parse stdin lines into batches on a separate thread. The channel holds at most `capacity`
+// batches, so parsing stays only a few batches ahead of inserting.
+fn spawn_parser<Stdin: BufRead + Send + 'static>(
+    stdin: Stdin,
+    skipped_lines: usize,
+    format: ImportMessageFormat,
+    tags: Vec<Tag>,
+    capacity: usize,
+) -> mpsc::Receiver<Result<Batch>> {
+    let (tx, rx) = mpsc::channel(capacity);
+    thread::spawn(move || {
+        let mut lines = stdin.lines().skip(skipped_lines);
+        loop {
+            let batch = match lines
+                .by_ref()
+                .take(BATCH_SIZE)
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(lines) if lines.is_empty() => break,
+                Ok(lines) => {
+                    let parsable_lines = lines.iter().filter(|line| !line.is_empty()).count();
+                    let mut messages = read_messages_stdin(lines.join("\n").as_bytes(), format);
+                    for message in &mut messages {
+                        message.tags.extend(tags.iter().cloned());
+                    }
+                    Ok(Batch {
+                        lines: lines.len(),
+                        failed: parsable_lines - messages.len(),
+                        messages,
+                    })
+                }
+                Err(err) => Err(anyhow::Error::from(err).context("Failed to read stdin")),
+            };
+            let failed = batch.is_err();
+            // Sending only fails if the import stopped early, in which case the rest of the
+            // batches are no longer needed
+            if tx.blocking_send(batch).is_err() || failed {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+// Import messages from stdin lines in batches, reporting progress and recording checkpoints after
+// every batch so that an interrupted import can be resumed without creating duplicates
+// Lines are parsed on a separate thread while up to `concurrency` batches are inserted at the same
+// time. Batches finish in order, so the checkpoint never skips over a batch that is still being
+// inserted.
+pub async fn import_stdin<B: Backend, Stdin: BufRead + Send + 'static>(
+    db: &Database<B>,
+    config: Option<&Config>,
+    stdin: Stdin,
+    options: ImportOptions,
+) -> Result<(Vec<Message>, OverrideReport)> {
+    let skipped_lines = match options.checkpoint.as_ref() {
+        Some(checkpoint) => read_checkpoint(checkpoint)?.max(options.resume_from),
+        None => options.resume_from,
+    };
+    let mut progress = ImportProgress {
+        lines: skipped_lines,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let concurrency = options.concurrency.max(1);
+    let mut batches = spawn_parser(
+        stdin,
+        skipped_lines,
+        options.format,
+        options.tags,
+        concurrency,
+    );
+    let mut results = stream::poll_fn(|cx| batches.poll_recv(cx))
+        .map(|batch| async {
+            let batch = batch?;
+            let (messages, report) = import_messages(
+                db,
+                config,
+                batch.messages,
+                options.keep_ansi,
+                options.upsert,
+            )
+            .await?;
+            Ok::<_, anyhow::Error>((batch.lines, batch.failed, messages, report))
+        })
+        .buffered(concurrency);
+
+    let mut imported = vec![];
+    let mut overrides = OverrideReport::default();
+    while let Some(result) = results.next().await {
+        let (lines, failed, messages, report) = result?;
+        progress.lines += lines;
+        progress.failed += failed;
+        progress.inserted += messages.len();
+        imported.extend(messages);
+        overrides.merge(report);
+
+        if let Some(checkpoint) = options.checkpoint.as_ref() {
+            fs::write(checkpoint, format!("{}\n", progress.lines)).with_context(|| {
+                format!("Failed to write checkpoint file {}", checkpoint.display())
+            })?;
+        }
+        if options.report_progress {
+            eprintln!(
+                "{}",
+                format_progress(&progress, progress.lines - skipped_lines, start.elapsed())
+            );
+        }
+    }
+
+    // The import finished, so there is nothing left to resume
+    if let Some(checkpoint) = options.checkpoint.as_ref() {
+        if checkpoint.exists() {
+            fs::remove_file(checkpoint).with_context(|| {
+                format!("Failed to remove checkpoint file {}", checkpoint.display())
+            })?;
+        }
+    }
+    Ok((imported, overrides))
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::NaiveDateTime;
     use database::State;
 
     use super::*;
@@ -91,19 +407,45 @@ mod tests {
 
     #[test]
     fn test_tsv() {
-        let stdin = "1\na\tb\nfoo\tbar\tread\nA\tB\tC\tD";
+        let stdin = "1\na\tb\nfoo\tbar\tread\nA\tB\tC\tD\nold\te\t\t2023-01-01T12:01:02\nold\tf\t\tyesterday";
         assert_eq!(
             read_messages_stdin(stdin.as_bytes(), ImportMessageFormat::Tsv),
             vec![
                 NewMessage {
                     mailbox: "a".try_into().unwrap(),
                     content: String::from("b"),
-                    state: None
+                    state: None,
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
                 },
                 NewMessage {
                     mailbox: "foo".try_into().unwrap(),
                     content: String::from("bar"),
-                    state: Some(State::Read)
+                    state: Some(State::Read),
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
+                },
+                NewMessage {
+                    mailbox: "old".try_into().unwrap(),
+                    content: String::from("e"),
+                    state: None,
+                    tags: vec![],
+                    timestamp: Some(
+                        NaiveDateTime::parse_from_str("2023-01-01 12:01:02", "%Y-%m-%d %H:%M:%S")
+                            .unwrap()
+                    ),
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
                 }
             ]
         );
@@ -114,21 +456,218 @@ mod tests {
         let stdin = r#"{"mailbox":"1"}
 {"mailbox":"a","content":"b"}
 {"mailbox":"foo","content":"bar","state":"read"}
-{"mailbox":"A","content":"B","unknown":"C"}"#;
+{"mailbox":"A","content":"B","unknown":"C"}
+{"mailbox":"tagged","content":"c","tags":["urgent","ci/project-a"]}
+{"mailbox":"tagged","content":"d","tags":["a b"]}
+{"mailbox":"old","content":"e","timestamp":"2023-01-01T12:01:02"}
+{"mailbox":"old","content":"f","timestamp":"yesterday"}"#;
         assert_eq!(
             read_messages_stdin(stdin.as_bytes(), ImportMessageFormat::Json),
             vec![
                 NewMessage {
                     mailbox: "a".try_into().unwrap(),
                     content: String::from("b"),
-                    state: None
+                    state: None,
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
                 },
                 NewMessage {
                     mailbox: "foo".try_into().unwrap(),
                     content: String::from("bar"),
-                    state: Some(State::Read)
+                    state: Some(State::Read),
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
+                },
+                NewMessage {
+                    mailbox: "tagged".try_into().unwrap(),
+                    content: String::from("c"),
+                    state: None,
+                    tags: vec![
+                        "urgent".try_into().unwrap(),
+                        "ci/project-a".try_into().unwrap()
+                    ],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
+                },
+                NewMessage {
+                    mailbox: "old".try_into().unwrap(),
+                    content: String::from("e"),
+                    state: None,
+                    tags: vec![],
+                    timestamp: Some(
+                        NaiveDateTime::parse_from_str("2023-01-01 12:01:02", "%Y-%m-%d %H:%M:%S")
+                            .unwrap()
+                    ),
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
                 }
             ]
         );
     }
+
+    // Create options for importing TSV messages from stdin
+    fn get_options(resume_from: usize, checkpoint: Option<PathBuf>) -> ImportOptions {
+        ImportOptions {
+            format: ImportMessageFormat::Tsv,
+            tags: vec![],
+            keep_ansi: false,
+            upsert: false,
+            resume_from,
+            checkpoint,
+            report_progress: false,
+            concurrency: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_messages_overrides() -> Result<()> {
+        let db = Database::new(database::MemoryBackend::new());
+        let config: Config =
+            toml::from_str("[overrides]\n'ci' = 'ignored'\n'logs' = 'read'\n'todo' = 'unread'")?;
+        let make_message = |mailbox: &str| NewMessage {
+            mailbox: mailbox.try_into().unwrap(),
+            content: String::from("content"),
+            state: Some(State::Unread),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        };
+        let (messages, report) = import_messages(
+            &db,
+            Some(&config),
+            vec![
+                make_message("ci/build"),
+                make_message("ci"),
+                make_message("logs/debug"),
+                make_message("todo"),
+                make_message("other"),
+            ],
+            false,
+            false,
+        )
+        .await?;
+        assert_eq!(messages.len(), 3);
+        assert_eq!(report.ignored, 2);
+        // The todo override didn't change the message's state
+        assert_eq!(report.adjusted, 1);
+        assert_eq!(
+            format_override_report(&report),
+            "Overrides ignored 2 messages and changed the state of 1 message
+  ci: ignored 2 messages
+  logs: marked 1 message as read
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_override_report() {
+        assert_eq!(
+            format_override_report(&OverrideReport::default()),
+            "No overrides were applied\n"
+        );
+    }
+
+    #[test]
+    fn test_format_progress() {
+        let progress = ImportProgress {
+            lines: 12_000,
+            inserted: 11_990,
+            failed: 10,
+        };
+        assert_eq!(
+            format_progress(&progress, 10_000, Duration::from_secs(2)),
+            "Processed 12,000 lines: 11,990 inserted, 10 failed (5,000 lines/s)"
+        );
+        assert_eq!(
+            format_progress(&ImportProgress::default(), 0, Duration::ZERO),
+            "Processed 0 lines: 0 inserted, 0 failed (0 lines/s)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_stdin_resume() -> Result<()> {
+        let db = Database::new(database::MemoryBackend::new());
+        let stdin = (0..BATCH_SIZE + 10)
+            .map(|index| format!("mailbox\tmessage {index}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (messages, _) =
+            import_stdin(&db, None, std::io::Cursor::new(stdin), get_options(5, None)).await?;
+        assert_eq!(messages.len(), BATCH_SIZE + 5);
+        assert!(!messages
+            .iter()
+            .any(|message| message.content == "message 4"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_stdin_checkpoint() -> Result<()> {
+        let db = Database::new(database::MemoryBackend::new());
+        let checkpoint =
+            std::env::temp_dir().join(format!("mailbox-import-checkpoint-{}", std::process::id()));
+
+        // An earlier import was interrupted after importing the first two lines
+        fs::write(&checkpoint, "2\n")?;
+        let stdin = "a\tmessage 1\na\tmessage 2\na\tmessage 3\n";
+        let (messages, _) = import_stdin(
+            &db,
+            None,
+            stdin.as_bytes(),
+            get_options(0, Some(checkpoint.clone())),
+        )
+        .await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["message 3"]
+        );
+        // The checkpoint is removed after a successful import
+        assert!(!checkpoint.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_stdin_concurrent() -> Result<()> {
+        let db = Database::new(database::MemoryBackend::new());
+        let stdin = (0..BATCH_SIZE * 3)
+            .map(|index| format!("mailbox\tmessage {index}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let options = ImportOptions {
+            concurrency: 4,
+            ..get_options(0, None)
+        };
+        let (messages, _) = import_stdin(&db, None, std::io::Cursor::new(stdin), options).await?;
+
+        // The messages are returned in the order of the input lines
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.content.clone())
+                .collect::<Vec<_>>(),
+            (0..BATCH_SIZE * 3)
+                .map(|index| format!("message {index}"))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,276 @@
+use anyhow::Result;
+use database::{
+    Archiver, Attachment, Backend, Bucket, BucketStats, Changes, DeadLetterPolicy, Filter,
+    Heartbeat, Id, Mailbox, MailboxConfig, MailboxInfo, Message, NewMessage, Seq, State,
+};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+// How much output the CLI prints, which is chosen by the global --quiet and --verbose flags
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Level {
+    // Don't echo the messages that commands changed
+    Quiet,
+    Normal,
+    // Also print how long each database operation took to stderr
+    Verbose,
+    // Also print the arguments of each database operation to stderr
+    Debug,
+}
+
+impl Level {
+    // Determine the level from the number of times --quiet and --verbose were passed
+    pub const fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, 0) => Self::Normal,
+            (false, 1) => Self::Verbose,
+            (false, _) => Self::Debug,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+// Set the level for the rest of the process
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Determine whether output at the level should be printed
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+// Print a line to stderr with --verbose
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Print a line to stderr with -vv
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use verbose;
+
+// Format a duration in milliseconds, like "12.3ms"
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("{:.1}ms", elapsed.as_secs_f64() * 1000.0)
+}
+
+// Run an operation and log how long it took
+async fn time<T>(operation: &str, future: impl Future<Output = Result<T>>) -> Result<T> {
+    let start = Instant::now();
+    let result = future.await;
+    let outcome = if result.is_ok() { "finished" } else { "failed" };
+    verbose!(
+        "{operation} {outcome} in {}",
+        format_elapsed(start.elapsed())
+    );
+    result
+}
+
+// A backend that logs how long each operation on the wrapped backend takes, and the arguments of
+// each operation with -vv
+pub struct TimedBackend<B: Backend> {
+    backend: B,
+}
+
+impl<B: Backend> TimedBackend<B> {
+    pub const fn new(backend: B) -> Self {
+        Self { backend }
+    }
+}
+
+impl<B: Backend + Sync> Backend for TimedBackend<B> {
+    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        debug!("add_messages: {} messages", messages.len());
+        time("add_messages", self.backend.add_messages(messages)).await
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        debug!("upsert_messages: {} messages", messages.len());
+        time("upsert_messages", self.backend.upsert_messages(messages)).await
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        debug!("load_messages: {filter:?}");
+        time("load_messages", self.backend.load_messages(filter)).await
+    }
+
+    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        debug!("change_state: {filter:?} to {new_state}");
+        time("change_state", self.backend.change_state(filter, new_state)).await
+    }
+
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        debug!("change_custom_state: {filter:?} to {custom_state} ({new_state})");
+        time(
+            "change_custom_state",
+            self.backend
+                .change_custom_state(filter, custom_state, new_state),
+        )
+        .await
+    }
+
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        debug!("delete_messages: {filter:?}");
+        time("delete_messages", self.backend.delete_messages(filter)).await
+    }
+
+    async fn archive_messages(&self, filter: Filter, archiver: Archiver) -> Result<Vec<Message>> {
+        debug!("archive_messages: {filter:?}");
+        time(
+            "archive_messages",
+            self.backend.archive_messages(filter, archiver),
+        )
+        .await
+    }
+
+    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+        debug!("load_mailboxes: {filter:?}");
+        time("load_mailboxes", self.backend.load_mailboxes(filter)).await
+    }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        debug!("load_changes: since {since}");
+        time("load_changes", self.backend.load_changes(since)).await
+    }
+
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        debug!("load_stats: {filter:?} by {bucket}");
+        time("load_stats", self.backend.load_stats(filter, bucket)).await
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        debug!("load_attachments: message {id}");
+        time("load_attachments", self.backend.load_attachments(id)).await
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        debug!("record_view: message {id}");
+        time("record_view", self.backend.record_view(id)).await
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        debug!("claim_messages: {filter:?} for {worker} with a {lease:?} lease");
+        time(
+            "claim_messages",
+            self.backend.claim_messages(filter, worker, lease),
+        )
+        .await
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        debug!("nack_message: message {id} because {reason:?}");
+        time(
+            "nack_message",
+            self.backend.nack_message(id, reason, dead_letter),
+        )
+        .await
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        debug!("remind_message: message {id} in {delay:?}");
+        time("remind_message", self.backend.remind_message(id, delay)).await
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        debug!("wake_reminders");
+        time("wake_reminders", self.backend.wake_reminders()).await
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        debug!("link_messages: message {id} to message {related_id}");
+        time("link_messages", self.backend.link_messages(id, related_id)).await
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        debug!("set_heartbeat: {mailbox} every {interval:?}");
+        time(
+            "set_heartbeat",
+            self.backend.set_heartbeat(mailbox, interval),
+        )
+        .await
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        debug!("load_heartbeats");
+        time("load_heartbeats", self.backend.load_heartbeats()).await
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        debug!("load_mailbox_configs");
+        time("load_mailbox_configs", self.backend.load_mailbox_configs()).await
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        debug!("load_ingestion_configs");
+        time(
+            "load_ingestion_configs",
+            self.backend.load_ingestion_configs(),
+        )
+        .await
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        debug!("set_mailbox_config: {mailbox} to {config:?}");
+        time(
+            "set_mailbox_config",
+            self.backend.set_mailbox_config(mailbox, config),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_from_flags() {
+        assert_eq!(Level::from_flags(true, 2), Level::Quiet);
+        assert_eq!(Level::from_flags(false, 0), Level::Normal);
+        assert_eq!(Level::from_flags(false, 1), Level::Verbose);
+        assert_eq!(Level::from_flags(false, 3), Level::Debug);
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_micros(12_345)), "12.3ms");
+    }
+}
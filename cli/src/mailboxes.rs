@@ -0,0 +1,285 @@
+use crate::alerts::format_duration;
+use database::{Mailbox, MailboxConfig, MailboxInfo, State};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+// The number of messages in a mailbox and its descendants in each state
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
+pub struct MailboxSummary {
+    pub name: String,
+    pub unread: usize,
+    pub read: usize,
+    pub archived: usize,
+    pub total: usize,
+}
+
+impl MailboxSummary {
+    // Return the number of messages in a particular state
+    fn get_count(&self, state: State) -> usize {
+        match state {
+            State::Unread => self.unread,
+            State::Read => self.read,
+            State::Archived => self.archived,
+        }
+    }
+}
+
+// Combine the mailbox counts of each state into summaries of every mailbox and its ancestors,
+// sorted so that every mailbox is followed by its descendants and pinned mailboxes are listed
+// before their siblings in their configured pinned order
+pub fn summarize_mailboxes(
+    counts: &[(State, Vec<MailboxInfo>)],
+    configs: &BTreeMap<Mailbox, MailboxConfig>,
+) -> Vec<MailboxSummary> {
+    // Sort by the mailbox's sections so that "a/b" comes before "a-b", and sort each section by
+    // its pinned order first so that unpinned mailboxes come after the pinned ones
+    let mut summaries = BTreeMap::<Vec<(u64, String)>, MailboxSummary>::new();
+    for (state, mailboxes) in counts {
+        for mailbox in mailboxes {
+            for ancestor in mailbox.name.iter_ancestors() {
+                let sections = ancestor
+                    .iter_ancestors()
+                    .map(|section| {
+                        let pinned_order = configs
+                            .get(&section)
+                            .and_then(|config| config.pinned_order)
+                            .map_or(u64::MAX, u64::from);
+                        (pinned_order, section.get_leaf_name().to_owned())
+                    })
+                    .collect();
+                let summary = summaries.entry(sections).or_insert_with(|| MailboxSummary {
+                    name: ancestor.to_string(),
+                    ..Default::default()
+                });
+                match state {
+                    State::Unread => summary.unread += mailbox.message_count,
+                    State::Read => summary.read += mailbox.message_count,
+                    State::Archived => summary.archived += mailbox.message_count,
+                }
+                summary.total += mailbox.message_count;
+            }
+        }
+    }
+    summaries.into_values().collect()
+}
+
+// Format mailbox summaries as a text table with a column for each state
+// When tree is true, mailboxes are indented under their parent mailbox instead of showing their
+// full name
+pub fn format_mailboxes(summaries: &[MailboxSummary], states: &[State], tree: bool) -> String {
+    let mut header = vec![String::from("mailbox")];
+    header.extend(states.iter().map(ToString::to_string));
+    // The total is redundant when there is only one state
+    let show_total = states.len() > 1;
+    if show_total {
+        header.push(String::from("total"));
+    }
+    let rows = summaries
+        .iter()
+        .map(|summary| {
+            let name = if tree {
+                let depth = summary.name.matches('/').count();
+                let leaf_name = summary.name.rsplit('/').next().unwrap_or_default();
+                format!("{}{leaf_name}", "  ".repeat(depth))
+            } else {
+                summary.name.clone()
+            };
+            let mut row = vec![name];
+            row.extend(
+                states
+                    .iter()
+                    .map(|state| summary.get_count(*state).to_string()),
+            );
+            if show_total {
+                row.push(summary.total.to_string());
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = vec![0; header.len()];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = std::cmp::max(*width, cell.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        // Left-align the mailbox column and right-align the numeric columns
+        let line = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(index, (cell, width))| {
+                if index == 0 {
+                    format!("{cell:<width$}")
+                } else {
+                    format!("{cell:>width$}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(output, "{}", line.trim_end());
+    }
+    output
+}
+
+// Format a mailbox's metadata with one line per field that is set
+pub fn format_mailbox_config(config: &MailboxConfig) -> String {
+    let fields = [
+        ("description", config.description.clone()),
+        ("color", config.color.clone()),
+        (
+            "default state",
+            config.default_state.map(|state| state.to_string()),
+        ),
+        ("quota", config.quota.map(|quota| quota.to_string())),
+        (
+            "heartbeat",
+            config
+                .heartbeat_seconds
+                .map(|seconds| format_duration(Duration::from_secs(seconds.into()))),
+        ),
+        (
+            "pinned order",
+            config.pinned_order.map(|order| order.to_string()),
+        ),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{name}: {}\n", value?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_info(name: &str, message_count: usize) -> MailboxInfo {
+        MailboxInfo {
+            name: name.try_into().unwrap(),
+            message_count,
+        }
+    }
+
+    fn get_summaries() -> Vec<MailboxSummary> {
+        summarize_mailboxes(
+            &[
+                (
+                    State::Unread,
+                    vec![make_info("ci/project-a", 2), make_info("ci-old", 1)],
+                ),
+                (State::Archived, vec![make_info("ci", 3)]),
+            ],
+            &BTreeMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(
+            get_summaries(),
+            vec![
+                MailboxSummary {
+                    name: String::from("ci"),
+                    unread: 2,
+                    read: 0,
+                    archived: 3,
+                    total: 5,
+                },
+                MailboxSummary {
+                    name: String::from("ci/project-a"),
+                    unread: 2,
+                    read: 0,
+                    archived: 0,
+                    total: 2,
+                },
+                MailboxSummary {
+                    name: String::from("ci-old"),
+                    unread: 1,
+                    read: 0,
+                    archived: 0,
+                    total: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize_pinned() {
+        let pin = |pinned_order| MailboxConfig {
+            pinned_order: Some(pinned_order),
+            ..Default::default()
+        };
+        let configs = BTreeMap::from([
+            ("deploys".try_into().unwrap(), pin(2)),
+            ("ci/project-b".try_into().unwrap(), pin(1)),
+            ("alerts".try_into().unwrap(), pin(0)),
+        ]);
+        let summaries = summarize_mailboxes(
+            &[(
+                State::Unread,
+                vec![
+                    make_info("ci/project-a", 1),
+                    make_info("ci/project-b", 1),
+                    make_info("deploys", 1),
+                    make_info("alerts", 1),
+                ],
+            )],
+            &configs,
+        );
+        assert_eq!(
+            summaries
+                .iter()
+                .map(|summary| summary.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["alerts", "deploys", "ci", "ci/project-b", "ci/project-a"]
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format_mailboxes(
+                &get_summaries(),
+                &[State::Unread, State::Read, State::Archived],
+                false
+            ),
+            "mailbox       unread  read  archived  total
+ci                 2     0         3      5
+ci/project-a       2     0         0      2
+ci-old             1     0         0      1
+"
+        );
+    }
+
+    #[test]
+    fn test_format_tree() {
+        assert_eq!(
+            format_mailboxes(&get_summaries(), &[State::Unread], true),
+            "mailbox      unread
+ci                2
+  project-a       2
+ci-old            1
+"
+        );
+    }
+
+    #[test]
+    fn test_format_mailbox_config() {
+        let config = MailboxConfig {
+            description: Some(String::from("Nightly backups")),
+            default_state: Some(State::Read),
+            heartbeat_seconds: Some(86_400),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_mailbox_config(&config),
+            "description: Nightly backups\ndefault state: read\nheartbeat: 1d\n"
+        );
+        assert_eq!(format_mailbox_config(&MailboxConfig::default()), "");
+    }
+}
@@ -6,27 +6,85 @@
 )]
 #![allow(clippy::future_not_send, clippy::missing_const_for_fn)]
 
+mod admin;
+mod alerts;
+mod ansi;
+mod archive;
+mod attachments;
 mod cli;
+mod compose;
 mod config;
+mod confirm;
+mod db_info;
+mod digest;
+mod doctor;
+mod exit_code;
+mod export;
+mod extract;
+mod ics;
 mod import;
+mod log;
+mod mailboxes;
+mod mcp;
 mod message_components;
 mod message_formatter;
+mod open;
+mod query;
+mod stats;
+mod theme;
 mod truncate;
 mod tui;
+mod workspace;
 
-use crate::cli::{AddMessageState, Cli, Command, TimestampFormat};
-use crate::config::Config;
-use crate::import::read_messages_stdin;
+use crate::admin::{format_bytes, run_admin};
+use crate::alerts::{check_alerts, check_heartbeats, format_violations};
+use crate::archive::{archive_to_file, import_from_file};
+use crate::attachments::make_attachment;
+use crate::cli::{
+    AddMessageKind, AddMessageState, Cli, Command, ExportFormat, MailboxesFormat, MessageSort,
+    OutputFormat, StatsBucket, StatsFormat, TimestampFormat,
+};
+use crate::compose::{compose_message, open_editor};
+use crate::config::{Config, DatabaseProvider, Defaults};
+use crate::confirm::{confirm, format_count, DEFAULT_CONFIRM_THRESHOLD};
+use crate::db_info::{format_http_info, format_sqlite_info};
+use crate::digest::format_digest;
+use crate::doctor::run_doctor;
+use crate::exit_code::{format_error_json, Exit};
+use crate::export::{write_maildir, write_mbox};
+use crate::extract::extract_fields;
+use crate::ics::ingest_ics;
+use crate::import::{format_override_report, import_stdin, ImportOptions, OverrideReport};
+use crate::log::{set_level, verbose, Level, TimedBackend};
+use crate::mailboxes::{format_mailbox_config, format_mailboxes, summarize_mailboxes};
+use crate::mcp::run_mcp;
+use crate::open::find_url;
+use crate::query::{format_query_table, query_rows_to_json};
 use anyhow::{bail, Context, Result};
 use clap::Parser;
-use cli::{ConfigSubcommand, ViewMessageState};
-use database::{Backend, Database, Filter, HttpBackend, NewMessage, SqliteBackend, State};
+use cli::{
+    ConfigSubcommand, DbSubcommand, HeartbeatSubcommand, IngestSubcommand, MailboxConfigField,
+    MailboxConfigSubcommand, ViewMessageState, WorkspaceSubcommand,
+};
+use database::{
+    Backend, Bucket, CustomState, Database, DynBackend, Filter, HttpBackend, Kind, Mailbox,
+    MailboxConfig, MemoryBackend, Message, NewMessage, Sort, SqliteBackend, State,
+};
 use directories::ProjectDirs;
 use import::import_messages;
+use mailbox_server::{serve, ServeOptions};
 use message_formatter::MessageFormatter;
-use std::fs::create_dir_all;
-use std::io::{stdin, stdout, IsTerminal};
+use regex::Regex;
+use stats::format_stats_table;
+use std::collections::BTreeSet;
+use std::fs::{self, create_dir_all, OpenOptions};
+use std::io::{stderr, stdin, stdout, BufReader, BufWriter, IsTerminal, Write};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tui::{Pane, PinnedMailboxes};
+use workspace::{format_workspaces, get_workspace_db_path, list_workspaces, DEFAULT_WORKSPACE};
 
 // Return the directories where this project stores its data
 fn get_project_dirs() -> Result<ProjectDirs> {
@@ -35,39 +93,56 @@ fn get_project_dirs() -> Result<ProjectDirs> {
 }
 
 // Return the path of the configuration file, creating its parent directories if necessary
-fn get_config_path() -> Result<PathBuf> {
-    let project_dirs = get_project_dirs()?;
-    let config_dir = project_dirs.config_dir();
-    create_dir_all(config_dir).context("Couldn't create config directory")?;
-    Ok(config_dir.join("config.toml"))
+fn get_config_path(cli: &Cli) -> Result<PathBuf> {
+    let config_path = match cli.config.as_ref() {
+        Some(path) => path.clone(),
+        None => get_project_dirs()?.config_dir().join("config.toml"),
+    };
+    if let Some(config_dir) = config_path.parent() {
+        create_dir_all(config_dir).context("Couldn't create config directory")?;
+    }
+    Ok(config_path)
 }
 
-// Load the configuration file
-fn load_config() -> Result<Option<Config>> {
-    Config::load(&get_config_path()?)
+// Return the directory where the local database is stored
+fn get_data_dir(cli: &Cli) -> Result<PathBuf> {
+    match cli.data_dir.as_ref() {
+        Some(path) => Ok(path.clone()),
+        None => Ok(get_project_dirs()?.data_local_dir().to_path_buf()),
+    }
 }
 
-// Open the configuration file in $EDITOR
-fn edit_config() -> Result<()> {
-    match std::env::var_os("EDITOR") {
-        Some(editor) => {
-            std::process::Command::new(&editor)
-                .arg(get_config_path()?)
-                .status()
-                .with_context(|| format!("Failed to open editor: {}", editor.to_string_lossy()))?;
-            Ok(())
-        }
-        None => bail!("$EDITOR environment variable isn't set"),
+// Return the path of the local database when the config doesn't choose one, which depends on the
+// workspace
+fn get_default_db_path(cli: &Cli) -> Result<PathBuf> {
+    Ok(get_workspace_db_path(
+        &get_data_dir(cli)?,
+        cli.workspace.as_deref(),
+    ))
+}
+
+// Determine whether output should be colored, giving precedence to the command line over the
+// environment
+fn should_colorize(cli: &Cli) -> bool {
+    if cli.color {
+        true
+    } else if cli.no_color {
+        false
+    } else {
+        colored::control::ShouldColorize::from_env().should_colorize()
     }
 }
 
 // Create the message formatter
-fn create_formatter(cli: &Cli) -> MessageFormatter {
+fn create_formatter(cli: &Cli, defaults: &Defaults) -> MessageFormatter {
     const DEFAULT_WIDTH: usize = 80;
     const DEFAULT_HEIGHT: usize = 8;
 
     let tty = stdout().is_terminal();
     let truncate = matches!(cli.command, Command::View { full_output, .. } if !full_output);
+    let keep_order =
+        matches!(cli.command, Command::View { sort, reverse, .. } if sort.is_some() || reverse);
+    let summary = matches!(cli.command, Command::View { summary, .. } if summary);
     let size = if truncate && tty {
         match crossterm::terminal::size() {
             Ok((width, height)) => Some((
@@ -80,25 +155,98 @@ fn create_formatter(cli: &Cli) -> MessageFormatter {
     } else {
         None
     };
-    let colorize = if cli.color {
-        true
-    } else if cli.no_color {
-        false
-    } else {
-        colored::control::ShouldColorize::from_env().should_colorize()
-    };
-    let timestamp_format = cli.timestamp_format.unwrap_or({
-        if tty {
-            TimestampFormat::Relative
-        } else {
-            TimestampFormat::Local
-        }
-    });
+    let colorize = should_colorize(cli);
+    let timestamp_format = cli
+        .timestamp_format
+        .clone()
+        .or_else(|| defaults.timestamp_format.clone())
+        .unwrap_or({
+            if tty {
+                TimestampFormat::Relative
+            } else {
+                TimestampFormat::Local
+            }
+        });
     MessageFormatter::new()
         .with_color(colorize)
         .with_timestamp_format(timestamp_format)
-        .with_max_columns(size.map(|(width, _)| width))
+        .with_timezone(cli.timezone.or(defaults.timezone))
+        .with_max_columns(
+            // An explicit width also truncates output that isn't going to a terminal
+            cli.width
+                .filter(|_| truncate)
+                .map(NonZeroUsize::get)
+                .or_else(|| size.map(|(width, _)| width)),
+        )
         .with_max_lines(size.map(|(_, height)| height))
+        .with_keep_ansi(cli.keep_ansi)
+        .with_keep_order(keep_order)
+        .with_summary(summary)
+}
+
+// Print the messages that a command changed unless --quiet is present
+fn echo_messages(formatter: &MessageFormatter, messages: &[Message]) -> Result<()> {
+    if log::enabled(Level::Normal) {
+        print!("{}", formatter.format_messages(messages)?);
+    }
+    Ok(())
+}
+
+// Print the messages that add or import added
+// JSON output always includes the overrides that ignored or changed the state of messages, and text
+// output only reports them on stderr in verbose mode.
+fn print_imported(
+    formatter: &MessageFormatter,
+    output: OutputFormat,
+    messages: &[Message],
+    overrides: &OverrideReport,
+) -> Result<()> {
+    match output {
+        OutputFormat::Text => {
+            echo_messages(formatter, messages)?;
+            if log::enabled(Level::Verbose) {
+                eprint!("{}", format_override_report(overrides));
+            }
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "messages": messages,
+                "overrides": overrides,
+            }))?
+        ),
+    }
+    Ok(())
+}
+
+// Determine which database to use, giving precedence to the command line and environment over the
+// config file
+fn get_database_provider(cli: &Cli, config: Option<&Config>) -> Result<DatabaseProvider> {
+    let provider = match cli.database.as_ref() {
+        Some(database) => database.parse()?,
+        None => config
+            .map(|config| config.database.clone())
+            .unwrap_or_default(),
+    };
+    // Workspaces choose between the local databases in the data directory, so any other database
+    // would silently ignore them
+    if cli.workspace.is_some() && !matches!(provider, DatabaseProvider::Sqlite { path: None }) {
+        bail!("--workspace only works with the default local SQLite database");
+    }
+    Ok(match provider {
+        DatabaseProvider::Http {
+            url,
+            token,
+            timeout,
+            retries,
+        } => DatabaseProvider::Http {
+            url,
+            token: cli.token.clone().or(token),
+            timeout,
+            retries,
+        },
+        provider @ (DatabaseProvider::Sqlite { .. } | DatabaseProvider::Memory) => provider,
+    })
 }
 
 // Convert a ViewMessageState into the list of states that it represents
@@ -114,44 +262,219 @@ fn states_from_view_message_state(state: ViewMessageState) -> Vec<State> {
     }
 }
 
-async fn run<B: Backend + Send + Sync + 'static>(
-    config: Option<Config>,
-    db: Database<B>,
-) -> Result<()> {
-    let cli = Cli::parse();
-    let formatter = create_formatter(&cli);
+// Look up a custom state defined in the config file
+fn get_custom_state<'a>(config: Option<&'a Config>, name: &str) -> Result<&'a CustomState> {
+    config
+        .with_context(|| {
+            format!("Custom state {name} isn't defined because there is no config file")
+        })?
+        .get_custom_state(name)
+}
+
+// Return the names of the custom states that a command skips, which is none without a config file
+fn get_excluded_custom_states(
+    config: Option<&Config>,
+    included: fn(&CustomState) -> bool,
+) -> Vec<String> {
+    config.map_or_else(Vec::new, |config| {
+        config.get_excluded_custom_states(included)
+    })
+}
+
+// Convert a MessageSort into the database sort that it represents
+fn sort_from_message_sort(sort: MessageSort) -> Sort {
+    match sort {
+        MessageSort::Timestamp => Sort::Timestamp,
+        MessageSort::Mailbox => Sort::Mailbox,
+        MessageSort::State => Sort::State,
+        MessageSort::LastViewed => Sort::LastViewed,
+    }
+}
+
+// Ask the user to confirm a mutation of the messages that match the filter if it would affect more
+// messages than the threshold, returning false if the user declined
+// Confirmation is only needed when stdout is a TTY so that scripts aren't interrupted
+async fn confirm_mutation<B: Backend>(
+    db: &Database<B>,
+    filter: &Filter,
+    action: &str,
+    mailbox: Option<&Mailbox>,
+    threshold: usize,
+) -> Result<bool> {
+    if !stdout().is_terminal() {
+        return Ok(true);
+    }
+
+    let count = db
+        .load_mailboxes(filter.clone())
+        .await?
+        .iter()
+        .map(|mailbox| mailbox.message_count)
+        .sum::<usize>();
+    if count <= threshold {
+        return Ok(true);
+    }
+    confirm(action, count, mailbox)
+}
+
+#[allow(clippy::too_many_lines)]
+async fn run(cli: Cli, config: Option<Config>, db: Database<Box<dyn DynBackend>>) -> Result<Exit> {
+    let defaults = config
+        .as_ref()
+        .map(|config| config.defaults.clone())
+        .unwrap_or_default();
+    let mut theme = config
+        .as_ref()
+        .map(|config| config.theme.clone())
+        .unwrap_or_default()
+        .with_color(should_colorize(&cli));
+    // ASCII mode can be enabled in the config file or on the command line
+    theme.ascii |= cli.ascii;
+    let formatter = create_formatter(&cli, &defaults).with_theme(theme.clone());
+    let confirm_threshold = defaults
+        .confirm_threshold
+        .unwrap_or(DEFAULT_CONFIRM_THRESHOLD);
+    let config_path = get_config_path(&cli)?;
+    let data_dir = get_data_dir(&cli)?;
+    let mut exit = Exit::Success;
 
     match cli.command {
         Command::Add {
             mailbox,
             content,
+            edit,
             state,
+            tags,
+            external_id,
+            upsert,
+            priority,
+            kind,
+            attachments,
+            // The HTTP backend signs the message before run is called
+            sign_key: _,
         } => {
-            let cli_state = match state {
+            let max_attachment_size = db.limits().max_attachment_size;
+            let attachments = attachments
+                .iter()
+                .map(|value| make_attachment(value, max_attachment_size))
+                .collect::<Result<Vec<_>>>()?;
+            // Messages without a state start in their mailbox's default state
+            let cli_state = state.map(|state| match state {
                 AddMessageState::Unread => State::Unread,
                 AddMessageState::Read => State::Read,
                 AddMessageState::Archived => State::Archived,
+            });
+            let (mailbox, content, state, tags) = if edit {
+                let message = compose_message(
+                    mailbox.as_ref(),
+                    cli_state.unwrap_or(State::Unread),
+                    &tags,
+                    content.as_deref().unwrap_or_default(),
+                )?
+                .context("Aborting because the message content is empty")?;
+                (
+                    message.mailbox,
+                    message.content,
+                    Some(message.state),
+                    message.tags,
+                )
+            } else {
+                // clap requires the mailbox and content unless --edit is present
+                (
+                    mailbox.context("Missing mailbox")?,
+                    content.context("Missing content")?,
+                    cli_state,
+                    tags,
+                )
             };
             let raw_messages = vec![NewMessage {
                 mailbox,
                 content,
-                state: Some(cli_state),
+                state,
+                tags,
+                timestamp: None,
+                external_id,
+                attachments,
+                priority,
+                kind: kind.map(|kind| match kind {
+                    AddMessageKind::Text => Kind::Text,
+                    AddMessageKind::Url => Kind::Url,
+                    AddMessageKind::Markdown => Kind::Markdown,
+                    AddMessageKind::Json => Kind::Json,
+                }),
             }];
-            let messages = import_messages(&db, config.as_ref(), raw_messages).await?;
-            print!("{}", formatter.format_messages(&messages)?);
+            let (messages, overrides) =
+                import_messages(&db, config.as_ref(), raw_messages, cli.keep_ansi, upsert).await?;
+            print_imported(&formatter, cli.output, &messages, &overrides)?;
         }
 
-        Command::Import { format } => {
-            let messages = import_messages(
-                &db,
-                config.as_ref(),
-                read_messages_stdin(stdin().lock(), format),
-            )
-            .await?;
-            print!("{}", formatter.format_messages(&messages)?);
+        Command::Import {
+            format,
+            tags,
+            upsert,
+            resume_from,
+            checkpoint,
+            concurrency,
+        } => {
+            let options = ImportOptions {
+                format,
+                tags,
+                keep_ansi: cli.keep_ansi,
+                upsert,
+                resume_from,
+                checkpoint,
+                report_progress: stderr().is_terminal(),
+                concurrency: usize::from(concurrency),
+            };
+            let (messages, overrides) =
+                import_stdin(&db, config.as_ref(), BufReader::new(stdin()), options).await?;
+            print_imported(&formatter, cli.output, &messages, &overrides)?;
         }
 
-        Command::View { mailbox, state, .. } => {
+        Command::Ingest { subcommand } => match subcommand {
+            IngestSubcommand::Ics {
+                source,
+                lookahead,
+                name,
+            } => {
+                let summary = ingest_ics(&db, &source, name, lookahead).await?;
+                println!(
+                    "Added {} and updated {} events in {}",
+                    format_count(summary.added),
+                    format_count(summary.updated),
+                    summary.mailbox
+                );
+            }
+        },
+
+        Command::ArchiveToFile {
+            mailbox,
+            before,
+            path,
+        } => {
+            let mut filter = Filter::new().with_mailbox_option(mailbox);
+            if let Some(before) = before {
+                filter = filter.with_before(before.and_time(chrono::NaiveTime::MIN));
+            }
+            let count = archive_to_file(&db, filter, &path).await?;
+            let plural = if count == 1 { "" } else { "s" };
+            if count == 0 {
+                println!("No messages to archive");
+            } else {
+                println!(
+                    "Archived {} message{plural} to {}",
+                    format_count(count),
+                    path.display()
+                );
+            }
+        }
+
+        Command::Export {
+            format,
+            mailbox,
+            state,
+            path,
+        } => {
             let messages = db
                 .load_messages(
                     Filter::new()
@@ -159,81 +482,822 @@ async fn run<B: Backend + Send + Sync + 'static>(
                         .with_states(states_from_view_message_state(state)),
                 )
                 .await?;
-            print!("{}", formatter.format_messages(&messages)?);
+            match (format, path) {
+                (ExportFormat::Mbox, None) => {
+                    let mut stdout = stdout().lock();
+                    write_mbox(&mut stdout, &messages)?;
+                    stdout.flush()?;
+                }
+                (format, Some(path)) => {
+                    let count = match format {
+                        ExportFormat::Mbox => {
+                            // An existing mbox file is never overwritten
+                            let file = OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(&path)
+                                .with_context(|| {
+                                    format!("Failed to create mbox file {}", path.display())
+                                })?;
+                            let mut writer = BufWriter::new(file);
+                            write_mbox(&mut writer, &messages)?;
+                            writer.flush()?;
+                            messages.len()
+                        }
+                        ExportFormat::Maildir => write_maildir(&path, &messages)?,
+                    };
+                    let plural = if count == 1 { "" } else { "s" };
+                    println!(
+                        "Exported {} message{plural} to {}",
+                        format_count(count),
+                        path.display()
+                    );
+                }
+                (ExportFormat::Maildir, None) => {
+                    bail!("Exporting to a Maildir requires a directory")
+                }
+            }
+        }
+
+        Command::ImportFromFile { path, upsert } => {
+            let messages = import_from_file(&db, &path, upsert, stderr().is_terminal()).await?;
+            echo_messages(&formatter, &messages)?;
         }
 
-        Command::Read { mailbox } => {
+        Command::View {
+            mailbox,
+            state,
+            custom_state,
+            tags,
+            sort,
+            reverse,
+            extract,
+            ..
+        } => {
+            let extract = extract
+                .map(|pattern| {
+                    Regex::new(&pattern)
+                        .with_context(|| format!("Invalid --extract regex {pattern}"))
+                })
+                .transpose()?;
+            let filter = match custom_state {
+                Some(custom_state) => {
+                    get_custom_state(config.as_ref(), &custom_state)?;
+                    Filter::new().with_custom_states(vec![custom_state])
+                }
+                None => Filter::new()
+                    .with_states(states_from_view_message_state(
+                        state.or(defaults.state).unwrap_or(ViewMessageState::Unread),
+                    ))
+                    .without_custom_states(get_excluded_custom_states(
+                        config.as_ref(),
+                        |custom_state| custom_state.view,
+                    )),
+            };
             let messages = db
-                .change_state(
-                    Filter::new()
+                .load_messages(
+                    filter
                         .with_mailbox_option(mailbox)
-                        .with_states(vec![State::Unread]),
-                    State::Read,
+                        .with_tags_option(tags)
+                        .with_sort_option(sort.map(sort_from_message_sort))
+                        .with_reverse(reverse),
                 )
                 .await?;
-            print!("{}", formatter.format_messages(&messages)?);
+            if let Some(regex) = extract {
+                for message in &messages {
+                    if let Some(fields) = extract_fields(&regex, &message.content) {
+                        println!("{fields}");
+                    }
+                }
+            } else {
+                print!("{}", formatter.format_messages(&messages)?);
+            }
+            exit = Exit::from_messages(&messages);
         }
 
-        Command::Archive { mailbox } => {
+        Command::Open {
+            mailbox,
+            external_id,
+        } => {
             let messages = db
-                .change_state(
-                    Filter::new()
-                        .with_mailbox_option(mailbox)
-                        .with_states(vec![State::Unread, State::Read]),
-                    State::Archived,
-                )
+                .load_messages(Filter::new().with_mailbox(mailbox.clone()))
                 .await?;
+            // Messages are loaded newest first
+            let Some(message) = messages
+                .into_iter()
+                .find(|message| external_id.is_none() || message.external_id == external_id)
+            else {
+                bail!("No message to open in mailbox {mailbox}");
+            };
+            let message = db.record_view(message.id).await?.unwrap_or(message);
+            match find_url(&message) {
+                Some(url) => {
+                    webbrowser::open(url).with_context(|| format!("Failed to open {url}"))?;
+                }
+                None => eprintln!("The message doesn't contain a URL to open"),
+            }
+            println!("{}", formatter.format_message(&message, None)?);
+        }
+
+        Command::Read {
+            mailbox,
+            dry_run,
+            yes,
+        } => {
+            let filter = Filter::new()
+                .with_mailbox_option(mailbox.clone())
+                .with_states(vec![State::Unread]);
+            let messages = if dry_run {
+                db.load_messages(filter).await?
+            } else if yes
+                || confirm_mutation(&db, &filter, "read", mailbox.as_ref(), confirm_threshold)
+                    .await?
+            {
+                db.change_state(filter, State::Read).await?
+            } else {
+                bail!("Aborted, no messages were read");
+            };
+            echo_messages(&formatter, &messages)?;
+            exit = Exit::from_messages(&messages);
+        }
+
+        Command::Archive {
+            mailbox,
+            dry_run,
+            yes,
+        } => {
+            let filter = Filter::new()
+                .with_mailbox_option(mailbox.clone())
+                .with_states(vec![State::Unread, State::Read])
+                .without_custom_states(get_excluded_custom_states(
+                    config.as_ref(),
+                    |custom_state| custom_state.archive,
+                ));
+            let messages = if dry_run {
+                db.load_messages(filter).await?
+            } else if yes
+                || confirm_mutation(&db, &filter, "archive", mailbox.as_ref(), confirm_threshold)
+                    .await?
+            {
+                db.change_state(filter, State::Archived).await?
+            } else {
+                bail!("Aborted, no messages were archived");
+            };
+            echo_messages(&formatter, &messages)?;
+            exit = Exit::from_messages(&messages);
+        }
+
+        Command::Clear {
+            mailbox,
+            dry_run,
+            yes,
+        } => {
+            let filter = Filter::new()
+                .with_mailbox_option(mailbox.clone())
+                .with_states(vec![State::Archived])
+                .without_custom_states(get_excluded_custom_states(
+                    config.as_ref(),
+                    |custom_state| custom_state.clear,
+                ));
+            let messages = if dry_run {
+                db.load_messages(filter).await?
+            } else if yes
+                || confirm_mutation(&db, &filter, "clear", mailbox.as_ref(), confirm_threshold)
+                    .await?
+            {
+                db.delete_messages(filter).await?
+            } else {
+                bail!("Aborted, no messages were cleared");
+            };
+            echo_messages(&formatter, &messages)?;
+            exit = Exit::from_messages(&messages);
+        }
+
+        Command::DeleteMailbox {
+            mailbox,
+            dry_run,
+            yes,
+        } => {
+            let filter = Filter::new().with_mailbox(mailbox.clone());
+            let messages = if dry_run {
+                db.load_messages(filter).await?
+            } else if yes || confirm_mutation(&db, &filter, "delete", Some(&mailbox), 0).await? {
+                db.delete_messages(filter).await?
+            } else {
+                bail!("Aborted, no messages were deleted");
+            };
+            echo_messages(&formatter, &messages)?;
+            exit = Exit::from_messages(&messages);
+        }
+
+        Command::Mailboxes {
+            state,
+            tree,
+            format,
+        } => {
+            let states = states_from_view_message_state(state);
+            let mut counts = vec![];
+            for state in &states {
+                let mailboxes = db
+                    .load_mailboxes(Filter::new().with_states(vec![*state]))
+                    .await?;
+                counts.push((*state, mailboxes));
+            }
+            let configs = db.load_mailbox_configs().await?;
+            let summaries = summarize_mailboxes(&counts, &configs);
+            let format = format.unwrap_or(match cli.output {
+                OutputFormat::Text => MailboxesFormat::Text,
+                OutputFormat::Json => MailboxesFormat::Json,
+            });
+            match format {
+                MailboxesFormat::Text => print!("{}", format_mailboxes(&summaries, &states, tree)),
+                MailboxesFormat::Json => println!("{}", serde_json::to_string_pretty(&summaries)?),
+            }
+        }
+
+        Command::Claim {
+            mailbox,
+            worker,
+            lease,
+            limit,
+            min_priority,
+        } => {
+            let filter = Filter::new()
+                .with_mailbox_option(mailbox)
+                .with_min_priority_option(min_priority)
+                .with_limit(limit);
+            let messages = db.claim_messages(filter, worker, lease).await?;
             print!("{}", formatter.format_messages(&messages)?);
+            exit = Exit::from_messages(&messages);
+        }
+
+        Command::Nack { id, reason } => {
+            let dead_letter = config
+                .as_ref()
+                .map(|config| config.dead_letter.clone())
+                .unwrap_or_default();
+            let message = db
+                .nack_message(id, reason, dead_letter)
+                .await?
+                .with_context(|| format!("Message {id} doesn't exist"))?;
+            echo_messages(&formatter, &[message])?;
+        }
+
+        Command::Mark { state, ids } => {
+            let filter = Filter::new().with_ids(ids);
+            let messages = match state.parse::<State>() {
+                Ok(state) => db.change_state(filter, state).await?,
+                Err(_) => {
+                    let base = get_custom_state(config.as_ref(), &state)?.base;
+                    db.change_custom_state(filter, state, base).await?
+                }
+            };
+            echo_messages(&formatter, &messages)?;
+        }
+
+        Command::Remind { id, delay } => {
+            let message = db
+                .remind_message(id, delay)
+                .await?
+                .with_context(|| format!("Message {id} doesn't exist"))?;
+            echo_messages(&formatter, &[message])?;
+        }
+
+        Command::Link { id, related_id } => {
+            let messages = db.link_messages(id, related_id).await?;
+            if messages.is_empty() {
+                bail!("Messages {id} and {related_id} must both exist");
+            }
+            echo_messages(&formatter, &messages)?;
         }
 
-        Command::Clear { mailbox } => {
+        Command::Stats {
+            mailbox,
+            since,
+            bucket,
+            format,
+        } => {
+            let bucket = match bucket {
+                StatsBucket::Hour => Bucket::Hour,
+                StatsBucket::Day => Bucket::Day,
+                StatsBucket::Week => Bucket::Week,
+                StatsBucket::Month => Bucket::Month,
+            };
+            let mut filter = Filter::new().with_mailbox_option(mailbox);
+            if let Some(since) = since {
+                let since = chrono::Duration::from_std(since).context("Invalid --since")?;
+                filter = filter.with_since(chrono::Utc::now().naive_utc() - since);
+            }
+            let stats = db.load_stats(filter, bucket).await?;
+            match format {
+                StatsFormat::Text => print!("{}", format_stats_table(&stats, bucket)),
+                StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+            }
+        }
+
+        Command::Digest {
+            mailbox,
+            since,
+            format,
+            post,
+        } => {
+            let since = chrono::Utc::now().naive_utc()
+                - chrono::Duration::from_std(since).context("Invalid --since")?;
             let messages = db
-                .delete_messages(
-                    Filter::new()
-                        .with_mailbox_option(mailbox)
-                        .with_states(vec![State::Archived]),
-                )
+                .load_messages(Filter::new().with_mailbox_option(mailbox).with_since(since))
                 .await?;
-            print!("{}", formatter.format_messages(&messages)?);
+            let digest = format_digest(&messages, since, format);
+            match post {
+                Some(mailbox) => {
+                    let new_message = NewMessage {
+                        mailbox,
+                        content: digest.trim_end().to_owned(),
+                        state: None,
+                        tags: vec![],
+                        timestamp: None,
+                        external_id: None,
+                        attachments: vec![],
+                        priority: None,
+                        kind: None,
+                    };
+                    let (messages, _) = import_messages(
+                        &db,
+                        config.as_ref(),
+                        vec![new_message],
+                        cli.keep_ansi,
+                        false,
+                    )
+                    .await?;
+                    echo_messages(&formatter, &messages)?;
+                }
+                None => print!("{digest}"),
+            }
+        }
+
+        Command::Check => {
+            let rules = config
+                .as_ref()
+                .map(|config| config.alerts.clone())
+                .unwrap_or_default();
+            let heartbeats = db.load_heartbeats().await?;
+            if rules.is_empty() && heartbeats.is_empty() {
+                bail!("No alert rules or heartbeats are configured, add rules to the alerts section of the config file or add heartbeats with `mailbox heartbeat set`");
+            }
+            let messages = db
+                .load_messages(Filter::new().with_states(vec![
+                    State::Unread,
+                    State::Read,
+                    State::Archived,
+                ]))
+                .await?;
+            let now = chrono::Utc::now().naive_utc();
+            let mut violations = check_alerts(&rules, &messages, now);
+            violations.extend(check_heartbeats(&heartbeats, now));
+            match cli.output {
+                OutputFormat::Text => print!("{}", format_violations(&violations)),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&violations)?),
+            }
+            if !violations.is_empty() {
+                exit = Exit::Alert;
+            }
         }
 
+        Command::Heartbeat { subcommand } => match subcommand {
+            HeartbeatSubcommand::Set { mailbox, interval } => {
+                let heartbeat = db
+                    .set_heartbeat(mailbox, Some(interval))
+                    .await?
+                    .context("The heartbeat wasn't saved")?;
+                match cli.output {
+                    OutputFormat::Text => println!(
+                        "{}: {}",
+                        heartbeat.mailbox,
+                        heartbeat.describe(chrono::Utc::now().naive_utc())
+                    ),
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&heartbeat)?),
+                }
+            }
+            HeartbeatSubcommand::Remove { mailbox } => {
+                db.set_heartbeat(mailbox.clone(), None).await?;
+                println!("{mailbox} is no longer a heartbeat");
+            }
+            HeartbeatSubcommand::List => {
+                let heartbeats = db.load_heartbeats().await?;
+                let now = chrono::Utc::now().naive_utc();
+                match cli.output {
+                    OutputFormat::Text => {
+                        for heartbeat in &heartbeats {
+                            println!(
+                                "{}: {}{}",
+                                heartbeat.mailbox,
+                                heartbeat.describe(now),
+                                if heartbeat.is_overdue(now) {
+                                    " OVERDUE"
+                                } else {
+                                    ""
+                                }
+                            );
+                        }
+                    }
+                    OutputFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&heartbeats)?);
+                    }
+                }
+                if heartbeats.iter().any(|heartbeat| heartbeat.is_overdue(now)) {
+                    exit = Exit::Alert;
+                }
+            }
+        },
+
+        Command::MailboxConfig { subcommand } => match subcommand {
+            MailboxConfigSubcommand::Show { mailbox } => {
+                let config = db.load_mailbox_config(&mailbox).await?;
+                match cli.output {
+                    OutputFormat::Text => print!("{}", format_mailbox_config(&config)),
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&config)?),
+                }
+            }
+            MailboxConfigSubcommand::List => {
+                let configs = db.load_mailbox_configs().await?;
+                match cli.output {
+                    OutputFormat::Text => {
+                        for (mailbox, config) in &configs {
+                            println!("{mailbox}");
+                            for line in format_mailbox_config(config).lines() {
+                                println!("  {line}");
+                            }
+                        }
+                    }
+                    OutputFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&configs)?);
+                    }
+                }
+            }
+            MailboxConfigSubcommand::Set {
+                mailbox,
+                description,
+                color,
+                default_state,
+                quota,
+                heartbeat,
+                pinned_order,
+                unset,
+            } => {
+                let mut config = db.load_mailbox_config(&mailbox).await?;
+                for field in unset {
+                    match field {
+                        MailboxConfigField::Description => config.description = None,
+                        MailboxConfigField::Color => config.color = None,
+                        MailboxConfigField::DefaultState => config.default_state = None,
+                        MailboxConfigField::Quota => config.quota = None,
+                        MailboxConfigField::Heartbeat => config.heartbeat_seconds = None,
+                        MailboxConfigField::PinnedOrder => config.pinned_order = None,
+                    }
+                }
+                config.description = description.or(config.description);
+                config.color = color.or(config.color);
+                config.default_state = default_state
+                    .map(|state| match state {
+                        AddMessageState::Unread => State::Unread,
+                        AddMessageState::Read => State::Read,
+                        AddMessageState::Archived => State::Archived,
+                    })
+                    .or(config.default_state);
+                config.quota = quota.or(config.quota);
+                if let Some(heartbeat) = heartbeat {
+                    config.heartbeat_seconds = Some(
+                        u32::try_from(heartbeat.as_secs())
+                            .context("The heartbeat interval is too long")?,
+                    );
+                }
+                config.pinned_order = pinned_order.or(config.pinned_order);
+                let config = db
+                    .set_mailbox_config(mailbox, config)
+                    .await
+                    .context("The mailbox config wasn't saved")?;
+                match cli.output {
+                    OutputFormat::Text => print!("{}", format_mailbox_config(&config)),
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&config)?),
+                }
+            }
+            MailboxConfigSubcommand::Remove { mailbox } => {
+                db.set_mailbox_config(mailbox.clone(), MailboxConfig::default())
+                    .await?;
+                println!("Removed the metadata of {mailbox}");
+            }
+        },
+
         Command::Tui { mailbox, state } => {
-            crate::tui::run(db, mailbox, states_from_view_message_state(state)).await?;
+            let mut states = states_from_view_message_state(
+                state.or(defaults.state).unwrap_or(ViewMessageState::Unread),
+            );
+            if state.is_none() && defaults.tui_show_archived && !states.contains(&State::Archived) {
+                states.push(State::Archived);
+            }
+            let pinned_mailboxes = PinnedMailboxes::load(data_dir.join("pinned-mailboxes.json"))?;
+            // Logging to stderr would draw over the TUI
+            set_level(Level::Normal);
+            crate::tui::run(
+                db,
+                mailbox,
+                states,
+                defaults.tui_pane.unwrap_or(Pane::Messages),
+                defaults.tui_unread_first.then_some(Sort::State),
+                pinned_mailboxes,
+                config
+                    .as_ref()
+                    .map(|config| config.aliases.clone())
+                    .unwrap_or_default(),
+                theme,
+                defaults.tui_persistent_selection,
+            )
+            .await?;
+        }
+
+        Command::Mcp => {
+            run_mcp(
+                &db,
+                config.as_ref(),
+                std::io::BufReader::new(std::io::stdin()),
+                std::io::stdout(),
+            )
+            .await?;
+        }
+
+        // The SQLite provider runs the server before getting here
+        Command::Serve { .. } => {
+            bail!("The serve command requires a SQLite database, not an HTTP or in-memory database")
+        }
+
+        // The SQLite provider runs db commands before getting here
+        Command::Db { .. } => {
+            bail!("The db command requires a SQLite database, not an HTTP or in-memory database")
+        }
+
+        Command::Query { .. } => {
+            bail!("The query command requires a SQLite database, not an HTTP or in-memory database")
+        }
+
+        // run_cli runs the doctor and lists workspaces before loading the config
+        Command::Doctor => bail!("The doctor command can't run with a database"),
+        Command::Workspace { .. } => bail!("The workspace command can't run with a database"),
+
+        // The HTTP provider runs admin commands before getting here
+        Command::Admin { .. } => {
+            bail!("The admin command requires an HTTP database")
         }
 
         Command::Config { subcommand } => match subcommand {
-            ConfigSubcommand::Locate => println!("{}", get_config_path()?.to_string_lossy()),
-            ConfigSubcommand::Edit => edit_config()?,
+            ConfigSubcommand::Locate => println!("{}", config_path.to_string_lossy()),
+            ConfigSubcommand::Edit => open_editor(&config_path)?,
         },
+
+        Command::CompleteMailboxes => {
+            // Include ancestor mailboxes, which can be used as filters even if they don't contain
+            // messages directly
+            let names = db
+                .load_mailboxes(Filter::new())
+                .await?
+                .iter()
+                .flat_map(|mailbox| mailbox.name.iter_ancestors())
+                .collect::<BTreeSet<_>>();
+            for name in names {
+                println!("{name}");
+            }
+        }
+    }
+
+    Ok(exit)
+}
+
+// Apply the configured options to a database
+fn configure_database<B: Backend>(db: Database<B>, config: Option<&Config>) -> Database<B> {
+    match config {
+        Some(config) => db
+            .with_archive_on_read(config.archive_on_read.clone())
+            .with_limits(config.limits.clone())
+            .with_transition_policy(config.transitions.clone())
+            .with_heartbeat_alerts(config.heartbeat_alerts.clone()),
+        None => db,
+    }
+}
+
+// Run the command, returning the exit code
+// Expand the configured mailbox aliases in the command's mailbox arguments
+fn resolve_aliases(command: &mut Command, config: &Config) {
+    let resolve = |mailbox: &mut Option<Mailbox>| {
+        *mailbox = mailbox.take().map(|mailbox| config.resolve_alias(mailbox));
     };
+    match command {
+        Command::Open { mailbox, .. }
+        | Command::DeleteMailbox { mailbox, .. }
+        | Command::Heartbeat {
+            subcommand:
+                HeartbeatSubcommand::Set { mailbox, .. } | HeartbeatSubcommand::Remove { mailbox },
+        }
+        | Command::MailboxConfig {
+            subcommand:
+                MailboxConfigSubcommand::Show { mailbox }
+                | MailboxConfigSubcommand::Set { mailbox, .. }
+                | MailboxConfigSubcommand::Remove { mailbox },
+        } => {
+            *mailbox = config.resolve_alias(mailbox.clone());
+        }
+        Command::Add { mailbox, .. }
+        | Command::ArchiveToFile { mailbox, .. }
+        | Command::Export { mailbox, .. }
+        | Command::View { mailbox, .. }
+        | Command::Read { mailbox, .. }
+        | Command::Archive { mailbox, .. }
+        | Command::Clear { mailbox, .. }
+        | Command::Claim { mailbox, .. }
+        | Command::Stats { mailbox, .. }
+        | Command::Tui { mailbox, .. } => resolve(mailbox),
+        Command::Digest { mailbox, post, .. } => {
+            resolve(mailbox);
+            resolve(post);
+        }
+        _ => {}
+    }
+}
 
-    Ok(())
+async fn run_cli(mut cli: Cli) -> Result<Exit> {
+    set_level(Level::from_flags(cli.quiet, cli.verbose));
+    // The doctor reports problems with the config and database instead of failing on them
+    if matches!(cli.command, Command::Doctor) {
+        return run_doctor(&cli).await;
+    }
+    if let Command::Workspace {
+        subcommand: WorkspaceSubcommand::List,
+    } = cli.command
+    {
+        let workspaces = list_workspaces(&get_data_dir(&cli)?)?;
+        let active = cli.workspace.as_deref().unwrap_or(DEFAULT_WORKSPACE);
+        print!("{}", format_workspaces(&workspaces, active));
+        return Ok(Exit::Success);
+    }
+    let config = Config::load(&get_config_path(&cli)?)?;
+    if let Some(config) = config.as_ref() {
+        resolve_aliases(&mut cli.command, config);
+    }
+    let consumer = cli
+        .consumer
+        .clone()
+        .or_else(|| config.as_ref().and_then(|config| config.consumer.clone()));
+    let db = match get_database_provider(&cli, config.as_ref())? {
+        DatabaseProvider::Sqlite { path } => {
+            let db_path = match path {
+                Some(path) => path,
+                None => get_default_db_path(&cli)?,
+            };
+            verbose!("Using the SQLite database at {}", db_path.display());
+            let backend = SqliteBackend::new(db_path.clone()).await?;
+            if let Command::Serve {
+                port,
+                expose,
+                auth_token,
+                admin_token,
+                shutdown_timeout,
+                signing_keys,
+                slack_signing_secret,
+            } = cli.command
+            {
+                let options = ServeOptions {
+                    expose,
+                    port,
+                    token: auth_token,
+                    admin_token,
+                    shutdown_timeout,
+                    signing_keys,
+                    slack_signing_secret,
+                    forwards: config
+                        .as_ref()
+                        .map(|config| config.forwards.clone())
+                        .unwrap_or_default(),
+                    bridges: config
+                        .as_ref()
+                        .map(|config| config.bridges.clone())
+                        .unwrap_or_default(),
+                };
+                serve(
+                    configure_database(Database::new(backend), config.as_ref()),
+                    options,
+                )
+                .await?;
+                return Ok(Exit::Success);
+            }
+            if let Command::Db { subcommand } = cli.command {
+                match subcommand {
+                    DbSubcommand::Backup { dest } => {
+                        let size = backend.backup(&dest).await?;
+                        println!(
+                            "Backed up the database to {} ({})",
+                            dest.display(),
+                            format_bytes(size)
+                        );
+                    }
+                    DbSubcommand::Info => {
+                        let info = backend.load_info().await?;
+                        let database_bytes = fs::metadata(&db_path)
+                            .with_context(|| format!("Failed to read {}", db_path.display()))?
+                            .len();
+                        let mut wal_path = db_path.clone().into_os_string();
+                        wal_path.push("-wal");
+                        // The WAL doesn't exist while no connections have the database open
+                        let wal_bytes = fs::metadata(wal_path).map_or(0, |metadata| metadata.len());
+                        print!(
+                            "{}",
+                            format_sqlite_info(&db_path, &info, database_bytes, wal_bytes)
+                        );
+                    }
+                }
+                return Ok(Exit::Success);
+            }
+            if let Command::Query { sql } = cli.command {
+                let result = SqliteBackend::query_read_only(&db_path, &sql).await?;
+                match cli.output {
+                    OutputFormat::Text => print!("{}", format_query_table(&result)),
+                    OutputFormat::Json => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&query_rows_to_json(&result))?
+                    ),
+                }
+                return Ok(Exit::Success);
+            }
+            Database::new_dyn(TimedBackend::new(backend))
+        }
+        DatabaseProvider::Http {
+            url,
+            token,
+            timeout,
+            retries,
+        } => {
+            verbose!("Using the database at {url}");
+            let mut backend = HttpBackend::new(url.clone(), token)?
+                .with_timeout(timeout.map(Duration::from_secs));
+            if let Some(retries) = retries {
+                backend = backend.with_max_retries(retries);
+            }
+            if let Command::Add { sign_key, .. } = &cli.command {
+                backend = backend.with_signing_key(sign_key.clone());
+            }
+            if let Command::Admin {
+                admin_token,
+                subcommand,
+            } = cli.command
+            {
+                let backend = backend.with_admin_token(Some(admin_token));
+                run_admin(&backend, subcommand).await?;
+                return Ok(Exit::Success);
+            }
+            if let Command::Db {
+                subcommand: DbSubcommand::Info,
+            } = cli.command
+            {
+                let version = backend.check_server().await?.version;
+                let mut counts = [0; 3];
+                for (index, state) in [State::Unread, State::Read, State::Archived]
+                    .into_iter()
+                    .enumerate()
+                {
+                    counts[index] = backend
+                        .load_mailboxes(Filter::new().with_states(vec![state]))
+                        .await?
+                        .iter()
+                        .map(|mailbox| mailbox.message_count)
+                        .sum();
+                }
+                print!("{}", format_http_info(&url, version.as_deref(), counts));
+                return Ok(Exit::Success);
+            }
+            Database::new_dyn(TimedBackend::new(backend))
+        }
+        DatabaseProvider::Memory => Database::new_dyn(TimedBackend::new(MemoryBackend::new())),
+    };
+
+    // Every other command works the same way regardless of the backend
+    let db = configure_database(db, config.as_ref()).with_consumer(consumer);
+    run(cli, config, db).await
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     // Fix broken pipe panics
     sigpipe::reset();
 
-    let config = load_config()?;
-    let database = config
-        .as_ref()
-        .map(|config| config.database.clone())
-        .unwrap_or_default();
-    match database {
-        config::DatabaseProvider::Sqlite => {
-            let project_dirs = get_project_dirs()?;
-            let backend =
-                SqliteBackend::new(project_dirs.data_local_dir().join("mailbox.db")).await?;
-            let db = Database::new(backend);
-            run(config, db).await?;
-        }
-        config::DatabaseProvider::Http { url, token } => {
-            let backend = HttpBackend::new(url, token)?;
-            let db = Database::new(backend);
-            run(config, db).await?;
+    let cli = Cli::parse();
+    let output = cli.output;
+    match run_cli(cli).await {
+        Ok(exit) => exit.into(),
+        Err(err) => {
+            match output {
+                OutputFormat::Text => {
+                    // Match the format of errors returned from main
+                    eprintln!("Error: {err:?}");
+                }
+                OutputFormat::Json => eprintln!("{}", format_error_json(&err)),
+            }
+            Exit::from_error(&err).into()
         }
     }
-
-    Ok(())
 }
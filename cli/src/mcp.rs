@@ -0,0 +1,463 @@
+use crate::config::Config;
+use crate::import::import_messages;
+use anyhow::{Context, Result};
+use database::{Backend, Database, Filter, Id, Mailbox, NewMessage, State, Tag};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::thread;
+use tokio::sync::mpsc;
+
+// The version of the Model Context Protocol that the server implements
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+// The maximum number of messages that view_messages returns by default, which keeps responses small
+// enough for an assistant's context window
+const DEFAULT_VIEW_LIMIT: usize = 50;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// A JSON-RPC request, or a notification if it doesn't have an id
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+// A JSON-RPC error
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(err: impl std::fmt::Display) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: format!("Invalid params: {err}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListMailboxesArgs {
+    states: Option<Vec<State>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ViewMessagesArgs {
+    mailbox: Option<Mailbox>,
+    states: Option<Vec<State>>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AddMessageArgs {
+    mailbox: Mailbox,
+    content: String,
+    #[serde(default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChangeStateArgs {
+    ids: Option<Vec<Id>>,
+    mailbox: Option<Mailbox>,
+    new_state: State,
+}
+
+// The JSON schema of a list of message states
+fn states_schema(description: &str) -> Value {
+    json!({
+        "type": "array",
+        "items": { "type": "string", "enum": ["unread", "read", "archived"] },
+        "description": description,
+    })
+}
+
+// Describe the tools that the server provides
+fn list_tools() -> Value {
+    json!({
+        "tools": [
+            {
+                "name": "list_mailboxes",
+                "description": "List the mailboxes and how many messages each one contains",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "states": states_schema("Only count messages in these states (defaults to unread and read)"),
+                    },
+                },
+            },
+            {
+                "name": "view_messages",
+                "description": "List messages, newest first",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "mailbox": {
+                            "type": "string",
+                            "description": "Only list messages in this mailbox and its descendants",
+                        },
+                        "states": states_schema("Only list messages in these states (defaults to unread and read)"),
+                        "limit": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": format!("The maximum number of messages to list (defaults to {DEFAULT_VIEW_LIMIT})"),
+                        },
+                    },
+                },
+            },
+            {
+                "name": "add_message",
+                "description": "Add a new unread message to a mailbox",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "mailbox": { "type": "string", "description": "The mailbox, like \"ci/project-a\"" },
+                        "content": { "type": "string" },
+                        "tags": { "type": "array", "items": { "type": "string" } },
+                    },
+                    "required": ["mailbox", "content"],
+                },
+            },
+            {
+                "name": "change_state",
+                "description": "Mark messages as unread, read, or archived",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "ids": {
+                            "type": "array",
+                            "items": { "type": "integer" },
+                            "description": "The ids of the messages to change",
+                        },
+                        "mailbox": {
+                            "type": "string",
+                            "description": "Change every message in this mailbox and its descendants",
+                        },
+                        "new_state": { "type": "string", "enum": ["unread", "read", "archived"] },
+                    },
+                    "required": ["new_state"],
+                },
+            },
+        ],
+    })
+}
+
+// Parse tool arguments, treating missing arguments as an empty object
+fn parse_args<T: DeserializeOwned>(arguments: Value) -> Result<T, RpcError> {
+    let arguments = if arguments.is_null() {
+        json!({})
+    } else {
+        arguments
+    };
+    serde_json::from_value(arguments).map_err(RpcError::invalid_params)
+}
+
+// Run a tool, returning its output as JSON
+async fn call_tool<B: Backend>(
+    db: &Database<B>,
+    config: Option<&Config>,
+    call: ToolCall,
+) -> Result<Result<Value>, RpcError> {
+    let active_states = || vec![State::Unread, State::Read];
+    Ok(match call.name.as_str() {
+        "list_mailboxes" => {
+            let args: ListMailboxesArgs = parse_args(call.arguments)?;
+            let filter = Filter::new().with_states(args.states.unwrap_or_else(active_states));
+            db.load_mailboxes(filter)
+                .await
+                .and_then(|mailboxes| Ok(serde_json::to_value(mailboxes)?))
+        }
+        "view_messages" => {
+            let args: ViewMessagesArgs = parse_args(call.arguments)?;
+            let filter = Filter::new()
+                .with_mailbox_option(args.mailbox)
+                .with_states(args.states.unwrap_or_else(active_states))
+                .with_limit(args.limit.unwrap_or(DEFAULT_VIEW_LIMIT));
+            db.load_messages(filter)
+                .await
+                .and_then(|messages| Ok(serde_json::to_value(messages)?))
+        }
+        "add_message" => {
+            let args: AddMessageArgs = parse_args(call.arguments)?;
+            let message = NewMessage {
+                mailbox: args.mailbox,
+                content: args.content,
+                state: None,
+                tags: args.tags,
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            };
+            import_messages(db, config, vec![message], false, false)
+                .await
+                .and_then(|(messages, _)| Ok(serde_json::to_value(messages)?))
+        }
+        "change_state" => {
+            let args: ChangeStateArgs = parse_args(call.arguments)?;
+            // Require a target so that a missing argument doesn't change every message
+            if args.ids.is_none() && args.mailbox.is_none() {
+                return Err(RpcError::invalid_params("ids or mailbox is required"));
+            }
+            let filter = Filter::new()
+                .with_mailbox_option(args.mailbox)
+                .with_ids(args.ids.unwrap_or_default());
+            db.change_state(filter, args.new_state)
+                .await
+                .and_then(|messages| Ok(serde_json::to_value(messages)?))
+        }
+        name => {
+            return Err(RpcError {
+                code: INVALID_PARAMS,
+                message: format!("Unknown tool \"{name}\""),
+            })
+        }
+    })
+}
+
+// Handle a JSON-RPC request, returning the result
+async fn handle_request<B: Backend>(
+    db: &Database<B>,
+    config: Option<&Config>,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "mailbox", "version": env!("CARGO_PKG_VERSION") },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(list_tools()),
+        "tools/call" => {
+            let call: ToolCall =
+                serde_json::from_value(params).map_err(RpcError::invalid_params)?;
+            // Tool failures are reported to the assistant in the result instead of as protocol
+            // errors so that it can react to them
+            Ok(match call_tool(db, config, call).await? {
+                Ok(output) => json!({
+                    "content": [{ "type": "text", "text": serde_json::to_string_pretty(&output).unwrap_or_default() }],
+                    "isError": false,
+                }),
+                Err(err) => json!({
+                    "content": [{ "type": "text", "text": format!("{err:#}") }],
+                    "isError": true,
+                }),
+            })
+        }
+        method => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unknown method \"{method}\""),
+        }),
+    }
+}
+
+// Handle a line of JSON-RPC input, returning the response or None if the line was a notification
+async fn handle_line<B: Backend>(
+    db: &Database<B>,
+    config: Option<&Config>,
+    line: &str,
+) -> Option<Value> {
+    let request = match serde_json::from_str::<Request>(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return Some(json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": format!("Parse error: {err}") },
+            }))
+        }
+    };
+    let result = handle_request(db, config, &request.method, request.params).await;
+    // Notifications don't get responses, even when they fail
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    })
+}
+
+// Serve the mailbox tools over the Model Context Protocol, reading JSON-RPC messages from stdin and
+// writing responses to stdout, one per line, until stdin closes
+pub async fn run_mcp<B: Backend>(
+    db: &Database<B>,
+    config: Option<&Config>,
+    stdin: impl BufRead + Send + 'static,
+    mut stdout: impl Write,
+) -> Result<()> {
+    // Read stdin on a separate thread so that reading doesn't block the runtime
+    let (tx, mut rx) = mpsc::channel(1);
+    thread::spawn(move || {
+        for line in stdin.lines() {
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(line) = rx.recv().await {
+        let line = line.context("Failed to read stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(db, config, &line).await {
+            writeln!(stdout, "{response}").context("Failed to write stdout")?;
+            stdout.flush().context("Failed to write stdout")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::MemoryBackend;
+
+    async fn call(db: &Database<MemoryBackend>, request: Value) -> Value {
+        handle_line(db, None, &request.to_string()).await.unwrap()
+    }
+
+    // Call a tool and return the text of its output
+    async fn call_tool_text(db: &Database<MemoryBackend>, name: &str, arguments: Value) -> Value {
+        let response = call(
+            db,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": name, "arguments": arguments },
+            }),
+        )
+        .await;
+        assert_eq!(response["result"]["isError"], false, "{response}");
+        serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_initialize() {
+        let db = Database::new(MemoryBackend::new());
+        let response = call(
+            &db,
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+        )
+        .await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(handle_line(&db, None, &notification.to_string())
+            .await
+            .is_none());
+
+        let response = call(
+            &db,
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+        )
+        .await;
+        assert_eq!(response["result"]["tools"].as_array().unwrap().len(), 4);
+
+        let response = call(
+            &db,
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "unknown" }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response = handle_line(&db, None, "not json").await.unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_tools() {
+        let db = Database::new(MemoryBackend::new());
+        let added = call_tool_text(
+            &db,
+            "add_message",
+            json!({ "mailbox": "ci/build", "content": "Build failed", "tags": ["urgent"] }),
+        )
+        .await;
+        let id = added[0]["id"].as_u64().unwrap();
+        call_tool_text(
+            &db,
+            "add_message",
+            json!({ "mailbox": "personal", "content": "Call mom" }),
+        )
+        .await;
+
+        let mailboxes = call_tool_text(&db, "list_mailboxes", Value::Null).await;
+        assert_eq!(mailboxes.as_array().unwrap().len(), 2);
+
+        let messages = call_tool_text(
+            &db,
+            "view_messages",
+            json!({ "mailbox": "ci", "limit": 10 }),
+        )
+        .await;
+        assert_eq!(messages[0]["content"], "Build failed");
+        assert_eq!(messages.as_array().unwrap().len(), 1);
+
+        let changed = call_tool_text(
+            &db,
+            "change_state",
+            json!({ "ids": [id], "new_state": "archived" }),
+        )
+        .await;
+        assert_eq!(changed[0]["state"], "archived");
+        let messages = call_tool_text(&db, "view_messages", json!({})).await;
+        assert_eq!(messages.as_array().unwrap().len(), 1);
+
+        // Changing state requires a target
+        let response = call(
+            &db,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "change_state", "arguments": { "new_state": "read" } },
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        // Tool failures are reported in the result
+        let response = call(
+            &db,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "add_message", "arguments": { "mailbox": "a", "content": "" } },
+            }),
+        )
+        .await;
+        assert_eq!(response["result"]["isError"], true);
+    }
+}
@@ -1,10 +1,14 @@
+use crate::ansi::{strip_ansi, RESET};
 use crate::cli::TimestampFormat;
 use crate::message_components::MessageComponents;
-use crate::truncate::TruncatedLine;
+use crate::theme::Theme;
+use crate::truncate::{ApplyColor, TruncatedLine, ASCII_ELLIPSIS, ELLIPSIS};
 use anyhow::{anyhow, Result};
-use chrono::{Local, TimeZone, Utc};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
 use chrono_humanize::HumanTime;
-use database::{Message, State};
+use chrono_tz::Tz;
+use database::{Kind, Message, State};
+use std::fmt::{Display, Write};
 use std::{collections::HashMap, sync::Once};
 
 enum Word {
@@ -53,10 +57,14 @@ impl<'messages> Mailbox<'messages> {
 }
 
 pub struct MessageFormatter {
-    color: bool,
+    theme: Theme,
     timestamp_format: TimestampFormat,
+    timezone: Option<Tz>,
     max_columns: Option<usize>,
     max_lines: Option<usize>,
+    keep_ansi: bool,
+    keep_order: bool,
+    summary: bool,
 }
 
 // MessageFormatter is responsible for formatting individual messages as well
@@ -66,16 +74,31 @@ pub struct MessageFormatter {
 impl MessageFormatter {
     pub fn new() -> Self {
         Self {
-            color: true,
+            theme: Theme::default().with_color(true),
             timestamp_format: TimestampFormat::Relative,
+            timezone: None,
             max_columns: None,
             max_lines: None,
+            keep_ansi: false,
+            keep_order: false,
+            summary: false,
         }
     }
 
     // Configure whether the output is colored
     pub fn with_color(self, color: bool) -> Self {
-        Self { color, ..self }
+        Self {
+            theme: self.theme.with_color(color),
+            ..self
+        }
+    }
+
+    // Configure the colors, state glyphs, and ASCII mode, keeping whether the output is colored
+    pub fn with_theme(self, theme: Theme) -> Self {
+        Self {
+            theme: theme.with_color(self.theme.color),
+            ..self
+        }
     }
 
     // Configure the output timestamp format
@@ -86,6 +109,11 @@ impl MessageFormatter {
         }
     }
 
+    // Configure the time zone of local and custom timestamps, None is the local time zone
+    pub fn with_timezone(self, timezone: Option<Tz>) -> Self {
+        Self { timezone, ..self }
+    }
+
     // Configure the maximum number of output columns, None is no limit
     pub fn with_max_columns(self, max_columns: Option<usize>) -> Self {
         Self {
@@ -99,9 +127,45 @@ impl MessageFormatter {
         Self { max_lines, ..self }
     }
 
+    // Configure whether ANSI escape sequences in message content are preserved instead of stripped
+    pub fn with_keep_ansi(self, keep_ansi: bool) -> Self {
+        Self { keep_ansi, ..self }
+    }
+
+    // Configure whether messages are output in the order that they were provided instead of being
+    // grouped by mailbox, newest first
+    pub fn with_keep_order(self, keep_order: bool) -> Self {
+        Self { keep_order, ..self }
+    }
+
+    // Configure whether each mailbox is summarized as its newest message and a count of its older
+    // messages
+    pub fn with_summary(self, summary: bool) -> Self {
+        Self { summary, ..self }
+    }
+
+    // Format a timestamp in the configured time zone, optionally with a strftime format string
+    fn format_zoned(&self, timestamp: NaiveDateTime, format: Option<&str>) -> String {
+        fn format_time<Zone: TimeZone>(time: &DateTime<Zone>, format: Option<&str>) -> String
+        where
+            Zone::Offset: Display,
+        {
+            format.map_or_else(
+                || time.to_string(),
+                |format| time.format(format).to_string(),
+            )
+        }
+
+        let time = timestamp.and_utc();
+        self.timezone.map_or_else(
+            || format_time(&time.with_timezone(&Local), format),
+            |timezone| format_time(&time.with_timezone(&timezone), format),
+        )
+    }
+
     // Format a single message into a string. There will not be a newline at the end.
     pub fn format_message(&self, message: &Message, appendix: Option<String>) -> Result<String> {
-        use colored::Colorize;
+        use colored::{Color, Colorize};
 
         static INIT: Once = Once::new();
         INIT.call_once(|| {
@@ -110,7 +174,7 @@ impl MessageFormatter {
         });
 
         // Display the time differently based on the requested format
-        let time = match self.timestamp_format {
+        let time = match &self.timestamp_format {
             TimestampFormat::Relative => Some(
                 HumanTime::from(
                     message
@@ -119,56 +183,78 @@ impl MessageFormatter {
                 )
                 .to_string(),
             ),
-            TimestampFormat::Local => Local
-                .timestamp_opt(message.timestamp.and_utc().timestamp(), 0)
-                .single()
-                .map(|time| time.to_string()),
+            TimestampFormat::Local => Some(self.format_zoned(message.timestamp, None)),
             TimestampFormat::Utc => Utc
                 .timestamp_opt(message.timestamp.and_utc().timestamp(), 0)
                 .single()
                 .map(|time| time.to_string()),
+            TimestampFormat::Custom(format) => {
+                Some(self.format_zoned(message.timestamp, Some(format)))
+            }
         };
 
         let max_columns = self.max_columns.unwrap_or(usize::MAX);
+        // Collapse JSON onto one line and drop the whitespace around URLs
+        let content = match message.kind {
+            Kind::Json => serde_json::from_str::<serde_json::Value>(&message.content)
+                .map_or_else(|_| message.content.clone(), |value| value.to_string()),
+            Kind::Url => message.content.trim().to_owned(),
+            Kind::Text | Kind::Markdown => message.content.clone(),
+        };
         let components = MessageComponents {
             state: message.state,
-            content: message.content.clone(),
+            content: if self.keep_ansi {
+                content
+            } else {
+                strip_ansi(&content)
+            },
             mailbox: message.mailbox.clone().into(),
             time: time.ok_or_else(|| anyhow!("Could not determine timestamp"))?,
-            appendix: appendix.unwrap_or_default(),
+            // Name the custom state that refines the message's state before the rest of the appendix
+            appendix: match message.custom_state.as_ref() {
+                Some(custom_state) => format!(" ({custom_state}){}", appendix.unwrap_or_default()),
+                None => appendix.unwrap_or_default(),
+            },
         }
-        .truncate(max_columns);
-
-        let mut line = TruncatedLine::new(max_columns);
+        .truncate(max_columns, self.theme.symbol(ELLIPSIS, ASCII_ELLIPSIS));
+
+        let mut line = TruncatedLine::new(max_columns)
+            .with_ellipsis(self.theme.symbol(ELLIPSIS, ASCII_ELLIPSIS));
+        let colors = self.theme.colors();
+        let paint = |color: Option<Color>, bold: bool| -> Option<ApplyColor> {
+            color.map(|color| -> ApplyColor {
+                if bold {
+                    Box::new(move |str: &str| str.color(color).bold())
+                } else {
+                    Box::new(move |str: &str| str.color(color))
+                }
+            })
+        };
         line.append(
-            match components.state {
-                State::Unread => "*",
-                State::Read => " ",
-                State::Archived => "-",
-            },
-            if matches!(message.state, State::Unread) && self.color {
-                Some(|str: &str| str.red().bold())
-            } else {
-                None
-            },
+            self.theme.state_glyph(components.state),
+            paint(
+                colors
+                    .as_ref()
+                    .filter(|_| message.state == State::Unread)
+                    .map(|colors| colors.unread),
+                true,
+            ),
         );
-        line.append(format!(" {} [", components.content), None);
+        // Reset the styles that the content set so that they don't leak into the rest of the line
+        let reset = if self.keep_ansi && components.content.contains('\u{1b}') {
+            RESET
+        } else {
+            ""
+        };
+        line.append(format!(" {}{reset} [", components.content), None);
         line.append(
             components.mailbox,
-            if self.color {
-                Some(|str: &str| str.green().bold())
-            } else {
-                None
-            },
+            paint(colors.as_ref().map(|colors| colors.mailbox), true),
         );
         line.append("] @ ", None);
         line.append(
             components.time,
-            if self.color {
-                Some(|str: &str| str.yellow())
-            } else {
-                None
-            },
+            paint(colors.as_ref().map(|colors| colors.timestamp), false),
         );
         line.append(components.appendix, None);
         Ok(line.to_string())
@@ -176,6 +262,10 @@ impl MessageFormatter {
 
     // Format multiple messages into a string. There will be a newline at the end.
     pub fn format_messages(&self, messages: &[Message]) -> Result<String> {
+        if self.keep_order {
+            return self.format_messages_in_order(messages);
+        }
+
         // Group the messages by mailbox
         let mut mailboxes: HashMap<&database::Mailbox, Vec<&Message>> = HashMap::new();
         for message in messages {
@@ -194,10 +284,12 @@ impl MessageFormatter {
             .collect::<Vec<_>>();
         mailboxes.sort_by_key(|mailbox| (-mailbox.most_recent_timestamp, mailbox.name));
 
+        // Summaries show at most one message from each mailbox
+        let lines_per_mailbox = if self.summary { 1 } else { usize::MAX };
         let max_lines = std::cmp::min(
             mailboxes
                 .iter()
-                .map(|mailbox| mailbox.messages.len())
+                .map(|mailbox| mailbox.messages.len().min(lines_per_mailbox))
                 .sum::<usize>(),
             self.max_lines.unwrap_or(usize::MAX),
         );
@@ -206,7 +298,7 @@ impl MessageFormatter {
         let mut line = 0;
         while line < max_lines {
             for mailbox in &mut mailboxes {
-                if mailbox.allocated_lines < mailbox.messages.len() {
+                if mailbox.allocated_lines < mailbox.messages.len().min(lines_per_mailbox) {
                     mailbox.allocated_lines += 1;
                     line += 1;
                 }
@@ -276,6 +368,33 @@ impl MessageFormatter {
             + &hidden_mailboxes_message.unwrap_or_default())
     }
 
+    // Format multiple messages in the order that they were provided. There will be a newline at
+    // the end.
+    fn format_messages_in_order(&self, messages: &[Message]) -> Result<String> {
+        let max_lines = self.max_lines.unwrap_or(usize::MAX);
+        // If there aren't enough lines to show every message, reserve one line for the hidden
+        // messages message
+        let displayed_count = if messages.len() > max_lines {
+            max_lines.saturating_sub(1)
+        } else {
+            messages.len()
+        };
+        let mut output = messages
+            .iter()
+            .take(displayed_count)
+            .map(|message| Ok(self.format_message(message, None)? + "\n"))
+            .collect::<Result<String>>()?;
+        let hidden_count = messages.len() - displayed_count;
+        if hidden_count > 0 {
+            let _ = writeln!(
+                output,
+                "(+{hidden_count} more {})",
+                Self::pluralize_word(Word::Message, hidden_count)
+            );
+        }
+        Ok(output)
+    }
+
     // Pluralize a word if count is not 1
     fn pluralize_word(word: Word, count: usize) -> &'static str {
         match (word, count) {
@@ -314,6 +433,7 @@ impl MessageFormatter {
 mod tests {
     use super::*;
 
+    use crate::theme::Glyphs;
     use chrono::DateTime;
 
     // Helper for creating a new message
@@ -326,6 +446,14 @@ mod tests {
             mailbox: mailbox.try_into().unwrap(),
             content: content.into(),
             state: State::Unread,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            tags: vec![],
+            priority: 0,
+            kind: Kind::Text,
+            related_ids: vec![],
         }
     }
 
@@ -346,6 +474,98 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_format_json() {
+        let message = Message {
+            kind: Kind::Json,
+            ..make_message("a", "{\n  \"status\": \"failed\"\n}", 0)
+        };
+        let formatter = make_formatter();
+        assert_eq!(
+            formatter.format_message(&message, None).unwrap(),
+            "* {\"status\":\"failed\"} [a] @ 2022-01-01 00:00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn test_format_custom_state() {
+        let message = Message {
+            state: State::Read,
+            custom_state: Some(String::from("in-progress")),
+            ..make_message("a", "foo", 0)
+        };
+        let formatter = make_formatter();
+        assert_eq!(
+            formatter
+                .format_message(&message, Some(String::from(" (+1 older message)")))
+                .unwrap(),
+            "  foo [a] @ 2022-01-01 00:00:00 UTC (in-progress) (+1 older message)"
+        );
+    }
+
+    #[test]
+    fn test_format_custom_timestamp() {
+        let messages = vec![make_message("a", "foo", 0)];
+        // Only format the seconds because the other fields depend on the local time zone
+        let formatter = make_formatter()
+            .with_timestamp_format(TimestampFormat::Custom(String::from("%S seconds")));
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* foo [a] @ 00 seconds\n"
+        );
+    }
+
+    #[test]
+    fn test_format_timezone() {
+        let messages = vec![make_message("a", "foo", 0)];
+        let formatter = make_formatter()
+            .with_timestamp_format(TimestampFormat::Local)
+            .with_timezone(Some(Tz::Europe__Berlin));
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* foo [a] @ 2022-01-01 01:00:00 CET\n"
+        );
+
+        let formatter = formatter
+            .with_timestamp_format(TimestampFormat::Custom(String::from("%H:%M %Z")))
+            .with_timezone(Some(Tz::America__New_York));
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* foo [a] @ 19:00 EST\n"
+        );
+    }
+
+    #[test]
+    fn test_format_glyphs() {
+        let messages = vec![make_message("a", "foo", 0)];
+        let formatter = make_formatter().with_theme(Theme {
+            glyphs: Glyphs {
+                unread: String::from("U"),
+                ..Glyphs::default()
+            },
+            ..Theme::default()
+        });
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "U foo [a] @ 2022-01-01 00:00:00 UTC\n"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let messages = vec![
+            make_message("a", "foo", 0),
+            make_message("a", "bar", 1),
+            make_message("a", "baz", 2),
+            make_message("b", "qux", 3),
+        ];
+        let formatter = make_formatter().with_summary(true);
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* qux [b] @ 2022-01-01 00:00:03 UTC\n* baz [a] @ 2022-01-01 00:00:02 UTC (+2 older messages)\n"
+        );
+    }
+
     #[test]
     fn test_empty() {
         let messages = vec![];
@@ -377,6 +597,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_keep_order() {
+        let messages = vec![
+            make_message("foo", "a", 0),
+            make_message("bar", "b", 2),
+            make_message("foo", "c", 1),
+        ];
+        let formatter = make_formatter().with_keep_order(true);
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* a [foo] @ 2022-01-01 00:00:00 UTC
+* b [bar] @ 2022-01-01 00:00:02 UTC
+* c [foo] @ 2022-01-01 00:00:01 UTC\n"
+        );
+
+        let formatter = formatter.with_max_lines(Some(2));
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* a [foo] @ 2022-01-01 00:00:00 UTC
+(+2 more messages)\n"
+        );
+    }
+
     #[test]
     fn test_truncate_content() {
         let formatter = make_formatter().with_max_columns(Some(60));
@@ -396,6 +639,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_strip_ansi() {
+        let messages = vec![make_message("a", "\u{1b}[31mfoo\u{1b}[0m", 0)];
+        let formatter = make_formatter();
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* foo [a] @ 2022-01-01 00:00:00 UTC\n"
+        );
+    }
+
+    #[test]
+    fn test_keep_ansi() {
+        let messages = vec![make_message("a", "\u{1b}[31mfoo", 0)];
+        let formatter = make_formatter().with_keep_ansi(true);
+        assert_eq!(
+            formatter.format_messages(&messages).unwrap().as_str(),
+            "* \u{1b}[31mfoo\u{1b}[0m [a] @ 2022-01-01 00:00:00 UTC\n"
+        );
+    }
+
+    #[test]
+    fn test_keep_ansi_truncate_content() {
+        let formatter = make_formatter()
+            .with_max_columns(Some(60))
+            .with_keep_ansi(true);
+        assert_eq!(
+            formatter
+                .format_message(
+                    &make_message(
+                        "foo",
+                        "\u{1b}[31mLorem ipsum dolor sit amet, consectetur adipiscing elit",
+                        0
+                    ),
+                    Some(String::from(" appendix"))
+                )
+                .unwrap()
+                .as_str(),
+            "* \u{1b}[31mLorem ipsum dolo…\u{1b}[0m [foo] @ 2022-01-01 00:00:00 UTC appendix"
+        );
+    }
+
+    #[test]
+    fn test_truncate_ascii() {
+        let formatter = make_formatter()
+            .with_max_columns(Some(60))
+            .with_theme(Theme {
+                ascii: true,
+                ..Theme::default()
+            });
+        assert_eq!(
+            formatter
+                .format_message(
+                    &make_message(
+                        "foo",
+                        "Lorem ipsum dolor sit amet, consectetur adipiscing elit",
+                        0
+                    ),
+                    Some(String::from(" appendix"))
+                )
+                .unwrap()
+                .as_str(),
+            "* Lorem ipsum do... [foo] @ 2022-01-01 00:00:00 UTC appendix"
+        );
+    }
+
     #[test]
     fn test_truncate_mailbox() {
         let formatter = make_formatter().with_max_columns(Some(60));
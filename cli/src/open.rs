@@ -0,0 +1,75 @@
+use database::{Kind, Message};
+use linkify::{LinkFinder, LinkKind};
+
+// Return the URL that opening the message visits, which is the content of URL messages and the
+// first link in text and markdown messages
+pub fn find_url(message: &Message) -> Option<&str> {
+    match message.kind {
+        Kind::Url => Some(message.content.trim()),
+        Kind::Json => None,
+        Kind::Text | Kind::Markdown => {
+            let mut finder = LinkFinder::new();
+            finder.kinds(&[LinkKind::Url]);
+            finder
+                .links(&message.content)
+                .next()
+                .map(|link| link.as_str())
+        }
+    }
+}
+
+// If the message contains a URL, open it in a web browser
+pub fn open_message(message: &Message) {
+    if let Some(url) = find_url(message) {
+        // Silently ignore errors if the URL couldn't be opened
+        let _ = webbrowser::open(url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use database::State;
+
+    fn make_message(content: &str, kind: Kind) -> Message {
+        Message {
+            id: 1,
+            timestamp: NaiveDateTime::MIN,
+            mailbox: "mailbox".try_into().unwrap(),
+            content: content.to_owned(),
+            state: State::Unread,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            tags: vec![],
+            priority: 0,
+            kind,
+            related_ids: vec![],
+        }
+    }
+
+    #[test]
+    fn test_find_url() {
+        assert_eq!(
+            find_url(&make_message(
+                "Build failed: https://ci.example.com/1 and https://ci.example.com/2",
+                Kind::Text
+            )),
+            Some("https://ci.example.com/1")
+        );
+        assert_eq!(find_url(&make_message("Build failed", Kind::Text)), None);
+        assert_eq!(
+            find_url(&make_message("https://ci.example.com/1\n", Kind::Url)),
+            Some("https://ci.example.com/1")
+        );
+        assert_eq!(
+            find_url(&make_message(
+                r#"{"url": "https://ci.example.com/1"}"#,
+                Kind::Json
+            )),
+            None
+        );
+    }
+}
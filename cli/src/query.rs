@@ -0,0 +1,120 @@
+use crate::ansi::display_width;
+use database::QueryResult;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+// Format a value for a text table, leaving NULL cells empty
+fn format_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.replace('\n', " "),
+        value => value.to_string(),
+    }
+}
+
+// Format the rows of a query as a text table with aligned columns
+// Numbers are right-aligned and everything else is left-aligned.
+pub fn format_query_table(result: &QueryResult) -> String {
+    let rows = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(format_cell).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut widths = result
+        .columns
+        .iter()
+        .map(|column| display_width(column))
+        .collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = std::cmp::max(*width, display_width(cell));
+        }
+    }
+
+    let mut output = String::new();
+    let mut write_row = |cells: Vec<(&str, bool)>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|((cell, numeric), width)| {
+                let padding = " ".repeat(width.saturating_sub(display_width(cell)));
+                if *numeric {
+                    format!("{padding}{cell}")
+                } else {
+                    format!("{cell}{padding}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(output, "{}", line.trim_end());
+    };
+    write_row(
+        result
+            .columns
+            .iter()
+            .map(|column| (column.as_str(), false))
+            .collect(),
+    );
+    for (row, values) in rows.iter().zip(&result.rows) {
+        write_row(
+            row.iter()
+                .zip(values)
+                .map(|(cell, value)| (cell.as_str(), value.is_number()))
+                .collect(),
+        );
+    }
+    output
+}
+
+// Convert the rows of a query into JSON objects keyed by column name
+pub fn query_rows_to_json(result: &QueryResult) -> Vec<Map<String, Value>> {
+    result
+        .rows
+        .iter()
+        .map(|row| {
+            result
+                .columns
+                .iter()
+                .cloned()
+                .zip(row.iter().cloned())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn make_result() -> QueryResult {
+        QueryResult {
+            columns: vec![String::from("mailbox"), String::from("count")],
+            rows: vec![
+                vec![json!("ci/build"), json!(12)],
+                vec![json!("backups"), json!(3)],
+                vec![Value::Null, json!(1.5)],
+            ],
+        }
+    }
+
+    #[test]
+    fn test_format_query_table() {
+        assert_eq!(
+            format_query_table(&make_result()),
+            "mailbox   count\nci/build     12\nbackups       3\n            1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_query_rows_to_json() {
+        assert_eq!(
+            serde_json::to_value(query_rows_to_json(&make_result())).unwrap(),
+            json!([
+                { "mailbox": "ci/build", "count": 12 },
+                { "mailbox": "backups", "count": 3 },
+                { "mailbox": null, "count": 1.5 },
+            ])
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use database::{Bucket, BucketStats};
+use std::fmt::Write;
+
+// Format the start of a bucket as precisely as the bucket length requires
+fn format_bucket(stats: &BucketStats, bucket: Bucket) -> String {
+    let format = match bucket {
+        Bucket::Hour => "%Y-%m-%d %H:00",
+        Bucket::Day | Bucket::Week => "%Y-%m-%d",
+        Bucket::Month => "%Y-%m",
+    };
+    stats.bucket.format(format).to_string()
+}
+
+// Format message statistics as a text table with aligned columns
+pub fn format_stats_table(stats: &[BucketStats], bucket: Bucket) -> String {
+    let header = [
+        bucket.to_string(),
+        String::from("mailbox"),
+        String::from("added"),
+        String::from("read"),
+        String::from("archived"),
+    ];
+    let rows = stats
+        .iter()
+        .map(|stats| {
+            [
+                format_bucket(stats, bucket),
+                stats.mailbox.to_string(),
+                stats.added.to_string(),
+                stats.read.to_string(),
+                stats.archived.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = std::cmp::max(*width, cell.len());
+        }
+    }
+
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let [bucket, mailbox, added, read, archived] = row;
+        // Left-align the text columns and right-align the numeric columns
+        let _ = writeln!(
+            output,
+            "{bucket:<0$}  {mailbox:<1$}  {added:>2$}  {read:>3$}  {archived:>4$}",
+            widths[0], widths[1], widths[2], widths[3], widths[4],
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn make_stats(bucket: &str, mailbox: &str, counts: (usize, usize, usize)) -> BucketStats {
+        BucketStats {
+            bucket: NaiveDateTime::parse_from_str(bucket, "%Y-%m-%d %H:%M:%S").unwrap(),
+            mailbox: mailbox.try_into().unwrap(),
+            added: counts.0,
+            read: counts.1,
+            archived: counts.2,
+        }
+    }
+
+    #[test]
+    fn test_format_stats_table() {
+        let stats = vec![
+            make_stats("2024-01-02 00:00:00", "a", (12, 3, 9)),
+            make_stats("2024-01-01 00:00:00", "parent/child", (1, 0, 1)),
+        ];
+        assert_eq!(
+            format_stats_table(&stats, Bucket::Day),
+            "day         mailbox       added  read  archived
+2024-01-02  a                12     3         9
+2024-01-01  parent/child      1     0         1
+"
+        );
+    }
+
+    #[test]
+    fn test_format_stats_table_bucket_precision() {
+        let stats = vec![make_stats("2024-01-02 03:00:00", "a", (1, 0, 0))];
+        assert!(format_stats_table(&stats, Bucket::Hour).contains("2024-01-02 03:00  a"));
+        assert!(format_stats_table(&stats, Bucket::Month).contains("2024-01  a"));
+    }
+
+    #[test]
+    fn test_format_stats_table_empty() {
+        assert_eq!(
+            format_stats_table(&[], Bucket::Week),
+            "week  mailbox  added  read  archived\n"
+        );
+    }
+}
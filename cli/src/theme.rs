@@ -0,0 +1,248 @@
+use crate::ansi::display_width;
+use colored::Color;
+use database::State;
+use serde::{de::Error, Deserialize, Deserializer};
+
+// The set of colors used by printed messages and the TUI
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    #[default]
+    Default,
+    // Bright colors that stand out on dark and light backgrounds
+    HighContrast,
+    // The Okabe-Ito colors, which avoid pairs that are hard to tell apart with color blindness
+    Colorblind,
+}
+
+// The colors that a palette assigns to each part of the output
+pub struct Colors {
+    pub unread: Color,
+    pub mailbox: Color,
+    pub timestamp: Color,
+    // The enabled state filters in the TUI
+    pub active: Color,
+    // Borders, highlighted items, and previews in the TUI
+    pub accent: Color,
+    pub related: Color,
+    pub error: Color,
+    pub muted: Color,
+}
+
+impl Palette {
+    pub const fn colors(self) -> Colors {
+        match self {
+            Self::Default => Colors {
+                unread: Color::Red,
+                mailbox: Color::Green,
+                timestamp: Color::Yellow,
+                active: Color::Green,
+                accent: Color::BrightBlue,
+                related: Color::Cyan,
+                error: Color::Red,
+                muted: Color::BrightBlack,
+            },
+            Self::HighContrast => Colors {
+                unread: Color::BrightRed,
+                mailbox: Color::BrightGreen,
+                timestamp: Color::BrightYellow,
+                active: Color::BrightGreen,
+                accent: Color::BrightWhite,
+                related: Color::BrightCyan,
+                error: Color::BrightRed,
+                muted: Color::White,
+            },
+            Self::Colorblind => Colors {
+                // Orange
+                unread: Color::TrueColor {
+                    r: 230,
+                    g: 159,
+                    b: 0,
+                },
+                // Bluish green
+                mailbox: Color::TrueColor {
+                    r: 0,
+                    g: 158,
+                    b: 115,
+                },
+                // Yellow
+                timestamp: Color::TrueColor {
+                    r: 240,
+                    g: 228,
+                    b: 66,
+                },
+                // Bluish green
+                active: Color::TrueColor {
+                    r: 0,
+                    g: 158,
+                    b: 115,
+                },
+                // Sky blue
+                accent: Color::TrueColor {
+                    r: 86,
+                    g: 180,
+                    b: 233,
+                },
+                // Reddish purple
+                related: Color::TrueColor {
+                    r: 204,
+                    g: 121,
+                    b: 167,
+                },
+                // Vermillion
+                error: Color::TrueColor {
+                    r: 213,
+                    g: 94,
+                    b: 0,
+                },
+                muted: Color::BrightBlack,
+            },
+        }
+    }
+}
+
+// The symbols that mark the state of messages and the message under the cursor in the TUI
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Glyphs {
+    #[serde(deserialize_with = "deserialize_glyph")]
+    pub unread: String,
+    #[serde(deserialize_with = "deserialize_glyph")]
+    pub read: String,
+    #[serde(deserialize_with = "deserialize_glyph")]
+    pub archived: String,
+    #[serde(deserialize_with = "deserialize_glyph")]
+    pub cursor: String,
+}
+
+impl Default for Glyphs {
+    fn default() -> Self {
+        Self {
+            unread: String::from(default_state_glyph(State::Unread)),
+            read: String::from(default_state_glyph(State::Read)),
+            archived: String::from(default_state_glyph(State::Archived)),
+            cursor: String::from("•"),
+        }
+    }
+}
+
+const fn default_state_glyph(state: State) -> &'static str {
+    match state {
+        State::Unread => "*",
+        State::Read => " ",
+        State::Archived => "-",
+    }
+}
+
+impl Glyphs {
+    // Return the glyph that marks messages in a state
+    pub fn state(&self, state: State) -> &str {
+        match state {
+            State::Unread => &self.unread,
+            State::Read => &self.read,
+            State::Archived => &self.archived,
+        }
+    }
+}
+
+// Glyphs must be one column wide so that messages stay aligned
+fn deserialize_glyph<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let glyph = String::deserialize(deserializer)?;
+    if display_width(&glyph) != 1 {
+        return Err(D::Error::custom(format!(
+            "glyph \"{glyph}\" must be one column wide"
+        )));
+    }
+    Ok(glyph)
+}
+
+// The colors and glyphs of printed messages and the TUI
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub palette: Palette,
+    pub glyphs: Glyphs,
+    // Whether output is limited to ASCII characters for terminals and logs that can't handle others
+    pub ascii: bool,
+    // Whether colors are used at all, which comes from the command line and environment instead of
+    // the config file
+    #[serde(skip)]
+    pub color: bool,
+}
+
+impl Theme {
+    // Configure whether colors are used
+    pub fn with_color(self, color: bool) -> Self {
+        Self { color, ..self }
+    }
+
+    // Choose between a Unicode symbol and its ASCII equivalent
+    pub const fn symbol(&self, unicode: &'static str, ascii: &'static str) -> &'static str {
+        if self.ascii {
+            ascii
+        } else {
+            unicode
+        }
+    }
+
+    // Return the glyph that marks messages in a state, which falls back to the default if it isn't
+    // ASCII in ASCII mode
+    pub fn state_glyph(&self, state: State) -> &str {
+        let glyph = self.glyphs.state(state);
+        if self.ascii && !glyph.is_ascii() {
+            default_state_glyph(state)
+        } else {
+            glyph
+        }
+    }
+
+    // Return the glyph that marks the message under the cursor in the TUI
+    pub fn cursor_glyph(&self) -> &str {
+        if self.ascii && !self.glyphs.cursor.is_ascii() {
+            ">"
+        } else {
+            &self.glyphs.cursor
+        }
+    }
+
+    // Return the colors of the palette, or None if colors are disabled
+    pub fn colors(&self) -> Option<Colors> {
+        self.color.then(|| self.palette.colors())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let theme: Theme =
+            toml::from_str("palette = 'colorblind'\n[glyphs]\nunread = '!'\ncursor = '>'\n")
+                .unwrap();
+        assert_eq!(theme.palette, Palette::Colorblind);
+        assert_eq!(
+            theme.glyphs,
+            Glyphs {
+                unread: String::from("!"),
+                cursor: String::from(">"),
+                ..Glyphs::default()
+            }
+        );
+        assert!(!theme.color);
+        assert!(theme.colors().is_none());
+        assert_eq!(theme.state_glyph(State::Unread), "!");
+        assert_eq!(theme.cursor_glyph(), ">");
+        assert!(theme.with_color(true).colors().is_some());
+
+        let theme: Theme = toml::from_str("ascii = true\n[glyphs]\nunread = '★'\n").unwrap();
+        assert_eq!(theme.state_glyph(State::Unread), "*");
+        assert_eq!(theme.state_glyph(State::Archived), "-");
+        assert_eq!(theme.cursor_glyph(), ">");
+        assert_eq!(theme.symbol("…", "..."), "...");
+
+        assert!(toml::from_str::<Theme>("palette = 'neon'\n").is_err());
+        assert!(toml::from_str::<Theme>("[glyphs]\nunread = '**'\n").is_err());
+        assert!(toml::from_str::<Theme>("[glyphs]\nread = ''\n").is_err());
+    }
+}
@@ -1,13 +1,17 @@
+use crate::ansi::{display_width, grapheme_width, tokenize, Token};
 use colored::{ColoredString, Colorize};
 use std::fmt::{self, Display, Formatter};
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-type ApplyColor = fn(&str) -> ColoredString;
+pub type ApplyColor = Box<dyn Fn(&str) -> ColoredString>;
 
 fn no_color(str: &str) -> ColoredString {
     str.normal()
 }
 
+// The marker added to the end of truncated strings
+pub const ELLIPSIS: &str = "…";
+pub const ASCII_ELLIPSIS: &str = "...";
+
 // Represents a line of characters with a max length that can be built up over time
 pub struct TruncatedLine {
     // The number of available columns in the line
@@ -15,6 +19,9 @@ pub struct TruncatedLine {
 
     // The sections that make up the truncated line
     sections: Vec<(String, ApplyColor)>,
+
+    // The marker added to truncated sections
+    ellipsis: &'static str,
 }
 
 impl TruncatedLine {
@@ -23,15 +30,21 @@ impl TruncatedLine {
         Self {
             available_columns: max_columns,
             sections: Vec::default(),
+            ellipsis: ELLIPSIS,
         }
     }
 
+    // Configure the marker added to truncated sections
+    pub fn with_ellipsis(self, ellipsis: &'static str) -> Self {
+        Self { ellipsis, ..self }
+    }
+
     // Add more characters to the line, enforcing the maximum line length
     pub fn append(&mut self, new_chars: impl Into<String>, colorize: Option<ApplyColor>) {
         let new_chars: String = new_chars.into();
         if !new_chars.is_empty() {
             self.sections
-                .push((new_chars, colorize.unwrap_or(no_color)));
+                .push((new_chars, colorize.unwrap_or_else(|| Box::new(no_color))));
         }
     }
 
@@ -44,12 +57,13 @@ impl TruncatedLine {
             // If this section exactly fits in the remaining columns, but there
             // are still other sections remaining, then force truncation
             let force_truncate =
-                remaining_columns == new_chars.width() && index != self.sections.len() - 1;
+                remaining_columns == display_width(new_chars) && index != self.sections.len() - 1;
             // Force truncation if necessary by adding an extra character that
             // will be truncated off
             let (truncated, width) = truncate_string(
                 &format!("{new_chars}{}", if force_truncate { " " } else { "" }),
                 remaining_columns,
+                self.ellipsis,
             );
             remaining_columns -= width;
             line = format!(
@@ -82,48 +96,47 @@ impl Display for TruncatedLine {
 }
 
 // Truncate the input string to fit within a given width, taking
-// non-single-width Unicode characters into account
+// non-single-width Unicode characters and ANSI escape sequences into account
+// Grapheme clusters like emoji with modifiers and letters with combining
+// accents are never split
 // Returns the truncated string and its width
-// Uses the algorithm in https://github.com/Aetf/unicode-truncate with added
-// support for adding ellipses when the string is truncated
+// An ellipsis is added when the string is truncated. Escape sequences are
+// never split, but escape sequences after the truncation point are dropped.
+// The ellipsis must be made of single-width characters and is itself shortened if the width is
+// too narrow for all of it.
 #[allow(clippy::module_name_repetitions)]
-pub fn truncate_string(input: &str, width: usize) -> (String, usize) {
-    let (add_ellipsis, byte_index, new_width) = input
-        .char_indices()
-        // Map to byte index and the width of the substring starting at the index
-        .map(|(byte_index, char)| (true, byte_index, char.width().unwrap_or(0)))
-        // Append a final element representing the position past the last char
-        .chain(std::iter::once((false, input.len(), 0)))
-        // Calculate the total substring width for each element
-        .scan(0, |substring_width, (last, byte_index, char_width)| {
-            let current_width = *substring_width;
-            *substring_width += char_width;
-            Some((last, byte_index, current_width))
-        })
-        // Ignore substrings that exceed the desired width
-        .take_while(|&(add_ellipsis, _, substring_width)| {
-            if add_ellipsis {
-                // Reserve an extra character for the ellipse
-                substring_width < width
-            } else {
-                substring_width <= width
+pub fn truncate_string(input: &str, width: usize, ellipsis: &str) -> (String, usize) {
+    let input_width = display_width(input);
+    if input_width <= width {
+        return (input.to_owned(), input_width);
+    }
+    let ellipsis_width = ellipsis.chars().count();
+    if width <= ellipsis_width {
+        return (ellipsis.chars().take(width).collect(), width);
+    }
+
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    // Escape sequences are only kept once a grapheme after them fits
+    let mut pending_escapes = String::new();
+    for token in tokenize(input) {
+        match token {
+            Token::Escape(escape) => pending_escapes.push_str(escape),
+            Token::Grapheme(grapheme) => {
+                let grapheme_width = grapheme_width(grapheme);
+                // Reserve columns for the ellipsis
+                if truncated_width + grapheme_width + ellipsis_width > width {
+                    break;
+                }
+                truncated.push_str(&pending_escapes);
+                pending_escapes.clear();
+                truncated.push_str(grapheme);
+                truncated_width += grapheme_width;
             }
-        })
-        // Take the longest possible substring
-        .last()
-        .unwrap_or((false, 0, 0));
-    (
-        format!(
-            "{}{}",
-            input.get(..byte_index).unwrap(),
-            if add_ellipsis { "…" } else { "" }
-        ),
-        if add_ellipsis {
-            new_width + 1
-        } else {
-            new_width
-        },
-    )
+        }
+    }
+    truncated.push_str(ellipsis);
+    (truncated, truncated_width + ellipsis_width)
 }
 
 #[cfg(test)]
@@ -195,7 +208,7 @@ mod tests {
     fn test_colored() {
         let mut line = TruncatedLine::new(11);
         line.append("hello ", None);
-        line.append("world", Some(|str| str.red()));
+        line.append("world", Some(Box::new(|str: &str| str.red())));
         assert_eq!(line.to_string(), "hello \u{1b}[31mworld\u{1b}[0m");
     }
 
@@ -203,37 +216,106 @@ mod tests {
     fn test_colored_empty_string() {
         let mut line = TruncatedLine::new(5);
         line.append("hello ", None);
-        line.append("world", Some(|str| str.red()));
+        line.append("world", Some(Box::new(|str: &str| str.red())));
         assert_eq!(line.to_string(), "hell…");
     }
 
     #[test]
     fn test_truncate_string() {
         let message = "Hello, world!";
-        assert_eq!(truncate_string(message, 0), (String::new(), 0));
-        assert_eq!(truncate_string(message, 6), (String::from("Hello…"), 6));
+        assert_eq!(truncate_string(message, 0, ELLIPSIS), (String::new(), 0));
+        assert_eq!(
+            truncate_string(message, 6, ELLIPSIS),
+            (String::from("Hello…"), 6)
+        );
         assert_eq!(
-            truncate_string(message, 13),
+            truncate_string(message, 13, ELLIPSIS),
             (String::from("Hello, world!"), 13)
         );
         assert_eq!(
-            truncate_string(message, 20),
+            truncate_string(message, 20, ELLIPSIS),
             (String::from("Hello, world!"), 13)
         );
     }
 
+    #[test]
+    fn test_truncate_string_ansi() {
+        let message = "\u{1b}[31mHello\u{1b}[0m, world!";
+        assert_eq!(
+            truncate_string(message, 6, ELLIPSIS),
+            (String::from("\u{1b}[31mHello…"), 6)
+        );
+        assert_eq!(
+            truncate_string(message, 13, ELLIPSIS),
+            (String::from(message), 13)
+        );
+    }
+
+    #[test]
+    fn test_truncate_string_graphemes() {
+        // Letters with combining accents
+        let message = "e\u{301}e\u{301}e\u{301}";
+        assert_eq!(
+            truncate_string(message, 2, ELLIPSIS),
+            (String::from("e\u{301}…"), 2)
+        );
+        assert_eq!(
+            truncate_string(message, 3, ELLIPSIS),
+            (String::from(message), 3)
+        );
+
+        // Emoji with skin tone modifiers and zero-width joiners
+        let message = "👍\u{1f3fd}👩\u{200d}💻ok";
+        assert_eq!(
+            truncate_string(message, 4, ELLIPSIS),
+            (String::from("👍\u{1f3fd}…"), 3)
+        );
+        assert_eq!(
+            truncate_string(message, 6, ELLIPSIS),
+            (String::from(message), 6)
+        );
+    }
+
+    #[test]
+    fn test_truncate_string_ascii() {
+        let message = "Hello, world!";
+        assert_eq!(
+            truncate_string(message, 8, ASCII_ELLIPSIS),
+            (String::from("Hello..."), 8)
+        );
+        assert_eq!(
+            truncate_string(message, 2, ASCII_ELLIPSIS),
+            (String::from(".."), 2)
+        );
+        assert_eq!(
+            truncate_string(message, 0, ASCII_ELLIPSIS),
+            (String::new(), 0)
+        );
+
+        let mut line = TruncatedLine::new(9).with_ellipsis(ASCII_ELLIPSIS);
+        line.append("hello ", None);
+        line.append("world", None);
+        assert_eq!(line.to_string(), "hello ...");
+    }
+
     #[test]
     fn test_truncate_string_unicode() {
         let message = "⭐a⭐b⭐c⭐";
-        assert_eq!(truncate_string(message, 0), (String::new(), 0));
-        assert_eq!(truncate_string(message, 5), (String::from("⭐a…"), 4));
-        assert_eq!(truncate_string(message, 6), (String::from("⭐a⭐…"), 6));
+        assert_eq!(truncate_string(message, 0, ELLIPSIS), (String::new(), 0));
+        assert_eq!(
+            truncate_string(message, 5, ELLIPSIS),
+            (String::from("⭐a…"), 4)
+        );
+        assert_eq!(
+            truncate_string(message, 6, ELLIPSIS),
+            (String::from("⭐a⭐…"), 6)
+        );
         assert_eq!(
-            truncate_string(message, 11),
+            truncate_string(message, 11, ELLIPSIS),
             (String::from("⭐a⭐b⭐c⭐"), 11)
         );
         assert_eq!(
-            truncate_string(message, 20),
+            truncate_string(message, 20, ELLIPSIS),
             (String::from("⭐a⭐b⭐c⭐"), 11)
         );
     }
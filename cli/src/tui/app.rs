@@ -1,14 +1,37 @@
+use super::command_line::{parse_command, CommandLine};
+use super::compose_form::ComposeForm;
+use super::mailbox_finder::MailboxFinder;
 use super::multiselect_list::MultiselectList;
 use super::navigable_list::{Keyed, NavigableList};
+use super::pinned_mailboxes::PinnedMailboxes;
 use super::tree_list::{Depth, TreeList};
 use super::worker::{spawn, Receiver, Request, Response, Sender};
+use crate::theme::Theme;
 use anyhow::Result;
-use database::{Backend, Database, Filter, MailboxInfo, Message, State};
+use chrono::Utc;
+use database::{Backend, Database, Filter, Heartbeat, Id, MailboxInfo, Message, Sort, State};
+use serde::Deserialize;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hasher;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+// The number of messages loaded at a time
+pub const PAGE_SIZE: usize = 500;
+
+// Load the next page once the cursor is this close to the last loaded message
+const LOAD_MORE_THRESHOLD: usize = 100;
+
+// How long the filters must stop changing before the messages are reloaded, so that a burst of key
+// presses only queries the database once
+const RELOAD_DELAY: Duration = Duration::from_millis(100);
+
+// How long until messages that a reminder was set for return to unread
+const REMIND_DELAY: Duration = Duration::from_hours(24);
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Pane {
     Mailboxes,
     Messages,
@@ -52,6 +75,37 @@ pub struct App {
     pub(crate) messages: MultiselectList<Message>,
     pub(crate) active_pane: Pane,
     pub(crate) active_states: HashSet<State>,
+    // The order of the messages list, which is ordered by id, newest first if sort is None
+    pub(crate) sort: Option<Sort>,
+    pub(crate) reverse: bool,
+    // Whether there may be more messages than the ones that have been loaded
+    pub(crate) has_more_messages: bool,
+    // Whether the next page of messages is currently being loaded
+    loading_more_messages: bool,
+    // When the messages will be reloaded with the current filters, which is pushed back every
+    // time the filters change again
+    pub(crate) reload_deadline: Option<Instant>,
+    // The mailboxes that are listed before their siblings
+    pub(crate) pinned_mailboxes: PinnedMailboxes,
+    // The mailboxes that are expected to receive messages regularly
+    pub(crate) heartbeats: HashMap<database::Mailbox, Heartbeat>,
+    // Short names that the mailbox finder also matches mailboxes by
+    aliases: HashMap<String, database::Mailbox>,
+    // The form for writing a new message, which receives all key presses while it is open
+    pub(crate) compose_form: Option<ComposeForm>,
+    // The picker for jumping to a mailbox, which receives all key presses while it is open
+    pub(crate) mailbox_finder: Option<MailboxFinder>,
+    // The command for changing the states of messages that match a filter, which receives all key
+    // presses while it is open
+    pub(crate) command_line: Option<CommandLine>,
+    // The most recent error reported by the worker
+    pub(crate) error: Option<anyhow::Error>,
+    // A warning that the lists were reloaded because they drifted from the database
+    pub(crate) warning: Option<String>,
+    // The colors and glyphs used to render the app
+    pub(crate) theme: Theme,
+    // Requests that failed and can be retried
+    failed_requests: Vec<Request>,
     worker_tx: Sender,
     worker_rx: Receiver,
 }
@@ -61,20 +115,42 @@ impl App {
         db: Database<B>,
         initial_mailbox: Option<database::Mailbox>,
         initial_states: Vec<State>,
+        initial_pane: Pane,
+        initial_sort: Option<Sort>,
+        pinned_mailboxes: PinnedMailboxes,
+        aliases: HashMap<String, database::Mailbox>,
     ) -> Result<Self> {
         let db = Arc::new(db);
         let (worker_tx, worker_rx) = spawn(Arc::clone(&db));
         let mut app = Self {
-            active_pane: Pane::Messages,
+            active_pane: initial_pane,
             mailboxes: TreeList::new(),
             messages: MultiselectList::new(),
             active_states: initial_states.into_iter().collect(),
+            sort: initial_sort,
+            reverse: false,
+            has_more_messages: false,
+            loading_more_messages: false,
+            reload_deadline: None,
+            pinned_mailboxes,
+            heartbeats: HashMap::new(),
+            aliases,
+            compose_form: None,
+            mailbox_finder: None,
+            command_line: None,
+            error: None,
+            warning: None,
+            theme: Theme::default(),
+            failed_requests: vec![],
             worker_tx,
             worker_rx,
         };
         app.mailboxes.replace_items(Self::build_mailbox_list(
-            db.load_mailboxes(app.get_display_filter()).await?,
+            db.load_mailboxes(app.get_display_filter().with_hierarchy_counts(true))
+                .await?,
+            &app.pinned_mailboxes,
         ));
+        app.set_heartbeats(db.load_heartbeats().await?);
         if let Some(initial_mailbox) = initial_mailbox {
             app.mailboxes.set_cursor(
                 app.mailboxes
@@ -83,12 +159,24 @@ impl App {
                     .position(|mailbox| mailbox.mailbox == initial_mailbox),
             );
         }
-        // Load the messages with the initial mailbox filter applied
-        app.messages
-            .replace_items(db.load_messages(app.get_display_filter()).await?);
+        // Load the first page of messages with the initial mailbox filter applied
+        let filter = app.get_load_filter();
+        app.set_loaded_messages(db.load_messages(filter).await?);
         Ok(app)
     }
 
+    // Configure the colors and glyphs used to render the app
+    pub fn with_theme(self, theme: Theme) -> Self {
+        Self { theme, ..self }
+    }
+
+    // Configure whether selected messages stay selected while the filters hide them, so that
+    // messages from several mailboxes can be acted on at once
+    pub fn with_persistent_selection(mut self, persistent_selection: bool) -> Self {
+        self.messages.set_persistent_selection(persistent_selection);
+        self
+    }
+
     // Change the active pane
     pub fn activate_pane(&mut self, pane: Pane) {
         self.active_pane = pane;
@@ -102,33 +190,91 @@ impl App {
             self.active_states.insert(state);
         }
         self.update_mailboxes()?;
-        self.update_messages()?;
+        self.update_messages();
         Ok(())
     }
 
-    // Generate the mailboxes list
-    pub(crate) fn build_mailbox_list(mailbox_sizes: Vec<MailboxInfo>) -> Vec<Mailbox> {
-        let mut mailboxes = HashMap::<database::Mailbox, Mailbox>::new();
-        for MailboxInfo {
-            name: mailbox,
-            message_count: count,
-        } in mailbox_sizes
-        {
-            for (index, full_name) in mailbox.iter_ancestors().enumerate() {
-                // Children mailboxes contribute to the size of their parents
-                mailboxes
-                    .entry(full_name.clone())
-                    .or_insert(Mailbox {
-                        mailbox: full_name,
-                        depth: index,
-                        message_count: 0,
-                    })
-                    .message_count += count;
-            }
+    // Switch to the next order of the messages list
+    pub fn cycle_sort(&mut self) {
+        self.sort = match self.sort {
+            None => Some(Sort::Mailbox),
+            Some(Sort::Mailbox) => Some(Sort::State),
+            Some(Sort::State) => Some(Sort::LastViewed),
+            Some(Sort::LastViewed | Sort::Timestamp) => None,
+        };
+        self.update_messages();
+    }
+
+    // Toggle between sorting unread messages first and the default order
+    pub fn toggle_unread_first(&mut self) {
+        self.sort = if self.sort == Some(Sort::State) {
+            None
+        } else {
+            Some(Sort::State)
+        };
+        self.update_messages();
+    }
+
+    // Toggle whether the order of the messages list is reversed
+    pub fn toggle_reverse(&mut self) {
+        self.reverse = !self.reverse;
+        self.update_messages();
+    }
+
+    // Generate the mailboxes list from mailboxes loaded with hierarchy counts, which already
+    // include every ancestor and count the messages in their descendants
+    pub(crate) fn build_mailbox_list(
+        mailbox_sizes: Vec<MailboxInfo>,
+        pinned_mailboxes: &PinnedMailboxes,
+    ) -> Vec<Mailbox> {
+        let mut mailboxes = mailbox_sizes
+            .into_iter()
+            .map(|mailbox| Mailbox {
+                depth: mailbox.name.as_ref().matches('/').count(),
+                mailbox: mailbox.name,
+                message_count: mailbox.message_count,
+            })
+            .collect::<Vec<_>>();
+        mailboxes.sort_by_cached_key(|mailbox| pinned_mailboxes.get_sort_key(&mailbox.mailbox));
+        mailboxes
+    }
+
+    // Pin or unpin the mailbox under the cursor
+    pub fn toggle_pinned_mailbox(&mut self) {
+        if let Some(mailbox) = self.mailboxes.get_cursor_item() {
+            let res = self.pinned_mailboxes.toggle(&mailbox.mailbox);
+            self.update_pinned_mailboxes(res);
         }
-        let mut mailboxes = mailboxes.into_values().collect::<Vec<_>>();
-        mailboxes.sort_by(|mailbox1, mailbox2| mailbox1.mailbox.cmp(&mailbox2.mailbox));
+    }
+
+    // Move the pinned mailbox under the cursor before or after the previous or next pinned sibling
+    pub fn move_pinned_mailbox(&mut self, up: bool) {
+        if let Some(mailbox) = self.mailboxes.get_cursor_item() {
+            let res = self.pinned_mailboxes.move_pin(&mailbox.mailbox, up);
+            self.update_pinned_mailboxes(res);
+        }
+    }
+
+    // Reorder the mailboxes list after the pinned mailboxes changed
+    // Failing to save the pinned mailboxes doesn't stop them from being used in this session, so
+    // the error is only shown in the status bar.
+    fn update_pinned_mailboxes(&mut self, res: Result<()>) {
+        if let Err(err) = res {
+            self.error = Some(err);
+        }
+        let mut mailboxes = self
+            .mailboxes
+            .get_items()
+            .iter()
+            .map(|mailbox| Mailbox {
+                mailbox: mailbox.mailbox.clone(),
+                depth: mailbox.depth,
+                message_count: mailbox.message_count,
+            })
+            .collect::<Vec<_>>();
         mailboxes
+            .sort_by_cached_key(|mailbox| self.pinned_mailboxes.get_sort_key(&mailbox.mailbox));
+        self.mailboxes.replace_items(mailboxes);
     }
 
     // Update the messages list based on the mailbox and other filters
@@ -144,33 +290,98 @@ impl App {
         );
     }
 
-    // Update the mailboxes list
+    // Update the mailboxes list and the heartbeats shown next to them
     pub fn update_mailboxes(&self) -> Result<()> {
         self.worker_tx.send(Request::LoadMailboxes(
-            Filter::new().with_states(self.get_active_states()),
+            Filter::new()
+                .with_states(self.get_active_states())
+                .with_hierarchy_counts(true),
         ))?;
+        self.worker_tx.send(Request::LoadHeartbeats)?;
         Ok(())
     }
 
+    // Replace the heartbeats with a freshly loaded set of heartbeats
+    fn set_heartbeats(&mut self, heartbeats: Vec<Heartbeat>) {
+        self.heartbeats = heartbeats
+            .into_iter()
+            .map(|heartbeat| (heartbeat.mailbox.clone(), heartbeat))
+            .collect();
+    }
+
     // Update the messages list based on the mailbox and other filters
-    pub fn update_messages(&self) -> Result<()> {
-        let filter = self.get_display_filter();
-        self.worker_tx.send(Request::LoadMessages(filter))?;
+    // The loaded messages are filtered immediately, and the messages are reloaded once the filters
+    // stop changing for RELOAD_DELAY.
+    pub fn update_messages(&mut self) {
+        self.filter_messages();
+        self.reload_deadline = Some(Instant::now() + RELOAD_DELAY);
+    }
+
+    // Reload the messages with the current filters if they have stopped changing
+    pub fn reload_settled_messages(&mut self) -> Result<()> {
+        if self
+            .reload_deadline
+            .is_none_or(|deadline| Instant::now() < deadline)
+        {
+            return Ok(());
+        }
+        self.reload_deadline = None;
+        self.worker_tx
+            .send(Request::LoadMessages(self.get_load_filter()))?;
+        Ok(())
+    }
+
+    // Load the next page of messages if the cursor is approaching the last loaded message
+    pub fn load_more_messages(&mut self) -> Result<()> {
+        // A pending reload will replace the loaded messages anyway
+        if !self.has_more_messages || self.loading_more_messages || self.reload_deadline.is_some() {
+            return Ok(());
+        }
+        let loaded = self.messages.get_items().len();
+        let cursor = self.messages.get_cursor().unwrap_or_default();
+        if cursor + LOAD_MORE_THRESHOLD < loaded {
+            return Ok(());
+        }
+        self.loading_more_messages = true;
+        self.worker_tx.send(Request::LoadMoreMessages(
+            self.get_display_filter()
+                .with_offset(loaded)
+                .with_limit(PAGE_SIZE),
+        ))?;
         Ok(())
     }
 
+    // Replace the messages list with a freshly loaded set of messages
+    fn set_loaded_messages(&mut self, messages: Vec<Message>) {
+        // Load limits are always whole pages, so a partial page means that every message was loaded
+        self.has_more_messages = !messages.is_empty() && messages.len().is_multiple_of(PAGE_SIZE);
+        self.loading_more_messages = false;
+        self.messages.replace_items(messages);
+    }
+
     // Handle any pending worker responses without blocking
     pub fn handle_worker_responses(&mut self) -> Result<()> {
         while let Ok(res) = self.worker_rx.try_recv() {
             match res {
-                Response::LoadMessages(messages) => self.messages.replace_items(messages),
+                Response::LoadMessages(messages) => self.set_loaded_messages(messages),
+                Response::LoadMoreMessages(messages) => {
+                    self.has_more_messages = messages.len() >= PAGE_SIZE;
+                    self.loading_more_messages = false;
+                    self.messages.append_items(messages);
+                }
                 Response::LoadMailboxes(mailboxes) => {
                     let old_display_filter = self.get_display_filter();
                     self.mailboxes
-                        .replace_items(Self::build_mailbox_list(mailboxes));
+                        .replace_items(Self::build_mailbox_list(mailboxes, &self.pinned_mailboxes));
                     if old_display_filter != self.get_display_filter() {
                         // If changing the mailbox list changed the active mailbox, refresh the message list
-                        self.update_messages()?;
+                        self.update_messages();
+                    }
+                }
+                Response::LoadHeartbeats(heartbeats) => self.set_heartbeats(heartbeats),
+                Response::CountMessages(count) => {
+                    if let Some(command_line) = self.command_line.as_mut() {
+                        command_line.match_count = Some(count);
                     }
                 }
                 Response::Refresh => {
@@ -178,9 +389,86 @@ impl App {
                     // refresh the mailbox and message lists. We have to wait for the mutation to complete first to
                     // avoid loading the unchanged messages.
                     self.update_mailboxes()?;
-                    self.update_messages()?;
+                    self.update_messages();
                 }
-            };
+                Response::Conflict(count) => {
+                    // Another client changed some of the messages first, so reload the lists to
+                    // replace the optimistic updates with the actual state of the database
+                    self.warning = Some(format!(
+                        "{count} {} changed by another client, so the messages were reloaded",
+                        if count == 1 {
+                            "message was"
+                        } else {
+                            "messages were"
+                        }
+                    ));
+                    self.update_mailboxes()?;
+                    self.update_messages();
+                }
+                Response::Error(error, request) => {
+                    match *request {
+                        Request::LoadMoreMessages(_) => self.loading_more_messages = false,
+                        // Reload the lists to revert the optimistic updates of the failed mutation
+                        Request::ChangeMessageStates { .. } | Request::DeleteMessages { .. } => {
+                            self.update_mailboxes()?;
+                            self.update_messages();
+                        }
+                        _ => {}
+                    }
+                    self.error = Some(error);
+                    self.failed_requests.push(*request);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Clear the error and send the failed requests again
+    pub fn retry_failed_requests(&mut self) -> Result<()> {
+        self.error = None;
+        let mut reload = false;
+        for request in std::mem::take(&mut self.failed_requests) {
+            match request {
+                // The filters may have changed since the load failed, so reload with the current filters
+                Request::LoadMessages(_)
+                | Request::LoadMoreMessages(_)
+                | Request::LoadMailboxes(_)
+                | Request::LoadHeartbeats => reload = true,
+                // The command may have changed since the count failed, so count the current command
+                Request::CountMessages(_) => self.preview_command()?,
+                // Refresh after retrying mutations because the optimistic updates were reverted when
+                // they failed
+                Request::ChangeMessageStates {
+                    filter, new_state, ..
+                } => self.worker_tx.send(Request::ChangeMessageStates {
+                    filter,
+                    new_state,
+                    refresh: true,
+                    optimistic: vec![],
+                })?,
+                Request::DeleteMessages { filter, .. } => {
+                    self.worker_tx.send(Request::DeleteMessages {
+                        filter,
+                        refresh: true,
+                        optimistic: vec![],
+                    })?;
+                }
+                Request::AddMessage(message) => {
+                    self.worker_tx.send(Request::AddMessage(message))?;
+                }
+                Request::OpenAttachments(id) => {
+                    self.worker_tx.send(Request::OpenAttachments(id))?;
+                }
+                Request::RecordView(id) => self.worker_tx.send(Request::RecordView(id))?,
+                Request::RemindMessages { ids, delay } => {
+                    self.worker_tx
+                        .send(Request::RemindMessages { ids, delay })?;
+                }
+            }
+        }
+        if reload {
+            self.update_mailboxes()?;
+            self.update_messages();
         }
         Ok(())
     }
@@ -199,16 +487,36 @@ impl App {
                     .map(|mailbox| mailbox.mailbox.clone()),
             )
             .with_states(self.get_active_states())
+            .with_sort_option(self.sort)
+            .with_reverse(self.reverse)
     }
 
-    // // Get the filter representing which messages are selected and should be acted upon
-    fn get_action_filter(&self) -> Filter {
+    // Get the number of messages to load, which includes every page that has been loaded so that
+    // reloading doesn't shrink the list out from under the cursor
+    fn get_load_limit(&self) -> usize {
+        self.messages.get_items().len().div_ceil(PAGE_SIZE).max(1) * PAGE_SIZE
+    }
+
+    // Get the filter representing which messages should be loaded
+    fn get_load_filter(&self) -> Filter {
+        self.get_display_filter().with_limit(self.get_load_limit())
+    }
+
+    // Get the ids of the messages that are selected and should be acted upon, including selected
+    // messages that the filters hide
+    fn get_action_ids(&self) -> Vec<Id> {
         let selected_items = self
             .messages
             .get_selected_items()
             .map(|message| message.id)
+            .chain(
+                self.messages
+                    .get_hidden_selected_keys()
+                    .into_iter()
+                    .filter_map(|key| Id::try_from(key).ok()),
+            )
             .collect::<Vec<_>>();
-        let active_ids = if selected_items.is_empty() {
+        if selected_items.is_empty() {
             // If no items are selected, then act on the active item
             self.messages
                 .get_cursor_item()
@@ -217,14 +525,23 @@ impl App {
                 .collect()
         } else {
             selected_items
-        };
-        Filter::new().with_ids(active_ids)
+        }
+    }
+
+    // // Get the filter representing which messages are selected and should be acted upon
+    fn get_action_filter(&self) -> Filter {
+        Filter::new().with_ids(self.get_action_ids())
     }
 
     // Change the state of all selected messages
     pub fn set_selected_message_states(&mut self, new_state: State) -> Result<()> {
         let action_filter = self.get_action_filter();
-        self.set_message_states(action_filter, new_state)
+        // The mailbox counts don't include the optimistic changes to hidden messages
+        let changes_hidden = !self.messages.get_hidden_selected_keys().is_empty();
+        self.set_message_states(action_filter, new_state, changes_hidden)?;
+        // The hidden messages were acted on, so they shouldn't be acted on again
+        self.messages.deselect_hidden();
+        Ok(())
     }
 
     // Change the state of all displayed messages in a message regardless of which messages are selected
@@ -234,15 +551,17 @@ impl App {
         new_state: State,
     ) -> Result<()> {
         let action_filter = self.get_display_filter().with_mailbox(mailbox);
-        self.set_message_states(action_filter, new_state)
+        self.set_message_states(action_filter, new_state, false)
     }
 
     // Delete all selected messages
     pub fn delete_selected_messages(&mut self) -> Result<()> {
         let filter = self.get_action_filter();
+        let deletes_hidden = !self.messages.get_hidden_selected_keys().is_empty();
+        self.warning = None;
 
         // Optimistically update the message list
-        let (deleted, remaining) = self
+        let (deleted, remaining): (Vec<_>, _) = self
             .messages
             .get_items()
             .iter()
@@ -252,27 +571,156 @@ impl App {
 
         // Optimistically update the mailbox list
         let old_display_filter = self.get_display_filter();
+        let optimistic = deleted.iter().map(|message| message.id).collect();
         self.remove_messages_from_mailboxes(deleted);
+        self.messages.deselect_hidden();
 
         // Apply the mutation
         self.worker_tx.send(Request::DeleteMessages {
             filter,
-            // If changing the mailbox list changed the active mailbox, the message list needs to be refreshed
+            // If changing the mailbox list changed the active mailbox or hidden messages were
+            // deleted, the lists need to be refreshed
             // The actual refreshing is done when handle_worker_response receives the refresh response
-            response: if old_display_filter == self.get_display_filter() {
-                None
-            } else {
-                Some(Response::Refresh)
-            },
+            refresh: deletes_hidden || old_display_filter != self.get_display_filter(),
+            optimistic,
         })?;
 
         Ok(())
     }
 
-    // Change the state of all messages matching the filter
-    fn set_message_states(&mut self, action_filter: Filter, new_state: State) -> Result<()> {
+    // Open the picker for jumping to one of the listed mailboxes
+    pub fn open_mailbox_finder(&mut self) {
+        self.mailbox_finder = Some(MailboxFinder::new(
+            self.mailboxes
+                .get_items()
+                .iter()
+                .map(|mailbox| mailbox.mailbox.clone())
+                .collect(),
+            &self.aliases,
+        ));
+    }
+
+    // Open the form for writing a new message in the mailbox under the cursor
+    pub fn open_compose_form(&mut self) {
+        self.compose_form = Some(ComposeForm::new(
+            self.mailboxes
+                .get_cursor_item()
+                .map(|mailbox| &mailbox.mailbox),
+        ));
+    }
+
+    // Close the form for writing a new message without adding the message
+    pub fn close_compose_form(&mut self) {
+        self.compose_form = None;
+    }
+
+    // Add the message in the compose form and close the form, or show why the message is invalid
+    pub fn submit_compose_form(&mut self) -> Result<()> {
+        let Some(form) = self.compose_form.as_mut() else {
+            return Ok(());
+        };
+        match form.to_message() {
+            Ok(message) => {
+                self.compose_form = None;
+                // The worker sends a refresh response once the message has been added
+                self.worker_tx.send(Request::AddMessage(message))?;
+            }
+            Err(err) => form.error = Some(format!("{err:#}")),
+        }
+        Ok(())
+    }
+
+    // Open the command line for changing the states of messages that match a filter
+    pub fn open_command_line(&mut self) {
+        self.command_line = Some(CommandLine::new());
+    }
+
+    // Count the messages that the command would change so that the count can be checked before
+    // running it
+    pub fn preview_command(&mut self) -> Result<()> {
+        let Some(command_line) = self.command_line.as_mut() else {
+            return Ok(());
+        };
+        command_line.match_count = None;
+        command_line.error = None;
+        if command_line.input.trim().is_empty() {
+            return Ok(());
+        }
+        match parse_command(&command_line.input, Utc::now().naive_utc()) {
+            Ok((_, filter)) => self.worker_tx.send(Request::CountMessages(filter))?,
+            Err(err) => command_line.error = Some(format!("{err:#}")),
+        }
+        Ok(())
+    }
+
+    // Run the command and close the command line, or show why the command is invalid
+    pub fn submit_command_line(&mut self) -> Result<()> {
+        let Some(command_line) = self.command_line.as_mut() else {
+            return Ok(());
+        };
+        match parse_command(&command_line.input, Utc::now().naive_utc()) {
+            Ok((new_state, filter)) => {
+                self.command_line = None;
+                self.worker_tx.send(Request::ChangeMessageStates {
+                    filter,
+                    new_state,
+                    refresh: true,
+                    optimistic: vec![],
+                })?;
+            }
+            Err(err) => command_line.error = Some(format!("{err:#}")),
+        }
+        Ok(())
+    }
+
+    // Open the attachments of the message under the cursor
+    pub fn open_attachments(&self) -> Result<()> {
+        if let Some(message) = self.messages.get_cursor_item() {
+            self.worker_tx.send(Request::OpenAttachments(message.id))?;
+        }
+        Ok(())
+    }
+
+    // Record that the message under the cursor was opened
+    pub fn record_view(&self) -> Result<()> {
+        if let Some(message) = self.messages.get_cursor_item() {
+            self.worker_tx.send(Request::RecordView(message.id))?;
+        }
+        Ok(())
+    }
+
+    // Set reminders that return the selected messages to unread tomorrow
+    pub fn remind_selected_messages(&mut self) -> Result<()> {
+        let ids = self.get_action_ids();
+        if !ids.is_empty() {
+            self.worker_tx.send(Request::RemindMessages {
+                ids,
+                delay: REMIND_DELAY,
+            })?;
+        }
+        self.messages.deselect_hidden();
+        Ok(())
+    }
+
+    // Change the state of all messages matching the filter, refreshing the lists afterwards if
+    // `refresh` is true or the active mailbox changes
+    fn set_message_states(
+        &mut self,
+        action_filter: Filter,
+        new_state: State,
+        refresh: bool,
+    ) -> Result<()> {
+        self.warning = None;
+
         // Optimistically update the messages list
         let display_filter = self.get_display_filter();
+        let optimistic = self
+            .messages
+            .get_items()
+            .iter()
+            .filter(|message| action_filter.matches_message(message))
+            .map(|message| message.id)
+            .collect();
         let (remaining, removed) = self
             .messages
             .get_items()
@@ -282,6 +730,7 @@ impl App {
                 if action_filter.matches_message(&message) {
                     Message {
                         state: new_state,
+                        custom_state: None,
                         ..message
                     }
                 } else {
@@ -289,8 +738,12 @@ impl App {
                     message
                 }
             })
-            .partition(|message| display_filter.matches_message(message));
+            .partition::<Vec<_>, _>(|message| display_filter.matches_message(message));
         self.messages.replace_items(remaining);
+        // The removed messages were acted on, so they shouldn't stay selected while they are hidden
+        for message in &removed {
+            self.messages.set_item_selected(message.get_key(), false);
+        }
 
         // Optimistically update the mailbox list
         let old_display_filter = self.get_display_filter();
@@ -302,11 +755,8 @@ impl App {
             new_state,
             // If changing the mailbox list changed the active mailbox, the message list needs to be refreshed
             // The actual refreshing is done when handle_worker_response receives the refresh response
-            response: if old_display_filter == self.get_display_filter() {
-                None
-            } else {
-                Some(Response::Refresh)
-            },
+            refresh: refresh || old_display_filter != self.get_display_filter(),
+            optimistic,
         })?;
 
         Ok(())
@@ -357,7 +807,7 @@ mod tests {
         let mailboxes = vec![
             MailboxInfo {
                 name: "a".try_into()?,
-                message_count: 1,
+                message_count: 2,
             },
             MailboxInfo {
                 name: "a/b".try_into()?,
@@ -369,7 +819,7 @@ mod tests {
             },
             MailboxInfo {
                 name: "b".try_into()?,
-                message_count: 1,
+                message_count: 4,
             },
             MailboxInfo {
                 name: "b/d/e".try_into()?,
@@ -381,11 +831,11 @@ mod tests {
             },
             MailboxInfo {
                 name: "b/d".try_into()?,
-                message_count: 1,
+                message_count: 2,
             },
         ];
         assert_eq!(
-            App::build_mailbox_list(mailboxes),
+            App::build_mailbox_list(mailboxes, &PinnedMailboxes::default()),
             vec![
                 Mailbox {
                     mailbox: "a".try_into()?,
@@ -426,4 +876,27 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_build_mailbox_list_pinned() -> Result<()> {
+        let mailboxes = ["a", "a/b", "a-b", "b", "b/c", "b/d", "b/d/e"]
+            .into_iter()
+            .map(|name| {
+                Ok(MailboxInfo {
+                    name: name.try_into()?,
+                    message_count: 1,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut pinned = PinnedMailboxes::default();
+        pinned.toggle(&"b/d".try_into()?)?;
+        pinned.toggle(&"b".try_into()?)?;
+        let names = App::build_mailbox_list(mailboxes, &pinned)
+            .into_iter()
+            .map(|mailbox| mailbox.mailbox.to_string())
+            .collect::<Vec<_>>();
+        // Pinned mailboxes are listed before their siblings, and children stay under their parent
+        assert_eq!(names, vec!["b", "b/d", "b/d/e", "b/c", "a", "a/b", "a-b"]);
+        Ok(())
+    }
 }
@@ -0,0 +1,128 @@
+use crate::cli::parse_duration;
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, TimeDelta};
+use database::{Filter, State};
+
+// A command typed after pressing ":" that changes the state of every message that matches a
+// filter, like "archive mailbox=ci state=read before=7d"
+#[derive(Default)]
+pub struct CommandLine {
+    pub(crate) input: String,
+    // The number of messages that the command would change, which is None until they are counted
+    pub(crate) match_count: Option<usize>,
+    // Why the command can't be run
+    pub(crate) error: Option<String>,
+}
+
+impl CommandLine {
+    // Create an empty command line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Type a character into the command
+    pub fn input(&mut self, char: char) {
+        self.input.push(char);
+    }
+
+    // Delete the last character of the command
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+}
+
+// Parse a command into the state that it moves messages into and the filter that selects them
+// The filter has the same keys as the server's query strings, except that "state" can be used
+// instead of "states" and "since" and "before" also accept how long ago, like "7d".
+pub fn parse_command(input: &str, now: NaiveDateTime) -> Result<(State, Filter)> {
+    let mut words = input.split_whitespace();
+    let new_state = match words.next() {
+        Some("unread") => State::Unread,
+        Some("read") => State::Read,
+        Some("archive") => State::Archived,
+        Some(command) => bail!("unknown command \"{command}\", expected unread, read, or archive"),
+        None => bail!("expected unread, read, or archive"),
+    };
+    let pairs = words
+        .map(|word| {
+            let (key, value) = word
+                .split_once('=')
+                .with_context(|| format!("expected key=value, found \"{word}\""))?;
+            let key = if key == "state" { "states" } else { key };
+            let value = match (key, parse_duration(value)) {
+                ("since" | "before", Ok(ago)) => (now - TimeDelta::from_std(ago)?)
+                    .format("%Y-%m-%dT%H:%M:%S")
+                    .to_string(),
+                _ => value.to_owned(),
+            };
+            Ok((key, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let filter = serde_urlencoded::from_str::<Filter>(&serde_urlencoded::to_string(pairs)?)?;
+    // Like the server's delete endpoint, refuse to change every message by accident
+    if filter.matches_all() {
+        bail!("expected a filter like mailbox=ci");
+    }
+    Ok((new_state, filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 8)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_command() -> Result<()> {
+        assert_eq!(
+            parse_command("archive mailbox=ci state=read before=7d", now())?,
+            (
+                State::Archived,
+                Filter::new()
+                    .with_mailbox("ci".try_into()?)
+                    .with_states(vec![State::Read])
+                    .with_before(
+                        NaiveDate::from_ymd_opt(2024, 1, 1)
+                            .unwrap()
+                            .and_hms_opt(12, 0, 0)
+                            .unwrap()
+                    )
+            )
+        );
+        assert_eq!(
+            parse_command(
+                " read  states=unread,archived since=2024-01-02T00:00:00 ",
+                now()
+            )?,
+            (
+                State::Read,
+                Filter::new()
+                    .with_states(vec![State::Unread, State::Archived])
+                    .with_since(
+                        NaiveDate::from_ymd_opt(2024, 1, 2)
+                            .unwrap()
+                            .and_hms_opt(0, 0, 0)
+                            .unwrap()
+                    )
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_command() {
+        assert!(parse_command("", now()).is_err());
+        assert!(parse_command("delete mailbox=ci", now()).is_err());
+        assert!(parse_command("archive mailbox", now()).is_err());
+        assert!(parse_command("archive color=red", now()).is_err());
+        assert!(parse_command("archive state=done", now()).is_err());
+        assert!(parse_command("archive before=7x", now()).is_err());
+        assert!(parse_command("archive", now()).is_err());
+    }
+}
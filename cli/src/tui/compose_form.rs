@@ -0,0 +1,163 @@
+use anyhow::{bail, Result};
+use database::{Mailbox, NewMessage, State};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Field {
+    Mailbox,
+    State,
+    Content,
+}
+
+// A form for writing a new message without leaving the TUI
+pub struct ComposeForm {
+    pub(crate) mailbox: String,
+    pub(crate) state: State,
+    pub(crate) content: String,
+    pub(crate) focus: Field,
+    // The reason that the message couldn't be added
+    pub(crate) error: Option<String>,
+}
+
+impl ComposeForm {
+    // Create a form for a message in a mailbox, focusing the content if the mailbox is known
+    pub fn new(mailbox: Option<&Mailbox>) -> Self {
+        Self {
+            mailbox: mailbox.map(ToString::to_string).unwrap_or_default(),
+            state: State::Unread,
+            content: String::new(),
+            focus: if mailbox.is_some() {
+                Field::Content
+            } else {
+                Field::Mailbox
+            },
+            error: None,
+        }
+    }
+
+    // Move the focus to the next field
+    pub fn next_field(&mut self) {
+        self.focus = match self.focus {
+            Field::Mailbox => Field::State,
+            Field::State => Field::Content,
+            Field::Content => Field::Mailbox,
+        };
+    }
+
+    // Move the focus to the previous field
+    pub fn previous_field(&mut self) {
+        self.focus = match self.focus {
+            Field::Mailbox => Field::Content,
+            Field::State => Field::Mailbox,
+            Field::Content => Field::State,
+        };
+    }
+
+    // Switch the state of the message to the next or previous state
+    pub fn cycle_state(&mut self, forward: bool) {
+        self.state = match (self.state, forward) {
+            (State::Unread, true) | (State::Archived, false) => State::Read,
+            (State::Read, true) | (State::Unread, false) => State::Archived,
+            (State::Archived, true) | (State::Read, false) => State::Unread,
+        };
+    }
+
+    // Type a character into the focused field
+    pub fn input(&mut self, char: char) {
+        match self.focus {
+            Field::Mailbox => self.mailbox.push(char),
+            Field::State => {
+                if char == ' ' {
+                    self.cycle_state(true);
+                }
+            }
+            Field::Content => self.content.push(char),
+        }
+    }
+
+    // Delete the last character of the focused field
+    pub fn backspace(&mut self) {
+        match self.focus {
+            Field::Mailbox => {
+                self.mailbox.pop();
+            }
+            Field::State => {}
+            Field::Content => {
+                self.content.pop();
+            }
+        }
+    }
+
+    // Create the message that the form describes
+    pub fn to_message(&self) -> Result<NewMessage> {
+        let mailbox = self.mailbox.trim().parse::<Mailbox>()?;
+        let content = self.content.trim();
+        if content.is_empty() {
+            bail!("content must not be empty");
+        }
+        Ok(NewMessage {
+            mailbox,
+            content: content.to_owned(),
+            state: Some(self.state),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input() -> Result<()> {
+        let mut form = ComposeForm::new(Some(&"ci".try_into()?));
+        assert_eq!(form.focus, Field::Content);
+        "Hi!".chars().for_each(|char| form.input(char));
+        form.backspace();
+        form.previous_field();
+        form.input(' ');
+        form.cycle_state(true);
+        form.previous_field();
+        form.input('/');
+        form.input('x');
+        assert_eq!(
+            form.to_message()?,
+            NewMessage {
+                mailbox: "ci/x".try_into()?,
+                content: String::from("Hi"),
+                state: Some(State::Archived),
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycle_state() {
+        let mut form = ComposeForm::new(None);
+        form.cycle_state(false);
+        assert_eq!(form.state, State::Archived);
+        form.cycle_state(true);
+        assert_eq!(form.state, State::Unread);
+    }
+
+    #[test]
+    fn test_invalid() -> Result<()> {
+        let mut form = ComposeForm::new(None);
+        form.content = String::from("Hello");
+        assert!(form.to_message().is_err());
+        let mut form = ComposeForm::new(Some(&"ci".try_into()?));
+        form.content = String::from("  ");
+        assert!(form.to_message().is_err());
+        Ok(())
+    }
+}
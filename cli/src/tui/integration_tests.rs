@@ -0,0 +1,684 @@
+use super::app::{App, Pane, PAGE_SIZE};
+use super::navigable_list::NavigableList;
+use super::pinned_mailboxes::PinnedMailboxes;
+use super::{
+    handle_command_key, handle_compose_key, handle_finder_key, handle_global_key,
+    handle_mailbox_key, handle_message_key, ui,
+};
+use crate::theme::{Glyphs, Theme};
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use database::{
+    Backend, Call, Database, FakeBackend, Filter, NewMessage, RecordingBackend, Sort, State,
+};
+use ratatui::backend::TestBackend;
+use ratatui::Terminal;
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+type TestDbBackend = RecordingBackend<FakeBackend>;
+
+// How long to wait for the worker before failing a test
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Helper for creating a NewMessage from its parts
+fn make_message(mailbox: &str, content: &str, state: State) -> Result<NewMessage> {
+    Ok(NewMessage {
+        mailbox: mailbox.try_into()?,
+        content: content.to_owned(),
+        state: Some(state),
+        tags: vec![],
+        timestamp: None,
+        external_id: None,
+        attachments: vec![],
+        priority: None,
+        kind: None,
+    })
+}
+
+// Create a backend containing several messages and an app that displays the unread messages
+// Also return a handle for scripting the backend
+async fn make_app() -> Result<(App, TestDbBackend, FakeBackend)> {
+    let fake = FakeBackend::with_messages(vec![
+        make_message("ci", "ci message", State::Unread)?,
+        make_message("ci/build", "build message", State::Unread)?,
+        make_message("home", "home message", State::Read)?,
+    ])
+    .await?;
+    let backend = RecordingBackend::new(fake.clone());
+    let app = App::new(
+        Database::new(backend.clone()),
+        None,
+        vec![State::Unread],
+        Pane::Messages,
+        None,
+        PinnedMailboxes::default(),
+        HashMap::new(),
+    )
+    .await?;
+    backend.clear_calls();
+    Ok((app, backend, fake))
+}
+
+// Render the app into a string containing the lines of the terminal
+fn render(app: &mut App) -> Result<String> {
+    let mut terminal = Terminal::new(TestBackend::new(120, 8))?;
+    terminal.draw(|frame| ui(frame, app))?;
+    let buffer = terminal.backend().buffer();
+    Ok(buffer
+        .content()
+        .chunks(buffer.area.width as usize)
+        .map(|row| {
+            row.iter()
+                .map(|cell| cell.symbol.as_str())
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+// Create a key press event without modifiers
+fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+}
+
+// Return the contents of the displayed messages
+fn get_contents(app: &App) -> Vec<&str> {
+    app.messages
+        .get_items()
+        .iter()
+        .map(|message| message.content.as_str())
+        .collect()
+}
+
+// Handle worker responses and pending reloads until the condition is satisfied, returning false if
+// it never is
+fn wait_for(app: &mut App, condition: impl Fn(&App) -> bool) -> Result<bool> {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        app.handle_worker_responses()?;
+        app.reload_settled_messages()?;
+        if condition(app) {
+            return Ok(true);
+        }
+        sleep(Duration::from_millis(5));
+    }
+    Ok(false)
+}
+
+// Load messages from the backend until the number of messages matching the filter is `count`,
+// returning false if it never is
+async fn wait_for_count(backend: &FakeBackend, filter: Filter, count: usize) -> Result<bool> {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if backend.load_messages(filter.clone()).await?.len() == count {
+            return Ok(true);
+        }
+        sleep(Duration::from_millis(5));
+    }
+    Ok(false)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_initial_render() -> Result<()> {
+    let (mut app, _, _) = make_app().await?;
+    let screen = render(&mut app)?;
+    assert!(screen.contains("Mailboxes (2)"));
+    assert!(screen.contains("ci (2)"));
+    assert!(screen.contains(" build (1)"));
+    assert!(!screen.contains("home"));
+    assert!(screen.contains("Messages (2)"));
+    assert!(screen.contains("* build message"));
+    assert!(screen.contains("* ci message"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_theme_glyphs() -> Result<()> {
+    let (app, _, _) = make_app().await?;
+    let mut app = app.with_theme(Theme {
+        glyphs: Glyphs {
+            unread: String::from("!"),
+            cursor: String::from(">"),
+            ..Glyphs::default()
+        },
+        ..Theme::default()
+    });
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char(' ')))?;
+    let screen = render(&mut app)?;
+    assert!(screen.contains("> ! ci message"));
+    assert!(screen.contains("  ! build message"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_select_and_read() -> Result<()> {
+    let (mut app, backend, fake) = make_app().await?;
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char(' ')))?;
+    let selected = app
+        .messages
+        .get_selected_items()
+        .map(|message| message.id)
+        .collect::<Vec<_>>();
+    assert_eq!(selected.len(), 1);
+    assert!(render(&mut app)?.contains("• * "));
+
+    // The message list and mailbox counts are updated before the backend responds
+    handle_message_key(&mut app, key(KeyCode::Char('r')))?;
+    assert_eq!(get_contents(&app), vec!["ci message"]);
+    assert!(render(&mut app)?.contains("ci (1)"));
+
+    assert!(
+        wait_for_count(
+            &fake,
+            Filter::new()
+                .with_ids(selected.clone())
+                .with_states(vec![State::Read]),
+            1
+        )
+        .await?
+    );
+    assert!(backend.get_calls().contains(&Call::ChangeState(
+        Filter::new().with_ids(selected),
+        State::Read
+    )));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_persistent_selection() -> Result<()> {
+    let (app, _, fake) = make_app().await?;
+    let mut app = app.with_persistent_selection(true);
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char(' ')))?;
+    let id = app.messages.get_cursor_item().unwrap().id;
+
+    // The selected message stays selected after the state filter hides it
+    handle_global_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL),
+    )?;
+    handle_global_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+    )?;
+    assert!(wait_for(&mut app, |app| get_contents(app) == vec!["home message"])?);
+    assert!(render(&mut app)?.contains("1 hidden selected message"));
+
+    // Archiving acts on the hidden message instead of the message under the cursor
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char('a')))?;
+    assert!(
+        wait_for_count(
+            &fake,
+            Filter::new()
+                .with_ids(vec![id])
+                .with_states(vec![State::Archived]),
+            1
+        )
+        .await?
+    );
+    assert!(app.messages.get_hidden_selected_keys().is_empty());
+    assert!(wait_for(&mut app, |app| get_contents(app) == vec!["home message"])?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_optimistic_update_with_latency() -> Result<()> {
+    let (mut app, backend, fake) = make_app().await?;
+    fake.set_latency(Duration::from_millis(100));
+
+    // The deleted message disappears before the slow backend responds
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    let id = app.messages.get_cursor_item().unwrap().id;
+    handle_message_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL),
+    )?;
+    assert_eq!(app.messages.get_items().len(), 1);
+
+    fake.set_latency(Duration::ZERO);
+    assert!(wait_for_count(&fake, Filter::new().with_ids(vec![id]), 0).await?);
+    assert!(backend
+        .get_calls()
+        .contains(&Call::DeleteMessages(Filter::new().with_ids(vec![id]))));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_optimistic_update_conflict() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+
+    // Another client deletes the message before this one archives it
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    let id = app.messages.get_cursor_item().unwrap().id;
+    fake.delete_messages(Filter::new().with_ids(vec![id]))
+        .await?;
+    fake.add_messages(vec![make_message("home", "new message", State::Unread)?])
+        .await?;
+    handle_message_key(&mut app, key(KeyCode::Char('a')))?;
+    assert_eq!(get_contents(&app), vec!["ci message"]);
+
+    // The conflict is reported and the lists are reloaded from the backend
+    assert!(wait_for(&mut app, |app| app.warning.is_some())?);
+    assert!(render(&mut app)?.contains(
+        "warning: 1 message was changed by another client, so the messages were reloaded"
+    ));
+    assert!(wait_for(&mut app, |app| get_contents(app)
+        == vec!["new message", "ci message"])?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_optimistic_update_failure() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+
+    // The optimistic update is reverted when the mutation fails
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    fake.fail_next(1);
+    handle_message_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL),
+    )?;
+    assert_eq!(app.messages.get_items().len(), 1);
+    assert!(wait_for(&mut app, |app| app.error.is_some())?);
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 2)?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_refresh_loads_new_messages() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+    fake.add_messages(vec![make_message("home", "new message", State::Unread)?])
+        .await?;
+
+    handle_mailbox_key(&mut app, key(KeyCode::Char('R')))?;
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 3)?);
+    assert!(wait_for(&mut app, |app| app.mailboxes.get_items().len() == 3)?);
+    assert!(render(&mut app)?.contains("* new message"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_remind() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+
+    // The reminded message is read until the reminder is due
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    let id = app.messages.get_cursor_item().unwrap().id;
+    handle_message_key(&mut app, key(KeyCode::Char('R')))?;
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 1)?);
+    assert!(backend
+        .get_calls()
+        .contains(&Call::RemindMessage(id, Duration::from_hours(24))));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_mailbox_navigation() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+
+    // Moving into a mailbox filters the messages locally without querying the backend
+    handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+    assert_eq!(get_contents(&app), vec!["build message"]);
+    assert!(backend.get_calls().is_empty());
+
+    // Moving out of the mailbox reloads the messages from the backend
+    handle_mailbox_key(&mut app, key(KeyCode::Esc))?;
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 2)?);
+    assert!(backend.get_calls().contains(&Call::LoadMessages(
+        app.get_display_filter().with_limit(PAGE_SIZE)
+    )));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_error_and_retry() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+    fake.add_messages(vec![make_message("home", "new message", State::Unread)?])
+        .await?;
+
+    // The failed load is reported in the status bar instead of stopping the worker
+    fake.fail_next(1);
+    handle_mailbox_key(&mut app, key(KeyCode::Char('R')))?;
+    assert!(wait_for(&mut app, |app| app.error.is_some())?);
+    assert!(render(&mut app)?.contains("error: Injected backend failure (press t to retry)"));
+
+    handle_global_key(&mut app, key(KeyCode::Char('t')))?;
+    assert!(app.error.is_none());
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 3)?);
+    assert!(wait_for(&mut app, |app| app.mailboxes.get_items().len() == 3)?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_compose() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+    handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+
+    // The form is pre-filled with the mailbox under the cursor
+    handle_global_key(&mut app, key(KeyCode::Char('c')))?;
+    for char in "Deploy done".chars() {
+        handle_compose_key(&mut app, key(KeyCode::Char(char)))?;
+    }
+    let screen = render(&mut app)?;
+    assert!(screen.contains("New message"));
+    assert!(screen.contains("Mailbox: ci/build"));
+    assert!(screen.contains("Content: Deploy done"));
+
+    // Invalid messages keep the form open and show the error
+    handle_compose_key(&mut app, key(KeyCode::BackTab))?;
+    handle_compose_key(&mut app, key(KeyCode::BackTab))?;
+    handle_compose_key(&mut app, key(KeyCode::Char('/')))?;
+    handle_compose_key(&mut app, key(KeyCode::Enter))?;
+    assert!(app.compose_form.is_some());
+    assert!(render(&mut app)?.contains("error: "));
+    handle_compose_key(&mut app, key(KeyCode::Backspace))?;
+
+    handle_compose_key(&mut app, key(KeyCode::Tab))?;
+    handle_compose_key(&mut app, key(KeyCode::Right))?;
+    assert!(render(&mut app)?.contains("State:   < read >"));
+    handle_compose_key(&mut app, key(KeyCode::Left))?;
+    handle_compose_key(&mut app, key(KeyCode::Enter))?;
+    assert!(app.compose_form.is_none());
+
+    // The new message is added and the lists are refreshed
+    let filter = Filter::new()
+        .with_mailbox("ci/build".try_into()?)
+        .with_states(vec![State::Unread]);
+    assert!(wait_for_count(&fake, filter, 2).await?);
+    assert!(wait_for(&mut app, |app| get_contents(app).contains(&"Deploy done"))?);
+    assert!(wait_for(&mut app, |app| app
+        .mailboxes
+        .get_items()
+        .iter()
+        .any(
+            |mailbox| mailbox.mailbox.as_ref() == "ci/build" && mailbox.message_count == 2
+        ))?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_command_line() -> Result<()> {
+    let (mut app, _, fake) = make_app().await?;
+    handle_global_key(&mut app, key(KeyCode::Char(':')))?;
+    for char in "archive mailbox=ci state=unread".chars() {
+        handle_command_key(&mut app, key(KeyCode::Char(char)))?;
+    }
+
+    // The matching messages are counted before the command runs
+    assert!(wait_for(&mut app, |app| app
+        .command_line
+        .as_ref()
+        .is_some_and(
+            |command_line| command_line.match_count == Some(2)
+        ))?);
+    assert!(render(&mut app)?.contains(":archive mailbox=ci state=unread   2 messages match"));
+
+    handle_command_key(&mut app, key(KeyCode::Enter))?;
+    assert!(app.command_line.is_none());
+    assert!(wait_for_count(&fake, Filter::new().with_states(vec![State::Archived]), 2).await?);
+    assert!(wait_for(&mut app, |app| app
+        .messages
+        .get_items()
+        .is_empty())?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_command_line_invalid() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+    handle_global_key(&mut app, key(KeyCode::Char(':')))?;
+    for char in "archive".chars() {
+        handle_command_key(&mut app, key(KeyCode::Char(char)))?;
+    }
+    handle_command_key(&mut app, key(KeyCode::Enter))?;
+    assert!(app.command_line.is_some());
+    assert!(render(&mut app)?.contains(":archive   expected a filter like mailbox=ci"));
+
+    handle_command_key(&mut app, key(KeyCode::Esc))?;
+    assert!(app.command_line.is_none());
+    assert!(backend.get_calls().is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_compose_cancel() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+    handle_global_key(&mut app, key(KeyCode::Char('c')))?;
+    assert!(render(&mut app)?.contains("Mailbox:  "));
+    handle_compose_key(&mut app, key(KeyCode::Char('q')))?;
+    handle_compose_key(&mut app, key(KeyCode::Esc))?;
+    assert!(app.compose_form.is_none());
+    assert!(!render(&mut app)?.contains("New message"));
+    assert!(backend.get_calls().is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pin_mailbox() -> Result<()> {
+    let (mut app, _, _) = make_app().await?;
+    handle_global_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+    )?;
+    assert!(wait_for(&mut app, |app| app.mailboxes.get_items().len() == 3)?);
+    let get_names = |app: &App| {
+        app.mailboxes
+            .get_items()
+            .iter()
+            .map(|mailbox| mailbox.mailbox.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(get_names(&app), vec!["ci", "ci/build", "home"]);
+
+    // Pinning a mailbox moves it to the top and keeps the cursor on it
+    for _ in 0..3 {
+        handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+    }
+    handle_mailbox_key(&mut app, key(KeyCode::Char('p')))?;
+    assert_eq!(get_names(&app), vec!["home", "ci", "ci/build"]);
+    assert_eq!(
+        app.mailboxes
+            .get_cursor_item()
+            .map(|mailbox| mailbox.mailbox.to_string()),
+        Some(String::from("home"))
+    );
+
+    // Pinned mailboxes can be reordered
+    handle_mailbox_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_mailbox_key(&mut app, key(KeyCode::Char('p')))?;
+    assert_eq!(get_names(&app), vec!["home", "ci", "ci/build"]);
+    handle_mailbox_key(&mut app, key(KeyCode::Char('[')))?;
+    assert_eq!(get_names(&app), vec!["ci", "ci/build", "home"]);
+
+    // Unpinning a mailbox moves it back to alphabetical order
+    handle_mailbox_key(&mut app, key(KeyCode::Char('p')))?;
+    assert_eq!(get_names(&app), vec!["home", "ci", "ci/build"]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_mailbox_finder() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+    handle_global_key(&mut app, key(KeyCode::Char('f')))?;
+    for char in "bld".chars() {
+        handle_finder_key(&mut app, key(KeyCode::Char(char)))?;
+    }
+    let screen = render(&mut app)?;
+    assert!(screen.contains("> bld"));
+    assert!(screen.contains("Matches (1)"));
+
+    // Picking a mailbox moves the mailboxes cursor to it and filters the messages
+    handle_finder_key(&mut app, key(KeyCode::Enter))?;
+    assert!(app.mailbox_finder.is_none());
+    assert_eq!(
+        app.mailboxes
+            .get_cursor_item()
+            .map(|mailbox| mailbox.mailbox.to_string()),
+        Some(String::from("ci/build"))
+    );
+    assert_eq!(get_contents(&app), vec!["build message"]);
+    assert!(backend.get_calls().is_empty());
+
+    // Closing the finder without picking a mailbox leaves the cursor alone
+    handle_global_key(&mut app, key(KeyCode::Char('f')))?;
+    handle_finder_key(&mut app, key(KeyCode::Down))?;
+    handle_finder_key(&mut app, key(KeyCode::Esc))?;
+    assert!(app.mailbox_finder.is_none());
+    assert_eq!(get_contents(&app), vec!["build message"]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_sort() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+    assert_eq!(get_contents(&app), vec!["ci message", "build message"]);
+
+    // Reversing the order reloads the messages from the backend
+    handle_global_key(&mut app, key(KeyCode::Char('S')))?;
+    assert!(wait_for(&mut app, |app| get_contents(app)
+        == vec!["build message", "ci message"])?);
+    assert!(backend.get_calls().contains(&Call::LoadMessages(
+        Filter::new()
+            .with_states(vec![State::Unread])
+            .with_reverse(true)
+            .with_limit(PAGE_SIZE)
+    )));
+
+    handle_global_key(&mut app, key(KeyCode::Char('s')))?;
+    assert_eq!(
+        app.get_display_filter(),
+        Filter::new()
+            .with_states(vec![State::Unread])
+            .with_sort(Sort::Mailbox)
+            .with_reverse(true)
+    );
+    assert!(render(&mut app)?.contains("sort: mailbox (reversed)"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_debounced_reload() -> Result<()> {
+    let (mut app, backend, _) = make_app().await?;
+
+    // A burst of key presses only reloads the messages once, with the final filters
+    for _ in 0..3 {
+        handle_global_key(&mut app, key(KeyCode::Char('S')))?;
+    }
+    app.reload_settled_messages()?;
+    assert!(backend.get_calls().is_empty());
+    assert!(wait_for(&mut app, |app| get_contents(app)
+        == vec!["build message", "ci message"])?);
+    let loads = backend
+        .get_calls()
+        .into_iter()
+        .filter(|call| matches!(call, Call::LoadMessages(_)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        loads,
+        vec![Call::LoadMessages(
+            app.get_display_filter().with_limit(PAGE_SIZE)
+        )]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unread_first() -> Result<()> {
+    let (mut app, backend, fake) = make_app().await?;
+    fake.add_messages(vec![make_message("home", "new message", State::Read)?])
+        .await?;
+    handle_global_key(
+        &mut app,
+        KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+    )?;
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len() == 4)?);
+    assert_eq!(get_contents(&app)[0], "new message");
+
+    // Unread messages are listed before the read messages
+    handle_global_key(&mut app, key(KeyCode::Char('U')))?;
+    assert_eq!(app.sort, Some(Sort::State));
+    assert!(wait_for(&mut app, |app| get_contents(app)[..2]
+        == ["ci message", "build message"])?);
+    assert!(backend.get_calls().contains(&Call::LoadMessages(
+        app.get_display_filter().with_limit(PAGE_SIZE)
+    )));
+
+    // Toggling again returns to the default order
+    handle_global_key(&mut app, key(KeyCode::Char('U')))?;
+    assert_eq!(app.sort, None);
+    assert!(wait_for(&mut app, |app| get_contents(app)[0] == "new message")?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_load_more_messages() -> Result<()> {
+    let messages = (0..PAGE_SIZE + 10)
+        .map(|index| make_message("ci", &format!("message {index}"), State::Unread))
+        .collect::<Result<Vec<_>>>()?;
+    let fake = FakeBackend::with_messages(messages).await?;
+    let backend = RecordingBackend::new(fake.clone());
+    let mut app = App::new(
+        Database::new(backend.clone()),
+        None,
+        vec![State::Unread],
+        Pane::Messages,
+        None,
+        PinnedMailboxes::default(),
+        HashMap::new(),
+    )
+    .await?;
+
+    // Only the first page is loaded initially
+    let initial_filter = app.get_display_filter().with_limit(PAGE_SIZE);
+    assert_eq!(app.messages.get_items().len(), PAGE_SIZE);
+    assert!(render(&mut app)?.contains(&format!("Messages ({PAGE_SIZE}+)")));
+
+    // Nothing more is loaded until the cursor approaches the end of the list
+    handle_message_key(&mut app, key(KeyCode::Char('j')))?;
+    handle_message_key(&mut app, key(KeyCode::Char(' ')))?;
+    app.load_more_messages()?;
+    assert!(!backend
+        .get_calls()
+        .iter()
+        .any(|call| matches!(call, Call::LoadMessages(filter) if filter != &initial_filter)));
+    let selected = app.messages.get_cursor_item().unwrap().id;
+    handle_message_key(&mut app, key(KeyCode::Char('J')))?;
+    let cursor = app.messages.get_cursor_item().unwrap().id;
+    app.load_more_messages()?;
+    assert!(wait_for(&mut app, |app| app.messages.get_items().len()
+        == PAGE_SIZE + 10)?);
+    assert!(backend.get_calls().contains(&Call::LoadMessages(
+        app.get_display_filter()
+            .with_offset(PAGE_SIZE)
+            .with_limit(PAGE_SIZE)
+    )));
+
+    // The cursor and selection are unchanged by the newly loaded messages
+    assert_eq!(app.messages.get_cursor_item().unwrap().id, cursor);
+    assert_eq!(
+        app.messages
+            .get_selected_items()
+            .map(|message| message.id)
+            .collect::<Vec<_>>(),
+        vec![selected]
+    );
+    assert!(!app.has_more_messages);
+
+    // Refreshing reloads every loaded page
+    let filter = app.get_display_filter().with_limit(2 * PAGE_SIZE);
+    handle_mailbox_key(&mut app, key(KeyCode::Char('R')))?;
+    assert!(wait_for(&mut app, |_| backend
+        .get_calls()
+        .contains(&Call::LoadMessages(filter.clone())))?);
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use database::Mailbox;
+use ratatui::widgets::ListState;
+use std::collections::{HashMap, HashSet};
+
+// A picker that filters mailboxes by fuzzy matching their names or aliases against a query
+pub struct MailboxFinder {
+    // The names that the query is matched against and the mailboxes that they pick, in the order
+    // that they are listed when the query is empty
+    candidates: Vec<(String, Mailbox)>,
+    pub(crate) query: String,
+    // The mailboxes that match the query, best match first
+    pub(crate) matches: Vec<Mailbox>,
+    state: ListState,
+}
+
+// Score how well a query matches a mailbox name, or return None if the name doesn't contain every
+// character of the query in order
+// Matches that are consecutive or at the start of a section of the name score higher, and shorter
+// names score higher than longer names with the same matches. Every name matches an empty query
+// equally.
+fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut score = 0;
+    let mut query_chars = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous_match = false;
+    let mut previous_char = None;
+    for char in name.chars().flat_map(char::to_lowercase) {
+        let Some(&query_char) = query_chars.peek() else {
+            break;
+        };
+        if char == query_char {
+            query_chars.next();
+            score += 1;
+            if previous_match {
+                score += 5;
+            }
+            if previous_char.is_none_or(|previous| matches!(previous, '/' | '-' | '_' | ' ')) {
+                score += 3;
+            }
+            previous_match = true;
+        } else {
+            previous_match = false;
+        }
+        previous_char = Some(char);
+    }
+    if query_chars.peek().is_some() {
+        return None;
+    }
+    Some(score * 100 - i64::try_from(name.len()).unwrap_or(i64::MAX).min(99))
+}
+
+impl MailboxFinder {
+    // Create a finder that picks from the provided mailboxes, which can also be found by the names
+    // of the aliases that point to them
+    pub fn new(mailboxes: Vec<Mailbox>, aliases: &HashMap<String, Mailbox>) -> Self {
+        let mut alias_candidates = aliases
+            .iter()
+            .filter(|(_, mailbox)| mailboxes.contains(mailbox))
+            .map(|(alias, mailbox)| (alias.clone(), mailbox.clone()))
+            .collect::<Vec<_>>();
+        alias_candidates.sort();
+        let candidates = mailboxes
+            .into_iter()
+            .map(|mailbox| (mailbox.to_string(), mailbox))
+            .chain(alias_candidates)
+            .collect();
+        let mut finder = Self {
+            candidates,
+            query: String::new(),
+            matches: vec![],
+            state: ListState::default(),
+        };
+        finder.update_matches();
+        finder
+    }
+
+    // Recalculate the matches after the query changed and move the cursor to the best match
+    fn update_matches(&mut self) {
+        let mut matches = self
+            .candidates
+            .iter()
+            .filter_map(|(name, mailbox)| {
+                fuzzy_score(&self.query, name).map(|score| (score, mailbox))
+            })
+            .collect::<Vec<_>>();
+        // The sort is stable, so mailboxes with the same score stay in list order
+        matches.sort_by_key(|(score, _)| -score);
+        // A mailbox that matches by both its name and an alias is only listed at its best match
+        let mut seen = HashSet::new();
+        self.matches = matches
+            .into_iter()
+            .filter(|(_, mailbox)| seen.insert(*mailbox))
+            .map(|(_, mailbox)| mailbox.clone())
+            .collect();
+        self.state.select(if self.matches.is_empty() {
+            None
+        } else {
+            Some(0)
+        });
+    }
+
+    // Type a character into the query
+    pub fn input(&mut self, char: char) {
+        self.query.push(char);
+        self.update_matches();
+    }
+
+    // Delete the last character of the query
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    // Move the cursor to the next or previous match, wrapping around at the ends
+    pub fn move_cursor(&mut self, forward: bool) {
+        let len = self.matches.len();
+        if let Some(cursor) = self.state.selected() {
+            self.state.select(Some(if forward {
+                (cursor + 1) % len
+            } else {
+                (cursor + len - 1) % len
+            }));
+        }
+    }
+
+    // Return the match under the cursor
+    pub fn get_selected(&self) -> Option<&Mailbox> {
+        self.state
+            .selected()
+            .and_then(|cursor| self.matches.get(cursor))
+    }
+
+    // Return a reference to the list state
+    pub fn get_list_state(&mut self) -> &mut ListState {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn make_finder(names: &[&str]) -> Result<MailboxFinder> {
+        Ok(MailboxFinder::new(
+            names
+                .iter()
+                .map(|name| Mailbox::try_from(*name))
+                .collect::<Result<_>>()?,
+            &HashMap::new(),
+        ))
+    }
+
+    fn get_matches(finder: &MailboxFinder) -> Vec<&str> {
+        finder.matches.iter().map(AsRef::as_ref).collect()
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("", "ci").is_some());
+        assert!(fuzzy_score("cb", "ci/build").is_some());
+        assert!(fuzzy_score("CB", "ci/build").is_some());
+        assert!(fuzzy_score("bc", "ci/build").is_none());
+        assert!(fuzzy_score("cix", "ci/build").is_none());
+        // Consecutive matches beat scattered matches
+        assert!(fuzzy_score("bui", "ci/build") > fuzzy_score("bui", "backup/ui"));
+        // Matches at the start of a section beat matches in the middle
+        assert!(fuzzy_score("b", "ci/build") > fuzzy_score("b", "cib"));
+        // Shorter names win ties
+        assert!(fuzzy_score("ci", "ci") > fuzzy_score("ci", "ci/build"));
+    }
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let mut finder = make_finder(&["backup", "ci", "ci/build", "ci/deploy", "home/bills"])?;
+        assert_eq!(finder.matches.len(), 5);
+        assert_eq!(finder.get_selected().map(AsRef::as_ref), Some("backup"));
+
+        "cib".chars().for_each(|char| finder.input(char));
+        assert_eq!(get_matches(&finder), vec!["ci/build"]);
+        finder.backspace();
+        assert_eq!(get_matches(&finder), vec!["ci", "ci/build", "ci/deploy"]);
+        finder.input('x');
+        assert!(finder.matches.is_empty());
+        assert!(finder.get_selected().is_none());
+        finder.move_cursor(true);
+        assert!(finder.get_selected().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aliases() -> Result<()> {
+        let aliases = HashMap::from([
+            (String::from("inbox"), Mailbox::try_from("personal/todo")?),
+            (String::from("pt"), Mailbox::try_from("personal/todo")?),
+            (String::from("gone"), Mailbox::try_from("deleted")?),
+        ]);
+        let mailboxes = vec![
+            Mailbox::try_from("personal")?,
+            Mailbox::try_from("personal/todo")?,
+        ];
+        let mut finder = MailboxFinder::new(mailboxes, &aliases);
+        assert_eq!(get_matches(&finder), vec!["personal", "personal/todo"]);
+        "inbox".chars().for_each(|char| finder.input(char));
+        assert_eq!(get_matches(&finder), vec!["personal/todo"]);
+        finder.query.clear();
+        "gone".chars().for_each(|char| finder.input(char));
+        assert!(finder.matches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_cursor() -> Result<()> {
+        let mut finder = make_finder(&["a", "b", "c"])?;
+        finder.move_cursor(false);
+        assert_eq!(finder.get_selected().map(AsRef::as_ref), Some("c"));
+        finder.move_cursor(true);
+        finder.move_cursor(true);
+        assert_eq!(finder.get_selected().map(AsRef::as_ref), Some("b"));
+        Ok(())
+    }
+}
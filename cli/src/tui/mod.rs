@@ -1,59 +1,188 @@
 mod app;
+mod command_line;
+mod compose_form;
+#[cfg(test)]
+mod integration_tests;
+mod mailbox_finder;
 mod monotonic_counter;
 mod multiselect_list;
 mod navigable_list;
+mod pinned_mailboxes;
+mod styles;
 mod tree_list;
 mod worker;
 
-use self::app::{App, Pane};
+pub use self::app::Pane;
+pub use self::pinned_mailboxes::PinnedMailboxes;
+
+use self::app::App;
+use self::command_line::CommandLine;
+use self::compose_form::{ComposeForm, Field};
+use self::mailbox_finder::MailboxFinder;
 use self::multiselect_list::SelectionMode;
 use self::navigable_list::NavigableList;
-use anyhow::Result;
+use self::styles::Styles;
+use crate::ansi::strip_ansi;
+use crate::open::open_message;
+use crate::theme::Theme;
+use anyhow::{Context, Result};
 use chrono::Utc;
 use chrono_humanize::HumanTime;
 use crossterm::{
+    cursor::Show,
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use database::{Backend as DbBackend, Database, Mailbox, Message, State};
-use linkify::{LinkFinder, LinkKind};
+use database::{Backend as DbBackend, Database, Mailbox, Sort, State};
 use ratatui::{
     backend::{Backend, CrosstermBackend},
+    buffer::Buffer,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Widget},
     Frame, Terminal,
 };
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
 use std::time::{Duration, Instant};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run<B: DbBackend + Send + Sync + 'static>(
     db: Database<B>,
     initial_mailbox: Option<Mailbox>,
     initial_states: Vec<State>,
+    initial_pane: Pane,
+    initial_sort: Option<Sort>,
+    pinned_mailboxes: PinnedMailboxes,
+    aliases: HashMap<String, Mailbox>,
+    theme: Theme,
+    persistent_selection: bool,
 ) -> Result<()> {
     // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
+    install_panic_hook();
+    setup_terminal()?;
+    // Restore the terminal even if the app returns early with an error
+    let _guard = TerminalGuard;
+    let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
 
     // Create app and run it
     let tick_rate = Duration::from_millis(30);
-    let app = App::new(db, initial_mailbox, initial_states).await?;
+    let app = App::new(
+        db,
+        initial_mailbox,
+        initial_states,
+        initial_pane,
+        initial_sort,
+        pinned_mailboxes,
+        aliases,
+    )
+    .await?
+    .with_theme(theme)
+    .with_persistent_selection(persistent_selection);
     let res = run_app(&mut terminal, app, tick_rate);
 
-    // Restore terminal
+    // Restore terminal, which makes the guard do nothing
+    restore_terminal()?;
+
+    res
+}
+
+// Whether the terminal is currently set up for the TUI
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// Restores the terminal when it is dropped
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = restore_terminal();
+    }
+}
+
+// Restore the terminal before the panic message is printed so that the message is readable and
+// the terminal is usable afterwards
+// Release builds abort on panic without running destructors, so TerminalGuard alone isn't enough.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = restore_terminal();
+            default_hook(info);
+        }));
+    });
+}
+
+// Prepare the terminal for drawing the TUI
+fn setup_terminal() -> Result<()> {
+    enable_raw_mode()?;
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    Ok(())
+}
+
+// Return the terminal to its normal state so that the shell and other programs can use it
+// Restoring a terminal that isn't set up does nothing, so that leaving the alternate screen twice
+// doesn't move the cursor.
+fn restore_terminal() -> Result<()> {
+    if !TERMINAL_ACTIVE.swap(false, Ordering::SeqCst) {
+        return Ok(());
+    }
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
+    execute!(io::stdout(), LeaveAlternateScreen, Show)?;
+    Ok(())
+}
 
+// Give the terminal back to the shell while running a function, then resume the TUI
+fn release_terminal<B: Backend>(
+    terminal: &mut Terminal<B>,
+    func: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    restore_terminal()?;
+    let res = func();
+    setup_terminal()?;
+    // Something else drew on the screen in the meantime, so the whole TUI needs to be redrawn
+    terminal.clear()?;
     res
 }
 
+// Stop the process like Ctrl+z normally does, which raw mode prevents the terminal from doing
+// The function returns once the shell resumes the process
+#[cfg(unix)]
+fn suspend() -> Result<()> {
+    // SAFETY: raise doesn't access any memory
+    if unsafe { libc::raise(libc::SIGTSTP) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to suspend");
+    }
+    Ok(())
+}
+
+// Suspending isn't supported on other platforms, so do nothing
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn suspend() -> Result<()> {
+    Ok(())
+}
+
+// Run an interactive shell, which returns to the TUI when it exits
+// $MAILBOX_SHELL runs a custom command instead of $SHELL
+fn run_shell() -> Result<()> {
+    let shell = std::env::var_os("MAILBOX_SHELL")
+        .or_else(|| std::env::var_os("SHELL"))
+        .unwrap_or_else(|| OsString::from("sh"));
+    // The exit status is ignored because the command already reported its own failure
+    std::process::Command::new(&shell)
+        .status()
+        .with_context(|| format!("Failed to run shell: {}", shell.to_string_lossy()))?;
+    Ok(())
+}
+
 fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
     mut app: App,
@@ -62,22 +191,53 @@ fn run_app<B: Backend>(
     let mut last_tick = Instant::now();
     loop {
         app.handle_worker_responses()?;
+        app.reload_settled_messages()?;
+        app.load_more_messages()?;
         terminal.draw(|f| ui(f, &mut app))?;
 
-        let timeout = tick_rate
+        let mut timeout = tick_rate
             .checked_sub(last_tick.elapsed())
             .unwrap_or_else(|| Duration::from_secs(0));
+        // Wake up when a pending reload is due instead of waiting for the next tick or key press
+        if let Some(reload_deadline) = app.reload_deadline {
+            timeout = timeout.min(reload_deadline.saturating_duration_since(Instant::now()));
+        }
         if event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
-                if key.code == KeyCode::Char('q') {
-                    return Ok(());
+                if key.code == KeyCode::Char('z') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    release_terminal(terminal, suspend)?;
+                    continue;
+                }
+
+                // The compose form receives every key press while it is open so that it can be
+                // typed into
+                if app.compose_form.is_some() {
+                    handle_compose_key(&mut app, key)?;
+                    continue;
+                }
+                if app.mailbox_finder.is_some() {
+                    handle_finder_key(&mut app, key)?;
+                    continue;
+                }
+                if app.command_line.is_some() {
+                    handle_command_key(&mut app, key)?;
+                    continue;
+                }
+
+                match key.code {
+                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('!') => {
+                        release_terminal(terminal, run_shell)?;
+                        continue;
+                    }
+                    _ => {}
                 }
 
                 handle_global_key(&mut app, key)?;
                 match app.active_pane {
                     Pane::Mailboxes => handle_mailbox_key(&mut app, key)?,
                     Pane::Messages => handle_message_key(&mut app, key)?,
-                };
+                }
             }
         }
         if last_tick.elapsed() >= tick_rate {
@@ -99,13 +259,100 @@ fn handle_global_key(app: &mut App, key: KeyEvent) -> Result<()> {
                 Pane::Mailboxes
             });
         }
-        KeyCode::Char('R') => {
-            app.update_mailboxes()?;
-            app.update_messages()?;
-        }
         KeyCode::Char('u') if control => app.toggle_active_state(State::Unread)?,
         KeyCode::Char('r') if control => app.toggle_active_state(State::Read)?,
         KeyCode::Char('a') if control => app.toggle_active_state(State::Archived)?,
+        KeyCode::Char('t') => app.retry_failed_requests()?,
+        KeyCode::Char('s') if !control => app.cycle_sort(),
+        KeyCode::Char('S') => app.toggle_reverse(),
+        KeyCode::Char('U') => app.toggle_unread_first(),
+        KeyCode::Char('c') if !control => app.open_compose_form(),
+        KeyCode::Char('f') if !control => app.open_mailbox_finder(),
+        KeyCode::Char(':') => app.open_command_line(),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Respond to keyboard presses while the compose form is open
+fn handle_compose_key(app: &mut App, key: KeyEvent) -> Result<()> {
+    let Some(form) = app.compose_form.as_mut() else {
+        return Ok(());
+    };
+    match key.code {
+        KeyCode::Esc => app.close_compose_form(),
+        KeyCode::Enter => app.submit_compose_form()?,
+        KeyCode::Tab | KeyCode::Down => form.next_field(),
+        KeyCode::BackTab | KeyCode::Up => form.previous_field(),
+        KeyCode::Left if form.focus == Field::State => form.cycle_state(false),
+        KeyCode::Right if form.focus == Field::State => form.cycle_state(true),
+        KeyCode::Backspace => form.backspace(),
+        KeyCode::Char(char) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+            form.input(char);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Respond to keyboard presses while the command line is open
+fn handle_command_key(app: &mut App, key: KeyEvent) -> Result<()> {
+    let Some(command_line) = app.command_line.as_mut() else {
+        return Ok(());
+    };
+    match key.code {
+        KeyCode::Esc => app.command_line = None,
+        KeyCode::Enter => app.submit_command_line()?,
+        KeyCode::Backspace => {
+            command_line.backspace();
+            app.preview_command()?;
+        }
+        KeyCode::Char(char) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+            command_line.input(char);
+            app.preview_command()?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Respond to keyboard presses while the mailbox finder is open
+fn handle_finder_key(app: &mut App, key: KeyEvent) -> Result<()> {
+    let Some(finder) = app.mailbox_finder.as_mut() else {
+        return Ok(());
+    };
+    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Esc => app.mailbox_finder = None,
+        KeyCode::Enter => {
+            let selected = finder.get_selected().cloned();
+            app.mailbox_finder = None;
+            if let Some(selected) = selected {
+                let old_active_mailbox = app
+                    .mailboxes
+                    .get_cursor_item()
+                    .map(|item| item.mailbox.clone());
+                let position = app
+                    .mailboxes
+                    .get_items()
+                    .iter()
+                    .position(|item| item.mailbox == selected);
+                // The mailbox may have disappeared while the finder was open
+                if position.is_some() {
+                    app.mailboxes.set_cursor(position);
+                    update_active_mailbox(app, old_active_mailbox)?;
+                }
+            }
+        }
+        KeyCode::Down | KeyCode::Tab => finder.move_cursor(true),
+        KeyCode::Up | KeyCode::BackTab => finder.move_cursor(false),
+        KeyCode::Char('j') if control => finder.move_cursor(true),
+        KeyCode::Char('k') if control => finder.move_cursor(false),
+        KeyCode::Backspace => finder.backspace(),
+        KeyCode::Char(char) if !control => finder.input(char),
         _ => {}
     }
 
@@ -140,6 +387,23 @@ fn handle_mailbox_key(app: &mut App, key: KeyEvent) -> Result<()> {
         KeyCode::Char('K') => {
             app.mailboxes.parent();
         }
+        KeyCode::Char('R') => {
+            app.update_mailboxes()?;
+            app.update_messages();
+            return Ok(());
+        }
+        KeyCode::Char('p') => {
+            app.toggle_pinned_mailbox();
+            return Ok(());
+        }
+        KeyCode::Char('[') => {
+            app.move_pinned_mailbox(true);
+            return Ok(());
+        }
+        KeyCode::Char(']') => {
+            app.move_pinned_mailbox(false);
+            return Ok(());
+        }
         KeyCode::Char('a') => {
             if let Some(active_mailbox) = old_active_mailbox {
                 app.set_mailbox_message_state(active_mailbox, State::Archived)?;
@@ -161,6 +425,12 @@ fn handle_mailbox_key(app: &mut App, key: KeyEvent) -> Result<()> {
         _ => return Ok(()),
     }
 
+    update_active_mailbox(app, old_active_mailbox)
+}
+
+// Update the messages list after the cursor in the mailboxes list moved away from the old active
+// mailbox
+fn update_active_mailbox(app: &mut App, old_active_mailbox: Option<Mailbox>) -> Result<()> {
     let active_mailbox = app.mailboxes.get_cursor_item().map(|item| &item.mailbox);
     if active_mailbox == old_active_mailbox.as_ref() {
         return Ok(());
@@ -170,9 +440,8 @@ fn handle_mailbox_key(app: &mut App, key: KeyEvent) -> Result<()> {
         // If the new active mailbox is a descendant of the old one or if there wasn't an old active mailbox, the
         // messages list can be optimistically updated by filtering against the new active mailbox instead of needing
         // to refresh the whole list
-        let local_update = old_active_mailbox.map_or(true, |old_active_mailbox| {
-            old_active_mailbox.is_ancestor_of(active_mailbox)
-        });
+        let local_update = old_active_mailbox
+            .is_none_or(|old_active_mailbox| old_active_mailbox.is_ancestor_of(active_mailbox));
 
         if local_update {
             // Optimistically update the messages list
@@ -183,7 +452,7 @@ fn handle_mailbox_key(app: &mut App, key: KeyEvent) -> Result<()> {
 
     // Update the mailboxes in case updating the messages list loads new messages that change the mailbox counts
     app.update_mailboxes()?;
-    app.update_messages()?;
+    app.update_messages();
 
     Ok(())
 }
@@ -226,10 +495,13 @@ fn handle_message_key(app: &mut App, key: KeyEvent) -> Result<()> {
             app.set_selected_message_states(State::Archived)?;
         }
         KeyCode::Char('x') if control => app.delete_selected_messages()?,
+        KeyCode::Char('o') => app.open_attachments()?,
+        KeyCode::Char('R') => app.remind_selected_messages()?,
         KeyCode::Enter => {
             if let Some(message) = app.messages.get_cursor_item() {
                 open_message(message);
             }
+            app.record_view()?;
         }
         _ => {}
     }
@@ -260,19 +532,52 @@ fn ui<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
     render_footer(frame, app, chunks[1]);
     render_mailboxes(frame, app, content_chunks[0]);
     render_messages(frame, app, content_chunks[1]);
+    let styles = Styles::new(&app.theme);
+    if let Some(form) = app.compose_form.as_ref() {
+        render_compose_form(frame, form, &styles, chunks[0]);
+    }
+    if let Some(finder) = app.mailbox_finder.as_mut() {
+        render_mailbox_finder(frame, finder, &styles, chunks[0]);
+    }
+    if app.theme.ascii {
+        frame.render_widget(AsciiBorders, frame_size);
+    }
+}
+
+// Replaces the box-drawing characters of the borders that have been rendered with ASCII ones
+struct AsciiBorders;
+
+impl Widget for AsciiBorders {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                let replacement = match cell.symbol.as_str() {
+                    "┌" | "┐" | "└" | "┘" => "+",
+                    "─" => "-",
+                    "│" => "|",
+                    _ => continue,
+                };
+                cell.set_symbol(replacement);
+            }
+        }
+    }
 }
 
 // Render the footer section of the UI
 fn render_footer<B: Backend>(frame: &mut Frame<B>, app: &App, area: Rect) {
-    const ACTIVE_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Green);
     const INACTIVE_STYLE: Style = Style::new();
-    const SELECTING_STYLE: Style = Style::new().fg(Color::LightBlue);
-    let footer = Paragraph::new(Line::from(vec![
+    let styles = Styles::new(&app.theme);
+    if let Some(command_line) = app.command_line.as_ref() {
+        render_command_line(frame, command_line, &styles, area);
+        return;
+    }
+    let mut spans = vec![
         Span::raw(" "),
         Span::styled(
             " unread ",
             if app.active_states.contains(&State::Unread) {
-                ACTIVE_STYLE
+                styles.active
             } else {
                 INACTIVE_STYLE
             },
@@ -281,7 +586,7 @@ fn render_footer<B: Backend>(frame: &mut Frame<B>, app: &App, area: Rect) {
         Span::styled(
             " read ",
             if app.active_states.contains(&State::Read) {
-                ACTIVE_STYLE
+                styles.active
             } else {
                 INACTIVE_STYLE
             },
@@ -290,50 +595,134 @@ fn render_footer<B: Backend>(frame: &mut Frame<B>, app: &App, area: Rect) {
         Span::styled(
             " archived ",
             if app.active_states.contains(&State::Archived) {
-                ACTIVE_STYLE
+                styles.active
             } else {
                 INACTIVE_STYLE
             },
         ),
         Span::raw("   "),
+        Span::raw(format!(
+            "sort: {}{}",
+            match app.sort {
+                None => "newest",
+                Some(Sort::Timestamp) => "timestamp",
+                Some(Sort::Mailbox) => "mailbox",
+                Some(Sort::State) => "state",
+                Some(Sort::LastViewed) => "last viewed",
+            },
+            if app.reverse { " (reversed)" } else { "" }
+        )),
+        Span::raw("   "),
         Span::styled(
             match app.messages.get_selection_mode() {
                 SelectionMode::None => "",
                 SelectionMode::Select => "selecting",
                 SelectionMode::Deselect => "deselecting",
             },
-            SELECTING_STYLE,
+            styles.accent,
         ),
-    ]));
+    ];
+    let hidden_selected = app.messages.get_hidden_selected_keys().len();
+    if hidden_selected > 0 {
+        spans.push(Span::raw("   "));
+        spans.push(Span::styled(
+            format!(
+                "{hidden_selected} hidden selected {}",
+                if hidden_selected == 1 {
+                    "message"
+                } else {
+                    "messages"
+                }
+            ),
+            styles.accent,
+        ));
+    }
+    if let Some(error) = app.error.as_ref() {
+        spans.push(Span::raw("   "));
+        spans.push(Span::styled(
+            format!(
+                "error: {} (press t to retry)",
+                format!("{error:#}").replace('\n', " ")
+            ),
+            styles.error,
+        ));
+    }
+    if let Some(warning) = app.warning.as_ref() {
+        spans.push(Span::raw("   "));
+        spans.push(Span::styled(format!("warning: {warning}"), styles.accent));
+    }
+    let footer = Paragraph::new(Line::from(spans));
     frame.render_widget(footer, area);
 }
 
+// Render the command line in place of the footer
+fn render_command_line<B: Backend>(
+    frame: &mut Frame<B>,
+    command_line: &CommandLine,
+    styles: &Styles,
+    area: Rect,
+) {
+    let mut spans = vec![Span::raw(format!(":{}", command_line.input))];
+    if let Some(error) = command_line.error.as_ref() {
+        spans.push(Span::raw("   "));
+        spans.push(Span::styled(error.replace('\n', " "), styles.error));
+    } else if let Some(count) = command_line.match_count {
+        spans.push(Span::raw("   "));
+        spans.push(Span::styled(
+            format!(
+                "{count} {} (press enter to change)",
+                if count == 1 {
+                    "message matches"
+                } else {
+                    "messages match"
+                }
+            ),
+            styles.accent,
+        ));
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
 // Render the mailboxes section of the UI
 fn render_mailboxes<B: Backend>(frame: &mut Frame<B>, app: &mut App, area: Rect) {
     const MAILBOX_STYLE: Style = Style::new();
-    const MAILBOX_BORDER_STYLE: Style = Style::new().fg(Color::LightBlue);
+    const PINNED_MAILBOX_STYLE: Style = Style::new().add_modifier(Modifier::BOLD);
     const MESSAGE_BORDER_STYLE: Style = Style::new();
-    const MAILBOX_HIGHLIGHT_STYLE: Style = Style::new()
-        .bg(Color::LightBlue)
-        .add_modifier(Modifier::BOLD);
+    let styles = Styles::new(&app.theme);
+    let now = Utc::now().naive_utc();
     let mailboxes = app
         .mailboxes
         .get_items()
         .iter()
         .map(|mailbox| {
-            ListItem::new(Span::styled(
+            let mut spans = vec![Span::styled(
                 format!(
                     "{}{} ({})",
                     " ".repeat(mailbox.depth),
                     mailbox.mailbox.get_leaf_name(),
                     mailbox.message_count
                 ),
-                MAILBOX_STYLE,
-            ))
+                if app.pinned_mailboxes.is_pinned(&mailbox.mailbox) {
+                    PINNED_MAILBOX_STYLE
+                } else {
+                    MAILBOX_STYLE
+                },
+            )];
+            if let Some(heartbeat) = app.heartbeats.get(&mailbox.mailbox) {
+                spans.push(Span::styled(
+                    format!(" {}", heartbeat.describe(now)),
+                    if heartbeat.is_overdue(now) {
+                        styles.error
+                    } else {
+                        styles.timestamp
+                    },
+                ));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect::<Vec<_>>();
     let border_style = match app.active_pane {
-        Pane::Mailboxes => MAILBOX_BORDER_STYLE,
+        Pane::Mailboxes => styles.border,
         Pane::Messages => MESSAGE_BORDER_STYLE,
     };
     let mailboxes_list = List::new(mailboxes)
@@ -349,50 +738,73 @@ fn render_mailboxes<B: Backend>(frame: &mut Frame<B>, app: &mut App, area: Rect)
                     app.mailboxes.get_items().len()
                 )),
         )
-        .highlight_style(MAILBOX_HIGHLIGHT_STYLE);
+        .highlight_style(styles.highlight);
     frame.render_stateful_widget(mailboxes_list, area, app.mailboxes.get_list_state());
 }
 
 // Render the messages section of the UI
 fn render_messages<B: Backend>(frame: &mut Frame<B>, app: &mut App, area: Rect) {
     const BULLET_STYLE: Style = Style::new().add_modifier(Modifier::BOLD);
-    const UNREAD_STYLE: Style = Style::new().fg(Color::Red).add_modifier(Modifier::BOLD);
-    const TIMESTAMP_STYLE: Style = Style::new().fg(Color::Yellow);
-    const MESSAGE_BORDER_STYLE: Style = Style::new().fg(Color::LightBlue);
     const MAILBOX_BORDER_STYLE: Style = Style::new();
-    const HIGHLIGHT_STYLE: Style = Style::new()
-        .bg(Color::LightBlue)
-        .add_modifier(Modifier::BOLD);
+    let styles = Styles::new(&app.theme);
+    let theme = &app.theme;
     let messages = app
         .messages
         .iter_items_with_selected()
         .map(|(message, selected)| {
             let active_marker = if selected {
-                Span::styled("• ", BULLET_STYLE)
+                Span::styled(format!("{} ", theme.cursor_glyph()), BULLET_STYLE)
             } else {
                 Span::raw("  ")
             };
-            let state_marker = match message.state {
-                State::Unread => Span::styled("* ", UNREAD_STYLE),
-                State::Read => Span::raw("  "),
-                State::Archived => Span::raw("- "),
-            };
+            let state_marker = Span::styled(
+                format!("{} ", theme.state_glyph(message.state)),
+                if message.state == State::Unread {
+                    styles.unread
+                } else {
+                    Style::new()
+                },
+            );
             let timestamp = HumanTime::from(
                 message
                     .timestamp
                     .signed_duration_since(Utc::now().naive_utc()),
             )
             .to_string();
-            ListItem::new(Line::from(vec![
+            let mut spans = vec![
                 active_marker,
                 state_marker,
-                Span::raw(message.content.clone()),
-                Span::styled(format!(" @ {timestamp}"), TIMESTAMP_STYLE),
-            ]))
+                // The terminal UI can't render escape sequences inside of spans
+                Span::raw(strip_ansi(&message.content)),
+                Span::styled(format!(" @ {timestamp}"), styles.timestamp),
+            ];
+            if !message.related_ids.is_empty() {
+                // Show the content of linked messages that are loaded and the ids of the rest
+                let related = message
+                    .related_ids
+                    .iter()
+                    .map(|id| {
+                        app.messages
+                            .get_items()
+                            .iter()
+                            .find(|related| related.id == *id)
+                            .map_or_else(
+                                || format!("#{id}"),
+                                |related| strip_ansi(&related.content),
+                            )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                spans.push(Span::styled(
+                    format!(" {} {related}", theme.symbol("↔", "<->")),
+                    styles.related,
+                ));
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect::<Vec<_>>();
     let border_style = match app.active_pane {
-        Pane::Messages => MESSAGE_BORDER_STYLE,
+        Pane::Messages => styles.border,
         Pane::Mailboxes => MAILBOX_BORDER_STYLE,
     };
     let messages_list = List::new(messages)
@@ -401,24 +813,115 @@ fn render_messages<B: Backend>(frame: &mut Frame<B>, app: &mut App, area: Rect)
                 .borders(Borders::ALL)
                 .border_style(border_style)
                 .title(format!(
-                    "Messages ({}{})",
+                    "Messages ({}{}{})",
                     app.messages
                         .get_cursor()
                         .map_or_else(String::new, |index| format!("{}/", index + 1)),
-                    app.messages.get_items().len()
+                    app.messages.get_items().len(),
+                    if app.has_more_messages { "+" } else { "" }
                 )),
         )
-        .highlight_style(HIGHLIGHT_STYLE);
+        .highlight_style(styles.highlight);
     frame.render_stateful_widget(messages_list, area, app.messages.get_list_state());
 }
 
-// If the message contains a URL, open it in a web browser
-fn open_message(message: &Message) {
-    let mut finder = LinkFinder::new();
-    finder.kinds(&[LinkKind::Url]);
-
-    if let Some(link) = finder.links(&message.content).next() {
-        // Silently ignore errors if the URL couldn't be opened
-        let _ = webbrowser::open(link.as_str());
+// Render the compose form in a popup over the center of the area
+fn render_compose_form<B: Backend>(
+    frame: &mut Frame<B>,
+    form: &ComposeForm,
+    styles: &Styles,
+    area: Rect,
+) {
+    const LABEL_STYLE: Style = Style::new().add_modifier(Modifier::BOLD);
+    let field_style = |field: Field| {
+        if form.focus == field {
+            styles.highlight
+        } else {
+            Style::new()
+        }
+    };
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("Mailbox: ", LABEL_STYLE),
+            Span::styled(form.mailbox.as_str(), field_style(Field::Mailbox)),
+        ]),
+        Line::from(vec![
+            Span::styled("State:   ", LABEL_STYLE),
+            Span::styled(format!("< {} >", form.state), field_style(Field::State)),
+        ]),
+        Line::from(vec![
+            Span::styled("Content: ", LABEL_STYLE),
+            Span::styled(form.content.as_str(), field_style(Field::Content)),
+        ]),
+        Line::from(""),
+    ];
+    if let Some(error) = form.error.as_ref() {
+        lines.push(Line::from(Span::styled(
+            format!("error: {error}"),
+            styles.error,
+        )));
     }
+    lines.push(Line::from(Span::styled(
+        "tab: next field  left/right: change state  enter: add  esc: cancel",
+        styles.help,
+    )));
+
+    let width = area.width.min(80);
+    // Leave room for the lines and the borders
+    let height = area
+        .height
+        .min(u16::try_from(lines.len()).unwrap_or(u16::MAX) + 2);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(styles.border)
+            .title("New message"),
+    );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(paragraph, popup);
+}
+
+// Render the mailbox finder in a popup over the top of the area
+fn render_mailbox_finder<B: Backend>(
+    frame: &mut Frame<B>,
+    finder: &mut MailboxFinder,
+    styles: &Styles,
+    area: Rect,
+) {
+    let width = area.width.min(60);
+    let height = area.height.min(20);
+    let popup = Rect::new(area.x + (area.width - width) / 2, area.y, width, height);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(popup);
+
+    let input = Paragraph::new(format!("> {}", finder.query)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(styles.border)
+            .title("Go to mailbox"),
+    );
+    let matches = finder
+        .matches
+        .iter()
+        .map(|mailbox| ListItem::new(mailbox.to_string()))
+        .collect::<Vec<_>>();
+    let matches_list = List::new(matches)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(styles.border)
+                .title(format!("Matches ({})", finder.matches.len())),
+        )
+        .highlight_style(styles.highlight);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(input, chunks[0]);
+    frame.render_stateful_widget(matches_list, chunks[1], finder.get_list_state());
 }
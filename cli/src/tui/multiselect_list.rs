@@ -24,6 +24,10 @@ where
 
     // Represents whether moving the cursor selects or deselects items
     selection_mode: SelectionMode,
+
+    // Whether items stay selected when replace_items removes them from the list so that they are
+    // selected again if they return
+    persistent_selection: bool,
 }
 
 impl<Item> NavigableList<Item> for MultiselectList<Item>
@@ -78,9 +82,15 @@ where
             items: vec![],
             selected_items: HashSet::new(),
             selection_mode: SelectionMode::None,
+            persistent_selection: false,
         }
     }
 
+    // Set whether items stay selected while they aren't in the list
+    pub fn set_persistent_selection(&mut self, persistent_selection: bool) {
+        self.persistent_selection = persistent_selection;
+    }
+
     // Return a reference to the list state
     pub fn get_list_state(&mut self) -> &mut ListState {
         &mut self.state
@@ -115,18 +125,35 @@ where
 
         NavigableList::replace_items(self, items);
 
-        // Restore the selected state of previously selected items
-        self.selected_items = self
-            .items
-            .iter()
-            .map(Keyed::get_key)
-            .filter(|id| old_selected_items.contains(id))
-            .collect();
+        // Restore the selected state of previously selected items, forgetting the items that were
+        // removed unless the selection is persistent
+        if !self.persistent_selection {
+            self.selected_items = self
+                .items
+                .iter()
+                .map(Keyed::get_key)
+                .filter(|id| old_selected_items.contains(id))
+                .collect();
+        }
 
         // Restore the selection mode
         self.selection_mode = selection_mode;
     }
 
+    // Add items to the end of the list without moving the cursor, skipping items already in the list
+    pub fn append_items(&mut self, items: Vec<Item>) {
+        let existing_keys = self
+            .items
+            .iter()
+            .map(Keyed::get_key)
+            .collect::<HashSet<_>>();
+        self.items.extend(
+            items
+                .into_iter()
+                .filter(|item| !existing_keys.contains(&item.get_key())),
+        );
+    }
+
     // Determine whether an item is selected by its key
     pub fn get_item_selected(&self, key: u64) -> bool {
         self.selected_items.contains(&key)
@@ -149,9 +176,15 @@ where
     }
 
     // Set the selected state of all items
+    // Selecting all items keeps the hidden selected items when the selection is persistent, and
+    // deselecting all items always deselects them.
     pub fn set_all_selected(&mut self, new_selected: bool) {
         if new_selected {
-            self.selected_items = self.items.iter().map(Keyed::get_key).collect();
+            if !self.persistent_selection {
+                self.selected_items.clear();
+            }
+            self.selected_items
+                .extend(self.items.iter().map(Keyed::get_key));
         } else {
             self.selected_items.clear();
         }
@@ -163,6 +196,30 @@ where
             .iter()
             .filter(|item| self.get_item_selected(item.get_key()))
     }
+
+    // Return the sorted keys of the selected items that aren't in the list
+    pub fn get_hidden_selected_keys(&self) -> Vec<u64> {
+        let visible_keys = self
+            .items
+            .iter()
+            .map(Keyed::get_key)
+            .collect::<HashSet<_>>();
+        let mut keys = self
+            .selected_items
+            .iter()
+            .copied()
+            .filter(|key| !visible_keys.contains(key))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    // Deselect the selected items that aren't in the list
+    pub fn deselect_hidden(&mut self) {
+        for key in self.get_hidden_selected_keys() {
+            self.selected_items.remove(&key);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -279,6 +336,51 @@ mod tests {
         assert_eq!(list.get_selected_items().count(), 0);
     }
 
+    #[test]
+    fn test_replace_items_persistent_selection() {
+        let mut list = get_sized_list(5);
+        list.set_persistent_selection(true);
+        list.set_item_selected(1, true);
+        list.set_item_selected(2, true);
+        list.replace_items(vec![0, 1, 3]);
+        assert_eq!(
+            list.get_selected_items().copied().collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(list.get_hidden_selected_keys(), vec![2]);
+
+        // Hidden items are selected again when they return to the list
+        list.replace_items(vec![2, 4]);
+        assert_eq!(
+            list.get_selected_items().copied().collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(list.get_hidden_selected_keys(), vec![1]);
+
+        list.set_all_selected(true);
+        assert_eq!(list.get_hidden_selected_keys(), vec![1]);
+        list.deselect_hidden();
+        assert!(list.get_hidden_selected_keys().is_empty());
+        assert_eq!(
+            list.get_selected_items().copied().collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+    }
+
+    #[test]
+    fn test_append_items() {
+        let mut list = get_sized_list(3);
+        list.set_cursor(Some(1));
+        list.set_item_selected(2, true);
+        list.append_items(vec![2, 3, 4]);
+        assert_eq!(list.get_items(), &vec![0, 1, 2, 3, 4]);
+        assert_eq!(list.get_cursor(), Some(1));
+        assert_eq!(
+            list.get_selected_items().copied().collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
     #[test]
     fn test_toggle_cursor_selected() {
         let mut list = get_sized_list(5);
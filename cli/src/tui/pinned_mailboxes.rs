@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use database::Mailbox;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// The mailboxes that the TUI lists before their siblings, in the order that they are listed
+// They are saved to a small state file so that they persist between sessions.
+#[derive(Debug, Default)]
+pub struct PinnedMailboxes {
+    // The file that the pinned mailboxes are saved to, or None to not save them
+    path: Option<PathBuf>,
+    mailboxes: Vec<Mailbox>,
+}
+
+// Return the name of a mailbox's parent, or None for root mailboxes
+fn get_parent(mailbox: &Mailbox) -> Option<&str> {
+    mailbox.as_ref().rsplit_once('/').map(|(parent, _)| parent)
+}
+
+impl PinnedMailboxes {
+    // Load the pinned mailboxes from a state file, which doesn't need to exist yet
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mailboxes = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).with_context(|| {
+                format!("Failed to parse pinned mailboxes file {}", path.display())
+            })?,
+            Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("Failed to read pinned mailboxes file {}", path.display())
+                })
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            mailboxes,
+        })
+    }
+
+    // Save the pinned mailboxes to the state file
+    fn save(&self) -> Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context("Couldn't create data directory")?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&self.mailboxes)?)
+            .with_context(|| format!("Failed to write pinned mailboxes file {}", path.display()))
+    }
+
+    // Determine whether a mailbox is pinned
+    pub fn is_pinned(&self, mailbox: &Mailbox) -> bool {
+        self.mailboxes.contains(mailbox)
+    }
+
+    // Pin a mailbox after the other pinned mailboxes, or unpin it if it is already pinned
+    pub fn toggle(&mut self, mailbox: &Mailbox) -> Result<()> {
+        if let Some(index) = self.mailboxes.iter().position(|pinned| pinned == mailbox) {
+            self.mailboxes.remove(index);
+        } else {
+            self.mailboxes.push(mailbox.clone());
+        }
+        self.save()
+    }
+
+    // Swap a pinned mailbox with the previous or next pinned sibling, which are the only pinned
+    // mailboxes that it is listed next to
+    pub fn move_pin(&mut self, mailbox: &Mailbox, up: bool) -> Result<()> {
+        let Some(index) = self.mailboxes.iter().position(|pinned| pinned == mailbox) else {
+            return Ok(());
+        };
+        let parent = get_parent(mailbox);
+        let is_sibling = |pinned: &&Mailbox| get_parent(pinned) == parent;
+        let sibling = if up {
+            self.mailboxes[..index]
+                .iter()
+                .rposition(|pinned| is_sibling(&pinned))
+        } else {
+            self.mailboxes[index + 1..]
+                .iter()
+                .position(|pinned| is_sibling(&pinned))
+                .map(|position| index + 1 + position)
+        };
+        if let Some(sibling) = sibling {
+            self.mailboxes.swap(index, sibling);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    // Generate the key that mailboxes are sorted by
+    // Each section of the mailbox name is sorted by pinned order first and name second, so pinned
+    // mailboxes are listed before their siblings and descendants stay under their parent.
+    pub fn get_sort_key(&self, mailbox: &Mailbox) -> Vec<(usize, String)> {
+        mailbox
+            .iter_ancestors()
+            .map(|ancestor| {
+                let rank = self
+                    .mailboxes
+                    .iter()
+                    .position(|pinned| pinned == &ancestor)
+                    .unwrap_or(usize::MAX);
+                (rank, ancestor.get_leaf_name().to_owned())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Create unsaved pinned mailboxes from a list of names
+    fn make_pinned(names: &[&str]) -> Result<PinnedMailboxes> {
+        Ok(PinnedMailboxes {
+            path: None,
+            mailboxes: names
+                .iter()
+                .map(|name| Mailbox::try_from(*name))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    #[test]
+    fn test_toggle() -> Result<()> {
+        let mut pinned = make_pinned(&["a"])?;
+        let mailbox = "b".try_into()?;
+        pinned.toggle(&mailbox)?;
+        assert!(pinned.is_pinned(&mailbox));
+        pinned.toggle(&mailbox)?;
+        assert!(!pinned.is_pinned(&mailbox));
+        assert!(pinned.is_pinned(&"a".try_into()?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_pin() -> Result<()> {
+        let mut pinned = make_pinned(&["a", "c/d", "b", "c/e"])?;
+        pinned.move_pin(&"b".try_into()?, true)?;
+        assert_eq!(
+            pinned.mailboxes,
+            make_pinned(&["b", "c/d", "a", "c/e"])?.mailboxes
+        );
+        pinned.move_pin(&"c/d".try_into()?, false)?;
+        assert_eq!(
+            pinned.mailboxes,
+            make_pinned(&["b", "c/e", "a", "c/d"])?.mailboxes
+        );
+        // Moving past the first or last sibling does nothing
+        pinned.move_pin(&"b".try_into()?, true)?;
+        pinned.move_pin(&"c/d".try_into()?, false)?;
+        assert_eq!(
+            pinned.mailboxes,
+            make_pinned(&["b", "c/e", "a", "c/d"])?.mailboxes
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_sort_key() -> Result<()> {
+        let pinned = make_pinned(&["b", "a/c"])?;
+        assert_eq!(
+            pinned.get_sort_key(&"a/c/d".try_into()?),
+            vec![
+                (usize::MAX, String::from("a")),
+                (1, String::from("c")),
+                (usize::MAX, String::from("d")),
+            ]
+        );
+        assert_eq!(
+            pinned.get_sort_key(&"b".try_into()?),
+            vec![(0, String::from("b"))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_and_save() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("mailbox-pinned-{}", std::process::id()))
+            .join("pinned-mailboxes.json");
+        let _ = fs::remove_file(&path);
+        let mut pinned = PinnedMailboxes::load(path.clone())?;
+        assert!(pinned.mailboxes.is_empty());
+        pinned.toggle(&"a".try_into()?)?;
+        pinned.toggle(&"b".try_into()?)?;
+        let loaded = PinnedMailboxes::load(path.clone());
+        fs::remove_file(&path)?;
+        assert_eq!(loaded?.mailboxes, pinned.mailboxes);
+        Ok(())
+    }
+}
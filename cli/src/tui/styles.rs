@@ -0,0 +1,86 @@
+use crate::theme::{Colors, Theme};
+use ratatui::style::{Color, Modifier, Style};
+
+// The styles of the parts of the TUI, which are derived from the theme so that the TUI uses the
+// same palette as printed messages and falls back to modifiers when colors are disabled
+pub struct Styles {
+    pub unread: Style,
+    pub timestamp: Style,
+    pub related: Style,
+    pub error: Style,
+    pub help: Style,
+    pub accent: Style,
+    // The border of the active pane and of popups
+    pub border: Style,
+    // The item under the cursor and the focused form field
+    pub highlight: Style,
+    // The enabled state filters in the footer
+    pub active: Style,
+}
+
+impl Styles {
+    pub fn new(theme: &Theme) -> Self {
+        let Some(colors) = theme.colors() else {
+            let reversed = Style::new().add_modifier(Modifier::REVERSED);
+            return Self {
+                unread: Style::new().add_modifier(Modifier::BOLD),
+                timestamp: Style::new(),
+                related: Style::new(),
+                error: Style::new().add_modifier(Modifier::BOLD),
+                help: Style::new(),
+                accent: Style::new(),
+                border: Style::new().add_modifier(Modifier::BOLD),
+                highlight: reversed.add_modifier(Modifier::BOLD),
+                active: reversed,
+            };
+        };
+        let fg = |color| Style::new().fg(convert(color));
+        let Colors {
+            unread,
+            timestamp,
+            active,
+            accent,
+            related,
+            error,
+            muted,
+            ..
+        } = colors;
+        Self {
+            unread: fg(unread).add_modifier(Modifier::BOLD),
+            timestamp: fg(timestamp),
+            related: fg(related),
+            error: fg(error),
+            help: fg(muted),
+            accent: fg(accent),
+            border: fg(accent),
+            highlight: Style::new()
+                .fg(Color::Black)
+                .bg(convert(accent))
+                .add_modifier(Modifier::BOLD),
+            active: Style::new().fg(Color::Black).bg(convert(active)),
+        }
+    }
+}
+
+// Convert a palette color to the equivalent terminal UI color
+const fn convert(color: colored::Color) -> Color {
+    match color {
+        colored::Color::Black => Color::Black,
+        colored::Color::Red => Color::Red,
+        colored::Color::Green => Color::Green,
+        colored::Color::Yellow => Color::Yellow,
+        colored::Color::Blue => Color::Blue,
+        colored::Color::Magenta => Color::Magenta,
+        colored::Color::Cyan => Color::Cyan,
+        colored::Color::White => Color::Gray,
+        colored::Color::BrightBlack => Color::DarkGray,
+        colored::Color::BrightRed => Color::LightRed,
+        colored::Color::BrightGreen => Color::LightGreen,
+        colored::Color::BrightYellow => Color::LightYellow,
+        colored::Color::BrightBlue => Color::LightBlue,
+        colored::Color::BrightMagenta => Color::LightMagenta,
+        colored::Color::BrightCyan => Color::LightCyan,
+        colored::Color::BrightWhite => Color::White,
+        colored::Color::TrueColor { r, g, b } => Color::Rgb(r, g, b),
+    }
+}
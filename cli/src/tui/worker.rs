@@ -1,88 +1,330 @@
 use super::monotonic_counter::MonotonicCounter;
-use database::{Backend, Database, Filter, MailboxInfo, Message, State};
+use crate::attachments::open_attachment;
+use anyhow::Result;
+use database::{Backend, Database, Filter, Heartbeat, Id, MailboxInfo, Message, NewMessage, State};
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::{self, channel};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
 
+#[derive(Clone)]
 pub enum Request {
     LoadMessages(Filter),
+    // Load the next page of messages to append to the loaded messages
+    LoadMoreMessages(Filter),
     LoadMailboxes(Filter),
+    LoadHeartbeats,
+    // Count the messages that match a filter
+    CountMessages(Filter),
     ChangeMessageStates {
         filter: Filter,
         new_state: State,
-        // This response will be sent after the message states have been changed
-        response: Option<Response>,
+        // Whether a refresh response will be sent after the message states have been changed
+        refresh: bool,
+        // The loaded messages that were optimistically changed, which the backend must change too
+        optimistic: Vec<Id>,
     },
     DeleteMessages {
         filter: Filter,
-        // This response will be sent after the messages have been deleted
-        response: Option<Response>,
+        // Whether a refresh response will be sent after the messages have been deleted
+        refresh: bool,
+        // The loaded messages that were optimistically deleted, which the backend must delete too
+        optimistic: Vec<Id>,
     },
+    // Add a new message and then send a refresh response
+    AddMessage(NewMessage),
+    // Load a message's attachments and open them
+    OpenAttachments(Id),
+    // Record that a message was opened
+    RecordView(Id),
+    // Schedule reminders for messages and then send a refresh response
+    RemindMessages {
+        ids: Vec<Id>,
+        delay: Duration,
+    },
+}
+
+// The kinds of load requests that newer requests of the same kind supersede
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+enum LoadKind {
+    Messages,
+    Mailboxes,
+    Counts,
+}
+
+impl Request {
+    // Return the kind of load that the request performs, if it can be superseded
+    const fn load_kind(&self) -> Option<LoadKind> {
+        match self {
+            Self::LoadMessages(_) | Self::LoadMoreMessages(_) => Some(LoadKind::Messages),
+            Self::LoadMailboxes(_) => Some(LoadKind::Mailboxes),
+            Self::CountMessages(_) => Some(LoadKind::Counts),
+            _ => None,
+        }
+    }
+
+    // Return true if the request makes in-progress loads of the same kind stale
+    // Loading more messages appends to the loaded messages, so it doesn't make a pending reload
+    // stale, but a reload does make pending appends stale.
+    const fn supersedes(&self) -> bool {
+        !matches!(self, Self::LoadMoreMessages(_))
+    }
 }
 
 pub enum Response {
     LoadMessages(Vec<Message>),
+    LoadMoreMessages(Vec<Message>),
     LoadMailboxes(Vec<MailboxInfo>),
+    LoadHeartbeats(Vec<Heartbeat>),
+    CountMessages(usize),
     Refresh,
+    // A mutation didn't change this many of the messages that were optimistically changed,
+    // probably because another client changed them first
+    Conflict(usize),
+    // The request failed, and the failed request is included so that it can be retried
+    Error(anyhow::Error, Box<Request>),
 }
 
 pub type Sender = mpsc::Sender<Request>;
 pub type Receiver = mpsc::Receiver<Response>;
 
+// Counters used to discard the responses of stale load requests that finished before they could be
+// cancelled
+#[derive(Clone)]
+struct Counters {
+    messages: MonotonicCounter,
+    mailboxes: MonotonicCounter,
+    counts: MonotonicCounter,
+}
+
+// Count the optimistically changed messages that the backend didn't actually change
+fn count_conflicts(optimistic: &[Id], changed: &[Message]) -> usize {
+    let changed = changed
+        .iter()
+        .map(|message| message.id)
+        .collect::<HashSet<_>>();
+    optimistic.iter().filter(|id| !changed.contains(id)).count()
+}
+
+// Send the response to a mutation, which reports a conflict instead of a refresh if the backend
+// didn't change every optimistically changed message
+fn send_mutation_response(
+    tx_res: &mpsc::Sender<Response>,
+    optimistic: &[Id],
+    changed: &[Message],
+    refresh: bool,
+) {
+    let conflicts = count_conflicts(optimistic, changed);
+    if conflicts > 0 {
+        let _ = tx_res.send(Response::Conflict(conflicts));
+    } else if refresh {
+        let _ = tx_res.send(Response::Refresh);
+    }
+}
+
+// Run the database query for a single request, sending the response if there is one
+async fn handle_request<B: Backend>(
+    db: &Database<B>,
+    req: Request,
+    tx_res: &mpsc::Sender<Response>,
+    counters: &Counters,
+) -> Result<()> {
+    // Sending only fails if the app has exited, in which case the response is no longer needed
+    match req {
+        Request::LoadMessages(filter) => {
+            let req_id = counters.messages.next();
+            let messages = db.load_messages(filter).await;
+            // Only use these messages if there aren't any fresher load requests in progress
+            if counters.messages.last() == req_id {
+                let _ = tx_res.send(Response::LoadMessages(messages?));
+            }
+        }
+        Request::LoadMoreMessages(filter) => {
+            // Don't advance the counter so that loading more messages doesn't make a pending reload stale
+            let req_id = counters.messages.last();
+            let messages = db.load_messages(filter).await;
+            // Only append these messages if the messages haven't been reloaded since
+            if counters.messages.last() == req_id {
+                let _ = tx_res.send(Response::LoadMoreMessages(messages?));
+            }
+        }
+        Request::LoadMailboxes(filter) => {
+            let req_id = counters.mailboxes.next();
+            let mailboxes = db.load_mailboxes(filter).await;
+            // Only use these mailboxes if there aren't any fresher load requests in progress
+            if counters.mailboxes.last() == req_id {
+                let _ = tx_res.send(Response::LoadMailboxes(mailboxes?));
+            }
+        }
+        Request::LoadHeartbeats => {
+            let _ = tx_res.send(Response::LoadHeartbeats(db.load_heartbeats().await?));
+        }
+        Request::CountMessages(filter) => {
+            let req_id = counters.counts.next();
+            let mailboxes = db.load_mailboxes(filter).await;
+            // Only use this count if the filter hasn't changed since
+            if counters.counts.last() == req_id {
+                let count = mailboxes?.iter().map(|info| info.message_count).sum();
+                let _ = tx_res.send(Response::CountMessages(count));
+            }
+        }
+        Request::ChangeMessageStates {
+            filter,
+            new_state,
+            refresh,
+            optimistic,
+        } => {
+            let changed = db.change_state(filter, new_state).await?;
+            send_mutation_response(tx_res, &optimistic, &changed, refresh);
+        }
+        Request::DeleteMessages {
+            filter,
+            refresh,
+            optimistic,
+        } => {
+            let deleted = db.delete_messages(filter).await?;
+            send_mutation_response(tx_res, &optimistic, &deleted, refresh);
+        }
+        Request::AddMessage(message) => {
+            db.add_messages(vec![message]).await?;
+            let _ = tx_res.send(Response::Refresh);
+        }
+        Request::OpenAttachments(id) => {
+            for attachment in db.load_attachments(id).await? {
+                open_attachment(id, &attachment)?;
+            }
+        }
+        Request::RecordView(id) => {
+            db.record_view(id).await?;
+        }
+        Request::RemindMessages { ids, delay } => {
+            for id in ids {
+                db.remind_message(id, delay).await?;
+            }
+            let _ = tx_res.send(Response::Refresh);
+        }
+    }
+    Ok(())
+}
+
+// The tasks running load requests, which are aborted when a newer request supersedes them
+// Aborting a task drops the backend future, which stops its SQL query or HTTP request.
+#[derive(Default)]
+struct InFlightLoads {
+    tasks: HashMap<LoadKind, Vec<JoinHandle<()>>>,
+}
+
+impl InFlightLoads {
+    // Abort the tasks that a new load request supersedes and forget the tasks that have finished
+    fn cancel_superseded(&mut self, kind: LoadKind, supersedes: bool) {
+        let tasks = self.tasks.entry(kind).or_default();
+        if supersedes {
+            for task in tasks.drain(..) {
+                task.abort();
+            }
+        } else {
+            tasks.retain(|task| !task.is_finished());
+        }
+    }
+
+    // Track a new load task so that newer requests can cancel it
+    fn track(&mut self, kind: LoadKind, task: JoinHandle<()>) {
+        self.tasks.entry(kind).or_default().push(task);
+    }
+}
+
 // Spawn a worker for asynchronously interacting with the database
 // It receives requests from a channel, runs the corresponding database query asynchronously,
-// and when the response is ready, sends it on another channel
+// and when the response is ready, sends it on another channel. Failed requests are sent back as
+// error responses instead of stopping the worker. Load requests that are superseded by a newer
+// request of the same kind are cancelled without a response.
 pub fn spawn<B: Backend + Send + Sync + 'static>(db: Arc<Database<B>>) -> (Sender, Receiver) {
     let (tx_req, rx_req) = channel::<Request>();
     let (tx_res, rx_res) = channel::<Response>();
 
     let handle = Handle::current();
-    let message_counter = MonotonicCounter::new();
-    let mailbox_counter = MonotonicCounter::new();
-    thread::spawn(move || loop {
-        let Ok(req) = rx_req.recv() else { break };
-        let tx_res = tx_res.clone();
-        let db = Arc::clone(&db);
-        let message_counter = message_counter.clone();
-        let mailbox_counter = mailbox_counter.clone();
-        handle.spawn(async move {
-            match req {
-                Request::LoadMessages(filter) => {
-                    let req_id = message_counter.next();
-                    let messages = db.load_messages(filter).await.unwrap();
-                    // Only use these messages if there aren't any fresher load requests in progress
-                    if message_counter.last() == req_id {
-                        tx_res.send(Response::LoadMessages(messages)).unwrap();
-                    }
-                }
-                Request::LoadMailboxes(filter) => {
-                    let req_id = mailbox_counter.next();
-                    let mailboxes = db.load_mailboxes(filter).await.unwrap();
-                    // Only use these mailboxes if there aren't any fresher load requests in progress
-                    if mailbox_counter.last() == req_id {
-                        tx_res.send(Response::LoadMailboxes(mailboxes)).unwrap();
-                    }
-                }
-                Request::ChangeMessageStates {
-                    filter,
-                    new_state,
-                    response,
-                } => {
-                    db.change_state(filter, new_state).await.unwrap();
-                    if let Some(response) = response {
-                        tx_res.send(response).unwrap();
-                    }
-                }
-                Request::DeleteMessages { filter, response } => {
-                    db.delete_messages(filter).await.unwrap();
-                    if let Some(response) = response {
-                        tx_res.send(response).unwrap();
-                    }
+    let counters = Counters {
+        messages: MonotonicCounter::new(),
+        mailboxes: MonotonicCounter::new(),
+        counts: MonotonicCounter::new(),
+    };
+    thread::spawn(move || {
+        let mut in_flight = InFlightLoads::default();
+        while let Ok(req) = rx_req.recv() {
+            let load_kind = req.load_kind();
+            if let Some(kind) = load_kind {
+                in_flight.cancel_superseded(kind, req.supersedes());
+            }
+
+            let tx_res = tx_res.clone();
+            let db = Arc::clone(&db);
+            let counters = counters.clone();
+            let task = handle.spawn(async move {
+                let retry = req.clone();
+                if let Err(err) = handle_request(&db, req, &tx_res, &counters).await {
+                    let _ = tx_res.send(Response::Error(err, Box::new(retry)));
                 }
+            });
+            if let Some(kind) = load_kind {
+                in_flight.track(kind, task);
             }
-        });
+        }
     });
 
     (tx_req, rx_res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    // Spawn a task that never finishes and a receiver that fails once the task is aborted
+    fn spawn_pending() -> (JoinHandle<()>, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+        (task, rx)
+    }
+
+    #[tokio::test]
+    async fn test_cancel_superseded() {
+        let mut in_flight = InFlightLoads::default();
+        let (reload, reload_rx) = spawn_pending();
+        in_flight.track(LoadKind::Messages, reload);
+        let (mailboxes, mut mailboxes_rx) = spawn_pending();
+        in_flight.track(LoadKind::Mailboxes, mailboxes);
+
+        // Loading more messages doesn't cancel a pending reload
+        in_flight.cancel_superseded(
+            LoadKind::Messages,
+            Request::LoadMoreMessages(Filter::new()).supersedes(),
+        );
+        assert_eq!(in_flight.tasks[&LoadKind::Messages].len(), 1);
+
+        in_flight.cancel_superseded(
+            LoadKind::Messages,
+            Request::LoadMessages(Filter::new()).supersedes(),
+        );
+        assert!(reload_rx.await.is_err());
+        assert!(in_flight.tasks[&LoadKind::Messages].is_empty());
+
+        // Loads of other kinds are unaffected
+        assert_eq!(
+            mailboxes_rx.try_recv(),
+            Err(oneshot::error::TryRecvError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_load_kind() {
+        assert!(Request::LoadMailboxes(Filter::new()).load_kind() == Some(LoadKind::Mailboxes));
+        assert!(Request::LoadMoreMessages(Filter::new()).load_kind() == Some(LoadKind::Messages));
+        assert!(Request::LoadHeartbeats.load_kind().is_none());
+    }
+}
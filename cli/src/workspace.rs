@@ -0,0 +1,128 @@
+use crate::cli::parse_workspace;
+use anyhow::{Context, Result};
+use std::fs::read_dir;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// The workspace that is used when none is chosen, whose database is mailbox.db in the data directory
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+// The directory in the data directory that contains the databases of the named workspaces
+const WORKSPACES_DIR: &str = "workspaces";
+
+// Return the path of a workspace's database in the data directory
+pub fn get_workspace_db_path(data_dir: &Path, workspace: Option<&str>) -> PathBuf {
+    match workspace {
+        None | Some(DEFAULT_WORKSPACE) => data_dir.join("mailbox.db"),
+        Some(workspace) => data_dir
+            .join(WORKSPACES_DIR)
+            .join(format!("{workspace}.db")),
+    }
+}
+
+// List the workspaces that have a database in the data directory, sorted by name
+// The default workspace is always included, even before its database is created.
+pub fn list_workspaces(data_dir: &Path) -> Result<Vec<String>> {
+    let dir = data_dir.join(WORKSPACES_DIR);
+    let entries = match read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![DEFAULT_WORKSPACE.into()]),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", dir.display()));
+        }
+    };
+    let mut workspaces = vec![];
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|extension| extension == "db") {
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                // Ignore files that weren't created by a workspace
+                if parse_workspace(name).is_ok() && name != DEFAULT_WORKSPACE {
+                    workspaces.push(name.to_owned());
+                }
+            }
+        }
+    }
+    workspaces.push(DEFAULT_WORKSPACE.into());
+    workspaces.sort();
+    Ok(workspaces)
+}
+
+// Format a list of workspaces, marking the active one
+pub fn format_workspaces(workspaces: &[String], active: &str) -> String {
+    workspaces
+        .iter()
+        .map(|workspace| {
+            let marker = if workspace == active { '*' } else { ' ' };
+            format!("{marker} {workspace}\n")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_parse_workspace() {
+        assert_eq!(parse_workspace("work-ci_2"), Ok(String::from("work-ci_2")));
+        assert!(parse_workspace("").is_err());
+        assert!(parse_workspace("Work").is_err());
+        assert!(parse_workspace("../work").is_err());
+        assert!(parse_workspace(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_get_workspace_db_path() {
+        let data_dir = Path::new("/data");
+        assert_eq!(
+            get_workspace_db_path(data_dir, None),
+            Path::new("/data/mailbox.db")
+        );
+        assert_eq!(
+            get_workspace_db_path(data_dir, Some("default")),
+            Path::new("/data/mailbox.db")
+        );
+        assert_eq!(
+            get_workspace_db_path(data_dir, Some("personal")),
+            Path::new("/data/workspaces/personal.db")
+        );
+    }
+
+    #[test]
+    fn test_list_workspaces() -> Result<()> {
+        let data_dir =
+            std::env::temp_dir().join(format!("mailbox-workspaces-{}", std::process::id()));
+        let _ = remove_dir_all(&data_dir);
+        let before = list_workspaces(&data_dir);
+
+        create_dir_all(data_dir.join(WORKSPACES_DIR))?;
+        for file in [
+            "work.db",
+            "personal.db",
+            "personal.db-wal",
+            "notes.txt",
+            "Bad.db",
+        ] {
+            write(data_dir.join(WORKSPACES_DIR).join(file), "")?;
+        }
+        let after = list_workspaces(&data_dir);
+        remove_dir_all(&data_dir)?;
+
+        assert_eq!(before?, vec!["default"]);
+        assert_eq!(after?, vec!["default", "personal", "work"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_workspaces() {
+        let workspaces = vec![String::from("default"), String::from("work")];
+        assert_eq!(
+            format_workspaces(&workspaces, "work"),
+            "  default\n* work\n"
+        );
+    }
+}
@@ -0,0 +1,20 @@
+#![warn(clippy::str_to_string, clippy::pedantic, clippy::nursery)]
+
+// The HTTP client for mailbox-server and the types that its API uses
+// Everything that clients need is re-exported here so that they depend on this crate instead of the
+// database crate, which also contains the server's SQLite storage. Without the http feature, the
+// crate compiles to WebAssembly, and browser code can use the types and the API functions to build
+// the same requests as HttpBackend.
+
+pub use database::{
+    choose_api_version, get_endpoint_url, get_filtered_url, sign_body, AdminStats, ApiToken,
+    Attachment, AuditEntry, Backend, Bucket, BucketStats, Changes, Compaction, Database,
+    DeadLetterPolicy, DeliveryStatus, DynBackend, Filter, ForwardDelivery, Id, Kind, Limits,
+    Mailbox, MailboxAcl, MailboxInfo, Message, NewMessage, QuotaStatus, Seq, Sort, State, Tag,
+    ValidationError, REQUEST_ID_HEADER, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER,
+    SUPPORTED_API_VERSIONS, UNVERSIONED_API, VERSION_HEADER,
+};
+#[cfg(feature = "http")]
+pub use database::{
+    generate_request_id, ConnectionError, HttpBackend, ResponseError, ServerStatus,
+};
@@ -0,0 +1,129 @@
+use crate::mailbox::Mailbox;
+use serde::{Deserialize, Serialize};
+
+// Determine whether a mailbox matches a glob pattern, where "*" matches any sequence of characters,
+// including "/", and every other character matches itself
+#[must_use]
+pub fn matches_pattern(pattern: &str, mailbox: &Mailbox) -> bool {
+    let mut sections = pattern.split('*');
+    let mut remaining = mailbox.as_ref();
+    // The first section is anchored to the start of the mailbox
+    let first = sections.next().unwrap_or_default();
+    let Some(rest) = remaining.strip_prefix(first) else {
+        return false;
+    };
+    remaining = rest;
+    let mut sections = sections.collect::<Vec<_>>();
+    // Without any "*", the pattern must match the whole mailbox
+    let Some(last) = sections.pop() else {
+        return remaining.is_empty();
+    };
+    // Match the middle sections as early as possible to leave the most room for the rest
+    for section in sections {
+        match remaining.find(section) {
+            Some(index) => remaining = &remaining[index + section.len()..],
+            None => return false,
+        }
+    }
+    // The last section is anchored to the end of the mailbox
+    remaining.ends_with(last)
+}
+
+// Convert a pattern into an SQLite GLOB pattern by escaping every special character except "*"
+#[must_use]
+pub fn pattern_to_glob(pattern: &str) -> String {
+    pattern.replace('[', "[[]").replace('?', "[?]")
+}
+
+// The mailboxes that an API token can read and write, each as a list of glob patterns
+// None grants access to every mailbox and an empty list grants access to none of them.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+#[serde(default)]
+pub struct MailboxAcl {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlite", sqlx(rename = "read_mailboxes", json(nullable)))]
+    pub read: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "sqlite", sqlx(rename = "write_mailboxes", json(nullable)))]
+    pub write: Option<Vec<String>>,
+}
+
+impl MailboxAcl {
+    // Determine whether the token can access every mailbox
+    #[must_use]
+    pub fn is_unrestricted(&self) -> bool {
+        self.read.is_none() && self.write.is_none()
+    }
+
+    // Determine whether the token can read messages in a mailbox
+    #[must_use]
+    pub fn can_read(&self, mailbox: &Mailbox) -> bool {
+        Self::allows(self.read.as_ref(), mailbox)
+    }
+
+    // Determine whether the token can add, change, or delete messages in a mailbox
+    #[must_use]
+    pub fn can_write(&self, mailbox: &Mailbox) -> bool {
+        Self::allows(self.write.as_ref(), mailbox)
+    }
+
+    fn allows(patterns: Option<&Vec<String>>, mailbox: &Mailbox) -> bool {
+        patterns.is_none_or(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, mailbox))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mailbox(name: &str) -> Mailbox {
+        name.try_into().unwrap()
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("ci", &mailbox("ci")));
+        assert!(!matches_pattern("ci", &mailbox("ci/build")));
+        assert!(matches_pattern("ci/*", &mailbox("ci/build")));
+        assert!(matches_pattern("ci/*", &mailbox("ci/build/linux")));
+        assert!(!matches_pattern("ci/*", &mailbox("ci")));
+        assert!(!matches_pattern("ci/*", &mailbox("cd/build")));
+        assert!(matches_pattern("*", &mailbox("anything")));
+        assert!(matches_pattern("*/errors", &mailbox("ci/errors")));
+        assert!(matches_pattern("a*b*c", &mailbox("abbc")));
+        assert!(!matches_pattern("a*b*c", &mailbox("acb")));
+        assert!(!matches_pattern("ab*ba", &mailbox("aba")));
+        assert!(matches_pattern("ci?", &mailbox("ci?")));
+        assert!(!matches_pattern("ci?", &mailbox("cid")));
+    }
+
+    #[test]
+    fn test_pattern_to_glob() {
+        assert_eq!(pattern_to_glob("ci/*"), "ci/*");
+        assert_eq!(pattern_to_glob("a?[b]"), "a[?][[]b]");
+    }
+
+    #[test]
+    fn test_acl() {
+        let acl = MailboxAcl {
+            read: None,
+            write: Some(vec![String::from("ci/*")]),
+        };
+        assert!(!acl.is_unrestricted());
+        assert!(acl.can_read(&mailbox("personal")));
+        assert!(acl.can_write(&mailbox("ci/build")));
+        assert!(!acl.can_write(&mailbox("personal")));
+
+        let acl = MailboxAcl {
+            read: Some(vec![]),
+            write: None,
+        };
+        assert!(!acl.can_read(&mailbox("ci")));
+        assert!(MailboxAcl::default().is_unrestricted());
+    }
+}
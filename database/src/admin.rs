@@ -0,0 +1,92 @@
+use crate::acl::MailboxAcl;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// Statistics about a server's database for administrators
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AdminStats {
+    pub message_count: usize,
+    pub mailbox_count: usize,
+    pub token_count: usize,
+    // The size of the database file
+    pub database_bytes: u64,
+    // The space in the database file that is unused and can be reclaimed by compacting
+    pub free_bytes: u64,
+}
+
+// The size of a database before and after it was compacted
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Compaction {
+    pub before_bytes: u64,
+    pub after_bytes: u64,
+}
+
+// A named token that authorizes requests to a server
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+pub struct ApiToken {
+    pub name: String,
+    pub created: NaiveDateTime,
+    // The secret token is only revealed when the token is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // The mailboxes that the token can access
+    #[serde(flatten)]
+    #[cfg_attr(feature = "sqlite", sqlx(flatten))]
+    pub acl: MailboxAcl,
+}
+
+// An entry in the audit log of the API calls that changed a server's messages or tokens
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: NaiveDateTime,
+    // The name of the API token that authorized the call, or how it was authorized otherwise
+    pub actor: String,
+    pub operation: String,
+    // The filter, message ids, mailbox, or token name that the call targeted
+    pub target: String,
+    // The number of messages or tokens that the call affected
+    pub affected: i64,
+}
+
+// The schema version of a database and the latest schema version that this build supports
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SchemaVersion {
+    pub current: i64,
+    pub latest: i64,
+}
+
+// The state of a database's write-ahead log after checkpointing as much of it as possible
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WalStatus {
+    // The journal mode, which is "wal" unless write-ahead logging is disabled
+    pub journal_mode: String,
+    // Whether another connection prevented the checkpoint from finishing
+    pub busy: bool,
+    // The number of frames in the log, or -1 if the database isn't in WAL mode
+    pub frames: i64,
+    // The number of frames in the log that have been copied back into the database file
+    pub checkpointed: i64,
+}
+
+// A summary of the contents of a local database
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DatabaseInfo {
+    pub schema_version: SchemaVersion,
+    // The number of messages in each state, ignoring consumers' own states
+    pub unread_count: usize,
+    pub read_count: usize,
+    pub archived_count: usize,
+    // The timestamps of the oldest and newest messages, which are unknown if there are no messages
+    pub oldest: Option<NaiveDateTime>,
+    pub newest: Option<NaiveDateTime>,
+}
+
+// The rows returned by an ad hoc SQL query, with each value converted to JSON
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
@@ -0,0 +1,104 @@
+use crate::filter::Filter;
+use anyhow::{anyhow, Context, Result};
+
+// The request conventions of the server's HTTP API, which don't depend on any HTTP client so that
+// clients in other environments, like browsers, can build the same requests as HttpBackend
+
+// The versions of the server's API that this client can use, oldest first
+pub const SUPPORTED_API_VERSIONS: [u32; 1] = [1];
+
+// The API version of servers from before the API was versioned, which only serve unprefixed routes
+pub const UNVERSIONED_API: u32 = 0;
+
+// The header that the server uses to report its version with every response
+pub const VERSION_HEADER: &str = "Mailbox-Version";
+
+// The header that identifies a request so that client errors can be matched with server logs
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Choose the newest API version that both the server and this client support
+pub fn choose_api_version(server_versions: &[u32]) -> Result<u32> {
+    SUPPORTED_API_VERSIONS
+        .iter()
+        .rev()
+        .find(|version| server_versions.contains(version))
+        .copied()
+        .ok_or_else(|| {
+            anyhow!(
+                "Server supports API versions {server_versions:?}, but this client only supports API versions {SUPPORTED_API_VERSIONS:?}. Try upgrading mailbox."
+            )
+        })
+}
+
+// Build the URL of an API endpoint in a particular API version
+#[must_use]
+pub fn get_endpoint_url(api_url: &str, api_version: u32, path: &str) -> String {
+    if api_version == UNVERSIONED_API {
+        format!("{api_url}/{path}")
+    } else {
+        format!("{api_url}/v{api_version}/{path}")
+    }
+}
+
+// Build the URL of an API endpoint with a filter in its query string, like HttpBackend sends when
+// it loads, changes, or deletes messages
+pub fn get_filtered_url(
+    api_url: &str,
+    api_version: u32,
+    path: &str,
+    filter: &Filter,
+) -> Result<String> {
+    let url = get_endpoint_url(api_url, api_version, path);
+    let query = serde_urlencoded::to_string(filter).context("Failed to serialize filter")?;
+    Ok(if query.is_empty() {
+        url
+    } else {
+        format!("{url}?{query}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::State;
+
+    #[test]
+    fn test_choose_api_version() {
+        assert_eq!(choose_api_version(&[1]).unwrap(), 1);
+        assert_eq!(choose_api_version(&[1, 2]).unwrap(), 1);
+        assert!(choose_api_version(&[2]).is_err());
+        assert!(choose_api_version(&[]).is_err());
+    }
+
+    #[test]
+    fn test_get_endpoint_url() {
+        assert_eq!(
+            get_endpoint_url("http://localhost:8080", 1, "messages"),
+            "http://localhost:8080/v1/messages"
+        );
+        assert_eq!(
+            get_endpoint_url("http://localhost:8080", UNVERSIONED_API, "stats/day"),
+            "http://localhost:8080/stats/day"
+        );
+    }
+
+    #[test]
+    fn test_get_filtered_url() {
+        assert_eq!(
+            get_filtered_url("http://localhost:8080", 1, "messages", &Filter::new()).unwrap(),
+            "http://localhost:8080/v1/messages"
+        );
+        assert_eq!(
+            get_filtered_url(
+                "http://localhost:8080",
+                1,
+                "messages",
+                &Filter::new()
+                    .with_mailbox("ci".try_into().unwrap())
+                    .with_states(vec![State::Unread, State::Read])
+            )
+            .unwrap(),
+            "http://localhost:8080/v1/messages?mailbox=ci&states=unread%2Cread"
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use crate::attachment::Attachment;
+use crate::message::Message;
+use anyhow::Result;
+
+// A message that is being archived along with its attachments
+#[derive(Clone)]
+pub struct ArchivedMessage {
+    pub message: Message,
+    pub attachments: Vec<Attachment>,
+}
+
+// Writes messages that are being archived somewhere durable before they are deleted
+// Backends only delete the messages if it succeeds, and the messages can't change between being
+// passed to it and being deleted.
+pub type Archiver = Box<dyn FnOnce(&[ArchivedMessage]) -> Result<()> + Send>;
@@ -0,0 +1,70 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// A file attached to a message
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "lowercase", tag = "kind")]
+pub enum Attachment {
+    // A small file whose contents are stored with the message
+    Blob {
+        name: String,
+        // The contents are base64-encoded in JSON
+        #[serde(deserialize_with = "decode_base64", serialize_with = "encode_base64")]
+        data: Vec<u8>,
+    },
+
+    // A file path or URL that the message refers to
+    Reference {
+        location: String,
+    },
+}
+
+impl Attachment {
+    // Return the name of the file, or the location of a reference
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Blob { name, .. } => name,
+            Self::Reference { location } => location,
+        }
+    }
+}
+
+fn encode_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+fn decode_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() -> anyhow::Result<()> {
+        let blob = Attachment::Blob {
+            name: String::from("report.txt"),
+            data: b"report".to_vec(),
+        };
+        let json = r#"{"kind":"blob","name":"report.txt","data":"cmVwb3J0"}"#;
+        assert_eq!(serde_json::to_string(&blob)?, json);
+        assert_eq!(serde_json::from_str::<Attachment>(json)?, blob);
+
+        let reference = Attachment::Reference {
+            location: String::from("https://example.com/build/1"),
+        };
+        let json = r#"{"kind":"reference","location":"https://example.com/build/1"}"#;
+        assert_eq!(serde_json::to_string(&reference)?, json);
+        assert_eq!(serde_json::from_str::<Attachment>(json)?, reference);
+
+        assert!(serde_json::from_str::<Attachment>(
+            r#"{"kind":"blob","name":"report.txt","data":"not base64!"}"#
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -1,24 +1,97 @@
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
 use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
 use crate::filter::Filter;
-use crate::message::{Message, State};
+use crate::heartbeat::Heartbeat;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
 use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
 use anyhow::Result;
+use std::collections::BTreeMap;
 use std::future::Future;
+use std::time::Duration;
 
 pub trait Backend {
     fn add_messages(
         &self,
         messages: Vec<NewMessage>,
     ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn upsert_messages(
+        &self,
+        messages: Vec<NewMessage>,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
     fn load_messages(&self, filter: Filter) -> impl Future<Output = Result<Vec<Message>>> + Send;
     fn change_state(
         &self,
         filter: Filter,
         new_state: State,
     ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
     fn delete_messages(&self, filter: Filter) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn archive_messages(
+        &self,
+        filter: Filter,
+        archiver: Archiver,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
     fn load_mailboxes(
         &self,
         filter: Filter,
     ) -> impl Future<Output = Result<Vec<MailboxInfo>>> + Send;
+    fn load_changes(&self, since: Seq) -> impl Future<Output = Result<Changes>> + Send;
+    fn load_stats(
+        &self,
+        filter: Filter,
+        bucket: Bucket,
+    ) -> impl Future<Output = Result<Vec<BucketStats>>> + Send;
+    fn load_attachments(&self, id: Id) -> impl Future<Output = Result<Vec<Attachment>>> + Send;
+    fn record_view(&self, id: Id) -> impl Future<Output = Result<Option<Message>>> + Send;
+    fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> impl Future<Output = Result<Option<Message>>> + Send;
+    fn remind_message(
+        &self,
+        id: Id,
+        delay: Duration,
+    ) -> impl Future<Output = Result<Option<Message>>> + Send;
+    fn wake_reminders(&self) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn link_messages(
+        &self,
+        id: Id,
+        related_id: Id,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send;
+    fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> impl Future<Output = Result<Option<Heartbeat>>> + Send;
+    fn load_heartbeats(&self) -> impl Future<Output = Result<Vec<Heartbeat>>> + Send;
+    fn load_mailbox_configs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<Mailbox, MailboxConfig>>> + Send;
+    fn load_ingestion_configs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<Mailbox, MailboxConfig>>> + Send;
+    fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> impl Future<Output = Result<MailboxConfig>> + Send;
 }
@@ -0,0 +1,19 @@
+use crate::message::{Id, Message};
+use serde::{Deserialize, Serialize};
+
+// A sequence number that increases every time a message is created, updated, or deleted
+pub type Seq = i64;
+
+// The changes made to messages after a particular sequence number
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Changes {
+    // The sequence number of the most recent change, which should be passed to the next
+    // load_changes call to receive only newer changes
+    pub seq: Seq,
+
+    // Messages that were created or updated, in their current state, sorted newest first
+    pub messages: Vec<Message>,
+
+    // The ids of messages that were deleted
+    pub deleted: Vec<Id>,
+}
@@ -0,0 +1,100 @@
+use crate::limits::ValidationError;
+use crate::message::State;
+use serde::Deserialize;
+
+// The names that custom states can't use because they would be confused with the built-in states
+const RESERVED_NAMES: [&str; 4] = ["unread", "read", "archived", "all"];
+
+// Check that the name of a custom state is short and only contains lowercase letters, digits, and
+// hyphens, which keeps it safe to use in comma-separated filters and on the command line
+pub fn validate_custom_state(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() || name.len() > 32 {
+        return Err(ValidationError(format!(
+            "custom state \"{name}\" must be between 1 and 32 characters"
+        )));
+    }
+    if !name
+        .chars()
+        .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '-')
+    {
+        return Err(ValidationError(format!(
+            "custom state \"{name}\" must only contain lowercase letters, digits, and hyphens"
+        )));
+    }
+    if RESERVED_NAMES.contains(&name) {
+        return Err(ValidationError(format!(
+            "custom state \"{name}\" conflicts with a built-in state"
+        )));
+    }
+    Ok(())
+}
+
+// A user-defined state that refines one of the built-in states, like an "in-progress" state between
+// read and archived. Messages in a custom state are also in its base state, which is what counts,
+// sorting, and transition policies use. The flags control whether commands that act on the base
+// state also act on the custom state, and they all default to true.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomState {
+    // The built-in state that messages in the custom state are in
+    pub base: State,
+    // Whether viewing the base state shows the messages
+    #[serde(default = "default_true")]
+    pub view: bool,
+    // Whether archiving read and unread messages archives the messages
+    #[serde(default = "default_true")]
+    pub archive: bool,
+    // Whether clearing archived messages deletes the messages
+    #[serde(default = "default_true")]
+    pub clear: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl CustomState {
+    // Create a custom state that behaves exactly like its base state
+    #[must_use]
+    pub fn new(base: State) -> Self {
+        Self {
+            base,
+            view: true,
+            archive: true,
+            clear: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_custom_state() {
+        assert!(validate_custom_state("in-progress").is_ok());
+        assert!(validate_custom_state("waiting2").is_ok());
+        assert!(validate_custom_state("").is_err());
+        assert!(validate_custom_state(&"a".repeat(33)).is_err());
+        assert!(validate_custom_state("In-Progress").is_err());
+        assert!(validate_custom_state("in,progress").is_err());
+        assert!(validate_custom_state("read").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let state: CustomState =
+            serde_json::from_str(r#"{ "base": "read", "archive": false }"#).unwrap();
+        assert_eq!(
+            state,
+            CustomState {
+                archive: false,
+                ..CustomState::new(State::Read)
+            }
+        );
+        assert!(serde_json::from_str::<CustomState>(r#"{ "archive": false }"#).is_err());
+        assert!(
+            serde_json::from_str::<CustomState>(r#"{ "base": "read", "delete": false }"#).is_err()
+        );
+    }
+}
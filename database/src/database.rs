@@ -1,19 +1,81 @@
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
+use crate::custom_state::validate_custom_state;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::dyn_backend::DynBackend;
 use crate::filter::Filter;
+use crate::heartbeat::Heartbeat;
+use crate::limits::{Limits, ValidationError};
 use crate::mailbox::Mailbox;
-use crate::message::{Message, State};
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
 use crate::new_message::NewMessage;
+use crate::quota::{QuotaAction, QuotaPolicy, QuotaStatus};
+use crate::stats::{Bucket, BucketStats};
+use crate::transition::{Transition, TransitionObserver, TransitionPolicy};
 use crate::Backend;
-use anyhow::{bail, Result};
+use anyhow::Result;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
-fn validate_message(message: &NewMessage) -> Result<()> {
+fn validate_message(message: &NewMessage, limits: &Limits) -> Result<(), ValidationError> {
+    let fail = |reason: String| Err(ValidationError(reason));
     if message.content.is_empty() {
-        bail!("content must not be empty");
+        return fail(String::from("content must not be empty"));
+    }
+    if message.content.len() > limits.max_content_length {
+        return fail(format!(
+            "content is {} bytes long, which exceeds the limit of {} bytes",
+            message.content.len(),
+            limits.max_content_length
+        ));
+    }
+    message.mailbox.validate(limits)?;
+    if message.external_id.as_ref().is_some_and(String::is_empty) {
+        return fail(String::from("external id must not be empty"));
+    }
+    for attachment in &message.attachments {
+        match attachment {
+            Attachment::Blob { name, data } => {
+                if name.is_empty() {
+                    return fail(String::from("attachment name must not be empty"));
+                }
+                if data.len() > limits.max_attachment_size {
+                    return fail(format!(
+                        "attachment \"{name}\" is {} bytes long, which exceeds the limit of {} bytes",
+                        data.len(),
+                        limits.max_attachment_size
+                    ));
+                }
+            }
+            Attachment::Reference { location } => {
+                if location.is_empty() {
+                    return fail(String::from("attachment location must not be empty"));
+                }
+            }
+        }
     }
 
     Ok(())
 }
 
+fn validate_messages(messages: &[NewMessage], limits: &Limits) -> Result<(), ValidationError> {
+    if messages.len() > limits.max_batch_size {
+        return Err(ValidationError(format!(
+            "{} messages can't be added at once, which exceeds the limit of {} messages",
+            messages.len(),
+            limits.max_batch_size
+        )));
+    }
+    messages
+        .iter()
+        .try_for_each(|message| validate_message(message, limits))
+}
+
 #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct MailboxInfo {
     pub name: Mailbox,
@@ -22,65 +84,1145 @@ pub struct MailboxInfo {
 
 pub struct Database<B: Backend + Sized> {
     backend: B,
+    // Messages in these mailboxes and their descendants are archived instead of being marked as read
+    archive_on_read: Vec<Mailbox>,
+    limits: Limits,
+    transition_policy: TransitionPolicy,
+    quota_policy: QuotaPolicy,
+    // Notified of every state transition that change_state makes
+    observers: Vec<Arc<dyn TransitionObserver>>,
+    // The consumer whose own message states filters use when they don't specify a consumer
+    consumer: Option<String>,
+    // The mailbox that alerts about overdue heartbeats are added to, if any
+    heartbeat_alerts: Option<Mailbox>,
 }
 
 impl<B: Backend + Sized> Database<B> {
     // Create a new Database that uses the provided backend
     #[must_use]
     pub fn new(backend: B) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            archive_on_read: vec![],
+            limits: Limits::default(),
+            transition_policy: TransitionPolicy::default(),
+            quota_policy: QuotaPolicy::default(),
+            observers: vec![],
+            consumer: None,
+            heartbeat_alerts: None,
+        }
     }
 
-    // Add multiple new messages, returning the new messages
-    pub async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
-        for message in &messages {
-            validate_message(message)?;
+    // Return the limits that new messages must satisfy
+    #[must_use]
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Return the backend that the database uses
+    #[must_use]
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    // Configure the mailboxes whose messages skip the read state and are archived instead
+    #[must_use]
+    pub fn with_archive_on_read(self, archive_on_read: Vec<Mailbox>) -> Self {
+        Self {
+            archive_on_read,
+            ..self
         }
+    }
+
+    // Configure the limits that new messages must satisfy
+    #[must_use]
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    // Configure the state transitions that change_state allows
+    #[must_use]
+    pub fn with_transition_policy(self, transition_policy: TransitionPolicy) -> Self {
+        Self {
+            transition_policy,
+            ..self
+        }
+    }
+
+    // Configure the quotas on the number of active messages in mailboxes
+    #[must_use]
+    pub fn with_quota_policy(self, quota_policy: QuotaPolicy) -> Self {
+        Self {
+            quota_policy,
+            ..self
+        }
+    }
+
+    // Register an observer that is notified of the state transitions that change_state makes
+    #[must_use]
+    pub fn with_observer(mut self, observer: Arc<dyn TransitionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    // Configure the consumer whose own message states are read and changed instead of the shared
+    // message states
+    #[must_use]
+    pub fn with_consumer(self, consumer: Option<String>) -> Self {
+        Self { consumer, ..self }
+    }
+
+    // Add an alert to a mailbox whenever a heartbeat becomes overdue
+    #[must_use]
+    pub fn with_heartbeat_alerts(self, heartbeat_alerts: Option<Mailbox>) -> Self {
+        Self {
+            heartbeat_alerts,
+            ..self
+        }
+    }
+
+    // Apply the database's consumer to a filter that doesn't have one
+    fn scope_filter(&self, filter: Filter) -> Filter {
+        filter.with_default_consumer(self.consumer.as_deref())
+    }
+
+    // Determine whether messages in the mailbox are archived instead of being marked as read
+    fn archives_on_read(&self, mailbox: &Mailbox) -> bool {
+        self.archive_on_read.iter().any(|archive_mailbox| {
+            archive_mailbox == mailbox || archive_mailbox.is_ancestor_of(mailbox)
+        })
+    }
 
-        self.backend.add_messages(messages).await
+    // Count the unread and read messages in each mailbox, which count against quotas
+    async fn load_active_counts(&self) -> Result<Vec<MailboxInfo>> {
+        // Quotas always use the shared message states
+        self.backend
+            .load_mailboxes(Filter::new().with_states(vec![State::Unread, State::Read]))
+            .await
+    }
+
+    // Reject new messages that would put a mailbox over its quota if the quota policy rejects them
+    async fn check_quotas(
+        &self,
+        messages: &[NewMessage],
+        quota_policy: &QuotaPolicy,
+    ) -> Result<()> {
+        if quota_policy.is_empty() || quota_policy.action() != QuotaAction::Reject {
+            return Ok(());
+        }
+        let active = self.load_active_counts().await?;
+        quota_policy.check(messages, &active)?;
+        Ok(())
+    }
+
+    // Archive the oldest active messages in mailboxes that new messages put over their quota if the
+    // quota policy archives them
+    async fn archive_over_quota(
+        &self,
+        messages: &[Message],
+        quota_policy: &QuotaPolicy,
+    ) -> Result<()> {
+        if quota_policy.is_empty() || quota_policy.action() != QuotaAction::Archive {
+            return Ok(());
+        }
+        let scopes = quota_policy.scopes_for_all(messages.iter().map(|message| &message.mailbox));
+        for scope in scopes {
+            let mut active = self
+                .backend
+                .load_messages(
+                    Filter::new()
+                        .with_mailbox(scope.mailbox.clone())
+                        .with_states(vec![State::Unread, State::Read]),
+                )
+                .await?;
+            active.retain(|message| scope.contains(&message.mailbox));
+            if active.len() <= scope.limit {
+                continue;
+            }
+            active.sort_by_key(|message| (message.timestamp, message.id));
+            let ids = active
+                .iter()
+                .take(active.len() - scope.limit)
+                .map(|message| message.id)
+                .collect();
+            self.change_state(Filter::new().with_ids(ids), State::Archived)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Combine the quota policy with the quotas in the mailboxes' metadata
+    fn get_quota_policy(&self, configs: &BTreeMap<Mailbox, MailboxConfig>) -> QuotaPolicy {
+        configs
+            .iter()
+            .filter_map(|(mailbox, config)| Some((mailbox, config.quota?)))
+            .fold(self.quota_policy.clone(), |policy, (mailbox, quota)| {
+                policy.with_exact_limit(mailbox.clone(), quota as usize)
+            })
+    }
+
+    // Start new messages that don't have a state in their mailbox's default state
+    fn apply_default_states(
+        messages: &mut [NewMessage],
+        configs: &BTreeMap<Mailbox, MailboxConfig>,
+    ) {
+        for message in messages
+            .iter_mut()
+            .filter(|message| message.state.is_none())
+        {
+            message.state = configs
+                .get(&message.mailbox)
+                .and_then(|config| config.default_state);
+        }
+    }
+
+    // Add multiple new messages, returning the new messages
+    pub async fn add_messages(&self, mut messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        validate_messages(&messages, &self.limits)?;
+        let configs = self.backend.load_ingestion_configs().await?;
+        Self::apply_default_states(&mut messages, &configs);
+        let quota_policy = self.get_quota_policy(&configs);
+        self.check_quotas(&messages, &quota_policy).await?;
+
+        let messages = self.backend.add_messages(messages).await?;
+        self.archive_over_quota(&messages, &quota_policy).await?;
+        Ok(messages)
+    }
+
+    // Add multiple new messages, returning the new messages. Messages with an external id that
+    // already exists in their mailbox update the existing message's content, state, timestamp, and
+    // tags instead of being added.
+    pub async fn upsert_messages(&self, mut messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        validate_messages(&messages, &self.limits)?;
+        let configs = self.backend.load_ingestion_configs().await?;
+        Self::apply_default_states(&mut messages, &configs);
+        let quota_policy = self.get_quota_policy(&configs);
+        self.check_quotas(&messages, &quota_policy).await?;
+
+        let messages = self.backend.upsert_messages(messages).await?;
+        self.archive_over_quota(&messages, &quota_policy).await?;
+        Ok(messages)
     }
 
     // Load all messages that match the filter
     pub async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
-        self.backend.load_messages(filter).await
+        self.wake().await?;
+        self.backend.load_messages(self.scope_filter(filter)).await
+    }
+
+    // Determine the state that a message in the mailbox moves into when it is changed to new_state
+    fn get_target_state(&self, mailbox: &Mailbox, new_state: State) -> State {
+        if new_state == State::Read && self.archives_on_read(mailbox) {
+            State::Archived
+        } else {
+            new_state
+        }
     }
 
     // Move messages that match the filter from their old state into new_state, returning the
     // modified messages
+    // Fails without changing any messages if the transition policy forbids any of the transitions
     pub async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
-        self.backend.change_state(filter, new_state).await
+        let filter = self.scope_filter(filter);
+        let archives_on_read = new_state == State::Read && !self.archive_on_read.is_empty();
+        if !archives_on_read && self.transition_policy.allows_all() && self.observers.is_empty() {
+            return self.backend.change_state(filter, new_state).await;
+        }
+
+        // Load the messages first to determine their old and new states, keeping the original filter
+        // when changing them so that messages that stopped matching it in the meantime aren't changed
+        let mut old_states = HashMap::new();
+        let mut ids_by_state: Vec<(State, Vec<Id>)> = vec![];
+        for message in self.backend.load_messages(filter.clone()).await? {
+            let target_state = self.get_target_state(&message.mailbox, new_state);
+            if !self
+                .transition_policy
+                .is_allowed(message.state, target_state)
+            {
+                return Err(ValidationError(format!(
+                    "message {} in mailbox \"{}\" can't move from {} to {}",
+                    message.id, message.mailbox, message.state, target_state
+                ))
+                .into());
+            }
+            match ids_by_state
+                .iter_mut()
+                .find(|(state, _)| *state == target_state)
+            {
+                Some((_, ids)) => ids.push(message.id),
+                None => ids_by_state.push((target_state, vec![message.id])),
+            }
+            old_states.insert(message.id, message.state);
+        }
+
+        let mut messages = vec![];
+        for (state, ids) in ids_by_state {
+            messages.extend(
+                self.backend
+                    .change_state(filter.clone().with_ids(ids), state)
+                    .await?,
+            );
+        }
+        messages.sort_by_key(|message| std::cmp::Reverse(message.id));
+        self.notify_observers(&old_states, &messages);
+        Ok(messages)
+    }
+
+    // Move messages that match the filter into a custom state that refines new_state, returning the
+    // modified messages
+    // Custom states are shared by all consumers, so the database's consumer isn't applied. The
+    // transition policy applies to the built-in states that the messages move between.
+    pub async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        validate_custom_state(&custom_state)?;
+        if self.transition_policy.allows_all() && self.observers.is_empty() {
+            return self
+                .backend
+                .change_custom_state(filter, custom_state, new_state)
+                .await;
+        }
+
+        let mut old_states = HashMap::new();
+        for message in self.backend.load_messages(filter.clone()).await? {
+            if !self.transition_policy.is_allowed(message.state, new_state) {
+                return Err(ValidationError(format!(
+                    "message {} in mailbox \"{}\" can't move from {} to {}",
+                    message.id, message.mailbox, message.state, new_state
+                ))
+                .into());
+            }
+            old_states.insert(message.id, message.state);
+        }
+        let messages = self
+            .backend
+            .change_custom_state(
+                filter.with_ids(old_states.keys().copied().collect()),
+                custom_state,
+                new_state,
+            )
+            .await?;
+        self.notify_observers(&old_states, &messages);
+        Ok(messages)
+    }
+
+    // Notify the observers of the messages whose states changed from their old states
+    fn notify_observers(&self, old_states: &HashMap<Id, State>, messages: &[Message]) {
+        let transitions = messages
+            .iter()
+            .filter_map(|message| {
+                let from = *old_states.get(&message.id)?;
+                (from != message.state).then(|| Transition {
+                    id: message.id,
+                    mailbox: message.mailbox.clone(),
+                    from,
+                    to: message.state,
+                })
+            })
+            .collect::<Vec<_>>();
+        if !transitions.is_empty() {
+            for observer in &self.observers {
+                observer.on_transitions(&transitions);
+            }
+        }
     }
 
     // Delete messages that match the filter, returning the deleted messages
     pub async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
-        self.backend.delete_messages(filter).await
+        self.backend
+            .delete_messages(self.scope_filter(filter))
+            .await
+    }
+
+    // Pass the messages that match the filter and their attachments to the archiver and then
+    // delete them, returning the archived messages
+    // Nothing is deleted if the archiver fails, and the messages can't change in between.
+    pub async fn archive_messages(
+        &self,
+        filter: Filter,
+        archiver: Archiver,
+    ) -> Result<Vec<Message>> {
+        self.backend
+            .archive_messages(self.scope_filter(filter), archiver)
+            .await
     }
 
     // Given all messages that match the filter, determine the names and sizes of all mailboxes
     // used by those messages
     pub async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
-        self.backend.load_mailboxes(filter).await
+        self.wake().await?;
+        self.backend.load_mailboxes(self.scope_filter(filter)).await
+    }
+
+    // Determine the status of the quota that each mailbox is closest to exceeding, or None for
+    // mailboxes without quotas
+    pub async fn load_quota_statuses(
+        &self,
+        mailboxes: &[Mailbox],
+    ) -> Result<Vec<Option<QuotaStatus>>> {
+        let quota_policy = self.get_quota_policy(&self.backend.load_mailbox_configs().await?);
+        if quota_policy.is_empty() {
+            return Ok(vec![None; mailboxes.len()]);
+        }
+        let active = self.load_active_counts().await?;
+        Ok(mailboxes
+            .iter()
+            .map(|mailbox| quota_policy.status(mailbox, &active))
+            .collect())
+    }
+
+    // Load the messages that were created, updated, or deleted after the sequence number
+    pub async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        self.backend.load_changes(since).await
+    }
+
+    // Count the messages that match the filter in each mailbox, grouped into time buckets
+    pub async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        self.backend
+            .load_stats(self.scope_filter(filter), bucket)
+            .await
+    }
+
+    // Load the files attached to a message
+    pub async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        self.backend.load_attachments(id).await
+    }
+
+    // Lease unread messages that match the filter to a worker, most urgent and then oldest first, so
+    // that other workers don't claim them too. The filter's limit caps how many messages are claimed. Claimed messages
+    // are marked as read, and if they are still read after the lease expires, the next claim returns
+    // them to unread first. Claims always use the shared message states.
+    pub async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        self.backend.claim_messages(filter, worker, lease).await
+    }
+
+    // Release a claimed message that its worker failed to process, returning the updated message or
+    // None if it doesn't exist. The message becomes unread so that it can be claimed again, unless it
+    // has been claimed as many times as the policy allows, in which case it is moved into the
+    // dead-letter mailbox with the reason appended to its content.
+    pub async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        self.backend.nack_message(id, reason, dead_letter).await
+    }
+
+    // Schedule a message to return to unread after the delay, returning the updated message or None
+    // if it doesn't exist. An unread message is marked as read until then so that it is out of the
+    // way. Scheduling another reminder for the same message replaces the first one. Reminders always
+    // use the shared message states.
+    pub async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        self.backend.remind_message(id, delay).await
+    }
+
+    // Return the messages whose reminders are due to unread and add alerts for heartbeats that became
+    // overdue, returning the woken and added messages
+    // Loading messages and mailboxes wakes them first, so this is only needed to find out which
+    // messages changed.
+    pub async fn wake(&self) -> Result<Vec<Message>> {
+        let mut messages = self.backend.wake_reminders().await?;
+        messages.extend(self.alert_overdue_heartbeats().await?);
+        Ok(messages)
+    }
+
+    // Add an alert to the heartbeat alerts mailbox for each overdue heartbeat, returning the added
+    // alerts. Each missed deadline only produces one alert, which is identified by its external id.
+    async fn alert_overdue_heartbeats(&self) -> Result<Vec<Message>> {
+        let Some(alerts_mailbox) = self.heartbeat_alerts.as_ref() else {
+            return Ok(vec![]);
+        };
+        let now = Utc::now().naive_utc();
+        let overdue = self
+            .backend
+            .load_heartbeats()
+            .await?
+            .into_iter()
+            .filter(|heartbeat| heartbeat.is_overdue(now))
+            .collect::<Vec<_>>();
+        let Some(earliest_deadline) = overdue.iter().map(Heartbeat::deadline).min() else {
+            return Ok(vec![]);
+        };
+        // Alerts are always added after the deadline that they are about
+        let existing = self
+            .backend
+            .load_messages(
+                Filter::new()
+                    .with_mailbox(alerts_mailbox.clone())
+                    .with_since(earliest_deadline),
+            )
+            .await?
+            .into_iter()
+            .filter_map(|message| message.external_id)
+            .collect::<HashSet<_>>();
+        let alerts = overdue
+            .into_iter()
+            .map(|heartbeat| NewMessage {
+                mailbox: alerts_mailbox.clone(),
+                content: format!(
+                    "{} is overdue: {}",
+                    heartbeat.mailbox,
+                    heartbeat.describe(now)
+                ),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: Some(format!(
+                    "heartbeat:{}:{}",
+                    heartbeat.mailbox,
+                    heartbeat.deadline().and_utc().timestamp()
+                )),
+                attachments: vec![],
+                // High priority so that forwards send alerts to phones by default
+                priority: Some(1),
+                kind: None,
+            })
+            .filter(|alert| {
+                alert
+                    .external_id
+                    .as_ref()
+                    .is_some_and(|external_id| !existing.contains(external_id))
+            })
+            .collect::<Vec<_>>();
+        if alerts.is_empty() {
+            return Ok(vec![]);
+        }
+        // Upsert so that concurrent reads that both add the same alert don't fail
+        self.backend.upsert_messages(alerts).await
+    }
+
+    // Expect a mailbox to receive a message at least once per interval, or stop expecting it to if
+    // the interval is None, returning the heartbeat or None if it was removed
+    pub async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        if interval.is_some_and(|interval| interval < Duration::from_mins(1)) {
+            return Err(ValidationError(String::from(
+                "heartbeat interval must be at least 1 minute",
+            ))
+            .into());
+        }
+        self.backend.set_heartbeat(mailbox, interval).await
+    }
+
+    // Load every heartbeat mailbox with the timestamp of its newest message
+    pub async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        self.backend.load_heartbeats().await
+    }
+
+    // Load every mailbox's metadata
+    pub async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.backend.load_mailbox_configs().await
+    }
+
+    // Load a mailbox's metadata, which is empty if it doesn't have any
+    pub async fn load_mailbox_config(&self, mailbox: &Mailbox) -> Result<MailboxConfig> {
+        Ok(self
+            .backend
+            .load_mailbox_configs()
+            .await?
+            .remove(mailbox)
+            .unwrap_or_default())
+    }
+
+    // Replace a mailbox's metadata, removing it if the config is empty
+    pub async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        config.validate()?;
+        self.backend.set_mailbox_config(mailbox, config).await
+    }
+
+    // Link two related messages, like an alert and the message that resolves it, returning both
+    // updated messages or an empty list if either doesn't exist
+    pub async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        if id == related_id {
+            return Err(ValidationError(format!("message {id} can't be linked to itself")).into());
+        }
+        self.backend.link_messages(id, related_id).await
+    }
+
+    // Record that a message was opened, returning the updated message or None if it doesn't exist
+    pub async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        self.backend.record_view(id).await
+    }
+}
+
+impl Database<Box<dyn DynBackend>> {
+    // Create a new database that hides the type of its backend so that code using it doesn't have
+    // to be generic over the backend
+    pub fn new_dyn(backend: impl Backend + Send + Sync + 'static) -> Self {
+        Self::new(Box::new(backend))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory_backend::MemoryBackend;
 
     #[test]
     fn test_validate() {
-        assert!(validate_message(&NewMessage {
-            mailbox: "mailbox".try_into().unwrap(),
-            content: String::new(),
-            state: None,
-        })
+        assert!(validate_message(
+            &NewMessage {
+                mailbox: "mailbox".try_into().unwrap(),
+                content: String::new(),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            },
+            &Limits::default()
+        )
+        .is_err());
+
+        assert!(validate_message(
+            &NewMessage {
+                mailbox: "mailbox".try_into().unwrap(),
+                content: String::from("message"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            },
+            &Limits::default()
+        )
+        .is_ok());
+
+        assert!(validate_message(
+            &NewMessage {
+                mailbox: "mailbox".try_into().unwrap(),
+                content: String::from("message"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: Some(String::new()),
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            },
+            &Limits::default()
+        )
         .is_err());
+    }
 
-        assert!(validate_message(&NewMessage {
-            mailbox: "mailbox".try_into().unwrap(),
+    #[test]
+    fn test_validate_limits() -> Result<()> {
+        let limits = Limits {
+            max_content_length: 5,
+            max_batch_size: 2,
+            max_mailbox_depth: 2,
+            max_mailbox_length: 8,
+            max_attachment_size: 4,
+            ..Default::default()
+        };
+        let new_message = |mailbox: &str, content: &str| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: mailbox.try_into()?,
+                content: String::from(content),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+
+        assert!(validate_message(&new_message("a/b", "12345")?, &limits).is_ok());
+        assert_eq!(
+            validate_message(&new_message("a/b", "123456")?, &limits)
+                .unwrap_err()
+                .to_string(),
+            "content is 6 bytes long, which exceeds the limit of 5 bytes"
+        );
+        assert_eq!(
+            validate_message(&new_message("a/b/c", "1")?, &limits)
+                .unwrap_err()
+                .to_string(),
+            "mailbox \"a/b/c\" has 3 sections, which exceeds the limit of 2 sections"
+        );
+        assert!(validate_message(&new_message("mailbox/a", "1")?, &limits).is_err());
+
+        let mut message = new_message("a", "1")?;
+        message.attachments = vec![Attachment::Blob {
+            name: String::from("file.txt"),
+            data: b"12345".to_vec(),
+        }];
+        assert_eq!(
+            validate_message(&message, &limits).unwrap_err().to_string(),
+            "attachment \"file.txt\" is 5 bytes long, which exceeds the limit of 4 bytes"
+        );
+        message.attachments = vec![Attachment::Reference {
+            location: String::new(),
+        }];
+        assert!(validate_message(&message, &limits).is_err());
+
+        let message = new_message("a", "1")?;
+        assert!(validate_messages(&[message.clone(), message.clone()], &limits).is_ok());
+        assert!(validate_messages(&[message.clone(), message.clone(), message], &limits).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_exceeding_limits() -> Result<()> {
+        let db = Database::new(MemoryBackend::new()).with_limits(Limits {
+            max_content_length: 5,
+            ..Default::default()
+        });
+        let err = db
+            .add_messages(vec![NewMessage {
+                mailbox: "mailbox".try_into()?,
+                content: String::from("123456"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            }])
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert!(db.load_messages(Filter::new()).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_new_dyn() -> Result<()> {
+        let db = Database::new_dyn(MemoryBackend::new());
+        db.add_messages(vec![NewMessage {
+            mailbox: "mailbox".try_into()?,
             content: String::from("message"),
             state: None,
-        })
-        .is_ok());
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        }])
+        .await?;
+        let messages = db.load_messages(Filter::new()).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "message");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_alerts() -> Result<()> {
+        let new_message = |mailbox: &str, hours_ago: i64| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: mailbox.try_into()?,
+                content: String::from("Backup succeeded"),
+                state: None,
+                tags: vec![],
+                timestamp: Some(Utc::now().naive_utc() - chrono::TimeDelta::hours(hours_ago)),
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+
+        let db =
+            Database::new(MemoryBackend::new()).with_heartbeat_alerts(Some("alerts".try_into()?));
+        let err = db
+            .set_heartbeat("cron/backup".try_into()?, Some(Duration::from_secs(30)))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        db.set_heartbeat("cron/backup".try_into()?, Some(Duration::from_hours(1)))
+            .await?;
+        db.add_messages(vec![new_message("cron/backup", 3)?])
+            .await?;
+        let alerts = db.wake().await?;
+        assert_eq!(
+            alerts
+                .iter()
+                .map(|alert| (
+                    alert.mailbox.as_ref(),
+                    alert.content.as_str(),
+                    alert.priority
+                ))
+                .collect::<Vec<_>>(),
+            vec![(
+                "alerts",
+                "cron/backup is overdue: last message 3h ago (expected hourly)",
+                1
+            )]
+        );
+
+        // The same missed deadline isn't alerted again
+        assert!(db.wake().await?.is_empty());
+        assert_eq!(
+            db.load_messages(Filter::new().with_mailbox("alerts".try_into()?))
+                .await?
+                .len(),
+            1
+        );
+
+        db.add_messages(vec![new_message("cron/backup", 0)?])
+            .await?;
+        assert!(db.wake().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quotas() -> Result<()> {
+        let new_message = |content: &str| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: "ci/build".try_into()?,
+                content: content.to_owned(),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+
+        let db = Database::new(MemoryBackend::new())
+            .with_quota_policy(QuotaPolicy::new().with_namespace_limit("ci".try_into()?, 2));
+        db.add_messages(vec![new_message("1")?, new_message("2")?])
+            .await?;
+        let err = db
+            .add_messages(vec![new_message("3")?])
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert_eq!(
+            db.load_quota_statuses(&["ci/build".try_into()?]).await?,
+            vec![Some(QuotaStatus {
+                mailbox: "ci".try_into()?,
+                limit: 2,
+                used: 2,
+            })]
+        );
+
+        let db = Database::new(MemoryBackend::new()).with_quota_policy(
+            QuotaPolicy::new()
+                .with_mailbox_limit(2)
+                .with_action(QuotaAction::Archive),
+        );
+        for content in ["1", "2", "3"] {
+            db.add_messages(vec![new_message(content)?]).await?;
+        }
+        let mut active = db
+            .load_messages(Filter::new().with_states(vec![State::Unread]))
+            .await?
+            .into_iter()
+            .map(|message| message.content)
+            .collect::<Vec<_>>();
+        active.sort();
+        assert_eq!(active, vec!["2", "3"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mailbox_configs() -> Result<()> {
+        let new_message = |state: Option<State>| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: "ci/build".try_into()?,
+                content: String::from("Build passed"),
+                state,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+
+        let db = Database::new(MemoryBackend::new());
+        let mailbox = Mailbox::try_from("ci/build")?;
+        let err = db
+            .set_mailbox_config(
+                mailbox.clone(),
+                MailboxConfig {
+                    quota: Some(0),
+                    ..Default::default()
+                },
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert_eq!(
+            db.load_mailbox_config(&mailbox).await?,
+            MailboxConfig::default()
+        );
+
+        db.set_mailbox_config(
+            mailbox.clone(),
+            MailboxConfig {
+                default_state: Some(State::Read),
+                quota: Some(2),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let added = db
+            .add_messages(vec![new_message(None)?, new_message(Some(State::Unread))?])
+            .await?;
+        assert_eq!(
+            added
+                .iter()
+                .map(|message| message.state)
+                .collect::<Vec<_>>(),
+            vec![State::Read, State::Unread]
+        );
+        let err = db
+            .add_messages(vec![new_message(None)?])
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert_eq!(
+            db.load_quota_statuses(&[mailbox]).await?,
+            vec![Some(QuotaStatus {
+                mailbox: "ci/build".try_into()?,
+                limit: 2,
+                used: 2,
+            })]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive_on_read() -> Result<()> {
+        let db = Database::new(MemoryBackend::new())
+            .with_archive_on_read(vec!["notifications".try_into()?]);
+        let new_message = |mailbox: &str| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: mailbox.try_into()?,
+                content: String::from("message"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+        db.add_messages(vec![
+            new_message("notifications/ci")?,
+            new_message("notifications")?,
+            new_message("notificationsx")?,
+            new_message("other")?,
+        ])
+        .await?;
+
+        let get_states = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .map(|message| (message.mailbox.to_string(), message.state))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            get_states(db.change_state(Filter::new(), State::Read).await?),
+            vec![
+                (String::from("notifications/ci"), State::Archived),
+                (String::from("notifications"), State::Archived),
+                (String::from("notificationsx"), State::Read),
+                (String::from("other"), State::Read),
+            ]
+        );
+
+        // Other state changes are unaffected
+        assert_eq!(
+            get_states(db.change_state(Filter::new(), State::Unread).await?),
+            vec![
+                (String::from("notifications/ci"), State::Unread),
+                (String::from("notifications"), State::Unread),
+                (String::from("notificationsx"), State::Unread),
+                (String::from("other"), State::Unread),
+            ]
+        );
+        Ok(())
+    }
+
+    // An observer that records every transition it is notified of
+    #[derive(Default)]
+    struct RecordingObserver(std::sync::Mutex<Vec<Transition>>);
+
+    impl TransitionObserver for RecordingObserver {
+        fn on_transitions(&self, transitions: &[Transition]) {
+            self.0.lock().unwrap().extend_from_slice(transitions);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transitions() -> Result<()> {
+        let observer = Arc::new(RecordingObserver::default());
+        let db = Database::new(MemoryBackend::new())
+            .with_archive_on_read(vec!["notifications".try_into()?])
+            .with_transition_policy(TransitionPolicy::new().forbid(State::Archived, State::Unread))
+            .with_observer(observer.clone());
+        let new_message = |mailbox: &str, state: State| -> Result<NewMessage> {
+            Ok(NewMessage {
+                mailbox: mailbox.try_into()?,
+                content: String::from("message"),
+                state: Some(state),
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            })
+        };
+        let added = db
+            .add_messages(vec![
+                new_message("notifications", State::Unread)?,
+                new_message("other", State::Unread)?,
+                new_message("other", State::Read)?,
+            ])
+            .await?;
+
+        // Observers are notified of the transitions made, including archive on read, but not of
+        // messages that were already in the new state
+        db.change_state(Filter::new(), State::Read).await?;
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec![
+                Transition {
+                    id: added[0].id,
+                    mailbox: "notifications".try_into()?,
+                    from: State::Unread,
+                    to: State::Archived,
+                },
+                Transition {
+                    id: added[1].id,
+                    mailbox: "other".try_into()?,
+                    from: State::Unread,
+                    to: State::Read,
+                },
+            ]
+        );
+
+        // Forbidden transitions fail without changing any messages or notifying observers
+        observer.0.lock().unwrap().clear();
+        let err = db
+            .change_state(Filter::new(), State::Unread)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert_eq!(
+            db.load_messages(Filter::new().with_states(vec![State::Unread]))
+                .await?
+                .len(),
+            0
+        );
+        assert!(observer.0.lock().unwrap().is_empty());
+
+        // Transitions that the policy allows still succeed
+        assert_eq!(
+            db.change_state(Filter::new().with_states(vec![State::Read]), State::Unread)
+                .await?
+                .len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_custom_states() -> Result<()> {
+        let observer = Arc::new(RecordingObserver::default());
+        let db = Database::new(MemoryBackend::new())
+            .with_transition_policy(TransitionPolicy::new().forbid(State::Archived, State::Read))
+            .with_observer(observer.clone());
+        let added = db
+            .add_messages(vec![
+                NewMessage {
+                    mailbox: "triage".try_into()?,
+                    content: String::from("message"),
+                    state: Some(State::Unread),
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
+                },
+                NewMessage {
+                    mailbox: "done".try_into()?,
+                    content: String::from("message"),
+                    state: Some(State::Archived),
+                    tags: vec![],
+                    timestamp: None,
+                    external_id: None,
+                    attachments: vec![],
+                    priority: None,
+                    kind: None,
+                },
+            ])
+            .await?;
+
+        let err = db
+            .change_custom_state(Filter::new(), String::from("In Progress"), State::Read)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+
+        // The transition policy applies to the custom state's base state
+        let err = db
+            .change_custom_state(Filter::new(), String::from("in-progress"), State::Read)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<ValidationError>().is_some());
+        assert!(observer.0.lock().unwrap().is_empty());
+
+        let changed = db
+            .change_custom_state(
+                Filter::new().with_mailbox("triage".try_into()?),
+                String::from("in-progress"),
+                State::Read,
+            )
+            .await?;
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].custom_state.as_deref(), Some("in-progress"));
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec![Transition {
+                id: added[0].id,
+                mailbox: "triage".try_into()?,
+                from: State::Unread,
+                to: State::Read,
+            }]
+        );
+        Ok(())
     }
 }
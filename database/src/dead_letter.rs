@@ -0,0 +1,64 @@
+use crate::mailbox::Mailbox;
+use serde::{Deserialize, Serialize};
+
+// Where claimed messages go after workers fail to process them too many times, so that a message
+// that always fails doesn't stay in the queue forever
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadLetterPolicy {
+    // The mailbox that failed messages are moved into
+    pub mailbox: Mailbox,
+    // The number of times that a message can be claimed and rejected before it is moved
+    pub max_attempts: u32,
+}
+
+impl Default for DeadLetterPolicy {
+    fn default() -> Self {
+        Self {
+            mailbox: Mailbox::default_dead_letter(),
+            max_attempts: 3,
+        }
+    }
+}
+
+impl DeadLetterPolicy {
+    // Determine whether a message that has been claimed this many times should be moved into the
+    // dead-letter mailbox when it is rejected
+    #[must_use]
+    pub fn is_exhausted(&self, attempts: u32) -> bool {
+        attempts >= self.max_attempts
+    }
+
+    // Generate the content of a failed message after it is moved into the dead-letter mailbox,
+    // which records where it came from and why it failed
+    #[must_use]
+    pub fn format_content(content: &str, mailbox: &Mailbox, attempts: u32, reason: &str) -> String {
+        format!("{content}\n\nFailed {attempts} times in {mailbox}: {reason}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_exhausted() {
+        let policy = DeadLetterPolicy::default();
+        assert!(!policy.is_exhausted(2));
+        assert!(policy.is_exhausted(3));
+    }
+
+    #[test]
+    fn test_format_content() -> anyhow::Result<()> {
+        assert_eq!(
+            DeadLetterPolicy::format_content(
+                "resize image",
+                &"jobs".try_into()?,
+                3,
+                "out of memory"
+            ),
+            "resize image\n\nFailed 3 times in jobs: out of memory"
+        );
+        Ok(())
+    }
+}
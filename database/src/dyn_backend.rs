@@ -0,0 +1,336 @@
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::backend::Backend;
+use crate::changes::{Changes, Seq};
+use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::filter::Filter;
+use crate::heartbeat::Heartbeat;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
+use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+// A version of Backend that can be used as a trait object because its methods return boxed futures
+// Every backend implements DynBackend, and Box<dyn DynBackend> implements Backend, so a database
+// can use a backend that is chosen at runtime without being generic over it.
+pub trait DynBackend: Send + Sync {
+    fn add_messages(&self, messages: Vec<NewMessage>) -> BoxFuture<'_, Vec<Message>>;
+    fn upsert_messages(&self, messages: Vec<NewMessage>) -> BoxFuture<'_, Vec<Message>>;
+    fn load_messages(&self, filter: Filter) -> BoxFuture<'_, Vec<Message>>;
+    fn change_state(&self, filter: Filter, new_state: State) -> BoxFuture<'_, Vec<Message>>;
+    fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> BoxFuture<'_, Vec<Message>>;
+    fn delete_messages(&self, filter: Filter) -> BoxFuture<'_, Vec<Message>>;
+    fn archive_messages(&self, filter: Filter, archiver: Archiver) -> BoxFuture<'_, Vec<Message>>;
+    fn load_mailboxes(&self, filter: Filter) -> BoxFuture<'_, Vec<MailboxInfo>>;
+    fn load_changes(&self, since: Seq) -> BoxFuture<'_, Changes>;
+    fn load_stats(&self, filter: Filter, bucket: Bucket) -> BoxFuture<'_, Vec<BucketStats>>;
+    fn load_attachments(&self, id: Id) -> BoxFuture<'_, Vec<Attachment>>;
+    fn record_view(&self, id: Id) -> BoxFuture<'_, Option<Message>>;
+    fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> BoxFuture<'_, Vec<Message>>;
+    fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> BoxFuture<'_, Option<Message>>;
+    fn remind_message(&self, id: Id, delay: Duration) -> BoxFuture<'_, Option<Message>>;
+    fn wake_reminders(&self) -> BoxFuture<'_, Vec<Message>>;
+    fn link_messages(&self, id: Id, related_id: Id) -> BoxFuture<'_, Vec<Message>>;
+    fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> BoxFuture<'_, Option<Heartbeat>>;
+    fn load_heartbeats(&self) -> BoxFuture<'_, Vec<Heartbeat>>;
+    fn load_mailbox_configs(&self) -> BoxFuture<'_, BTreeMap<Mailbox, MailboxConfig>>;
+    fn load_ingestion_configs(&self) -> BoxFuture<'_, BTreeMap<Mailbox, MailboxConfig>>;
+    fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> BoxFuture<'_, MailboxConfig>;
+}
+
+impl<B: Backend + Send + Sync> DynBackend for B {
+    fn add_messages(&self, messages: Vec<NewMessage>) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::add_messages(self, messages))
+    }
+
+    fn upsert_messages(&self, messages: Vec<NewMessage>) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::upsert_messages(self, messages))
+    }
+
+    fn load_messages(&self, filter: Filter) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::load_messages(self, filter))
+    }
+
+    fn change_state(&self, filter: Filter, new_state: State) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::change_state(self, filter, new_state))
+    }
+
+    fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::change_custom_state(
+            self,
+            filter,
+            custom_state,
+            new_state,
+        ))
+    }
+
+    fn delete_messages(&self, filter: Filter) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::delete_messages(self, filter))
+    }
+
+    fn archive_messages(&self, filter: Filter, archiver: Archiver) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::archive_messages(self, filter, archiver))
+    }
+
+    fn load_mailboxes(&self, filter: Filter) -> BoxFuture<'_, Vec<MailboxInfo>> {
+        Box::pin(Backend::load_mailboxes(self, filter))
+    }
+
+    fn load_changes(&self, since: Seq) -> BoxFuture<'_, Changes> {
+        Box::pin(Backend::load_changes(self, since))
+    }
+
+    fn load_stats(&self, filter: Filter, bucket: Bucket) -> BoxFuture<'_, Vec<BucketStats>> {
+        Box::pin(Backend::load_stats(self, filter, bucket))
+    }
+
+    fn load_attachments(&self, id: Id) -> BoxFuture<'_, Vec<Attachment>> {
+        Box::pin(Backend::load_attachments(self, id))
+    }
+
+    fn record_view(&self, id: Id) -> BoxFuture<'_, Option<Message>> {
+        Box::pin(Backend::record_view(self, id))
+    }
+
+    fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::claim_messages(self, filter, worker, lease))
+    }
+
+    fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> BoxFuture<'_, Option<Message>> {
+        Box::pin(Backend::nack_message(self, id, reason, dead_letter))
+    }
+
+    fn remind_message(&self, id: Id, delay: Duration) -> BoxFuture<'_, Option<Message>> {
+        Box::pin(Backend::remind_message(self, id, delay))
+    }
+
+    fn wake_reminders(&self) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::wake_reminders(self))
+    }
+
+    fn link_messages(&self, id: Id, related_id: Id) -> BoxFuture<'_, Vec<Message>> {
+        Box::pin(Backend::link_messages(self, id, related_id))
+    }
+
+    fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> BoxFuture<'_, Option<Heartbeat>> {
+        Box::pin(Backend::set_heartbeat(self, mailbox, interval))
+    }
+
+    fn load_heartbeats(&self) -> BoxFuture<'_, Vec<Heartbeat>> {
+        Box::pin(Backend::load_heartbeats(self))
+    }
+
+    fn load_mailbox_configs(&self) -> BoxFuture<'_, BTreeMap<Mailbox, MailboxConfig>> {
+        Box::pin(Backend::load_mailbox_configs(self))
+    }
+
+    fn load_ingestion_configs(&self) -> BoxFuture<'_, BTreeMap<Mailbox, MailboxConfig>> {
+        Box::pin(Backend::load_ingestion_configs(self))
+    }
+
+    fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> BoxFuture<'_, MailboxConfig> {
+        Box::pin(Backend::set_mailbox_config(self, mailbox, config))
+    }
+}
+
+impl Backend for Box<dyn DynBackend> {
+    fn add_messages(
+        &self,
+        messages: Vec<NewMessage>,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().add_messages(messages)
+    }
+
+    fn upsert_messages(
+        &self,
+        messages: Vec<NewMessage>,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().upsert_messages(messages)
+    }
+
+    fn load_messages(&self, filter: Filter) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().load_messages(filter)
+    }
+
+    fn change_state(
+        &self,
+        filter: Filter,
+        new_state: State,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().change_state(filter, new_state)
+    }
+
+    fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref()
+            .change_custom_state(filter, custom_state, new_state)
+    }
+
+    fn delete_messages(&self, filter: Filter) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().delete_messages(filter)
+    }
+
+    fn archive_messages(
+        &self,
+        filter: Filter,
+        archiver: Archiver,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().archive_messages(filter, archiver)
+    }
+
+    fn load_mailboxes(
+        &self,
+        filter: Filter,
+    ) -> impl Future<Output = Result<Vec<MailboxInfo>>> + Send {
+        self.as_ref().load_mailboxes(filter)
+    }
+
+    fn load_changes(&self, since: Seq) -> impl Future<Output = Result<Changes>> + Send {
+        self.as_ref().load_changes(since)
+    }
+
+    fn load_stats(
+        &self,
+        filter: Filter,
+        bucket: Bucket,
+    ) -> impl Future<Output = Result<Vec<BucketStats>>> + Send {
+        self.as_ref().load_stats(filter, bucket)
+    }
+
+    fn load_attachments(&self, id: Id) -> impl Future<Output = Result<Vec<Attachment>>> + Send {
+        self.as_ref().load_attachments(id)
+    }
+
+    fn record_view(&self, id: Id) -> impl Future<Output = Result<Option<Message>>> + Send {
+        self.as_ref().record_view(id)
+    }
+
+    fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().claim_messages(filter, worker, lease)
+    }
+
+    fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> impl Future<Output = Result<Option<Message>>> + Send {
+        self.as_ref().nack_message(id, reason, dead_letter)
+    }
+
+    fn remind_message(
+        &self,
+        id: Id,
+        delay: Duration,
+    ) -> impl Future<Output = Result<Option<Message>>> + Send {
+        self.as_ref().remind_message(id, delay)
+    }
+
+    fn wake_reminders(&self) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().wake_reminders()
+    }
+
+    fn link_messages(
+        &self,
+        id: Id,
+        related_id: Id,
+    ) -> impl Future<Output = Result<Vec<Message>>> + Send {
+        self.as_ref().link_messages(id, related_id)
+    }
+
+    fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> impl Future<Output = Result<Option<Heartbeat>>> + Send {
+        self.as_ref().set_heartbeat(mailbox, interval)
+    }
+
+    fn load_heartbeats(&self) -> impl Future<Output = Result<Vec<Heartbeat>>> + Send {
+        self.as_ref().load_heartbeats()
+    }
+
+    fn load_mailbox_configs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<Mailbox, MailboxConfig>>> + Send {
+        self.as_ref().load_mailbox_configs()
+    }
+
+    fn load_ingestion_configs(
+        &self,
+    ) -> impl Future<Output = Result<BTreeMap<Mailbox, MailboxConfig>>> + Send {
+        self.as_ref().load_ingestion_configs()
+    }
+
+    fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> impl Future<Output = Result<MailboxConfig>> + Send {
+        self.as_ref().set_mailbox_config(mailbox, config)
+    }
+}
@@ -0,0 +1,272 @@
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
+use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::filter::Filter;
+use crate::heartbeat::Heartbeat;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::memory_backend::MemoryBackend;
+use crate::message::{Id, Message, State};
+use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
+use crate::Backend;
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Script {
+    // How long each call waits before it completes
+    latency: Mutex<Duration>,
+
+    // The number of upcoming calls that will fail
+    failures: AtomicUsize,
+}
+
+// FakeBackend is a scriptable backend for tests. It stores canned messages in memory and can be
+// configured to delay its responses and to fail upcoming calls. Clones share the same messages and
+// script, so a test can keep a clone to adjust the script after moving the original into a
+// Database.
+#[derive(Clone, Default)]
+pub struct FakeBackend {
+    messages: Arc<MemoryBackend>,
+    script: Arc<Script>,
+}
+
+impl FakeBackend {
+    // Create a new FakeBackend with no messages that responds immediately
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Create a new FakeBackend containing the provided messages
+    pub async fn with_messages(messages: Vec<NewMessage>) -> Result<Self> {
+        let backend = Self::new();
+        backend.messages.add_messages(messages).await?;
+        Ok(backend)
+    }
+
+    // Make every subsequent call wait for the provided duration before completing
+    pub fn set_latency(&self, latency: Duration) {
+        *self
+            .script
+            .latency
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = latency;
+    }
+
+    // Make the next `count` calls fail
+    pub fn fail_next(&self, count: usize) {
+        self.script.failures.store(count, Ordering::SeqCst);
+    }
+
+    // Wait for the configured latency and then fail if a failure was scripted
+    async fn respond(&self) -> Result<()> {
+        let latency = *self
+            .script
+            .latency
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+
+        let failed = self
+            .script
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                failures.checked_sub(1)
+            })
+            .is_ok();
+        if failed {
+            bail!("Injected backend failure");
+        }
+        Ok(())
+    }
+}
+
+impl Backend for FakeBackend {
+    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.add_messages(messages).await
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.upsert_messages(messages).await
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.load_messages(filter).await
+    }
+
+    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.change_state(filter, new_state).await
+    }
+
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages
+            .change_custom_state(filter, custom_state, new_state)
+            .await
+    }
+
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.delete_messages(filter).await
+    }
+
+    async fn archive_messages(&self, filter: Filter, archiver: Archiver) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.archive_messages(filter, archiver).await
+    }
+
+    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+        self.respond().await?;
+        self.messages.load_mailboxes(filter).await
+    }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        self.respond().await?;
+        self.messages.load_changes(since).await
+    }
+
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        self.respond().await?;
+        self.messages.load_stats(filter, bucket).await
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        self.respond().await?;
+        self.messages.load_attachments(id).await
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        self.respond().await?;
+        self.messages.record_view(id).await
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.claim_messages(filter, worker, lease).await
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        self.respond().await?;
+        self.messages.nack_message(id, reason, dead_letter).await
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        self.respond().await?;
+        self.messages.remind_message(id, delay).await
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.wake_reminders().await
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        self.respond().await?;
+        self.messages.link_messages(id, related_id).await
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        self.respond().await?;
+        self.messages.set_heartbeat(mailbox, interval).await
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        self.respond().await?;
+        self.messages.load_heartbeats().await
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.respond().await?;
+        self.messages.load_mailbox_configs().await
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.respond().await?;
+        self.messages.load_ingestion_configs().await
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        self.respond().await?;
+        self.messages.set_mailbox_config(mailbox, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_canned_messages() -> Result<()> {
+        let backend = FakeBackend::with_messages(vec![NewMessage {
+            mailbox: "mailbox".try_into()?,
+            content: String::from("message"),
+            state: None,
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        }])
+        .await?;
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fail_next() -> Result<()> {
+        let backend = FakeBackend::new();
+        backend.fail_next(2);
+        assert!(backend.load_messages(Filter::new()).await.is_err());
+        assert!(backend.load_mailboxes(Filter::new()).await.is_err());
+        assert!(backend.load_messages(Filter::new()).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_latency() -> Result<()> {
+        let backend = FakeBackend::new();
+        backend.set_latency(Duration::from_millis(20));
+        let start = Instant::now();
+        backend.load_messages(Filter::new()).await?;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
+use crate::acl::{matches_pattern, pattern_to_glob};
 use crate::mailbox::Mailbox;
 use crate::message::{Id, Message, MessageIden, State};
-use sea_query::{Cond, Condition, Expr};
+use crate::tag::{Tag, MESSAGE_TAG_TABLE, TAG_TABLE};
+use chrono::NaiveDateTime;
+use sea_query::{Alias, BinOper, Cond, Condition, Expr, Order, Query};
 use serde::de::{self, Deserializer};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::str::FromStr;
 use std::string::ToString;
 
@@ -47,6 +51,18 @@ where
     .transpose()
 }
 
+// The field that loaded messages are ordered by
+// Timestamps sort newest first, mailboxes sort alphabetically, and states sort unread first and
+// then newest first. Ties are broken by id, newest first.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    Timestamp,
+    Mailbox,
+    State,
+    LastViewed,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 #[must_use]
@@ -69,6 +85,74 @@ pub struct Filter {
         default
     )]
     states: Option<Vec<State>>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_vec_to_csv",
+        deserialize_with = "deserialize_vec_from_csv",
+        default
+    )]
+    custom_states: Option<Vec<String>>,
+
+    // Custom states that matching messages must not be in, which lets commands skip messages whose
+    // custom state opts out of them
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_vec_to_csv",
+        deserialize_with = "deserialize_vec_from_csv",
+        default
+    )]
+    excluded_custom_states: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    since: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    before: Option<NaiveDateTime>,
+
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_vec_to_csv",
+        deserialize_with = "deserialize_vec_from_csv",
+        default
+    )]
+    tags: Option<Vec<Tag>>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    min_priority: Option<i32>,
+
+    // The order of loaded messages, which doesn't affect which messages match. Without a sort,
+    // messages are ordered by id, newest first.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    sort: Option<Sort>,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    reverse: bool,
+
+    // A page of loaded messages, which also doesn't affect which messages match and is ignored when
+    // changing or deleting messages. The offset skips that many messages in the sorted order and the
+    // limit caps how many are loaded after that.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    limit: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    offset: Option<usize>,
+
+    // Whether loaded mailboxes include their ancestors and count the messages in their
+    // descendants, which doesn't affect which messages match either
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    hierarchy_counts: bool,
+
+    // The consumer whose own message states are filtered, sorted, loaded, and changed instead of
+    // the messages' shared states. Messages that the consumer hasn't changed yet have their shared
+    // state.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    consumer: Option<String>,
+
+    // Glob patterns that matching messages' mailboxes must match at least one of, which the server
+    // uses to restrict API tokens to their mailboxes. Clients can't send them.
+    #[serde(skip)]
+    mailbox_patterns: Option<Vec<String>>,
 }
 
 // Filter is a consistent interface for filtering messages in Database methods.
@@ -99,32 +183,312 @@ impl Filter {
         self
     }
 
+    // Add a filter that only matches messages in at least one of the custom states
+    pub fn with_custom_states(mut self, custom_states: Vec<String>) -> Self {
+        self.custom_states = Some(custom_states);
+        self
+    }
+
+    // Add a filter that skips messages in any of the custom states, leaving the filter unrestricted
+    // if the list of custom states is empty
+    pub fn without_custom_states(mut self, custom_states: Vec<String>) -> Self {
+        self.excluded_custom_states = (!custom_states.is_empty()).then_some(custom_states);
+        self
+    }
+
     // Add IDs to a filter
     pub fn with_ids(mut self, ids: Vec<Id>) -> Self {
         self.ids = Some(ids);
         self
     }
 
+    // Add a filter that only matches messages with at least one of the tags
+    pub fn with_tags(mut self, tags: Vec<Tag>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    // Add a tags filter if the list of tags isn't empty
+    pub fn with_tags_option(self, tags: Vec<Tag>) -> Self {
+        if tags.is_empty() {
+            self
+        } else {
+            self.with_tags(tags)
+        }
+    }
+
+    // Add a filter that only matches messages created at or after a timestamp
+    pub fn with_since(mut self, since: NaiveDateTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    // Add a filter that only matches messages created before a timestamp
+    pub fn with_before(mut self, before: NaiveDateTime) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    // Add a filter that only matches messages with at least a certain priority
+    pub fn with_min_priority(mut self, min_priority: i32) -> Self {
+        self.min_priority = Some(min_priority);
+        self
+    }
+
+    // Add a minimum priority filter if the option is Some
+    pub fn with_min_priority_option(self, min_priority: Option<i32>) -> Self {
+        match min_priority {
+            Some(min_priority) => self.with_min_priority(min_priority),
+            None => self,
+        }
+    }
+
+    // Order loaded messages by a field
+    pub fn with_sort(mut self, sort: Sort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    // Order loaded messages by a field if the option is Some
+    pub fn with_sort_option(self, sort: Option<Sort>) -> Self {
+        match sort {
+            Some(sort) => self.with_sort(sort),
+            None => self,
+        }
+    }
+
+    // Reverse the order of loaded messages
+    pub fn with_reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    // Load at most a certain number of messages
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // Skip a certain number of messages before loading any
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    // Include ancestor mailboxes in loaded mailboxes and count the messages in their descendants
+    pub fn with_hierarchy_counts(mut self, hierarchy_counts: bool) -> Self {
+        self.hierarchy_counts = hierarchy_counts;
+        self
+    }
+
+    // Use a consumer's own message states instead of the shared message states
+    pub fn with_consumer(mut self, consumer: String) -> Self {
+        self.consumer = Some(consumer);
+        self
+    }
+
+    // Use a consumer's own message states if the filter doesn't already have a consumer and the
+    // option is Some
+    pub fn with_default_consumer(self, consumer: Option<&str>) -> Self {
+        match consumer {
+            Some(consumer) if self.consumer.is_none() => self.with_consumer(consumer.to_owned()),
+            _ => self,
+        }
+    }
+
+    // Only match messages in mailboxes that match at least one of the glob patterns
+    pub fn with_mailbox_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.mailbox_patterns = Some(patterns);
+        self
+    }
+
+    // Add a mailbox patterns filter if the option is Some
+    pub fn with_mailbox_patterns_option(self, patterns: Option<Vec<String>>) -> Self {
+        match patterns {
+            Some(patterns) => self.with_mailbox_patterns(patterns),
+            None => self,
+        }
+    }
+
+    // Return the consumer whose message states the filter uses
+    #[must_use]
+    pub fn get_consumer(&self) -> Option<&str> {
+        self.consumer.as_deref()
+    }
+
+    // Determine whether loaded mailboxes should include ancestors and count their descendants
+    #[must_use]
+    pub(crate) fn has_hierarchy_counts(&self) -> bool {
+        self.hierarchy_counts
+    }
+
+    // Return the range of sorted messages that should be loaded as an offset and optional limit
+    #[must_use]
+    pub(crate) fn get_page(&self) -> (usize, Option<usize>) {
+        (self.offset.unwrap_or_default(), self.limit)
+    }
+
+    // Remove the messages outside of the page from sorted messages
+    pub(crate) fn paginate_messages(&self, messages: &mut Vec<Message>) {
+        let (offset, limit) = self.get_page();
+        messages.drain(..offset.min(messages.len()));
+        if let Some(limit) = limit {
+            messages.truncate(limit);
+        }
+    }
+
+    // Generate the columns and directions that loaded messages are ordered by
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    #[must_use]
+    pub(crate) fn get_order_by(&self) -> Vec<(MessageIden, Order)> {
+        let mut columns = match self.sort {
+            None => vec![],
+            Some(Sort::Timestamp) => vec![(MessageIden::Timestamp, Order::Desc)],
+            Some(Sort::Mailbox) => vec![(MessageIden::Mailbox, Order::Asc)],
+            Some(Sort::State) => vec![
+                (MessageIden::State, Order::Asc),
+                (MessageIden::Timestamp, Order::Desc),
+            ],
+            // SQLite sorts NULL first, so messages that were never viewed come last
+            Some(Sort::LastViewed) => vec![(MessageIden::LastViewed, Order::Desc)],
+        };
+        columns.push((MessageIden::Id, Order::Desc));
+        if self.reverse {
+            for (_, order) in &mut columns {
+                *order = match order {
+                    Order::Asc => Order::Desc,
+                    _ => Order::Asc,
+                };
+            }
+        }
+        columns
+    }
+
+    // Sort messages in the order that the filter requests, matching the order of get_order_by
+    pub(crate) fn sort_messages(&self, messages: &mut [Message]) {
+        messages.sort_by(|message1, message2| {
+            let ordering = match self.sort {
+                None => Ordering::Equal,
+                Some(Sort::Timestamp) => message2.timestamp.cmp(&message1.timestamp),
+                Some(Sort::Mailbox) => message1.mailbox.cmp(&message2.mailbox),
+                Some(Sort::State) => u32::from(message1.state)
+                    .cmp(&u32::from(message2.state))
+                    .then_with(|| message2.timestamp.cmp(&message1.timestamp)),
+                Some(Sort::LastViewed) => message2.last_viewed.cmp(&message1.last_viewed),
+            }
+            .then_with(|| message2.id.cmp(&message1.id));
+            if self.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
     // Generate a sea-query where expression message filter
     #[must_use]
     pub fn get_where(self) -> Condition {
         Cond::all()
             .add_option(self.ids.map(|ids| Expr::col(MessageIden::Id).is_in(ids)))
             .add_option(self.mailbox.map(|mailbox| {
+                // Match descendant mailboxes with a range instead of LIKE so that the condition can
+                // use the mailbox index. "0" is the character after "/". The comparisons use the
+                // index's NOCASE collation so that they ignore case like LIKE does.
+                let column = || {
+                    Expr::expr(Expr::cust_with_expr(
+                        "? COLLATE NOCASE",
+                        Expr::col(MessageIden::Mailbox),
+                    ))
+                };
                 Cond::any()
-                    .add(Expr::col(MessageIden::Mailbox).like(format!("{mailbox}/%")))
-                    .add(Expr::col(MessageIden::Mailbox).eq(mailbox))
+                    .add(
+                        column()
+                            .gt(format!("{mailbox}/"))
+                            .and(column().lt(format!("{mailbox}0"))),
+                    )
+                    .add(column().eq(mailbox))
+            }))
+            .add_option(self.mailbox_patterns.map(|patterns| {
+                // Without any patterns, the empty IN matches nothing, like an empty ids filter
+                patterns.iter().fold(
+                    Cond::any().add(Expr::col(MessageIden::Mailbox).is_in(Vec::<String>::new())),
+                    |condition, pattern| {
+                        condition.add(
+                            Expr::col(MessageIden::Mailbox)
+                                .binary(BinOper::Custom("GLOB"), pattern_to_glob(pattern)),
+                        )
+                    },
+                )
             }))
             .add_option(
                 self.states
                     .map(|states| Expr::col(MessageIden::State).is_in(states.iter().copied())),
             )
+            .add_option(
+                self.custom_states
+                    .map(|custom_states| Expr::col(MessageIden::CustomState).is_in(custom_states)),
+            )
+            .add_option(self.excluded_custom_states.map(|custom_states| {
+                Cond::any()
+                    .add(Expr::col(MessageIden::CustomState).is_null())
+                    .add(Expr::col(MessageIden::CustomState).is_not_in(custom_states))
+            }))
+            .add_option(
+                self.since
+                    .map(|since| Expr::col(MessageIden::Timestamp).gte(since)),
+            )
+            .add_option(
+                self.before
+                    .map(|before| Expr::col(MessageIden::Timestamp).lt(before)),
+            )
+            .add_option(
+                self.min_priority
+                    .map(|min_priority| Expr::col(MessageIden::Priority).gte(min_priority)),
+            )
+            .add_option(self.tags.map(|tags| {
+                Expr::col(MessageIden::Id).in_subquery(
+                    Query::select()
+                        .column(Alias::new("message_id"))
+                        .from(Alias::new(MESSAGE_TAG_TABLE))
+                        .inner_join(
+                            Alias::new(TAG_TABLE),
+                            Expr::col((Alias::new(TAG_TABLE), Alias::new("id")))
+                                .equals((Alias::new(MESSAGE_TAG_TABLE), Alias::new("tag_id"))),
+                        )
+                        .and_where(Expr::col(Alias::new("name")).is_in(tags))
+                        .take(),
+                )
+            }))
+    }
+
+    // Determine whether the filter only restricts the mailbox and state, which means that it can be
+    // applied to tables other than the message table that have mailbox and state columns
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    #[must_use]
+    pub(crate) fn only_filters_mailbox_and_state(&self) -> bool {
+        self.ids.is_none()
+            && self.custom_states.is_none()
+            && self.excluded_custom_states.is_none()
+            && self.since.is_none()
+            && self.before.is_none()
+            && self.tags.is_none()
+            && self.min_priority.is_none()
+            && self.consumer.is_none()
     }
 
     // Determine whether a message filter is unrestricted and matches all messages
     #[must_use]
     pub fn matches_all(&self) -> bool {
-        self.ids.is_none() && self.mailbox.is_none() && self.states.is_none()
+        self.ids.is_none()
+            && self.mailbox.is_none()
+            && self.states.is_none()
+            && self.custom_states.is_none()
+            && self.excluded_custom_states.is_none()
+            && self.since.is_none()
+            && self.before.is_none()
+            && self.tags.is_none()
+            && self.min_priority.is_none()
+            && self.mailbox_patterns.is_none()
     }
 
     // Determine whether a message matches the filter
@@ -136,11 +500,22 @@ impl Filter {
             }
         }
         if let Some(mailbox) = self.mailbox.as_ref() {
-            if !(mailbox == &message.mailbox
-                || message
-                    .mailbox
-                    .as_ref()
-                    .starts_with(format!("{mailbox}/").as_str()))
+            // Ignore the case of ASCII letters like SQLite's NOCASE collation does
+            let prefix = format!("{mailbox}/");
+            let name = message.mailbox.as_ref();
+            if !(name.eq_ignore_ascii_case(mailbox.as_ref())
+                || name
+                    .as_bytes()
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix.as_bytes())))
+            {
+                return false;
+            }
+        }
+        if let Some(patterns) = self.mailbox_patterns.as_ref() {
+            if !patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, &message.mailbox))
             {
                 return false;
             }
@@ -150,15 +525,52 @@ impl Filter {
                 return false;
             }
         }
+        if let Some(custom_states) = self.custom_states.as_ref() {
+            if !message
+                .custom_state
+                .as_ref()
+                .is_some_and(|custom_state| custom_states.contains(custom_state))
+            {
+                return false;
+            }
+        }
+        if let Some(custom_states) = self.excluded_custom_states.as_ref() {
+            if message
+                .custom_state
+                .as_ref()
+                .is_some_and(|custom_state| custom_states.contains(custom_state))
+            {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if message.timestamp < since {
+                return false;
+            }
+        }
+        if let Some(before) = self.before {
+            if message.timestamp >= before {
+                return false;
+            }
+        }
+        if let Some(tags) = self.tags.as_ref() {
+            if !message.tags.iter().any(|tag| tags.contains(tag)) {
+                return false;
+            }
+        }
+        if let Some(min_priority) = self.min_priority {
+            if message.priority < min_priority {
+                return false;
+            }
+        }
         true
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::NaiveDateTime;
-
     use super::*;
+    use crate::message::Kind;
 
     fn get_message() -> Message {
         Message {
@@ -167,6 +579,14 @@ mod tests {
             mailbox: "parent/child".try_into().unwrap(),
             content: String::from("Content"),
             state: State::Unread,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            tags: vec!["urgent".try_into().unwrap()],
+            priority: 2,
+            kind: Kind::Text,
+            related_ids: vec![],
         }
     }
 
@@ -178,6 +598,11 @@ mod tests {
             .with_mailbox("foo".try_into().unwrap())
             .matches_all());
         assert!(!Filter::new().with_states(vec![State::Unread]).matches_all());
+        assert!(!Filter::new().with_since(NaiveDateTime::MIN).matches_all());
+        assert!(!Filter::new().with_before(NaiveDateTime::MAX).matches_all());
+        assert!(!Filter::new()
+            .with_tags(vec!["urgent".try_into().unwrap()])
+            .matches_all());
     }
 
     #[test]
@@ -209,6 +634,26 @@ mod tests {
         assert!(!Filter::new()
             .with_mailbox("parent/child/grandchild".try_into().unwrap())
             .matches_message(&message));
+        assert!(Filter::new()
+            .with_mailbox("Parent/CHILD".try_into().unwrap())
+            .matches_message(&message));
+        assert!(Filter::new()
+            .with_mailbox("PARENT".try_into().unwrap())
+            .matches_message(&message));
+    }
+
+    #[test]
+    fn test_matches_message_mailbox_patterns_filter() {
+        let message = get_message();
+        assert!(Filter::new()
+            .with_mailbox_patterns(vec![String::from("other"), String::from("parent/*")])
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_mailbox_patterns(vec![String::from("parent")])
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_mailbox_patterns(vec![])
+            .matches_message(&message));
     }
 
     #[test]
@@ -225,6 +670,85 @@ mod tests {
             .matches_message(&message));
     }
 
+    #[test]
+    fn test_matches_message_custom_states_filter() {
+        let message = Message {
+            custom_state: Some(String::from("in-progress")),
+            ..get_message()
+        };
+        assert!(Filter::new()
+            .with_custom_states(vec![String::from("in-progress")])
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_custom_states(vec![String::from("waiting")])
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_custom_states(vec![String::from("in-progress")])
+            .matches_message(&get_message()));
+        assert!(!Filter::new()
+            .without_custom_states(vec![String::from("in-progress")])
+            .matches_message(&message));
+        assert!(Filter::new()
+            .without_custom_states(vec![String::from("in-progress")])
+            .matches_message(&get_message()));
+        assert!(Filter::new().without_custom_states(vec![]).matches_all());
+    }
+
+    #[test]
+    fn test_matches_message_since_filter() {
+        let message = get_message();
+        assert!(Filter::new()
+            .with_since(NaiveDateTime::MIN)
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_since(NaiveDateTime::MAX)
+            .matches_message(&message));
+    }
+
+    #[test]
+    fn test_matches_message_before_filter() {
+        let message = get_message();
+        assert!(Filter::new()
+            .with_before(NaiveDateTime::MAX)
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_before(NaiveDateTime::MIN)
+            .matches_message(&message));
+    }
+
+    #[test]
+    fn test_matches_message_tags_filter() {
+        let message = get_message();
+        assert!(Filter::new()
+            .with_tags(vec!["urgent".try_into().unwrap()])
+            .matches_message(&message));
+        assert!(Filter::new()
+            .with_tags(vec![
+                "other".try_into().unwrap(),
+                "urgent".try_into().unwrap()
+            ])
+            .matches_message(&message));
+        assert!(!Filter::new()
+            .with_tags(vec!["other".try_into().unwrap()])
+            .matches_message(&message));
+        assert!(!Filter::new().with_tags(vec![]).matches_message(&message));
+    }
+
+    #[test]
+    fn test_matches_message_min_priority_filter() {
+        let message = get_message();
+        assert!(Filter::new()
+            .with_min_priority(-1)
+            .matches_message(&message));
+        assert!(Filter::new().with_min_priority(2).matches_message(&message));
+        assert!(!Filter::new().with_min_priority(3).matches_message(&message));
+        assert!(!Filter::new().with_min_priority(0).matches_all());
+        assert_eq!(
+            serde_urlencoded::to_string(Filter::new().with_min_priority(1)).unwrap(),
+            "min_priority=1"
+        );
+    }
+
     #[test]
     fn test_serialize_ids() {
         let filter = Filter::new().with_ids(vec![1]);
@@ -258,6 +782,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serialize_custom_states() {
+        let filter = Filter::new()
+            .with_custom_states(vec![String::from("in-progress")])
+            .without_custom_states(vec![String::from("waiting"), String::from("blocked")]);
+        assert_eq!(
+            serde_urlencoded::to_string(&filter).unwrap(),
+            "custom_states=in-progress&excluded_custom_states=waiting%2Cblocked"
+        );
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>(
+                "custom_states=in-progress&excluded_custom_states=waiting%2Cblocked"
+            )
+            .unwrap(),
+            filter
+        );
+    }
+
     #[test]
     fn test_serialize_multiple() {
         let filter = Filter::new()
@@ -313,6 +855,184 @@ mod tests {
         assert!(serde_urlencoded::from_str::<Filter>("states=unread,foo").is_err());
     }
 
+    #[test]
+    fn test_deserialize_tags() {
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("tags=urgent,ci/project-a").unwrap(),
+            Filter::new().with_tags(vec![
+                "urgent".try_into().unwrap(),
+                "ci/project-a".try_into().unwrap()
+            ])
+        );
+
+        assert!(serde_urlencoded::from_str::<Filter>("tags=a%20b").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_since() {
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("since=2024-01-02T03:04:05").unwrap(),
+            Filter::new().with_since(
+                NaiveDateTime::parse_from_str("2024-01-02 03:04:05", "%Y-%m-%d %H:%M:%S").unwrap()
+            )
+        );
+
+        assert!(serde_urlencoded::from_str::<Filter>("since=yesterday").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_before() {
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("before=2024-01-01T00:00:00").unwrap(),
+            Filter::new().with_before(
+                NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_serialize_sort() {
+        let filter = Filter::new().with_sort(Sort::LastViewed).with_reverse(true);
+        assert_eq!(
+            serde_urlencoded::to_string(&filter).unwrap(),
+            "sort=last-viewed&reverse=true"
+        );
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("sort=last-viewed&reverse=true").unwrap(),
+            filter
+        );
+        assert!(serde_urlencoded::from_str::<Filter>("sort=priority").is_err());
+    }
+
+    #[test]
+    fn test_sort_messages() {
+        let make_message = |id: Id, mailbox: &str, state: State| Message {
+            id,
+            mailbox: mailbox.try_into().unwrap(),
+            state,
+            ..get_message()
+        };
+        let mut messages = vec![
+            make_message(1, "b", State::Read),
+            make_message(2, "a", State::Unread),
+            make_message(3, "b", State::Unread),
+        ];
+        let get_ids = |messages: &[Message]| {
+            messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        };
+
+        Filter::new().sort_messages(&mut messages);
+        assert_eq!(get_ids(&messages), vec![3, 2, 1]);
+        Filter::new()
+            .with_reverse(true)
+            .sort_messages(&mut messages);
+        assert_eq!(get_ids(&messages), vec![1, 2, 3]);
+        Filter::new()
+            .with_sort(Sort::Mailbox)
+            .sort_messages(&mut messages);
+        assert_eq!(get_ids(&messages), vec![2, 3, 1]);
+        Filter::new()
+            .with_sort(Sort::State)
+            .with_reverse(true)
+            .sort_messages(&mut messages);
+        assert_eq!(get_ids(&messages), vec![1, 2, 3]);
+        assert!(Filter::new().with_sort(Sort::State).matches_all());
+
+        // Messages in the same state are sorted newest first before being sorted by id
+        messages[1].timestamp += chrono::Duration::days(1);
+        Filter::new()
+            .with_sort(Sort::State)
+            .sort_messages(&mut messages);
+        assert_eq!(get_ids(&messages), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_paginate_messages() {
+        let messages = (1..=5)
+            .map(|id| Message {
+                id,
+                ..get_message()
+            })
+            .collect::<Vec<_>>();
+        let paginate = |filter: Filter| {
+            let mut messages = messages.clone();
+            filter.paginate_messages(&mut messages);
+            messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paginate(Filter::new()), vec![1, 2, 3, 4, 5]);
+        assert_eq!(paginate(Filter::new().with_limit(2)), vec![1, 2]);
+        assert_eq!(paginate(Filter::new().with_offset(3)), vec![4, 5]);
+        assert_eq!(
+            paginate(Filter::new().with_offset(1).with_limit(2)),
+            vec![2, 3]
+        );
+        assert!(paginate(Filter::new().with_offset(10)).is_empty());
+        assert!(Filter::new().with_limit(1).matches_all());
+    }
+
+    #[test]
+    fn test_consumer() {
+        let filter = Filter::new().with_consumer(String::from("laptop"));
+        assert_eq!(
+            serde_urlencoded::to_string(&filter).unwrap(),
+            "consumer=laptop"
+        );
+        assert!(filter.matches_all());
+        assert_eq!(
+            filter
+                .clone()
+                .with_default_consumer(Some("desktop"))
+                .get_consumer(),
+            Some("laptop")
+        );
+        assert_eq!(
+            Filter::new()
+                .with_default_consumer(Some("desktop"))
+                .get_consumer(),
+            Some("desktop")
+        );
+        assert!(!filter.only_filters_mailbox_and_state());
+    }
+
+    #[test]
+    fn test_serialize_page() {
+        let filter = Filter::new().with_limit(100).with_offset(200);
+        assert_eq!(
+            serde_urlencoded::to_string(&filter).unwrap(),
+            "limit=100&offset=200"
+        );
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("limit=100&offset=200").unwrap(),
+            filter
+        );
+        assert!(serde_urlencoded::from_str::<Filter>("limit=-1").is_err());
+    }
+
+    #[test]
+    fn test_serialize_hierarchy_counts() {
+        let filter = Filter::new().with_hierarchy_counts(true);
+        assert_eq!(
+            serde_urlencoded::to_string(&filter).unwrap(),
+            "hierarchy_counts=true"
+        );
+        assert_eq!(
+            serde_urlencoded::from_str::<Filter>("hierarchy_counts=true").unwrap(),
+            filter
+        );
+        assert_eq!(
+            serde_urlencoded::to_string(Filter::new().with_hierarchy_counts(false)).unwrap(),
+            ""
+        );
+        assert!(filter.matches_all());
+    }
+
     #[test]
     fn test_deserialize_multiple() {
         assert_eq!(
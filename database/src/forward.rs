@@ -0,0 +1,313 @@
+use crate::acl::matches_pattern;
+use crate::message::{Id, Message, State};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// The push notification service that a forward sends messages to
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "service")]
+pub enum Service {
+    Ntfy {
+        // The URL of the topic, like https://ntfy.sh/my-topic
+        url: String,
+        // The access token for protected topics
+        token: Option<String>,
+    },
+    Gotify {
+        // The URL of the Gotify server
+        url: String,
+        // The application token
+        token: String,
+    },
+    Pushover {
+        // The application's API token
+        token: String,
+        // The user or group key that receives the notifications
+        user: String,
+    },
+}
+
+// Forwards new unread messages in matching mailboxes to a push notification service so that they
+// can appear as phone notifications
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Forward {
+    // The name that deliveries are tracked under
+    pub name: String,
+    #[serde(flatten)]
+    pub service: Service,
+    // Glob patterns of the mailboxes whose messages are forwarded
+    #[serde(default = "all_mailboxes")]
+    pub mailboxes: Vec<String>,
+    // The minimum priority of forwarded messages, which defaults to only forwarding high-priority
+    // messages
+    #[serde(default = "high_priority")]
+    pub min_priority: i32,
+}
+
+fn all_mailboxes() -> Vec<String> {
+    vec![String::from("*")]
+}
+
+const fn high_priority() -> i32 {
+    1
+}
+
+impl Forward {
+    // Determine whether a message should be forwarded
+    #[must_use]
+    pub fn matches(&self, message: &Message) -> bool {
+        message.state == State::Unread
+            && message.priority >= self.min_priority
+            && self
+                .mailboxes
+                .iter()
+                .any(|pattern| matches_pattern(pattern, &message.mailbox))
+    }
+}
+
+// A chat service that a bridge mirrors messages to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Chat {
+    Slack,
+    Discord,
+}
+
+impl Chat {
+    // The name that the bridge's deliveries are tracked under
+    #[must_use]
+    pub fn delivery_name(self) -> &'static str {
+        match self {
+            Self::Slack => "bridges.slack",
+            Self::Discord => "bridges.discord",
+        }
+    }
+
+    // The maximum length of a chat message in characters
+    #[must_use]
+    pub fn max_length(self) -> usize {
+        match self {
+            Self::Slack => 4000,
+            Self::Discord => 2000,
+        }
+    }
+
+    fn default_template(self) -> &'static str {
+        match self {
+            Self::Slack => "*{mailbox}*: {content}",
+            Self::Discord => "**{mailbox}**: {content}",
+        }
+    }
+}
+
+// Mirrors new unread messages in matching mailboxes to a chat channel through an incoming webhook
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Bridge {
+    pub webhook_url: String,
+    // Glob patterns of the mailboxes whose messages are mirrored
+    #[serde(default = "all_mailboxes")]
+    pub mailboxes: Vec<String>,
+    // The text of each message in the channel, where {id}, {mailbox}, {content}, {priority}, and
+    // {tags} are replaced with the message's fields
+    pub template: Option<String>,
+}
+
+impl Bridge {
+    // Determine whether a message should be mirrored
+    #[must_use]
+    pub fn matches(&self, message: &Message) -> bool {
+        message.state == State::Unread
+            && self
+                .mailboxes
+                .iter()
+                .any(|pattern| matches_pattern(pattern, &message.mailbox))
+    }
+
+    // Render a message with the bridge's template
+    // Template placeholders like "{mailbox}" look like format strings
+    #[allow(clippy::literal_string_with_formatting_args)]
+    #[must_use]
+    pub fn render(&self, chat: Chat, message: &Message) -> String {
+        let tags = message
+            .tags
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(", ");
+        // Replace the content last so that placeholders in the content are left alone
+        self.template
+            .as_deref()
+            .unwrap_or_else(|| chat.default_template())
+            .replace("{id}", &message.id.to_string())
+            .replace("{mailbox}", message.mailbox.as_ref())
+            .replace("{priority}", &message.priority.to_string())
+            .replace("{tags}", &tags)
+            .replace("{content}", &message.content)
+    }
+}
+
+// The chat channels that new messages are mirrored to
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bridges {
+    pub slack: Option<Bridge>,
+    pub discord: Option<Bridge>,
+}
+
+impl Bridges {
+    // Determine whether any bridges are configured
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.slack.is_none() && self.discord.is_none()
+    }
+
+    // Iterate over the configured bridges and their chat services
+    pub fn iter(&self) -> impl Iterator<Item = (Chat, &Bridge)> {
+        [(Chat::Slack, &self.slack), (Chat::Discord, &self.discord)]
+            .into_iter()
+            .filter_map(|(chat, bridge)| Some((chat, bridge.as_ref()?)))
+    }
+}
+
+// Whether a message has been delivered to a forward's service
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::Type))]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "sqlite", sqlx(rename_all = "lowercase"))]
+pub enum DeliveryStatus {
+    // The message hasn't been delivered yet but will be attempted again
+    Pending,
+    Delivered,
+    // Every attempt to deliver the message failed
+    Failed,
+}
+
+// The delivery of a message to a forward's service
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+pub struct ForwardDelivery {
+    pub message_id: Id,
+    pub forward: String,
+    pub status: DeliveryStatus,
+    // The number of times that delivery has been attempted
+    pub attempts: u32,
+    // Why the most recent attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    // When delivery will be attempted next, which is only meaningful for pending deliveries
+    pub next_attempt: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(mailbox: &str, state: State, priority: i32) -> Message {
+        Message {
+            id: 1,
+            timestamp: NaiveDateTime::default(),
+            mailbox: mailbox.try_into().unwrap(),
+            content: String::from("Build failed"),
+            state,
+            custom_state: None,
+            external_id: None,
+            view_count: 0,
+            last_viewed: None,
+            priority,
+            kind: crate::message::Kind::Text,
+            tags: vec!["ci".try_into().unwrap()],
+            related_ids: vec![],
+        }
+    }
+
+    fn forward(service: Service) -> Forward {
+        Forward {
+            name: String::from("phone"),
+            service,
+            mailboxes: vec![String::from("ci/*")],
+            min_priority: 1,
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let forward: Forward = serde_json::from_value(serde_json::json!({
+            "name": "phone",
+            "service": "pushover",
+            "token": "app",
+            "user": "me",
+        }))
+        .unwrap();
+        assert_eq!(
+            forward,
+            Forward {
+                name: String::from("phone"),
+                service: Service::Pushover {
+                    token: String::from("app"),
+                    user: String::from("me"),
+                },
+                mailboxes: vec![String::from("*")],
+                min_priority: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let forward = forward(Service::Ntfy {
+            url: String::from("https://ntfy.sh/topic"),
+            token: None,
+        });
+        assert!(forward.matches(&message("ci/build", State::Unread, 1)));
+        assert!(!forward.matches(&message("ci/build", State::Unread, 0)));
+        assert!(!forward.matches(&message("ci/build", State::Read, 2)));
+        assert!(!forward.matches(&message("deploys", State::Unread, 2)));
+    }
+
+    #[test]
+    fn test_render() {
+        let bridge = Bridge {
+            webhook_url: String::from("https://hooks.slack.com/services/T0/B0/X"),
+            mailboxes: vec![String::from("*")],
+            template: None,
+        };
+        let message = Message {
+            content: String::from("Missing {tags}"),
+            ..message("ci/build", State::Unread, 0)
+        };
+        assert_eq!(
+            bridge.render(Chat::Slack, &message),
+            "*ci/build*: Missing {tags}"
+        );
+        assert_eq!(
+            bridge.render(Chat::Discord, &message),
+            "**ci/build**: Missing {tags}"
+        );
+
+        let bridge = Bridge {
+            template: Some(String::from(
+                "#{id} [{tags}] p{priority} {mailbox}: {content}",
+            )),
+            ..bridge
+        };
+        assert_eq!(
+            bridge.render(Chat::Slack, &message),
+            "#1 [ci] p0 ci/build: Missing {tags}"
+        );
+    }
+
+    #[test]
+    fn test_bridges() {
+        let bridges: Bridges = serde_json::from_value(serde_json::json!({
+            "discord": { "webhook_url": "https://discord.com/api/webhooks/1/x" },
+        }))
+        .unwrap();
+        assert!(!bridges.is_empty());
+        assert_eq!(
+            bridges.iter().map(|(chat, _)| chat).collect::<Vec<_>>(),
+            vec![Chat::Discord]
+        );
+        assert!(Bridges::default().is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+use crate::changes::Seq;
+use crate::filter::Filter;
+use crate::forward::{Bridge, Bridges, Chat, Forward, ForwardDelivery, Service};
+use crate::message::{Id, Message};
+use crate::sqlite_backend::SqliteBackend;
+use crate::Backend;
+use anyhow::{Context, Result};
+use reqwest::{Client, RequestBuilder};
+use std::time::Duration;
+
+// The number of times that delivering a message is attempted before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+// How often new messages are checked for and failed deliveries are retried
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// The number of deliveries that are attempted in each poll
+const DELIVERY_BATCH_SIZE: usize = 100;
+
+// How long to wait between posts to a chat webhook, which keeps bridges within Slack's limit of one
+// message per second
+const BRIDGE_POST_INTERVAL: Duration = Duration::from_secs(1);
+
+// The maximum length of a Pushover message in characters
+const PUSHOVER_MAX_LENGTH: usize = 1024;
+
+// Determine how long to wait before retrying a delivery that has failed this many times, which
+// doubles after each failure starting at 30 seconds
+fn retry_delay(attempts: u32) -> Duration {
+    Duration::from_secs(30 << attempts.saturating_sub(1).min(10))
+}
+
+// Build the request that delivers a message to a forward's service
+fn build_request(client: &Client, forward: &Forward, message: &Message) -> RequestBuilder {
+    let title = message.mailbox.as_ref();
+    match &forward.service {
+        Service::Ntfy { url, token } => {
+            // ntfy priorities range from 1 to 5 with 3 as the default
+            let mut request = client
+                .post(url)
+                .header("Title", title)
+                .header("Priority", (message.priority + 3).clamp(1, 5))
+                .body(message.content.clone());
+            if !message.tags.is_empty() {
+                let tags = message
+                    .tags
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                request = request.header("Tags", tags);
+            }
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            request
+        }
+        Service::Gotify { url, token } => {
+            // Gotify priorities range from 0 to 10, and clients usually only make a sound for
+            // priorities of at least 4
+            client
+                .post(format!("{}/message", url.trim_end_matches('/')))
+                .header("X-Gotify-Key", token)
+                .json(&serde_json::json!({
+                    "title": title,
+                    "message": message.content,
+                    "priority": (message.priority * 2 + 5).clamp(0, 10),
+                }))
+        }
+        Service::Pushover { token, user } => {
+            // Emergency priority 2 requires acknowledgement, so the highest priority used is 1
+            let priority = message.priority.clamp(-2, 1).to_string();
+            let content = message
+                .content
+                .chars()
+                .take(PUSHOVER_MAX_LENGTH)
+                .collect::<String>();
+            client
+                .post("https://api.pushover.net/1/messages.json")
+                .form(&[
+                    ("token", token.as_str()),
+                    ("user", user.as_str()),
+                    ("title", title),
+                    ("message", content.as_str()),
+                    ("priority", priority.as_str()),
+                ])
+        }
+    }
+}
+
+// Sends new messages to the forwards' services and the bridges' chat channels, tracking each
+// delivery in the database so that failed deliveries are retried and administrators can see what
+// was delivered
+pub struct Forwarder {
+    client: Client,
+    forwards: Vec<Forward>,
+    bridges: Bridges,
+}
+
+impl Forwarder {
+    pub fn new(forwards: Vec<Forward>, bridges: Bridges) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to create forwarding HTTP client")?;
+        Ok(Self {
+            client,
+            forwards,
+            bridges,
+        })
+    }
+
+    // Forward messages that are added after the forwarder starts until the future is dropped
+    // Messages that already exist when it starts aren't forwarded.
+    pub async fn run(&self, backend: &SqliteBackend) -> Result<()> {
+        let mut seq = backend.load_changes(Seq::MAX).await?.seq;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.poll(backend, seq).await {
+                Ok(new_seq) => seq = new_seq,
+                Err(err) => eprintln!("Failed to forward messages: {err:#}"),
+            }
+        }
+    }
+
+    // Queue deliveries for the messages that changed after a sequence number and attempt the
+    // deliveries that are due, returning the sequence number of the latest change
+    async fn poll(&self, backend: &SqliteBackend, seq: Seq) -> Result<Seq> {
+        let changes = backend.load_changes(seq).await?;
+        self.enqueue(backend, &changes.messages).await?;
+        self.deliver_due(backend).await?;
+        Ok(changes.seq)
+    }
+
+    // Queue deliveries of the messages to the forwards and bridges that match them, returning the
+    // number of deliveries queued
+    // Messages that were already queued for a forward aren't queued again, even if they changed.
+    pub async fn enqueue(&self, backend: &SqliteBackend, messages: &[Message]) -> Result<usize> {
+        let deliveries = messages
+            .iter()
+            .flat_map(|message| {
+                let forwards = self
+                    .forwards
+                    .iter()
+                    .filter(|forward| forward.matches(message))
+                    .map(|forward| (message.id, forward.name.as_str()));
+                let bridges = self
+                    .bridges
+                    .iter()
+                    .filter(|(_, bridge)| bridge.matches(message))
+                    .map(|(chat, _)| (message.id, chat.delivery_name()));
+                forwards.chain(bridges)
+            })
+            .collect::<Vec<_>>();
+        backend.enqueue_forward_deliveries(&deliveries).await
+    }
+
+    // Attempt the pending deliveries that are due, returning the number that succeeded
+    pub async fn deliver_due(&self, backend: &SqliteBackend) -> Result<usize> {
+        let deliveries = backend
+            .load_due_forward_deliveries(DELIVERY_BATCH_SIZE)
+            .await?;
+        if deliveries.is_empty() {
+            return Ok(0);
+        }
+        let ids = deliveries
+            .iter()
+            .map(|delivery| delivery.message_id)
+            .collect::<Vec<Id>>();
+        let messages = backend.load_messages(Filter::new().with_ids(ids)).await?;
+
+        let mut delivered = 0;
+        // Bridges post all of their due messages together, so their deliveries are collected into
+        // batches and posted after the forwards' deliveries
+        let mut batches: Vec<Batch> = vec![];
+        for delivery in deliveries {
+            let forward = self
+                .forwards
+                .iter()
+                .find(|forward| forward.name == delivery.forward);
+            let bridge = self
+                .bridges
+                .iter()
+                .find(|(chat, _)| chat.delivery_name() == delivery.forward);
+            let message = messages
+                .iter()
+                .find(|message| message.id == delivery.message_id);
+            // Failures that retrying can't fix aren't retried
+            let result = match (forward, bridge, message) {
+                (_, _, None) => Err((String::from("The message was deleted"), false)),
+                (None, None, _) => {
+                    Err((String::from("The forward is no longer configured"), false))
+                }
+                (_, Some((chat, bridge)), Some(message)) => {
+                    let text = bridge.render(chat, message);
+                    match batches
+                        .iter_mut()
+                        .find(|(batch_chat, ..)| *batch_chat == chat)
+                    {
+                        Some((.., batch)) => batch.push((delivery, text)),
+                        None => batches.push((chat, bridge, vec![(delivery, text)])),
+                    }
+                    continue;
+                }
+                (Some(forward), None, Some(message)) => {
+                    send(build_request(&self.client, forward, message)).await
+                }
+            };
+            delivered += record_result(backend, &delivery, result).await?;
+        }
+        for (chat, bridge, batch) in batches {
+            delivered += self.deliver_batch(backend, chat, bridge, batch).await?;
+        }
+        Ok(delivered)
+    }
+
+    // Post a bridge's messages to its channel, combining as many as fit into each chat message and
+    // waiting between posts to stay within the webhook's rate limit, and return the number of
+    // messages delivered
+    async fn deliver_batch(
+        &self,
+        backend: &SqliteBackend,
+        chat: Chat,
+        bridge: &Bridge,
+        mut batch: Vec<(ForwardDelivery, String)>,
+    ) -> Result<usize> {
+        // Post the oldest messages first so that the channel reads chronologically
+        batch.sort_by_key(|(delivery, _)| delivery.message_id);
+        let mut delivered = 0;
+        for (index, chunk) in chunk_texts(chat.max_length(), batch)
+            .into_iter()
+            .enumerate()
+        {
+            if index > 0 {
+                tokio::time::sleep(BRIDGE_POST_INTERVAL).await;
+            }
+            let (deliveries, text) = chunk;
+            let body = match chat {
+                Chat::Slack => serde_json::json!({ "text": text }),
+                Chat::Discord => serde_json::json!({ "content": text }),
+            };
+            let result = send(self.client.post(&bridge.webhook_url).json(&body)).await;
+            for delivery in &deliveries {
+                delivered += record_result(backend, delivery, result.clone()).await?;
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+// A bridge's due deliveries and the rendered text of their messages
+type Batch<'a> = (Chat, &'a Bridge, Vec<(ForwardDelivery, String)>);
+
+// A failed delivery's error and whether retrying it could succeed
+type DeliveryError = (String, bool);
+
+// Send a delivery request, treating error statuses as retryable failures
+async fn send(request: RequestBuilder) -> Result<(), DeliveryError> {
+    request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map(|_| ())
+        .map_err(|err| (err.to_string(), true))
+}
+
+// Record the result of attempting a delivery, scheduling a retry if it failed and can be retried,
+// and return 1 if it was delivered
+async fn record_result(
+    backend: &SqliteBackend,
+    delivery: &ForwardDelivery,
+    result: Result<(), DeliveryError>,
+) -> Result<usize> {
+    match result {
+        Ok(()) => {
+            backend
+                .record_forward_delivered(delivery.message_id, &delivery.forward)
+                .await?;
+            Ok(1)
+        }
+        Err((error, retryable)) => {
+            let attempts = delivery.attempts + 1;
+            let retry_after = (retryable && attempts < MAX_ATTEMPTS).then(|| retry_delay(attempts));
+            backend
+                .record_forward_failure(delivery.message_id, &delivery.forward, &error, retry_after)
+                .await?;
+            Ok(0)
+        }
+    }
+}
+
+// Combine the texts of deliveries into chat messages that are each at most max_length characters,
+// truncating texts that are too long on their own
+fn chunk_texts<T>(max_length: usize, texts: Vec<(T, String)>) -> Vec<(Vec<T>, String)> {
+    let mut chunks: Vec<(Vec<T>, String)> = vec![];
+    for (item, text) in texts {
+        let text = text.chars().take(max_length).collect::<String>();
+        let length = text.chars().count();
+        match chunks.last_mut() {
+            Some((items, chunk)) if chunk.chars().count() + 1 + length <= max_length => {
+                items.push(item);
+                chunk.push('\n');
+                chunk.push_str(&text);
+            }
+            _ => chunks.push((vec![item], text)),
+        }
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forward::DeliveryStatus;
+    use crate::new_message::NewMessage;
+
+    fn forward(service: Service) -> Forward {
+        Forward {
+            name: String::from("phone"),
+            service,
+            mailboxes: vec![String::from("ci/*")],
+            min_priority: 1,
+        }
+    }
+
+    fn new_message(mailbox: &str, priority: i32) -> NewMessage {
+        NewMessage {
+            mailbox: mailbox.try_into().unwrap(),
+            content: String::from("Build failed"),
+            state: None,
+            tags: vec!["ci".try_into().unwrap()],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: Some(priority),
+            kind: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_request() -> Result<()> {
+        let client = Client::new();
+        let backend = SqliteBackend::new_test().await?;
+        let message = backend
+            .add_messages(vec![new_message("ci/build", 1)])
+            .await?
+            .remove(0);
+
+        let request = build_request(
+            &client,
+            &forward(Service::Ntfy {
+                url: String::from("https://ntfy.sh/topic"),
+                token: Some(String::from("secret")),
+            }),
+            &message,
+        )
+        .build()?;
+        assert_eq!(request.url().as_str(), "https://ntfy.sh/topic");
+        assert_eq!(request.headers()["Title"], "ci/build");
+        assert_eq!(request.headers()["Priority"], "4");
+        assert_eq!(request.headers()["Tags"], "ci");
+        assert_eq!(request.headers()["Authorization"], "Bearer secret");
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some(b"Build failed".as_slice())
+        );
+
+        let request = build_request(
+            &client,
+            &forward(Service::Gotify {
+                url: String::from("https://gotify.example.com/"),
+                token: String::from("app"),
+            }),
+            &message,
+        )
+        .build()?;
+        assert_eq!(request.url().as_str(), "https://gotify.example.com/message");
+        assert_eq!(request.headers()["X-Gotify-Key"], "app");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(
+                request.body().and_then(|body| body.as_bytes()).unwrap()
+            )?,
+            serde_json::json!({ "title": "ci/build", "message": "Build failed", "priority": 7 })
+        );
+
+        let request = build_request(
+            &client,
+            &forward(Service::Pushover {
+                token: String::from("app"),
+                user: String::from("me"),
+            }),
+            &message,
+        )
+        .build()?;
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some(b"token=app&user=me&title=ci%2Fbuild&message=Build+failed&priority=1".as_slice())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(30));
+        assert_eq!(retry_delay(2), Duration::from_mins(1));
+        assert_eq!(retry_delay(4), Duration::from_mins(4));
+    }
+
+    #[tokio::test]
+    async fn test_deliver_due() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        // Nothing listens on port 1, so every delivery fails
+        let forwarder = Forwarder::new(
+            vec![forward(Service::Ntfy {
+                url: String::from("http://127.0.0.1:1/topic"),
+                token: None,
+            })],
+            Bridges::default(),
+        )?;
+        let messages = backend
+            .add_messages(vec![
+                new_message("ci/build", 1),
+                new_message("ci/test", 0),
+                new_message("deploys", 2),
+            ])
+            .await?;
+        assert_eq!(forwarder.enqueue(&backend, &messages).await?, 1);
+        // Queuing the same messages again doesn't duplicate their deliveries
+        assert_eq!(forwarder.enqueue(&backend, &messages).await?, 0);
+
+        assert_eq!(forwarder.deliver_due(&backend).await?, 0);
+        let deliveries = backend.load_forward_deliveries(10).await?;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].forward, "phone");
+        assert_eq!(deliveries[0].status, DeliveryStatus::Pending);
+        assert_eq!(deliveries[0].attempts, 1);
+        assert!(deliveries[0].last_error.is_some());
+
+        // The failed delivery isn't due again until its retry delay passes
+        assert!(backend.load_due_forward_deliveries(10).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_texts() {
+        let texts = vec![
+            (1, String::from("aaaa")),
+            (2, String::from("bbb")),
+            (3, String::from("cccccccccccc")),
+            (4, String::from("dd")),
+        ];
+        assert_eq!(
+            chunk_texts(10, texts),
+            vec![
+                (vec![1, 2], String::from("aaaa\nbbb")),
+                (vec![3], String::from("cccccccccc")),
+                (vec![4], String::from("dd")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver_bridge_batch() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let forwarder = Forwarder::new(
+            vec![],
+            Bridges {
+                slack: Some(Bridge {
+                    webhook_url: String::from("http://127.0.0.1:1/services/T0/B0/X"),
+                    mailboxes: vec![String::from("ci/*")],
+                    template: None,
+                }),
+                discord: None,
+            },
+        )?;
+        let messages = backend
+            .add_messages(vec![
+                new_message("ci/build", 0),
+                new_message("ci/test", 0),
+                new_message("deploys", 0),
+            ])
+            .await?;
+        assert_eq!(forwarder.enqueue(&backend, &messages).await?, 2);
+
+        // Both messages fit in one post, so they fail together
+        assert_eq!(forwarder.deliver_due(&backend).await?, 0);
+        let deliveries = backend.load_forward_deliveries(10).await?;
+        assert_eq!(
+            deliveries
+                .iter()
+                .map(|delivery| (
+                    delivery.forward.as_str(),
+                    delivery.status,
+                    delivery.attempts
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("bridges.slack", DeliveryStatus::Pending, 1),
+                ("bridges.slack", DeliveryStatus::Pending, 1),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::mailbox::Mailbox;
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// A mailbox that is expected to receive a message at least once per interval, like the mailbox
+// that a cron job reports to, so that a job that stopped running can be noticed
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
+pub struct Heartbeat {
+    #[cfg_attr(feature = "sqlite", sqlx(try_from = "String"))]
+    pub mailbox: Mailbox,
+    // The longest that the mailbox is expected to go without a new message
+    pub interval_seconds: u32,
+    // When the mailbox became a heartbeat, which the interval is measured from until the mailbox
+    // receives its first message
+    pub created: NaiveDateTime,
+    // The timestamp of the newest message in the mailbox, not counting its descendants
+    pub last_message: Option<NaiveDateTime>,
+}
+
+// Format a number of seconds in the largest unit that represents it exactly, like "90m" or "26h"
+fn format_seconds(seconds: u64) -> String {
+    [("w", 7 * 24 * 60 * 60), ("d", 24 * 60 * 60), ("h", 60 * 60)]
+        .into_iter()
+        .find(|(_, unit_seconds)| seconds.is_multiple_of(*unit_seconds))
+        .map_or_else(
+            || format!("{}m", seconds / 60),
+            |(unit, unit_seconds)| format!("{}{unit}", seconds / unit_seconds),
+        )
+}
+
+// Format how long ago something happened in the largest whole unit, like "3h ago"
+fn format_age(age: TimeDelta) -> String {
+    let minutes = age.num_minutes();
+    if minutes < 1 {
+        String::from("just now")
+    } else if minutes < 60 {
+        format!("{minutes}m ago")
+    } else if minutes < 24 * 60 {
+        format!("{}h ago", minutes / 60)
+    } else {
+        format!("{}d ago", minutes / (24 * 60))
+    }
+}
+
+impl Heartbeat {
+    // Return the longest that the mailbox is expected to go without a new message
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_seconds.into())
+    }
+
+    // Return when the mailbox is due to receive its next message
+    #[must_use]
+    pub fn deadline(&self) -> NaiveDateTime {
+        self.last_message.unwrap_or(self.created) + TimeDelta::seconds(self.interval_seconds.into())
+    }
+
+    // Determine whether the mailbox has gone longer than its interval without a new message
+    #[must_use]
+    pub fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        now > self.deadline()
+    }
+
+    // Describe the expected interval, like "hourly" or "every 26h"
+    #[must_use]
+    pub fn describe_interval(&self) -> String {
+        match self.interval_seconds {
+            3600 => String::from("hourly"),
+            86_400 => String::from("daily"),
+            604_800 => String::from("weekly"),
+            seconds => format!("every {}", format_seconds(seconds.into())),
+        }
+    }
+
+    // Describe when the mailbox last received a message compared to how often it is expected to,
+    // like "last message 3h ago (expected hourly)"
+    #[must_use]
+    pub fn describe(&self, now: NaiveDateTime) -> String {
+        let last_message = self.last_message.map_or_else(
+            || String::from("no messages yet"),
+            |timestamp| format!("last message {}", format_age(now - timestamp)),
+        );
+        format!("{last_message} (expected {})", self.describe_interval())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 2)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn heartbeat(interval_seconds: u32, last_message_hours_ago: Option<i64>) -> Heartbeat {
+        Heartbeat {
+            mailbox: "cron/backup".try_into().unwrap(),
+            interval_seconds,
+            created: now() - TimeDelta::days(7),
+            last_message: last_message_hours_ago.map(|hours| now() - TimeDelta::hours(hours)),
+        }
+    }
+
+    #[test]
+    fn test_is_overdue() {
+        assert!(heartbeat(3600, Some(3)).is_overdue(now()));
+        assert!(!heartbeat(3600, Some(1)).is_overdue(now()));
+        assert!(!heartbeat(86_400, Some(23)).is_overdue(now()));
+        // Without any messages, the interval starts when the heartbeat was created
+        assert!(heartbeat(86_400, None).is_overdue(now()));
+        let heartbeat = Heartbeat {
+            created: now() - TimeDelta::hours(1),
+            ..heartbeat(86_400, None)
+        };
+        assert!(!heartbeat.is_overdue(now()));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            heartbeat(3600, Some(3)).describe(now()),
+            "last message 3h ago (expected hourly)"
+        );
+        assert_eq!(
+            heartbeat(86_400, Some(50)).describe(now()),
+            "last message 2d ago (expected daily)"
+        );
+        assert_eq!(
+            heartbeat(26 * 3600, None).describe(now()),
+            "no messages yet (expected every 26h)"
+        );
+        assert_eq!(
+            heartbeat(90 * 60, Some(0)).describe(now()),
+            "last message just now (expected every 90m)"
+        );
+        assert_eq!(heartbeat(604_800, None).describe_interval(), "weekly");
+        assert_eq!(heartbeat(14 * 86_400, None).describe_interval(), "every 2w");
+    }
+}
@@ -1,16 +1,198 @@
+use crate::acl::MailboxAcl;
+use crate::admin::{AdminStats, ApiToken, AuditEntry, Compaction};
+use crate::api::{
+    choose_api_version, get_endpoint_url, REQUEST_ID_HEADER, UNVERSIONED_API, VERSION_HEADER,
+};
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
 use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
 use crate::filter::Filter;
-use crate::message::{Message, State};
+use crate::forward::ForwardDelivery;
+use crate::heartbeat::Heartbeat;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
 use crate::new_message::NewMessage;
+use crate::signature::{sign_body, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER};
+use crate::stats::{Bucket, BucketStats};
 use crate::Backend;
 use anyhow::{anyhow, Context, Result};
-use reqwest::Response;
-use reqwest::{header::HeaderMap, Client};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, DATE, ETAG, IF_NONE_MATCH,
+};
+use reqwest::{Client, Url};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+// The number of times that idempotent requests are retried by default
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+// The delay before the first retry, which doubles after every attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+// The maximum delay between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+// Calculate how long to wait before retrying after the zero-based attempt failed
+// Half of the delay is random so that many clients don't all retry at the same time
+fn get_backoff(attempt: u32) -> Duration {
+    let backoff = INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    let half = backoff / 2;
+    let half_millis = u64::try_from(half.as_millis()).unwrap_or(u64::MAX);
+    let jitter = RandomState::new().build_hasher().finish() % (half_millis + 1);
+    half + Duration::from_millis(jitter)
+}
+
+// Request bodies smaller than this many bytes are sent uncompressed because compressing them
+// wouldn't save much
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+// Serialize a value into a JSON request body, gzipping it if it is large
+// Returns the body and whether it was compressed
+fn encode_json_body(value: &impl Serialize) -> Result<(Vec<u8>, bool)> {
+    let json = serde_json::to_vec(value).context("Failed to serialize request body")?;
+    if json.len() < COMPRESSION_THRESHOLD {
+        return Ok((json, false));
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&json)
+        .context("Failed to compress request body")?;
+    let compressed = encoder
+        .finish()
+        .context("Failed to compress request body")?;
+    Ok((compressed, true))
+}
+
+// Determine whether a response status indicates a transient failure that is worth retrying
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// The body of the server's version response
+#[derive(Deserialize)]
+struct VersionResponse {
+    api_versions: Vec<u32>,
+}
+
+// Generate a random identifier for a request
+#[must_use]
+pub fn generate_request_id() -> String {
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+// The context of errors caused by failing to get any response from the server, like connection
+// failures and timeouts
+#[derive(Debug)]
+pub struct ConnectionError {
+    request_id: String,
+}
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Request failed (request id {})", self.request_id)
+    }
+}
+
+// An error caused by the server responding with an unsuccessful status code
+#[derive(Debug)]
+pub struct ResponseError {
+    pub status: StatusCode,
+    message: String,
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+// The version of this client, which is released together with the server
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Parse a version like "1.2.3" into its numeric parts so that versions can be compared
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+// Explain a failure to parse a response, suggesting an upgrade if the server's version differs
+// from this client's version because the response format may have changed
+fn describe_parse_error(response: &str, server_version: Option<&str>) -> String {
+    let message = format!("Error parsing {response} response");
+    let Some(server_version) = server_version.filter(|version| *version != CLIENT_VERSION) else {
+        return message;
+    };
+    let upgrade = match (parse_version(server_version), parse_version(CLIENT_VERSION)) {
+        (Some(server), Some(client)) if server < client => "Try upgrading the mailbox server.",
+        _ => "Try upgrading mailbox.",
+    };
+    format!(
+        "{message}: the server is version {server_version}, but this client is version {CLIENT_VERSION}. {upgrade}"
+    )
+}
+
+// The maximum number of responses to keep in the ETag cache
+const MAX_CACHED_RESPONSES: usize = 16;
+
+// A response body and the ETag that the server sent with it
+#[derive(Clone)]
+struct CachedResponse {
+    etag: HeaderValue,
+    body: Vec<u8>,
+}
+
+// The server's response to a health check
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerStatus {
+    // Whether the server accepted the API token
+    pub authorized: bool,
+    // The server's version, if it reported one
+    pub version: Option<String>,
+    // The time on the server's clock when it responded, if it reported one
+    pub date: Option<DateTime<Utc>>,
+}
 
 pub struct HttpBackend {
     client: Client,
     api_url: String,
+    timeout: Option<Duration>,
+    max_retries: u32,
+    // The most recent responses to GET requests, keyed by URL
+    cache: Mutex<HashMap<String, CachedResponse>>,
+    // The API version negotiated with the server, which is determined by the first request
+    api_version: OnceCell<u32>,
+    // The token that authorizes requests to the server's admin routes
+    admin_token: Option<String>,
+    // The version that the server reported in its most recent response
+    server_version: Mutex<Option<String>>,
+    // The producer secret that new messages are signed with
+    signing_key: Option<String>,
 }
 
 impl HttpBackend {
@@ -31,19 +213,379 @@ impl HttpBackend {
                 .build()
                 .context("Failed to create HTTP client")?,
             api_url,
+            timeout: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            cache: Mutex::new(HashMap::new()),
+            api_version: OnceCell::new(),
+            admin_token: None,
+            server_version: Mutex::new(None),
+            signing_key: None,
+        })
+    }
+
+    // Configure how long to wait for each request to complete, None is no limit
+    #[must_use]
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    // Configure how many times idempotent requests are retried after transient failures
+    #[must_use]
+    pub fn with_max_retries(self, max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..self
+        }
+    }
+
+    // Configure the token used to authorize admin requests
+    #[must_use]
+    pub fn with_admin_token(self, admin_token: Option<String>) -> Self {
+        Self {
+            admin_token,
+            ..self
+        }
+    }
+
+    // Configure the secret that new messages are signed with so that servers that don't share their
+    // API token with this producer accept them
+    #[must_use]
+    pub fn with_signing_key(self, signing_key: Option<String>) -> Self {
+        Self {
+            signing_key,
+            ..self
+        }
+    }
+
+    // Load statistics about the server's database
+    pub async fn load_admin_stats(&self) -> Result<AdminStats> {
+        self.send_admin(Method::GET, "stats", None, true).await
+    }
+
+    // Reclaim unused space in the server's database
+    pub async fn compact(&self) -> Result<Compaction> {
+        self.send_admin(Method::POST, "compact", None, true).await
+    }
+
+    // Load the server's API tokens, without their secrets
+    pub async fn load_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        self.send_admin(Method::GET, "tokens", None, true).await
+    }
+
+    // Create an API token on the server that can access the mailboxes in the access list,
+    // returning it with its secret
+    pub async fn create_api_token(&self, name: &str, acl: &MailboxAcl) -> Result<ApiToken> {
+        self.send_admin(
+            Method::POST,
+            "tokens",
+            Some(json!({ "name": name, "read": acl.read, "write": acl.write })),
+            false,
+        )
+        .await
+    }
+
+    // Revoke one of the server's API tokens
+    pub async fn revoke_api_token(&self, name: &str) -> Result<ApiToken> {
+        self.send_admin(Method::DELETE, &format!("tokens/{name}"), None, true)
+            .await
+    }
+
+    // Load the most recent entries in the server's audit log, newest first
+    pub async fn load_audit_log(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        self.send_admin(Method::GET, &format!("audit?limit={limit}"), None, true)
+            .await
+    }
+
+    // Load the server's most recently updated forward deliveries, newest first
+    pub async fn load_forward_deliveries(&self, limit: usize) -> Result<Vec<ForwardDelivery>> {
+        self.send_admin(Method::GET, &format!("forwards?limit={limit}"), None, true)
+            .await
+    }
+
+    // Send a request to one of the server's admin routes and parse the response
+    async fn send_admin<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+        idempotent: bool,
+    ) -> Result<T> {
+        let admin_token = self
+            .admin_token
+            .as_ref()
+            .context("Admin requests require an admin token")?;
+        let url = format!("{}/admin/{path}", self.api_url);
+        let res = self
+            .send(
+                || {
+                    let request = self
+                        .client
+                        .request(method.clone(), &url)
+                        .bearer_auth(admin_token);
+                    match body.as_ref() {
+                        Some(body) => request.json(body),
+                        None => request,
+                    }
+                },
+                idempotent,
+            )
+            .await?;
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error(&format!("admin {path}")))
+    }
+
+    // Check that the server is reachable and whether it accepts the API token
+    // Connection failures are errors, but rejected tokens aren't
+    pub async fn check_server(&self) -> Result<ServerStatus> {
+        let res = self
+            .send(
+                || self.client.get(format!("{}/version", self.api_url)),
+                true,
+            )
+            .await?;
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        Ok(ServerStatus {
+            authorized: !matches!(
+                res.status(),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+            ),
+            version: header(VERSION_HEADER).map(ToOwned::to_owned),
+            date: header(DATE.as_str())
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc)),
         })
     }
 
+    // Return the API version negotiated with the server, or None if no requests have been made yet
+    #[must_use]
+    pub fn api_version(&self) -> Option<u32> {
+        self.api_version.get().copied()
+    }
+
+    // Determine which API version to use, asking the server the first time
+    // Servers from before the API was versioned don't have a version endpoint, so they only serve
+    // the unprefixed routes
+    async fn negotiate_api_version(&self) -> Result<u32> {
+        self.api_version
+            .get_or_try_init(|| async {
+                let res = self
+                    .send(
+                        || self.client.get(format!("{}/version", self.api_url)),
+                        true,
+                    )
+                    .await?;
+                if res.status() == StatusCode::NOT_FOUND {
+                    return Ok(UNVERSIONED_API);
+                }
+                if !res.status().is_success() {
+                    return Err(Self::make_error(res).await);
+                }
+                let version = res
+                    .json::<VersionResponse>()
+                    .await
+                    .with_context(|| self.parse_error("version"))?;
+                choose_api_version(&version.api_versions)
+            })
+            .await
+            .copied()
+    }
+
+    // Build the URL of an API endpoint in the negotiated API version
+    async fn get_url(&self, path: &str) -> Result<String> {
+        let api_version = self.negotiate_api_version().await?;
+        Ok(get_endpoint_url(&self.api_url, api_version, path))
+    }
+
+    // Send a request, retrying with exponential backoff after connection failures, timeouts, and
+    // transient server errors. Only idempotent requests are retried. make_request is called to
+    // build a fresh request for every attempt.
+    async fn send(
+        &self,
+        make_request: impl Fn() -> RequestBuilder,
+        idempotent: bool,
+    ) -> Result<Response> {
+        let max_retries = if idempotent { self.max_retries } else { 0 };
+        // Retries reuse the request id so that the server logs every attempt under the same id
+        let request_id = generate_request_id();
+        let mut attempt = 0;
+        loop {
+            let mut request = make_request().header(REQUEST_ID_HEADER, &request_id);
+            if let Some(timeout) = self.timeout {
+                request = request.timeout(timeout);
+            }
+            match request.send().await {
+                Ok(res) if is_transient_status(res.status()) && attempt < max_retries => {}
+                Ok(res) => {
+                    self.record_server_version(&res);
+                    return Ok(res);
+                }
+                Err(err) if (err.is_connect() || err.is_timeout()) && attempt < max_retries => {}
+                Err(err) => {
+                    return Err(anyhow::Error::from(err).context(ConnectionError { request_id }))
+                }
+            }
+            tokio::time::sleep(get_backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    // Remember the version that the server reported in a response
+    fn record_server_version(&self, res: &Response) {
+        if let Some(version) = res
+            .headers()
+            .get(VERSION_HEADER)
+            .and_then(|version| version.to_str().ok())
+        {
+            *self
+                .server_version
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(version.to_owned());
+        }
+    }
+
+    // Explain a failure to parse a response from the server
+    fn parse_error(&self, response: &str) -> String {
+        let server_version = self
+            .server_version
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone();
+        describe_parse_error(response, server_version.as_deref())
+    }
+
+    // Lock the response cache
+    fn lock_cache(&self) -> MutexGuard<'_, HashMap<String, CachedResponse>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    // Send a GET request and return the response body. If the server reports that the response
+    // hasn't changed since the last identical request, the cached body is returned instead of
+    // downloading it again.
+    async fn get_cached(&self, path: &str, filter: &Filter) -> Result<Vec<u8>> {
+        let url = self.get_url(path).await?;
+        let make_request = || self.client.get(&url).query(filter);
+        let key = make_request()
+            .build()
+            .context("Failed to build request")?
+            .url()
+            .to_string();
+        let cached = self.lock_cache().get(&key).cloned();
+        let res = self
+            .send(
+                || {
+                    cached.as_ref().map_or_else(make_request, |cached| {
+                        make_request().header(IF_NONE_MATCH, cached.etag.clone())
+                    })
+                },
+                true,
+            )
+            .await?;
+        if res.status() == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                return Ok(cached.body);
+            }
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+
+        let etag = res.headers().get(ETAG).cloned();
+        let body = res.bytes().await?.to_vec();
+        if let Some(etag) = etag {
+            let mut cache = self.lock_cache();
+            if cache.len() >= MAX_CACHED_RESPONSES && !cache.contains_key(&key) {
+                cache.clear();
+            }
+            cache.insert(
+                key,
+                CachedResponse {
+                    etag,
+                    body: body.clone(),
+                },
+            );
+        }
+        Ok(body)
+    }
+
+    // Send new messages to the server, optionally upserting them
+    async fn post_messages(&self, messages: Vec<NewMessage>, upsert: bool) -> Result<Vec<Message>> {
+        // Bulk imports can be large, so compress them to save bandwidth
+        let (body, compressed) = encode_json_body(&messages)?;
+        // The signature covers the uncompressed body, which is what the server verifies after
+        // decompressing it
+        let signature = match self.signing_key.as_ref() {
+            Some(key) => {
+                let timestamp = Utc::now().timestamp().to_string();
+                let signature = sign_body(key, &timestamp, &serde_json::to_vec(&messages)?)?;
+                Some((timestamp, signature))
+            }
+            None => None,
+        };
+        let url = self.get_url("messages").await?;
+        let res = self
+            .send(
+                || {
+                    let mut request = self
+                        .client
+                        .post(&url)
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(body.clone());
+                    if upsert {
+                        request = request.query(&[("upsert", true)]);
+                    }
+                    if let Some((timestamp, signature)) = signature.as_ref() {
+                        request = request
+                            .header(SIGNATURE_TIMESTAMP_HEADER, timestamp)
+                            .header(SIGNATURE_HEADER, signature);
+                    }
+                    if compressed {
+                        request.header(CONTENT_ENCODING, "gzip")
+                    } else {
+                        request
+                    }
+                },
+                // Upserting the same messages again has no additional effect, but adding them again
+                // would create duplicates
+                upsert,
+            )
+            .await?;
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("add messages"))
+    }
+
     // Generate an error from a failed response
+    // The server echoes the request id, which identifies the request in the server's logs
     async fn make_error(res: Response) -> anyhow::Error {
         let url = res.url().to_string();
         let status = res.status();
+        let request_id = res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|request_id| request_id.to_str().ok())
+            .map(|request_id| format!(" (request id {request_id})"))
+            .unwrap_or_default();
         match res.text().await {
-            Ok(body) => anyhow!(
-                "Request to {url} failed with status code {}\n\nResponse:{}",
+            Ok(body) => ResponseError {
                 status,
-                body
-            ),
+                message: format!(
+                    "Request to {url} failed with status code {status}{request_id}\n\nResponse:{body}"
+                ),
+            }
+            .into(),
             Err(err) => err.into(),
         }
     }
@@ -51,78 +593,431 @@ impl HttpBackend {
 
 impl Backend for HttpBackend {
     async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.post_messages(messages, false).await
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.post_messages(messages, true).await
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        let body = self.get_cached("messages", &filter).await?;
+        serde_json::from_slice(&body).with_context(|| self.parse_error("load messages"))
+    }
+
+    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        let url = self.get_url("messages").await?;
         let res = self
-            .client
-            .post(format!("{}/messages", self.api_url))
-            .json(&messages)
-            .send()
+            .send(
+                || {
+                    self.client
+                        .put(&url)
+                        .query(&filter)
+                        .json(&json!({ "new_state": new_state }))
+                },
+                true,
+            )
             .await?;
         if !res.status().is_success() {
             return Err(Self::make_error(res).await);
         }
         res.json()
             .await
-            .context("Error parsing add messages response")
+            .with_context(|| self.parse_error("change state"))
     }
 
-    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        let url = self.get_url("messages").await?;
         let res = self
-            .client
-            .get(format!("{}/messages", self.api_url))
-            .query(&filter)
-            .send()
+            .send(
+                || {
+                    self.client
+                        .put(&url)
+                        .query(&filter)
+                        .json(&json!({ "new_state": new_state, "custom_state": custom_state }))
+                },
+                true,
+            )
             .await?;
         if !res.status().is_success() {
             return Err(Self::make_error(res).await);
         }
         res.json()
             .await
-            .context("Error parsing load messages response")
+            .with_context(|| self.parse_error("change custom state"))
     }
 
-    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        let url = self.get_url("messages").await?;
         let res = self
-            .client
-            .put(format!("{}/messages", self.api_url))
-            .query(&filter)
-            .json(&json!({ "new_state": new_state }))
-            .send()
+            .send(|| self.client.delete(&url).query(&filter), true)
             .await?;
         if !res.status().is_success() {
             return Err(Self::make_error(res).await);
         }
         res.json()
             .await
-            .context("Error parsing change state response")
+            .with_context(|| self.parse_error("delete messages"))
     }
 
-    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+    async fn archive_messages(&self, _filter: Filter, _archiver: Archiver) -> Result<Vec<Message>> {
+        // The server can't hold a transaction open while the client writes the archive, so the
+        // messages couldn't be deleted atomically with archiving them
+        Err(anyhow!(
+            "Archiving messages to a file requires a local database instead of a server"
+        ))
+    }
+
+    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+        let body = self.get_cached("mailboxes", &filter).await?;
+        serde_json::from_slice(&body).with_context(|| self.parse_error("load mailboxes"))
+    }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        let url = self.get_url("changes").await?;
         let res = self
-            .client
-            .delete(format!("{}/messages", self.api_url))
-            .query(&filter)
-            .send()
+            .send(|| self.client.get(&url).query(&[("since", since)]), true)
             .await?;
         if !res.status().is_success() {
             return Err(Self::make_error(res).await);
         }
         res.json()
             .await
-            .context("Error parsing delete messages response")
+            .with_context(|| self.parse_error("load changes"))
     }
 
-    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        let body = self.get_cached(&format!("stats/{bucket}"), &filter).await?;
+        serde_json::from_slice(&body).with_context(|| self.parse_error("load stats"))
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        let url = self.get_url(&format!("messages/{id}/attachments")).await?;
+        let res = self.send(|| self.client.get(&url), true).await?;
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("load attachments"))
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        let url = self.get_url(&format!("messages/{id}/views")).await?;
+        // Recording a view isn't idempotent, so don't retry it
+        let res = self.send(|| self.client.post(&url), false).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("record view"))
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        let url = self.get_url("messages/claim").await?;
+        let res = self
+            .send(
+                || {
+                    self.client
+                        .post(&url)
+                        .query(&filter)
+                        .json(&json!({ "worker": worker, "lease_seconds": lease.as_secs() }))
+                },
+                // Claiming again would lease more messages to the worker
+                false,
+            )
+            .await?;
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("claim messages"))
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        let url = self.get_url(&format!("messages/{id}/nack")).await?;
+        let res = self
+            .send(
+                || {
+                    self.client
+                        .post(&url)
+                        .json(&json!({ "reason": reason, "dead_letter": dead_letter }))
+                },
+                // Rejecting again would fail because the first rejection released the lease
+                false,
+            )
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("reject message"))
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        let url = self.get_url(&format!("messages/{id}/remind")).await?;
+        let res = self
+            .send(
+                || {
+                    self.client
+                        .post(&url)
+                        .json(&json!({ "delay_seconds": delay.as_secs() }))
+                },
+                // Scheduling the same reminder again replaces it, but the delay would restart
+                false,
+            )
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("remind message"))
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        // The server wakes due reminders itself whenever it loads messages
+        Ok(vec![])
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        let url = self.get_url(&format!("messages/{id}/links")).await?;
+        let res = self
+            .send(
+                || {
+                    self.client
+                        .post(&url)
+                        .json(&json!({ "related_id": related_id }))
+                },
+                // Linking messages that are already linked doesn't change anything
+                true,
+            )
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("link messages"))
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        let url = self.get_url("heartbeats").await?;
+        let query = [("mailbox", mailbox.as_ref())];
+        // Setting the same interval again or removing a heartbeat again doesn't change anything
+        let res = match interval {
+            Some(interval) => {
+                self.send(
+                    || {
+                        self.client
+                            .put(&url)
+                            .query(&query)
+                            .json(&json!({ "interval_seconds": interval.as_secs() }))
+                    },
+                    true,
+                )
+                .await?
+            }
+            None => {
+                self.send(|| self.client.delete(&url).query(&query), true)
+                    .await?
+            }
+        };
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        if interval.is_none() {
+            return Ok(None);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("set heartbeat"))
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        let url = self.get_url("heartbeats").await?;
+        let res = self.send(|| self.client.get(&url), true).await?;
+        // Servers from before heartbeats were added don't have any
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("load heartbeats"))
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        let url = self.get_url("mailbox-configs").await?;
+        let res = self.send(|| self.client.get(&url), true).await?;
+        // Servers from before mailbox metadata was added don't have any
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(BTreeMap::new());
+        }
+        if !res.status().is_success() {
+            return Err(Self::make_error(res).await);
+        }
+        res.json()
+            .await
+            .with_context(|| self.parse_error("load mailbox configs"))
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        // The server applies the mailboxes' default states and quotas itself when messages are
+        // added, and producers that sign their messages can't read the mailbox metadata anyway
+        Ok(BTreeMap::new())
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        // Encode each section of the mailbox name separately so that the slashes between them
+        // stay in the path
+        let mut url = Url::parse(&self.get_url("mailboxes").await?)?;
+        url.path_segments_mut()
+            .map_err(|()| anyhow!("Invalid server URL"))?
+            .extend(mailbox.as_ref().split('/'));
+        // Setting the same config again doesn't change anything
         let res = self
-            .client
-            .get(format!("{}/mailboxes", self.api_url))
-            .query(&filter)
-            .send()
+            .send(|| self.client.put(url.clone()).json(&config), true)
             .await?;
         if !res.status().is_success() {
             return Err(Self::make_error(res).await);
         }
         res.json()
             .await
-            .context("Error parsing load mailboxes response")
+            .with_context(|| self.parse_error("set mailbox config"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        for attempt in 0..10 {
+            let expected = INITIAL_BACKOFF * 2u32.pow(attempt);
+            let backoff = get_backoff(attempt);
+            assert!(backoff >= expected.min(MAX_BACKOFF) / 2);
+            assert!(backoff <= expected.min(MAX_BACKOFF));
+        }
+    }
+
+    #[test]
+    fn test_is_transient_status() {
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_transient_status(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn test_encode_json_body() -> Result<()> {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let (body, compressed) = encode_json_body(&vec!["message"])?;
+        assert!(!compressed);
+        assert_eq!(body, br#"["message"]"#);
+
+        let messages = vec!["message"; 200];
+        let (body, compressed) = encode_json_body(&messages)?;
+        assert!(compressed);
+        let mut json = String::new();
+        GzDecoder::new(body.as_slice()).read_to_string(&mut json)?;
+        assert_eq!(serde_json::from_str::<Vec<String>>(&json)?, messages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_parse_error() {
+        assert_eq!(
+            describe_parse_error("load messages", None),
+            "Error parsing load messages response"
+        );
+        assert_eq!(
+            describe_parse_error("load messages", Some(CLIENT_VERSION)),
+            "Error parsing load messages response"
+        );
+        assert_eq!(
+            describe_parse_error("load messages", Some("1000.0.0")),
+            format!("Error parsing load messages response: the server is version 1000.0.0, but this client is version {CLIENT_VERSION}. Try upgrading mailbox.")
+        );
+        assert!(describe_parse_error("load messages", Some("0.0.1"))
+            .ends_with("Try upgrading the mailbox server."));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("0.8.3"), Some(vec![0, 8, 3]));
+        assert!(parse_version("0.10.0") > parse_version("0.9.1"));
+        assert_eq!(parse_version("1.0.0-beta"), None);
+    }
+
+    #[test]
+    fn test_generate_request_id() {
+        let request_id = generate_request_id();
+        assert_eq!(request_id.len(), 16);
+        assert!(request_id.chars().all(|char| char.is_ascii_hexdigit()));
+        assert_ne!(request_id, generate_request_id());
+    }
+
+    #[tokio::test]
+    async fn test_retry_connection_failure() -> Result<()> {
+        // Nothing listens on port 1, so every attempt fails to connect
+        let backend =
+            HttpBackend::new(String::from("http://127.0.0.1:1"), None)?.with_max_retries(1);
+        let start = std::time::Instant::now();
+        let err = backend.load_messages(Filter::new()).await.err().unwrap();
+        assert!(start.elapsed() >= INITIAL_BACKOFF / 2);
+        assert!(err.to_string().starts_with("Request failed (request id "));
+        assert!(err.downcast_ref::<ConnectionError>().is_some());
+
+        // Adding messages isn't idempotent, so it isn't retried
+        // Skip negotiating the API version, which would be retried
+        backend.api_version.set(1)?;
+        let start = std::time::Instant::now();
+        assert!(backend.add_messages(vec![]).await.is_err());
+        assert!(start.elapsed() < INITIAL_BACKOFF / 2);
+        Ok(())
     }
 }
@@ -5,20 +5,87 @@
     clippy::missing_errors_doc
 )]
 
+mod acl;
+mod admin;
+mod api;
+mod archive;
+mod attachment;
 mod backend;
+mod changes;
+mod custom_state;
 mod database;
+mod dead_letter;
+mod dyn_backend;
+#[cfg(any(test, feature = "test-utils"))]
+mod fake_backend;
 mod filter;
+mod forward;
+#[cfg(all(feature = "http", feature = "sqlite"))]
+mod forwarder;
+mod heartbeat;
+#[cfg(feature = "http")]
 mod http_backend;
+mod limits;
 mod mailbox;
+mod mailbox_config;
+mod memory_backend;
 mod message;
 mod new_message;
+mod quota;
+#[cfg(any(test, feature = "test-utils"))]
+mod recording_backend;
+mod signature;
+#[cfg(feature = "sqlite")]
 mod sqlite_backend;
+mod stats;
+mod tag;
+mod transition;
 
+pub use crate::acl::{matches_pattern, MailboxAcl};
+pub use crate::admin::{
+    AdminStats, ApiToken, AuditEntry, Compaction, DatabaseInfo, QueryResult, SchemaVersion,
+    WalStatus,
+};
+pub use crate::api::{
+    choose_api_version, get_endpoint_url, get_filtered_url, REQUEST_ID_HEADER,
+    SUPPORTED_API_VERSIONS, UNVERSIONED_API, VERSION_HEADER,
+};
+pub use crate::archive::{ArchivedMessage, Archiver};
+pub use crate::attachment::Attachment;
 pub use crate::backend::Backend;
+pub use crate::changes::{Changes, Seq};
+pub use crate::custom_state::{validate_custom_state, CustomState};
 pub use crate::database::{Database, MailboxInfo};
-pub use crate::filter::Filter;
-pub use crate::http_backend::HttpBackend;
+pub use crate::dead_letter::DeadLetterPolicy;
+pub use crate::dyn_backend::DynBackend;
+#[cfg(any(test, feature = "test-utils"))]
+pub use crate::fake_backend::FakeBackend;
+pub use crate::filter::{Filter, Sort};
+pub use crate::forward::{
+    Bridge, Bridges, Chat, DeliveryStatus, Forward, ForwardDelivery, Service,
+};
+#[cfg(all(feature = "http", feature = "sqlite"))]
+pub use crate::forwarder::Forwarder;
+pub use crate::heartbeat::Heartbeat;
+#[cfg(feature = "http")]
+pub use crate::http_backend::{
+    generate_request_id, ConnectionError, HttpBackend, ResponseError, ServerStatus,
+};
+pub use crate::limits::{Limits, MailboxCharset, ValidationError};
 pub use crate::mailbox::Mailbox;
-pub use crate::message::{Message, State};
+pub use crate::mailbox_config::MailboxConfig;
+pub use crate::memory_backend::MemoryBackend;
+pub use crate::message::{Id, Kind, Message, State};
 pub use crate::new_message::NewMessage;
+pub use crate::quota::{QuotaAction, QuotaPolicy, QuotaStatus};
+#[cfg(any(test, feature = "test-utils"))]
+pub use crate::recording_backend::{Call, RecordingBackend};
+pub use crate::signature::{
+    sign_body, sign_slack_request, verify_signature, verify_slack_signature, SIGNATURE_HEADER,
+    SIGNATURE_TIMESTAMP_HEADER, SLACK_SIGNATURE_HEADER, SLACK_TIMESTAMP_HEADER,
+};
+#[cfg(feature = "sqlite")]
 pub use crate::sqlite_backend::SqliteBackend;
+pub use crate::stats::{Bucket, BucketStats};
+pub use crate::tag::Tag;
+pub use crate::transition::{Transition, TransitionObserver, TransitionPolicy};
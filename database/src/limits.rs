@@ -0,0 +1,90 @@
+use serde::Deserialize;
+use std::fmt::{self, Display, Formatter};
+
+// An error caused by an invalid new message rather than by the database
+// Servers use it to distinguish bad requests from internal errors
+#[derive(Debug)]
+pub struct ValidationError(pub String);
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+// The characters that new mailbox names can contain, not counting the / between sections
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailboxCharset {
+    // Any characters that mailbox names can contain
+    #[default]
+    Unicode,
+
+    // ASCII letters, digits, "-", "_", and "."
+    Ascii,
+}
+
+impl MailboxCharset {
+    // Return true if mailbox names can contain the character
+    #[must_use]
+    pub const fn allows(self, character: char) -> bool {
+        match self {
+            Self::Unicode => true,
+            Self::Ascii => {
+                character.is_ascii_alphanumeric() || matches!(character, '-' | '_' | '.')
+            }
+        }
+    }
+}
+
+impl Display for MailboxCharset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unicode => write!(f, "unicode"),
+            Self::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+// Limits on new messages that protect the database and the programs that display its messages from
+// runaway scripts
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // The maximum length of a message's content in bytes
+    pub max_content_length: usize,
+
+    // The maximum number of messages that can be added at once
+    pub max_batch_size: usize,
+
+    // The maximum number of sections in a mailbox name, like 3 for "a/b/c"
+    pub max_mailbox_depth: usize,
+
+    // The maximum length of a mailbox name in bytes
+    pub max_mailbox_length: usize,
+
+    // The maximum length of each section of a mailbox name in bytes, like 1 for "a" in "a/b"
+    pub max_mailbox_section_length: usize,
+
+    // The characters that mailbox names can contain
+    pub mailbox_charset: MailboxCharset,
+
+    // The maximum size of a file attached to a message in bytes
+    pub max_attachment_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_content_length: 1024 * 1024,
+            max_batch_size: 100_000,
+            max_mailbox_depth: 16,
+            max_mailbox_length: 256,
+            max_mailbox_section_length: 256,
+            mailbox_charset: MailboxCharset::Unicode,
+            max_attachment_size: 1024 * 1024,
+        }
+    }
+}
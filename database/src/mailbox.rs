@@ -1,10 +1,16 @@
-use anyhow::bail;
+use crate::limits::{Limits, ValidationError};
 use sea_query::Value;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
 
+// Characters that new mailbox names can't contain because they are wildcards in mailbox glob
+// patterns. Parsing still accepts them so that patterns like "ci/*" can be parsed as mailboxes.
+const FORBIDDEN_CHARS: [char; 2] = ['*', '?'];
+
+// Deserializing a mailbox validates it like parsing one does
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String")]
 pub struct Mailbox(String);
 
 impl Mailbox {
@@ -18,7 +24,12 @@ impl Mailbox {
     // Return the name of the mailbox without its ancestors
     #[must_use]
     pub fn get_leaf_name(&self) -> &str {
-        self.0.split('/').last().unwrap_or_default()
+        self.0.split('/').next_back().unwrap_or_default()
+    }
+
+    // Return the mailbox that failed messages are moved into by default
+    pub(crate) fn default_dead_letter() -> Self {
+        Self(String::from("dead-letter"))
     }
 
     // Return true if the mailbox is an ancestor of the other mailbox
@@ -26,6 +37,57 @@ impl Mailbox {
     pub fn is_ancestor_of(&self, other: &Self) -> bool {
         other.0.starts_with(&format!("{}/", self.0))
     }
+
+    // Check that a new mailbox name has no wildcards and is within the configurable limits
+    // These checks only apply to new messages so that mailbox patterns can still be parsed and
+    // existing messages can still be loaded after the limits are tightened.
+    pub fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        let fail = |reason: String| Err(ValidationError(reason));
+        let mailbox = self.0.as_str();
+        if let Some(character) = mailbox
+            .chars()
+            .find(|character| FORBIDDEN_CHARS.contains(character))
+        {
+            return fail(format!("mailbox must not contain {character}"));
+        }
+        if mailbox.chars().any(char::is_control) {
+            return fail(String::from("mailbox must not contain control characters"));
+        }
+        if mailbox.len() > limits.max_mailbox_length {
+            return fail(format!(
+                "mailbox \"{mailbox}\" is {} bytes long, which exceeds the limit of {} bytes",
+                mailbox.len(),
+                limits.max_mailbox_length
+            ));
+        }
+        let depth = mailbox.split('/').count();
+        if depth > limits.max_mailbox_depth {
+            return fail(format!(
+                "mailbox \"{mailbox}\" has {depth} sections, which exceeds the limit of {} sections",
+                limits.max_mailbox_depth
+            ));
+        }
+        for section in mailbox.split('/') {
+            if section.len() > limits.max_mailbox_section_length {
+                return fail(format!(
+                    "mailbox section \"{section}\" is {} bytes long, which exceeds the limit of {} bytes",
+                    section.len(),
+                    limits.max_mailbox_section_length
+                ));
+            }
+        }
+        if let Some(character) = mailbox
+            .chars()
+            .find(|character| *character != '/' && !limits.mailbox_charset.allows(*character))
+        {
+            return fail(format!(
+                "mailbox \"{mailbox}\" contains {character:?}, which isn't allowed by the {} mailbox charset",
+                limits.mailbox_charset
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl AsRef<str> for Mailbox {
@@ -59,21 +121,22 @@ impl TryFrom<&str> for Mailbox {
 impl TryFrom<String> for Mailbox {
     type Error = anyhow::Error;
 
+    // Invalid mailboxes fail with a ValidationError so that servers can reject them as bad requests
     fn try_from(value: String) -> anyhow::Result<Self> {
+        let fail = |reason: &str| -> anyhow::Result<Self> {
+            Err(ValidationError(format!("mailbox {reason}")).into())
+        };
         if value.is_empty() {
-            bail!("mailbox must not be empty");
+            return fail("must not be empty");
         }
         if value.starts_with('/') {
-            bail!("mailbox must not start with /");
+            return fail("must not start with /");
         }
         if value.ends_with('/') {
-            bail!("mailbox must not end with /");
+            return fail("must not end with /");
         }
         if value.contains("//") {
-            bail!("mailbox must not contain //");
-        }
-        if value.contains('%') {
-            bail!("mailbox must not contain %");
+            return fail("must not contain //");
         }
 
         Ok(Self(value))
@@ -95,6 +158,7 @@ impl From<Mailbox> for Value {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::limits::MailboxCharset;
 
     #[test]
     fn test_iter_ancestors() {
@@ -131,6 +195,67 @@ mod tests {
         assert!(TryInto::<Mailbox>::try_into("mailbox/").is_err());
         assert!(TryInto::<Mailbox>::try_into("/mailbox").is_err());
         assert!(TryInto::<Mailbox>::try_into("parent//child").is_err());
-        assert!(TryInto::<Mailbox>::try_into("parent/%").is_err());
+        assert!(TryInto::<Mailbox>::try_into("parent/%").is_ok());
+        assert!(TryInto::<Mailbox>::try_into("parent/*").is_ok());
+        assert!(TryInto::<Mailbox>::try_into("parent/?").is_ok());
+        assert!(TryInto::<Mailbox>::try_into("parent//child")
+            .unwrap_err()
+            .is::<ValidationError>());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        assert!(serde_json::from_str::<Mailbox>("\"parent/child\"").is_ok());
+        assert!(serde_json::from_str::<Mailbox>("\"parent//child\"").is_err());
+        assert!(serde_json::from_str::<Mailbox>("\"parent/*\"").is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let limits = Limits {
+            max_mailbox_depth: 2,
+            max_mailbox_length: 12,
+            max_mailbox_section_length: 5,
+            ..Default::default()
+        };
+        let validate = |mailbox: &str| Mailbox::try_from(mailbox).unwrap().validate(&limits);
+        assert!(validate("a/b").is_ok());
+        assert_eq!(validate("a/*").unwrap_err().0, "mailbox must not contain *");
+        assert_eq!(validate("a/?").unwrap_err().0, "mailbox must not contain ?");
+        assert!(validate("a/\nb").is_err());
+        assert_eq!(
+            validate("a/b/c").unwrap_err().0,
+            "mailbox \"a/b/c\" has 3 sections, which exceeds the limit of 2 sections"
+        );
+        assert_eq!(
+            validate("aaaaaa/b").unwrap_err().0,
+            "mailbox section \"aaaaaa\" is 6 bytes long, which exceeds the limit of 5 bytes"
+        );
+        assert_eq!(
+            validate("aaaaa/bbbbbbb").unwrap_err().0,
+            "mailbox \"aaaaa/bbbbbbb\" is 13 bytes long, which exceeds the limit of 12 bytes"
+        );
+
+        assert!(validate("café").is_ok());
+        let limits = Limits {
+            mailbox_charset: MailboxCharset::Ascii,
+            ..Default::default()
+        };
+        assert!(Mailbox::try_from("ci/build-2_x.y")
+            .unwrap()
+            .validate(&limits)
+            .is_ok());
+        assert_eq!(
+            Mailbox::try_from("ci/café")
+                .unwrap()
+                .validate(&limits)
+                .unwrap_err()
+                .0,
+            "mailbox \"ci/café\" contains 'é', which isn't allowed by the ascii mailbox charset"
+        );
+        assert!(Mailbox::try_from("ci/a b")
+            .unwrap()
+            .validate(&limits)
+            .is_err());
     }
 }
@@ -0,0 +1,120 @@
+use crate::limits::ValidationError;
+use crate::message::State;
+use serde::{Deserialize, Serialize};
+
+// Metadata attached to a mailbox itself instead of being derived from its messages
+// Every field is optional, and a mailbox without any metadata has the default config.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailboxConfig {
+    // What the mailbox's messages are about
+    pub description: Option<String>,
+    // The color that clients show the mailbox in, like "red" or "#ff8800"
+    pub color: Option<String>,
+    // The state that new messages in the mailbox start in when they don't specify one
+    pub default_state: Option<State>,
+    // The maximum number of unread and read messages in the mailbox, not counting its descendants
+    pub quota: Option<u32>,
+    // The longest that the mailbox is expected to go without a new message
+    pub heartbeat_seconds: Option<u32>,
+    // Where the mailbox is listed among its pinned siblings, lowest first
+    pub pinned_order: Option<u32>,
+}
+
+impl MailboxConfig {
+    // Determine whether the config doesn't have any metadata, in which case it isn't stored
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    // Check that the metadata is reasonable
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self
+            .description
+            .as_ref()
+            .is_some_and(|description| description.chars().count() > 1000)
+        {
+            return Err(ValidationError(String::from(
+                "mailbox description must not be longer than 1000 characters",
+            )));
+        }
+        if self
+            .color
+            .as_ref()
+            .is_some_and(|color| color.is_empty() || color.chars().count() > 32)
+        {
+            return Err(ValidationError(String::from(
+                "mailbox color must be between 1 and 32 characters",
+            )));
+        }
+        if self.quota == Some(0) {
+            return Err(ValidationError(String::from(
+                "mailbox quota must be at least 1",
+            )));
+        }
+        if self
+            .heartbeat_seconds
+            .is_some_and(|heartbeat_seconds| heartbeat_seconds < 60)
+        {
+            return Err(ValidationError(String::from(
+                "heartbeat interval must be at least 1 minute",
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(MailboxConfig::default().validate().is_ok());
+        assert!(MailboxConfig {
+            description: Some(String::from("Nightly backups")),
+            color: Some(String::from("#ff8800")),
+            default_state: Some(State::Read),
+            quota: Some(100),
+            heartbeat_seconds: Some(86_400),
+            pinned_order: Some(0),
+        }
+        .validate()
+        .is_ok());
+        assert!(MailboxConfig {
+            color: Some(String::new()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(MailboxConfig {
+            quota: Some(0),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(MailboxConfig {
+            heartbeat_seconds: Some(30),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: MailboxConfig =
+            serde_json::from_str(r#"{ "description": "Backups", "default_state": "read" }"#)
+                .unwrap();
+        assert_eq!(
+            config,
+            MailboxConfig {
+                description: Some(String::from("Backups")),
+                default_state: Some(State::Read),
+                ..Default::default()
+            }
+        );
+        assert!(serde_json::from_str::<MailboxConfig>(r#"{ "colour": "red" }"#).is_err());
+    }
+}
@@ -0,0 +1,1549 @@
+use crate::archive::{ArchivedMessage, Archiver};
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
+use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::filter::Filter;
+use crate::heartbeat::Heartbeat;
+use crate::limits::ValidationError;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
+use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
+use crate::Backend;
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, SubsecRound, TimeDelta, Utc};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Store {
+    // The id that will be assigned to the next added message
+    next_id: Id,
+
+    // The messages, sorted by id ascending
+    messages: Vec<Message>,
+
+    // The sequence number of the most recent change
+    last_seq: Seq,
+
+    // The sequence number of the most recent change to each message
+    seqs: HashMap<Id, Seq>,
+
+    // The ids of deleted messages and the sequence numbers of their deletion
+    deleted: Vec<(Id, Seq)>,
+
+    // The files attached to each message that has attachments
+    attachments: HashMap<Id, Vec<Attachment>>,
+
+    // Each consumer's own states of the messages that it has changed
+    consumer_states: HashMap<String, HashMap<Id, State>>,
+
+    // The worker that claimed each leased message and when its lease expires
+    leases: HashMap<Id, (String, NaiveDateTime)>,
+
+    // The number of times that each message has been claimed
+    claim_attempts: HashMap<Id, u32>,
+
+    // When each message with a reminder returns to unread
+    reminders: HashMap<Id, NaiveDateTime>,
+
+    // The metadata of each mailbox that has any
+    mailbox_configs: BTreeMap<Mailbox, MailboxConfig>,
+
+    // When each heartbeat mailbox became a heartbeat
+    heartbeats_created: HashMap<Mailbox, NaiveDateTime>,
+}
+
+impl Store {
+    // Record a change to a message, returning its new sequence number
+    fn next_seq(&mut self, id: Id) -> Seq {
+        self.last_seq += 1;
+        self.seqs.insert(id, self.last_seq);
+        self.last_seq
+    }
+
+    // Build a heartbeat from its stored interval and creation time and its mailbox's newest message
+    fn heartbeat(
+        &self,
+        mailbox: &Mailbox,
+        interval_seconds: u32,
+        created: NaiveDateTime,
+    ) -> Heartbeat {
+        Heartbeat {
+            mailbox: mailbox.clone(),
+            interval_seconds,
+            created,
+            last_message: self
+                .messages
+                .iter()
+                .filter(|message| &message.mailbox == mailbox)
+                .map(|message| message.timestamp)
+                .max(),
+        }
+    }
+
+    // Replace a mailbox's metadata, removing it if it is empty
+    // Changing the interval of an existing heartbeat keeps its creation time.
+    fn store_mailbox_config(&mut self, mailbox: Mailbox, config: MailboxConfig) {
+        if config.heartbeat_seconds.is_some() {
+            self.heartbeats_created
+                .entry(mailbox.clone())
+                .or_insert_with(|| Utc::now().naive_utc().trunc_subsecs(0));
+        } else {
+            self.heartbeats_created.remove(&mailbox);
+        }
+        if config.is_empty() {
+            self.mailbox_configs.remove(&mailbox);
+        } else {
+            self.mailbox_configs.insert(mailbox, config);
+        }
+    }
+
+    // Build the heartbeats from the mailboxes' metadata
+    fn heartbeats(&self) -> impl Iterator<Item = Heartbeat> + '_ {
+        self.mailbox_configs.iter().filter_map(|(mailbox, config)| {
+            Some(self.heartbeat(
+                mailbox,
+                config.heartbeat_seconds?,
+                *self.heartbeats_created.get(mailbox)?,
+            ))
+        })
+    }
+
+    // Return a message with a consumer's own state instead of its shared state
+    fn scope_to_consumer<'a>(
+        &self,
+        message: &'a Message,
+        consumer: Option<&str>,
+    ) -> Cow<'a, Message> {
+        match consumer.and_then(|consumer| self.consumer_states.get(consumer)?.get(&message.id)) {
+            Some(&state) if state != message.state => Cow::Owned(Message {
+                state,
+                ..message.clone()
+            }),
+            _ => Cow::Borrowed(message),
+        }
+    }
+
+    // Remove messages and everything attached to them from the store
+    fn remove_messages(&mut self, removed: &[Message]) {
+        let ids = removed
+            .iter()
+            .map(|message| message.id)
+            .collect::<HashSet<_>>();
+        self.messages.retain(|message| !ids.contains(&message.id));
+        for message in &mut self.messages {
+            message.related_ids.retain(|id| !ids.contains(id));
+        }
+        for message in removed {
+            let seq = self.next_seq(message.id);
+            self.seqs.remove(&message.id);
+            self.attachments.remove(&message.id);
+            for states in self.consumer_states.values_mut() {
+                states.remove(&message.id);
+            }
+            self.leases.remove(&message.id);
+            self.claim_attempts.remove(&message.id);
+            self.reminders.remove(&message.id);
+            self.deleted.push((message.id, seq));
+        }
+    }
+
+    // Iterate over the messages that match the filter, with the filter consumer's own states
+    fn iter_matching<'a>(&'a self, filter: &'a Filter) -> impl Iterator<Item = Cow<'a, Message>> {
+        self.messages
+            .iter()
+            .map(|message| self.scope_to_consumer(message, filter.get_consumer()))
+            .filter(|message| filter.matches_message(message))
+    }
+}
+
+// MemoryBackend stores messages entirely in memory, so the messages are lost when it is dropped. It is
+// useful for ephemeral sessions and for tests that shouldn't touch the filesystem.
+#[derive(Default)]
+pub struct MemoryBackend {
+    store: Mutex<Store>,
+}
+
+impl MemoryBackend {
+    // Create a new MemoryBackend instance with no messages
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Lock the message store
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        // A panic while the lock was held can't leave the store in an inconsistent state, so ignore
+        // poisoning
+        self.store
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    // Sort messages in the same order that the SQLite backend returns them
+    fn sort_newest_first(messages: &mut [Message]) {
+        messages.sort_by_key(|message| std::cmp::Reverse(message.id));
+    }
+
+    // Add messages to the store, returning the added messages
+    // If upsert is true, messages with an external id that already exists in their mailbox update
+    // the existing message instead. Otherwise, duplicate external ids are an error like they are in
+    // the SQLite backend.
+    fn insert_messages(&self, messages: Vec<NewMessage>, upsert: bool) -> Result<Vec<Message>> {
+        if messages.iter().any(|message| message.content.is_empty()) {
+            bail!("Failed to add messages: content must not be empty");
+        }
+
+        let mut store = self.lock();
+        if !upsert {
+            // Check for duplicates up front so that a failed batch doesn't add any messages
+            let mut external_ids = store
+                .messages
+                .iter()
+                .filter_map(|message| Some((&message.mailbox, message.external_id.as_ref()?)))
+                .collect::<HashSet<_>>();
+            for message in &messages {
+                if let Some(external_id) = message.external_id.as_ref() {
+                    if !external_ids.insert((&message.mailbox, external_id)) {
+                        bail!(
+                            "Failed to add messages: external id {external_id} already exists in mailbox {}",
+                            message.mailbox
+                        );
+                    }
+                }
+            }
+        }
+
+        // Match the second-level precision of SQLite's CURRENT_TIMESTAMP
+        let timestamp = Utc::now().naive_utc().trunc_subsecs(0);
+        // Assign ids in reverse order like the SQLite backend so that the first message in the batch
+        // will appear first when the messages are loaded
+        let mut added = Vec::with_capacity(messages.len());
+        for message in messages.into_iter().rev() {
+            let kind = message.resolved_kind();
+            // Sort the tags like the SQLite backend
+            let mut tags = message.tags;
+            tags.sort();
+            tags.dedup();
+            let attachments = message.attachments;
+            let timestamp = message.timestamp.unwrap_or(timestamp);
+            let state = message.state.unwrap_or(State::Unread);
+            let priority = message.priority.unwrap_or_default();
+            let existing_index = message.external_id.as_ref().and_then(|external_id| {
+                store.messages.iter().position(|existing| {
+                    existing.mailbox == message.mailbox
+                        && existing.external_id.as_ref() == Some(external_id)
+                })
+            });
+            let message = if let Some(index) = existing_index {
+                let existing = &mut store.messages[index];
+                existing.timestamp = timestamp;
+                existing.content = message.content;
+                existing.state = state;
+                existing.custom_state = None;
+                existing.priority = priority;
+                existing.kind = kind;
+                existing.tags = tags;
+                existing.clone()
+            } else {
+                store.next_id += 1;
+                let message = Message {
+                    id: store.next_id,
+                    timestamp,
+                    mailbox: message.mailbox,
+                    content: message.content,
+                    state,
+                    custom_state: None,
+                    external_id: message.external_id,
+                    view_count: 0,
+                    last_viewed: None,
+                    priority,
+                    kind,
+                    tags,
+                    related_ids: vec![],
+                };
+                store.messages.push(message.clone());
+                message
+            };
+            store.next_seq(message.id);
+            // Upserted messages replace their existing attachments
+            if attachments.is_empty() {
+                store.attachments.remove(&message.id);
+            } else {
+                store.attachments.insert(message.id, attachments);
+            }
+            added.push(message);
+        }
+        drop(store);
+        added.reverse();
+        Ok(added)
+    }
+}
+
+impl Backend for MemoryBackend {
+    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.insert_messages(messages, false)
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.insert_messages(messages, true)
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        let mut messages = self
+            .lock()
+            .iter_matching(&filter)
+            .map(Cow::into_owned)
+            .collect::<Vec<_>>();
+        filter.sort_messages(&mut messages);
+        filter.paginate_messages(&mut messages);
+        Ok(messages)
+    }
+
+    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        let mut store = self.lock();
+        if let Some(consumer) = filter.get_consumer() {
+            let mut messages = store
+                .iter_matching(&filter)
+                .map(|message| Message {
+                    state: new_state,
+                    ..message.into_owned()
+                })
+                .collect::<Vec<_>>();
+            let states = store
+                .consumer_states
+                .entry(consumer.to_owned())
+                .or_default();
+            for message in &messages {
+                states.insert(message.id, new_state);
+            }
+            for message in &messages {
+                store.next_seq(message.id);
+            }
+            drop(store);
+            Self::sort_newest_first(&mut messages);
+            return Ok(messages);
+        }
+
+        let mut messages = store
+            .messages
+            .iter_mut()
+            .filter(|message| filter.matches_message(message))
+            .map(|message| {
+                message.state = new_state;
+                message.custom_state = None;
+                message.clone()
+            })
+            .collect::<Vec<_>>();
+        for message in &messages {
+            store.next_seq(message.id);
+        }
+        drop(store);
+        Self::sort_newest_first(&mut messages);
+        Ok(messages)
+    }
+
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        if filter.get_consumer().is_some() {
+            return Err(
+                ValidationError(String::from("custom states are shared by all consumers")).into(),
+            );
+        }
+
+        let mut store = self.lock();
+        let mut messages = store
+            .messages
+            .iter_mut()
+            .filter(|message| filter.matches_message(message))
+            .map(|message| {
+                message.state = new_state;
+                message.custom_state = Some(custom_state.clone());
+                message.clone()
+            })
+            .collect::<Vec<_>>();
+        for message in &messages {
+            store.next_seq(message.id);
+        }
+        drop(store);
+        Self::sort_newest_first(&mut messages);
+        Ok(messages)
+    }
+
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        let mut store = self.lock();
+        let mut deleted = store
+            .iter_matching(&filter)
+            .map(Cow::into_owned)
+            .collect::<Vec<_>>();
+        store.remove_messages(&deleted);
+        drop(store);
+        Self::sort_newest_first(&mut deleted);
+        Ok(deleted)
+    }
+
+    async fn archive_messages(&self, filter: Filter, archiver: Archiver) -> Result<Vec<Message>> {
+        // Hold the lock while the archiver runs so that the messages can't change before they are
+        // deleted
+        let mut store = self.lock();
+        let mut messages = store
+            .iter_matching(&filter)
+            .map(Cow::into_owned)
+            .collect::<Vec<_>>();
+        filter.sort_messages(&mut messages);
+        filter.paginate_messages(&mut messages);
+        if messages.is_empty() {
+            return Ok(messages);
+        }
+
+        let archived_messages = messages
+            .iter()
+            .map(|message| ArchivedMessage {
+                message: message.clone(),
+                attachments: store
+                    .attachments
+                    .get(&message.id)
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        archiver(&archived_messages)?;
+        store.remove_messages(&messages);
+        drop(store);
+        Ok(messages)
+    }
+
+    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+        let mut counts = BTreeMap::<Mailbox, usize>::new();
+        for message in self.lock().iter_matching(&filter) {
+            if filter.has_hierarchy_counts() {
+                for mailbox in message.mailbox.iter_ancestors() {
+                    *counts.entry(mailbox).or_default() += 1;
+                }
+            } else {
+                *counts.entry(message.mailbox.clone()).or_default() += 1;
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(name, message_count)| MailboxInfo {
+                name,
+                message_count,
+            })
+            .collect())
+    }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        let store = self.lock();
+        let mut messages = store
+            .messages
+            .iter()
+            .filter(|message| store.seqs.get(&message.id).is_some_and(|&seq| seq > since))
+            .cloned()
+            .collect::<Vec<_>>();
+        let deleted = store
+            .deleted
+            .iter()
+            .filter(|(_, seq)| *seq > since)
+            .map(|(id, _)| *id)
+            .collect();
+        let seq = store.last_seq;
+        drop(store);
+        Self::sort_newest_first(&mut messages);
+        Ok(Changes {
+            seq,
+            messages,
+            deleted,
+        })
+    }
+
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        let mut stats = BTreeMap::<(std::cmp::Reverse<_>, Mailbox), BucketStats>::new();
+        for message in self.lock().iter_matching(&filter) {
+            let start = bucket.truncate(message.timestamp);
+            let entry = stats
+                .entry((std::cmp::Reverse(start), message.mailbox.clone()))
+                .or_insert_with(|| BucketStats {
+                    bucket: start,
+                    mailbox: message.mailbox.clone(),
+                    added: 0,
+                    read: 0,
+                    archived: 0,
+                });
+            entry.added += 1;
+            match message.state {
+                State::Unread => {}
+                State::Read => entry.read += 1,
+                State::Archived => entry.archived += 1,
+            }
+        }
+        Ok(stats.into_values().collect())
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        Ok(self
+            .lock()
+            .attachments
+            .get(&id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        // Match the second-level precision of SQLite's CURRENT_TIMESTAMP
+        let timestamp = Utc::now().naive_utc().trunc_subsecs(0);
+        Ok(self
+            .lock()
+            .messages
+            .iter_mut()
+            .find(|message| message.id == id)
+            .map(|message| {
+                message.view_count += 1;
+                message.last_viewed = Some(timestamp);
+                message.clone()
+            }))
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        let now = Utc::now().naive_utc().trunc_subsecs(0);
+        let lease_expires = now + TimeDelta::from_std(lease)?;
+        let mut store = self.lock();
+
+        // Return the messages whose leases expired to the queue
+        let expired = store
+            .leases
+            .iter()
+            .filter(|(_, (_, expires))| *expires <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            store.leases.remove(&id);
+            if let Some(message) = store.messages.iter_mut().find(|message| message.id == id) {
+                if message.state == State::Read {
+                    message.state = State::Unread;
+                    message.custom_state = None;
+                    store.next_seq(id);
+                }
+            }
+        }
+
+        // Claim the most urgent unread messages first, and the oldest first within a priority
+        let (_, limit) = filter.get_page();
+        let mut candidates = store
+            .messages
+            .iter_mut()
+            .filter(|message| message.state == State::Unread && filter.matches_message(message))
+            .collect::<Vec<_>>();
+        // The messages are sorted by id, and the sort is stable
+        candidates.sort_by_key(|message| std::cmp::Reverse(message.priority));
+        let messages = candidates
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|message| {
+                message.state = State::Read;
+                message.custom_state = None;
+                message.clone()
+            })
+            .collect::<Vec<_>>();
+        for message in &messages {
+            store
+                .leases
+                .insert(message.id, (worker.clone(), lease_expires));
+            *store.claim_attempts.entry(message.id).or_default() += 1;
+            store.next_seq(message.id);
+        }
+        drop(store);
+        Ok(messages)
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        let mut store = self.lock();
+        if !store.messages.iter().any(|message| message.id == id) {
+            return Ok(None);
+        }
+        if store.leases.remove(&id).is_none() {
+            return Err(ValidationError(format!("Message {id} isn't claimed")).into());
+        }
+
+        let attempts = store.claim_attempts.get(&id).copied().unwrap_or_default();
+        if dead_letter.is_exhausted(attempts) {
+            store.claim_attempts.remove(&id);
+        }
+        let message = store
+            .messages
+            .iter_mut()
+            .find(|message| message.id == id)
+            .map(|message| {
+                if dead_letter.is_exhausted(attempts) {
+                    message.content = DeadLetterPolicy::format_content(
+                        &message.content,
+                        &message.mailbox,
+                        attempts,
+                        &reason,
+                    );
+                    message.mailbox = dead_letter.mailbox;
+                }
+                message.state = State::Unread;
+                message.custom_state = None;
+                message.clone()
+            });
+        store.next_seq(id);
+        drop(store);
+        Ok(message)
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        let remind_at = Utc::now().naive_utc().trunc_subsecs(0) + TimeDelta::from_std(delay)?;
+        let mut store = self.lock();
+        let Some(message) = store.messages.iter_mut().find(|message| message.id == id) else {
+            return Ok(None);
+        };
+        // Move unread messages out of the way until the reminder is due
+        let changed = message.state == State::Unread;
+        if changed {
+            message.state = State::Read;
+            message.custom_state = None;
+        }
+        let message = message.clone();
+        if changed {
+            store.next_seq(id);
+        }
+        store.reminders.insert(id, remind_at);
+        drop(store);
+        Ok(Some(message))
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        let now = Utc::now().naive_utc().trunc_subsecs(0);
+        let mut store = self.lock();
+        let due = store
+            .reminders
+            .iter()
+            .filter(|(_, remind_at)| **remind_at <= now)
+            .map(|(id, _)| *id)
+            .collect::<HashSet<_>>();
+        if due.is_empty() {
+            return Ok(vec![]);
+        }
+        store.reminders.retain(|id, _| !due.contains(id));
+        let mut messages = store
+            .messages
+            .iter_mut()
+            .filter(|message| due.contains(&message.id))
+            .map(|message| {
+                message.state = State::Unread;
+                message.custom_state = None;
+                message.clone()
+            })
+            .collect::<Vec<_>>();
+        for message in &messages {
+            store.next_seq(message.id);
+        }
+        drop(store);
+        Self::sort_newest_first(&mut messages);
+        Ok(messages)
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        let mut store = self.lock();
+        let ids = [id, related_id];
+        if ids
+            .iter()
+            .any(|id| !store.messages.iter().any(|message| message.id == *id))
+        {
+            return Ok(vec![]);
+        }
+        let mut messages = vec![];
+        for (id, related_id) in [(id, related_id), (related_id, id)] {
+            if let Some(message) = store.messages.iter_mut().find(|message| message.id == id) {
+                if let Err(index) = message.related_ids.binary_search(&related_id) {
+                    message.related_ids.insert(index, related_id);
+                }
+                messages.push(message.clone());
+            }
+            store.next_seq(id);
+        }
+        drop(store);
+        Self::sort_newest_first(&mut messages);
+        Ok(messages)
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        let interval_seconds = interval
+            .map(|interval| u32::try_from(interval.as_secs()))
+            .transpose()
+            .context("Heartbeat interval is too long")?;
+        let mut store = self.lock();
+        let config = MailboxConfig {
+            heartbeat_seconds: interval_seconds,
+            ..store
+                .mailbox_configs
+                .get(&mailbox)
+                .cloned()
+                .unwrap_or_default()
+        };
+        store.store_mailbox_config(mailbox.clone(), config);
+        let heartbeat = store
+            .heartbeats()
+            .find(|heartbeat| heartbeat.mailbox == mailbox);
+        drop(store);
+        Ok(heartbeat)
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        Ok(self.lock().heartbeats().collect())
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        Ok(self.lock().mailbox_configs.clone())
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.load_mailbox_configs().await
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        self.lock().store_mailbox_config(mailbox, config.clone());
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::Tag;
+    use chrono::NaiveDateTime;
+
+    // Helper for creating a NewMessage from its parts
+    fn make_message(
+        mailbox: &str,
+        content: &str,
+        state: impl Into<Option<State>>,
+    ) -> Result<NewMessage> {
+        Ok(NewMessage {
+            mailbox: mailbox.try_into()?,
+            content: content.to_owned(),
+            state: state.into(),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        })
+    }
+
+    // Create a memory backend containing several existing messages
+    async fn get_populated_backend() -> Result<MemoryBackend> {
+        let backend = MemoryBackend::new();
+        backend
+            .add_messages(vec![
+                make_message("unread", "unread1", State::Unread)?,
+                make_message("unread", "unread2", State::Unread)?,
+                make_message("read", "read1", State::Read)?,
+                make_message("read", "read2", State::Read)?,
+                make_message("read", "read3", State::Read)?,
+                make_message("archived", "archive1", State::Archived)?,
+            ])
+            .await?;
+        Ok(backend)
+    }
+
+    #[tokio::test]
+    async fn test_add_many() -> Result<()> {
+        let backend = MemoryBackend::new();
+        let messages = backend
+            .add_messages(vec![
+                make_message("mailbox2", "message2", None)?,
+                make_message("mailbox1", "message1", None)?,
+            ])
+            .await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.id, message.content.as_str(), message.state))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "message2", State::Unread),
+                (1, "message1", State::Unread)
+            ]
+        );
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_mailbox("mailbox1".try_into()?))
+                .await?
+                .len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_with_timestamp() -> Result<()> {
+        let backend = MemoryBackend::new();
+        let timestamp = NaiveDateTime::parse_from_str("2023-01-01 12:01:02", "%Y-%m-%d %H:%M:%S")?;
+        let messages = backend
+            .add_messages(vec![
+                NewMessage {
+                    timestamp: Some(timestamp),
+                    ..make_message("mailbox", "old", None)?
+                },
+                make_message("mailbox", "new", None)?,
+            ])
+            .await?;
+        assert_eq!(messages[0].timestamp, timestamp);
+        assert!(messages[1].timestamp > timestamp);
+
+        let messages = backend.load_messages(Filter::new()).await?;
+        assert_eq!(
+            messages
+                .iter()
+                .find(|message| message.content == "old")
+                .map(|message| message.timestamp),
+            Some(timestamp)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_invalid() -> Result<()> {
+        let backend = MemoryBackend::new();
+        assert!(backend
+            .add_messages(vec![make_message("mailbox", "", None)?])
+            .await
+            .is_err());
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_newest_first() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_mailbox("unread".try_into()?))
+                .await?
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>(),
+            vec!["unread1", "unread2"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
+                .change_state(
+                    Filter::new().with_states(vec![State::Unread, State::Read]),
+                    State::Archived,
+                )
+                .await?
+                .len(),
+            5
+        );
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_states(vec![State::Archived]))
+                .await?
+                .len(),
+            6
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_custom_states() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let in_progress = String::from("in-progress");
+        let changed = backend
+            .change_custom_state(
+                Filter::new().with_mailbox("read".try_into()?),
+                in_progress.clone(),
+                State::Read,
+            )
+            .await?;
+        assert_eq!(changed.len(), 3);
+        assert!(changed
+            .iter()
+            .all(|message| message.custom_state.as_ref() == Some(&in_progress)));
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_custom_states(vec![in_progress.clone()]))
+                .await?
+                .len(),
+            3
+        );
+        assert_eq!(
+            backend
+                .load_mailboxes(
+                    Filter::new()
+                        .with_states(vec![State::Read])
+                        .without_custom_states(vec![in_progress.clone()])
+                )
+                .await?
+                .len(),
+            0
+        );
+
+        // Changing to a built-in state leaves the custom state
+        let changed = backend
+            .change_state(Filter::new().with_mailbox("read".try_into()?), State::Read)
+            .await?;
+        assert!(changed.iter().all(|message| message.custom_state.is_none()));
+
+        assert!(backend
+            .change_custom_state(
+                Filter::new().with_consumer(String::from("laptop")),
+                in_progress,
+                State::Read,
+            )
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tags() -> Result<()> {
+        let backend = MemoryBackend::new();
+        let tagged = |mailbox: &str, content: &str, tags: &[&str]| -> Result<NewMessage> {
+            Ok(NewMessage {
+                tags: tags
+                    .iter()
+                    .map(|tag| Tag::try_from(*tag))
+                    .collect::<Result<_>>()?,
+                ..make_message(mailbox, content, None)?
+            })
+        };
+        let added = backend
+            .add_messages(vec![
+                tagged("ci/project-a", "build failed", &["urgent", "ci", "urgent"])?,
+                tagged("backups", "disk full", &["urgent"])?,
+                tagged("backups", "backup done", &[])?,
+            ])
+            .await?;
+        let get_tags = |messages: &[Message]| {
+            messages
+                .iter()
+                .map(|message| {
+                    message
+                        .tags
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            get_tags(&added),
+            vec![vec!["ci", "urgent"], vec!["urgent"], vec![]]
+        );
+
+        let urgent = Filter::new().with_tags(vec!["urgent".try_into()?]);
+        let messages = backend.load_messages(urgent.clone()).await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["build failed", "disk full"]
+        );
+        assert_eq!(
+            get_tags(&messages),
+            vec![vec!["ci", "urgent"], vec!["urgent"]]
+        );
+        assert_eq!(
+            backend.load_mailboxes(urgent.clone()).await?,
+            vec![
+                MailboxInfo {
+                    name: "backups".try_into()?,
+                    message_count: 1
+                },
+                MailboxInfo {
+                    name: "ci/project-a".try_into()?,
+                    message_count: 1
+                },
+            ]
+        );
+
+        let changed = backend.change_state(urgent.clone(), State::Read).await?;
+        assert_eq!(changed.len(), 2);
+        assert!(changed.iter().all(|message| !message.tags.is_empty()));
+
+        let deleted = backend
+            .delete_messages(Filter::new().with_tags(vec!["ci".try_into()?]))
+            .await?;
+        assert_eq!(get_tags(&deleted), vec![vec!["ci", "urgent"]]);
+        assert_eq!(backend.load_messages(urgent).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upsert() -> Result<()> {
+        let backend = MemoryBackend::new();
+        let with_external_id =
+            |mailbox: &str, content: &str, external_id: &str| -> Result<NewMessage> {
+                Ok(NewMessage {
+                    external_id: Some(external_id.to_owned()),
+                    ..make_message(mailbox, content, None)?
+                })
+            };
+        let added = backend
+            .add_messages(vec![
+                with_external_id("job", "Running", "run-1")?,
+                with_external_id("other", "Running", "run-1")?,
+            ])
+            .await?;
+
+        // Adding a duplicate external id to the same mailbox fails
+        assert!(backend
+            .add_messages(vec![with_external_id("job", "Done", "run-1")?])
+            .await
+            .is_err());
+
+        backend
+            .change_state(Filter::new().with_ids(vec![added[0].id]), State::Read)
+            .await?;
+        let since = backend.load_changes(0).await?.seq;
+        let upserted = backend
+            .upsert_messages(vec![
+                NewMessage {
+                    tags: vec![Tag::try_from("done")?],
+                    ..with_external_id("job", "Done", "run-1")?
+                },
+                with_external_id("job", "Queued", "run-2")?,
+            ])
+            .await?;
+        assert_eq!(upserted[0].id, added[0].id);
+        assert_eq!(upserted[0].content, "Done");
+        assert_eq!(upserted[0].state, State::Unread);
+        assert_eq!(upserted[0].tags, vec![Tag::try_from("done")?]);
+        assert!(upserted[1].id > added[0].id.max(added[1].id));
+
+        let messages = backend
+            .load_messages(Filter::new().with_mailbox("job".try_into()?))
+            .await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.content.as_str(), message.external_id.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("Queued", Some("run-2")), ("Done", Some("run-1"))]
+        );
+        let messages = backend
+            .load_messages(Filter::new().with_mailbox("other".try_into()?))
+            .await?;
+        assert_eq!(messages[0].content, "Running");
+
+        // Updated messages are reported as changes
+        let mut changed = backend
+            .load_changes(since)
+            .await?
+            .messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+        assert_eq!(changed, vec![added[0].id, upserted[1].id]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
+                .delete_messages(Filter::new().with_states(vec![State::Unread, State::Read]))
+                .await?
+                .len(),
+            5
+        );
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_consumer_states() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let laptop = Filter::new().with_consumer(String::from("laptop"));
+        let changed = backend
+            .change_state(
+                laptop.clone().with_mailbox("unread".try_into()?),
+                State::Read,
+            )
+            .await?;
+        assert_eq!(changed.len(), 2);
+        assert!(changed.iter().all(|message| message.state == State::Read));
+
+        // Only the consumer's own states changed
+        let unread = Filter::new().with_states(vec![State::Unread]);
+        assert_eq!(backend.load_messages(unread.clone()).await?.len(), 2);
+        assert_eq!(
+            backend
+                .load_messages(unread.clone().with_consumer(String::from("desktop")))
+                .await?
+                .len(),
+            2
+        );
+        assert!(backend
+            .load_messages(unread.clone().with_consumer(String::from("laptop")))
+            .await?
+            .is_empty());
+        assert!(backend
+            .load_mailboxes(unread.with_consumer(String::from("laptop")))
+            .await?
+            .is_empty());
+
+        let deleted = backend
+            .delete_messages(laptop.with_states(vec![State::Read]))
+            .await?;
+        assert_eq!(deleted.len(), 5);
+        assert!(deleted.iter().all(|message| message.state == State::Read));
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let filter = Filter::new().with_limit(1);
+        let lease = Duration::from_mins(1);
+        let claim = |worker: &str| backend.claim_messages(filter.clone(), worker.to_owned(), lease);
+        let claimed = claim("worker1").await?;
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].content, "unread2");
+        assert_eq!(claimed[0].state, State::Read);
+        assert_eq!(claim("worker2").await?[0].content, "unread1");
+        assert!(claim("worker3").await?.is_empty());
+
+        // Messages with expired leases can be claimed again
+        let get_ids = |messages: Vec<Message>| {
+            messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        };
+        let backend = get_populated_backend().await?;
+        let expired = backend
+            .claim_messages(Filter::new(), String::from("worker1"), Duration::ZERO)
+            .await?;
+        assert_eq!(expired.len(), 2);
+        let reclaimed = backend
+            .claim_messages(Filter::new(), String::from("worker2"), lease)
+            .await?;
+        assert_eq!(get_ids(reclaimed), get_ids(expired));
+
+        // Urgent messages are claimed first
+        let with_priority = |content: &str, priority: i32| -> Result<NewMessage> {
+            Ok(NewMessage {
+                priority: Some(priority),
+                ..make_message("jobs", content, None)?
+            })
+        };
+        backend
+            .add_messages(vec![
+                with_priority("low", 0)?,
+                with_priority("high", 5)?,
+                with_priority("medium", 1)?,
+                with_priority("high2", 5)?,
+            ])
+            .await?;
+        let claimed = backend
+            .claim_messages(
+                Filter::new().with_min_priority(1).with_limit(3),
+                String::from("worker1"),
+                lease,
+            )
+            .await?;
+        assert_eq!(
+            claimed
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["high2", "high", "medium"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attachments() -> Result<()> {
+        let backend = MemoryBackend::new();
+        let report = Attachment::Blob {
+            name: String::from("report.txt"),
+            data: b"report".to_vec(),
+        };
+        let added = backend
+            .add_messages(vec![
+                NewMessage {
+                    external_id: Some(String::from("build")),
+                    attachments: vec![report.clone()],
+                    ..make_message("mailbox", "Building", None)?
+                },
+                make_message("mailbox", "Plain", None)?,
+            ])
+            .await?;
+        assert_eq!(backend.load_attachments(added[0].id).await?, vec![report]);
+        assert!(backend.load_attachments(added[1].id).await?.is_empty());
+
+        let log = Attachment::Reference {
+            location: String::from("https://example.com/build/1"),
+        };
+        backend
+            .upsert_messages(vec![NewMessage {
+                external_id: Some(String::from("build")),
+                attachments: vec![log.clone()],
+                ..make_message("mailbox", "Built", None)?
+            }])
+            .await?;
+        assert_eq!(backend.load_attachments(added[0].id).await?, vec![log]);
+
+        backend.delete_messages(Filter::new()).await?;
+        assert!(backend.load_attachments(added[0].id).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_view() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let id = backend.load_messages(Filter::new()).await?[0].id;
+        let viewed = backend.record_view(id).await?.unwrap();
+        assert_eq!(viewed.view_count, 1);
+        assert!(viewed.last_viewed.is_some());
+        assert_eq!(backend.record_view(id).await?.unwrap().view_count, 2);
+        assert!(backend.record_view(0).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nack() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let dead_letter = DeadLetterPolicy {
+            mailbox: "failed".try_into()?,
+            max_attempts: 2,
+        };
+        let filter = Filter::new().with_mailbox("jobs".try_into()?);
+        let lease = Duration::from_mins(1);
+        let message = backend
+            .add_messages(vec![make_message("jobs", "job", None)?])
+            .await?
+            .remove(0);
+        let nack =
+            || backend.nack_message(message.id, String::from("timeout"), dead_letter.clone());
+
+        // Unclaimed and missing messages can't be rejected
+        assert!(nack().await.is_err());
+        assert!(backend
+            .nack_message(100, String::from("timeout"), dead_letter.clone())
+            .await?
+            .is_none());
+
+        // Rejected messages return to the queue until they run out of attempts
+        backend
+            .claim_messages(filter.clone(), String::from("worker"), lease)
+            .await?;
+        let rejected = nack().await?.unwrap();
+        assert_eq!(rejected.state, State::Unread);
+        assert_eq!(rejected.mailbox.as_ref(), "jobs");
+        assert!(nack().await.is_err());
+
+        backend
+            .claim_messages(filter.clone(), String::from("worker"), lease)
+            .await?;
+        let rejected = nack().await?.unwrap();
+        assert_eq!(rejected.state, State::Unread);
+        assert_eq!(rejected.mailbox.as_ref(), "failed");
+        assert_eq!(rejected.content, "job\n\nFailed 2 times in jobs: timeout");
+        assert!(backend
+            .claim_messages(filter, String::from("worker"), lease)
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let mailbox = Mailbox::try_from("cron/backup")?;
+        let heartbeat = backend
+            .set_heartbeat(mailbox.clone(), Some(Duration::from_hours(1)))
+            .await?
+            .unwrap();
+        assert_eq!(heartbeat.interval_seconds, 3600);
+        assert_eq!(heartbeat.last_message, None);
+
+        // Messages in descendants don't count
+        backend
+            .add_messages(vec![make_message("cron/backup/nas", "ok", None)?])
+            .await?;
+        let added = backend
+            .add_messages(vec![make_message("cron/backup", "ok", None)?])
+            .await?;
+
+        // Changing the interval keeps the creation time
+        let updated = backend
+            .set_heartbeat(mailbox.clone(), Some(Duration::from_hours(24)))
+            .await?
+            .unwrap();
+        assert_eq!(
+            updated,
+            Heartbeat {
+                interval_seconds: 86_400,
+                last_message: Some(added[0].timestamp),
+                ..heartbeat
+            }
+        );
+        assert_eq!(backend.load_heartbeats().await?, vec![updated]);
+
+        assert_eq!(backend.set_heartbeat(mailbox, None).await?, None);
+        assert!(backend.load_heartbeats().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mailbox_configs() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let mailbox = Mailbox::try_from("cron/backup")?;
+        let config = MailboxConfig {
+            description: Some(String::from("Nightly backups")),
+            default_state: Some(State::Read),
+            heartbeat_seconds: Some(3600),
+            ..Default::default()
+        };
+        assert_eq!(
+            backend
+                .set_mailbox_config(mailbox.clone(), config.clone())
+                .await?,
+            config
+        );
+        assert_eq!(
+            backend.load_mailbox_configs().await?,
+            BTreeMap::from([(mailbox.clone(), config.clone())])
+        );
+        let heartbeats = backend.load_heartbeats().await?;
+        assert_eq!(heartbeats.len(), 1);
+        assert_eq!(heartbeats[0].interval_seconds, 3600);
+
+        // Removing the heartbeat keeps the rest of the metadata
+        backend.set_heartbeat(mailbox.clone(), None).await?;
+        assert_eq!(
+            backend.load_mailbox_configs().await?,
+            BTreeMap::from([(
+                mailbox.clone(),
+                MailboxConfig {
+                    heartbeat_seconds: None,
+                    ..config
+                }
+            )])
+        );
+        assert!(backend.load_heartbeats().await?.is_empty());
+
+        // Empty metadata isn't stored
+        backend
+            .set_mailbox_config(mailbox, MailboxConfig::default())
+            .await?;
+        assert!(backend.load_mailbox_configs().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_link() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("alerts", "fixed", None)?,
+                make_message("alerts", "down", None)?,
+            ])
+            .await?;
+        let (fixed, down) = (messages[0].id, messages[1].id);
+        assert!(backend.link_messages(fixed, 100).await?.is_empty());
+
+        // Links are visible from both messages and aren't duplicated
+        backend.link_messages(fixed, down).await?;
+        let linked = backend.link_messages(down, fixed).await?;
+        assert_eq!(
+            linked
+                .iter()
+                .map(|message| (message.id, message.related_ids.clone()))
+                .collect::<Vec<_>>(),
+            vec![(fixed, vec![down]), (down, vec![fixed])]
+        );
+
+        // Deleting a message removes its links
+        backend
+            .delete_messages(Filter::new().with_ids(vec![down]))
+            .await?;
+        let messages = backend
+            .load_messages(Filter::new().with_ids(vec![fixed]))
+            .await?;
+        assert!(messages[0].related_ids.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remind() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("todo", "later", None)?,
+                make_message("todo", "done", State::Archived)?,
+            ])
+            .await?;
+        let (later, done) = (messages[0].id, messages[1].id);
+        assert!(backend.remind_message(100, Duration::ZERO).await?.is_none());
+
+        // Unread messages are read until the reminder is due
+        let reminded = backend
+            .remind_message(later, Duration::from_hours(1))
+            .await?
+            .unwrap();
+        assert_eq!(reminded.state, State::Read);
+        assert!(backend.wake_reminders().await?.is_empty());
+
+        // Due reminders return messages in any state to unread once
+        let reminded = backend.remind_message(done, Duration::ZERO).await?.unwrap();
+        assert_eq!(reminded.state, State::Archived);
+        backend.remind_message(later, Duration::ZERO).await?;
+        let woken = backend.wake_reminders().await?;
+        assert_eq!(
+            woken
+                .iter()
+                .map(|message| (message.content.as_str(), message.state))
+                .collect::<Vec<_>>(),
+            vec![("later", State::Unread), ("done", State::Unread)]
+        );
+        assert!(backend.wake_reminders().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_changes() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let changes = backend.load_changes(0).await?;
+        assert_eq!(changes.messages.len(), 6);
+        assert!(changes.deleted.is_empty());
+
+        let since = changes.seq;
+        backend
+            .change_state(Filter::new().with_ids(vec![1]), State::Read)
+            .await?;
+        backend
+            .delete_messages(Filter::new().with_ids(vec![2]))
+            .await?;
+        let changes = backend.load_changes(since).await?;
+        assert_eq!(
+            changes
+                .messages
+                .iter()
+                .map(|message| (message.id, message.state))
+                .collect::<Vec<_>>(),
+            vec![(1, State::Read)]
+        );
+        assert_eq!(changes.deleted, vec![2]);
+        assert_eq!(changes.seq, since + 2);
+
+        assert!(backend.load_changes(changes.seq).await?.messages.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_stats() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let stats = backend
+            .load_stats(
+                Filter::new().with_states(vec![State::Read, State::Archived]),
+                Bucket::Day,
+            )
+            .await?;
+        assert_eq!(
+            stats
+                .iter()
+                .map(|stats| (
+                    stats.mailbox.as_ref(),
+                    stats.added,
+                    stats.read,
+                    stats.archived
+                ))
+                .collect::<Vec<_>>(),
+            vec![("archived", 1, 0, 1), ("read", 3, 3, 0)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_mailboxes() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
+                .load_mailboxes(Filter::new().with_states(vec![State::Read, State::Archived]))
+                .await?,
+            vec![
+                MailboxInfo {
+                    name: "archived".try_into()?,
+                    message_count: 1
+                },
+                MailboxInfo {
+                    name: "read".try_into()?,
+                    message_count: 3
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_mailboxes_hierarchy_counts() -> Result<()> {
+        let backend = MemoryBackend::new();
+        backend
+            .add_messages(vec![
+                make_message("a", "message1", None)?,
+                make_message("a/b", "message2", None)?,
+                make_message("a/b/c", "message3", None)?,
+                make_message("a-b", "message4", None)?,
+            ])
+            .await?;
+        assert_eq!(
+            backend
+                .load_mailboxes(Filter::new().with_hierarchy_counts(true))
+                .await?
+                .into_iter()
+                .map(|mailbox| (mailbox.name.to_string(), mailbox.message_count))
+                .collect::<Vec<_>>(),
+            vec![
+                (String::from("a"), 3),
+                (String::from("a-b"), 1),
+                (String::from("a/b"), 2),
+                (String::from("a/b/c"), 1),
+            ]
+        );
+        Ok(())
+    }
+}
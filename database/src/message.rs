@@ -1,6 +1,6 @@
-use crate::Mailbox;
+use crate::{Mailbox, Tag};
 use anyhow::anyhow;
-use sea_query::{enum_def, Value};
+use sea_query::{Iden, Value};
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
@@ -65,16 +65,179 @@ impl From<State> for Value {
     }
 }
 
+// The format of a message's content, which determines how it is displayed and opened
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Text,
+    Url,
+    Markdown,
+    Json,
+}
+
+impl Kind {
+    // Guess the kind of a message from its content, falling back to text
+    #[must_use]
+    pub fn detect(content: &str) -> Self {
+        let content = content.trim();
+        if (content.starts_with("http://") || content.starts_with("https://"))
+            && !content.contains(char::is_whitespace)
+        {
+            return Self::Url;
+        }
+        if (content.starts_with('{') || content.starts_with('['))
+            && serde_json::from_str::<serde_json::Value>(content).is_ok()
+        {
+            return Self::Json;
+        }
+        // Look for headings and fenced code blocks
+        let is_markdown_line = |line: &str| {
+            let line = line.trim_start();
+            let heading_level = line.len() - line.trim_start_matches('#').len();
+            line.starts_with("```")
+                || ((1..=6).contains(&heading_level) && line[heading_level..].starts_with(' '))
+        };
+        if content.lines().any(is_markdown_line) || content.contains("](") {
+            return Self::Markdown;
+        }
+        Self::Text
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Url => "url",
+            Self::Markdown => "markdown",
+            Self::Json => "json",
+        })
+    }
+}
+
+impl TryFrom<u32> for Kind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u32) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Self::Text),
+            1 => Ok(Self::Url),
+            2 => Ok(Self::Markdown),
+            3 => Ok(Self::Json),
+            _ => Err(anyhow!("Invalid message kind {value}")),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "url" => Ok(Self::Url),
+            "markdown" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("Invalid message kind {value}")),
+        }
+    }
+}
+
+impl From<Kind> for u32 {
+    fn from(value: Kind) -> Self {
+        match value {
+            Kind::Text => 0,
+            Kind::Url => 1,
+            Kind::Markdown => 2,
+            Kind::Json => 3,
+        }
+    }
+}
+
+impl From<Kind> for Value {
+    fn from(value: Kind) -> Self {
+        Self::Unsigned(Some(value.into()))
+    }
+}
+
 pub type Id = u32;
 
-#[derive(Clone, Deserialize, Serialize, sqlx::FromRow)]
-#[enum_def]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "sqlite", derive(sqlx::FromRow))]
 pub struct Message {
     pub id: Id,
     pub timestamp: chrono::NaiveDateTime,
-    #[sqlx(try_from = "String")]
+    #[cfg_attr(feature = "sqlite", sqlx(try_from = "String"))]
     pub mailbox: Mailbox,
     pub content: String,
-    #[sqlx(try_from = "u32")]
+    #[cfg_attr(feature = "sqlite", sqlx(try_from = "u32"))]
     pub state: State,
+    // The custom state that refines the message's built-in state, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    // The number of times that the message has been opened and when it was last opened
+    #[serde(default)]
+    pub view_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_viewed: Option<chrono::NaiveDateTime>,
+    #[serde(default)]
+    pub priority: i32,
+    #[cfg_attr(feature = "sqlite", sqlx(try_from = "u32"))]
+    #[serde(default)]
+    pub kind: Kind,
+    // Tags are stored in a separate table, so they are loaded separately
+    #[cfg_attr(feature = "sqlite", sqlx(skip))]
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+    // The ids of the messages linked to this one, like the alert that this message resolves, which
+    // are also stored in a separate table
+    #[cfg_attr(feature = "sqlite", sqlx(skip))]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related_ids: Vec<Id>,
+}
+
+// The columns of the message table, which doesn't include the tags because they are stored in a
+// separate table
+#[derive(Clone, Copy, Debug, Eq, Hash, Iden, PartialEq)]
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+#[iden = "message"]
+pub enum MessageIden {
+    Table,
+    Id,
+    Timestamp,
+    Mailbox,
+    Content,
+    State,
+    ExternalId,
+    ViewCount,
+    LastViewed,
+    Priority,
+    Kind,
+    CustomState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_kind() {
+        assert_eq!(Kind::detect("https://ci.example.com/1\n"), Kind::Url);
+        assert_eq!(
+            Kind::detect("Build failed: https://ci.example.com/1"),
+            Kind::Text
+        );
+        assert_eq!(Kind::detect(r#"{"status": "failed"}"#), Kind::Json);
+        assert_eq!(Kind::detect("[not json"), Kind::Text);
+        assert_eq!(Kind::detect("# Report\n\nAll good"), Kind::Markdown);
+        assert_eq!(
+            Kind::detect("See [the logs](https://ci.example.com)"),
+            Kind::Markdown
+        );
+        assert_eq!(Kind::detect("#hashtag"), Kind::Text);
+        assert_eq!(Kind::detect("Build passed"), Kind::Text);
+    }
 }
@@ -1,11 +1,39 @@
+use crate::attachment::Attachment;
 use crate::mailbox::Mailbox;
-use crate::message::State;
+use crate::message::{Kind, State};
+use crate::tag::Tag;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct NewMessage {
     pub mailbox: Mailbox,
     pub content: String,
     pub state: Option<State>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Tag>,
+    // When present, the message keeps this timestamp instead of being stamped with the current time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<NaiveDateTime>,
+    // An identifier chosen by the sender that is unique within the mailbox, which allows the message
+    // to be updated later by upserting a message with the same external id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    // Messages with a higher priority are claimed first. Defaults to 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    // The format of the content, which is detected from the content when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Kind>,
+}
+
+impl NewMessage {
+    // Determine the kind of the message, detecting it from the content if it wasn't provided
+    #[must_use]
+    pub fn resolved_kind(&self) -> Kind {
+        self.kind.unwrap_or_else(|| Kind::detect(&self.content))
+    }
 }
@@ -0,0 +1,296 @@
+use crate::database::MailboxInfo;
+use crate::limits::ValidationError;
+use crate::mailbox::Mailbox;
+use crate::message::State;
+use crate::new_message::NewMessage;
+use serde::{Deserialize, Serialize};
+
+// What happens when new messages would put a mailbox over its quota
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaAction {
+    // Reject the new messages
+    #[default]
+    Reject,
+    // Add the new messages and then archive the oldest messages until the mailbox is within its
+    // quota again
+    Archive,
+}
+
+// The number of active messages that count against a quota compared to the quota's limit
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct QuotaStatus {
+    // The mailbox whose messages are counted, which is the root of the namespace for namespace
+    // quotas
+    pub mailbox: Mailbox,
+    pub limit: usize,
+    pub used: usize,
+}
+
+// A quota on the active messages in a mailbox, and in its descendants if it is a namespace quota
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Scope {
+    pub mailbox: Mailbox,
+    pub namespace: bool,
+    pub limit: usize,
+}
+
+impl Scope {
+    // Determine whether messages in the mailbox count against the quota
+    pub fn contains(&self, mailbox: &Mailbox) -> bool {
+        &self.mailbox == mailbox || (self.namespace && self.mailbox.is_ancestor_of(mailbox))
+    }
+
+    // Count the active messages that count against the quota
+    fn count(&self, active: &[MailboxInfo]) -> usize {
+        active
+            .iter()
+            .filter(|info| self.contains(&info.name))
+            .map(|info| info.message_count)
+            .sum()
+    }
+}
+
+// Soft limits on the number of active messages, meaning unread and read messages, in each mailbox
+// and namespace, which keep one runaway producer from filling a shared database
+// Quotas are soft because concurrent additions can each pass the check before either is saved.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[must_use]
+pub struct QuotaPolicy {
+    // The maximum number of active messages in any single mailbox, not counting its descendants
+    mailbox_limit: Option<usize>,
+    // The maximum number of active messages in specific mailboxes, not counting their descendants
+    exact_limits: Vec<(Mailbox, usize)>,
+    // The maximum number of active messages in mailboxes and their descendants
+    namespace_limits: Vec<(Mailbox, usize)>,
+    action: QuotaAction,
+}
+
+impl QuotaPolicy {
+    // Create a policy without any quotas
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Limit the number of active messages in every mailbox
+    pub fn with_mailbox_limit(mut self, limit: usize) -> Self {
+        self.mailbox_limit = Some(limit);
+        self
+    }
+
+    // Limit the number of active messages in one mailbox, not counting its descendants
+    pub fn with_exact_limit(mut self, mailbox: Mailbox, limit: usize) -> Self {
+        self.exact_limits.push((mailbox, limit));
+        self
+    }
+
+    // Limit the number of active messages in a mailbox and its descendants
+    pub fn with_namespace_limit(mut self, namespace: Mailbox, limit: usize) -> Self {
+        self.namespace_limits.push((namespace, limit));
+        self
+    }
+
+    // Configure what happens when new messages would exceed a quota
+    pub fn with_action(mut self, action: QuotaAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    #[must_use]
+    pub fn action(&self) -> QuotaAction {
+        self.action
+    }
+
+    // Determine whether the policy has any quotas
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.mailbox_limit.is_none()
+            && self.exact_limits.is_empty()
+            && self.namespace_limits.is_empty()
+    }
+
+    // Return the quotas that messages in the mailbox count against
+    fn scopes_for(&self, mailbox: &Mailbox) -> Vec<Scope> {
+        let mailbox_scope = self.mailbox_limit.map(|limit| Scope {
+            mailbox: mailbox.clone(),
+            namespace: false,
+            limit,
+        });
+        let exact_scopes = self
+            .exact_limits
+            .iter()
+            .filter(|(exact_mailbox, _)| exact_mailbox == mailbox)
+            .map(|(exact_mailbox, limit)| Scope {
+                mailbox: exact_mailbox.clone(),
+                namespace: false,
+                limit: *limit,
+            });
+        let namespace_scopes = self
+            .namespace_limits
+            .iter()
+            .map(|(namespace, limit)| Scope {
+                mailbox: namespace.clone(),
+                namespace: true,
+                limit: *limit,
+            })
+            .filter(|scope| scope.contains(mailbox));
+        mailbox_scope
+            .into_iter()
+            .chain(exact_scopes)
+            .chain(namespace_scopes)
+            .collect()
+    }
+
+    // Return the quotas that messages in any of the mailboxes count against, without duplicates
+    pub(crate) fn scopes_for_all<'a>(
+        &self,
+        mailboxes: impl IntoIterator<Item = &'a Mailbox>,
+    ) -> Vec<Scope> {
+        let mut scopes = vec![];
+        for mailbox in mailboxes {
+            for scope in self.scopes_for(mailbox) {
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                }
+            }
+        }
+        scopes
+    }
+
+    // Determine the status of the quota that the mailbox is closest to exceeding, or None if no
+    // quotas apply to it
+    pub(crate) fn status(&self, mailbox: &Mailbox, active: &[MailboxInfo]) -> Option<QuotaStatus> {
+        self.scopes_for(mailbox)
+            .into_iter()
+            .map(|scope| QuotaStatus {
+                used: scope.count(active),
+                mailbox: scope.mailbox,
+                limit: scope.limit,
+            })
+            .min_by_key(|status| status.limit.saturating_sub(status.used))
+    }
+
+    // Check that adding the new messages won't put any mailboxes over their quota
+    pub(crate) fn check(
+        &self,
+        messages: &[NewMessage],
+        active: &[MailboxInfo],
+    ) -> Result<(), ValidationError> {
+        for scope in self.scopes_for_all(messages.iter().map(|message| &message.mailbox)) {
+            let added = messages
+                .iter()
+                .filter(|message| {
+                    message.state != Some(State::Archived) && scope.contains(&message.mailbox)
+                })
+                .count();
+            if added > 0 && scope.count(active) + added > scope.limit {
+                let mailboxes = if scope.namespace {
+                    " and its descendants"
+                } else {
+                    ""
+                };
+                return Err(ValidationError(format!(
+                    "Mailbox \"{}\"{mailboxes} can't have more than {} unread and read messages",
+                    scope.mailbox, scope.limit
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, message_count: usize) -> MailboxInfo {
+        MailboxInfo {
+            name: name.try_into().unwrap(),
+            message_count,
+        }
+    }
+
+    fn new_message(mailbox: &str) -> NewMessage {
+        NewMessage {
+            mailbox: mailbox.try_into().unwrap(),
+            content: String::from("content"),
+            state: None,
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = QuotaPolicy::new()
+            .with_mailbox_limit(3)
+            .with_namespace_limit("ci".try_into().unwrap(), 4);
+        let active = [info("ci/build", 2), info("ci/test", 1), info("other", 3)];
+        assert!(policy.check(&[new_message("ci/build")], &active).is_ok());
+        assert_eq!(
+            policy
+                .check(&[new_message("ci/test"), new_message("ci/test")], &active)
+                .unwrap_err()
+                .to_string(),
+            "Mailbox \"ci\" and its descendants can't have more than 4 unread and read messages"
+        );
+        assert_eq!(
+            policy
+                .check(&[new_message("other")], &active)
+                .unwrap_err()
+                .to_string(),
+            "Mailbox \"other\" can't have more than 3 unread and read messages"
+        );
+
+        let exact_policy = QuotaPolicy::new().with_exact_limit("ci/build".try_into().unwrap(), 2);
+        assert_eq!(
+            exact_policy
+                .check(&[new_message("ci/build")], &active)
+                .unwrap_err()
+                .to_string(),
+            "Mailbox \"ci/build\" can't have more than 2 unread and read messages"
+        );
+        assert!(exact_policy
+            .check(&[new_message("ci/test")], &active)
+            .is_ok());
+
+        // Archived messages don't count against quotas
+        let archived = NewMessage {
+            state: Some(State::Archived),
+            ..new_message("other")
+        };
+        assert!(policy.check(&[archived], &active).is_ok());
+    }
+
+    #[test]
+    fn test_status() {
+        let policy = QuotaPolicy::new()
+            .with_mailbox_limit(10)
+            .with_namespace_limit("ci".try_into().unwrap(), 4);
+        let active = [info("ci/build", 2), info("ci/test", 1), info("other", 3)];
+        assert_eq!(
+            policy.status(&"ci/build".try_into().unwrap(), &active),
+            Some(QuotaStatus {
+                mailbox: "ci".try_into().unwrap(),
+                limit: 4,
+                used: 3,
+            })
+        );
+        assert_eq!(
+            policy.status(&"other".try_into().unwrap(), &active),
+            Some(QuotaStatus {
+                mailbox: "other".try_into().unwrap(),
+                limit: 10,
+                used: 3,
+            })
+        );
+        assert_eq!(
+            QuotaPolicy::new().status(&"other".try_into().unwrap(), &active),
+            None
+        );
+    }
+}
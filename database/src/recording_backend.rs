@@ -0,0 +1,277 @@
+use crate::archive::Archiver;
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
+use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::filter::Filter;
+use crate::heartbeat::Heartbeat;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, State};
+use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
+use crate::Backend;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Represents a single call made to a backend
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Call {
+    AddMessages(Vec<NewMessage>),
+    UpsertMessages(Vec<NewMessage>),
+    LoadMessages(Filter),
+    ChangeState(Filter, State),
+    ChangeCustomState(Filter, String, State),
+    DeleteMessages(Filter),
+    ArchiveMessages(Filter),
+    LoadMailboxes(Filter),
+    LoadChanges(Seq),
+    LoadStats(Filter, Bucket),
+    LoadAttachments(Id),
+    RecordView(Id),
+    ClaimMessages(Filter, String, Duration),
+    NackMessage(Id, String, DeadLetterPolicy),
+    RemindMessage(Id, Duration),
+    WakeReminders,
+    LinkMessages(Id, Id),
+    SetHeartbeat(Mailbox, Option<Duration>),
+    LoadHeartbeats,
+    LoadMailboxConfigs,
+    LoadIngestionConfigs,
+    SetMailboxConfig(Mailbox, MailboxConfig),
+}
+
+// RecordingBackend wraps another backend and logs every call made to it before forwarding the call
+// to the wrapped backend. Clones share the same wrapped backend and call log, so a test can keep a
+// clone to inspect the calls after moving the original into a Database.
+pub struct RecordingBackend<B: Backend> {
+    inner: Arc<B>,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl<B: Backend> Clone for RecordingBackend<B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            calls: Arc::clone(&self.calls),
+        }
+    }
+}
+
+impl<B: Backend> RecordingBackend<B> {
+    // Create a new RecordingBackend that forwards calls to the provided backend
+    #[must_use]
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    // Return the calls made so far, in the order they were made
+    #[must_use]
+    pub fn get_calls(&self) -> Vec<Call> {
+        self.calls
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    // Forget the calls made so far
+    pub fn clear_calls(&self) {
+        self.calls
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clear();
+    }
+
+    // Add a call to the log
+    fn record(&self, call: Call) {
+        self.calls
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(call);
+    }
+}
+
+impl<B: Backend + Send + Sync> Backend for RecordingBackend<B> {
+    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.record(Call::AddMessages(messages.clone()));
+        self.inner.add_messages(messages).await
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.record(Call::UpsertMessages(messages.clone()));
+        self.inner.upsert_messages(messages).await
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        self.record(Call::LoadMessages(filter.clone()));
+        self.inner.load_messages(filter).await
+    }
+
+    async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        self.record(Call::ChangeState(filter.clone(), new_state));
+        self.inner.change_state(filter, new_state).await
+    }
+
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        self.record(Call::ChangeCustomState(
+            filter.clone(),
+            custom_state.clone(),
+            new_state,
+        ));
+        self.inner
+            .change_custom_state(filter, custom_state, new_state)
+            .await
+    }
+
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        self.record(Call::DeleteMessages(filter.clone()));
+        self.inner.delete_messages(filter).await
+    }
+
+    async fn archive_messages(&self, filter: Filter, archiver: Archiver) -> Result<Vec<Message>> {
+        self.record(Call::ArchiveMessages(filter.clone()));
+        self.inner.archive_messages(filter, archiver).await
+    }
+
+    async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
+        self.record(Call::LoadMailboxes(filter.clone()));
+        self.inner.load_mailboxes(filter).await
+    }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        self.record(Call::LoadChanges(since));
+        self.inner.load_changes(since).await
+    }
+
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        self.record(Call::LoadStats(filter.clone(), bucket));
+        self.inner.load_stats(filter, bucket).await
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        self.record(Call::LoadAttachments(id));
+        self.inner.load_attachments(id).await
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        self.record(Call::RecordView(id));
+        self.inner.record_view(id).await
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        self.record(Call::ClaimMessages(filter.clone(), worker.clone(), lease));
+        self.inner.claim_messages(filter, worker, lease).await
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        self.record(Call::NackMessage(id, reason.clone(), dead_letter.clone()));
+        self.inner.nack_message(id, reason, dead_letter).await
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        self.record(Call::RemindMessage(id, delay));
+        self.inner.remind_message(id, delay).await
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        self.record(Call::WakeReminders);
+        self.inner.wake_reminders().await
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        self.record(Call::LinkMessages(id, related_id));
+        self.inner.link_messages(id, related_id).await
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        self.record(Call::SetHeartbeat(mailbox.clone(), interval));
+        self.inner.set_heartbeat(mailbox, interval).await
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        self.record(Call::LoadHeartbeats);
+        self.inner.load_heartbeats().await
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.record(Call::LoadMailboxConfigs);
+        self.inner.load_mailbox_configs().await
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.record(Call::LoadIngestionConfigs);
+        self.inner.load_ingestion_configs().await
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        self.record(Call::SetMailboxConfig(mailbox.clone(), config.clone()));
+        self.inner.set_mailbox_config(mailbox, config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_records_calls() -> Result<()> {
+        let backend = RecordingBackend::new(MemoryBackend::new());
+        let recorder = backend.clone();
+        let new_message = NewMessage {
+            mailbox: "mailbox".try_into()?,
+            content: String::from("message"),
+            state: None,
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        };
+        backend.add_messages(vec![new_message.clone()]).await?;
+        let filter = Filter::new().with_states(vec![State::Unread]);
+        assert_eq!(backend.load_messages(filter.clone()).await?.len(), 1);
+        backend.change_state(filter.clone(), State::Read).await?;
+        assert_eq!(
+            recorder.get_calls(),
+            vec![
+                Call::AddMessages(vec![new_message]),
+                Call::LoadMessages(filter.clone()),
+                Call::ChangeState(filter, State::Read),
+            ]
+        );
+
+        recorder.clear_calls();
+        assert!(backend.get_calls().is_empty());
+        Ok(())
+    }
+}
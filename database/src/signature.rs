@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// The headers that carry the signature of a request body and when it was signed
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+// The prefix of signatures, which names the algorithm so that others can be added later
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Create an HMAC keyed with a producer's secret
+fn make_mac(key: &str) -> Result<HmacSha256> {
+    HmacSha256::new_from_slice(key.as_bytes()).context("Invalid signing key")
+}
+
+// Sign a request body with a producer's secret, producing a header value like "sha256=<hex digest>"
+// The signature covers the timestamp so that old requests can't be replayed.
+pub fn sign_body(key: &str, timestamp: &str, body: &[u8]) -> Result<String> {
+    let mut mac = make_mac(key)?;
+    mac.update(format!("{timestamp}:").as_bytes());
+    mac.update(body);
+    Ok(format!(
+        "{SIGNATURE_PREFIX}{}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+// Determine whether a signature was made from the timestamp and request body with any of the
+// producers' secrets
+// The digests are compared in constant time so that the signature can't be guessed byte by byte
+#[must_use]
+pub fn verify_signature(keys: &[String], timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix(SIGNATURE_PREFIX)
+        .and_then(|digest| hex::decode(digest).ok())
+    else {
+        return false;
+    };
+    keys.iter().any(|key| {
+        make_mac(key).is_ok_and(|mut mac| {
+            mac.update(format!("{timestamp}:").as_bytes());
+            mac.update(body);
+            mac.verify_slice(&digest).is_ok()
+        })
+    })
+}
+
+// The headers that Slack sends with the signature of a slash command and when it was signed
+pub const SLACK_SIGNATURE_HEADER: &str = "X-Slack-Signature";
+pub const SLACK_TIMESTAMP_HEADER: &str = "X-Slack-Request-Timestamp";
+
+// Sign a Slack request the way that Slack does, producing a header value like "v0=<hex digest>"
+// The signature covers the timestamp so that old requests can't be replayed.
+pub fn sign_slack_request(secret: &str, timestamp: &str, body: &[u8]) -> Result<String> {
+    let mut mac = make_mac(secret)?;
+    mac.update(format!("v0:{timestamp}:").as_bytes());
+    mac.update(body);
+    Ok(format!("v0={}", hex::encode(mac.finalize().into_bytes())))
+}
+
+// Determine whether a Slack request was signed with the app's signing secret
+#[must_use]
+pub fn verify_slack_signature(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("v0=")
+        .and_then(|digest| hex::decode(digest).ok())
+    else {
+        return false;
+    };
+    make_mac(secret).is_ok_and(|mut mac| {
+        mac.update(format!("v0:{timestamp}:").as_bytes());
+        mac.update(body);
+        mac.verify_slice(&digest).is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_body() -> Result<()> {
+        // Computed with `openssl dgst -sha256 -hmac secret` like the example in the server's README
+        assert_eq!(
+            sign_body(
+                "secret",
+                "1531420618",
+                br#"{"mailbox":"ci","content":"Build passed"}"#
+            )?,
+            "sha256=1a220e213a44a118d82547810e6fe18f6e692319702a8280ffb43d68398ac86a"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_signature() -> Result<()> {
+        let keys = vec![String::from("producer-a"), String::from("producer-b")];
+        let signature = sign_body("producer-b", "1531420618", b"body")?;
+        assert!(verify_signature(&keys, "1531420618", b"body", &signature));
+        assert!(!verify_signature(
+            &keys,
+            "1531420618",
+            b"other body",
+            &signature
+        ));
+        assert!(!verify_signature(&keys, "1531420619", b"body", &signature));
+        assert!(!verify_signature(
+            &keys,
+            "1531420618",
+            b"body",
+            &sign_body("producer-c", "1531420618", b"body")?
+        ));
+        assert!(!verify_signature(
+            &keys,
+            "1531420618",
+            b"body",
+            &signature["sha256=".len()..]
+        ));
+        assert!(!verify_signature(&keys, "1531420618", b"body", "sha256=zz"));
+        assert!(!verify_signature(&[], "1531420618", b"body", &signature));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_slack_signature() -> Result<()> {
+        // Example from Slack's documentation on verifying requests
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        assert_eq!(
+            sign_slack_request("8f742231b10e8888abcd99yyyzzz85a5", "1531420618", body)?,
+            signature
+        );
+        assert!(verify_slack_signature(
+            "8f742231b10e8888abcd99yyyzzz85a5",
+            "1531420618",
+            body,
+            signature
+        ));
+        assert!(!verify_slack_signature(
+            "8f742231b10e8888abcd99yyyzzz85a5",
+            "1531420619",
+            body,
+            signature
+        ));
+        assert!(!verify_slack_signature(
+            "other",
+            "1531420618",
+            body,
+            signature
+        ));
+        Ok(())
+    }
+}
@@ -1,20 +1,161 @@
+use crate::acl::MailboxAcl;
+use crate::admin::{
+    AdminStats, ApiToken, AuditEntry, Compaction, DatabaseInfo, QueryResult, SchemaVersion,
+    WalStatus,
+};
+use crate::archive::{ArchivedMessage, Archiver};
+use crate::attachment::Attachment;
+use crate::changes::{Changes, Seq};
 use crate::database::MailboxInfo;
+use crate::dead_letter::DeadLetterPolicy;
 use crate::filter::Filter;
-use crate::message::{Message, MessageIden, State};
+use crate::forward::ForwardDelivery;
+use crate::heartbeat::Heartbeat;
+use crate::limits::ValidationError;
+use crate::mailbox::Mailbox;
+use crate::mailbox_config::MailboxConfig;
+use crate::message::{Id, Message, MessageIden, State};
 use crate::new_message::NewMessage;
+use crate::stats::{Bucket, BucketStats};
+use crate::tag::Tag;
 use crate::Backend;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
 use sea_query::{
-    Alias, Asterisk, ColumnDef, Expr, Func, Keyword, Order, Query, SqliteQueryBuilder, Table, Value,
+    Alias, Asterisk, ColumnDef, Expr, Func, Keyword, OnConflict, Order, Query, SimpleExpr,
+    SqliteQueryBuilder, Table, Value, Values,
+};
+use sea_query_binder::{SqlxBinder, SqlxValues};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteRow};
+use sqlx::types::Json;
+use sqlx::{
+    query, Column, Connection, Executor, Row, Sqlite, SqliteConnection, SqlitePool, Statement,
+    Transaction, TypeInfo, ValueRef,
 };
-use sea_query_binder::SqlxBinder;
-use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
-use sqlx::{query, Row, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
+
+// How long to wait for other processes to release their lock on the database before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The name of the message column containing the sequence number of the message's last change
+const SEQ_COLUMN: &str = "seq";
+
+// The name of the table containing the number of messages in each mailbox and state
+const MAILBOX_COUNT_TABLE: &str = "mailbox_count";
+
+// The name of the table containing the ids of deleted messages
+const DELETED_MESSAGE_TABLE: &str = "deleted_message";
+
+// An SQL expression that calculates the next sequence number
+const NEXT_SEQ: &str = "MAX(
+    (SELECT COALESCE(MAX(seq), 0) FROM message),
+    (SELECT COALESCE(MAX(seq), 0) FROM deleted_message)
+) + 1";
+
+// SQL trigger statements that update the mailbox counts when a message is added or removed
+const INCREMENT_MAILBOX_COUNT: &str = "INSERT INTO mailbox_count (mailbox, state, count)
+    VALUES (NEW.mailbox, NEW.state, 1)
+    ON CONFLICT (mailbox, state) DO UPDATE SET count = count + 1;";
+const DECREMENT_MAILBOX_COUNT: &str = "UPDATE mailbox_count SET count = count - 1
+    WHERE mailbox = OLD.mailbox AND state = OLD.state;
+    DELETE FROM mailbox_count WHERE mailbox = OLD.mailbox AND state = OLD.state AND count <= 0;";
+
+// An SQL common table expression that shadows the message table with one whose state column
+// contains a consumer's own message states, falling back to the shared state of messages that the
+// consumer hasn't changed. The consumer is its only parameter. It must list every message column.
+const CONSUMER_MESSAGE_TABLE: &str = "WITH message AS (
+    SELECT shared.id, shared.timestamp, shared.mailbox, shared.content,
+        COALESCE(
+            (SELECT state FROM consumer_state WHERE message_id = shared.id AND consumer = ?),
+            shared.state
+        ) AS state,
+        shared.custom_state, shared.seq, shared.external_id, shared.view_count, shared.last_viewed,
+        shared.priority, shared.kind
+    FROM main.message AS shared
+)";
+
+// An SQL query that selects heartbeats with the timestamp of the newest message in their mailbox
+const SELECT_HEARTBEATS: &str = "SELECT name AS mailbox,
+    heartbeat_seconds AS interval_seconds,
+    heartbeat_created AS created,
+    (SELECT MAX(timestamp) FROM message WHERE message.mailbox = mailbox.name) AS last_message
+FROM mailbox
+WHERE heartbeat_seconds IS NOT NULL";
+
+// An SQL statement that removes a mailbox's metadata if none of it is set anymore
+const DELETE_EMPTY_MAILBOX: &str = "DELETE FROM mailbox
+WHERE name = ?
+    AND description IS NULL
+    AND color IS NULL
+    AND default_state IS NULL
+    AND quota IS NULL
+    AND heartbeat_seconds IS NULL
+    AND pinned_order IS NULL";
+
+// Read a mailbox's metadata from a row of the mailbox table
+fn mailbox_config_from_row(row: &SqliteRow) -> Result<(Mailbox, MailboxConfig)> {
+    Ok((
+        row.try_get::<String, _>("name")?.try_into()?,
+        MailboxConfig {
+            description: row.try_get("description")?,
+            color: row.try_get("color")?,
+            default_state: row
+                .try_get::<Option<u32>, _>("default_state")?
+                .map(State::try_from)
+                .transpose()?,
+            quota: row.try_get("quota")?,
+            heartbeat_seconds: row.try_get("heartbeat_seconds")?,
+            pinned_order: row.try_get("pinned_order")?,
+        },
+    ))
+}
+
+// Make a query that reads from the message table use a consumer's own message states
+fn scope_to_consumer(
+    (sql, values): (String, SqlxValues),
+    consumer: Option<&str>,
+) -> (String, SqlxValues) {
+    let Some(consumer) = consumer else {
+        return (sql, values);
+    };
+    let mut scoped_values = vec![Value::from(consumer)];
+    scoped_values.extend(values.0 .0);
+    (
+        format!("{CONSUMER_MESSAGE_TABLE} {sql}"),
+        SqlxValues(Values(scoped_values)),
+    )
+}
+
+// Serialize the ids of messages as a JSON array so that the number of messages isn't limited by the
+// maximum number of SQL parameters
+fn serialize_ids(messages: &[Message]) -> Result<String> {
+    Ok(serde_json::to_string(
+        &messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>(),
+    )?)
+}
+
+// Build an attachment from a row of the attachment table
+fn attachment_from_row(row: &SqliteRow) -> Result<Attachment> {
+    Ok(match row.try_get::<Option<String>, _>("location")? {
+        Some(location) => Attachment::Reference { location },
+        None => Attachment::Blob {
+            name: row.try_get("name")?,
+            data: row.try_get("data")?,
+        },
+    })
+}
 
 pub struct SqliteBackend {
     pool: SqlitePool,
+    // Held while writing so that writes from this process don't compete for the database lock
+    write_lock: Mutex<()>,
 }
 
 impl SqliteBackend {
@@ -27,12 +168,16 @@ impl SqliteBackend {
         let options = SqliteConnectOptions::new()
             .filename(db_path)
             .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT)
             .create_if_missing(true);
 
         let pool = SqlitePool::connect_with(options)
             .await
             .context("Failed to open database")?;
-        let backend = Self { pool };
+        let backend = Self {
+            pool,
+            write_lock: Mutex::new(()),
+        };
         backend.init().await?;
         Ok(backend)
     }
@@ -52,29 +197,29 @@ impl SqliteBackend {
             "mailbox-{}.db",
             INDEX.fetch_add(1, Ordering::Relaxed)
         ));
+        // Reset the database
+        match std::fs::remove_file(&db_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).context("Failed to delete database");
+            }
+            _ => {}
+        }
 
         let options = SqliteConnectOptions::new()
             .filename(db_path)
             // Disable WAL during testing so that tests that write to the database
             // and then immediately read from the database will pass
             .journal_mode(SqliteJournalMode::Delete)
+            .busy_timeout(BUSY_TIMEOUT)
             .create_if_missing(true);
 
         let pool = SqlitePool::connect_with(options)
             .await
             .context("Failed to open database")?;
-        let backend = Self { pool };
-
-        // Reset the database
-        let sql = Table::drop()
-            .table(MessageIden::Table)
-            .if_exists()
-            .build(SqliteQueryBuilder);
-        query(&sql)
-            .execute(&backend.pool)
-            .await
-            .context("Failed to delete database table")?;
-
+        let backend = Self {
+            pool,
+            write_lock: Mutex::new(()),
+        };
         backend.init().await?;
 
         Ok(backend)
@@ -125,67 +270,1262 @@ impl SqliteBackend {
             .execute(&self.pool)
             .await
             .context("Failed to create database tables")?;
+
+        self.migrate().await
+    }
+
+    // Close the database after waiting for writes in progress to finish
+    // Checkpointing first merges the WAL back into the database file and truncates the WAL so that
+    // it doesn't linger on disk until the next time the database is opened
+    pub async fn close(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&self.pool)
+            .await
+            .context("Failed to checkpoint database")?;
+        self.pool.close().await;
         Ok(())
     }
-}
 
-impl Backend for SqliteBackend {
-    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+    // Return the size of the database file and how much of it is unused, in bytes
+    async fn load_size(&self) -> Result<(u64, u64)> {
+        let row = query(
+            "SELECT page_count * page_size, freelist_count * page_size
+            FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to load database size")?;
+        Ok((
+            u64::try_from(row.try_get::<i64, _>(0)?)?,
+            u64::try_from(row.try_get::<i64, _>(1)?)?,
+        ))
+    }
+
+    // Load statistics about the database for administrators
+    pub async fn load_admin_stats(&self) -> Result<AdminStats> {
+        let row = query(
+            "SELECT (SELECT count(*) FROM message), (SELECT count(DISTINCT mailbox) FROM message),
+                (SELECT count(*) FROM api_token)",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to load admin stats")?;
+        let (database_bytes, free_bytes) = self.load_size().await?;
+        Ok(AdminStats {
+            message_count: usize::try_from(row.try_get::<i64, _>(0)?)?,
+            mailbox_count: usize::try_from(row.try_get::<i64, _>(1)?)?,
+            token_count: usize::try_from(row.try_get::<i64, _>(2)?)?,
+            database_bytes,
+            free_bytes,
+        })
+    }
+
+    // Rebuild the database file to reclaim unused space and truncate the WAL
+    pub async fn compact(&self) -> Result<Compaction> {
+        let _guard = self.write_lock.lock().await;
+        let (before_bytes, _) = self.load_size().await?;
+        query("VACUUM")
+            .execute(&self.pool)
+            .await
+            .context("Failed to compact database")?;
+        query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&self.pool)
+            .await
+            .context("Failed to checkpoint database")?;
+        let (after_bytes, _) = self.load_size().await?;
+        Ok(Compaction {
+            before_bytes,
+            after_bytes,
+        })
+    }
+
+    // Write a consistent snapshot of the database to a new file, returning the size of the backup
+    // in bytes
+    // VACUUM INTO reads the whole database, including changes still in the WAL, in a single read
+    // transaction, so it is safe to run while other connections and processes keep writing
+    pub async fn backup(&self, dest: &Path) -> Result<u64> {
+        if dest.exists() {
+            bail!("Backup destination {} already exists", dest.display());
+        }
+        let dest_str = dest
+            .to_str()
+            .with_context(|| format!("Backup destination {} isn't valid UTF-8", dest.display()))?;
+        query("VACUUM INTO ?")
+            .bind(dest_str)
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Failed to back up database to {}", dest.display()))?;
+        Ok(std::fs::metadata(dest)
+            .with_context(|| format!("Failed to read backup {}", dest.display()))?
+            .len())
+    }
+
+    // Run an ad hoc SELECT query against the database at the path, returning its rows
+    // The query runs on its own connection that SQLite opens as read-only, so it can't change the
+    // database even if it contains other statements.
+    pub async fn query_read_only(db_path: &Path, sql: &str) -> Result<QueryResult> {
+        let statement = sql.trim_start().to_ascii_lowercase();
+        if !(statement.starts_with("select") || statement.starts_with("with")) {
+            return Err(ValidationError(String::from(
+                "only SELECT queries can be run against the database",
+            ))
+            .into());
+        }
+
+        let options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .read_only(true)
+            .busy_timeout(BUSY_TIMEOUT);
+        let mut connection = SqliteConnection::connect_with(&options)
+            .await
+            .with_context(|| format!("Failed to open database {}", db_path.display()))?;
+        // The columns come from the prepared statement so that they are known even if the query
+        // doesn't return any rows
+        let statement = connection
+            .prepare(sql)
+            .await
+            .context("Failed to run query")?;
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|column| column.name().to_owned())
+            .collect();
+        let rows = statement
+            .query()
+            .fetch_all(&mut connection)
+            .await
+            .context("Failed to run query")?;
+        connection.close().await?;
+
+        let mut result = QueryResult {
+            columns,
+            rows: Vec::with_capacity(rows.len()),
+        };
+        for row in rows {
+            let mut values = Vec::with_capacity(row.len());
+            for index in 0..row.len() {
+                let raw = row.try_get_raw(index)?;
+                // SQLite reports the storage class of each value rather than the column's type
+                let value = if raw.is_null() {
+                    serde_json::Value::Null
+                } else {
+                    match raw.type_info().name() {
+                        "INTEGER" => row.try_get_unchecked::<i64, _>(index)?.into(),
+                        "REAL" => row.try_get_unchecked::<f64, _>(index)?.into(),
+                        "BLOB" => hex::encode(row.try_get_unchecked::<Vec<u8>, _>(index)?).into(),
+                        _ => row.try_get_unchecked::<String, _>(index)?.into(),
+                    }
+                };
+                values.push(value);
+            }
+            result.rows.push(values);
+        }
+        Ok(result)
+    }
+
+    // Load the database's schema version and the latest schema version that this build supports
+    pub async fn load_schema_version(&self) -> Result<SchemaVersion> {
+        let current = query("PRAGMA user_version")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to read database schema version")?
+            .try_get::<i64, _>(0)?;
+        Ok(SchemaVersion {
+            current,
+            latest: i64::try_from(get_migrations().len())?,
+        })
+    }
+
+    // Load the schema version, message counts, and the range of message timestamps
+    pub async fn load_info(&self) -> Result<DatabaseInfo> {
+        let rows = query(
+            "SELECT state, count(*), min(timestamp), max(timestamp) FROM message GROUP BY state",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load database info")?;
+        let mut info = DatabaseInfo {
+            schema_version: self.load_schema_version().await?,
+            unread_count: 0,
+            read_count: 0,
+            archived_count: 0,
+            oldest: None,
+            newest: None,
+        };
+        for row in rows {
+            let count = usize::try_from(row.try_get::<i64, _>(1)?)?;
+            match State::try_from(row.try_get::<u32, _>(0)?)? {
+                State::Unread => info.unread_count = count,
+                State::Read => info.read_count = count,
+                State::Archived => info.archived_count = count,
+            }
+            let oldest = row.try_get::<NaiveDateTime, _>(2)?;
+            let newest = row.try_get::<NaiveDateTime, _>(3)?;
+            info.oldest = Some(info.oldest.map_or(oldest, |current| current.min(oldest)));
+            info.newest = Some(info.newest.map_or(newest, |current| current.max(newest)));
+        }
+        Ok(info)
+    }
+
+    // Checkpoint as much of the write-ahead log as possible without waiting for other connections,
+    // and report how much of it is left
+    pub async fn check_wal(&self) -> Result<WalStatus> {
+        let journal_mode = query("PRAGMA journal_mode")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to read journal mode")?
+            .try_get::<String, _>(0)?;
+        let row = query("PRAGMA wal_checkpoint(PASSIVE)")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to checkpoint database")?;
+        Ok(WalStatus {
+            journal_mode,
+            busy: row.try_get::<i64, _>(0)? != 0,
+            frames: row.try_get(1)?,
+            checkpointed: row.try_get(2)?,
+        })
+    }
+
+    // Load all API tokens, sorted by name
+    pub async fn load_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        sqlx::query_as::<_, ApiToken>(
+            "SELECT name, created, token, read_mailboxes, write_mailboxes FROM api_token ORDER BY name",
+        )
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load API tokens")
+    }
+
+    // Create an API token with a random secret that can access the mailboxes in the access list,
+    // returning None if a token with that name already exists
+    pub async fn create_api_token(&self, name: &str, acl: &MailboxAcl) -> Result<Option<ApiToken>> {
+        let _guard = self.write_lock.lock().await;
+        sqlx::query_as::<_, ApiToken>(
+            "INSERT INTO api_token (name, token, read_mailboxes, write_mailboxes)
+            VALUES (?, lower(hex(randomblob(32))), ?, ?)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING name, created, token, read_mailboxes, write_mailboxes",
+        )
+        .bind(name)
+        .bind(acl.read.as_ref().map(Json))
+        .bind(acl.write.as_ref().map(Json))
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to create API token")
+    }
+
+    // Delete an API token, returning None if no token has that name
+    pub async fn revoke_api_token(&self, name: &str) -> Result<Option<ApiToken>> {
+        let _guard = self.write_lock.lock().await;
+        sqlx::query_as::<_, ApiToken>(
+            "DELETE FROM api_token WHERE name = ?
+            RETURNING name, created, token, read_mailboxes, write_mailboxes",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to revoke API token")
+    }
+
+    // Append an entry to the audit log
+    pub async fn record_audit(
+        &self,
+        actor: &str,
+        operation: &str,
+        target: &str,
+        affected: usize,
+    ) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        query("INSERT INTO audit_log (actor, operation, target, affected) VALUES (?, ?, ?, ?)")
+            .bind(actor)
+            .bind(operation)
+            .bind(target)
+            .bind(i64::try_from(affected)?)
+            .execute(&self.pool)
+            .await
+            .context("Failed to record audit log entry")?;
+        Ok(())
+    }
+
+    // Load the most recent entries in the audit log, newest first
+    pub async fn load_audit_log(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        sqlx::query_as::<_, AuditEntry>("SELECT * FROM audit_log ORDER BY id DESC LIMIT ?")
+            .bind(i64::try_from(limit)?)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load audit log")
+    }
+
+    // Queue deliveries of messages to forwards, ignoring messages that were already queued for a
+    // forward, and return the number of deliveries queued
+    pub async fn enqueue_forward_deliveries(&self, deliveries: &[(Id, &str)]) -> Result<usize> {
+        if deliveries.is_empty() {
+            return Ok(0);
+        }
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let mut queued = 0;
+        for (message_id, forward) in deliveries {
+            queued +=
+                query("INSERT OR IGNORE INTO forward_delivery (message_id, forward) VALUES (?, ?)")
+                    .bind(message_id)
+                    .bind(forward)
+                    .execute(&mut *transaction)
+                    .await
+                    .context("Failed to queue forward delivery")?
+                    .rows_affected();
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to queue forward deliveries")?;
+        Ok(usize::try_from(queued)?)
+    }
+
+    // Load the pending deliveries that are due to be attempted, oldest first
+    pub async fn load_due_forward_deliveries(&self, limit: usize) -> Result<Vec<ForwardDelivery>> {
+        sqlx::query_as::<_, ForwardDelivery>(
+            "SELECT * FROM forward_delivery
+            WHERE status = 'pending' AND next_attempt <= CURRENT_TIMESTAMP
+            ORDER BY next_attempt LIMIT ?",
+        )
+        .bind(i64::try_from(limit)?)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load due forward deliveries")
+    }
+
+    // Load the most recently updated deliveries, newest first
+    pub async fn load_forward_deliveries(&self, limit: usize) -> Result<Vec<ForwardDelivery>> {
+        sqlx::query_as::<_, ForwardDelivery>(
+            "SELECT * FROM forward_delivery ORDER BY updated DESC, message_id DESC LIMIT ?",
+        )
+        .bind(i64::try_from(limit)?)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load forward deliveries")
+    }
+
+    // Record that a message was delivered to a forward
+    pub async fn record_forward_delivered(&self, message_id: Id, forward: &str) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        query(
+            "UPDATE forward_delivery
+            SET status = 'delivered', attempts = attempts + 1, last_error = NULL,
+                updated = CURRENT_TIMESTAMP
+            WHERE message_id = ? AND forward = ?",
+        )
+        .bind(message_id)
+        .bind(forward)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record forward delivery")?;
+        Ok(())
+    }
+
+    // Record that delivering a message to a forward failed, retrying it after a delay or giving up
+    // if the delay is None
+    pub async fn record_forward_failure(
+        &self,
+        message_id: Id,
+        forward: &str,
+        error: &str,
+        retry_after: Option<Duration>,
+    ) -> Result<()> {
+        let retry_seconds = retry_after
+            .map(|delay| i64::try_from(delay.as_secs()))
+            .transpose()?;
+        let _guard = self.write_lock.lock().await;
+        query(
+            "UPDATE forward_delivery
+            SET status = CASE WHEN ? IS NULL THEN 'failed' ELSE 'pending' END,
+                attempts = attempts + 1, last_error = ?,
+                next_attempt = datetime('now', '+' || coalesce(?, 0) || ' seconds'),
+                updated = CURRENT_TIMESTAMP
+            WHERE message_id = ? AND forward = ?",
+        )
+        .bind(retry_seconds)
+        .bind(error)
+        .bind(retry_seconds)
+        .bind(message_id)
+        .bind(forward)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record forward failure")?;
+        Ok(())
+    }
+
+    // Start a transaction that modifies messages
+    // Writes from this process are queued so that only one runs at a time, and each write runs in
+    // an immediate transaction so that it waits for other processes' writes to finish up front
+    // instead of failing with SQLITE_BUSY partway through. The returned guard must be held until
+    // the transaction is committed.
+    async fn begin_write(&self) -> Result<(MutexGuard<'_, ()>, Transaction<'static, Sqlite>)> {
+        let guard = self.write_lock.lock().await;
+        let transaction = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        Ok((guard, transaction))
+    }
+
+    // Run a statement that modifies messages and return the modified messages
+    async fn write_messages(&self, sql: &str, values: SqlxValues) -> Result<Vec<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let mut messages = sqlx::query_as_with::<_, Message, _>(sql, values)
+            .fetch_all(&mut *transaction)
+            .await?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction.commit().await?;
+        Ok(messages)
+    }
+
+    // Populate the tags of the messages
+    // Populate the tags and related ids of the messages, which are stored in separate tables
+    async fn load_relations(
+        connection: &mut SqliteConnection,
+        messages: &mut [Message],
+    ) -> Result<()> {
+        Self::load_tags(connection, messages).await?;
+        Self::load_links(connection, messages).await
+    }
+
+    async fn load_links(connection: &mut SqliteConnection, messages: &mut [Message]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let ids = serde_json::to_string(
+            &messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+        )?;
+        let rows = query(
+            "SELECT message_id, related_id FROM message_link
+            WHERE message_id IN (SELECT value FROM json_each(?))
+            ORDER BY related_id",
+        )
+        .bind(ids)
+        .fetch_all(connection)
+        .await
+        .context("Failed to load linked messages")?;
+        let mut links = HashMap::<Id, Vec<Id>>::new();
+        for row in rows {
+            links
+                .entry(row.try_get("message_id")?)
+                .or_default()
+                .push(row.try_get("related_id")?);
+        }
+        for message in messages {
+            message.related_ids = links.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    async fn load_tags(connection: &mut SqliteConnection, messages: &mut [Message]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        // Pass the ids as a JSON array so that the number of messages isn't limited by the maximum
+        // number of SQL parameters
+        let ids = serde_json::to_string(
+            &messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+        )?;
+        let rows = query(
+            "SELECT message_tag.message_id, tag.name FROM message_tag
+            JOIN tag ON tag.id = message_tag.tag_id
+            WHERE message_tag.message_id IN (SELECT value FROM json_each(?))
+            ORDER BY tag.name",
+        )
+        .bind(ids)
+        .fetch_all(connection)
+        .await
+        .context("Failed to load tags")?;
+        let mut tags = HashMap::<Id, Vec<Tag>>::new();
+        for row in rows {
+            tags.entry(row.try_get("message_id")?)
+                .or_default()
+                .push(row.try_get::<String, _>("name")?.try_into()?);
+        }
+        for message in messages {
+            message.tags = tags.remove(&message.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    // Load the messages that match the filter in the order and page that it requests
+    async fn select_messages(
+        connection: &mut SqliteConnection,
+        filter: Filter,
+    ) -> Result<Vec<Message>> {
+        let consumer = filter.get_consumer().map(ToOwned::to_owned);
+        let order_by = filter.get_order_by();
+        let filter_page = filter.get_page();
+        let mut statement = Query::select();
+        statement
+            .column((MessageIden::Table, Asterisk))
+            .from(MessageIden::Table)
+            .cond_where(filter.get_where());
+        for (column, order) in order_by {
+            statement.order_by(column, order);
+        }
+        let (offset, limit) = filter_page;
+        if offset > 0 || limit.is_some() {
+            // SQLite only supports OFFSET after LIMIT, and a negative limit means no limit
+            statement.limit(limit.map_or(i64::MAX as u64, |limit| limit as u64));
+            statement.offset(offset as u64);
+        }
+        let (sql, values) = scope_to_consumer(
+            statement.build_sqlx(SqliteQueryBuilder),
+            consumer.as_deref(),
+        );
+
+        let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
+            .fetch_all(&mut *connection)
+            .await
+            .context("Failed to load messages")?;
+        Self::load_relations(connection, &mut messages).await?;
+        Ok(messages)
+    }
+
+    // Load the attachments of many messages at once, keyed by message id
+    async fn load_attachments_by_message(
+        connection: &mut SqliteConnection,
+        messages: &[Message],
+    ) -> Result<HashMap<Id, Vec<Attachment>>> {
+        let rows = query(
+            "SELECT message_id, name, data, location FROM attachment
+            WHERE message_id IN (SELECT value FROM json_each(?))
+            ORDER BY id",
+        )
+        .bind(serialize_ids(messages)?)
+        .fetch_all(connection)
+        .await
+        .context("Failed to load attachments")?;
+        let mut attachments = HashMap::<Id, Vec<Attachment>>::new();
+        for row in rows {
+            attachments
+                .entry(row.try_get("message_id")?)
+                .or_default()
+                .push(attachment_from_row(&row)?);
+        }
+        Ok(attachments)
+    }
+
+    // Delete the messages that match the filter along with their tags, attachments, and other
+    // related rows, returning the deleted messages
+    async fn delete_matching(
+        connection: &mut SqliteConnection,
+        filter: Filter,
+    ) -> Result<Vec<Message>> {
+        let consumer = filter.get_consumer().map(ToOwned::to_owned);
+        let condition = if consumer.is_some() {
+            Filter::new()
+                .with_ids(Self::load_consumer_ids(&mut *connection, filter).await?)
+                .get_where()
+        } else {
+            filter.get_where()
+        };
+        let (sql, values) = Query::delete()
+            .from_table(MessageIden::Table)
+            .returning_all()
+            .cond_where(condition)
+            .build_sqlx(SqliteQueryBuilder);
+        let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
+            .fetch_all(&mut *connection)
+            .await
+            .context("Failed to clear messages")?;
+        // Load the tags and consumer states before detaching them from the deleted messages
+        Self::load_relations(&mut *connection, &mut messages).await?;
+        if let Some(consumer) = consumer.as_deref() {
+            Self::load_consumer_states(&mut *connection, consumer, &mut messages).await?;
+        }
+        let ids = serialize_ids(&messages)?;
+        query("DELETE FROM message_tag WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear message tags")?;
+        query("DELETE FROM attachment WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear attachments")?;
+        query("DELETE FROM consumer_state WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear consumer states")?;
+        query("DELETE FROM lease WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear leases")?;
+        query("DELETE FROM claim_attempt WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear claim attempts")?;
+        query("DELETE FROM reminder WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(&ids)
+            .execute(&mut *connection)
+            .await
+            .context("Failed to clear reminders")?;
+        query(
+            "DELETE FROM message_link WHERE message_id IN (SELECT value FROM json_each(?1))
+            OR related_id IN (SELECT value FROM json_each(?1))",
+        )
+        .bind(&ids)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to clear linked messages")?;
+        Ok(messages)
+    }
+
+    // Replace the shared states of the messages with a consumer's own states
+    async fn load_consumer_states(
+        connection: &mut SqliteConnection,
+        consumer: &str,
+        messages: &mut [Message],
+    ) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let rows = query(
+            "SELECT message_id, state FROM consumer_state
+            WHERE consumer = ? AND message_id IN (SELECT value FROM json_each(?))",
+        )
+        .bind(consumer)
+        .bind(serialize_ids(messages)?)
+        .fetch_all(connection)
+        .await
+        .context("Failed to load consumer states")?;
+        let mut states = HashMap::<Id, State>::new();
+        for row in rows {
+            states.insert(
+                row.try_get("message_id")?,
+                row.try_get::<u32, _>("state")?.try_into()?,
+            );
+        }
+        for message in messages {
+            if let Some(state) = states.remove(&message.id) {
+                message.state = state;
+            }
+        }
+        Ok(())
+    }
+
+    // Load the ids of the messages that match a filter with a consumer, which can't be applied to
+    // the message table directly because it filters by the consumer's own states
+    async fn load_consumer_ids(
+        connection: &mut SqliteConnection,
+        filter: Filter,
+    ) -> Result<Vec<Id>> {
+        let consumer = filter.get_consumer().map(ToOwned::to_owned);
+        let (sql, values) = scope_to_consumer(
+            Query::select()
+                .column(MessageIden::Id)
+                .from(MessageIden::Table)
+                .cond_where(filter.get_where())
+                .build_sqlx(SqliteQueryBuilder),
+            consumer.as_deref(),
+        );
+        sqlx::query_with(&sql, values)
+            .fetch_all(connection)
+            .await
+            .context("Failed to load messages")?
+            .iter()
+            .map(|row| Ok(row.try_get("id")?))
+            .collect()
+    }
+
+    // Change a consumer's own states of the messages that match the filter, leaving their shared
+    // states alone
+    async fn change_consumer_state(
+        &self,
+        consumer: &str,
+        filter: Filter,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let ids = serde_json::to_string(&Self::load_consumer_ids(&mut transaction, filter).await?)?;
+        query(
+            "INSERT INTO consumer_state (message_id, consumer, state)
+            SELECT value, ?, ? FROM json_each(?) WHERE true
+            ON CONFLICT (message_id, consumer) DO UPDATE SET state = excluded.state",
+        )
+        .bind(consumer)
+        .bind(u32::from(new_state))
+        .bind(&ids)
+        .execute(&mut *transaction)
+        .await?;
+        // Give the messages new sequence numbers so that clients watching for changes reload them
+        let mut messages = sqlx::query_as::<_, Message>(&format!(
+            "UPDATE message SET seq = {NEXT_SEQ}
+            WHERE id IN (SELECT value FROM json_each(?)) RETURNING *"
+        ))
+        .bind(&ids)
+        .fetch_all(&mut *transaction)
+        .await?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        Self::load_consumer_states(&mut transaction, consumer, &mut messages).await?;
+        transaction.commit().await?;
+        Ok(messages)
+    }
+
+    // Attach tags to newly added messages
+    async fn add_tags(connection: &mut SqliteConnection, messages: &[Message]) -> Result<()> {
+        let message_tags = messages
+            .iter()
+            .flat_map(|message| message.tags.iter().map(|tag| (message.id, tag.as_ref())))
+            .collect::<Vec<_>>();
+        if message_tags.is_empty() {
+            return Ok(());
+        }
+
+        // Pass the tags as a JSON array of [message_id, tag] pairs so that the number of tags isn't
+        // limited by the maximum number of SQL parameters
+        let message_tags = serde_json::to_string(&message_tags)?;
+        query(
+            "INSERT INTO tag (name) SELECT DISTINCT json_extract(value, '$[1]') FROM json_each(?)
+            WHERE true ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(&message_tags)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to add tags")?;
+        query(
+            "INSERT OR IGNORE INTO message_tag (message_id, tag_id)
+            SELECT json_extract(pair.value, '$[0]'), tag.id FROM json_each(?) AS pair
+            JOIN tag ON tag.name = json_extract(pair.value, '$[1]')",
+        )
+        .bind(&message_tags)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to add message tags")?;
+        Ok(())
+    }
+
+    // Detach all tags from the messages
+    async fn remove_tags(connection: &mut SqliteConnection, messages: &[Message]) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let ids = serde_json::to_string(
+            &messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+        )?;
+        query("DELETE FROM message_tag WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(ids)
+            .execute(connection)
+            .await
+            .context("Failed to remove tags")?;
+        Ok(())
+    }
+
+    // Store the files attached to each message
+    async fn add_attachments(
+        connection: &mut SqliteConnection,
+        messages: &[Message],
+        attachments: Vec<Vec<Attachment>>,
+    ) -> Result<()> {
+        // Attachments can be large binary files, so insert them one at a time with bound
+        // parameters instead of encoding them all as JSON
+        for (message, attachments) in messages.iter().zip(attachments) {
+            for attachment in attachments {
+                let (name, data, location) = match attachment {
+                    Attachment::Blob { name, data } => (Some(name), Some(data), None),
+                    Attachment::Reference { location } => (None, None, Some(location)),
+                };
+                query(
+                    "INSERT INTO attachment (message_id, name, data, location) VALUES (?, ?, ?, ?)",
+                )
+                .bind(message.id)
+                .bind(name)
+                .bind(data)
+                .bind(location)
+                .execute(&mut *connection)
+                .await
+                .context("Failed to add attachments")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Delete all attachments of the messages
+    async fn remove_attachments(
+        connection: &mut SqliteConnection,
+        messages: &[Message],
+    ) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let ids = serde_json::to_string(
+            &messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>(),
+        )?;
+        query("DELETE FROM attachment WHERE message_id IN (SELECT value FROM json_each(?))")
+            .bind(ids)
+            .execute(connection)
+            .await
+            .context("Failed to remove attachments")?;
+        Ok(())
+    }
+
+    // Add messages, returning the added messages
+    // If upsert is true, messages with an external id that already exists in their mailbox update
+    // the existing message instead. Otherwise, duplicate external ids violate the unique index.
+    async fn insert_messages(
+        &self,
+        mut messages: Vec<NewMessage>,
+        upsert: bool,
+    ) -> Result<Vec<Message>> {
         if messages.is_empty() {
             // The SQL query will be malformed if there are no messages to add, so bail
             return Ok(vec![]);
         }
 
+        // Tags are stored separately, so save them to attach to the added messages
+        let new_tags = messages
+            .iter()
+            .map(|message| {
+                let mut tags = message.tags.clone();
+                tags.sort();
+                tags.dedup();
+                tags
+            })
+            .collect::<Vec<_>>();
+        // Attachments are also stored separately, and they are moved out of the messages because
+        // they can be large
+        let new_attachments = messages
+            .iter_mut()
+            .map(|message| std::mem::take(&mut message.attachments))
+            .collect::<Vec<_>>();
+
         let mut statement = Query::insert();
         statement.into_table(MessageIden::Table).columns([
             MessageIden::Mailbox,
             MessageIden::Content,
             MessageIden::State,
+            MessageIden::Timestamp,
+            MessageIden::ExternalId,
+            MessageIden::Priority,
+            MessageIden::Kind,
         ]);
         // Add the messages in reverse order so that the first message in the batch will appear
         // first when the messages are loaded
         for message in messages.into_iter().rev() {
+            let kind = message.resolved_kind();
             statement.values(vec![
                 message.mailbox.into(),
                 message.content.into(),
                 message.state.unwrap_or(State::Unread).into(),
+                // Fall back to the column's default when no explicit timestamp was provided
+                message
+                    .timestamp
+                    .map_or(SimpleExpr::Keyword(Keyword::CurrentTimestamp), Into::into),
+                message.external_id.into(),
+                message.priority.unwrap_or_default().into(),
+                kind.into(),
             ])?;
         }
+        if upsert {
+            statement.on_conflict(
+                OnConflict::columns([MessageIden::Mailbox, MessageIden::ExternalId])
+                    .update_columns([
+                        MessageIden::Content,
+                        MessageIden::State,
+                        MessageIden::CustomState,
+                        MessageIden::Timestamp,
+                        MessageIden::Priority,
+                        MessageIden::Kind,
+                    ])
+                    .to_owned(),
+            );
+        }
         let (sql, values) = statement.returning_all().build_sqlx(SqliteQueryBuilder);
 
+        let (_guard, mut transaction) = self.begin_write().await?;
         let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *transaction)
             .await
             .context("Failed to add messages")?;
         // Reverse the messages back to the order from the input
         messages.reverse();
+        for (message, tags) in messages.iter_mut().zip(new_tags) {
+            message.tags = tags;
+        }
+        if upsert {
+            // Updated messages replace their old tags
+            Self::remove_tags(&mut transaction, &messages).await?;
+            Self::remove_attachments(&mut transaction, &messages).await?;
+        }
+        Self::add_tags(&mut transaction, &messages).await?;
+        Self::add_attachments(&mut transaction, &messages, new_attachments).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to add messages")?;
         Ok(messages)
     }
 
-    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
-        let (sql, values) = Query::select()
-            .column((MessageIden::Table, Asterisk))
+    // Build a query that counts the messages matching the filter in each mailbox
+    fn build_count_messages_query(filter: Filter) -> (String, SqlxValues) {
+        let consumer = filter.get_consumer().map(ToOwned::to_owned);
+        let query = Query::select()
             .from(MessageIden::Table)
+            .column(MessageIden::Mailbox)
             .cond_where(filter.get_where())
-            .order_by(MessageIden::Id, Order::Desc)
+            .expr_as(Func::count(Expr::col(MessageIden::Id)), Alias::new("count"))
+            .group_by_col(MessageIden::Mailbox)
+            .order_by(MessageIden::Mailbox, Order::Asc)
+            .distinct()
             .build_sqlx(SqliteQueryBuilder);
+        scope_to_consumer(query, consumer.as_deref())
+    }
 
-        sqlx::query_as_with::<_, Message, _>(&sql, values)
-            .fetch_all(&self.pool)
+    // Bring the database schema up to date by running the migrations that haven't been applied yet
+    // The schema version is stored in the database's user_version
+    async fn migrate(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        let mut transaction = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .context("Failed to migrate database")?;
+        let version = query("PRAGMA user_version")
+            .fetch_one(&mut *transaction)
+            .await
+            .context("Failed to read database schema version")?
+            .try_get::<i64, _>(0)?;
+        let migrations = get_migrations();
+        let target_version = i64::try_from(migrations.len())?;
+        if version > target_version {
+            bail!(
+                "The database schema version is {version}, but this version of mailbox only supports schema versions up to {target_version}. Try upgrading mailbox."
+            );
+        }
+        if version == target_version {
+            return Ok(());
+        }
+
+        for (index, migration) in migrations
+            .iter()
+            .enumerate()
+            .skip(usize::try_from(version)?)
+        {
+            for sql in migration {
+                query(sql)
+                    .execute(&mut *transaction)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to migrate database to version {}", index + 1)
+                    })?;
+            }
+        }
+        query(&format!("PRAGMA user_version = {target_version}"))
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to update database schema version")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to migrate database")?;
+        Ok(())
+    }
+}
+
+// Return the SQL statements of each migration. Migrations must never be modified after they are
+// released, only appended to.
+// The list only grows, so it isn't worth splitting up
+#[allow(clippy::too_many_lines)]
+fn get_migrations() -> Vec<Vec<String>> {
+    vec![
+        // Track message changes for load_changes
+        vec![
+            // Start existing messages with distinct sequence numbers in the order they were added
+            String::from("ALTER TABLE message ADD COLUMN seq INTEGER NOT NULL DEFAULT 0"),
+            String::from("UPDATE message SET seq = id"),
+            String::from(
+                "CREATE TABLE deleted_message (id INTEGER NOT NULL PRIMARY KEY, seq INTEGER NOT NULL)",
+            ),
+            String::from("CREATE INDEX message_seq ON message (seq)"),
+            String::from("CREATE INDEX deleted_message_seq ON deleted_message (seq)"),
+            format!(
+                "CREATE TRIGGER message_insert_seq AFTER INSERT ON message
+                BEGIN UPDATE message SET seq = {NEXT_SEQ} WHERE id = NEW.id; END"
+            ),
+            // Only watch the state column so that updating seq doesn't trigger another update
+            format!(
+                "CREATE TRIGGER message_update_seq AFTER UPDATE OF state ON message
+                BEGIN UPDATE message SET seq = {NEXT_SEQ} WHERE id = NEW.id; END"
+            ),
+            format!(
+                "CREATE TRIGGER message_delete_seq AFTER DELETE ON message
+                BEGIN INSERT OR REPLACE INTO deleted_message (id, seq) VALUES (OLD.id, {NEXT_SEQ}); END"
+            ),
+        ],
+        // Keep a running count of the messages in each mailbox and state for load_mailboxes
+        vec![
+            String::from(
+                "CREATE TABLE mailbox_count (
+                    mailbox TEXT NOT NULL,
+                    state INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (mailbox, state)
+                )",
+            ),
+            String::from(
+                "INSERT INTO mailbox_count (mailbox, state, count)
+                SELECT mailbox, state, COUNT(*) FROM message GROUP BY mailbox, state",
+            ),
+            format!(
+                "CREATE TRIGGER mailbox_count_insert AFTER INSERT ON message
+                BEGIN {INCREMENT_MAILBOX_COUNT} END"
+            ),
+            format!(
+                "CREATE TRIGGER mailbox_count_delete AFTER DELETE ON message
+                BEGIN {DECREMENT_MAILBOX_COUNT} END"
+            ),
+            format!(
+                "CREATE TRIGGER mailbox_count_update AFTER UPDATE OF mailbox, state ON message
+                BEGIN {DECREMENT_MAILBOX_COUNT} {INCREMENT_MAILBOX_COUNT} END"
+            ),
+        ],
+        // Index the columns that messages are commonly filtered and sorted by
+        vec![
+            String::from("CREATE INDEX message_mailbox ON message (mailbox)"),
+            String::from("CREATE INDEX message_state ON message (state)"),
+            String::from("CREATE INDEX message_timestamp ON message (timestamp)"),
+        ],
+        // Store tags, which can be attached to any number of messages
+        vec![
+            String::from(
+                "CREATE TABLE tag (id INTEGER NOT NULL PRIMARY KEY, name TEXT NOT NULL UNIQUE)",
+            ),
+            String::from(
+                "CREATE TABLE message_tag (
+                    message_id INTEGER NOT NULL,
+                    tag_id INTEGER NOT NULL,
+                    PRIMARY KEY (message_id, tag_id)
+                )",
+            ),
+            String::from("CREATE INDEX message_tag_tag_id ON message_tag (tag_id)"),
+        ],
+        // Identify messages by an external id so that they can be upserted
+        vec![
+            String::from("ALTER TABLE message ADD COLUMN external_id TEXT"),
+            // NULL external ids are distinct, so messages without one are never in conflict
+            String::from(
+                "CREATE UNIQUE INDEX message_mailbox_external_id ON message (mailbox, external_id)",
+            ),
+            // Upserts can change a message's content and timestamp without changing its state
+            String::from("DROP TRIGGER message_update_seq"),
+            format!(
+                "CREATE TRIGGER message_update_seq AFTER UPDATE OF state, content, timestamp ON message
+                BEGIN UPDATE message SET seq = {NEXT_SEQ} WHERE id = NEW.id; END"
+            ),
+        ],
+        // Store the API tokens that administrators create for servers
+        vec![String::from(
+            "CREATE TABLE api_token (
+                name TEXT NOT NULL PRIMARY KEY,
+                token TEXT NOT NULL UNIQUE,
+                created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )],
+        // Store the files attached to messages. Blobs have a name and data, and references only
+        // have a location.
+        vec![
+            String::from(
+                "CREATE TABLE attachment (
+                    id INTEGER PRIMARY KEY,
+                    message_id INTEGER NOT NULL,
+                    name TEXT,
+                    data BLOB,
+                    location TEXT
+                )",
+            ),
+            String::from("CREATE INDEX attachment_message_id ON attachment (message_id)"),
+        ],
+        // Track how often and how recently each message was opened
+        vec![
+            String::from("ALTER TABLE message ADD COLUMN view_count INTEGER NOT NULL DEFAULT 0"),
+            String::from("ALTER TABLE message ADD COLUMN last_viewed TIMESTAMP"),
+        ],
+        // Store each consumer's own message states for messages shared by multiple consumers
+        vec![String::from(
+            "CREATE TABLE consumer_state (
+                message_id INTEGER NOT NULL,
+                consumer TEXT NOT NULL,
+                state INTEGER NOT NULL,
+                PRIMARY KEY (message_id, consumer)
+            )",
+        )],
+        // Track which worker claimed each message and when its lease expires
+        vec![String::from(
+            "CREATE TABLE lease (
+                message_id INTEGER NOT NULL PRIMARY KEY,
+                worker TEXT NOT NULL,
+                expires TIMESTAMP NOT NULL
+            )",
+        )],
+        // Let urgent messages be claimed first
+        vec![String::from(
+            "ALTER TABLE message ADD COLUMN priority INTEGER NOT NULL DEFAULT 0",
+        )],
+        // Count how many times each message has been claimed so that failing messages can be
+        // moved into the dead-letter mailbox
+        vec![String::from(
+            "CREATE TABLE claim_attempt (
+                message_id INTEGER NOT NULL PRIMARY KEY,
+                attempts INTEGER NOT NULL
+            )",
+        )],
+        // Restrict API tokens to the mailboxes in their access lists, which are stored as JSON
+        // arrays of glob patterns, or NULL to allow every mailbox
+        vec![
+            String::from("ALTER TABLE api_token ADD COLUMN read_mailboxes TEXT"),
+            String::from("ALTER TABLE api_token ADD COLUMN write_mailboxes TEXT"),
+        ],
+        // Record the API calls that changed messages or tokens so that administrators can see who
+        // made each change
+        vec![String::from(
+            "CREATE TABLE audit_log (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                actor TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                target TEXT NOT NULL,
+                affected INTEGER NOT NULL
+            )",
+        )],
+        // Record the format of each message's content so that clients can display and open it
+        // without guessing. Existing messages are treated as text.
+        vec![String::from(
+            "ALTER TABLE message ADD COLUMN kind INTEGER NOT NULL DEFAULT 0",
+        )],
+        // Track the delivery of messages to push notification services so that failed deliveries
+        // can be retried
+        vec![String::from(
+            "CREATE TABLE forward_delivery (
+                message_id INTEGER NOT NULL,
+                forward TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                next_attempt TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (message_id, forward)
+            )",
+        )],
+        // Schedule messages to return to unread later
+        vec![String::from(
+            "CREATE TABLE reminder (
+                message_id INTEGER NOT NULL PRIMARY KEY,
+                remind_at TIMESTAMP NOT NULL
+            )",
+        )],
+        // Link related messages, like an alert and the message that resolves it. Each link is
+        // stored in both directions.
+        vec![String::from(
+            "CREATE TABLE message_link (
+                message_id INTEGER NOT NULL,
+                related_id INTEGER NOT NULL,
+                PRIMARY KEY (message_id, related_id)
+            )",
+        )],
+        // Expect heartbeat mailboxes to receive a message at least once per interval
+        vec![String::from(
+            "CREATE TABLE heartbeat (
+                mailbox TEXT NOT NULL PRIMARY KEY,
+                interval_seconds INTEGER NOT NULL,
+                created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )],
+        // Attach metadata to mailboxes themselves, including the heartbeat intervals that were
+        // previously stored in their own table
+        vec![
+            String::from(
+                "CREATE TABLE mailbox (
+                    name TEXT NOT NULL PRIMARY KEY,
+                    description TEXT,
+                    color TEXT,
+                    default_state INTEGER,
+                    quota INTEGER,
+                    heartbeat_seconds INTEGER,
+                    heartbeat_created TIMESTAMP,
+                    pinned_order INTEGER
+                )",
+            ),
+            String::from(
+                "INSERT INTO mailbox (name, heartbeat_seconds, heartbeat_created)
+                SELECT mailbox, interval_seconds, created FROM heartbeat",
+            ),
+            String::from("DROP TABLE heartbeat"),
+        ],
+        // Let messages be in custom states that refine their built-in state
+        vec![String::from(
+            "ALTER TABLE message ADD COLUMN custom_state TEXT",
+        )],
+        // Index mailboxes case-insensitively because mailbox filters ignore case
+        vec![
+            String::from("DROP INDEX message_mailbox"),
+            String::from("CREATE INDEX message_mailbox ON message (mailbox COLLATE NOCASE)"),
+        ],
+    ]
+}
+
+impl Backend for SqliteBackend {
+    async fn add_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.insert_messages(messages, false).await
+    }
+
+    async fn upsert_messages(&self, messages: Vec<NewMessage>) -> Result<Vec<Message>> {
+        self.insert_messages(messages, true).await
+    }
+
+    async fn load_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        // Use a transaction so that the tags are loaded from the same snapshot as the messages
+        let mut transaction = self.pool.begin().await.context("Failed to load messages")?;
+        let messages = Self::select_messages(&mut transaction, filter).await?;
+        transaction
+            .commit()
             .await
-            .context("Failed to load messages")
+            .context("Failed to load messages")?;
+        Ok(messages)
     }
 
     async fn change_state(&self, filter: Filter, new_state: State) -> Result<Vec<Message>> {
+        if let Some(consumer) = filter.get_consumer().map(ToOwned::to_owned) {
+            let mut messages = self
+                .change_consumer_state(&consumer, filter, new_state)
+                .await
+                .context("Failed to change message states")?;
+            messages.sort_by_key(|message| -message.timestamp.and_utc().timestamp());
+            return Ok(messages);
+        }
+
         let (sql, values) = Query::update()
             .table(MessageIden::Table)
             .cond_where(filter.get_where())
             .value::<_, u32>(MessageIden::State, new_state.into())
+            .value(MessageIden::CustomState, Option::<String>::None)
             .returning_all()
             .build_sqlx(SqliteQueryBuilder);
 
-        let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
-            .fetch_all(&self.pool)
+        let mut messages = self
+            .write_messages(&sql, values)
             .await
             .context("Failed to change message states")?;
         // Sort the messages manually since SQLite doesn't support sorting RETURNING results
@@ -193,15 +1533,40 @@ impl Backend for SqliteBackend {
         Ok(messages)
     }
 
-    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
-        let (sql, values) = Query::delete()
-            .from_table(MessageIden::Table)
-            .returning_all()
+    async fn change_custom_state(
+        &self,
+        filter: Filter,
+        custom_state: String,
+        new_state: State,
+    ) -> Result<Vec<Message>> {
+        if filter.get_consumer().is_some() {
+            return Err(
+                ValidationError(String::from("custom states are shared by all consumers")).into(),
+            );
+        }
+
+        let (sql, values) = Query::update()
+            .table(MessageIden::Table)
             .cond_where(filter.get_where())
+            .value::<_, u32>(MessageIden::State, new_state.into())
+            .value(MessageIden::CustomState, custom_state)
+            .returning_all()
             .build_sqlx(SqliteQueryBuilder);
 
-        let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
-            .fetch_all(&self.pool)
+        let mut messages = self
+            .write_messages(&sql, values)
+            .await
+            .context("Failed to change message custom states")?;
+        // Sort the messages manually since SQLite doesn't support sorting RETURNING results
+        messages.sort_by_key(|message| -message.timestamp.and_utc().timestamp());
+        Ok(messages)
+    }
+
+    async fn delete_messages(&self, filter: Filter) -> Result<Vec<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let mut messages = Self::delete_matching(&mut transaction, filter).await?;
+        transaction
+            .commit()
             .await
             .context("Failed to clear messages")?;
         // Sort the messages manually since SQLite doesn't support sorting RETURNING results
@@ -209,16 +1574,70 @@ impl Backend for SqliteBackend {
         Ok(messages)
     }
 
+    async fn archive_messages(&self, filter: Filter, archiver: Archiver) -> Result<Vec<Message>> {
+        // Select, archive, and delete the messages in one write transaction so that they can't
+        // change in between, and only commit the deletion after the archiver succeeds
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let messages = Self::select_messages(&mut transaction, filter).await?;
+        if messages.is_empty() {
+            return Ok(messages);
+        }
+
+        let mut attachments =
+            Self::load_attachments_by_message(&mut transaction, &messages).await?;
+        let archived_messages = messages
+            .iter()
+            .map(|message| ArchivedMessage {
+                message: message.clone(),
+                attachments: attachments.remove(&message.id).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        archiver(&archived_messages)?;
+        let ids = messages.iter().map(|message| message.id).collect();
+        Self::delete_matching(&mut transaction, Filter::new().with_ids(ids)).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to delete archived messages")?;
+        Ok(messages)
+    }
+
     async fn load_mailboxes(&self, filter: Filter) -> Result<Vec<MailboxInfo>> {
-        let (sql, values) = Query::select()
-            .from(MessageIden::Table)
-            .column(MessageIden::Mailbox)
-            .cond_where(filter.get_where())
-            .expr_as(Func::count(Expr::col(MessageIden::Id)), Alias::new("count"))
-            .group_by_col(MessageIden::Mailbox)
-            .order_by(MessageIden::Mailbox, Order::Asc)
-            .distinct()
-            .build_sqlx(SqliteQueryBuilder);
+        let hierarchy_counts = filter.has_hierarchy_counts();
+        // The mailbox counts table can only filter by mailbox and state, so count the messages
+        // directly otherwise
+        let (sql, values) = if filter.only_filters_mailbox_and_state() {
+            Query::select()
+                .from(Alias::new(MAILBOX_COUNT_TABLE))
+                .column(MessageIden::Mailbox)
+                .cond_where(filter.get_where())
+                .expr_as(
+                    Func::sum(Expr::col(Alias::new("count"))),
+                    Alias::new("count"),
+                )
+                .group_by_col(MessageIden::Mailbox)
+                .order_by(MessageIden::Mailbox, Order::Asc)
+                .build_sqlx(SqliteQueryBuilder)
+        } else {
+            Self::build_count_messages_query(filter)
+        };
+        let sql = if hierarchy_counts {
+            // Add each mailbox's count to every one of its ancestors by recursively finding the
+            // length of each prefix of the mailbox name that ends before a "/"
+            format!(
+                "WITH RECURSIVE counts AS ({sql}),
+                prefixes (mailbox, prefix_length, count) AS (
+                    SELECT mailbox, instr(mailbox || '/', '/') - 1, count FROM counts
+                    UNION ALL
+                    SELECT mailbox, prefix_length + instr(substr(mailbox || '/', prefix_length + 2), '/'), count
+                    FROM prefixes WHERE prefix_length < length(mailbox)
+                )
+                SELECT substr(mailbox, 1, prefix_length) AS mailbox, SUM(count) AS count
+                FROM prefixes GROUP BY 1 ORDER BY 1"
+            )
+        } else {
+            sql
+        };
         let rows = sqlx::query_with(&sql, values)
             .fetch_all(&self.pool)
             .await
@@ -234,12 +1653,524 @@ impl Backend for SqliteBackend {
             .collect::<Result<Vec<_>>>()?;
         Ok(mailboxes)
     }
+
+    async fn load_changes(&self, since: Seq) -> Result<Changes> {
+        // Use a transaction so that all three queries see the same snapshot of the database
+        let mut transaction = self.pool.begin().await.context("Failed to load changes")?;
+
+        let seq = query(&format!("SELECT {NEXT_SEQ} - 1 AS seq"))
+            .fetch_one(&mut *transaction)
+            .await
+            .context("Failed to load changes")?
+            .try_get::<Seq, _>("seq")?;
+
+        let (sql, values) = Query::select()
+            .column((MessageIden::Table, Asterisk))
+            .from(MessageIden::Table)
+            .and_where(Expr::col(Alias::new(SEQ_COLUMN)).gt(since))
+            .order_by(MessageIden::Id, Order::Desc)
+            .build_sqlx(SqliteQueryBuilder);
+        let mut messages = sqlx::query_as_with::<_, Message, _>(&sql, values)
+            .fetch_all(&mut *transaction)
+            .await
+            .context("Failed to load changed messages")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+
+        let (sql, values) = Query::select()
+            .column(MessageIden::Id)
+            .from(Alias::new(DELETED_MESSAGE_TABLE))
+            .and_where(Expr::col(Alias::new(SEQ_COLUMN)).gt(since))
+            .order_by(MessageIden::Id, Order::Desc)
+            .build_sqlx(SqliteQueryBuilder);
+        let deleted = sqlx::query_with(&sql, values)
+            .fetch_all(&mut *transaction)
+            .await
+            .context("Failed to load deleted messages")?
+            .iter()
+            .map(|row| row.try_get("id"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to load changes")?;
+        Ok(Changes {
+            seq,
+            messages,
+            deleted,
+        })
+    }
+
+    async fn load_stats(&self, filter: Filter, bucket: Bucket) -> Result<Vec<BucketStats>> {
+        // An SQL expression that calculates the start of the bucket containing the message
+        let bucket_start = match bucket {
+            Bucket::Hour => "strftime('%Y-%m-%d %H:00:00', timestamp)",
+            Bucket::Day => "strftime('%Y-%m-%d 00:00:00', timestamp)",
+            Bucket::Week => "strftime('%Y-%m-%d 00:00:00', timestamp, '-6 days', 'weekday 1')",
+            Bucket::Month => "strftime('%Y-%m-01 00:00:00', timestamp)",
+        };
+        let consumer = filter.get_consumer().map(ToOwned::to_owned);
+        let query = Query::select()
+            .from(MessageIden::Table)
+            .expr_as(Expr::cust(bucket_start), Alias::new("bucket"))
+            .column(MessageIden::Mailbox)
+            .expr_as(Func::count(Expr::col(MessageIden::Id)), Alias::new("added"))
+            .expr_as(
+                Func::sum(Expr::col(MessageIden::State).eq(State::Read)),
+                Alias::new("read"),
+            )
+            .expr_as(
+                Func::sum(Expr::col(MessageIden::State).eq(State::Archived)),
+                Alias::new("archived"),
+            )
+            .cond_where(filter.get_where())
+            .group_by_col(Alias::new("bucket"))
+            .group_by_col(MessageIden::Mailbox)
+            .order_by(Alias::new("bucket"), Order::Desc)
+            .order_by(MessageIden::Mailbox, Order::Asc)
+            .build_sqlx(SqliteQueryBuilder);
+        let (sql, values) = scope_to_consumer(query, consumer.as_deref());
+        let rows = sqlx::query_with(&sql, values)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load stats")?;
+        rows.iter()
+            .map(|row| {
+                Ok(BucketStats {
+                    bucket: NaiveDateTime::parse_from_str(
+                        &row.try_get::<String, _>("bucket")?,
+                        "%Y-%m-%d %H:%M:%S",
+                    )?,
+                    mailbox: row.try_get::<String, _>("mailbox")?.try_into()?,
+                    added: row.try_get::<i64, _>("added")?.try_into()?,
+                    read: row.try_get::<i64, _>("read")?.try_into()?,
+                    archived: row.try_get::<i64, _>("archived")?.try_into()?,
+                })
+            })
+            .collect()
+    }
+
+    async fn load_attachments(&self, id: Id) -> Result<Vec<Attachment>> {
+        let rows =
+            query("SELECT name, data, location FROM attachment WHERE message_id = ? ORDER BY id")
+                .bind(id)
+                .fetch_all(&self.pool)
+                .await
+                .context("Failed to load attachments")?;
+        rows.iter().map(attachment_from_row).collect()
+    }
+
+    async fn record_view(&self, id: Id) -> Result<Option<Message>> {
+        let (sql, values) = Query::update()
+            .table(MessageIden::Table)
+            .and_where(Expr::col(MessageIden::Id).eq(id))
+            .value(
+                MessageIden::ViewCount,
+                Expr::col(MessageIden::ViewCount).add(1),
+            )
+            .value(
+                MessageIden::LastViewed,
+                SimpleExpr::Keyword(Keyword::CurrentTimestamp),
+            )
+            .returning_all()
+            .build_sqlx(SqliteQueryBuilder);
+
+        let messages = self
+            .write_messages(&sql, values)
+            .await
+            .context("Failed to record message view")?;
+        Ok(messages.into_iter().next())
+    }
+
+    async fn claim_messages(
+        &self,
+        filter: Filter,
+        worker: String,
+        lease: Duration,
+    ) -> Result<Vec<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+
+        // Return the messages whose leases expired to the queue
+        query(
+            "UPDATE message SET state = ?, custom_state = NULL WHERE state = ? AND id IN (
+                SELECT message_id FROM lease WHERE expires <= datetime('now')
+            )",
+        )
+        .bind(u32::from(State::Unread))
+        .bind(u32::from(State::Read))
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to expire leases")?;
+        query("DELETE FROM lease WHERE expires <= datetime('now')")
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to expire leases")?;
+
+        // Claim the most urgent unread messages first, and the oldest first within a priority
+        let (_, limit) = filter.get_page();
+        let mut statement = Query::select();
+        statement
+            .column(MessageIden::Id)
+            .from(MessageIden::Table)
+            .cond_where(filter.get_where())
+            .and_where(Expr::col(MessageIden::State).eq(State::Unread))
+            .order_by(MessageIden::Priority, Order::Desc)
+            .order_by(MessageIden::Id, Order::Asc);
+        if let Some(limit) = limit {
+            statement.limit(limit as u64);
+        }
+        let (sql, values) = statement.build_sqlx(SqliteQueryBuilder);
+        let ids = sqlx::query_with(&sql, values)
+            .fetch_all(&mut *transaction)
+            .await
+            .context("Failed to claim messages")?
+            .iter()
+            .map(|row| row.try_get("id"))
+            .collect::<Result<Vec<Id>, _>>()?;
+        let ids = serde_json::to_string(&ids)?;
+
+        query(
+            "INSERT OR REPLACE INTO lease (message_id, worker, expires)
+            SELECT value, ?, datetime('now', ?) FROM json_each(?)",
+        )
+        .bind(worker)
+        .bind(format!("+{} seconds", lease.as_secs()))
+        .bind(&ids)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to claim messages")?;
+        // The WHERE clause is needed to disambiguate the ON CONFLICT clause from a join constraint
+        query(
+            "INSERT INTO claim_attempt (message_id, attempts)
+            SELECT value, 1 FROM json_each(?) WHERE true
+            ON CONFLICT (message_id) DO UPDATE SET attempts = attempts + 1",
+        )
+        .bind(&ids)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to claim messages")?;
+        let mut messages = sqlx::query_as::<_, Message>(
+            "UPDATE message SET state = ?, custom_state = NULL
+            WHERE id IN (SELECT value FROM json_each(?)) RETURNING *",
+        )
+        .bind(u32::from(State::Read))
+        .bind(&ids)
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to claim messages")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to claim messages")?;
+        // Sort the messages manually since SQLite doesn't support sorting RETURNING results
+        messages.sort_by_key(|message| (std::cmp::Reverse(message.priority), message.id));
+        Ok(messages)
+    }
+
+    async fn nack_message(
+        &self,
+        id: Id,
+        reason: String,
+        dead_letter: DeadLetterPolicy,
+    ) -> Result<Option<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+
+        let Some(message) = sqlx::query_as::<_, Message>("SELECT * FROM message WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .context("Failed to load message")?
+        else {
+            return Ok(None);
+        };
+        let released = query("DELETE FROM lease WHERE message_id = ?")
+            .bind(id)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to release lease")?
+            .rows_affected();
+        if released == 0 {
+            return Err(ValidationError(format!("Message {id} isn't claimed")).into());
+        }
+
+        let attempts = query("SELECT attempts FROM claim_attempt WHERE message_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .context("Failed to load claim attempts")?
+            .map(|row| row.try_get::<u32, _>("attempts"))
+            .transpose()?
+            .unwrap_or_default();
+        let mut messages = if dead_letter.is_exhausted(attempts) {
+            query("DELETE FROM claim_attempt WHERE message_id = ?")
+                .bind(id)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to clear claim attempts")?;
+            let content = DeadLetterPolicy::format_content(
+                &message.content,
+                &message.mailbox,
+                attempts,
+                &reason,
+            );
+            sqlx::query_as::<_, Message>(
+                "UPDATE message SET mailbox = ?, content = ?, state = ?, custom_state = NULL
+                WHERE id = ? RETURNING *",
+            )
+            .bind(dead_letter.mailbox.as_ref())
+            .bind(content)
+        } else {
+            sqlx::query_as::<_, Message>(
+                "UPDATE message SET state = ?, custom_state = NULL WHERE id = ? RETURNING *",
+            )
+        }
+        .bind(u32::from(State::Unread))
+        .bind(id)
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to reject message")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to reject message")?;
+        Ok(messages.into_iter().next())
+    }
+
+    async fn remind_message(&self, id: Id, delay: Duration) -> Result<Option<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+
+        // Move unread messages out of the way until the reminder is due
+        query("UPDATE message SET state = ?, custom_state = NULL WHERE id = ? AND state = ?")
+            .bind(u32::from(State::Read))
+            .bind(id)
+            .bind(u32::from(State::Unread))
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to schedule reminder")?;
+        let mut messages = sqlx::query_as::<_, Message>("SELECT * FROM message WHERE id = ?")
+            .bind(id)
+            .fetch_all(&mut *transaction)
+            .await
+            .context("Failed to load message")?;
+        if messages.is_empty() {
+            return Ok(None);
+        }
+        query(
+            "INSERT OR REPLACE INTO reminder (message_id, remind_at)
+            VALUES (?, datetime('now', ?))",
+        )
+        .bind(id)
+        .bind(format!("+{} seconds", delay.as_secs()))
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to schedule reminder")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to schedule reminder")?;
+        Ok(messages.into_iter().next())
+    }
+
+    async fn wake_reminders(&self) -> Result<Vec<Message>> {
+        // Check for due reminders without waiting for the write lock because this runs before
+        // every load and there usually aren't any
+        let due: bool =
+            query("SELECT EXISTS (SELECT 1 FROM reminder WHERE remind_at <= datetime('now'))")
+                .fetch_one(&self.pool)
+                .await
+                .context("Failed to load reminders")?
+                .try_get(0)?;
+        if !due {
+            return Ok(vec![]);
+        }
+
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let ids =
+            query("DELETE FROM reminder WHERE remind_at <= datetime('now') RETURNING message_id")
+                .fetch_all(&mut *transaction)
+                .await
+                .context("Failed to wake reminders")?
+                .iter()
+                .map(|row| row.try_get("message_id"))
+                .collect::<Result<Vec<Id>, _>>()?;
+        let mut messages = sqlx::query_as::<_, Message>(
+            "UPDATE message SET state = ?, custom_state = NULL
+            WHERE id IN (SELECT value FROM json_each(?)) RETURNING *",
+        )
+        .bind(u32::from(State::Unread))
+        .bind(serde_json::to_string(&ids)?)
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to wake reminders")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to wake reminders")?;
+        // Sort the messages manually since SQLite doesn't support sorting RETURNING results
+        messages.sort_by_key(|message| std::cmp::Reverse(message.id));
+        Ok(messages)
+    }
+
+    async fn link_messages(&self, id: Id, related_id: Id) -> Result<Vec<Message>> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+        let ids = serde_json::to_string(&[id, related_id])?;
+        let count: i64 =
+            query("SELECT COUNT(*) FROM message WHERE id IN (SELECT value FROM json_each(?))")
+                .bind(&ids)
+                .fetch_one(&mut *transaction)
+                .await
+                .context("Failed to load messages")?
+                .try_get(0)?;
+        if count < 2 {
+            return Ok(vec![]);
+        }
+        query(
+            "INSERT OR IGNORE INTO message_link (message_id, related_id) VALUES (?1, ?2), (?2, ?1)",
+        )
+        .bind(id)
+        .bind(related_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to link messages")?;
+        // Bump the sequence numbers so that watchers reload the linked messages
+        let mut messages = sqlx::query_as::<_, Message>(&format!(
+            "UPDATE message SET seq = {NEXT_SEQ}
+            WHERE id IN (SELECT value FROM json_each(?))
+            RETURNING *"
+        ))
+        .bind(&ids)
+        .fetch_all(&mut *transaction)
+        .await
+        .context("Failed to link messages")?;
+        Self::load_relations(&mut transaction, &mut messages).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to link messages")?;
+        messages.sort_by_key(|message| std::cmp::Reverse(message.id));
+        Ok(messages)
+    }
+
+    async fn set_heartbeat(
+        &self,
+        mailbox: Mailbox,
+        interval: Option<Duration>,
+    ) -> Result<Option<Heartbeat>> {
+        let interval_seconds = interval
+            .map(|interval| u32::try_from(interval.as_secs()))
+            .transpose()
+            .context("Heartbeat interval is too long")?;
+        let (_guard, mut transaction) = self.begin_write().await?;
+        // Changing the interval of an existing heartbeat keeps its creation time
+        query(
+            "INSERT INTO mailbox (name, heartbeat_seconds, heartbeat_created)
+            VALUES (?1, ?2, CASE WHEN ?2 IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END)
+            ON CONFLICT (name) DO UPDATE SET
+                heartbeat_seconds = excluded.heartbeat_seconds,
+                heartbeat_created = CASE WHEN excluded.heartbeat_seconds IS NULL THEN NULL
+                    ELSE COALESCE(mailbox.heartbeat_created, excluded.heartbeat_created) END",
+        )
+        .bind(mailbox.as_ref())
+        .bind(interval_seconds)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to set heartbeat")?;
+        query(DELETE_EMPTY_MAILBOX)
+            .bind(mailbox.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to set heartbeat")?;
+        let heartbeat =
+            sqlx::query_as::<_, Heartbeat>(&format!("{SELECT_HEARTBEATS} AND name = ?"))
+                .bind(mailbox.as_ref())
+                .fetch_optional(&mut *transaction)
+                .await
+                .context("Failed to set heartbeat")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to set heartbeat")?;
+        Ok(heartbeat)
+    }
+
+    async fn load_heartbeats(&self) -> Result<Vec<Heartbeat>> {
+        sqlx::query_as::<_, Heartbeat>(&format!("{SELECT_HEARTBEATS} ORDER BY name"))
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load heartbeats")
+    }
+
+    async fn load_mailbox_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        query("SELECT * FROM mailbox")
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load mailbox configs")?
+            .iter()
+            .map(mailbox_config_from_row)
+            .collect()
+    }
+
+    async fn load_ingestion_configs(&self) -> Result<BTreeMap<Mailbox, MailboxConfig>> {
+        self.load_mailbox_configs().await
+    }
+
+    async fn set_mailbox_config(
+        &self,
+        mailbox: Mailbox,
+        config: MailboxConfig,
+    ) -> Result<MailboxConfig> {
+        let (_guard, mut transaction) = self.begin_write().await?;
+        // Changing the interval of an existing heartbeat keeps its creation time
+        query(
+            "INSERT INTO mailbox (
+                name, description, color, default_state, quota, heartbeat_seconds,
+                heartbeat_created, pinned_order
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, CASE WHEN ?6 IS NULL THEN NULL ELSE CURRENT_TIMESTAMP END, ?7)
+            ON CONFLICT (name) DO UPDATE SET
+                description = excluded.description,
+                color = excluded.color,
+                default_state = excluded.default_state,
+                quota = excluded.quota,
+                heartbeat_seconds = excluded.heartbeat_seconds,
+                heartbeat_created = CASE WHEN excluded.heartbeat_seconds IS NULL THEN NULL
+                    ELSE COALESCE(mailbox.heartbeat_created, excluded.heartbeat_created) END,
+                pinned_order = excluded.pinned_order",
+        )
+        .bind(mailbox.as_ref())
+        .bind(config.description.as_deref())
+        .bind(config.color.as_deref())
+        .bind(config.default_state.map(u32::from))
+        .bind(config.quota)
+        .bind(config.heartbeat_seconds)
+        .bind(config.pinned_order)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to set mailbox config")?;
+        query(DELETE_EMPTY_MAILBOX)
+            .bind(mailbox.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to set mailbox config")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to set mailbox config")?;
+        Ok(config)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::filter::Sort;
+    use crate::memory_backend::MemoryBackend;
+    use crate::message::Kind;
 
     // Helper for creating a NewMessage from its parts
     fn make_message(
@@ -251,6 +2182,12 @@ mod tests {
             mailbox: mailbox.try_into()?,
             content: content.to_owned(),
             state: state.into(),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
         })
     }
 
@@ -321,6 +2258,33 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_add_with_timestamp() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let timestamp = NaiveDateTime::parse_from_str("2023-01-01 12:01:02", "%Y-%m-%d %H:%M:%S")?;
+        let messages = backend
+            .add_messages(vec![
+                NewMessage {
+                    timestamp: Some(timestamp),
+                    ..make_message("mailbox", "old", None)?
+                },
+                make_message("mailbox", "new", None)?,
+            ])
+            .await?;
+        assert_eq!(messages[0].timestamp, timestamp);
+        assert!(messages[1].timestamp > timestamp);
+
+        let messages = backend.load_messages(Filter::new()).await?;
+        assert_eq!(
+            messages
+                .iter()
+                .find(|message| message.content == "old")
+                .map(|message| message.timestamp),
+            Some(timestamp)
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_add_invalid() -> Result<()> {
         let backend = SqliteBackend::new_test().await?;
@@ -331,6 +2295,39 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_concurrent_writers() -> Result<()> {
+        async fn add_many(backend: &SqliteBackend) -> Result<()> {
+            for _ in 0..20 {
+                backend
+                    .add_messages(vec![make_message("mailbox", "message", None)?])
+                    .await?;
+            }
+            Ok(())
+        }
+
+        // Simulate two processes writing to the same database at the same time
+        let db_path = std::env::temp_dir()
+            .join("mailbox")
+            .join(format!("mailbox-concurrent-{}.db", std::process::id()));
+        let backend1 = SqliteBackend::new(db_path.clone()).await?;
+        let backend2 = SqliteBackend::new(db_path).await?;
+        let (result1, result2) = tokio::join!(add_many(&backend1), add_many(&backend2));
+        result1?;
+        result2?;
+        assert_eq!(
+            backend1
+                .load_messages(Filter::new().with_mailbox("mailbox".try_into()?))
+                .await?
+                .len(),
+            40
+        );
+        backend1
+            .delete_messages(Filter::new().with_mailbox("mailbox".try_into()?))
+            .await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_load() -> Result<()> {
         let backend = get_populated_backend().await?;
@@ -338,6 +2335,67 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_load_sorted() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let get_contents = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>()
+        };
+
+        // Messages in a batch are added in reverse order, so the last message has the lowest id
+        let messages = backend
+            .load_messages(Filter::new().with_reverse(true))
+            .await?;
+        assert_eq!(
+            get_contents(messages),
+            vec!["archive1", "read3", "read2", "read1", "unread2", "unread1"]
+        );
+
+        let messages = backend
+            .load_messages(Filter::new().with_sort(Sort::Mailbox))
+            .await?;
+        assert_eq!(
+            get_contents(messages),
+            vec!["archive1", "read1", "read2", "read3", "unread1", "unread2"]
+        );
+
+        let id = backend
+            .load_messages(Filter::new().with_mailbox("read".try_into()?))
+            .await?[2]
+            .id;
+        backend.record_view(id).await?;
+        let messages = backend
+            .load_messages(Filter::new().with_sort(Sort::LastViewed))
+            .await?;
+        assert_eq!(messages[0].content, "read3");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_page() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let get_contents = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>()
+        };
+
+        let filter = Filter::new().with_sort(Sort::Mailbox);
+        let messages = backend.load_messages(filter.clone().with_limit(2)).await?;
+        assert_eq!(get_contents(messages), vec!["archive1", "read1"]);
+        let messages = backend
+            .load_messages(filter.clone().with_offset(2).with_limit(3))
+            .await?;
+        assert_eq!(get_contents(messages), vec!["read2", "read3", "unread1"]);
+        let messages = backend.load_messages(filter.with_offset(5)).await?;
+        assert_eq!(get_contents(messages), vec!["unread2"]);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_load_with_mailbox_filter() -> Result<()> {
         let backend = get_populated_backend().await?;
@@ -402,71 +2460,799 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_read() -> Result<()> {
-        let backend = get_populated_backend().await?;
+    async fn test_load_with_mailbox_filter_exact_match() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
         backend
-            .change_state(Filter::new().with_states(vec![State::Unread]), State::Read)
+            .add_messages(vec![
+                make_message("a_b/c", "message", None)?,
+                make_message("axb/c", "message", None)?,
+                make_message("A_B/c", "message", None)?,
+            ])
             .await?;
+        // Mailbox names are matched exactly instead of as LIKE patterns, apart from case
         assert_eq!(
             backend
-                .load_messages(Filter::new().with_states(vec![State::Read]))
+                .load_messages(Filter::new().with_mailbox("a_b".try_into()?))
                 .await?
                 .len(),
-            5
+            2
         );
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_archive() -> Result<()> {
-        let backend = get_populated_backend().await?;
+    async fn test_load_with_mailbox_filter_ignores_case() -> Result<()> {
+        let messages = vec![
+            make_message("CI", "message", None)?,
+            make_message("ci/Build", "message", None)?,
+            make_message("Ci/build/linux", "message", None)?,
+            make_message("cid", "message", None)?,
+            make_message("CI0", "message", None)?,
+        ];
+        let sqlite = SqliteBackend::new_test().await?;
+        sqlite.add_messages(messages.clone()).await?;
+        let memory = MemoryBackend::new();
+        memory.add_messages(messages).await?;
+
+        // Both backends ignore case when matching mailboxes and their descendants
+        for (mailbox, expected) in [("ci", 3), ("CI/BUILD", 2), ("ci/build/LINUX", 1), ("c", 0)] {
+            let filter = Filter::new().with_mailbox(mailbox.try_into()?);
+            assert_eq!(
+                sqlite.load_messages(filter.clone()).await?.len(),
+                expected,
+                "{mailbox}"
+            );
+            assert_eq!(
+                memory.load_messages(filter).await?.len(),
+                expected,
+                "{mailbox}"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_with_mailbox_patterns_filter() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        backend
+            .add_messages(vec![
+                make_message("ci", "message", None)?,
+                make_message("ci/build", "message", None)?,
+                make_message("ci/build/linux", "message", None)?,
+                make_message("ci?", "message", None)?,
+                make_message("cid", "message", None)?,
+            ])
+            .await?;
+        let get_mailboxes = |messages: Vec<Message>| {
+            let mut mailboxes = messages
+                .into_iter()
+                .map(|message| message.mailbox.to_string())
+                .collect::<Vec<_>>();
+            mailboxes.sort();
+            mailboxes
+        };
+        let filter = Filter::new().with_mailbox_patterns(vec![String::from("ci/*")]);
         assert_eq!(
-            backend
-                .change_state(
-                    Filter::new().with_states(vec![State::Unread, State::Read]),
-                    State::Archived,
-                )
-                .await?
-                .len(),
-            5
+            get_mailboxes(backend.load_messages(filter.clone()).await?),
+            vec!["ci/build", "ci/build/linux"]
         );
+        assert_eq!(backend.load_mailboxes(filter).await?.len(), 2);
+        // Only "*" is a wildcard
+        let filter = Filter::new().with_mailbox_patterns(vec![String::from("ci?")]);
         assert_eq!(
-            backend
-                .load_messages(Filter::new().with_states(vec![State::Archived]))
-                .await?
-                .len(),
-            6
+            get_mailboxes(backend.load_messages(filter).await?),
+            vec!["ci?"]
         );
+        let filter = Filter::new().with_mailbox_patterns(vec![]);
+        assert!(backend.load_messages(filter).await?.is_empty());
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_delete() -> Result<()> {
-        let backend = get_populated_backend().await?;
-        assert_eq!(
-            backend
+    async fn test_uses_indexes() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        // Give the query planner statistics about a realistic database because it doesn't use
+        // indexes for tiny tables
+        let messages = (0..100)
+            .map(|index| make_message(&format!("mailbox{}", index % 10), "message", None))
+            .collect::<Result<Vec<_>>>()?;
+        backend.add_messages(messages).await?;
+        query("ANALYZE").execute(&backend.pool).await?;
+        let (sql, values) = Query::select()
+            .column((MessageIden::Table, Asterisk))
+            .from(MessageIden::Table)
+            .cond_where(
+                Filter::new()
+                    .with_mailbox("mailbox1".try_into()?)
+                    .get_where(),
+            )
+            .build_sqlx(SqliteQueryBuilder);
+        let plan = sqlx::query_with(&format!("EXPLAIN QUERY PLAN {sql}"), values)
+            .fetch_all(&backend.pool)
+            .await?
+            .iter()
+            .map(|row| row.try_get::<String, _>("detail"))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(plan.iter().any(|detail| detail.contains("message_mailbox")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        backend
+            .change_state(Filter::new().with_states(vec![State::Unread]), State::Read)
+            .await?;
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_states(vec![State::Read]))
+                .await?
+                .len(),
+            5
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
+                .change_state(
+                    Filter::new().with_states(vec![State::Unread, State::Read]),
+                    State::Archived,
+                )
+                .await?
+                .len(),
+            5
+        );
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_states(vec![State::Archived]))
+                .await?
+                .len(),
+            6
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_custom_states() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let in_progress = String::from("in-progress");
+        let changed = backend
+            .change_custom_state(
+                Filter::new().with_mailbox("read".try_into()?),
+                in_progress.clone(),
+                State::Read,
+            )
+            .await?;
+        assert_eq!(changed.len(), 3);
+        assert!(changed
+            .iter()
+            .all(|message| message.custom_state.as_ref() == Some(&in_progress)));
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_custom_states(vec![in_progress.clone()]))
+                .await?
+                .len(),
+            3
+        );
+        assert_eq!(
+            backend
+                .load_mailboxes(
+                    Filter::new()
+                        .with_states(vec![State::Read])
+                        .without_custom_states(vec![in_progress.clone()])
+                )
+                .await?
+                .len(),
+            0
+        );
+
+        // Changing to a built-in state leaves the custom state
+        let changed = backend
+            .change_state(Filter::new().with_mailbox("read".try_into()?), State::Read)
+            .await?;
+        assert!(changed.iter().all(|message| message.custom_state.is_none()));
+
+        assert!(backend
+            .change_custom_state(
+                Filter::new().with_consumer(String::from("laptop")),
+                in_progress,
+                State::Read,
+            )
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend
                 .delete_messages(Filter::new().with_states(vec![State::Unread, State::Read]))
                 .await?
                 .len(),
             5
         );
-        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive_messages() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let log = Attachment::Reference {
+            location: String::from("/var/log/build.log"),
+        };
+        backend
+            .add_messages(vec![
+                NewMessage {
+                    attachments: vec![log.clone()],
+                    ..make_message("ci", "Build failed", None)?
+                },
+                make_message("ci", "Build passed", None)?,
+                make_message("other", "Other", None)?,
+            ])
+            .await?;
+        let filter = Filter::new().with_mailbox("ci".try_into()?);
+
+        // Nothing is deleted when the archiver fails
+        let result = backend
+            .archive_messages(filter.clone(), Box::new(|_| bail!("disk full")))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 3);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let archived = backend
+            .archive_messages(
+                filter,
+                Box::new(move |messages| {
+                    sender.send(messages.to_vec())?;
+                    Ok(())
+                }),
+            )
+            .await?;
+        assert_eq!(archived.len(), 2);
+        let written = receiver.recv()?;
+        assert_eq!(
+            written
+                .iter()
+                .map(|archived| (
+                    archived.message.content.as_str(),
+                    archived.attachments.len()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("Build failed", 1), ("Build passed", 0)]
+        );
+        assert_eq!(written[0].attachments, vec![log]);
+        let remaining = backend.load_messages(Filter::new()).await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].content, "Other");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_consumer_states() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let laptop = Filter::new().with_consumer(String::from("laptop"));
+        let changed = backend
+            .change_state(
+                laptop.clone().with_mailbox("unread".try_into()?),
+                State::Read,
+            )
+            .await?;
+        assert_eq!(changed.len(), 2);
+        assert!(changed.iter().all(|message| message.state == State::Read));
+
+        // Only the consumer's own states changed
+        let unread = Filter::new().with_states(vec![State::Unread]);
+        assert_eq!(backend.load_messages(unread.clone()).await?.len(), 2);
+        assert_eq!(
+            backend
+                .load_messages(unread.clone().with_consumer(String::from("desktop")))
+                .await?
+                .len(),
+            2
+        );
+        assert!(backend
+            .load_messages(unread.clone().with_consumer(String::from("laptop")))
+            .await?
+            .is_empty());
+        assert!(backend
+            .load_mailboxes(unread.with_consumer(String::from("laptop")))
+            .await?
+            .is_empty());
+
+        let deleted = backend
+            .delete_messages(laptop.with_states(vec![State::Read]))
+            .await?;
+        assert_eq!(deleted.len(), 5);
+        assert!(deleted.iter().all(|message| message.state == State::Read));
+        assert_eq!(backend.load_messages(Filter::new()).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_claim() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let filter = Filter::new().with_limit(1);
+        let lease = Duration::from_mins(1);
+        let claim = |worker: &str| backend.claim_messages(filter.clone(), worker.to_owned(), lease);
+        let claimed = claim("worker1").await?;
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].content, "unread2");
+        assert_eq!(claimed[0].state, State::Read);
+        assert_eq!(claim("worker2").await?[0].content, "unread1");
+        assert!(claim("worker3").await?.is_empty());
+
+        // Messages with expired leases can be claimed again
+        let get_ids = |messages: Vec<Message>| {
+            messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        };
+        let backend = get_populated_backend().await?;
+        let expired = backend
+            .claim_messages(Filter::new(), String::from("worker1"), Duration::ZERO)
+            .await?;
+        assert_eq!(expired.len(), 2);
+        let reclaimed = backend
+            .claim_messages(Filter::new(), String::from("worker2"), lease)
+            .await?;
+        assert_eq!(get_ids(reclaimed), get_ids(expired));
+
+        // Urgent messages are claimed first
+        let with_priority = |content: &str, priority: i32| -> Result<NewMessage> {
+            Ok(NewMessage {
+                priority: Some(priority),
+                ..make_message("jobs", content, None)?
+            })
+        };
+        backend
+            .add_messages(vec![
+                with_priority("low", 0)?,
+                with_priority("high", 5)?,
+                with_priority("medium", 1)?,
+                with_priority("high2", 5)?,
+            ])
+            .await?;
+        let claimed = backend
+            .claim_messages(
+                Filter::new().with_min_priority(1).with_limit(3),
+                String::from("worker1"),
+                lease,
+            )
+            .await?;
+        assert_eq!(
+            claimed
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["high2", "high", "medium"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_message_kinds() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("links", "https://example.com", None)?,
+                NewMessage {
+                    kind: Some(Kind::Text),
+                    ..make_message("links", "https://example.com", None)?
+                },
+            ])
+            .await?;
+        assert_eq!(messages[0].kind, Kind::Url);
+        assert_eq!(messages[1].kind, Kind::Text);
+
+        let loaded = backend
+            .load_messages(Filter::new().with_consumer(String::from("reader")))
+            .await?;
+        assert_eq!(
+            loaded
+                .iter()
+                .map(|message| message.kind)
+                .collect::<Vec<_>>(),
+            vec![Kind::Url, Kind::Text]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nack() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let dead_letter = DeadLetterPolicy {
+            mailbox: "failed".try_into()?,
+            max_attempts: 2,
+        };
+        let filter = Filter::new().with_mailbox("jobs".try_into()?);
+        let lease = Duration::from_mins(1);
+        let message = backend
+            .add_messages(vec![make_message("jobs", "job", None)?])
+            .await?
+            .remove(0);
+        let nack =
+            || backend.nack_message(message.id, String::from("timeout"), dead_letter.clone());
+
+        // Unclaimed and missing messages can't be rejected
+        assert!(nack().await.is_err());
+        assert!(backend
+            .nack_message(100, String::from("timeout"), dead_letter.clone())
+            .await?
+            .is_none());
+
+        // Rejected messages return to the queue until they run out of attempts
+        backend
+            .claim_messages(filter.clone(), String::from("worker"), lease)
+            .await?;
+        let rejected = nack().await?.unwrap();
+        assert_eq!(rejected.state, State::Unread);
+        assert_eq!(rejected.mailbox.as_ref(), "jobs");
+        assert!(nack().await.is_err());
+
+        backend
+            .claim_messages(filter.clone(), String::from("worker"), lease)
+            .await?;
+        let rejected = nack().await?.unwrap();
+        assert_eq!(rejected.state, State::Unread);
+        assert_eq!(rejected.mailbox.as_ref(), "failed");
+        assert_eq!(rejected.content, "job\n\nFailed 2 times in jobs: timeout");
+        assert!(backend
+            .claim_messages(filter, String::from("worker"), lease)
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let mailbox = Mailbox::try_from("cron/backup")?;
+        let heartbeat = backend
+            .set_heartbeat(mailbox.clone(), Some(Duration::from_hours(1)))
+            .await?
+            .unwrap();
+        assert_eq!(heartbeat.interval_seconds, 3600);
+        assert_eq!(heartbeat.last_message, None);
+
+        // Messages in descendants don't count
+        backend
+            .add_messages(vec![make_message("cron/backup/nas", "ok", None)?])
+            .await?;
+        let added = backend
+            .add_messages(vec![make_message("cron/backup", "ok", None)?])
+            .await?;
+
+        // Changing the interval keeps the creation time
+        let updated = backend
+            .set_heartbeat(mailbox.clone(), Some(Duration::from_hours(24)))
+            .await?
+            .unwrap();
+        assert_eq!(
+            updated,
+            Heartbeat {
+                interval_seconds: 86_400,
+                last_message: Some(added[0].timestamp),
+                ..heartbeat
+            }
+        );
+        assert_eq!(backend.load_heartbeats().await?, vec![updated]);
+
+        assert_eq!(backend.set_heartbeat(mailbox, None).await?, None);
+        assert!(backend.load_heartbeats().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mailbox_configs() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let mailbox = Mailbox::try_from("cron/backup")?;
+        let config = MailboxConfig {
+            description: Some(String::from("Nightly backups")),
+            default_state: Some(State::Read),
+            heartbeat_seconds: Some(3600),
+            ..Default::default()
+        };
+        assert_eq!(
+            backend
+                .set_mailbox_config(mailbox.clone(), config.clone())
+                .await?,
+            config
+        );
+        assert_eq!(
+            backend.load_mailbox_configs().await?,
+            BTreeMap::from([(mailbox.clone(), config.clone())])
+        );
+        let heartbeats = backend.load_heartbeats().await?;
+        assert_eq!(heartbeats.len(), 1);
+        assert_eq!(heartbeats[0].interval_seconds, 3600);
+
+        // Removing the heartbeat keeps the rest of the metadata
+        backend.set_heartbeat(mailbox.clone(), None).await?;
+        assert_eq!(
+            backend.load_mailbox_configs().await?,
+            BTreeMap::from([(
+                mailbox.clone(),
+                MailboxConfig {
+                    heartbeat_seconds: None,
+                    ..config
+                }
+            )])
+        );
+        assert!(backend.load_heartbeats().await?.is_empty());
+
+        // Empty metadata isn't stored
+        backend
+            .set_mailbox_config(mailbox, MailboxConfig::default())
+            .await?;
+        assert!(backend.load_mailbox_configs().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_link() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("alerts", "fixed", None)?,
+                make_message("alerts", "down", None)?,
+            ])
+            .await?;
+        let (fixed, down) = (messages[0].id, messages[1].id);
+        assert!(backend.link_messages(fixed, 100).await?.is_empty());
+
+        // Links are visible from both messages and aren't duplicated
+        backend.link_messages(fixed, down).await?;
+        let linked = backend.link_messages(down, fixed).await?;
+        assert_eq!(
+            linked
+                .iter()
+                .map(|message| (message.id, message.related_ids.clone()))
+                .collect::<Vec<_>>(),
+            vec![(fixed, vec![down]), (down, vec![fixed])]
+        );
+
+        // Deleting a message removes its links
+        backend
+            .delete_messages(Filter::new().with_ids(vec![down]))
+            .await?;
+        let messages = backend
+            .load_messages(Filter::new().with_ids(vec![fixed]))
+            .await?;
+        assert!(messages[0].related_ids.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remind() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("todo", "later", None)?,
+                make_message("todo", "done", State::Archived)?,
+            ])
+            .await?;
+        let (later, done) = (messages[0].id, messages[1].id);
+        assert!(backend.remind_message(100, Duration::ZERO).await?.is_none());
+
+        // Unread messages are read until the reminder is due
+        let reminded = backend
+            .remind_message(later, Duration::from_hours(1))
+            .await?
+            .unwrap();
+        assert_eq!(reminded.state, State::Read);
+        assert!(backend.wake_reminders().await?.is_empty());
+
+        // Due reminders return messages in any state to unread once
+        let reminded = backend.remind_message(done, Duration::ZERO).await?.unwrap();
+        assert_eq!(reminded.state, State::Archived);
+        backend.remind_message(later, Duration::ZERO).await?;
+        let woken = backend.wake_reminders().await?;
+        assert_eq!(
+            woken
+                .iter()
+                .map(|message| (message.content.as_str(), message.state))
+                .collect::<Vec<_>>(),
+            vec![("later", State::Unread), ("done", State::Unread)]
+        );
+        assert!(backend.wake_reminders().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_health() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let version = backend.load_schema_version().await?;
+        assert_eq!(version.current, version.latest);
+        // Tests disable write-ahead logging
+        let wal = backend.check_wal().await?;
+        assert_eq!(wal.journal_mode, "delete");
+        assert!(!wal.busy);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_changes() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let changes = backend.load_changes(0).await?;
+        assert_eq!(changes.messages.len(), 6);
+        assert!(changes.deleted.is_empty());
+
+        let since = changes.seq;
+        backend
+            .change_state(Filter::new().with_ids(vec![1]), State::Read)
+            .await?;
+        backend
+            .delete_messages(Filter::new().with_ids(vec![2]))
+            .await?;
+        let changes = backend.load_changes(since).await?;
+        assert_eq!(
+            changes
+                .messages
+                .iter()
+                .map(|message| (message.id, message.state))
+                .collect::<Vec<_>>(),
+            vec![(1, State::Read)]
+        );
+        assert_eq!(changes.deleted, vec![2]);
+        assert_eq!(changes.seq, since + 2);
+
+        assert!(backend.load_changes(changes.seq).await?.messages.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_mailboxes() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        assert_eq!(
+            backend.load_mailboxes(Filter::new()).await?,
+            vec![
+                MailboxInfo {
+                    name: "archived".try_into()?,
+                    message_count: 1
+                },
+                MailboxInfo {
+                    name: "read".try_into()?,
+                    message_count: 3
+                },
+                MailboxInfo {
+                    name: "unread".try_into()?,
+                    message_count: 2
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_mailboxes_hierarchy_counts() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let messages = backend
+            .add_messages(vec![
+                make_message("a", "message1", State::Unread)?,
+                make_message("a/b", "message2", State::Unread)?,
+                make_message("a/b/c", "message3", State::Read)?,
+                make_message("a-b", "message4", State::Unread)?,
+                make_message("d/e", "message5", State::Unread)?,
+            ])
+            .await?;
+        let mailbox_info = |name: &str, message_count| -> Result<MailboxInfo> {
+            Ok(MailboxInfo {
+                name: name.try_into()?,
+                message_count,
+            })
+        };
+
+        let filter = Filter::new().with_hierarchy_counts(true);
+        assert_eq!(
+            backend.load_mailboxes(filter.clone()).await?,
+            vec![
+                mailbox_info("a", 3)?,
+                mailbox_info("a-b", 1)?,
+                mailbox_info("a/b", 2)?,
+                mailbox_info("a/b/c", 1)?,
+                mailbox_info("d", 1)?,
+                mailbox_info("d/e", 1)?,
+            ]
+        );
+        assert_eq!(
+            backend
+                .load_mailboxes(filter.clone().with_states(vec![State::Unread]))
+                .await?,
+            vec![
+                mailbox_info("a", 2)?,
+                mailbox_info("a-b", 1)?,
+                mailbox_info("a/b", 1)?,
+                mailbox_info("d", 1)?,
+                mailbox_info("d/e", 1)?,
+            ]
+        );
+        // Counting the messages directly also supports hierarchy counts
+        assert_eq!(
+            backend
+                .load_mailboxes(
+                    filter.with_ids(
+                        messages
+                            .iter()
+                            .filter(|message| message.mailbox.as_ref().starts_with("a/"))
+                            .map(|message| message.id)
+                            .collect()
+                    )
+                )
+                .await?,
+            vec![
+                mailbox_info("a", 2)?,
+                mailbox_info("a/b", 2)?,
+                mailbox_info("a/b/c", 1)?,
+            ]
+        );
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_load_mailboxes() -> Result<()> {
+    async fn test_migrate() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let version = query("PRAGMA user_version")
+            .fetch_one(&backend.pool)
+            .await?
+            .try_get::<i64, _>(0)?;
+        assert_eq!(version, i64::try_from(get_migrations().len())?);
+
+        // Migrating an up-to-date database does nothing
+        backend.init().await?;
+
+        // Databases created by newer versions can't be migrated
+        query(&format!("PRAGMA user_version = {}", version + 1))
+            .execute(&backend.pool)
+            .await?;
+        let err = backend.init().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("The database schema version is {}, but this version of mailbox only supports schema versions up to {version}. Try upgrading mailbox.", version + 1)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_mailboxes_after_changes() -> Result<()> {
         let backend = get_populated_backend().await?;
+        backend
+            .change_state(Filter::new().with_states(vec![State::Unread]), State::Read)
+            .await?;
+        backend
+            .delete_messages(Filter::new().with_states(vec![State::Archived]))
+            .await?;
         assert_eq!(
-            backend.load_mailboxes(Filter::new()).await?,
+            backend
+                .load_mailboxes(Filter::new().with_states(vec![State::Read]))
+                .await?,
             vec![
                 MailboxInfo {
-                    name: "archived".try_into()?,
-                    message_count: 1
+                    name: "read".try_into()?,
+                    message_count: 3
+                },
+                MailboxInfo {
+                    name: "unread".try_into()?,
+                    message_count: 2
                 },
+            ]
+        );
+        assert_eq!(
+            backend
+                .load_mailboxes(Filter::new().with_ids(vec![4, 5, 6]))
+                .await?,
+            vec![
                 MailboxInfo {
                     name: "read".try_into()?,
-                    message_count: 3
+                    message_count: 1
                 },
                 MailboxInfo {
                     name: "unread".try_into()?,
@@ -477,6 +3263,219 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_tags() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let tagged = |mailbox: &str, content: &str, tags: &[&str]| -> Result<NewMessage> {
+            Ok(NewMessage {
+                tags: tags
+                    .iter()
+                    .map(|tag| Tag::try_from(*tag))
+                    .collect::<Result<_>>()?,
+                ..make_message(mailbox, content, None)?
+            })
+        };
+        let added = backend
+            .add_messages(vec![
+                tagged("ci/project-a", "build failed", &["urgent", "ci", "urgent"])?,
+                tagged("backups", "disk full", &["urgent"])?,
+                tagged("backups", "backup done", &[])?,
+            ])
+            .await?;
+        let get_tags = |messages: &[Message]| {
+            messages
+                .iter()
+                .map(|message| {
+                    message
+                        .tags
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            get_tags(&added),
+            vec![vec!["ci", "urgent"], vec!["urgent"], vec![]]
+        );
+
+        let urgent = Filter::new().with_tags(vec!["urgent".try_into()?]);
+        let messages = backend.load_messages(urgent.clone()).await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["build failed", "disk full"]
+        );
+        assert_eq!(
+            get_tags(&messages),
+            vec![vec!["ci", "urgent"], vec!["urgent"]]
+        );
+        assert_eq!(
+            backend.load_mailboxes(urgent.clone()).await?,
+            vec![
+                MailboxInfo {
+                    name: "backups".try_into()?,
+                    message_count: 1
+                },
+                MailboxInfo {
+                    name: "ci/project-a".try_into()?,
+                    message_count: 1
+                },
+            ]
+        );
+
+        let changed = backend.change_state(urgent.clone(), State::Read).await?;
+        assert_eq!(changed.len(), 2);
+        assert!(changed.iter().all(|message| !message.tags.is_empty()));
+
+        let deleted = backend
+            .delete_messages(Filter::new().with_tags(vec!["ci".try_into()?]))
+            .await?;
+        assert_eq!(get_tags(&deleted), vec![vec!["ci", "urgent"]]);
+        assert_eq!(backend.load_messages(urgent).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upsert() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let with_external_id =
+            |mailbox: &str, content: &str, external_id: &str| -> Result<NewMessage> {
+                Ok(NewMessage {
+                    external_id: Some(external_id.to_owned()),
+                    ..make_message(mailbox, content, None)?
+                })
+            };
+        let added = backend
+            .add_messages(vec![
+                with_external_id("job", "Running", "run-1")?,
+                with_external_id("other", "Running", "run-1")?,
+            ])
+            .await?;
+
+        // Adding a duplicate external id to the same mailbox fails
+        assert!(backend
+            .add_messages(vec![with_external_id("job", "Done", "run-1")?])
+            .await
+            .is_err());
+
+        backend
+            .change_state(Filter::new().with_ids(vec![added[0].id]), State::Read)
+            .await?;
+        let since = backend.load_changes(0).await?.seq;
+        let upserted = backend
+            .upsert_messages(vec![
+                NewMessage {
+                    tags: vec![Tag::try_from("done")?],
+                    ..with_external_id("job", "Done", "run-1")?
+                },
+                with_external_id("job", "Queued", "run-2")?,
+            ])
+            .await?;
+        assert_eq!(upserted[0].id, added[0].id);
+        assert_eq!(upserted[0].content, "Done");
+        assert_eq!(upserted[0].state, State::Unread);
+        assert_eq!(upserted[0].tags, vec![Tag::try_from("done")?]);
+        assert!(upserted[1].id > added[0].id.max(added[1].id));
+
+        let messages = backend
+            .load_messages(Filter::new().with_mailbox("job".try_into()?))
+            .await?;
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.content.as_str(), message.external_id.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("Queued", Some("run-2")), ("Done", Some("run-1"))]
+        );
+        let messages = backend
+            .load_messages(Filter::new().with_mailbox("other".try_into()?))
+            .await?;
+        assert_eq!(messages[0].content, "Running");
+
+        // Updated messages are reported as changes
+        let mut changed = backend
+            .load_changes(since)
+            .await?
+            .messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        changed.sort_unstable();
+        assert_eq!(changed, vec![added[0].id, upserted[1].id]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_with_since_filter() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let now = chrono::Utc::now().naive_utc();
+        let past = now - chrono::Duration::days(1);
+        let future = now + chrono::Duration::days(1);
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_since(past))
+                .await?
+                .len(),
+            6
+        );
+        assert!(backend
+            .load_messages(Filter::new().with_since(future))
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_with_before_filter() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let now = chrono::Utc::now().naive_utc();
+        let past = now - chrono::Duration::days(1);
+        let future = now + chrono::Duration::days(1);
+        assert!(backend
+            .load_messages(Filter::new().with_before(past))
+            .await?
+            .is_empty());
+        assert_eq!(
+            backend
+                .load_messages(Filter::new().with_before(future))
+                .await?
+                .len(),
+            6
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_stats() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let stats = backend
+            .load_stats(
+                Filter::new().with_states(vec![State::Read, State::Archived]),
+                Bucket::Week,
+            )
+            .await?;
+        assert_eq!(
+            stats
+                .iter()
+                .map(|stats| (
+                    stats.mailbox.as_ref(),
+                    stats.added,
+                    stats.read,
+                    stats.archived
+                ))
+                .collect::<Vec<_>>(),
+            vec![("archived", 1, 0, 1), ("read", 3, 3, 0)]
+        );
+        let now = chrono::Utc::now().naive_utc();
+        assert!(stats
+            .iter()
+            .all(|stats| stats.bucket == Bucket::Week.truncate(now)));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_load_mailboxes_with_filter() -> Result<()> {
         let backend = get_populated_backend().await?;
@@ -491,4 +3490,263 @@ mod tests {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_admin_stats() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let stats = backend.load_admin_stats().await?;
+        assert_eq!(stats.message_count, 6);
+        assert_eq!(stats.mailbox_count, 3);
+        assert_eq!(stats.token_count, 0);
+        assert!(stats.database_bytes > 0);
+
+        let compaction = backend.compact().await?;
+        assert!(compaction.after_bytes <= compaction.before_bytes);
+        assert_eq!(backend.load_admin_stats().await?.free_bytes, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_read_only() -> Result<()> {
+        let db_path = std::env::temp_dir()
+            .join("mailbox")
+            .join(format!("mailbox-query-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let backend = SqliteBackend::new(db_path.clone()).await?;
+        backend
+            .add_messages(vec![
+                make_message("a", "one", State::Unread)?,
+                make_message("a", "two", State::Read)?,
+            ])
+            .await?;
+
+        let result = SqliteBackend::query_read_only(
+            &db_path,
+            "SELECT mailbox, COUNT(*) AS count, NULL AS empty FROM message GROUP BY mailbox",
+        )
+        .await;
+        let no_rows =
+            SqliteBackend::query_read_only(&db_path, "SELECT id, content FROM message WHERE 0")
+                .await;
+        let delete = SqliteBackend::query_read_only(&db_path, "DELETE FROM message").await;
+        let smuggled_delete =
+            SqliteBackend::query_read_only(&db_path, "SELECT 1; DELETE FROM message").await;
+        let remaining = backend.load_messages(Filter::new()).await?.len();
+        backend.close().await?;
+        std::fs::remove_file(&db_path)?;
+
+        assert_eq!(
+            result?,
+            QueryResult {
+                columns: vec![
+                    String::from("mailbox"),
+                    String::from("count"),
+                    String::from("empty")
+                ],
+                rows: vec![vec![
+                    serde_json::json!("a"),
+                    serde_json::json!(2),
+                    serde_json::Value::Null
+                ]],
+            }
+        );
+        assert_eq!(
+            no_rows?,
+            QueryResult {
+                columns: vec![String::from("id"), String::from("content")],
+                rows: vec![],
+            }
+        );
+        assert!(delete.is_err());
+        assert!(smuggled_delete.is_err());
+        assert_eq!(remaining, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_info() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        assert_eq!(backend.load_info().await?.oldest, None);
+
+        let timestamp = NaiveDateTime::parse_from_str("2023-01-01 12:01:02", "%Y-%m-%d %H:%M:%S")?;
+        backend
+            .add_messages(vec![
+                NewMessage {
+                    timestamp: Some(timestamp),
+                    ..make_message("a", "old", State::Archived)?
+                },
+                make_message("a", "one", State::Unread)?,
+                make_message("b", "two", State::Unread)?,
+            ])
+            .await?;
+        let info = backend.load_info().await?;
+        assert_eq!(info.schema_version, backend.load_schema_version().await?);
+        assert_eq!(
+            (info.unread_count, info.read_count, info.archived_count),
+            (2, 0, 1)
+        );
+        assert_eq!(info.oldest, Some(timestamp));
+        assert!(info.newest > Some(timestamp));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backup() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let dest = std::env::temp_dir().join(format!("mailbox-backup-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&dest);
+        let size = backend.backup(&dest).await?;
+        assert_eq!(size, std::fs::metadata(&dest)?.len());
+
+        // The backup contains the same messages and an existing backup isn't overwritten
+        let backup = SqliteBackend::new(dest.clone()).await?;
+        let get_contents = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            get_contents(backup.load_messages(Filter::new()).await?),
+            get_contents(backend.load_messages(Filter::new()).await?)
+        );
+        backup.close().await?;
+        assert!(backend.backup(&dest).await.is_err());
+        std::fs::remove_file(&dest)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let acl = MailboxAcl::default();
+        let token = backend.create_api_token("laptop", &acl).await?.unwrap();
+        assert_eq!(token.name, "laptop");
+        assert_eq!(token.token.as_ref().unwrap().len(), 64);
+        assert!(token.acl.is_unrestricted());
+        assert!(backend.create_api_token("laptop", &acl).await?.is_none());
+
+        let acl = MailboxAcl {
+            read: Some(vec![]),
+            write: Some(vec![String::from("ci/*")]),
+        };
+        let other_token = backend.create_api_token("desktop", &acl).await?.unwrap();
+        assert_eq!(other_token.acl, acl);
+        assert_ne!(token.token, other_token.token);
+        assert_eq!(
+            backend
+                .load_api_tokens()
+                .await?
+                .into_iter()
+                .map(|token| token.name)
+                .collect::<Vec<_>>(),
+            vec!["desktop", "laptop"]
+        );
+
+        assert_eq!(backend.revoke_api_token("laptop").await?, Some(token));
+        assert!(backend.revoke_api_token("laptop").await?.is_none());
+        assert_eq!(backend.load_api_tokens().await?, vec![other_token]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        backend
+            .record_audit("laptop", "delete_mailbox", "mailbox=alerts", 3)
+            .await?;
+        backend
+            .record_audit("ci", "create_messages", "ids=4", 1)
+            .await?;
+        let entries = backend.load_audit_log(10).await?;
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.actor.as_str(),
+                    entry.operation.as_str(),
+                    entry.affected
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ci", "create_messages", 1),
+                ("laptop", "delete_mailbox", 3)
+            ]
+        );
+        assert_eq!(backend.load_audit_log(1).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attachments() -> Result<()> {
+        let backend = SqliteBackend::new_test().await?;
+        let report = Attachment::Blob {
+            name: String::from("report.html"),
+            data: vec![0, 1, 2, 255],
+        };
+        let log = Attachment::Reference {
+            location: String::from("/var/log/build.log"),
+        };
+        let added = backend
+            .add_messages(vec![
+                NewMessage {
+                    external_id: Some(String::from("build")),
+                    attachments: vec![report.clone(), log.clone()],
+                    ..make_message("mailbox", "Building", None)?
+                },
+                make_message("mailbox", "Plain", None)?,
+            ])
+            .await?;
+        assert_eq!(
+            backend.load_attachments(added[0].id).await?,
+            vec![report, log.clone()]
+        );
+        assert!(backend.load_attachments(added[1].id).await?.is_empty());
+
+        // Upserting a message replaces its attachments
+        backend
+            .upsert_messages(vec![NewMessage {
+                external_id: Some(String::from("build")),
+                attachments: vec![log.clone()],
+                ..make_message("mailbox", "Built", None)?
+            }])
+            .await?;
+        assert_eq!(backend.load_attachments(added[0].id).await?, vec![log]);
+
+        backend.delete_messages(Filter::new()).await?;
+        assert!(backend.load_attachments(added[0].id).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_view() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        let message = backend.load_messages(Filter::new()).await?.remove(0);
+        assert_eq!(message.view_count, 0);
+        assert!(message.last_viewed.is_none());
+
+        let viewed = backend.record_view(message.id).await?.unwrap();
+        assert_eq!(viewed.view_count, 1);
+        assert!(viewed.last_viewed.is_some());
+        assert_eq!(viewed.content, message.content);
+        assert_eq!(
+            backend.record_view(message.id).await?.unwrap().view_count,
+            2
+        );
+        assert!(backend.record_view(0).await?.is_none());
+
+        // Viewing a message isn't a change that clients need to sync
+        let seq = backend.load_changes(0).await?.seq;
+        backend.record_view(message.id).await?;
+        assert_eq!(backend.load_changes(0).await?.seq, seq);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_close() -> Result<()> {
+        let backend = get_populated_backend().await?;
+        backend.close().await?;
+        assert!(backend.load_messages(Filter::new()).await.is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,98 @@
+use crate::mailbox::Mailbox;
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+// The length of the time periods that message statistics are grouped into
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    // Return the start of the bucket containing the timestamp
+    // Weeks start on Monday
+    #[must_use]
+    pub fn truncate(self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let date = timestamp.date();
+        match self {
+            Self::Hour => timestamp
+                .with_nanosecond(0)
+                .and_then(|timestamp| timestamp.with_second(0))
+                .and_then(|timestamp| timestamp.with_minute(0))
+                .unwrap_or(timestamp),
+            Self::Day => date.and_time(NaiveTime::MIN),
+            Self::Week => date
+                .checked_sub_days(Days::new(u64::from(date.weekday().num_days_from_monday())))
+                .unwrap_or(date)
+                .and_time(NaiveTime::MIN),
+            Self::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)
+                .unwrap_or(date)
+                .and_time(NaiveTime::MIN),
+        }
+    }
+}
+
+impl Display for Bucket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        })
+    }
+}
+
+// Statistics about the messages added to a mailbox during a single time bucket
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BucketStats {
+    // The start of the time bucket in UTC
+    pub bucket: NaiveDateTime,
+
+    pub mailbox: Mailbox,
+
+    // The number of messages added during the bucket
+    pub added: usize,
+
+    // The number of the added messages that are now read
+    pub read: usize,
+
+    // The number of the added messages that are now archived
+    pub archived: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_truncate() {
+        // 2024-01-03 is a Wednesday
+        let timestamp = parse("2024-01-03 12:34:56");
+        assert_eq!(
+            Bucket::Hour.truncate(timestamp),
+            parse("2024-01-03 12:00:00")
+        );
+        assert_eq!(
+            Bucket::Day.truncate(timestamp),
+            parse("2024-01-03 00:00:00")
+        );
+        assert_eq!(
+            Bucket::Week.truncate(timestamp),
+            parse("2024-01-01 00:00:00")
+        );
+        assert_eq!(
+            Bucket::Month.truncate(timestamp),
+            parse("2024-01-01 00:00:00")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::bail;
+use sea_query::Value;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+// The names of the tables that store tags and the tags attached to each message
+pub const TAG_TABLE: &str = "tag";
+pub const MESSAGE_TAG_TABLE: &str = "message_tag";
+
+// A label that can be attached to any number of messages regardless of their mailbox
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String")]
+pub struct Tag(String);
+
+impl AsRef<str> for Tag {
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Tag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        s.try_into()
+    }
+}
+
+impl TryFrom<&str> for Tag {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        String::from(value).try_into()
+    }
+}
+
+impl TryFrom<String> for Tag {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        if value.is_empty() {
+            bail!("tag must not be empty");
+        }
+        // Commas separate tags in filter query strings
+        if value.contains(',') {
+            bail!("tag must not contain ,");
+        }
+        if value.chars().any(char::is_whitespace) {
+            bail!("tag must not contain whitespace");
+        }
+
+        Ok(Self(value))
+    }
+}
+
+impl From<Tag> for String {
+    fn from(value: Tag) -> Self {
+        value.0
+    }
+}
+
+impl From<Tag> for Value {
+    fn from(value: Tag) -> Self {
+        Self::String(Some(Box::new(value.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_into() {
+        assert!(TryInto::<Tag>::try_into("urgent").is_ok());
+        assert!(TryInto::<Tag>::try_into("ci/project-a").is_ok());
+        assert!(TryInto::<Tag>::try_into("").is_err());
+        assert!(TryInto::<Tag>::try_into("a,b").is_err());
+        assert!(TryInto::<Tag>::try_into("a b").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        assert_eq!(
+            serde_json::from_str::<Tag>(r#""urgent""#).unwrap(),
+            Tag(String::from("urgent"))
+        );
+        assert!(serde_json::from_str::<Tag>(r#""a,b""#).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::mailbox::Mailbox;
+use crate::message::{Id, State};
+use serde::Deserialize;
+
+// A message that moved from one state to another
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transition {
+    pub id: Id,
+    pub mailbox: Mailbox,
+    pub from: State,
+    pub to: State,
+}
+
+// Receives the transitions that Database::change_state made after they are saved, which lets
+// features like hooks react to state changes without each caller of change_state notifying them
+pub trait TransitionObserver: Send + Sync {
+    fn on_transitions(&self, transitions: &[Transition]);
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ForbiddenTransition {
+    from: State,
+    to: State,
+}
+
+// The transitions between states that messages are allowed to make
+// By default, messages can move from any state to any other state
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+#[must_use]
+pub struct TransitionPolicy {
+    forbidden: Vec<ForbiddenTransition>,
+}
+
+impl TransitionPolicy {
+    // Create a policy that allows every transition
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Forbid messages from moving from one state to another
+    pub fn forbid(mut self, from: State, to: State) -> Self {
+        self.forbidden.push(ForbiddenTransition { from, to });
+        self
+    }
+
+    // Determine whether the policy allows every transition
+    #[must_use]
+    pub fn allows_all(&self) -> bool {
+        self.forbidden.is_empty()
+    }
+
+    // Determine whether a message can move from one state to another
+    // Staying in the same state is always allowed
+    #[must_use]
+    pub fn is_allowed(&self, from: State, to: State) -> bool {
+        from == to
+            || !self
+                .forbidden
+                .iter()
+                .any(|forbidden| forbidden.from == from && forbidden.to == to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        let policy = TransitionPolicy::new().forbid(State::Archived, State::Unread);
+        assert!(!policy.allows_all());
+        assert!(!policy.is_allowed(State::Archived, State::Unread));
+        assert!(policy.is_allowed(State::Archived, State::Read));
+        assert!(policy.is_allowed(State::Unread, State::Archived));
+        assert!(policy.is_allowed(State::Archived, State::Archived));
+        assert!(TransitionPolicy::new().allows_all());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let policy: TransitionPolicy =
+            serde_json::from_str(r#"{"forbidden":[{"from":"archived","to":"unread"}]}"#).unwrap();
+        assert_eq!(
+            policy,
+            TransitionPolicy::new().forbid(State::Archived, State::Unread)
+        );
+        assert!(
+            serde_json::from_str::<TransitionPolicy>(r#"{"forbidden":[{"from":"archived"}]}"#)
+                .is_err()
+        );
+    }
+}
@@ -1,6 +1,18 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
 
+#[derive(Clone, Copy, ValueEnum)]
+pub enum QuotaActionArg {
+    Reject,
+    Archive,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum MailboxCharsetArg {
+    Unicode,
+    Ascii,
+}
+
 #[derive(Parser)]
 #[clap(about, version, author)]
 pub struct Cli {
@@ -16,8 +28,86 @@ pub struct Cli {
     #[clap(long, env = "MAILBOX_AUTH_TOKEN")]
     pub token: Option<String>,
 
+    /// Enable the admin routes and require them to have an "Authorization: Bearer" header
+    /// containing this token
+    #[clap(long, env = "MAILBOX_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Accept new messages without a token if they are signed with this producer secret (can be
+    /// repeated)
+    #[clap(
+        long = "signing-key",
+        env = "MAILBOX_SIGNING_KEYS",
+        value_delimiter = ','
+    )]
+    pub signing_keys: Vec<String>,
+
+    /// Accept Slack slash commands at /slack/commands that are signed with this Slack app signing
+    /// secret
+    #[clap(long, env = "MAILBOX_SLACK_SIGNING_SECRET")]
+    pub slack_signing_secret: Option<String>,
+
     /// SQLite mailbox database filename
     #[allow(clippy::doc_markdown)]
     #[clap(short = 'f', long, default_value = "mailbox.db")]
     pub db_file: PathBuf,
+
+    /// The maximum length of a message's content in bytes (defaults to 1 MiB)
+    #[clap(long)]
+    pub max_content_length: Option<usize>,
+
+    /// The maximum number of messages that can be added in one request (defaults to 100,000)
+    #[clap(long)]
+    pub max_batch_size: Option<usize>,
+
+    /// The maximum number of sections in a mailbox name (defaults to 16)
+    #[clap(long)]
+    pub max_mailbox_depth: Option<usize>,
+
+    /// The maximum length of a mailbox name in bytes (defaults to 256)
+    #[clap(long)]
+    pub max_mailbox_length: Option<usize>,
+
+    /// The maximum length of each section of a mailbox name in bytes (defaults to 256)
+    #[clap(long)]
+    pub max_mailbox_section_length: Option<usize>,
+
+    /// The characters that new mailbox names can contain: "unicode" allows any character and
+    /// "ascii" only allows ASCII letters, digits, "-", "_", and "."
+    #[clap(long, value_enum, default_value_t = MailboxCharsetArg::Unicode)]
+    pub mailbox_charset: MailboxCharsetArg,
+
+    /// The maximum size of a file attached to a message in bytes (defaults to 1 MiB)
+    #[clap(long)]
+    pub max_attachment_size: Option<usize>,
+
+    /// The maximum number of unread and read messages in each mailbox, not counting its
+    /// descendants
+    #[clap(long, value_name = "MAX")]
+    pub mailbox_quota: Option<usize>,
+
+    /// The maximum number of unread and read messages in a mailbox and its descendants, like
+    /// "ci=1000" (can be repeated)
+    #[clap(long, value_name = "MAILBOX=MAX")]
+    pub namespace_quota: Vec<String>,
+
+    /// What to do with new messages that would exceed a quota: "reject" them or add them and
+    /// "archive" the oldest messages
+    #[clap(long, value_enum, default_value_t = QuotaActionArg::Reject)]
+    pub quota_action: QuotaActionArg,
+
+    /// A TOML file of the push notification services that new high-priority messages are forwarded
+    /// to and the chat channels that new messages are mirrored to
+    #[clap(long, value_name = "FILE")]
+    pub forward_config: Option<PathBuf>,
+
+    /// Add an alert message to this mailbox whenever a heartbeat mailbox goes longer than its
+    /// expected interval without a new message
+    #[clap(long, value_name = "MAILBOX")]
+    pub heartbeat_alerts: Option<String>,
+
+    /// The number of seconds to wait for in-flight requests to finish after receiving SIGTERM or
+    /// SIGINT before forcibly stopping
+    #[clap(long, default_value = "30")]
+    pub shutdown_timeout: u64,
 }
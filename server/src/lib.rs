@@ -0,0 +1,3175 @@
+#![warn(clippy::str_to_string, clippy::pedantic, clippy::nursery)]
+// Route paths like "/mailboxes/{mailbox:.*}" look like format strings
+#![allow(
+    clippy::future_not_send,
+    clippy::literal_string_with_formatting_args,
+    clippy::missing_errors_doc
+)]
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorUnauthorized,
+};
+use actix_web::http::header::{
+    HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, ETAG, IF_NONE_MATCH,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::{Compress, DefaultHeaders};
+use actix_web::web::{self, Bytes, Data, Json, Path, PayloadConfig, Query, ReqData, ServiceConfig};
+use actix_web::{
+    delete, get, post, put, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Result,
+};
+use anyhow::Context;
+use database::{
+    verify_signature, verify_slack_signature, AdminStats, ApiToken, Attachment, AuditEntry,
+    Bridges, Bucket, Changes, Compaction, Database, DeadLetterPolicy, Filter, Forward,
+    ForwardDelivery, Forwarder, Heartbeat, Id, Mailbox, MailboxAcl, MailboxConfig, MailboxInfo,
+    Message, NewMessage, QuotaStatus, Seq, SqliteBackend, State, ValidationError,
+    REQUEST_ID_HEADER, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER, SLACK_SIGNATURE_HEADER,
+    SLACK_TIMESTAMP_HEADER, VERSION_HEADER,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+type AppData = Arc<Database<SqliteBackend>>;
+
+// The credentials that authorized a request, which handlers use to restrict and audit it
+#[derive(Clone)]
+struct Caller {
+    // The name of the API token, or how the request was authorized otherwise in parentheses, which
+    // token names can't contain
+    name: String,
+    acl: MailboxAcl,
+}
+
+impl Caller {
+    // Create a caller that can access every mailbox
+    fn unrestricted(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            acl: MailboxAcl::default(),
+        }
+    }
+}
+
+// The caller that admin requests are audited as
+const ADMIN_CALLER: &str = "(admin token)";
+
+// The secrets of the API tokens that administrators have created mapped to their callers, which
+// are kept in memory so that requests can be authorized without querying the database
+type ApiTokens = RwLock<HashMap<String, Caller>>;
+
+// The secrets of the producers that can add messages by signing them instead of sending a token
+struct SigningKeys {
+    keys: Vec<String>,
+    // The signatures that have already been accepted mapped to their timestamps, which are kept
+    // until the timestamps are too old to be accepted anyway so that requests can't be replayed
+    used_signatures: Mutex<HashMap<String, u64>>,
+}
+
+impl SigningKeys {
+    fn new(keys: Vec<String>) -> Self {
+        Self {
+            keys,
+            used_signatures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Record that a signature was accepted, returning false if it was already used
+    fn use_signature(&self, timestamp: u64, signature: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let mut used_signatures = self
+            .used_signatures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        used_signatures
+            .retain(|_, timestamp| now.abs_diff(*timestamp) <= SIGNATURE_MAX_AGE.as_secs());
+        used_signatures
+            .insert(signature.to_owned(), timestamp)
+            .is_none()
+    }
+}
+
+// The largest request body that the server accepts, which matches actix's default JSON limit
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+// Use the request id that the client sent if it is short and only contains safe characters to log,
+// otherwise generate one so that every request can be identified in the logs
+fn get_request_id(header: Option<&HeaderValue>) -> String {
+    header
+        .and_then(|value| value.to_str().ok())
+        .filter(|request_id| {
+            !request_id.is_empty()
+                && request_id.len() <= 64
+                && request_id
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+        })
+        .map_or_else(database::generate_request_id, ToOwned::to_owned)
+}
+
+// Format the log line for a handled request
+fn format_request_log(
+    request_id: &str,
+    method: &Method,
+    path: &str,
+    status: StatusCode,
+    elapsed: Duration,
+) -> String {
+    format!(
+        "[{request_id}] {method} {path} {} {}ms",
+        status.as_u16(),
+        elapsed.as_millis()
+    )
+}
+
+// Middleware that logs every request to stderr with its request id and echoes the request id in the
+// response so that client errors can be matched with the log line
+fn log_request<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = get_request_id(req.headers().get(REQUEST_ID_HEADER));
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+    let start = Instant::now();
+    let res = srv.call(req);
+    async move {
+        let mut res = res.await?;
+        eprintln!(
+            "{}",
+            format_request_log(&request_id, &method, &path, res.status(), start.elapsed())
+        );
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            res.headers_mut()
+                .insert(HeaderName::from_static("x-request-id"), value);
+        }
+        Ok(res)
+    }
+}
+
+// Determine which caller a request's Authorization header identifies, returning None if the request
+// isn't authorized to use the API at all
+// Requests are authorized if they contain the server's token, which can access every mailbox, or
+// any API token, which can access the mailboxes in its access list. If the server has neither,
+// every request is authorized unless anonymous writes aren't allowed because the server has
+// signing keys, which unsigned requests would otherwise bypass.
+fn authorize(
+    header: Option<&HeaderValue>,
+    auth_header: Option<&HeaderValue>,
+    api_tokens: &ApiTokens,
+    allow_anonymous_writes: bool,
+    method: &Method,
+) -> Option<Caller> {
+    if auth_header.is_some() && header == auth_header {
+        return Some(Caller::unrestricted("(server token)"));
+    }
+    let token = header
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_tokens = api_tokens.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(caller) = token.and_then(|token| api_tokens.get(token)) {
+        return Some(caller.clone());
+    }
+    (auth_header.is_none() && api_tokens.is_empty() && (allow_anonymous_writes || method.is_safe()))
+        .then(|| Caller::unrestricted("(anonymous)"))
+}
+
+// Return an error unless the access list allows the request to write to every mailbox
+fn check_writable<'a>(
+    acl: &MailboxAcl,
+    mut mailboxes: impl Iterator<Item = &'a Mailbox>,
+) -> Result<()> {
+    if let Some(mailbox) = mailboxes.find(|mailbox| !acl.can_write(mailbox)) {
+        return Err(ErrorForbidden(format!(
+            "Token can't write to mailbox \"{mailbox}\""
+        )));
+    }
+    Ok(())
+}
+
+// Record a call that changed messages or tokens in the audit log
+// The change already happened, so failing to record it is logged instead of failing the request
+async fn audit(data: &AppData, caller: &str, operation: &str, target: &str, affected: usize) {
+    if let Err(err) = data
+        .backend()
+        .record_audit(caller, operation, target, affected)
+        .await
+    {
+        eprintln!("Failed to record {operation} by {caller} in the audit log: {err:#}");
+    }
+}
+
+// Format the messages that a call changed as an audit log target, like "ids=1,2,3"
+fn format_ids_target(messages: &[Message]) -> String {
+    let ids = messages
+        .iter()
+        .map(|message| message.id.to_string())
+        .collect::<Vec<_>>();
+    format!("ids={}", ids.join(","))
+}
+
+// Format a request's query string with extra parameters as an audit log target
+fn format_query_target(req: &HttpRequest, extra: &str) -> String {
+    [req.query_string(), extra]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Load a single message that the request can read, or write if writable is true, returning None
+// if it doesn't exist or the access list hides it
+async fn load_accessible_message(
+    data: &AppData,
+    acl: &MailboxAcl,
+    id: Id,
+    writable: bool,
+) -> Result<Option<Message>> {
+    let patterns = if writable { &acl.write } else { &acl.read };
+    let messages = data
+        .load_messages(
+            Filter::new()
+                .with_ids(vec![id])
+                .with_mailbox_patterns_option(patterns.clone()),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(messages.into_iter().next())
+}
+
+// Determine whether a request is a signed request to add messages, which its signature authorizes
+// instead of a token. The signature itself is verified by create_messages because it covers the
+// body, which middleware can't read. Producers also need to be able to negotiate the API version.
+fn is_signed_ingestion(req: &ServiceRequest, signing_keys: &SigningKeys) -> bool {
+    if signing_keys.keys.is_empty() {
+        return false;
+    }
+    match (req.method(), req.path()) {
+        (&Method::POST, "/messages" | "/v1/messages") => {
+            req.headers().contains_key(SIGNATURE_HEADER)
+        }
+        (&Method::GET, "/version") => true,
+        _ => false,
+    }
+}
+
+// DataVersion tracks a version number that is bumped every time the server changes messages. It is
+// used to generate ETags so that clients polling for messages can skip downloading unchanged data.
+struct DataVersion {
+    // Identifies this run of the server so that ETags from before a restart are never reused
+    epoch: u64,
+    version: AtomicU64,
+}
+
+impl DataVersion {
+    fn new() -> Self {
+        Self {
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    // Generate the ETag for the current version
+    fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.epoch, self.version.load(Ordering::SeqCst))
+    }
+
+    // Record that messages were changed
+    fn bump(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Generate a response with an ETag header, or a 304 Not Modified response if the client already
+// has the current version
+async fn respond_with_etag<T: Serialize>(
+    req: &HttpRequest,
+    version: &DataVersion,
+    load: impl std::future::Future<Output = anyhow::Result<T>>,
+) -> Result<HttpResponse> {
+    // Read the version before loading so that a change during the load invalidates the response
+    let etag = version.etag();
+    let matches = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if matches {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((ETAG, etag))
+            .finish());
+    }
+
+    let body = load.await.map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().insert_header((ETAG, etag)).json(body))
+}
+
+// Return the messages whose reminders are due to unread and add alerts for overdue heartbeats
+// before a read so that the ETag that the read responds with accounts for them
+async fn wake_reminders(data: &AppData, version: &DataVersion) -> Result<()> {
+    let woken = data.wake().await.map_err(ErrorInternalServerError)?;
+    if !woken.is_empty() {
+        version.bump();
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CreateMessage {
+    Message(RawNewMessage),
+    Messages(Vec<RawNewMessage>),
+}
+
+// A new message whose mailbox hasn't been parsed yet. An invalid mailbox would otherwise be reported
+// as the untagged enum's generic error instead of the specific violation.
+#[derive(Deserialize)]
+struct RawNewMessage {
+    mailbox: String,
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RawNewMessage {
+    // Validate the mailbox and then parse the rest of the message
+    fn parse(self) -> Result<NewMessage> {
+        Mailbox::try_from(self.mailbox.as_str()).map_err(ErrorBadRequest)?;
+        let mut fields = self.fields;
+        fields.insert(
+            String::from("mailbox"),
+            serde_json::Value::String(self.mailbox),
+        );
+        serde_json::from_value(serde_json::Value::Object(fields)).map_err(ErrorBadRequest)
+    }
+}
+
+// A mailbox and the status of the quota that it is closest to exceeding, if it has any quotas
+#[derive(Serialize)]
+struct MailboxWithQuota {
+    #[serde(flatten)]
+    info: MailboxInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota: Option<QuotaStatus>,
+}
+
+#[get("/mailboxes")]
+async fn read_mailboxes(
+    req: HttpRequest,
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    filter: Query<Filter>,
+) -> Result<HttpResponse> {
+    let filter = filter
+        .into_inner()
+        .with_mailbox_patterns_option(caller.acl.read.clone());
+    wake_reminders(&data, &version).await?;
+    let load = async {
+        let mailboxes = data.load_mailboxes(filter).await?;
+        let names = mailboxes
+            .iter()
+            .map(|info| info.name.clone())
+            .collect::<Vec<_>>();
+        let quotas = data.load_quota_statuses(&names).await?;
+        Ok(mailboxes
+            .into_iter()
+            .zip(quotas)
+            .map(|(info, quota)| MailboxWithQuota { info, quota })
+            .collect::<Vec<_>>())
+    };
+    respond_with_etag(&req, &version, load).await
+}
+
+#[get("/messages")]
+async fn read_messages(
+    req: HttpRequest,
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    filter: Query<Filter>,
+) -> Result<HttpResponse> {
+    let filter = filter
+        .into_inner()
+        .with_mailbox_patterns_option(caller.acl.read.clone());
+    wake_reminders(&data, &version).await?;
+    respond_with_etag(&req, &version, data.load_messages(filter)).await
+}
+
+#[get("/stats/{bucket}")]
+async fn read_stats(
+    req: HttpRequest,
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    bucket: Path<Bucket>,
+    filter: Query<Filter>,
+) -> Result<HttpResponse> {
+    let filter = filter
+        .into_inner()
+        .with_mailbox_patterns_option(caller.acl.read.clone());
+    wake_reminders(&data, &version).await?;
+    respond_with_etag(&req, &version, data.load_stats(filter, bucket.into_inner())).await
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChangesQuery {
+    #[serde(default)]
+    since: Seq,
+}
+
+#[get("/changes")]
+async fn read_changes(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    query: Query<ChangesQuery>,
+) -> Result<Json<Changes>> {
+    // Woken messages are reported as changes
+    wake_reminders(&data, &version).await?;
+    let mut changes = data
+        .load_changes(query.since)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    changes
+        .messages
+        .retain(|message| caller.acl.can_read(&message.mailbox));
+    Ok(Json(changes))
+}
+
+#[get("/messages/{id}/attachments")]
+async fn read_attachments(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+    id: Path<Id>,
+) -> Result<Json<Vec<Attachment>>> {
+    let id = id.into_inner();
+    if !caller.acl.is_unrestricted()
+        && load_accessible_message(&data, &caller.acl, id, false)
+            .await?
+            .is_none()
+    {
+        return Err(ErrorNotFound("Message not found"));
+    }
+    let attachments = data
+        .load_attachments(id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(attachments))
+}
+
+#[post("/messages/{id}/views")]
+async fn record_view(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    id: Path<Id>,
+) -> Result<Json<Message>> {
+    let id = id.into_inner();
+    if !caller.acl.is_unrestricted()
+        && load_accessible_message(&data, &caller.acl, id, false)
+            .await?
+            .is_none()
+    {
+        return Err(ErrorNotFound("Message not found"));
+    }
+    // Views aren't audited because they only update statistics and happen whenever a message is
+    // opened
+    let message = data
+        .record_view(id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("Message not found"))?;
+    version.bump();
+    Ok(Json(message))
+}
+
+// How old a signed request can be before it is rejected as a possible replay
+const SIGNATURE_MAX_AGE: Duration = Duration::from_mins(5);
+
+// Determine whether a request timestamp in seconds since the epoch is within max_age of now
+// Timestamps in the future are allowed by the same amount to tolerate clock skew.
+fn is_recent_timestamp(timestamp: &str, max_age: Duration) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    timestamp
+        .parse::<u64>()
+        .is_ok_and(|timestamp| now.abs_diff(timestamp) <= max_age.as_secs())
+}
+
+// Determine whether a signed request has a valid signature that was signed recently and hasn't
+// been used before
+fn verify_signed_request(signing_keys: &SigningKeys, req: &HttpRequest, body: &[u8]) -> bool {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let (Some(timestamp), Some(signature)) =
+        (header(SIGNATURE_TIMESTAMP_HEADER), header(SIGNATURE_HEADER))
+    else {
+        return false;
+    };
+    is_recent_timestamp(timestamp, SIGNATURE_MAX_AGE)
+        && verify_signature(&signing_keys.keys, timestamp, body, signature)
+        && timestamp
+            .parse()
+            .is_ok_and(|timestamp| signing_keys.use_signature(timestamp, signature))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreateMessagesQuery {
+    #[serde(default)]
+    upsert: bool,
+}
+
+#[post("/messages")]
+async fn create_messages(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    signing_keys: Data<SigningKeys>,
+    caller: ReqData<Caller>,
+    query: Query<CreateMessagesQuery>,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<Json<Vec<Message>>> {
+    // Signed requests must have a valid signature even if they also have a valid token
+    if req.headers().contains_key(SIGNATURE_HEADER)
+        && !verify_signed_request(&signing_keys, &req, &body)
+    {
+        return Err(ErrorUnauthorized("Invalid, expired, or reused signature"));
+    }
+    // The body is parsed manually because the signature covers its raw bytes
+    let messages = serde_json::from_slice::<CreateMessage>(&body).map_err(ErrorBadRequest)?;
+    let new_messages = match messages {
+        CreateMessage::Message(message) => vec![message],
+        CreateMessage::Messages(messages) => messages,
+    }
+    .into_iter()
+    .map(RawNewMessage::parse)
+    .collect::<Result<Vec<_>>>()?;
+    check_writable(
+        &caller.acl,
+        new_messages.iter().map(|message| &message.mailbox),
+    )?;
+    let (operation, messages) = if query.upsert {
+        ("upsert_messages", data.upsert_messages(new_messages).await)
+    } else {
+        ("create_messages", data.add_messages(new_messages).await)
+    };
+    let messages = messages.map_err(|err| {
+        // Messages that exceed the limits are the client's fault
+        if err.is::<ValidationError>() {
+            ErrorBadRequest(err)
+        } else {
+            ErrorInternalServerError(err)
+        }
+    })?;
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        operation,
+        &format_ids_target(&messages),
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateMessages {
+    new_state: State,
+    // The custom state that refines new_state, if any
+    #[serde(default)]
+    custom_state: Option<String>,
+}
+
+#[put("/messages")]
+async fn update_messages(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    req: HttpRequest,
+    filter: Query<Filter>,
+    update: Json<UpdateMessages>,
+) -> Result<Json<Vec<Message>>> {
+    let filter = filter
+        .into_inner()
+        .with_mailbox_patterns_option(caller.acl.write.clone());
+    let UpdateMessages {
+        new_state,
+        custom_state,
+    } = update.into_inner();
+    let target = custom_state.as_ref().map_or_else(
+        || format!("new_state={new_state}"),
+        |custom_state| format!("new_state={new_state}&custom_state={custom_state}"),
+    );
+    let messages = match custom_state {
+        Some(custom_state) => {
+            data.change_custom_state(filter, custom_state, new_state)
+                .await
+        }
+        None => data.change_state(filter, new_state).await,
+    }
+    .map_err(|err| {
+        // Transitions that the policy forbids and invalid custom states are the client's fault
+        if err.is::<ValidationError>() {
+            ErrorBadRequest(err)
+        } else {
+            ErrorInternalServerError(err)
+        }
+    })?;
+    version.bump();
+    let target = format_query_target(&req, &target);
+    audit(
+        &data,
+        &caller.name,
+        "update_messages",
+        &target,
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClaimMessages {
+    worker: String,
+    lease_seconds: u64,
+}
+
+#[post("/messages/claim")]
+async fn claim_messages(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    req: HttpRequest,
+    filter: Query<Filter>,
+    claim: Json<ClaimMessages>,
+) -> Result<Json<Vec<Message>>> {
+    let ClaimMessages {
+        worker,
+        lease_seconds,
+    } = claim.into_inner();
+    if worker.is_empty() {
+        return Err(ErrorBadRequest("Worker is required"));
+    }
+    let target = format_query_target(&req, &format!("worker={worker}"));
+    let messages = data
+        .claim_messages(
+            filter
+                .into_inner()
+                .with_mailbox_patterns_option(caller.acl.write.clone()),
+            worker,
+            Duration::from_secs(lease_seconds),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        "claim_messages",
+        &target,
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NackMessage {
+    reason: String,
+    #[serde(default)]
+    dead_letter: DeadLetterPolicy,
+}
+
+#[post("/messages/{id}/nack")]
+async fn nack_message(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    id: Path<Id>,
+    nack: Json<NackMessage>,
+) -> Result<Json<Message>> {
+    let NackMessage {
+        reason,
+        dead_letter,
+    } = nack.into_inner();
+    let id = id.into_inner();
+    if !caller.acl.is_unrestricted() {
+        if load_accessible_message(&data, &caller.acl, id, true)
+            .await?
+            .is_none()
+        {
+            return Err(ErrorNotFound("Message not found"));
+        }
+        // Exhausted messages are moved into the dead-letter mailbox, which must be writable too
+        check_writable(&caller.acl, std::iter::once(&dead_letter.mailbox))?;
+    }
+    let message = data
+        .nack_message(id, reason, dead_letter)
+        .await
+        .map_err(|err| {
+            // Rejecting a message that isn't claimed is the client's fault
+            if err.is::<ValidationError>() {
+                ErrorBadRequest(err)
+            } else {
+                ErrorInternalServerError(err)
+            }
+        })?
+        .ok_or_else(|| ErrorNotFound("Message not found"))?;
+    version.bump();
+    audit(&data, &caller.name, "nack_message", &format!("ids={id}"), 1).await;
+    Ok(Json(message))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RemindMessage {
+    delay_seconds: u64,
+}
+
+#[post("/messages/{id}/remind")]
+async fn remind_message(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    id: Path<Id>,
+    remind: Json<RemindMessage>,
+) -> Result<Json<Message>> {
+    let id = id.into_inner();
+    if !caller.acl.is_unrestricted()
+        && load_accessible_message(&data, &caller.acl, id, true)
+            .await?
+            .is_none()
+    {
+        return Err(ErrorNotFound("Message not found"));
+    }
+    let message = data
+        .remind_message(id, Duration::from_secs(remind.delay_seconds))
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("Message not found"))?;
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        "remind_message",
+        &format!("ids={id} delay_seconds={}", remind.delay_seconds),
+        1,
+    )
+    .await;
+    Ok(Json(message))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkMessages {
+    related_id: Id,
+}
+
+#[post("/messages/{id}/links")]
+async fn link_messages(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    id: Path<Id>,
+    link: Json<LinkMessages>,
+) -> Result<Json<Vec<Message>>> {
+    let id = id.into_inner();
+    let related_id = link.related_id;
+    // Linking changes both messages, so both must be writable
+    if !caller.acl.is_unrestricted() {
+        for id in [id, related_id] {
+            if load_accessible_message(&data, &caller.acl, id, true)
+                .await?
+                .is_none()
+            {
+                return Err(ErrorNotFound("Message not found"));
+            }
+        }
+    }
+    let messages = data.link_messages(id, related_id).await.map_err(|err| {
+        // Linking a message to itself is the client's fault
+        if err.is::<ValidationError>() {
+            ErrorBadRequest(err)
+        } else {
+            ErrorInternalServerError(err)
+        }
+    })?;
+    if messages.is_empty() {
+        return Err(ErrorNotFound("Message not found"));
+    }
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        "link_messages",
+        &format!("ids={id},{related_id}"),
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+#[delete("/messages")]
+async fn delete_messages(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    req: HttpRequest,
+    filter: Query<Filter>,
+) -> Result<Json<Vec<Message>>> {
+    if filter.matches_all() {
+        return Err(ErrorBadRequest("Filter is required"));
+    }
+    let filter = filter
+        .into_inner()
+        .with_mailbox_patterns_option(caller.acl.write.clone());
+    let messages = data
+        .delete_messages(filter)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        "delete_messages",
+        &format_query_target(&req, ""),
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+#[delete("/mailboxes/{mailbox:.*}")]
+async fn delete_mailbox(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+    mailbox: Path<String>,
+) -> Result<Json<Vec<Message>>> {
+    let mailbox = Mailbox::try_from(mailbox.into_inner()).map_err(ErrorBadRequest)?;
+    let target = format!("mailbox={mailbox}");
+    let filter = Filter::new()
+        .with_mailbox(mailbox)
+        .with_mailbox_patterns_option(caller.acl.write.clone());
+    let messages = data
+        .delete_messages(filter)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    version.bump();
+    audit(
+        &data,
+        &caller.name,
+        "delete_mailbox",
+        &target,
+        messages.len(),
+    )
+    .await;
+    Ok(Json(messages))
+}
+
+// A heartbeat with a description of whether its mailbox received a message recently enough
+#[derive(Serialize)]
+struct HeartbeatStatus {
+    #[serde(flatten)]
+    heartbeat: Heartbeat,
+    overdue: bool,
+    // Like "last message 3h ago (expected hourly)"
+    status: String,
+}
+
+#[get("/heartbeats")]
+async fn read_heartbeats(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    caller: ReqData<Caller>,
+) -> Result<Json<Vec<HeartbeatStatus>>> {
+    // Overdue heartbeats add alerts
+    wake_reminders(&data, &version).await?;
+    let now = chrono::Utc::now().naive_utc();
+    let heartbeats = data
+        .load_heartbeats()
+        .await
+        .map_err(ErrorInternalServerError)?
+        .into_iter()
+        .filter(|heartbeat| caller.acl.can_read(&heartbeat.mailbox))
+        .map(|heartbeat| HeartbeatStatus {
+            overdue: heartbeat.is_overdue(now),
+            status: heartbeat.describe(now),
+            heartbeat,
+        })
+        .collect();
+    Ok(Json(heartbeats))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HeartbeatQuery {
+    mailbox: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetHeartbeat {
+    interval_seconds: u64,
+}
+
+#[put("/heartbeats")]
+async fn set_heartbeat(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+    query: Query<HeartbeatQuery>,
+    heartbeat: Json<SetHeartbeat>,
+) -> Result<Json<Option<Heartbeat>>> {
+    let mailbox = Mailbox::try_from(query.into_inner().mailbox).map_err(ErrorBadRequest)?;
+    check_writable(&caller.acl, std::iter::once(&mailbox))?;
+    let target = format!("mailbox={mailbox}");
+    let interval = Duration::from_secs(heartbeat.interval_seconds);
+    let heartbeat = data
+        .set_heartbeat(mailbox, Some(interval))
+        .await
+        .map_err(|err| {
+            // Intervals that are too short are the client's fault
+            if err.is::<ValidationError>() {
+                ErrorBadRequest(err)
+            } else {
+                ErrorInternalServerError(err)
+            }
+        })?;
+    audit(&data, &caller.name, "set_heartbeat", &target, 1).await;
+    Ok(Json(heartbeat))
+}
+
+#[delete("/heartbeats")]
+async fn delete_heartbeat(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+    query: Query<HeartbeatQuery>,
+) -> Result<HttpResponse> {
+    let mailbox = Mailbox::try_from(query.into_inner().mailbox).map_err(ErrorBadRequest)?;
+    check_writable(&caller.acl, std::iter::once(&mailbox))?;
+    let target = format!("mailbox={mailbox}");
+    data.set_heartbeat(mailbox, None)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    audit(&data, &caller.name, "delete_heartbeat", &target, 1).await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/mailbox-configs")]
+async fn read_mailbox_configs(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+) -> Result<Json<BTreeMap<Mailbox, MailboxConfig>>> {
+    let mut configs = data
+        .load_mailbox_configs()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    configs.retain(|mailbox, _| caller.acl.can_read(mailbox));
+    Ok(Json(configs))
+}
+
+#[get("/mailboxes/{mailbox:.*}")]
+async fn read_mailbox_config(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+    mailbox: Path<String>,
+) -> Result<Json<MailboxConfig>> {
+    let mailbox = Mailbox::try_from(mailbox.into_inner()).map_err(ErrorBadRequest)?;
+    if !caller.acl.can_read(&mailbox) {
+        return Err(ErrorForbidden(format!(
+            "Token can't read mailbox \"{mailbox}\""
+        )));
+    }
+    let config = data
+        .load_mailbox_config(&mailbox)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(config))
+}
+
+#[put("/mailboxes/{mailbox:.*}")]
+async fn set_mailbox_config(
+    data: Data<AppData>,
+    caller: ReqData<Caller>,
+    mailbox: Path<String>,
+    config: Json<MailboxConfig>,
+) -> Result<Json<MailboxConfig>> {
+    let mailbox = Mailbox::try_from(mailbox.into_inner()).map_err(ErrorBadRequest)?;
+    check_writable(&caller.acl, std::iter::once(&mailbox))?;
+    let target = format!("mailbox={mailbox}");
+    let config = data
+        .set_mailbox_config(mailbox, config.into_inner())
+        .await
+        .map_err(|err| {
+            if err.is::<ValidationError>() {
+                ErrorBadRequest(err)
+            } else {
+                ErrorInternalServerError(err)
+            }
+        })?;
+    audit(&data, &caller.name, "set_mailbox_config", &target, 1).await;
+    Ok(Json(config))
+}
+
+#[get("/stats")]
+async fn read_admin_stats(data: Data<AppData>) -> Result<Json<AdminStats>> {
+    let stats = data
+        .backend()
+        .load_admin_stats()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(stats))
+}
+
+#[post("/compact")]
+async fn compact(data: Data<AppData>) -> Result<Json<Compaction>> {
+    let compaction = data
+        .backend()
+        .compact()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    audit(&data, ADMIN_CALLER, "compact", "", 0).await;
+    Ok(Json(compaction))
+}
+
+#[get("/tokens")]
+async fn read_api_tokens(data: Data<AppData>) -> Result<Json<Vec<ApiToken>>> {
+    let tokens = data
+        .backend()
+        .load_api_tokens()
+        .await
+        .map_err(ErrorInternalServerError)?
+        .into_iter()
+        // Only reveal the secrets when the tokens are created
+        .map(|token| ApiToken {
+            token: None,
+            ..token
+        })
+        .collect();
+    Ok(Json(tokens))
+}
+
+#[derive(Deserialize)]
+struct CreateApiToken {
+    name: String,
+    #[serde(flatten)]
+    acl: MailboxAcl,
+}
+
+#[post("/tokens")]
+async fn create_api_token(
+    data: Data<AppData>,
+    api_tokens: Data<ApiTokens>,
+    body: Json<CreateApiToken>,
+) -> Result<HttpResponse> {
+    // Names are restricted so that they can be used in URLs without escaping
+    let name = body.name.as_str();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_' | '.'))
+    {
+        return Err(ErrorBadRequest(
+            "Token names must only contain letters, numbers, '-', '_', and '.'",
+        ));
+    }
+    let Some(token) = data
+        .backend()
+        .create_api_token(name, &body.acl)
+        .await
+        .map_err(ErrorInternalServerError)?
+    else {
+        return Err(ErrorConflict(format!("Token \"{name}\" already exists")));
+    };
+    if let Some(secret) = &token.token {
+        api_tokens
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                secret.clone(),
+                Caller {
+                    name: token.name.clone(),
+                    acl: token.acl.clone(),
+                },
+            );
+    }
+    audit(
+        &data,
+        ADMIN_CALLER,
+        "create_api_token",
+        &format!("name={name}"),
+        1,
+    )
+    .await;
+    Ok(HttpResponse::Created().json(token))
+}
+
+#[delete("/tokens/{name}")]
+async fn revoke_api_token(
+    data: Data<AppData>,
+    api_tokens: Data<ApiTokens>,
+    name: Path<String>,
+) -> Result<Json<ApiToken>> {
+    let Some(token) = data
+        .backend()
+        .revoke_api_token(&name)
+        .await
+        .map_err(ErrorInternalServerError)?
+    else {
+        return Err(ErrorNotFound(format!("Token \"{name}\" doesn't exist")));
+    };
+    if let Some(secret) = &token.token {
+        api_tokens
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(secret);
+    }
+    audit(
+        &data,
+        ADMIN_CALLER,
+        "revoke_api_token",
+        &format!("name={name}"),
+        1,
+    )
+    .await;
+    Ok(Json(ApiToken {
+        token: None,
+        ..token
+    }))
+}
+
+// The number of audit log entries or forward deliveries that are loaded when the request doesn't
+// specify a limit
+const DEFAULT_ADMIN_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitQuery {
+    limit: Option<usize>,
+}
+
+#[get("/audit")]
+async fn read_audit_log(
+    data: Data<AppData>,
+    query: Query<LimitQuery>,
+) -> Result<Json<Vec<AuditEntry>>> {
+    let entries = data
+        .backend()
+        .load_audit_log(query.limit.unwrap_or(DEFAULT_ADMIN_LIMIT))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(entries))
+}
+
+#[get("/forwards")]
+async fn read_forward_deliveries(
+    data: Data<AppData>,
+    query: Query<LimitQuery>,
+) -> Result<Json<Vec<ForwardDelivery>>> {
+    let deliveries = data
+        .backend()
+        .load_forward_deliveries(query.limit.unwrap_or(DEFAULT_ADMIN_LIMIT))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(Json(deliveries))
+}
+
+// Register the admin routes
+fn configure_admin_routes(cfg: &mut ServiceConfig) {
+    cfg.service(read_admin_stats)
+        .service(read_audit_log)
+        .service(read_forward_deliveries)
+        .service(compact)
+        .service(read_api_tokens)
+        .service(create_api_token)
+        .service(revoke_api_token);
+}
+
+// The versions of the API that the server supports
+// Every version is served under its own prefix, like /v1/messages
+const API_VERSIONS: [u32; 1] = [1];
+
+#[derive(Deserialize, Serialize)]
+struct VersionResponse {
+    api_versions: Vec<u32>,
+}
+
+#[get("/version")]
+async fn read_version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        api_versions: API_VERSIONS.to_vec(),
+    })
+}
+
+// Register the routes of the API
+fn configure_routes(cfg: &mut ServiceConfig) {
+    cfg.service(read_mailboxes)
+        .service(read_messages)
+        .service(read_attachments)
+        .service(record_view)
+        .service(read_changes)
+        .service(read_stats)
+        .service(create_messages)
+        .service(update_messages)
+        .service(claim_messages)
+        .service(nack_message)
+        .service(remind_message)
+        .service(link_messages)
+        .service(delete_messages)
+        .service(delete_mailbox)
+        .service(read_heartbeats)
+        .service(set_heartbeat)
+        .service(delete_heartbeat)
+        .service(read_mailbox_configs)
+        .service(read_mailbox_config)
+        .service(set_mailbox_config);
+}
+
+// The signing secret of the Slack app whose slash commands the server accepts
+struct SlackSigningSecret(String);
+
+// How old a Slack request can be before it is rejected as a possible replay
+const SLACK_MAX_AGE: Duration = Duration::from_mins(5);
+
+// The number of messages that the Slack view command lists
+const SLACK_VIEW_LIMIT: usize = 20;
+
+// The usage of the Slack slash command, which is shown for the help command and invalid commands
+const SLACK_USAGE: &str = "Usage:
+`/mailbox add <mailbox> <content>` adds a message
+`/mailbox view [mailbox]` lists unread messages";
+
+// A Slack slash command that the server understands
+#[derive(Debug, Eq, PartialEq)]
+enum SlackCommand {
+    Add { mailbox: Mailbox, content: String },
+    View { mailbox: Option<Mailbox> },
+    Help,
+}
+
+// Parse the text of a Slack slash command like `add ci "deploy done"` or `view ci`
+// Content can be quoted or left bare, in which case it is the rest of the text.
+fn parse_slack_command(text: &str) -> Result<SlackCommand, String> {
+    let text = text.trim();
+    let (command, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let rest = rest.trim_start();
+    match command {
+        "add" => {
+            let (mailbox, content) = rest
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| String::from("Provide a mailbox and the message's content"))?;
+            let content = content.trim();
+            let content = content
+                .strip_prefix('"')
+                .and_then(|content| content.strip_suffix('"'))
+                .unwrap_or(content);
+            if content.is_empty() {
+                return Err(String::from("The message's content can't be empty"));
+            }
+            Ok(SlackCommand::Add {
+                mailbox: mailbox.parse().map_err(|err| format!("{err:#}"))?,
+                content: content.to_owned(),
+            })
+        }
+        "view" => Ok(SlackCommand::View {
+            mailbox: match rest {
+                "" => None,
+                mailbox => Some(mailbox.parse().map_err(|err| format!("{err:#}"))?),
+            },
+        }),
+        "" | "help" => Ok(SlackCommand::Help),
+        command => Err(format!("Unknown command \"{command}\"")),
+    }
+}
+
+// The fields of a Slack slash command payload that the server uses
+#[derive(Deserialize)]
+struct SlackPayload {
+    text: String,
+    user_name: String,
+}
+
+// A response to a Slack slash command, which is only shown to the user who ran it unless the
+// response type is "in_channel"
+#[derive(Serialize)]
+struct SlackResponse {
+    response_type: &'static str,
+    text: String,
+}
+
+impl SlackResponse {
+    const fn ephemeral(text: String) -> Json<Self> {
+        Json(Self {
+            response_type: "ephemeral",
+            text,
+        })
+    }
+}
+
+// Determine whether a Slack request has a valid signature and was signed recently
+fn verify_slack_request(secret: &str, req: &HttpRequest, body: &[u8]) -> bool {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let (Some(timestamp), Some(signature)) = (
+        header(SLACK_TIMESTAMP_HEADER),
+        header(SLACK_SIGNATURE_HEADER),
+    ) else {
+        return false;
+    };
+    is_recent_timestamp(timestamp, SLACK_MAX_AGE)
+        && verify_slack_signature(secret, timestamp, body, signature)
+}
+
+// Run a Slack slash command, which Slack authorizes by signing it with the app's signing secret
+// instead of sending a token
+#[post("/commands")]
+async fn run_slack_command(
+    data: Data<AppData>,
+    version: Data<DataVersion>,
+    secret: Data<SlackSigningSecret>,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<Json<SlackResponse>> {
+    if !verify_slack_request(&secret.0, &req, &body) {
+        return Err(ErrorUnauthorized("Invalid Slack signature"));
+    }
+    let payload = serde_urlencoded::from_bytes::<SlackPayload>(&body).map_err(ErrorBadRequest)?;
+    let command = match parse_slack_command(&payload.text) {
+        Ok(command) => command,
+        Err(err) => return Ok(SlackResponse::ephemeral(format!("{err}\n{SLACK_USAGE}"))),
+    };
+    match command {
+        SlackCommand::Add { mailbox, content } => {
+            let new_message = NewMessage {
+                mailbox,
+                content,
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            };
+            let messages = match data.add_messages(vec![new_message]).await {
+                Ok(messages) => messages,
+                // Tell the user why invalid messages were rejected
+                Err(err) if err.is::<ValidationError>() => {
+                    return Ok(SlackResponse::ephemeral(err.to_string()));
+                }
+                Err(err) => return Err(ErrorInternalServerError(err)),
+            };
+            version.bump();
+            audit(
+                &data,
+                &format!("(slack {})", payload.user_name),
+                "create_messages",
+                &format_ids_target(&messages),
+                messages.len(),
+            )
+            .await;
+            let text = messages
+                .iter()
+                .map(|message| format!("Added message #{} to {}", message.id, message.mailbox))
+                .collect::<Vec<_>>()
+                .join("\n");
+            // Show new messages to the whole channel
+            Ok(Json(SlackResponse {
+                response_type: "in_channel",
+                text,
+            }))
+        }
+        SlackCommand::View { mailbox } => {
+            let filter = Filter::new()
+                .with_mailbox_option(mailbox)
+                .with_states(vec![State::Unread])
+                .with_limit(SLACK_VIEW_LIMIT);
+            let messages = data
+                .load_messages(filter)
+                .await
+                .map_err(ErrorInternalServerError)?;
+            let text = if messages.is_empty() {
+                String::from("No unread messages")
+            } else {
+                messages
+                    .iter()
+                    .map(|message| {
+                        format!("#{} *{}*: {}", message.id, message.mailbox, message.content)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            Ok(SlackResponse::ephemeral(text))
+        }
+        SlackCommand::Help => Ok(SlackResponse::ephemeral(String::from(SLACK_USAGE))),
+    }
+}
+
+// Return a config factory function that can be passed to App::configure to setup all the data,
+// routes and middleware for the app
+// The admin routes are only available when the server has an admin token, and the Slack route is
+// only available when it has a Slack signing secret
+#[allow(clippy::too_many_lines)]
+async fn get_config_factory(
+    db: Arc<Database<SqliteBackend>>,
+    auth_token: Option<&str>,
+    admin_token: Option<&str>,
+    signing_keys: Vec<String>,
+    slack_signing_secret: Option<String>,
+) -> anyhow::Result<impl FnOnce(&mut ServiceConfig) + Clone> {
+    let version = Data::new(DataVersion::new());
+    let signing_keys = Data::new(SigningKeys::new(signing_keys));
+    let slack_signing_secret =
+        slack_signing_secret.map(|secret| Data::new(SlackSigningSecret(secret)));
+    let parse_token = |token: &str| {
+        HeaderValue::from_str(format!("Bearer {token}").as_str()).context("Failed to parse header")
+    };
+    let auth_header = auth_token.map(parse_token).transpose()?;
+    let admin_header = admin_token.map(parse_token).transpose()?;
+    let api_tokens = Data::new(ApiTokens::new(
+        db.backend()
+            .load_api_tokens()
+            .await?
+            .into_iter()
+            .filter_map(|token| {
+                let caller = Caller {
+                    name: token.name,
+                    acl: token.acl,
+                };
+                Some((token.token?, caller))
+            })
+            .collect(),
+    ));
+    let config_factory = move |cfg: &mut ServiceConfig| {
+        let app_data = Data::new(db);
+        cfg.service(
+            web::scope("/admin")
+                .wrap_fn(move |req, srv| {
+                    if admin_header.is_some()
+                        && req.headers().get("Authorization") == admin_header.as_ref()
+                    {
+                        srv.call(req)
+                    } else {
+                        Box::pin(async {
+                            let res = HttpResponse::Forbidden().finish();
+                            Ok(ServiceResponse::new(req.into_parts().0, res))
+                        })
+                    }
+                })
+                .wrap_fn(log_request)
+                .app_data(app_data.clone())
+                .app_data(api_tokens.clone())
+                .configure(configure_admin_routes),
+        );
+        // Slack requests are authorized by their signatures instead of by the server's tokens
+        if let Some(slack_signing_secret) = slack_signing_secret {
+            cfg.service(
+                web::scope("/slack")
+                    .wrap_fn(log_request)
+                    .app_data(app_data.clone())
+                    .app_data(version.clone())
+                    .app_data(slack_signing_secret)
+                    .service(run_slack_command),
+            );
+        }
+        let authorized_tokens = api_tokens.clone();
+        let authorized_signing_keys = signing_keys.clone();
+        cfg.service(
+            web::scope("")
+                .wrap_fn(move |req, srv| {
+                    // Signed requests can add messages to any mailbox
+                    let caller = authorize(
+                        req.headers().get("Authorization"),
+                        auth_header.as_ref(),
+                        &authorized_tokens,
+                        authorized_signing_keys.keys.is_empty(),
+                        req.method(),
+                    )
+                    .or_else(|| {
+                        is_signed_ingestion(&req, &authorized_signing_keys)
+                            .then(|| Caller::unrestricted("(signature)"))
+                    });
+                    if let Some(caller) = caller {
+                        // Handlers restrict the messages that they access to the token's mailboxes
+                        req.extensions_mut().insert(caller);
+                        srv.call(req)
+                    } else {
+                        Box::pin(async {
+                            let res = HttpResponse::Forbidden().finish();
+                            Ok(ServiceResponse::new(req.into_parts().0, res))
+                        })
+                    }
+                })
+                .wrap(
+                    DefaultHeaders::new()
+                        .add((ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+                        .add((VERSION_HEADER, env!("CARGO_PKG_VERSION"))),
+                )
+                // Compress responses for clients that send Accept-Encoding
+                // Compressed request bodies are decompressed automatically
+                .wrap(Compress::default())
+                .wrap_fn(log_request)
+                .app_data(app_data)
+                .app_data(version)
+                .app_data(signing_keys)
+                .app_data(PayloadConfig::new(MAX_BODY_SIZE))
+                .service(read_version)
+                .service(web::scope("/v1").configure(configure_routes))
+                // The unprefixed routes are deprecated aliases of the v1 routes for clients from
+                // before the API was versioned
+                .service(
+                    web::scope("")
+                        .wrap(DefaultHeaders::new().add(("Deprecation", "true")))
+                        .configure(configure_routes),
+                ),
+        );
+    };
+
+    Ok(config_factory)
+}
+
+// Options that control how the server listens for requests
+pub struct ServeOptions {
+    // Accept connections from the local network instead of only from this machine
+    pub expose: bool,
+    pub port: u16,
+    // Require all requests to have an "Authorization: Bearer" header containing this token
+    pub token: Option<String>,
+    // Enable the admin routes and require them to have an "Authorization: Bearer" header
+    // containing this token
+    pub admin_token: Option<String>,
+    // The number of seconds to wait for in-flight requests to finish when shutting down
+    pub shutdown_timeout: u64,
+    // The secrets of producers that can add messages without a token by signing them
+    pub signing_keys: Vec<String>,
+    // The signing secret of the Slack app whose slash commands the server accepts
+    pub slack_signing_secret: Option<String>,
+    // The push notification services that new high-priority messages are forwarded to
+    pub forwards: Vec<Forward>,
+    // The chat channels that new messages are mirrored to
+    pub bridges: Bridges,
+}
+
+// Run the server until it receives SIGTERM or SIGINT, then close the database
+// On shutdown, the server stops accepting connections and waits for in-flight requests to finish
+pub async fn serve(db: Database<SqliteBackend>, options: ServeOptions) -> anyhow::Result<()> {
+    let db = Arc::new(db);
+    let config_factory = get_config_factory(
+        Arc::clone(&db),
+        options.token.as_deref(),
+        options.admin_token.as_deref(),
+        options.signing_keys,
+        options.slack_signing_secret,
+    )
+    .await?;
+    // Forward messages in the background for as long as the server runs
+    let forwarder = if options.forwards.is_empty() && options.bridges.is_empty() {
+        None
+    } else {
+        let forwarder = Forwarder::new(options.forwards, options.bridges)?;
+        let db = Arc::clone(&db);
+        Some(actix_web::rt::spawn(async move {
+            if let Err(err) = forwarder.run(db.backend()).await {
+                eprintln!("Stopped forwarding messages: {err:#}");
+            }
+        }))
+    };
+    let host = if options.expose {
+        "0.0.0.0"
+    } else {
+        "127.0.0.1"
+    };
+    HttpServer::new(move || App::new().configure(config_factory.clone()))
+        .shutdown_timeout(options.shutdown_timeout)
+        .bind((host, options.port))?
+        .run()
+        .await?;
+
+    if let Some(forwarder) = forwarder {
+        forwarder.abort();
+    }
+    db.backend().close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header;
+    use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+    use database::{HttpBackend, QuotaPolicy, TransitionPolicy};
+
+    async fn make_config_factory() -> anyhow::Result<impl FnOnce(&mut ServiceConfig)> {
+        get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await?)),
+            None,
+            Some("admin"),
+            vec![],
+            None,
+        )
+        .await
+    }
+
+    #[actix_web::test]
+    async fn test_missing_authorization_header() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            Some("token"),
+            None,
+            vec![],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_authorization_header() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            Some("token"),
+            None,
+            vec![],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((header::AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_extraneous_authorization_header() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((header::AUTHORIZATION, "Bearer token"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_signed_messages() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            Some("token"),
+            None,
+            vec![String::from("secret")],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let body = r#"{ "mailbox": "ci", "content": "Build passed" }"#;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let post = |key: Option<&str>, timestamp: u64| {
+            let mut req = TestRequest::post()
+                .uri("/v1/messages")
+                .append_header(header::ContentType::json())
+                .set_payload(body);
+            if let Some(key) = key {
+                let timestamp = timestamp.to_string();
+                let signature = database::sign_body(key, &timestamp, body.as_bytes()).unwrap();
+                req = req
+                    .append_header((SIGNATURE_TIMESTAMP_HEADER, timestamp))
+                    .append_header((SIGNATURE_HEADER, signature));
+            }
+            req.to_request()
+        };
+        let res = call_service(&service, post(Some("secret"), now)).await;
+        assert!(res.status().is_success());
+        // Recent signed requests can't be replayed either
+        let res = call_service(&service, post(Some("secret"), now)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = call_service(&service, post(Some("secret"), now - 1)).await;
+        assert!(res.status().is_success());
+        let res = call_service(&service, post(Some("wrong"), now)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = call_service(&service, post(None, now)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // Old signed requests can't be replayed
+        let res = call_service(&service, post(Some("secret"), now - 600)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let req = TestRequest::post()
+            .uri("/v1/messages")
+            .append_header(header::ContentType::json())
+            .append_header((
+                SIGNATURE_HEADER,
+                database::sign_body("secret", "", body.as_bytes()).unwrap(),
+            ))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Signatures only authorize adding messages and negotiating the API version
+        let req = TestRequest::get().uri("/version").to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+        let req = TestRequest::get()
+            .uri("/v1/messages")
+            .append_header((SIGNATURE_HEADER, "sha256=00"))
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_signed_messages_without_tokens() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            None,
+            None,
+            vec![String::from("secret")],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        // Servers without tokens still require writes to be signed when they have signing keys
+        let req = TestRequest::post()
+            .uri("/v1/messages")
+            .set_json(serde_json::json!({ "mailbox": "ci", "content": "Build passed" }))
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        let req = TestRequest::delete().uri("/v1/messages").to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        let req = TestRequest::get().uri("/v1/messages").to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_signed_messages_through_http_backend() {
+        let db = Arc::new(Database::new(SqliteBackend::new_test().await.unwrap()));
+        db.set_mailbox_config(
+            "ci".try_into().unwrap(),
+            MailboxConfig {
+                default_state: Some(State::Read),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let config_factory = get_config_factory(
+            Arc::clone(&db),
+            Some("token"),
+            None,
+            vec![String::from("secret")],
+            None,
+        )
+        .await
+        .unwrap();
+        let server = HttpServer::new(move || App::new().configure(config_factory.clone()))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        // A producer with only a signing key can add messages, and the server applies the
+        // mailbox's default state because the producer can't read the mailbox metadata
+        let producer = Database::new(
+            HttpBackend::new(url, None)
+                .unwrap()
+                .with_signing_key(Some(String::from("secret"))),
+        );
+        let messages = producer
+            .add_messages(vec![NewMessage {
+                mailbox: "ci".try_into().unwrap(),
+                content: String::from("Build passed"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: None,
+                kind: None,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].state, State::Read);
+        assert!(producer.load_messages(Filter::new()).await.is_err());
+
+        handle.stop(true).await;
+    }
+
+    #[test]
+    fn test_parse_slack_command() {
+        assert_eq!(
+            parse_slack_command(r#"add ci "deploy done""#),
+            Ok(SlackCommand::Add {
+                mailbox: "ci".try_into().unwrap(),
+                content: String::from("deploy done"),
+            })
+        );
+        assert_eq!(
+            parse_slack_command("add ci/deploys  deploy done "),
+            Ok(SlackCommand::Add {
+                mailbox: "ci/deploys".try_into().unwrap(),
+                content: String::from("deploy done"),
+            })
+        );
+        assert_eq!(
+            parse_slack_command("view ci"),
+            Ok(SlackCommand::View {
+                mailbox: Some("ci".try_into().unwrap()),
+            })
+        );
+        assert_eq!(
+            parse_slack_command("view"),
+            Ok(SlackCommand::View { mailbox: None })
+        );
+        assert_eq!(parse_slack_command(""), Ok(SlackCommand::Help));
+        assert!(parse_slack_command("add ci").is_err());
+        assert!(parse_slack_command(r#"add ci """#).is_err());
+        assert!(parse_slack_command("delete ci").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_slack_commands() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            Some("token"),
+            None,
+            vec![],
+            Some(String::from("slack-secret")),
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string();
+        let post = |text: &str, secret: &str, timestamp: &str| {
+            let body = serde_urlencoded::to_string([
+                ("command", "/mailbox"),
+                ("text", text),
+                ("user_name", "ada"),
+            ])
+            .unwrap();
+            let signature =
+                database::sign_slack_request(secret, timestamp, body.as_bytes()).unwrap();
+            TestRequest::post()
+                .uri("/slack/commands")
+                .append_header(header::ContentType::form_url_encoded())
+                .append_header((SLACK_TIMESTAMP_HEADER, timestamp))
+                .append_header((SLACK_SIGNATURE_HEADER, signature))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let res = call_service(
+            &service,
+            post(r#"add ci "deploy done""#, "slack-secret", &timestamp),
+        )
+        .await;
+        let response: serde_json::Value = read_body_json(res).await;
+        assert_eq!(
+            response,
+            serde_json::json!({ "response_type": "in_channel", "text": "Added message #1 to ci" })
+        );
+        let res = call_service(&service, post("view ci", "slack-secret", &timestamp)).await;
+        let response: serde_json::Value = read_body_json(res).await;
+        assert_eq!(
+            response,
+            serde_json::json!({ "response_type": "ephemeral", "text": "#1 *ci*: deploy done" })
+        );
+
+        let res = call_service(&service, post("view", "wrong", &timestamp)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // Old requests are rejected even if they are signed correctly
+        let res = call_service(&service, post("view", "slack-secret", "1531420618")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_valid_authorization_header() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            Some("token"),
+            None,
+            vec![],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((header::AUTHORIZATION, "Bearer token"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_cors_header() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+    }
+
+    #[actix_web::test]
+    async fn test_filter_ids() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages?ids=1").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/messages?ids=1,2,3").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/messages?ids=1,2,a").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_filter_mailbox() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages?mailbox=foo").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_sort() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages?sort=mailbox&reverse=true")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/messages?sort=foo").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_page() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages?sort=timestamp&offset=100&limit=50")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/messages?limit=-1").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+
+        // Pagination alone doesn't restrict which messages are deleted
+        let req = TestRequest::delete().uri("/messages?limit=1").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_filter_states() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages?states=unread")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/messages?states=read,archived")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/messages?states=unread,foo")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_claim_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{ "mailbox": "jobs", "content": "job1" }, { "mailbox": "jobs", "content": "job2" }]"#,
+            )
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+
+        let claim = || {
+            TestRequest::post()
+                .uri("/messages/claim?mailbox=jobs&limit=1")
+                .append_header(header::ContentType::json())
+                .set_payload(r#"{ "worker": "worker1", "lease_seconds": 60 }"#)
+                .to_request()
+        };
+        let messages: Vec<Message> = read_body_json(call_service(&service, claim()).await).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].state, State::Read);
+        let messages: Vec<Message> = read_body_json(call_service(&service, claim()).await).await;
+        assert_eq!(messages.len(), 1);
+        let messages: Vec<Message> = read_body_json(call_service(&service, claim()).await).await;
+        assert!(messages.is_empty());
+
+        let req = TestRequest::post()
+            .uri("/messages/claim")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{ "worker": "", "lease_seconds": 60 }"#)
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_nack_message() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{ "mailbox": "jobs", "content": "job" }"#)
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        let id = messages[0].id;
+
+        let nack = |id: Id| {
+            TestRequest::post()
+                .uri(&format!("/messages/{id}/nack"))
+                .append_header(header::ContentType::json())
+                .set_payload(
+                    r#"{ "reason": "timeout", "dead_letter": { "mailbox": "failed", "max_attempts": 1 } }"#,
+                )
+                .to_request()
+        };
+        assert_eq!(
+            call_service(&service, nack(id)).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            call_service(&service, nack(100)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let req = TestRequest::post()
+            .uri("/messages/claim?mailbox=jobs")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{ "worker": "worker1", "lease_seconds": 60 }"#)
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+        let message: Message = read_body_json(call_service(&service, nack(id)).await).await;
+        assert_eq!(message.mailbox.as_ref(), "failed");
+        assert_eq!(message.state, State::Unread);
+    }
+
+    #[actix_web::test]
+    async fn test_remind_message() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{ "mailbox": "todo", "content": "follow up" }"#)
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        let id = messages[0].id;
+
+        let remind = |id: Id| {
+            TestRequest::post()
+                .uri(&format!("/messages/{id}/remind"))
+                .append_header(header::ContentType::json())
+                .set_payload(r#"{ "delay_seconds": 0 }"#)
+                .to_request()
+        };
+        assert_eq!(
+            call_service(&service, remind(100)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let message: Message = read_body_json(call_service(&service, remind(id)).await).await;
+        assert_eq!(message.state, State::Read);
+
+        // The reminder is already due, so reading the messages wakes it
+        let req = TestRequest::get().uri("/messages").to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages[0].state, State::Unread);
+    }
+
+    #[actix_web::test]
+    async fn test_link_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"[{ "mailbox": "alerts", "content": "down" }, { "mailbox": "alerts", "content": "fixed" }]"#)
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        let (first, second) = (messages[0].id, messages[1].id);
+
+        let link = |id: Id, related_id: Id| {
+            TestRequest::post()
+                .uri(&format!("/messages/{id}/links"))
+                .append_header(header::ContentType::json())
+                .set_payload(format!(r#"{{ "related_id": {related_id} }}"#))
+                .to_request()
+        };
+        assert_eq!(
+            call_service(&service, link(first, 100)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            call_service(&service, link(first, first)).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let messages: Vec<Message> =
+            read_body_json(call_service(&service, link(first, second)).await).await;
+        assert_eq!(messages.len(), 2);
+
+        let req = TestRequest::get()
+            .uri(&format!("/messages?ids={first}"))
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages[0].related_ids, vec![second]);
+    }
+
+    #[actix_web::test]
+    async fn test_heartbeats() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let set = |interval_seconds: u64| {
+            TestRequest::put()
+                .uri("/heartbeats?mailbox=cron%2Fbackup")
+                .append_header(header::ContentType::json())
+                .set_payload(format!(r#"{{ "interval_seconds": {interval_seconds} }}"#))
+                .to_request()
+        };
+        assert_eq!(
+            call_service(&service, set(30)).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let heartbeat: Heartbeat = read_body_json(call_service(&service, set(3600)).await).await;
+        assert_eq!(heartbeat.interval_seconds, 3600);
+
+        let req = TestRequest::get().uri("/heartbeats").to_request();
+        let heartbeats: serde_json::Value = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(heartbeats[0]["mailbox"], "cron/backup");
+        assert_eq!(heartbeats[0]["overdue"], false);
+        assert_eq!(heartbeats[0]["status"], "no messages yet (expected hourly)");
+
+        let req = TestRequest::delete()
+            .uri("/heartbeats?mailbox=cron%2Fbackup")
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let req = TestRequest::get().uri("/heartbeats").to_request();
+        let heartbeats: Vec<Heartbeat> = read_body_json(call_service(&service, req).await).await;
+        assert!(heartbeats.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_mailbox_configs() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let set = |payload: &str| {
+            TestRequest::put()
+                .uri("/mailboxes/cron/backup")
+                .append_header(header::ContentType::json())
+                .set_payload(payload.to_owned())
+                .to_request()
+        };
+        assert_eq!(
+            call_service(&service, set(r#"{ "quota": 0 }"#))
+                .await
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+        let config: MailboxConfig = read_body_json(
+            call_service(
+                &service,
+                set(r#"{ "description": "Nightly backups", "heartbeat_seconds": 3600 }"#),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(config.description.as_deref(), Some("Nightly backups"));
+
+        let req = TestRequest::get()
+            .uri("/mailboxes/cron/backup")
+            .to_request();
+        let loaded: MailboxConfig = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(loaded, config);
+        let req = TestRequest::get().uri("/mailbox-configs").to_request();
+        let configs: BTreeMap<Mailbox, MailboxConfig> =
+            read_body_json(call_service(&service, req).await).await;
+        assert_eq!(
+            configs,
+            BTreeMap::from([("cron/backup".try_into().unwrap(), config)])
+        );
+
+        // The heartbeat interval is part of the metadata
+        let req = TestRequest::get().uri("/heartbeats").to_request();
+        let heartbeats: Vec<Heartbeat> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(heartbeats[0].interval_seconds, 3600);
+
+        // Mailboxes without metadata have an empty config
+        let req = TestRequest::get().uri("/mailboxes/other").to_request();
+        let loaded: MailboxConfig = read_body_json(call_service(&service, req).await).await;
+        assert!(loaded.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_filter_multiple() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages?ids=1,2,3&mailbox=foo&states=unread,read")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_delete_no_filter() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::delete().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_mailboxes() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/mailboxes").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_create_single_message() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"{
+  "mailbox": "my-script",
+  "content": "Hello, world!",
+  "state": "read"
+}"#,
+            )
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_create_multiple_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{
+  "mailbox": "my-script",
+  "content": "Hello, world!",
+  "state": "archived"
+}, {
+  "mailbox": "my-script",
+  "content": "Hello, universe!"
+}]"#,
+            )
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_upsert_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let make_request = |uri: &str, content: &str| {
+            TestRequest::post()
+                .uri(uri)
+                .append_header(header::ContentType::json())
+                .set_payload(format!(
+                    r#"{{"mailbox": "my-job", "content": "{content}", "external_id": "run-1"}}"#
+                ))
+                .to_request()
+        };
+
+        let res = call_service(&service, make_request("/messages", "Running")).await;
+        assert!(res.status().is_success());
+
+        // Adding a duplicate external id fails, but upserting it succeeds
+        let res = call_service(&service, make_request("/messages", "Done")).await;
+        assert!(res.status().is_server_error());
+        let res = call_service(&service, make_request("/messages?upsert=true", "Done")).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/messages?mailbox=my-job")
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Done");
+    }
+
+    #[actix_web::test]
+    async fn test_delete_mailbox() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{"mailbox": "project/a", "content": "a"}, {"mailbox": "project/a/b", "content": "b", "state": "archived"}, {"mailbox": "project/ab", "content": "ab"}]"#,
+            )
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::delete()
+            .uri("/mailboxes/project/a")
+            .to_request();
+        let deleted: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(deleted.len(), 2);
+
+        let req = TestRequest::get()
+            .uri("/messages?mailbox=project")
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "ab");
+
+        let req = TestRequest::delete()
+            .uri("/mailboxes/project/")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_admin_disabled() {
+        let config_factory = get_config_factory(
+            Arc::new(Database::new(SqliteBackend::new_test().await.unwrap())),
+            None,
+            None,
+            vec![],
+            None,
+        )
+        .await
+        .unwrap();
+        let app = App::new().configure(config_factory);
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/admin/stats")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_stats() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/admin/stats").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 403);
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "mailbox", "content": "a"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/admin/stats")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let stats: AdminStats = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(stats.message_count, 1);
+        assert_eq!(stats.mailbox_count, 1);
+
+        let req = TestRequest::post()
+            .uri("/admin/compact")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_admin_tokens() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/admin/tokens")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"name": "laptop"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 201);
+        let token: ApiToken = read_body_json(res).await;
+        let secret = token.token.unwrap();
+
+        let req = TestRequest::post()
+            .uri("/admin/tokens")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"name": "laptop"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 409);
+
+        // Once a token exists, requests must use it
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 403);
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((header::AUTHORIZATION, format!("Bearer {secret}")))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/admin/tokens")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let tokens: Vec<ApiToken> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].name, "laptop");
+        assert_eq!(tokens[0].token, None);
+
+        let req = TestRequest::delete()
+            .uri("/admin/tokens/laptop")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::delete()
+            .uri("/admin/tokens/laptop")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 404);
+
+        // Without any tokens, the server is open again
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_audit_log() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/admin/tokens")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"name": "laptop"}"#)
+            .to_request();
+        let token: ApiToken = read_body_json(call_service(&service, req).await).await;
+        let auth = (
+            header::AUTHORIZATION,
+            format!("Bearer {}", token.token.unwrap()),
+        );
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(auth.clone())
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{"mailbox": "alerts", "content": "a"}, {"mailbox": "alerts", "content": "b"}]"#,
+            )
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+        let req = TestRequest::delete()
+            .uri("/mailboxes/alerts")
+            .append_header(auth)
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/admin/audit?limit=2")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let entries: Vec<AuditEntry> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.actor.as_str(),
+                    entry.operation.as_str(),
+                    entry.target.as_str(),
+                    entry.affected
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("laptop", "delete_mailbox", "mailbox=alerts", 2),
+                ("laptop", "create_messages", "ids=2,1", 2),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_forward_deliveries() {
+        let db = Database::new(SqliteBackend::new_test().await.unwrap());
+        let message = db
+            .add_messages(vec![NewMessage {
+                mailbox: "alerts".try_into().unwrap(),
+                content: String::from("Disk full"),
+                state: None,
+                tags: vec![],
+                timestamp: None,
+                external_id: None,
+                attachments: vec![],
+                priority: Some(1),
+                kind: None,
+            }])
+            .await
+            .unwrap()
+            .remove(0);
+        db.backend()
+            .enqueue_forward_deliveries(&[(message.id, "phone")])
+            .await
+            .unwrap();
+        let app = App::new().configure(
+            get_config_factory(Arc::new(db), None, Some("admin"), vec![], None)
+                .await
+                .unwrap(),
+        );
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/admin/forwards")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .to_request();
+        let deliveries: Vec<ForwardDelivery> =
+            read_body_json(call_service(&service, req).await).await;
+        assert_eq!(
+            deliveries
+                .iter()
+                .map(|delivery| (delivery.message_id, delivery.forward.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(message.id, "phone")]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_token_acl() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{"mailbox": "ci/build", "content": "a"}, {"mailbox": "personal", "content": "b"}]"#,
+            )
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+
+        let req = TestRequest::post()
+            .uri("/admin/tokens")
+            .append_header((header::AUTHORIZATION, "Bearer admin"))
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"name": "ci", "read": ["ci/*"], "write": ["ci/*"]}"#)
+            .to_request();
+        let token: ApiToken = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(token.acl.write, Some(vec![String::from("ci/*")]));
+        let auth = (
+            header::AUTHORIZATION,
+            format!("Bearer {}", token.token.unwrap()),
+        );
+
+        // The token can only add messages to its mailboxes
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(auth.clone())
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "ci/test", "content": "c"}"#)
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(auth.clone())
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "personal", "content": "d"}"#)
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        // Reads and writes only see the token's mailboxes
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header(auth.clone())
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages.len(), 2);
+        let req = TestRequest::get()
+            .uri("/changes")
+            .append_header(auth.clone())
+            .to_request();
+        let changes: Changes = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(changes.messages.len(), 2);
+        let req = TestRequest::delete()
+            .uri("/messages?states=unread")
+            .append_header(auth.clone())
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(messages.len(), 2);
+        let req = TestRequest::post()
+            .uri("/messages/2/views")
+            .append_header(auth)
+            .to_request();
+        assert_eq!(call_service(&service, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_create_invalid_message() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "mailbox", "content": ""}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "ci/*", "content": "a"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+        let body = read_body(res).await;
+        assert!(String::from_utf8_lossy(&body).contains("mailbox must not contain *"));
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"[{"mailbox": "ci//build", "content": "a"}]"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 400);
+        let body = read_body(res).await;
+        assert!(String::from_utf8_lossy(&body).contains("mailbox must not contain //"));
+
+        let req = TestRequest::get()
+            .uri("/messages?mailbox=ci//build")
+            .to_request();
+        assert_eq!(call_service(&service, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_attachments() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"{"mailbox": "mailbox", "content": "Report", "attachments": [{"kind": "blob", "name": "report.txt", "data": "cmVwb3J0"}]}"#,
+            )
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/messages/{}/attachments", messages[0].id))
+            .to_request();
+        let attachments: Vec<Attachment> = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(
+            attachments,
+            vec![Attachment::Blob {
+                name: String::from("report.txt"),
+                data: b"report".to_vec(),
+            }]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_record_view() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "mailbox", "content": "Hello"}"#)
+            .to_request();
+        let messages: Vec<Message> = read_body_json(call_service(&service, req).await).await;
+
+        let req = TestRequest::post()
+            .uri(&format!("/messages/{}/views", messages[0].id))
+            .to_request();
+        let message: Message = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(message.view_count, 1);
+
+        let req = TestRequest::post().uri("/messages/0/views").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_version() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/version").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(
+            res.headers().get(VERSION_HEADER).unwrap(),
+            env!("CARGO_PKG_VERSION")
+        );
+        let body: VersionResponse = read_body_json(res).await;
+        assert_eq!(body.api_versions, vec![1]);
+    }
+
+    #[test]
+    fn test_get_request_id() {
+        assert_eq!(
+            get_request_id(Some(&HeaderValue::from_static("0123abcd-ef"))),
+            "0123abcd-ef"
+        );
+
+        // Missing and unsafe request ids are replaced with generated ones
+        assert_eq!(get_request_id(None).len(), 16);
+        assert_eq!(
+            get_request_id(Some(&HeaderValue::from_static("a b\tc"))).len(),
+            16
+        );
+        assert_eq!(
+            get_request_id(Some(&HeaderValue::from_str(&"a".repeat(65)).unwrap())).len(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_format_request_log() {
+        assert_eq!(
+            format_request_log(
+                "0123abcd",
+                &Method::PUT,
+                "/v1/messages",
+                StatusCode::BAD_REQUEST,
+                Duration::from_millis(12),
+            ),
+            "[0123abcd] PUT /v1/messages 400 12ms"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_request_id() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/v1/messages")
+            .append_header((REQUEST_ID_HEADER, "0123abcd"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "0123abcd");
+
+        // Requests without an id and rejected requests are also identified
+        let req = TestRequest::get().uri("/admin/stats").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().contains_key(REQUEST_ID_HEADER));
+    }
+
+    #[actix_web::test]
+    async fn test_versioned_routes() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/v1/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "mailbox", "content": "a"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        assert!(!res.headers().contains_key("Deprecation"));
+
+        let req = TestRequest::get().uri("/v1/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(!res.headers().contains_key("Deprecation"));
+        let messages: Vec<Message> = read_body_json(res).await;
+        assert_eq!(messages.len(), 1);
+
+        // The unprefixed routes still work but are marked as deprecated
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.headers().get("Deprecation").unwrap(), "true");
+        let messages: Vec<Message> = read_body_json(res).await;
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_changes() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/changes?since=0").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/changes").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/changes?since=a").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_stats() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/stats/day?mailbox=my-script&since=2024-01-01T00:00:00")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/stats/week").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::get().uri("/stats/year").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_client_error());
+    }
+
+    #[actix_web::test]
+    async fn test_compressed_response() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((header::ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[actix_web::test]
+    async fn test_etag() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::get().uri("/messages").to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        let etag = res.headers().get(ETAG).unwrap().clone();
+
+        // Unchanged messages aren't sent again
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        // Changing messages changes the ETag
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "my-script", "content": "Hello, world!"}"#)
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+        let req = TestRequest::get()
+            .uri("/messages")
+            .append_header((IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        assert_ne!(res.headers().get(ETAG).unwrap(), etag);
+    }
+
+    #[actix_web::test]
+    async fn test_create_compressed_messages() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(br#"[{"mailbox": "my-script", "content": "Hello, world!"}]"#)
+            .unwrap();
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .append_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(encoder.finish().unwrap())
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_update_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::put()
+            .uri("/messages?states=unread")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"new_state": "read"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_update_messages_custom_state() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "triage", "content": "Investigate"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+
+        let req = TestRequest::put()
+            .uri("/messages?states=unread")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"new_state": "read", "custom_state": "in-progress"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+        let messages: Vec<Message> = read_body_json(res).await;
+        assert!(!messages.is_empty());
+        assert!(messages.iter().all(|message| message.state == State::Read
+            && message.custom_state.as_deref() == Some("in-progress")));
+
+        let req = TestRequest::put()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"new_state": "read", "custom_state": "In Progress"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_update_messages_forbidden_transition() {
+        let db = Database::new(SqliteBackend::new_test().await.unwrap())
+            .with_transition_policy(TransitionPolicy::new().forbid(State::Archived, State::Unread));
+        db.add_messages(vec![NewMessage {
+            mailbox: "mailbox".try_into().unwrap(),
+            content: String::from("message"),
+            state: Some(State::Archived),
+            tags: vec![],
+            timestamp: None,
+            external_id: None,
+            attachments: vec![],
+            priority: None,
+            kind: None,
+        }])
+        .await
+        .unwrap();
+        let config_factory = get_config_factory(Arc::new(db), None, None, vec![], None)
+            .await
+            .unwrap();
+        let service = init_service(App::new().configure(config_factory)).await;
+
+        let req = TestRequest::put()
+            .uri("/messages?states=archived")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"new_state": "unread"}"#)
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_quotas() {
+        let db = Database::new(SqliteBackend::new_test().await.unwrap()).with_quota_policy(
+            QuotaPolicy::new().with_namespace_limit("ci".try_into().unwrap(), 2),
+        );
+        let config_factory = get_config_factory(Arc::new(db), None, None, vec![], None)
+            .await
+            .unwrap();
+        let service = init_service(App::new().configure(config_factory)).await;
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(
+                r#"[{"mailbox": "ci/build", "content": "a"}, {"mailbox": "ci/test", "content": "b"}]"#,
+            )
+            .to_request();
+        assert!(call_service(&service, req).await.status().is_success());
+
+        let req = TestRequest::post()
+            .uri("/messages")
+            .append_header(header::ContentType::json())
+            .set_payload(r#"{"mailbox": "ci/build", "content": "c"}"#)
+            .to_request();
+        assert_eq!(
+            call_service(&service, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let req = TestRequest::get().uri("/mailboxes").to_request();
+        let mailboxes: serde_json::Value = read_body_json(call_service(&service, req).await).await;
+        assert_eq!(
+            mailboxes[0]["quota"],
+            serde_json::json!({"mailbox": "ci", "limit": 2, "used": 2})
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_delete_messages() {
+        let app = App::new().configure(make_config_factory().await.unwrap());
+        let service = init_service(app).await;
+
+        let req = TestRequest::delete()
+            .uri("/messages?states=unread")
+            .to_request();
+        let res = call_service(&service, req).await;
+        assert!(res.status().is_success());
+    }
+}
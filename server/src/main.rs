@@ -2,390 +2,116 @@
 
 mod cli;
 
-use actix_web::dev::{Service, ServiceResponse};
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
-use actix_web::http::header::{HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN};
-use actix_web::middleware::DefaultHeaders;
-use actix_web::web::{self, Data, Json, Query, ServiceConfig};
-use actix_web::{delete, get, post, put, App, HttpResponse, HttpServer, Result};
 use anyhow::Context;
 use clap::Parser;
-use cli::Cli;
-use database::{Database, Filter, MailboxInfo, Message, NewMessage, SqliteBackend, State};
+use cli::{Cli, MailboxCharsetArg, QuotaActionArg};
+use database::{
+    Bridges, Database, Forward, Limits, Mailbox, MailboxCharset, QuotaAction, QuotaPolicy,
+    SqliteBackend,
+};
+use mailbox_server::{serve, ServeOptions};
 use serde::Deserialize;
-use std::sync::Arc;
-
-type AppData = Arc<Database<SqliteBackend>>;
-
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum CreateMessage {
-    Message(NewMessage),
-    Messages(Vec<NewMessage>),
-}
-
-#[get("/mailboxes")]
-async fn read_mailboxes(
-    data: Data<AppData>,
-    filter: Query<Filter>,
-) -> Result<Json<Vec<MailboxInfo>>> {
-    let mailboxes = data
-        .load_mailboxes(filter.into_inner())
-        .await
-        .map_err(ErrorInternalServerError)?;
-    Ok(Json(mailboxes))
-}
-
-#[get("/messages")]
-async fn read_messages(data: Data<AppData>, filter: Query<Filter>) -> Result<Json<Vec<Message>>> {
-    let messages = data
-        .load_messages(filter.into_inner())
-        .await
-        .map_err(ErrorInternalServerError)?;
-    Ok(Json(messages))
-}
-
-#[post("/messages")]
-async fn create_messages(
-    data: Data<AppData>,
-    messages: Json<CreateMessage>,
-) -> Result<Json<Vec<Message>>> {
-    let new_messages = match messages.into_inner() {
-        CreateMessage::Message(message) => vec![message],
-        CreateMessage::Messages(messages) => messages,
-    };
-    let messages = data
-        .add_messages(new_messages)
-        .await
-        .map_err(ErrorInternalServerError)?;
-    Ok(Json(messages))
-}
+use std::path::Path;
 
+// The contents of the --forward-config file
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
-struct UpdateMessages {
-    new_state: State,
+struct ForwardConfig {
+    #[serde(default)]
+    forwards: Vec<Forward>,
+    #[serde(default)]
+    bridges: Bridges,
 }
 
-#[put("/messages")]
-async fn update_messages(
-    data: Data<AppData>,
-    filter: Query<Filter>,
-    new_state: Json<UpdateMessages>,
-) -> Result<Json<Vec<Message>>> {
-    let messages = data
-        .change_state(filter.into_inner(), new_state.into_inner().new_state)
-        .await
-        .map_err(ErrorInternalServerError)?;
-    Ok(Json(messages))
+// Load the forwards and bridges from a --forward-config file
+fn load_forward_config(path: &Path) -> anyhow::Result<ForwardConfig> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read forward config file {}", path.display()))?;
+    let config: ForwardConfig = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse forward config file {}", path.display()))?;
+    Ok(config)
 }
 
-#[delete("/messages")]
-async fn delete_messages(data: Data<AppData>, filter: Query<Filter>) -> Result<Json<Vec<Message>>> {
-    if filter.matches_all() {
-        return Err(ErrorBadRequest("Filter is required"));
-    }
-    let messages = data
-        .delete_messages(filter.into_inner())
-        .await
-        .map_err(ErrorInternalServerError)?;
-    Ok(Json(messages))
-}
-
-// Return a config factory function that can be passed to App::configure to setup all the data,
-// routes and middleware for the app
-fn get_config_factory(
-    backend: SqliteBackend,
-    auth_token: Option<&str>,
-) -> anyhow::Result<impl FnOnce(&mut ServiceConfig) + Clone> {
-    let db = Arc::new(Database::new(backend));
-    let auth_header = auth_token
-        .map(|token| {
-            HeaderValue::from_str(format!("Bearer {token}").as_str())
-                .context("Failed to parse header")
-        })
-        .transpose()?;
-    let config_factory = |cfg: &mut ServiceConfig| {
-        let app_data = Data::new(db);
-        cfg.service(
-            web::scope("")
-                .wrap_fn(move |req, srv| {
-                    if auth_header.is_none()
-                        || req.headers().get("Authorization") == auth_header.as_ref()
-                    {
-                        srv.call(req)
-                    } else {
-                        Box::pin(async {
-                            let res = HttpResponse::Forbidden().finish();
-                            Ok(ServiceResponse::new(req.into_parts().0, res))
-                        })
-                    }
-                })
-                .wrap(DefaultHeaders::new().add((ACCESS_CONTROL_ALLOW_ORIGIN, "*")))
-                .app_data(app_data)
-                .service(read_mailboxes)
-                .service(read_messages)
-                .service(create_messages)
-                .service(update_messages)
-                .service(delete_messages),
-        );
-    };
-
-    Ok(config_factory)
+// Parse a namespace quota like "ci=1000"
+fn parse_namespace_quota(value: &str) -> anyhow::Result<(Mailbox, usize)> {
+    let (mailbox, max) = value
+        .rsplit_once('=')
+        .with_context(|| format!("Namespace quota \"{value}\" must look like MAILBOX=MAX"))?;
+    Ok((
+        mailbox.parse()?,
+        max.parse()
+            .with_context(|| format!("Namespace quota \"{value}\" must have a numeric maximum"))?,
+    ))
 }
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    let backend = SqliteBackend::new(cli.db_file).await?;
-    let config_factory = get_config_factory(backend, cli.token.as_deref())?;
-    HttpServer::new(move || App::new().configure(config_factory.clone()))
-        .bind((if cli.expose { "0.0.0.0" } else { "127.0.0.1" }, cli.port))?
-        .run()
-        .await?;
-
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use actix_web::http::header;
-    use actix_web::test::{call_service, init_service, TestRequest};
-    use actix_web::App;
-
-    use super::*;
-
-    async fn make_config_factory() -> anyhow::Result<impl FnOnce(&mut ServiceConfig)> {
-        get_config_factory(SqliteBackend::new_test().await?, None)
-    }
-
-    #[actix_web::test]
-    async fn test_missing_authorization_header() {
-        let config_factory =
-            get_config_factory(SqliteBackend::new_test().await.unwrap(), Some("token")).unwrap();
-        let app = App::new().configure(config_factory);
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/messages").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_client_error());
-    }
-
-    #[actix_web::test]
-    async fn test_invalid_authorization_header() {
-        let config_factory =
-            get_config_factory(SqliteBackend::new_test().await.unwrap(), Some("token")).unwrap();
-        let app = App::new().configure(config_factory);
-        let service = init_service(app).await;
-
-        let req = TestRequest::get()
-            .uri("/messages")
-            .append_header((header::AUTHORIZATION, "Bearer invalid"))
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_client_error());
-    }
-
-    #[actix_web::test]
-    async fn test_extraneous_authorization_header() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get()
-            .uri("/messages")
-            .append_header((header::AUTHORIZATION, "Bearer token"))
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_valid_authorization_header() {
-        let config_factory =
-            get_config_factory(SqliteBackend::new_test().await.unwrap(), Some("token")).unwrap();
-        let app = App::new().configure(config_factory);
-        let service = init_service(app).await;
-
-        let req = TestRequest::get()
-            .uri("/messages")
-            .append_header((header::AUTHORIZATION, "Bearer token"))
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_cors_header() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/messages").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-        assert_eq!(res.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
-    }
-
-    #[actix_web::test]
-    async fn test_filter_ids() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/messages?ids=1").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-
-        let req = TestRequest::get().uri("/messages?ids=1,2,3").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-
-        let req = TestRequest::get().uri("/messages?ids=1,2,a").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_client_error());
-    }
-
-    #[actix_web::test]
-    async fn test_filter_mailbox() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/messages?mailbox=foo").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_filter_states() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get()
-            .uri("/messages?states=unread")
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-
-        let req = TestRequest::get()
-            .uri("/messages?states=read,archived")
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-
-        let req = TestRequest::get()
-            .uri("/messages?states=unread,foo")
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_client_error());
-    }
-
-    #[actix_web::test]
-    async fn test_filter_multiple() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get()
-            .uri("/messages?ids=1,2,3&mailbox=foo&states=unread,read")
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_delete_no_filter() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::delete().uri("/messages").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_client_error());
-    }
-
-    #[actix_web::test]
-    async fn test_messages() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/messages").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_mailboxes() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::get().uri("/mailboxes").to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_create_single_message() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::post()
-            .uri("/messages")
-            .append_header(header::ContentType::json())
-            .set_payload(
-                r#"{
-  "mailbox": "my-script",
-  "content": "Hello, world!",
-  "state": "read"
-}"#,
-            )
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_create_multiple_messages() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::post()
-            .uri("/messages")
-            .append_header(header::ContentType::json())
-            .set_payload(
-                r#"[{
-  "mailbox": "my-script",
-  "content": "Hello, world!",
-  "state": "archived"
-}, {
-  "mailbox": "my-script",
-  "content": "Hello, universe!"
-}]"#,
-            )
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_update_messages() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::put()
-            .uri("/messages?states=unread")
-            .append_header(header::ContentType::json())
-            .set_payload(r#"{"new_state": "read"}"#)
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
-
-    #[actix_web::test]
-    async fn test_delete_messages() {
-        let app = App::new().configure(make_config_factory().await.unwrap());
-        let service = init_service(app).await;
-
-        let req = TestRequest::delete()
-            .uri("/messages?states=unread")
-            .to_request();
-        let res = call_service(&service, req).await;
-        assert!(res.status().is_success());
-    }
+    let default_limits = Limits::default();
+    let limits = Limits {
+        max_content_length: cli
+            .max_content_length
+            .unwrap_or(default_limits.max_content_length),
+        max_batch_size: cli.max_batch_size.unwrap_or(default_limits.max_batch_size),
+        max_mailbox_depth: cli
+            .max_mailbox_depth
+            .unwrap_or(default_limits.max_mailbox_depth),
+        max_mailbox_length: cli
+            .max_mailbox_length
+            .unwrap_or(default_limits.max_mailbox_length),
+        max_mailbox_section_length: cli
+            .max_mailbox_section_length
+            .unwrap_or(default_limits.max_mailbox_section_length),
+        mailbox_charset: match cli.mailbox_charset {
+            MailboxCharsetArg::Unicode => MailboxCharset::Unicode,
+            MailboxCharsetArg::Ascii => MailboxCharset::Ascii,
+        },
+        max_attachment_size: cli
+            .max_attachment_size
+            .unwrap_or(default_limits.max_attachment_size),
+    };
+    let mut quota_policy = QuotaPolicy::new().with_action(match cli.quota_action {
+        QuotaActionArg::Reject => QuotaAction::Reject,
+        QuotaActionArg::Archive => QuotaAction::Archive,
+    });
+    if let Some(limit) = cli.mailbox_quota {
+        quota_policy = quota_policy.with_mailbox_limit(limit);
+    }
+    for quota in &cli.namespace_quota {
+        let (namespace, limit) = parse_namespace_quota(quota)?;
+        quota_policy = quota_policy.with_namespace_limit(namespace, limit);
+    }
+    let forward_config = match &cli.forward_config {
+        Some(path) => load_forward_config(path)?,
+        None => ForwardConfig {
+            forwards: vec![],
+            bridges: Bridges::default(),
+        },
+    };
+    let heartbeat_alerts = cli
+        .heartbeat_alerts
+        .as_deref()
+        .map(Mailbox::try_from)
+        .transpose()
+        .context("Invalid --heartbeat-alerts mailbox")?;
+    let db = Database::new(SqliteBackend::new(cli.db_file).await?)
+        .with_limits(limits)
+        .with_quota_policy(quota_policy)
+        .with_heartbeat_alerts(heartbeat_alerts);
+    serve(
+        db,
+        ServeOptions {
+            expose: cli.expose,
+            port: cli.port,
+            token: cli.token,
+            admin_token: cli.admin_token,
+            shutdown_timeout: cli.shutdown_timeout,
+            signing_keys: cli.signing_keys,
+            slack_signing_secret: cli.slack_signing_secret,
+            forwards: forward_config.forwards,
+            bridges: forward_config.bridges,
+        },
+    )
+    .await
 }